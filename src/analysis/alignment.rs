@@ -0,0 +1,493 @@
+//! Inter-channel time and phase alignment
+//!
+//! [`AlignmentAnalyzer`] estimates how far one input lags another that
+//! picked up the same source, e.g. a close and a room mic on a guitar
+//! amp, or two mics on a snare, one above and one below. It cross-
+//! correlates the two with phase transform weighting (GCC-PHAT), which
+//! keeps the peak sharp on tonal material, finds the peak to a fraction
+//! of a sample by parabolic interpolation, and reads the polarity off the
+//! sign of the peak: mics facing each other come out opposite.
+//!
+//! The two inputs are the first two channels of an interleaved stream, or
+//! two mono streams fed side by side. The estimate, a
+//! [`ChannelAlignment`], is what [`ChannelAlign`] applies, offline or
+//! live.
+//!
+//! ```no_run
+//! use audio_engine::analysis::alignment;
+//!
+//! let alignment = alignment::analyze_files("kick_in.wav", "kick_out.wav")?;
+//! println!("{alignment}");
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! [`ChannelAlign`]: crate::dsp::channel_align::ChannelAlign
+
+use std::fmt;
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::buffer::memory::heap_bytes;
+use crate::dsp::fft::{Fft, hann_window};
+use crate::error::{AudioEngineError, Result};
+use crate::io::wav::WavReader;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+const FFT_SIZE: usize = 16384;
+const HOP: usize = 8192;
+
+/// Frames read per pass through a file
+const READ_FRAMES: usize = 8192;
+
+/// Frames where either input is below this mean power (-70 dBFS) aren't
+/// correlated
+const SILENCE_POWER: f64 = 1e-7;
+
+/// Cross-spectrum bins below this magnitude carry no phase worth weighting
+const MIN_CROSS: f64 = 1e-20;
+
+/// Longest delay searched unless set with
+/// [`AlignmentAnalyzer::with_max_delay`]
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_millis(20);
+
+/// How the second input lines up with the first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelAlignment {
+    /// How far the second input lags the first, in samples; negative when
+    /// it leads
+    pub delay_samples: f32,
+    /// The same delay in milliseconds
+    pub delay_ms: f32,
+    /// Correlation of the two at that delay, from -1 to 1; its size says
+    /// how alike they are, and so how far to trust the estimate
+    pub correlation: f32,
+    /// Whether the second input is opposite in polarity to the first
+    pub invert_polarity: bool,
+}
+
+impl fmt::Display for ChannelAlignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = if self.delay_samples < 0.0 {
+            "early"
+        } else {
+            "late"
+        };
+        write!(
+            f,
+            "second input {:.3} ms ({:.2} samples) {direction}",
+            self.delay_ms.abs(),
+            self.delay_samples.abs()
+        )?;
+        if self.invert_polarity {
+            write!(f, ", polarity inverted")?;
+        }
+        write!(f, ", correlation {:+.2}", self.correlation)
+    }
+}
+
+/// Estimates the delay and polarity between two inputs.
+#[derive(Debug, Clone)]
+pub struct AlignmentAnalyzer {
+    fft: Fft,
+    sample_rate: SampleRate,
+    /// Longest lag searched either way, in samples
+    max_lag: usize,
+    window: Vec<f32>,
+    first: Vec<f32>,
+    second: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    /// Averaged cross-spectrum of the second input against the first
+    cross_re: Vec<f64>,
+    cross_im: Vec<f64>,
+    /// Averaged windowed energy of each input
+    first_energy: f64,
+    second_energy: f64,
+    /// Weight of a new frame when the average forgets, `None` to average
+    /// everything equally
+    smoothing: Option<f64>,
+    frames: u64,
+    alignment: Option<ChannelAlignment>,
+}
+
+impl AlignmentAnalyzer {
+    /// # Panics
+    /// Never; the FFT size is a fixed power of two.
+    #[must_use]
+    pub fn new(sample_rate: SampleRate) -> Self {
+        let mut window = vec![0.0; FFT_SIZE];
+        hann_window(&mut window);
+        let mut analyzer = Self {
+            fft: Fft::new(FFT_SIZE).expect("FFT size is a power of two"),
+            sample_rate,
+            max_lag: 0,
+            window,
+            first: Vec::with_capacity(FFT_SIZE),
+            second: Vec::with_capacity(FFT_SIZE),
+            re: vec![0.0; FFT_SIZE],
+            im: vec![0.0; FFT_SIZE],
+            cross_re: vec![0.0; FFT_SIZE / 2 + 1],
+            cross_im: vec![0.0; FFT_SIZE / 2 + 1],
+            first_energy: 0.0,
+            second_energy: 0.0,
+            smoothing: None,
+            frames: 0,
+            alignment: None,
+        };
+        analyzer.set_max_delay(DEFAULT_MAX_DELAY);
+        analyzer
+    }
+
+    /// Sets the longest delay searched either way. It is capped at a
+    /// quarter of the analysis frame, about 85 ms at 48 kHz.
+    #[must_use]
+    pub fn with_max_delay(mut self, delay: Duration) -> Self {
+        self.set_max_delay(delay);
+        self
+    }
+
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn set_max_delay(&mut self, delay: Duration) {
+        let lag = (delay.as_secs_f64() * f64::from(self.sample_rate.as_hz())).ceil() as usize;
+        self.max_lag = lag.clamp(1, FFT_SIZE / 4);
+    }
+
+    /// Longest delay searched either way
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_secs_f64(self.max_lag as f64 / f64::from(self.sample_rate.as_hz()))
+    }
+
+    /// Makes the average exponential, forgetting with a time constant of
+    /// `time`, so the estimate follows mics that move.
+    #[must_use]
+    pub fn with_time_constant(mut self, time: Duration) -> Self {
+        self.set_time_constant(time);
+        self
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn set_time_constant(&mut self, time: Duration) {
+        let hops = time.as_secs_f64() * f64::from(self.sample_rate.as_hz()) / HOP as f64;
+        self.smoothing = Some(1.0 - (-1.0 / hops.max(1.0)).exp());
+    }
+
+    /// Frames correlated so far
+    #[must_use]
+    pub const fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The estimate from the input so far, or `None` before a frame where
+    /// both inputs were louder than the silence threshold
+    #[must_use]
+    pub const fn alignment(&self) -> Option<ChannelAlignment> {
+        self.alignment
+    }
+
+    /// Bytes of the FFT tables and frame buffers
+    #[must_use]
+    pub const fn memory_bytes(&self) -> usize {
+        self.fft.memory_bytes()
+            + heap_bytes(&self.window)
+            + heap_bytes(&self.first)
+            + heap_bytes(&self.second)
+            + heap_bytes(&self.re)
+            + heap_bytes(&self.im)
+            + heap_bytes(&self.cross_re)
+            + heap_bytes(&self.cross_im)
+    }
+
+    /// Forgets everything fed so far.
+    pub fn reset(&mut self) {
+        self.first.clear();
+        self.second.clear();
+        self.cross_re.fill(0.0);
+        self.cross_im.fill(0.0);
+        self.first_energy = 0.0;
+        self.second_energy = 0.0;
+        self.frames = 0;
+        self.alignment = None;
+    }
+
+    /// Feeds interleaved samples, comparing the second channel with the
+    /// first. Mono input is ignored. Doesn't allocate.
+    pub fn process(&mut self, samples: &[Sample], channels: ChannelCount) {
+        let count = channels.count_usize();
+        if count < 2 {
+            return;
+        }
+        for frame in samples.chunks_exact(count) {
+            self.push(frame[0].value(), frame[1].value());
+        }
+    }
+
+    /// Feeds two mono inputs side by side, comparing `second` with
+    /// `first`. Samples past the end of the shorter are ignored. Doesn't
+    /// allocate.
+    pub fn process_pair(&mut self, first: &[Sample], second: &[Sample]) {
+        for (a, b) in first.iter().zip(second) {
+            self.push(a.value(), b.value());
+        }
+    }
+
+    fn push(&mut self, first: f32, second: f32) {
+        self.first.push(first);
+        self.second.push(second);
+        if self.first.len() == FFT_SIZE {
+            self.analyze_frame();
+            self.first.drain(..HOP);
+            self.second.drain(..HOP);
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn analyze_frame(&mut self) {
+        // Both inputs go through one transform, the first as the real part
+        // and the second as the imaginary part
+        let mut first_energy = 0.0;
+        let mut second_energy = 0.0;
+        let mut window_energy = 0.0;
+        for (index, &w) in self.window.iter().enumerate() {
+            let (a, b) = (self.first[index] * w, self.second[index] * w);
+            first_energy += f64::from(a * a);
+            second_energy += f64::from(b * b);
+            window_energy += f64::from(w * w);
+            self.re[index] = a;
+            self.im[index] = b;
+        }
+        if first_energy.min(second_energy) < SILENCE_POWER * window_energy {
+            return;
+        }
+        self.fft.forward(&mut self.re, &mut self.im);
+
+        self.frames += 1;
+        let weight = self
+            .smoothing
+            .unwrap_or(1.0 / self.frames as f64)
+            .max(1.0 / self.frames as f64);
+        for k in 0..=FFT_SIZE / 2 {
+            let mirror = (FFT_SIZE - k) % FFT_SIZE;
+            let (zr, zi) = (self.re[k], self.im[k]);
+            let (wr, wi) = (self.re[mirror], -self.im[mirror]);
+            // A = (Z + W) / 2 and B = (Z - W) / 2i, with W the conjugate
+            // of the mirrored bin
+            let (ar, ai) = (f32::midpoint(zr, wr), f32::midpoint(zi, wi));
+            let (br, bi) = ((zi - wi) / 2.0, (wr - zr) / 2.0);
+            // B times the conjugate of A
+            let cross_re = f64::from(br.mul_add(ar, bi * ai));
+            let cross_im = f64::from(bi.mul_add(ar, -br * ai));
+            self.cross_re[k] += (cross_re - self.cross_re[k]) * weight;
+            self.cross_im[k] += (cross_im - self.cross_im[k]) * weight;
+        }
+        self.first_energy += (first_energy - self.first_energy) * weight;
+        self.second_energy += (second_energy - self.second_energy) * weight;
+        self.alignment = Some(self.estimate());
+    }
+
+    /// Finds the peak of the weighted cross-correlation of the averaged
+    /// cross-spectrum.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_possible_wrap,
+        clippy::cast_precision_loss
+    )]
+    fn estimate(&mut self) -> ChannelAlignment {
+        // Both correlations are real, so one inverse transform gives the
+        // weighted one in its real part and the plain one in its imaginary
+        // part
+        for k in 0..=FFT_SIZE / 2 {
+            let (cr, ci) = (self.cross_re[k], self.cross_im[k]);
+            let magnitude = cr.hypot(ci);
+            let (pr, pi) = if k == 0 || k == FFT_SIZE / 2 || magnitude < MIN_CROSS {
+                (0.0, 0.0)
+            } else {
+                ((cr / magnitude) as f32, (ci / magnitude) as f32)
+            };
+            let (qr, qi) = (cr as f32, ci as f32);
+            self.re[k] = pr - qi;
+            self.im[k] = pi + qr;
+            if k > 0 && k < FFT_SIZE / 2 {
+                self.re[FFT_SIZE - k] = pr + qi;
+                self.im[FFT_SIZE - k] = qr - pi;
+            }
+        }
+        self.fft.inverse(&mut self.re, &mut self.im);
+
+        let lag_index = |lag: isize| lag.rem_euclid(FFT_SIZE as isize) as usize;
+        let max_lag = self.max_lag as isize;
+        let peak = (-max_lag..=max_lag)
+            .max_by(|&a, &b| {
+                self.re[lag_index(a)]
+                    .abs()
+                    .total_cmp(&self.re[lag_index(b)].abs())
+            })
+            .unwrap_or(0);
+        let peak_value = self.re[lag_index(peak)];
+        let sign = if peak_value < 0.0 { -1.0 } else { 1.0 };
+        let offset = if peak.abs() < max_lag {
+            let before = self.re[lag_index(peak - 1)] * sign;
+            let after = self.re[lag_index(peak + 1)] * sign;
+            let curve = before - 2.0f32.mul_add(peak_value * sign, -after);
+            if curve < 0.0 {
+                0.5 * (before - after) / curve
+            } else {
+                0.0
+            }
+        } else {
+            0.0
+        };
+
+        let delay_samples = peak as f32 + offset;
+        let energy = (self.first_energy * self.second_energy).sqrt();
+        let correlation = if energy > 0.0 {
+            (f64::from(self.im[lag_index(peak)]) / energy).clamp(-1.0, 1.0) as f32
+        } else {
+            0.0
+        };
+        ChannelAlignment {
+            delay_samples,
+            delay_ms: delay_samples * 1000.0 / self.sample_rate.as_f32(),
+            correlation,
+            invert_polarity: peak_value < 0.0,
+        }
+    }
+}
+
+/// Estimates the alignment of the second channel of the WAV file at
+/// `path` to its first, e.g. two mics recorded to one stereo file.
+///
+/// # Errors
+/// Returns an error if the file can't be read, isn't a supported WAV file
+/// or has one channel, or is too short or quiet to correlate.
+pub fn analyze_file(path: impl AsRef<Path>) -> Result<ChannelAlignment> {
+    let path = path.as_ref();
+    let mut reader = WavReader::open(path)?;
+    let format = reader.format();
+    if format.channels == ChannelCount::Mono {
+        return Err(AudioEngineError::configuration(format!(
+            "{} has one channel; alignment needs two",
+            path.display()
+        )));
+    }
+    let mut analyzer = AlignmentAnalyzer::new(format.sample_rate);
+    let mut buffer = vec![Sample::SILENCE; READ_FRAMES * format.channels.count_usize()];
+    loop {
+        let count = reader.read_samples(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        analyzer.process(&buffer[..count], format.channels);
+    }
+    analyzer.alignment().ok_or_else(|| too_quiet(path))
+}
+
+/// Estimates the alignment of the WAV file at `second` to the one at
+/// `first`, comparing their first channels.
+///
+/// # Errors
+/// Returns an error if either file can't be read or isn't a supported WAV
+/// file, their sample rates differ, or they are too short or quiet to
+/// correlate.
+pub fn analyze_files(
+    first: impl AsRef<Path>,
+    second: impl AsRef<Path>,
+) -> Result<ChannelAlignment> {
+    let first = first.as_ref();
+    let mut first_reader = WavReader::open(first)?;
+    let mut second_reader = WavReader::open(second.as_ref())?;
+    let sample_rate = check_rates(&first_reader, &second_reader)?;
+    let mut analyzer = AlignmentAnalyzer::new(sample_rate);
+    let mut pair = PairReader::new(&first_reader, &second_reader);
+    while pair.read(&mut first_reader, &mut second_reader)? > 0 {
+        analyzer.process_pair(&pair.first, &pair.second);
+    }
+    analyzer.alignment().ok_or_else(|| too_quiet(first))
+}
+
+fn too_quiet(path: &Path) -> AudioEngineError {
+    AudioEngineError::configuration(format!(
+        "{} is too short or too quiet to align",
+        path.display()
+    ))
+}
+
+/// The sample rate two files share.
+///
+/// # Errors
+/// Returns an error if their sample rates differ.
+pub(crate) fn check_rates<R: Read + Seek>(
+    first: &WavReader<R>,
+    second: &WavReader<R>,
+) -> Result<SampleRate> {
+    let (a, b) = (first.format().sample_rate, second.format().sample_rate);
+    if a == b {
+        Ok(a)
+    } else {
+        Err(AudioEngineError::configuration(format!(
+            "can't align {} Hz audio to {} Hz audio",
+            b.as_hz(),
+            a.as_hz()
+        )))
+    }
+}
+
+/// Reads the first channels of two files in step.
+pub(crate) struct PairReader {
+    first_buffer: Vec<Sample>,
+    second_buffer: Vec<Sample>,
+    pub first: Vec<Sample>,
+    pub second: Vec<Sample>,
+}
+
+impl PairReader {
+    pub fn new<R: Read + Seek>(first: &WavReader<R>, second: &WavReader<R>) -> Self {
+        Self {
+            first_buffer: vec![
+                Sample::SILENCE;
+                READ_FRAMES * first.format().channels.count_usize()
+            ],
+            second_buffer: vec![
+                Sample::SILENCE;
+                READ_FRAMES * second.format().channels.count_usize()
+            ],
+            first: Vec::with_capacity(READ_FRAMES),
+            second: Vec::with_capacity(READ_FRAMES),
+        }
+    }
+
+    /// Reads the next block of both, returning the frames read: the fewer
+    /// of the two, 0 once either ends.
+    pub fn read<R: Read + Seek>(
+        &mut self,
+        first: &mut WavReader<R>,
+        second: &mut WavReader<R>,
+    ) -> Result<usize> {
+        read_channel(first, &mut self.first_buffer, &mut self.first)?;
+        read_channel(second, &mut self.second_buffer, &mut self.second)?;
+        let frames = self.first.len().min(self.second.len());
+        self.first.truncate(frames);
+        self.second.truncate(frames);
+        Ok(frames)
+    }
+}
+
+/// Reads a block of `reader` and keeps its first channel in `channel`.
+fn read_channel<R: Read + Seek>(
+    reader: &mut WavReader<R>,
+    buffer: &mut [Sample],
+    channel: &mut Vec<Sample>,
+) -> Result<usize> {
+    let count = reader.read_samples(buffer)?;
+    let channels = reader.format().channels.count_usize();
+    channel.clear();
+    channel.extend(buffer[..count].iter().step_by(channels));
+    Ok(channel.len())
+}
@@ -0,0 +1,461 @@
+//! Sound exposure dose metering
+//!
+//! [`ExposureMeter`] estimates how much of a daily or weekly noise dose a
+//! listener has taken in from the audio played to them, for installations
+//! and headphone products that have to keep people's hearing safe. It
+//! A-weights every channel, measures the level of each second in dB(A) SPL
+//! using a calibration the caller supplies, and adds the second to the
+//! dose with the criterion level, duration and exchange rate of
+//! [`ExposureSettings`]: the NIOSH, OSHA and WHO safe listening criteria
+//! are built in. With several channels the loudest counts, as each ear
+//! takes its own dose.
+//!
+//! Warnings come once when the dose reaches the warning share of the
+//! limit, and once when it reaches the limit. The meter never allocates
+//! after construction, so it can run on the audio thread; an engine runs
+//! one on its output with
+//! [`EngineBuilder::with_exposure`](crate::engine::EngineBuilder::with_exposure).
+//!
+//! The estimate is only as good as the calibration: the level a full-scale
+//! sine reaches at the listener's ear through the actual amplifier, volume
+//! setting and speakers or headphones. It is a guide, not a substitute for
+//! a certified dosimeter.
+//!
+//! ```
+//! use audio_engine::analysis::exposure::{ExposureMeter, ExposureSettings};
+//! use audio_engine::types::{AudioFormat, Sample};
+//!
+//! // A full-scale sine measured at 100 dB(A) at the ear
+//! let mut meter = ExposureMeter::new(ExposureSettings::niosh(100.0), AudioFormat::default());
+//! meter.process(&[Sample::SILENCE; 96_000], |warning| println!("{warning}"));
+//! assert_eq!(meter.dose(), 0.0);
+//! ```
+
+use std::f64::consts::{PI, TAU};
+use std::fmt;
+use std::time::Duration;
+
+use crate::types::{AudioFormat, Sample, SampleRate};
+
+/// Length of the level measurements the dose is built from
+const BLOCK_MS: u32 = 1000;
+/// Most channels metered; further channels are ignored
+const MAX_CHANNELS: usize = 8;
+/// Corner frequencies of the IEC 61672 A-weighting curve, in Hz
+const A_LOW_HZ: f64 = 20.598_997;
+const A_MID_LOW_HZ: f64 = 107.652_65;
+const A_MID_HIGH_HZ: f64 = 737.862_23;
+const A_HIGH_HZ: f64 = 12_194.217;
+/// Frequency the A-weighting curve passes at unity gain
+const A_REFERENCE_HZ: f64 = 1_000.0;
+
+/// The criterion exposure is measured against, and when to warn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExposureSettings {
+    /// Level in dB(A) SPL a full-scale sine reaches at the listener
+    pub calibration: f32,
+    /// Level in dB(A) that may be heard for the whole
+    /// [`criterion_duration`](Self::criterion_duration)
+    pub criterion_level: f32,
+    /// Time the criterion level makes a full dose
+    pub criterion_duration: Duration,
+    /// Level increase, in dB, that halves the time allowed
+    pub exchange_rate: f32,
+    /// Share of the dose at which the meter warns before the limit
+    pub warn_at: f32,
+}
+
+impl Default for ExposureSettings {
+    /// NIOSH criteria with a calibration of 100 dB(A); measure the real
+    /// one rather than relying on it
+    fn default() -> Self {
+        Self::niosh(100.0)
+    }
+}
+
+impl ExposureSettings {
+    /// The NIOSH recommended limit, 85 dB(A) for 8 hours with a 3 dB
+    /// exchange rate, warning at half the dose.
+    #[must_use]
+    pub const fn niosh(calibration: f32) -> Self {
+        Self {
+            calibration,
+            criterion_level: 85.0,
+            criterion_duration: Duration::from_hours(8),
+            exchange_rate: 3.0,
+            warn_at: 0.5,
+        }
+    }
+
+    /// The OSHA permissible exposure limit, 90 dB(A) for 8 hours with a
+    /// 5 dB exchange rate, warning at the 85 dB(A) action level, half the
+    /// dose.
+    #[must_use]
+    pub const fn osha(calibration: f32) -> Self {
+        Self {
+            criterion_level: 90.0,
+            exchange_rate: 5.0,
+            ..Self::niosh(calibration)
+        }
+    }
+
+    /// The WHO/ITU safe listening limit for adults' personal audio, 80
+    /// dB(A) for 40 hours a week with a 3 dB exchange rate, warning at
+    /// 80% of the weekly dose as the standard does.
+    #[must_use]
+    pub const fn who_weekly(calibration: f32) -> Self {
+        Self {
+            criterion_level: 80.0,
+            criterion_duration: Duration::from_hours(40),
+            warn_at: 0.8,
+            ..Self::niosh(calibration)
+        }
+    }
+
+    #[must_use]
+    pub const fn with_calibration(mut self, calibration: f32) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    /// Sets the criterion level, the time it makes a full dose, and the
+    /// exchange rate.
+    #[must_use]
+    pub const fn with_criterion(
+        mut self,
+        level: f32,
+        duration: Duration,
+        exchange_rate: f32,
+    ) -> Self {
+        self.criterion_level = level;
+        self.criterion_duration = duration;
+        self.exchange_rate = exchange_rate;
+        self
+    }
+
+    /// Sets the share of the dose to warn at, clamped to 0..1.
+    #[must_use]
+    pub const fn with_warn_at(mut self, share: f32) -> Self {
+        self.warn_at = share.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Time `level` dB(A) may be heard before it makes a full dose
+    #[must_use]
+    pub fn allowed_time(&self, level: f64) -> Duration {
+        Duration::try_from_secs_f64(self.criterion_duration.as_secs_f64() / self.dose_factor(level))
+            .unwrap_or(Duration::MAX)
+    }
+
+    /// How many times faster than the criterion level `level` builds up a
+    /// dose
+    fn dose_factor(&self, level: f64) -> f64 {
+        ((level - f64::from(self.criterion_level)) / f64::from(self.exchange_rate)).exp2()
+    }
+}
+
+/// Which limit an [`ExposureWarning`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExposureLimit {
+    /// The dose reached the warning share of the limit
+    Approaching,
+    /// The dose reached the limit
+    Reached,
+}
+
+impl fmt::Display for ExposureLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Approaching => write!(f, "approaching"),
+            Self::Reached => write!(f, "reached"),
+        }
+    }
+}
+
+/// A limit crossed by an [`ExposureMeter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExposureWarning {
+    pub limit: ExposureLimit,
+    /// Dose so far, 1.0 being the full dose
+    pub dose: f64,
+    /// Level of the last second in dB(A) SPL
+    pub level: f64,
+    /// Listening time so far
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for ExposureWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "exposure limit {} at {:.0}% dose after {:.0} min, now {:.1} dB(A)",
+            self.limit,
+            self.dose * 100.0,
+            self.elapsed.as_secs_f64() / 60.0,
+            self.level
+        )
+    }
+}
+
+/// First order section of the A-weighting filter
+#[derive(Debug, Clone, Copy, Default)]
+struct OnePole {
+    b0: f64,
+    b1: f64,
+    a1: f64,
+}
+
+impl OnePole {
+    /// Bilinear transform of `s / (s + w)`, or of `w / (s + w)` if not
+    /// `high_pass`, with the corner prewarped where the rate allows
+    fn new(corner_hz: f64, sample_rate: f64, high_pass: bool) -> Self {
+        let k = 2.0 * sample_rate;
+        let w = k * (PI * corner_hz.min(0.45 * sample_rate) / sample_rate).tan();
+        let b0 = if high_pass { k } else { w } / (k + w);
+        Self {
+            b0,
+            b1: if high_pass { -b0 } else { b0 },
+            a1: (w - k) / (k + w),
+        }
+    }
+
+    /// Magnitude of the response at `frequency`
+    fn gain(&self, frequency: f64, sample_rate: f64) -> f64 {
+        let cos = (TAU * frequency / sample_rate).cos();
+        let numerator =
+            (2.0 * self.b0 * self.b1).mul_add(cos, self.b0.mul_add(self.b0, self.b1 * self.b1));
+        let denominator = (2.0 * self.a1).mul_add(cos, self.a1.mul_add(self.a1, 1.0));
+        (numerator / denominator).sqrt()
+    }
+}
+
+/// The A-weighting curve as six first order sections, normalised to
+/// unity gain at 1 kHz.
+#[derive(Debug, Clone, Copy)]
+struct AWeighting {
+    sections: [OnePole; 6],
+    gain: f64,
+}
+
+impl AWeighting {
+    fn new(sample_rate: SampleRate) -> Self {
+        let fs = f64::from(sample_rate.as_hz());
+        let sections = [
+            OnePole::new(A_LOW_HZ, fs, true),
+            OnePole::new(A_LOW_HZ, fs, true),
+            OnePole::new(A_MID_LOW_HZ, fs, true),
+            OnePole::new(A_MID_HIGH_HZ, fs, true),
+            OnePole::new(A_HIGH_HZ, fs, false),
+            OnePole::new(A_HIGH_HZ, fs, false),
+        ];
+        let reference: f64 = sections
+            .iter()
+            .map(|s| s.gain(A_REFERENCE_HZ, fs))
+            .product();
+        Self {
+            sections,
+            gain: reference.recip(),
+        }
+    }
+}
+
+/// Per-channel A-weighting filter memory, the last input and output of
+/// each section
+#[derive(Debug, Clone, Copy, Default)]
+struct AWeightingState {
+    memory: [(f64, f64); 6],
+}
+
+impl AWeightingState {
+    fn process(&mut self, x: f64, a: &AWeighting) -> f64 {
+        let mut x = x * a.gain;
+        for (section, (x1, y1)) in a.sections.iter().zip(&mut self.memory) {
+            let y = section
+                .b0
+                .mul_add(x, section.b1.mul_add(*x1, -section.a1 * *y1));
+            *x1 = x;
+            *y1 = y;
+            x = y;
+        }
+        x
+    }
+}
+
+/// Estimates a listener's sound exposure dose from the audio played to
+/// them.
+#[derive(Debug, Clone)]
+pub struct ExposureMeter {
+    settings: ExposureSettings,
+    format: AudioFormat,
+    channels: usize,
+    filter: AWeighting,
+    states: [AWeightingState; MAX_CHANNELS],
+    /// Sums of squares of the current block, per channel
+    energies: [f64; MAX_CHANNELS],
+    block_frames: usize,
+    block_position: usize,
+    /// Share of the criterion duration one block is
+    block_share: f64,
+    frames: u64,
+    dose: f64,
+    level: f64,
+    warned: bool,
+    reached: bool,
+}
+
+impl ExposureMeter {
+    #[must_use]
+    pub fn new(settings: ExposureSettings, format: AudioFormat) -> Self {
+        let block_frames = format.sample_rate.samples_for_milliseconds(BLOCK_MS) as usize;
+        let block = Duration::from_millis(u64::from(BLOCK_MS));
+        Self {
+            settings,
+            format,
+            channels: format.channels.count_usize(),
+            filter: AWeighting::new(format.sample_rate),
+            states: [AWeightingState::default(); MAX_CHANNELS],
+            energies: [0.0; MAX_CHANNELS],
+            block_frames: block_frames.max(1),
+            block_position: 0,
+            block_share: block.as_secs_f64() / settings.criterion_duration.as_secs_f64(),
+            frames: 0,
+            dose: 0.0,
+            level: f64::NEG_INFINITY,
+            warned: false,
+            reached: false,
+        }
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &ExposureSettings {
+        &self.settings
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Starts a new listener's dose, e.g. when a kiosk session ends or
+    /// headphones change hands.
+    pub fn reset(&mut self) {
+        self.states = [AWeightingState::default(); MAX_CHANNELS];
+        self.energies = [0.0; MAX_CHANNELS];
+        self.block_position = 0;
+        self.frames = 0;
+        self.dose = 0.0;
+        self.level = f64::NEG_INFINITY;
+        self.warned = false;
+        self.reached = false;
+    }
+
+    /// Measures a block of interleaved samples, calling `on_warning` when
+    /// the dose crosses a limit.
+    pub fn process(&mut self, input: &[Sample], mut on_warning: impl FnMut(ExposureWarning)) {
+        for frame in input.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate().take(MAX_CHANNELS) {
+                let weighted =
+                    self.states[channel].process(f64::from(sample.value()), &self.filter);
+                self.energies[channel] += weighted * weighted;
+            }
+            if let Some(warning) = self.advance() {
+                on_warning(warning);
+            }
+        }
+    }
+
+    /// Measures one interleaved frame of raw output, returning a warning
+    /// if it completed a second that crossed a limit.
+    pub(crate) fn process_frame(&mut self, frame: &[f32]) -> Option<ExposureWarning> {
+        for (channel, &x) in frame.iter().enumerate().take(MAX_CHANNELS) {
+            let weighted = self.states[channel].process(f64::from(x), &self.filter);
+            self.energies[channel] += weighted * weighted;
+        }
+        self.advance()
+    }
+
+    /// Counts a frame, adding the block to the dose when it is complete.
+    fn advance(&mut self) -> Option<ExposureWarning> {
+        self.frames += 1;
+        self.block_position += 1;
+        if self.block_position < self.block_frames {
+            return None;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let frames = self.block_frames as f64;
+        let loudest = self.energies.iter().fold(0.0_f64, |max, &e| max.max(e)) / frames;
+        self.energies = [0.0; MAX_CHANNELS];
+        self.block_position = 0;
+        // A full-scale sine has a mean square of one half
+        self.level = if loudest > 0.0 {
+            10.0_f64.mul_add(
+                (2.0 * loudest).log10(),
+                f64::from(self.settings.calibration),
+            )
+        } else {
+            f64::NEG_INFINITY
+        };
+        self.dose += self.block_share * self.settings.dose_factor(self.level);
+
+        let limit = if !self.reached && self.dose >= 1.0 {
+            self.reached = true;
+            self.warned = true;
+            ExposureLimit::Reached
+        } else if !self.warned && self.dose >= f64::from(self.settings.warn_at) {
+            self.warned = true;
+            ExposureLimit::Approaching
+        } else {
+            return None;
+        };
+        Some(ExposureWarning {
+            limit,
+            dose: self.dose,
+            level: self.level,
+            elapsed: self.elapsed(),
+        })
+    }
+
+    /// Dose so far, 1.0 being the full dose of the criterion
+    #[must_use]
+    pub const fn dose(&self) -> f64 {
+        self.dose
+    }
+
+    /// Level of the last complete second in dB(A) SPL, negative infinity
+    /// before the first or in silence
+    #[must_use]
+    pub const fn level(&self) -> f64 {
+        self.level
+    }
+
+    /// Listening time measured so far
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64(self.frames as f64 / f64::from(self.format.sample_rate.as_hz()))
+    }
+
+    /// The constant level in dB(A) that would have made the same dose
+    /// over the time so far, negative infinity in silence
+    #[must_use]
+    pub fn average_level(&self) -> f64 {
+        let elapsed = self.elapsed().as_secs_f64();
+        if self.dose <= 0.0 || elapsed <= 0.0 {
+            return f64::NEG_INFINITY;
+        }
+        let ratio = self.dose * self.settings.criterion_duration.as_secs_f64() / elapsed;
+        f64::from(self.settings.exchange_rate)
+            .mul_add(ratio.log2(), f64::from(self.settings.criterion_level))
+    }
+
+    /// How much longer the listener can listen at the level of the last
+    /// second before reaching the full dose; zero once it is reached
+    #[must_use]
+    pub fn time_remaining(&self) -> Duration {
+        let left = (1.0 - self.dose).max(0.0);
+        Duration::try_from_secs_f64(left * self.settings.allowed_time(self.level).as_secs_f64())
+            .unwrap_or(Duration::MAX)
+    }
+}
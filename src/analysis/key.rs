@@ -0,0 +1,320 @@
+//! Musical key estimation
+//!
+//! [`KeyAnalyzer`] folds the magnitude spectrum between 55 Hz and 5 kHz into
+//! a 12-bin chroma vector and correlates the accumulated chroma with the
+//! Krumhansl-Kessler major and minor key profiles in all 12 transpositions.
+
+use std::fmt;
+
+use crate::dsp::fft::{Fft, hann_window};
+use crate::types::SampleRate;
+
+const FFT_SIZE: usize = 8192;
+const HOP: usize = 4096;
+const MIN_HZ: f32 = 55.0;
+const MAX_HZ: f32 = 5000.0;
+
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+/// A key: tonic pitch class (0 = C) and mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MusicalKey {
+    pub tonic: u8,
+    pub mode: Mode,
+}
+
+impl MusicalKey {
+    /// Name of the tonic, e.g. `"F#"`
+    #[must_use]
+    pub const fn tonic_name(self) -> &'static str {
+        NOTE_NAMES[self.tonic as usize % 12]
+    }
+
+    /// Position on the Camelot wheel used by DJ software, e.g. `"8A"` for
+    /// A minor
+    #[must_use]
+    pub fn camelot(self) -> String {
+        let (major_tonic, letter) = match self.mode {
+            Mode::Major => (self.tonic, 'B'),
+            Mode::Minor => ((self.tonic + 3) % 12, 'A'),
+        };
+        let number = (usize::from(major_tonic) * 7 + 7) % 12 + 1;
+        format!("{number}{letter}")
+    }
+}
+
+impl fmt::Display for MusicalKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self.mode {
+            Mode::Major => "major",
+            Mode::Minor => "minor",
+        };
+        write!(f, "{} {mode}", self.tonic_name())
+    }
+}
+
+/// A key estimate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEstimate {
+    pub key: MusicalKey,
+    /// Correlation of the chroma with the winning key profile, -1 to 1
+    pub correlation: f32,
+    /// How far the winner is ahead of the runner-up, 0 (a toss-up) to 1
+    pub confidence: f32,
+    /// Normalised chroma the estimate is based on, starting at C
+    pub chroma: [f32; 12],
+}
+
+/// Streaming key analyser for mono audio.
+#[derive(Debug, Clone)]
+pub struct KeyAnalyzer {
+    fft: Fft,
+    window: Vec<f32>,
+    input: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    /// Pitch class of each FFT bin, or `None` outside the analysed range
+    bin_classes: Vec<Option<usize>>,
+    chroma: [f64; 12],
+}
+
+impl KeyAnalyzer {
+    /// # Panics
+    /// Never; the FFT size is a fixed power of two.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn new(sample_rate: SampleRate) -> Self {
+        let bin_hz = sample_rate.as_f32() / FFT_SIZE as f32;
+        let bin_classes = (0..=FFT_SIZE / 2)
+            .map(|k| {
+                let hz = k as f32 * bin_hz;
+                (MIN_HZ..=MAX_HZ).contains(&hz).then(|| {
+                    // MIDI note 69 is A440; pitch class 0 is C
+                    let note = 12.0f32.mul_add((hz / 440.0).log2(), 69.0).round() as i32;
+                    note.rem_euclid(12) as usize
+                })
+            })
+            .collect();
+        let mut window = vec![0.0; FFT_SIZE];
+        hann_window(&mut window);
+        Self {
+            fft: Fft::new(FFT_SIZE).expect("FFT size is a power of two"),
+            window,
+            input: Vec::with_capacity(FFT_SIZE),
+            re: vec![0.0; FFT_SIZE],
+            im: vec![0.0; FFT_SIZE],
+            bin_classes,
+            chroma: [0.0; 12],
+        }
+    }
+
+    /// Feeds mono samples.
+    pub fn process(&mut self, samples: &[f32]) {
+        let mut remaining = samples;
+        while !remaining.is_empty() {
+            let take = (FFT_SIZE - self.input.len()).min(remaining.len());
+            self.input.extend_from_slice(&remaining[..take]);
+            remaining = &remaining[take..];
+            if self.input.len() == FFT_SIZE {
+                self.analyze_frame();
+                self.input.drain(..HOP);
+            }
+        }
+    }
+
+    fn analyze_frame(&mut self) {
+        for ((re, im), (&x, &w)) in self
+            .re
+            .iter_mut()
+            .zip(self.im.iter_mut())
+            .zip(self.input.iter().zip(&self.window))
+        {
+            *re = x * w;
+            *im = 0.0;
+        }
+        self.fft.forward(&mut self.re, &mut self.im);
+
+        for (k, class) in self.bin_classes.iter().enumerate() {
+            if let Some(class) = *class {
+                self.chroma[class] += f64::from(self.re[k].hypot(self.im[k]));
+            }
+        }
+    }
+
+    /// Estimates the key of everything fed so far, or `None` for silence.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn estimate(&self) -> Option<KeyEstimate> {
+        let max = self.chroma.iter().copied().fold(0.0, f64::max);
+        if max <= 0.0 {
+            return None;
+        }
+        let mut chroma = [0.0f32; 12];
+        for (out, &value) in chroma.iter_mut().zip(&self.chroma) {
+            *out = (value / max) as f32;
+        }
+
+        let mut best = (f32::MIN, 0u8, Mode::Major);
+        let mut runner_up = f32::MIN;
+        for tonic in 0..12u8 {
+            for (profile, mode) in [(&MAJOR_PROFILE, Mode::Major), (&MINOR_PROFILE, Mode::Minor)] {
+                let score = correlation(&chroma, profile, usize::from(tonic));
+                if score > best.0 {
+                    runner_up = best.0;
+                    best = (score, tonic, mode);
+                } else if score > runner_up {
+                    runner_up = score;
+                }
+            }
+        }
+
+        Some(KeyEstimate {
+            key: MusicalKey {
+                tonic: best.1,
+                mode: best.2,
+            },
+            correlation: best.0,
+            confidence: ((best.0 - runner_up) / (1.0 - runner_up).max(f32::EPSILON))
+                .clamp(0.0, 1.0),
+            chroma,
+        })
+    }
+
+    /// Clears all accumulated analysis.
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.chroma = [0.0; 12];
+    }
+}
+
+/// Pearson correlation of `chroma` with `profile` rotated to start at `tonic`.
+#[allow(clippy::cast_precision_loss)]
+fn correlation(chroma: &[f32; 12], profile: &[f32; 12], tonic: usize) -> f32 {
+    let mean_chroma = chroma.iter().sum::<f32>() / 12.0;
+    let mean_profile = profile.iter().sum::<f32>() / 12.0;
+    let (mut covariance, mut var_chroma, mut var_profile) = (0.0, 0.0, 0.0);
+    for (class, &value) in chroma.iter().enumerate() {
+        let c = value - mean_chroma;
+        let p = profile[(class + 12 - tonic) % 12] - mean_profile;
+        covariance += c * p;
+        var_chroma += c * c;
+        var_profile += p * p;
+    }
+    let denominator = (var_chroma * var_profile).sqrt();
+    if denominator > 0.0 {
+        covariance / denominator
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(clippy::cast_precision_loss)]
+    fn chord(hz: &[f32], seconds: usize) -> Vec<f32> {
+        let rate = SampleRate::Hz44100.as_f32();
+        (0..44_100 * seconds)
+            .map(|index| {
+                let t = index as f32 / rate;
+                hz.iter()
+                    .map(|hz| (std::f32::consts::TAU * hz * t).sin() * 0.2)
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn estimate(hz: &[f32]) -> KeyEstimate {
+        let mut analyzer = KeyAnalyzer::new(SampleRate::Hz44100);
+        analyzer.process(&chord(hz, 4));
+        analyzer.estimate().expect("not silence")
+    }
+
+    #[test]
+    fn camelot_wheel_matches_the_published_chart() {
+        let major = [
+            "8B", "3B", "10B", "5B", "12B", "7B", "2B", "9B", "4B", "11B", "6B", "1B",
+        ];
+        let minor = [
+            "5A", "12A", "7A", "2A", "9A", "4A", "11A", "6A", "1A", "8A", "3A", "10A",
+        ];
+        for tonic in 0..12u8 {
+            let key = |mode| MusicalKey { tonic, mode };
+            assert_eq!(key(Mode::Major).camelot(), major[usize::from(tonic)]);
+            assert_eq!(key(Mode::Minor).camelot(), minor[usize::from(tonic)]);
+            // Relative keys share a number
+            let relative = MusicalKey {
+                tonic: (tonic + 9) % 12,
+                mode: Mode::Minor,
+            };
+            assert_eq!(
+                relative.camelot().trim_end_matches('A'),
+                key(Mode::Major).camelot().trim_end_matches('B')
+            );
+        }
+    }
+
+    #[test]
+    fn keys_print_their_tonic_and_mode() {
+        let key = MusicalKey {
+            tonic: 6,
+            mode: Mode::Minor,
+        };
+        assert_eq!(key.to_string(), "F# minor");
+        assert_eq!(key.tonic_name(), "F#");
+    }
+
+    #[test]
+    fn finds_the_key_of_a_triad() {
+        // C E G, and A C E
+        let major = estimate(&[261.63, 329.63, 392.00, 130.81]);
+        assert_eq!(
+            major.key,
+            MusicalKey {
+                tonic: 0,
+                mode: Mode::Major
+            }
+        );
+        let minor = estimate(&[220.00, 261.63, 329.63, 110.00]);
+        assert_eq!(
+            minor.key,
+            MusicalKey {
+                tonic: 9,
+                mode: Mode::Minor
+            }
+        );
+        assert!(major.correlation > 0.5 && minor.correlation > 0.5);
+        assert!((0.0..=1.0).contains(&major.confidence));
+    }
+
+    #[test]
+    fn silence_has_no_key() {
+        let mut analyzer = KeyAnalyzer::new(SampleRate::Hz48000);
+        analyzer.process(&[0.0; FFT_SIZE * 2]);
+        assert!(analyzer.estimate().is_none());
+        analyzer.process(&chord(&[440.0], 1));
+        assert!(analyzer.estimate().is_some());
+        analyzer.reset();
+        assert!(analyzer.estimate().is_none());
+    }
+}
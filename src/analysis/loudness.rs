@@ -0,0 +1,485 @@
+//! EBU R128 / ITU-R BS.1770 loudness metering
+//!
+//! [`LoudnessMeter`] K-weights every channel, sums the mean square energy in
+//! 100 ms blocks and reports momentary (400 ms), short-term (3 s) and gated
+//! integrated loudness, the loudness range and the 4x oversampled true peak.
+//! Gating uses fixed-size histograms, so the meter never allocates after
+//! construction and can run on the audio thread.
+
+use std::f64::consts::PI;
+
+use crate::types::{AudioFormat, ChannelLayout, Sample, SampleRate};
+
+/// Blocks quieter than this never count towards integrated loudness or range
+pub const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Interval between readings
+pub const UPDATE_INTERVAL_MS: u32 = 100;
+
+const RELATIVE_GATE_LU: f64 = -10.0;
+const RANGE_RELATIVE_GATE_LU: f64 = -20.0;
+const MOMENTARY_BLOCKS: usize = 4;
+const SHORT_TERM_BLOCKS: usize = 30;
+
+/// Histogram resolution, in bins per LU
+const HISTOGRAM_RESOLUTION: f64 = 10.0;
+/// Histogram covers -70..+10 LUFS
+const HISTOGRAM_BINS: usize = 800;
+
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// Converts a mean square energy to LUFS.
+fn energy_to_lufs(energy: f64) -> f64 {
+    if energy > 0.0 {
+        10.0f64.mul_add(energy.log10(), -0.691)
+    } else {
+        f64::NEG_INFINITY
+    }
+}
+
+fn lufs_to_energy(lufs: f64) -> f64 {
+    10.0f64.powf((lufs + 0.691) / 10.0)
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn histogram_bin(lufs: f64) -> Option<usize> {
+    if lufs < ABSOLUTE_GATE_LUFS {
+        return None;
+    }
+    let bin = ((lufs - ABSOLUTE_GATE_LUFS) * HISTOGRAM_RESOLUTION) as usize;
+    Some(bin.min(HISTOGRAM_BINS - 1))
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn bin_lufs(bin: usize) -> f64 {
+    ABSOLUTE_GATE_LUFS + bin as f64 / HISTOGRAM_RESOLUTION
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BiquadState {
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl BiquadState {
+    fn process(&mut self, x: f64, c: &Biquad) -> f64 {
+        let y = c.b0.mul_add(x, c.b1.mul_add(self.x1, c.b2 * self.x2))
+            - c.a1.mul_add(self.y1, c.a2 * self.y2);
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// The BS.1770 K-weighting curve: a high shelf followed by a high pass,
+/// derived for any sample rate.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    pub(crate) fn new(sample_rate: SampleRate) -> Self {
+        let fs = f64::from(sample_rate.as_hz());
+
+        let k = (PI * 1_681.974_450_955_533 / fs).tan();
+        let q = 0.707_175_236_955_419_6;
+        let vh = 10.0f64.powf(3.999_843_853_973_347 / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad {
+            b0: (vh + vb * k / q + k * k) / a0,
+            b1: 2.0 * (k * k - vh) / a0,
+            b2: (vh - vb * k / q + k * k) / a0,
+            a1: 2.0 * (k * k - 1.0) / a0,
+            a2: (1.0 - k / q + k * k) / a0,
+        };
+
+        let k = (PI * 38.135_470_876_024_44 / fs).tan();
+        let q = 0.500_327_037_323_877_3;
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad {
+            b0: 1.0,
+            b1: -2.0,
+            b2: 1.0,
+            a1: 2.0 * (k * k - 1.0) / a0,
+            a2: (1.0 - k / q + k * k) / a0,
+        };
+
+        Self { shelf, high_pass }
+    }
+}
+
+/// Per-channel K-weighting filter memory
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct KWeightingState {
+    shelf: BiquadState,
+    high_pass: BiquadState,
+}
+
+impl KWeightingState {
+    pub(crate) fn process(&mut self, x: f64, k: &KWeighting) -> f64 {
+        let shelved = self.shelf.process(x, &k.shelf);
+        self.high_pass.process(shelved, &k.high_pass)
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// BS.1770 channel weight for each position of a layout
+pub(crate) fn channel_weights(layout: ChannelLayout) -> [f64; 8] {
+    let mut weights = [1.0; 8];
+    for (weight, label) in weights.iter_mut().zip(layout.channel_labels()) {
+        *weight = match *label {
+            "LFE" => 0.0,
+            "RL" | "RR" | "SL" | "SR" => 1.41,
+            _ => 1.0,
+        };
+    }
+    weights
+}
+
+/// 4x oversampling peak detector (polyphase windowed-sinc interpolator).
+#[derive(Debug, Clone)]
+pub(crate) struct TruePeak {
+    phases: [[f32; TAPS_PER_PHASE]; OVERSAMPLING],
+    history: [[f32; TAPS_PER_PHASE]; 8],
+}
+
+impl TruePeak {
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn new() -> Self {
+        let taps = OVERSAMPLING * TAPS_PER_PHASE;
+        let centre = (taps - 1) as f64 / 2.0;
+        let mut phases = [[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+        for n in 0..taps {
+            let x = (n as f64 - centre) / OVERSAMPLING as f64;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let t = 2.0 * PI * n as f64 / (taps - 1) as f64;
+            let blackman = 0.08f64.mul_add((2.0 * t).cos(), 0.5f64.mul_add(-t.cos(), 0.42));
+            #[allow(clippy::cast_possible_truncation)]
+            {
+                phases[n % OVERSAMPLING][n / OVERSAMPLING] = (sinc * blackman) as f32;
+            }
+        }
+        Self {
+            phases,
+            history: [[0.0; TAPS_PER_PHASE]; 8],
+        }
+    }
+
+    /// Feeds one sample of a channel and returns the largest absolute
+    /// interpolated value.
+    pub(crate) fn process(&mut self, channel: usize, x: f32) -> f32 {
+        let history = &mut self.history[channel];
+        history.copy_within(..TAPS_PER_PHASE - 1, 1);
+        history[0] = x;
+        self.phases
+            .iter()
+            .map(|phase| {
+                phase
+                    .iter()
+                    .zip(history.iter())
+                    .map(|(h, x)| h * x)
+                    .sum::<f32>()
+                    .abs()
+            })
+            .fold(x.abs(), f32::max)
+    }
+
+    pub(crate) const fn reset(&mut self) {
+        self.history = [[0.0; TAPS_PER_PHASE]; 8];
+    }
+}
+
+/// One update of a [`LoudnessMeter`], produced every 100 ms.
+///
+/// Loudness values are in LUFS and are negative infinity for silence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessReading {
+    /// Frames since the meter was started or reset
+    pub position: u64,
+    /// Loudness of the last 400 ms
+    pub momentary: f64,
+    /// Loudness of the last 3 s
+    pub short_term: f64,
+    /// Gated loudness since the start
+    pub integrated: f64,
+    /// Highest true peak during the last update interval, in dBTP
+    pub true_peak: f64,
+}
+
+impl LoudnessReading {
+    /// Position in seconds
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn seconds(&self, sample_rate: SampleRate) -> f64 {
+        self.position as f64 / f64::from(sample_rate.as_hz())
+    }
+}
+
+/// EBU R128 loudness meter.
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    format: AudioFormat,
+    channels: usize,
+    filter: KWeighting,
+    states: [KWeightingState; 8],
+    weights: [f64; 8],
+    true_peak: TruePeak,
+    block_frames: usize,
+    block_position: usize,
+    block_energy: f64,
+    block_peak: f32,
+    /// Energies of the most recent 100 ms blocks, as a ring
+    blocks: [f64; SHORT_TERM_BLOCKS],
+    block_count: u64,
+    frames: u64,
+    momentary: f64,
+    short_term: f64,
+    max_momentary: f64,
+    max_short_term: f64,
+    max_true_peak: f32,
+    gating_counts: Vec<u64>,
+    gating_energy: Vec<f64>,
+    range_counts: Vec<u64>,
+}
+
+impl LoudnessMeter {
+    #[must_use]
+    pub fn new(format: AudioFormat) -> Self {
+        let block_frames = format
+            .sample_rate
+            .samples_for_milliseconds(UPDATE_INTERVAL_MS) as usize;
+        Self {
+            format,
+            channels: format.channels.count_usize(),
+            filter: KWeighting::new(format.sample_rate),
+            states: [KWeightingState::default(); 8],
+            weights: channel_weights(format.channels.into()),
+            true_peak: TruePeak::new(),
+            block_frames: block_frames.max(1),
+            block_position: 0,
+            block_energy: 0.0,
+            block_peak: 0.0,
+            blocks: [0.0; SHORT_TERM_BLOCKS],
+            block_count: 0,
+            frames: 0,
+            momentary: f64::NEG_INFINITY,
+            short_term: f64::NEG_INFINITY,
+            max_momentary: f64::NEG_INFINITY,
+            max_short_term: f64::NEG_INFINITY,
+            max_true_peak: 0.0,
+            gating_counts: vec![0; HISTOGRAM_BINS],
+            gating_energy: vec![0.0; HISTOGRAM_BINS],
+            range_counts: vec![0; HISTOGRAM_BINS],
+        }
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Clears all measurements and filter state.
+    pub fn reset(&mut self) {
+        for state in &mut self.states {
+            state.reset();
+        }
+        self.true_peak.reset();
+        self.block_position = 0;
+        self.block_energy = 0.0;
+        self.block_peak = 0.0;
+        self.blocks = [0.0; SHORT_TERM_BLOCKS];
+        self.block_count = 0;
+        self.frames = 0;
+        self.momentary = f64::NEG_INFINITY;
+        self.short_term = f64::NEG_INFINITY;
+        self.max_momentary = f64::NEG_INFINITY;
+        self.max_short_term = f64::NEG_INFINITY;
+        self.max_true_peak = 0.0;
+        self.gating_counts.fill(0);
+        self.gating_energy.fill(0.0);
+        self.range_counts.fill(0);
+    }
+
+    /// Measures a block of interleaved samples, calling `on_reading` each
+    /// time an update interval completes.
+    pub fn process(&mut self, input: &[Sample], mut on_reading: impl FnMut(LoudnessReading)) {
+        for frame in input.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let x = sample.value();
+                let weighted = self.states[channel].process(f64::from(x), &self.filter);
+                self.block_energy += self.weights[channel] * weighted * weighted;
+                self.block_peak = self.block_peak.max(self.true_peak.process(channel, x));
+            }
+            self.frames += 1;
+            self.block_position += 1;
+            if self.block_position == self.block_frames {
+                on_reading(self.finish_block());
+            }
+        }
+    }
+
+    /// Feeds a block without collecting readings.
+    pub fn measure(&mut self, input: &[Sample]) {
+        self.process(input, |_| {});
+    }
+
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    fn finish_block(&mut self) -> LoudnessReading {
+        let slot = (self.block_count % SHORT_TERM_BLOCKS as u64) as usize;
+        self.blocks[slot] = self.block_energy / self.block_frames as f64;
+        self.block_count += 1;
+
+        let recent = |count: usize| -> f64 {
+            (0..count)
+                .map(|back| self.blocks[(slot + SHORT_TERM_BLOCKS - back) % SHORT_TERM_BLOCKS])
+                .sum::<f64>()
+                / count as f64
+        };
+        let momentary_energy = recent(MOMENTARY_BLOCKS);
+        let short_term_energy = recent(SHORT_TERM_BLOCKS);
+
+        if self.block_count >= MOMENTARY_BLOCKS as u64 {
+            self.momentary = energy_to_lufs(momentary_energy);
+            self.max_momentary = self.max_momentary.max(self.momentary);
+            if let Some(bin) = histogram_bin(self.momentary) {
+                self.gating_counts[bin] += 1;
+                self.gating_energy[bin] += momentary_energy;
+            }
+        }
+        if self.block_count >= SHORT_TERM_BLOCKS as u64 {
+            self.short_term = energy_to_lufs(short_term_energy);
+            self.max_short_term = self.max_short_term.max(self.short_term);
+            if let Some(bin) = histogram_bin(self.short_term) {
+                self.range_counts[bin] += 1;
+            }
+        }
+
+        self.max_true_peak = self.max_true_peak.max(self.block_peak);
+        let reading = LoudnessReading {
+            position: self.frames,
+            momentary: self.momentary,
+            short_term: self.short_term,
+            integrated: self.integrated(),
+            true_peak: linear_to_dbtp(self.block_peak),
+        };
+        self.block_position = 0;
+        self.block_energy = 0.0;
+        self.block_peak = 0.0;
+        reading
+    }
+
+    /// Momentary loudness of the last 400 ms, in LUFS
+    #[must_use]
+    pub const fn momentary(&self) -> f64 {
+        self.momentary
+    }
+
+    /// Short-term loudness of the last 3 s, in LUFS
+    #[must_use]
+    pub const fn short_term(&self) -> f64 {
+        self.short_term
+    }
+
+    #[must_use]
+    pub const fn max_momentary(&self) -> f64 {
+        self.max_momentary
+    }
+
+    #[must_use]
+    pub const fn max_short_term(&self) -> f64 {
+        self.max_short_term
+    }
+
+    /// Highest true peak so far, in dBTP
+    #[must_use]
+    pub fn max_true_peak(&self) -> f64 {
+        linear_to_dbtp(self.max_true_peak)
+    }
+
+    /// Gated integrated loudness since the start, in LUFS
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn integrated(&self) -> f64 {
+        let mean_above = |first: usize| -> Option<f64> {
+            let count: u64 = self.gating_counts[first..].iter().sum();
+            (count > 0).then(|| self.gating_energy[first..].iter().sum::<f64>() / count as f64)
+        };
+        let Some(ungated) = mean_above(0) else {
+            return f64::NEG_INFINITY;
+        };
+        let threshold = energy_to_lufs(ungated) + RELATIVE_GATE_LU;
+        let first = histogram_bin(threshold).unwrap_or(0);
+        mean_above(first).map_or(f64::NEG_INFINITY, energy_to_lufs)
+    }
+
+    /// Loudness range (EBU Tech 3342) since the start, in LU
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn loudness_range(&self) -> f64 {
+        let total: u64 = self.range_counts.iter().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let energy: f64 = self
+            .range_counts
+            .iter()
+            .enumerate()
+            .map(|(bin, &count)| count as f64 * lufs_to_energy(bin_lufs(bin)))
+            .sum();
+        let threshold = energy_to_lufs(energy / total as f64) + RANGE_RELATIVE_GATE_LU;
+        let first = histogram_bin(threshold).unwrap_or(0);
+        let gated = &self.range_counts[first..];
+        let count: u64 = gated.iter().sum();
+        if count == 0 {
+            return 0.0;
+        }
+
+        let percentile = |p: f64| -> f64 {
+            let rank = ((count - 1) as f64 * p).round() as u64;
+            let mut seen = 0;
+            for (offset, &bin_count) in gated.iter().enumerate() {
+                seen += bin_count;
+                if seen > rank {
+                    return bin_lufs(first + offset);
+                }
+            }
+            bin_lufs(HISTOGRAM_BINS - 1)
+        };
+        percentile(0.95) - percentile(0.10)
+    }
+}
+
+fn linear_to_dbtp(linear: f32) -> f64 {
+    if linear > 0.0 {
+        20.0 * f64::from(linear).log10()
+    } else {
+        f64::NEG_INFINITY
+    }
+}
@@ -0,0 +1,228 @@
+//! Loudness history logging
+//!
+//! [`LoudnessLogger`] meters audio as it is fed and writes every 100 ms
+//! reading with a UTC timestamp to a CSV or JSON file, as needed for EBU R128
+//! compliance reports. JSON logs end with a summary of the whole programme.
+//!
+//! The logger does file I/O and must not run on the audio thread; feed it from
+//! the thread that drains a recording or streaming ring buffer.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::analysis::loudness::{LoudnessMeter, LoudnessReading};
+use crate::error::Result;
+use crate::scheduler::UtcDateTime;
+use crate::types::{AudioFormat, Sample, SampleRate};
+
+/// File format of a loudness log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LoudnessLogFormat {
+    /// One row per reading with a header line
+    #[default]
+    Csv,
+    /// A single document with the readings and a summary
+    Json,
+}
+
+impl LoudnessLogFormat {
+    /// File extension, without the dot
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// Meters audio and writes the readings to a log.
+#[derive(Debug)]
+pub struct LoudnessLogger<W: Write> {
+    meter: LoudnessMeter,
+    sink: LogSink<W>,
+    finished: bool,
+}
+
+/// The writing half, kept apart so it can be borrowed while the meter runs
+#[derive(Debug)]
+struct LogSink<W: Write> {
+    writer: W,
+    log_format: LoudnessLogFormat,
+    sample_rate: SampleRate,
+    started_at: SystemTime,
+    entries: u64,
+    line: String,
+    error: Option<std::io::Error>,
+}
+
+impl LoudnessLogger<BufWriter<File>> {
+    /// Creates (or truncates) a log file at `path`, timestamped from now.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be created or the header can't be written.
+    pub fn create(
+        path: impl AsRef<Path>,
+        format: AudioFormat,
+        log_format: LoudnessLogFormat,
+    ) -> Result<Self> {
+        let file = File::create(path)?;
+        Self::new(BufWriter::new(file), format, log_format, SystemTime::now())
+    }
+}
+
+impl<W: Write> LoudnessLogger<W> {
+    /// Wraps a writer and writes the log header. Readings are timestamped
+    /// relative to `started_at`, the wall-clock time of the first sample.
+    ///
+    /// # Errors
+    /// Returns an error if the header can't be written.
+    pub fn new(
+        mut writer: W,
+        format: AudioFormat,
+        log_format: LoudnessLogFormat,
+        started_at: SystemTime,
+    ) -> Result<Self> {
+        match log_format {
+            LoudnessLogFormat::Csv => writer.write_all(
+                b"time,elapsed_s,momentary_lufs,short_term_lufs,integrated_lufs,true_peak_dbtp\n",
+            )?,
+            LoudnessLogFormat::Json => write!(
+                writer,
+                "{{\n  \"sample_rate\": {},\n  \"started_at\": \"{}\",\n  \"readings\": [",
+                format.sample_rate.as_hz(),
+                UtcDateTime::from_system_time(started_at)
+            )?,
+        }
+        Ok(Self {
+            meter: LoudnessMeter::new(format),
+            sink: LogSink {
+                writer,
+                log_format,
+                sample_rate: format.sample_rate,
+                started_at,
+                entries: 0,
+                line: String::with_capacity(128),
+                error: None,
+            },
+            finished: false,
+        })
+    }
+
+    /// The meter behind the log, for live display
+    #[must_use]
+    pub const fn meter(&self) -> &LoudnessMeter {
+        &self.meter
+    }
+
+    /// Number of readings written
+    #[must_use]
+    pub const fn entries(&self) -> u64 {
+        self.sink.entries
+    }
+
+    /// Measures interleaved samples and logs every completed reading.
+    ///
+    /// # Errors
+    /// Returns an error if a reading can't be written.
+    pub fn process(&mut self, input: &[Sample]) -> Result<()> {
+        let sink = &mut self.sink;
+        self.meter
+            .process(input, |reading| sink.write_reading(&reading));
+        self.sink
+            .error
+            .take()
+            .map_or(Ok(()), |error| Err(error.into()))
+    }
+
+    /// Completes the log and flushes it. JSON logs get a closing summary.
+    ///
+    /// # Errors
+    /// Returns an error if the log can't be written.
+    pub fn finish(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        if self.sink.log_format == LoudnessLogFormat::Json {
+            let meter = &self.meter;
+            write!(
+                self.sink.writer,
+                "\n  ],\n  \"summary\": {{ \"integrated\": {}, \"loudness_range\": {:.1}, \
+                 \"max_momentary\": {}, \"max_short_term\": {}, \"max_true_peak\": {} }}\n}}\n",
+                json_value(meter.integrated()),
+                meter.loudness_range(),
+                json_value(meter.max_momentary()),
+                json_value(meter.max_short_term()),
+                json_value(meter.max_true_peak()),
+            )?;
+        }
+        self.sink.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> LogSink<W> {
+    fn write_reading(&mut self, reading: &LoudnessReading) {
+        if self.error.is_some() {
+            return;
+        }
+        let elapsed = reading.seconds(self.sample_rate);
+        let time =
+            UtcDateTime::from_system_time(self.started_at + Duration::from_secs_f64(elapsed));
+        self.line.clear();
+        let _ = match self.log_format {
+            LoudnessLogFormat::Csv => writeln!(
+                self.line,
+                "{time},{elapsed:.3},{},{},{},{}",
+                csv_value(reading.momentary),
+                csv_value(reading.short_term),
+                csv_value(reading.integrated),
+                csv_value(reading.true_peak),
+            ),
+            LoudnessLogFormat::Json => write!(
+                self.line,
+                "{}\n    {{ \"time\": \"{time}\", \"elapsed\": {elapsed:.3}, \"momentary\": {}, \
+                 \"short_term\": {}, \"integrated\": {}, \"true_peak\": {} }}",
+                if self.entries == 0 { "" } else { "," },
+                json_value(reading.momentary),
+                json_value(reading.short_term),
+                json_value(reading.integrated),
+                json_value(reading.true_peak),
+            ),
+        };
+        match self.writer.write_all(self.line.as_bytes()) {
+            Ok(()) => self.entries += 1,
+            Err(error) => self.error = Some(error),
+        }
+    }
+}
+
+impl<W: Write> Drop for LoudnessLogger<W> {
+    fn drop(&mut self) {
+        if let Err(error) = self.finish() {
+            log::error!("failed to finish loudness log: {error}");
+        }
+    }
+}
+
+/// Formats a level for CSV, leaving silence empty.
+fn csv_value(value: f64) -> String {
+    if value.is_finite() {
+        format!("{value:.1}")
+    } else {
+        String::new()
+    }
+}
+
+/// Formats a level for JSON, writing silence as `null`.
+fn json_value(value: f64) -> String {
+    if value.is_finite() {
+        format!("{value:.1}")
+    } else {
+        "null".to_string()
+    }
+}
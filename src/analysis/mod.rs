@@ -0,0 +1,27 @@
+//! Signal analysis
+//!
+//! Meters and analysers that observe audio without changing it.
+
+pub mod alignment;
+pub mod exposure;
+pub mod key;
+pub mod loudness;
+pub mod loudness_log;
+pub mod mono_compat;
+pub mod music;
+pub mod replay_gain;
+pub mod spectrum;
+pub mod tempo;
+pub mod waveform;
+
+pub use alignment::{AlignmentAnalyzer, ChannelAlignment};
+pub use exposure::{ExposureLimit, ExposureMeter, ExposureSettings, ExposureWarning};
+pub use key::{KeyAnalyzer, KeyEstimate, MusicalKey};
+pub use loudness::{LoudnessMeter, LoudnessReading};
+pub use loudness_log::{LoudnessLogFormat, LoudnessLogger};
+pub use mono_compat::{MonoCompatAnalyzer, MonoCompatibility};
+pub use music::MusicAnalysis;
+pub use replay_gain::ReplayGain;
+pub use spectrum::{SpectrumAnalyzer, SpectrumProfile};
+pub use tempo::{TempoAnalyzer, TempoEstimate};
+pub use waveform::{Peak, PeakLevel, WaveformPeaks};
@@ -0,0 +1,438 @@
+//! Mono fold-down compatibility
+//!
+//! [`MonoCompatAnalyzer`] compares the spectrum of a stereo mix with that
+//! of its mono fold-down, `(L + R) / 2`, in third-octave bands from
+//! 31.5 Hz to 16 kHz. A band whose channels are the same loses nothing in
+//! mono, one whose channels are unrelated loses 3 dB, and one whose
+//! channels are out of phase cancels. Bands that lose more than the
+//! threshold (6 dB by default) on average are flagged as cancelling: wide
+//! stereo effects, phase-flipped mics or Haas delays that will thin out
+//! or vanish on a mono speaker.
+//!
+//! The result, a [`MonoCompatibility`], is plain data for a UI to show:
+//! the loss of every band, the worst moment of each, and the overall
+//! phase correlation. Levels are floored at -120 dB and losses capped at
+//! 60 dB, so they stay finite.
+//!
+//! ```no_run
+//! use audio_engine::analysis::mono_compat;
+//!
+//! let report = mono_compat::analyze_file("mix.wav")?;
+//! for band in report.cancelling_bands() {
+//!     println!("{} Hz loses {:.1} dB in mono", band.center_hz, band.loss_db);
+//! }
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::buffer::memory::heap_bytes;
+use crate::dsp::fft::{Fft, hann_window};
+use crate::error::{AudioEngineError, Result};
+use crate::io::wav::WavReader;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+const FFT_SIZE: usize = 8192;
+const HOP: usize = 4096;
+
+/// Frames read per pass through the file
+const READ_FRAMES: usize = 8192;
+
+/// Frames below this mean power (-70 dBFS) aren't compared
+const SILENCE_POWER: f64 = 1e-7;
+
+/// Number of third-octave bands
+pub const BANDS: usize = 28;
+
+/// Lowest level reported, in dB
+pub const FLOOR_DB: f32 = -120.0;
+
+/// Largest loss reported, in dB
+pub const MAX_LOSS_DB: f32 = 60.0;
+
+/// Default loss, in dB, above which a band counts as cancelling
+pub const DEFAULT_THRESHOLD_DB: f32 = 6.0;
+
+/// Bands further than this below the loudest one, in dB, hold too little
+/// of the mix to count as cancelling
+const AUDIBLE_RANGE_DB: f32 = 40.0;
+
+/// Centre frequencies of the third-octave bands, in Hz
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn band_centers() -> [f32; BANDS] {
+    std::array::from_fn(|band| 1000.0 * ((band as f32 - 15.0) / 3.0).exp2())
+}
+
+/// How one band fares in mono.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BandCompatibility {
+    pub center_hz: f32,
+    /// Average level of the two channels, in dB relative to white noise
+    /// of unit power
+    pub stereo_db: f32,
+    /// Level of the fold-down, on the same scale
+    pub mono_db: f32,
+    /// How much quieter the band is in mono on average, in dB
+    pub loss_db: f32,
+    /// Largest loss in any one analysis frame while the band was playing
+    pub worst_loss_db: f32,
+    /// When that frame was, from the start
+    pub worst_at: Duration,
+    /// Whether the average loss exceeds the threshold in a band within
+    /// 40 dB of the loudest
+    pub cancelling: bool,
+}
+
+/// How a mix folds down to mono.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MonoCompatibility {
+    /// Every band, lowest first
+    pub bands: Vec<BandCompatibility>,
+    /// Correlation of the two channels, from -1 (opposite) through 0
+    /// (unrelated) to 1 (the same)
+    pub correlation: f32,
+    /// How much quieter the whole mix is in mono, in dB
+    pub loss_db: f32,
+    /// Loss above which bands count as cancelling
+    pub threshold_db: f32,
+}
+
+impl MonoCompatibility {
+    /// The bands that lose more than the threshold
+    pub fn cancelling_bands(&self) -> impl Iterator<Item = &BandCompatibility> {
+        self.bands.iter().filter(|band| band.cancelling)
+    }
+
+    /// Whether no band cancels
+    #[must_use]
+    pub fn is_compatible(&self) -> bool {
+        self.cancelling_bands().next().is_none()
+    }
+}
+
+impl fmt::Display for MonoCompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "correlation {:+.2}, {:.1} dB quieter in mono",
+            self.correlation, self.loss_db
+        )?;
+        if self.is_compatible() {
+            return write!(f, ", no band loses more than {:.0} dB", self.threshold_db);
+        }
+        write!(f, ", cancels at")?;
+        for (index, band) in self.cancelling_bands().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+            write!(
+                f,
+                "{separator}{:.0} Hz ({:.1} dB)",
+                band.center_hz, band.loss_db
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares the spectra of the stereo input and its mono fold-down.
+#[derive(Debug, Clone)]
+pub struct MonoCompatAnalyzer {
+    fft: Fft,
+    sample_rate: SampleRate,
+    window: Vec<f32>,
+    /// Sum of the squared window, which scales bin power to density
+    window_power: f64,
+    left: Vec<f32>,
+    right: Vec<f32>,
+    left_re: Vec<f32>,
+    left_im: Vec<f32>,
+    right_re: Vec<f32>,
+    right_im: Vec<f32>,
+    /// FFT bins of each band
+    band_bins: [(usize, usize); BANDS],
+    threshold_db: f32,
+    stereo_power: [f64; BANDS],
+    mono_power: [f64; BANDS],
+    worst: [(f32, u64); BANDS],
+    /// Sums over every sample for the correlation
+    left_energy: f64,
+    right_energy: f64,
+    cross: f64,
+    /// Frames compared, and frames seen including silent ones
+    frames: u64,
+    hops: u64,
+}
+
+impl MonoCompatAnalyzer {
+    /// # Panics
+    /// Never; the FFT size is a fixed power of two.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn new(sample_rate: SampleRate) -> Self {
+        let bin_hz = sample_rate.as_f32() / FFT_SIZE as f32;
+        let edge = (1.0f32 / 6.0).exp2();
+        let band_bins = band_centers().map(|hz| {
+            let low = ((hz / edge / bin_hz).ceil() as usize).clamp(1, FFT_SIZE / 2);
+            let high = ((hz * edge / bin_hz).ceil() as usize).min(FFT_SIZE / 2);
+            // Narrow low bands get at least their nearest bin
+            (low, high.max((low + 1).min(FFT_SIZE / 2)))
+        });
+        let mut window = vec![0.0; FFT_SIZE];
+        hann_window(&mut window);
+        Self {
+            fft: Fft::new(FFT_SIZE).expect("FFT size is a power of two"),
+            sample_rate,
+            window_power: window.iter().map(|&w| f64::from(w * w)).sum(),
+            window,
+            left: Vec::with_capacity(FFT_SIZE),
+            right: Vec::with_capacity(FFT_SIZE),
+            left_re: vec![0.0; FFT_SIZE],
+            left_im: vec![0.0; FFT_SIZE],
+            right_re: vec![0.0; FFT_SIZE],
+            right_im: vec![0.0; FFT_SIZE],
+            band_bins,
+            threshold_db: DEFAULT_THRESHOLD_DB,
+            stereo_power: [0.0; BANDS],
+            mono_power: [0.0; BANDS],
+            worst: [(0.0, 0); BANDS],
+            left_energy: 0.0,
+            right_energy: 0.0,
+            cross: 0.0,
+            frames: 0,
+            hops: 0,
+        }
+    }
+
+    /// Sets the loss, in dB, above which a band counts as cancelling.
+    #[must_use]
+    pub const fn with_threshold_db(mut self, db: f32) -> Self {
+        self.threshold_db = db;
+        self
+    }
+
+    #[must_use]
+    pub const fn threshold_db(&self) -> f32 {
+        self.threshold_db
+    }
+
+    /// Frames compared so far
+    #[must_use]
+    pub const fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Bytes of the FFT tables and frame buffers
+    #[must_use]
+    pub const fn memory_bytes(&self) -> usize {
+        self.fft.memory_bytes()
+            + heap_bytes(&self.window)
+            + heap_bytes(&self.left)
+            + heap_bytes(&self.right)
+            + heap_bytes(&self.left_re)
+            + heap_bytes(&self.left_im)
+            + heap_bytes(&self.right_re)
+            + heap_bytes(&self.right_im)
+    }
+
+    /// Forgets everything fed so far.
+    pub fn reset(&mut self) {
+        self.left.clear();
+        self.right.clear();
+        self.stereo_power = [0.0; BANDS];
+        self.mono_power = [0.0; BANDS];
+        self.worst = [(0.0, 0); BANDS];
+        self.left_energy = 0.0;
+        self.right_energy = 0.0;
+        self.cross = 0.0;
+        self.frames = 0;
+        self.hops = 0;
+    }
+
+    /// Feeds interleaved samples. The first two channels are compared;
+    /// mono input is its own fold-down and never cancels. Doesn't
+    /// allocate.
+    pub fn process(&mut self, samples: &[Sample], channels: ChannelCount) {
+        let count = channels.count_usize();
+        for frame in samples.chunks_exact(count) {
+            let left = frame[0].value();
+            let right = frame.get(1).map_or(left, |sample| sample.value());
+            self.left_energy += f64::from(left * left);
+            self.right_energy += f64::from(right * right);
+            self.cross += f64::from(left * right);
+            self.left.push(left);
+            self.right.push(right);
+            if self.left.len() == FFT_SIZE {
+                self.analyze_frame();
+                self.left.drain(..HOP);
+                self.right.drain(..HOP);
+            }
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    fn analyze_frame(&mut self) {
+        self.hops += 1;
+        for (index, &w) in self.window.iter().enumerate() {
+            self.left_re[index] = self.left[index] * w;
+            self.right_re[index] = self.right[index] * w;
+        }
+        self.left_im.fill(0.0);
+        self.right_im.fill(0.0);
+        self.fft.forward(&mut self.left_re, &mut self.left_im);
+        self.fft.forward(&mut self.right_re, &mut self.right_im);
+
+        // Densities of a bin in the channels and in the fold-down
+        let scale = 1.0 / self.window_power;
+        let densities = |k: usize| {
+            let (lr, li) = (self.left_re[k], self.left_im[k]);
+            let (rr, ri) = (self.right_re[k], self.right_im[k]);
+            let stereo = f32::midpoint(lr.mul_add(lr, li * li), rr.mul_add(rr, ri * ri));
+            let (mr, mi) = (f32::midpoint(lr, rr), f32::midpoint(li, ri));
+            let mono = mr.mul_add(mr, mi * mi);
+            (f64::from(stereo) * scale, f64::from(mono) * scale)
+        };
+        let total = (1..FFT_SIZE / 2).map(|k| densities(k).0).sum::<f64>() / (FFT_SIZE / 2) as f64;
+        if total < SILENCE_POWER {
+            return;
+        }
+
+        self.frames += 1;
+        let weight = 1.0 / self.frames as f64;
+        for band in 0..BANDS {
+            let (low, high) = self.band_bins[band];
+            if high <= low {
+                continue;
+            }
+            let (stereo, mono) = (low..high)
+                .map(densities)
+                .fold((0.0, 0.0), |(s, m), (stereo, mono)| (s + stereo, m + mono));
+            let bins = (high - low) as f64;
+            let (stereo, mono) = (stereo / bins, mono / bins);
+            self.stereo_power[band] += (stereo - self.stereo_power[band]) * weight;
+            self.mono_power[band] += (mono - self.mono_power[band]) * weight;
+            if stereo >= SILENCE_POWER {
+                let loss = loss_db(stereo, mono);
+                if loss > self.worst[band].0 {
+                    self.worst[band] = (loss, self.hops);
+                }
+            }
+        }
+    }
+
+    /// What the input so far says about its fold-down, or `None` before a
+    /// frame louder than the silence threshold
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    pub fn report(&self) -> Option<MonoCompatibility> {
+        if self.frames == 0 {
+            return None;
+        }
+        let rate = f64::from(self.sample_rate.as_hz());
+        let loudest = level_db(self.stereo_power.iter().copied().fold(0.0, f64::max));
+        let bands = band_centers()
+            .iter()
+            .zip(self.stereo_power.iter().zip(&self.mono_power))
+            .zip(&self.worst)
+            .map(|((&center_hz, (&stereo, &mono)), &(worst_loss_db, hop))| {
+                let loss_db = loss_db(stereo, mono);
+                // Middle of the worst frame; frame n starts n - 1 hops in
+                let middle =
+                    (hop.saturating_sub(1) as f64).mul_add(HOP as f64, FFT_SIZE as f64 / 2.0);
+                BandCompatibility {
+                    center_hz,
+                    stereo_db: level_db(stereo),
+                    mono_db: level_db(mono),
+                    loss_db,
+                    worst_loss_db,
+                    worst_at: Duration::from_secs_f64(middle / rate),
+                    cancelling: loss_db > self.threshold_db
+                        && level_db(stereo) >= loudest - AUDIBLE_RANGE_DB,
+                }
+            })
+            .collect();
+        let energy = self.left_energy * self.right_energy;
+        let correlation = if energy > 0.0 {
+            (self.cross / energy.sqrt()) as f32
+        } else {
+            1.0
+        };
+        // Power of (L + R) / 2 against the average power of L and R
+        let stereo = f64::midpoint(self.left_energy, self.right_energy);
+        let mono = 2.0f64.mul_add(self.cross, self.left_energy + self.right_energy) / 4.0;
+        Some(MonoCompatibility {
+            bands,
+            correlation,
+            loss_db: loss_db(stereo, mono),
+            threshold_db: self.threshold_db,
+        })
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn level_db(power: f64) -> f32 {
+    if power > 0.0 {
+        ((10.0 * power.log10()) as f32).max(FLOOR_DB)
+    } else {
+        FLOOR_DB
+    }
+}
+
+/// How much quieter `mono` is than `stereo`, in dB, capped
+#[allow(clippy::cast_possible_truncation)]
+fn loss_db(stereo: f64, mono: f64) -> f32 {
+    if stereo <= 0.0 {
+        return 0.0;
+    }
+    if mono <= 0.0 {
+        return MAX_LOSS_DB;
+    }
+    ((10.0 * (stereo / mono).log10()) as f32).clamp(0.0, MAX_LOSS_DB)
+}
+
+/// Folds interleaved audio down to mono: the average of the first two
+/// channels, or the one channel of mono audio.
+#[must_use]
+pub fn fold_down(samples: &[Sample], channels: ChannelCount) -> Vec<Sample> {
+    samples
+        .chunks_exact(channels.count_usize())
+        .map(|frame| match frame {
+            [left, right, ..] => Sample::new(f32::midpoint(left.value(), right.value())),
+            [only] => *only,
+            [] => Sample::SILENCE,
+        })
+        .collect()
+}
+
+/// Checks how the WAV file at `path` folds down to mono.
+///
+/// # Errors
+/// Returns an error if the file can't be read or isn't a supported WAV
+/// file, or is silent throughout.
+pub fn analyze_file(path: impl AsRef<Path>) -> Result<MonoCompatibility> {
+    let path = path.as_ref();
+    let mut reader = WavReader::open(path)?;
+    let format = reader.format();
+    let mut analyzer = MonoCompatAnalyzer::new(format.sample_rate);
+    let mut buffer = vec![Sample::SILENCE; READ_FRAMES * format.channels.count_usize()];
+    loop {
+        let count = reader.read_samples(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        analyzer.process(&buffer[..count], format.channels);
+    }
+    analyzer.report().ok_or_else(|| {
+        AudioEngineError::configuration(format!(
+            "{} is too short or too quiet to check in mono",
+            path.display()
+        ))
+    })
+}
@@ -0,0 +1,59 @@
+//! Offline tempo and key analysis of music files
+
+use std::path::Path;
+
+use crate::analysis::key::{KeyAnalyzer, KeyEstimate};
+use crate::analysis::tempo::{TempoAnalyzer, TempoEstimate};
+use crate::error::Result;
+use crate::io::wav::WavReader;
+use crate::types::Sample;
+
+/// Frames read per pass through the file
+const READ_FRAMES: usize = 8192;
+
+/// Tempo and key of a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MusicAnalysis {
+    /// Length of the file in seconds
+    pub duration_seconds: f64,
+    /// `None` if the file is too short or has no detectable pulse
+    pub tempo: Option<TempoEstimate>,
+    /// `None` if the file is silent
+    pub key: Option<KeyEstimate>,
+}
+
+/// Estimates the tempo and key of a WAV file in a single pass.
+///
+/// # Errors
+/// Returns an error if the file can't be read or isn't a supported WAV file.
+#[allow(clippy::cast_precision_loss)]
+pub fn analyze_file(path: impl AsRef<Path>) -> Result<MusicAnalysis> {
+    let mut reader = WavReader::open(path)?;
+    let format = reader.format();
+    let channels = format.channels.count_usize();
+    let mut tempo = TempoAnalyzer::new(format.sample_rate);
+    let mut key = KeyAnalyzer::new(format.sample_rate);
+
+    let mut buffer = vec![Sample::SILENCE; READ_FRAMES * channels];
+    let mut mono = Vec::with_capacity(READ_FRAMES);
+    loop {
+        let count = reader.read_samples(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        mono.clear();
+        mono.extend(
+            buffer[..count]
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().map(|s| s.value()).sum::<f32>() / channels as f32),
+        );
+        tempo.process(&mono);
+        key.process(&mono);
+    }
+
+    Ok(MusicAnalysis {
+        duration_seconds: reader.frames() as f64 / f64::from(format.sample_rate.as_hz()),
+        tempo: tempo.estimate(),
+        key: key.estimate(),
+    })
+}
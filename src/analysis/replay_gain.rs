@@ -0,0 +1,130 @@
+//! Replay Gain 2.0 track analysis
+//!
+//! Replay Gain 2.0 is based on EBU R128 loudness: the track gain is the
+//! difference between the track's integrated loudness and a -18 LUFS
+//! reference, and the peak is the true peak as a linear amplitude.
+//! [`write_tags`] stores the result as ID3 v2.4 `TXXX` frames in an `id3 `
+//! chunk, which is where tag editors and players look for tags in WAV files.
+
+use std::path::Path;
+
+use crate::analysis::loudness::LoudnessMeter;
+use crate::error::{AudioEngineError, Result};
+use crate::io::wav::{self, WavReader};
+use crate::types::Sample;
+
+/// Loudness Replay Gain 2.0 normalises to
+pub const REFERENCE_LUFS: f64 = -18.0;
+
+/// Frames read per pass through the file
+const READ_FRAMES: usize = 4096;
+
+/// Result of a Replay Gain analysis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayGain {
+    /// Integrated loudness of the track, in LUFS
+    pub integrated_lufs: f64,
+    /// Gain to apply to reach the reference loudness, in dB
+    pub track_gain_db: f64,
+    /// True peak as a linear amplitude (1.0 is full scale)
+    pub track_peak: f64,
+}
+
+impl ReplayGain {
+    /// Derives the gain and peak from a meter that has measured a whole track.
+    ///
+    /// Silent tracks get a gain of 0 dB.
+    #[must_use]
+    pub fn from_meter(meter: &LoudnessMeter) -> Self {
+        let integrated_lufs = meter.integrated();
+        let track_gain_db = if integrated_lufs.is_finite() {
+            REFERENCE_LUFS - integrated_lufs
+        } else {
+            0.0
+        };
+        let peak_db = meter.max_true_peak();
+        Self {
+            integrated_lufs,
+            track_gain_db,
+            track_peak: if peak_db.is_finite() {
+                10.0f64.powf(peak_db / 20.0)
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// The standard tag names and values for this result
+    #[must_use]
+    pub fn tags(&self) -> [(&'static str, String); 3] {
+        [
+            (
+                "REPLAYGAIN_TRACK_GAIN",
+                format!("{:.2} dB", self.track_gain_db),
+            ),
+            ("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", self.track_peak)),
+            (
+                "REPLAYGAIN_REFERENCE_LOUDNESS",
+                format!("{REFERENCE_LUFS:.2} LUFS"),
+            ),
+        ]
+    }
+}
+
+/// Analyses a WAV file.
+///
+/// # Errors
+/// Returns an error if the file can't be read or isn't a supported WAV file.
+pub fn analyze_file(path: impl AsRef<Path>) -> Result<ReplayGain> {
+    let mut reader = WavReader::open(path)?;
+    let format = reader.format();
+    let mut meter = LoudnessMeter::new(format);
+    let mut buffer = vec![Sample::SILENCE; READ_FRAMES * format.channels.count_usize()];
+    loop {
+        let count = reader.read_samples(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        meter.measure(&buffer[..count]);
+    }
+    Ok(ReplayGain::from_meter(&meter))
+}
+
+/// Writes the Replay Gain tags into a WAV file, replacing any existing ID3 tag.
+///
+/// # Errors
+/// Returns an error if the file can't be modified.
+pub fn write_tags(path: impl AsRef<Path>, gain: &ReplayGain) -> Result<()> {
+    let mut frames = Vec::new();
+    for (description, value) in gain.tags() {
+        let mut content = vec![3]; // UTF-8
+        content.extend_from_slice(description.as_bytes());
+        content.push(0);
+        content.extend_from_slice(value.as_bytes());
+        frames.extend_from_slice(b"TXXX");
+        frames.extend_from_slice(&syncsafe(content.len())?);
+        frames.extend_from_slice(&[0, 0]);
+        frames.extend_from_slice(&content);
+    }
+
+    let mut tag = Vec::with_capacity(frames.len() + 10);
+    tag.extend_from_slice(b"ID3");
+    tag.extend_from_slice(&[4, 0, 0]);
+    tag.extend_from_slice(&syncsafe(frames.len())?);
+    tag.extend_from_slice(&frames);
+    wav::write_chunk(path, *b"id3 ", &tag)
+}
+
+/// Encodes a size as an ID3 v2 synchsafe integer (7 bits per byte).
+fn syncsafe(size: usize) -> Result<[u8; 4]> {
+    let size = u32::try_from(size)
+        .ok()
+        .filter(|&size| size < 1 << 28)
+        .ok_or_else(|| AudioEngineError::numeric_conversion("ID3 tag too large"))?;
+    Ok([
+        ((size >> 21) & 0x7F) as u8,
+        ((size >> 14) & 0x7F) as u8,
+        ((size >> 7) & 0x7F) as u8,
+        (size & 0x7F) as u8,
+    ])
+}
@@ -0,0 +1,314 @@
+//! Long-term average spectrum
+//!
+//! [`SpectrumAnalyzer`] averages the power spectrum of its input over
+//! octave bands centred from 31.25 Hz to 16 kHz. Levels are power
+//! densities in dB relative to full scale white noise, so white noise
+//! measures flat and pink noise falls 3 dB per octave. Frames quieter than
+//! -70 dBFS are left out of the average, so pauses don't pull it down.
+//!
+//! Two [`SpectrumProfile`]s give the correction that makes one sound like
+//! the other; see [`SpectrumProfile::correction`] and
+//! [`EqMatch`](crate::dsp::eq_match::EqMatch).
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::buffer::memory::heap_bytes;
+use crate::dsp::fft::{Fft, hann_window};
+use crate::error::{AudioEngineError, Result};
+use crate::io::wav::WavReader;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+const FFT_SIZE: usize = 8192;
+const HOP: usize = 4096;
+
+/// Frames read per pass through the file
+const READ_FRAMES: usize = 8192;
+
+/// Frames below this mean power (-70 dBFS) aren't averaged
+const SILENCE_POWER: f64 = 1e-7;
+
+/// Number of octave bands
+pub const BANDS: usize = 10;
+
+/// Centre frequencies of the octave bands, in Hz
+pub const BAND_CENTERS: [f32; BANDS] = [
+    31.25, 62.5, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+/// Level of each octave band, in dB.
+///
+/// Bands above the Nyquist frequency of the analysed audio are
+/// `-inf`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpectrumProfile {
+    levels_db: [f32; BANDS],
+}
+
+impl SpectrumProfile {
+    #[must_use]
+    pub const fn from_levels(levels_db: [f32; BANDS]) -> Self {
+        Self { levels_db }
+    }
+
+    /// A straight line through 0 dB at 1 kHz, sloping `db_per_octave`;
+    /// -3 is pink noise and about -4.5 the average of commercial mixes.
+    #[must_use]
+    pub fn tilt(db_per_octave: f32) -> Self {
+        Self {
+            levels_db: BAND_CENTERS.map(|hz| db_per_octave * (hz / 1000.0).log2()),
+        }
+    }
+
+    #[must_use]
+    pub const fn levels_db(&self) -> &[f32; BANDS] {
+        &self.levels_db
+    }
+
+    /// Slope of the best fitting straight line through the levels, in dB
+    /// per octave, or `None` with fewer than two bands measured
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn tilt_db_per_octave(&self) -> Option<f32> {
+        let points: Vec<(f32, f32)> = self
+            .levels_db
+            .iter()
+            .enumerate()
+            .filter(|(_, level)| level.is_finite())
+            .map(|(octave, &level)| (octave as f32, level))
+            .collect();
+        if points.len() < 2 {
+            return None;
+        }
+        let count = points.len() as f32;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f32>() / count;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f32>() / count;
+        let (covariance, variance) = points.iter().fold((0.0, 0.0), |(c, v), (x, y)| {
+            (
+                (x - mean_x).mul_add(y - mean_y, c),
+                (x - mean_x).mul_add(x - mean_x, v),
+            )
+        });
+        Some(covariance / variance)
+    }
+
+    /// Gain per band, in dB, that moves this spectrum towards `target`.
+    ///
+    /// Only the shape is matched: the overall level is left alone, so the
+    /// gains average 0 dB before they are limited to `max_db` either way.
+    /// Bands missing from either profile get 0 dB.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn correction(&self, target: &Self, max_db: f32) -> [f32; BANDS] {
+        let mut gains = [0.0; BANDS];
+        let mut measured = 0;
+        for ((gain, &level), &wanted) in
+            gains.iter_mut().zip(&self.levels_db).zip(&target.levels_db)
+        {
+            if level.is_finite() && wanted.is_finite() {
+                *gain = wanted - level;
+                measured += 1;
+            }
+        }
+        if measured == 0 {
+            return gains;
+        }
+        let mean = gains.iter().sum::<f32>() / measured as f32;
+        for ((gain, &level), &wanted) in
+            gains.iter_mut().zip(&self.levels_db).zip(&target.levels_db)
+        {
+            if level.is_finite() && wanted.is_finite() {
+                *gain = (*gain - mean).clamp(-max_db, max_db);
+            }
+        }
+        gains
+    }
+}
+
+/// Averages the octave band spectrum of interleaved audio.
+#[derive(Debug, Clone)]
+pub struct SpectrumAnalyzer {
+    fft: Fft,
+    window: Vec<f32>,
+    /// Sum of the squared window, which scales bin power to density
+    window_power: f64,
+    input: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    /// FFT bins of each band
+    band_bins: [(usize, usize); BANDS],
+    /// Weight of the newest frame in an exponential average, `None` to
+    /// average every frame equally
+    smoothing: Option<f64>,
+    power: [f64; BANDS],
+    frames: u64,
+}
+
+impl SpectrumAnalyzer {
+    /// Averages everything fed to it equally.
+    ///
+    /// # Panics
+    /// Never; the FFT size is a fixed power of two.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn new(sample_rate: SampleRate) -> Self {
+        let bin_hz = sample_rate.as_f32() / FFT_SIZE as f32;
+        let band_bins = BAND_CENTERS.map(|hz| {
+            let low = (hz * std::f32::consts::FRAC_1_SQRT_2 / bin_hz).ceil() as usize;
+            let high = ((hz * std::f32::consts::SQRT_2 / bin_hz).ceil() as usize).min(FFT_SIZE / 2);
+            (low.max(1), high.max(low.max(1)))
+        });
+        let mut window = vec![0.0; FFT_SIZE];
+        hann_window(&mut window);
+        Self {
+            fft: Fft::new(FFT_SIZE).expect("FFT size is a power of two"),
+            window_power: window.iter().map(|&w| f64::from(w * w)).sum(),
+            window,
+            input: Vec::with_capacity(FFT_SIZE),
+            re: vec![0.0; FFT_SIZE],
+            im: vec![0.0; FFT_SIZE],
+            band_bins,
+            smoothing: None,
+            power: [0.0; BANDS],
+            frames: 0,
+        }
+    }
+
+    /// Makes the average exponential, forgetting with a time constant of
+    /// `time`, so it follows changes in the input.
+    #[must_use]
+    pub fn with_time_constant(mut self, time: Duration, sample_rate: SampleRate) -> Self {
+        self.set_time_constant(time, sample_rate);
+        self
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn set_time_constant(&mut self, time: Duration, sample_rate: SampleRate) {
+        let hops = time.as_secs_f64() * f64::from(sample_rate.as_hz()) / HOP as f64;
+        self.smoothing = Some(1.0 - (-1.0 / hops.max(1.0)).exp());
+    }
+
+    /// Frames averaged so far
+    #[must_use]
+    pub const fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Bytes of the FFT tables and frame buffers
+    #[must_use]
+    pub const fn memory_bytes(&self) -> usize {
+        self.fft.memory_bytes()
+            + heap_bytes(&self.window)
+            + heap_bytes(&self.input)
+            + heap_bytes(&self.re)
+            + heap_bytes(&self.im)
+    }
+
+    /// Forgets everything fed so far.
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.power = [0.0; BANDS];
+        self.frames = 0;
+    }
+
+    /// Feeds interleaved samples, mixed down to mono. Doesn't allocate.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn process(&mut self, samples: &[Sample], channels: ChannelCount) {
+        let count = channels.count_usize();
+        let scale = 1.0 / count as f32;
+        for frame in samples.chunks_exact(count) {
+            self.input
+                .push(frame.iter().map(|s| s.value()).sum::<f32>() * scale);
+            if self.input.len() == FFT_SIZE {
+                self.analyze_frame();
+                self.input.drain(..HOP);
+            }
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn analyze_frame(&mut self) {
+        for ((re, im), (&x, &w)) in self
+            .re
+            .iter_mut()
+            .zip(self.im.iter_mut())
+            .zip(self.input.iter().zip(&self.window))
+        {
+            *re = x * w;
+            *im = 0.0;
+        }
+        self.fft.forward(&mut self.re, &mut self.im);
+
+        // Density of each bin; the one-sided spectrum holds half the power
+        let density = |k: usize| {
+            2.0 * f64::from(self.re[k].mul_add(self.re[k], self.im[k] * self.im[k]))
+                / self.window_power
+        };
+        let total = (1..FFT_SIZE / 2).map(density).sum::<f64>() / FFT_SIZE as f64;
+        if total < SILENCE_POWER {
+            return;
+        }
+
+        self.frames += 1;
+        let weight = self
+            .smoothing
+            .unwrap_or(1.0 / self.frames as f64)
+            .max(1.0 / self.frames as f64);
+        for (power, &(low, high)) in self.power.iter_mut().zip(&self.band_bins) {
+            if high > low {
+                let band = (low..high).map(density).sum::<f64>() / (high - low) as f64;
+                *power += (band - *power) * weight;
+            }
+        }
+    }
+
+    /// The spectrum averaged so far, or `None` before a frame louder
+    /// than the silence threshold
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn profile(&self) -> Option<SpectrumProfile> {
+        (self.frames > 0).then(|| {
+            let mut levels_db = [f32::NEG_INFINITY; BANDS];
+            for ((level, &power), &(low, high)) in
+                levels_db.iter_mut().zip(&self.power).zip(&self.band_bins)
+            {
+                if high > low && power > 0.0 {
+                    *level = (10.0 * power.log10()) as f32;
+                }
+            }
+            SpectrumProfile { levels_db }
+        })
+    }
+}
+
+/// Measures the long-term spectrum of a WAV file, e.g. a reference mix to
+/// match.
+///
+/// # Errors
+/// Returns an error if the file can't be read or isn't a supported WAV
+/// file, or is silent throughout.
+pub fn analyze_file(path: impl AsRef<Path>) -> Result<SpectrumProfile> {
+    let path = path.as_ref();
+    let mut reader = WavReader::open(path)?;
+    let format = reader.format();
+    let mut analyzer = SpectrumAnalyzer::new(format.sample_rate);
+    let mut buffer = vec![Sample::SILENCE; READ_FRAMES * format.channels.count_usize()];
+    loop {
+        let count = reader.read_samples(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        analyzer.process(&buffer[..count], format.channels);
+    }
+    analyzer.profile().ok_or_else(|| {
+        AudioEngineError::configuration(format!(
+            "{} is too short or too quiet to measure its spectrum",
+            path.display()
+        ))
+    })
+}
@@ -0,0 +1,205 @@
+//! Tempo estimation
+//!
+//! [`TempoAnalyzer`] builds an onset strength envelope from the positive
+//! spectral flux of log-compressed magnitude spectra, then autocorrelates
+//! that envelope. The lag with the strongest periodicity, weighted by a broad
+//! prior centred on 120 BPM to settle octave ambiguity, gives the tempo.
+
+use crate::dsp::fft::{Fft, hann_window};
+use crate::types::SampleRate;
+
+const FFT_SIZE: usize = 1024;
+const HOP: usize = 512;
+const BINS: usize = FFT_SIZE / 2 + 1;
+/// Compression constant for the log magnitude
+const COMPRESSION: f32 = 1000.0;
+/// Centre and width (in octaves) of the tempo prior
+const PRIOR_BPM: f32 = 120.0;
+const PRIOR_OCTAVES: f32 = 1.0;
+
+/// A tempo estimate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoEstimate {
+    pub bpm: f32,
+    /// Strength of the winning periodicity relative to the envelope energy,
+    /// from 0 (no pulse) to 1
+    pub confidence: f32,
+}
+
+/// Streaming tempo analyser for mono audio.
+#[derive(Debug, Clone)]
+pub struct TempoAnalyzer {
+    sample_rate: SampleRate,
+    min_bpm: f32,
+    max_bpm: f32,
+    fft: Fft,
+    window: Vec<f32>,
+    input: Vec<f32>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    previous: Vec<f32>,
+    onsets: Vec<f32>,
+}
+
+impl TempoAnalyzer {
+    /// Creates an analyser looking for tempos between 60 and 200 BPM.
+    ///
+    /// # Panics
+    /// Never; the FFT size is a fixed power of two.
+    #[must_use]
+    pub fn new(sample_rate: SampleRate) -> Self {
+        let mut window = vec![0.0; FFT_SIZE];
+        hann_window(&mut window);
+        Self {
+            sample_rate,
+            min_bpm: 60.0,
+            max_bpm: 200.0,
+            fft: Fft::new(FFT_SIZE).expect("FFT size is a power of two"),
+            window,
+            input: Vec::with_capacity(FFT_SIZE * 2),
+            re: vec![0.0; FFT_SIZE],
+            im: vec![0.0; FFT_SIZE],
+            previous: vec![0.0; BINS],
+            onsets: Vec::new(),
+        }
+    }
+
+    /// Restricts the search to `min_bpm..=max_bpm`.
+    #[must_use]
+    pub const fn with_range(mut self, min_bpm: f32, max_bpm: f32) -> Self {
+        self.min_bpm = min_bpm.clamp(20.0, 400.0);
+        self.max_bpm = max_bpm.clamp(self.min_bpm, 400.0);
+        self
+    }
+
+    /// Onset envelope frames per second
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn frame_rate(&self) -> f32 {
+        self.sample_rate.as_f32() / HOP as f32
+    }
+
+    /// Feeds mono samples.
+    pub fn process(&mut self, samples: &[f32]) {
+        let mut remaining = samples;
+        while !remaining.is_empty() {
+            let take = (FFT_SIZE - self.input.len()).min(remaining.len());
+            self.input.extend_from_slice(&remaining[..take]);
+            remaining = &remaining[take..];
+            if self.input.len() == FFT_SIZE {
+                self.analyze_frame();
+                self.input.drain(..HOP);
+            }
+        }
+    }
+
+    fn analyze_frame(&mut self) {
+        for ((re, im), (&x, &w)) in self
+            .re
+            .iter_mut()
+            .zip(self.im.iter_mut())
+            .zip(self.input.iter().zip(&self.window))
+        {
+            *re = x * w;
+            *im = 0.0;
+        }
+        self.fft.forward(&mut self.re, &mut self.im);
+
+        let mut flux = 0.0;
+        for k in 0..BINS {
+            let magnitude = (COMPRESSION * self.re[k].hypot(self.im[k])).ln_1p();
+            flux += (magnitude - self.previous[k]).max(0.0);
+            self.previous[k] = magnitude;
+        }
+        self.onsets.push(flux);
+    }
+
+    /// Estimates the tempo of everything fed so far.
+    ///
+    /// Returns `None` if there is too little audio to cover two beats at the
+    /// slowest tempo, or no periodicity at all.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn estimate(&self) -> Option<TempoEstimate> {
+        let fps = self.frame_rate();
+        let min_lag = ((60.0 * fps / self.max_bpm).floor() as usize).max(2);
+        let max_lag = (60.0 * fps / self.min_bpm).ceil() as usize;
+        if self.onsets.len() < 2 * max_lag + 1 {
+            return None;
+        }
+
+        // Remove the slowly varying part so loudness changes don't correlate
+        let envelope = detrend(&self.onsets, (fps / 2.0) as usize);
+        // Unbiased: longer lags have fewer overlapping frames
+        let correlation = |lag: usize| -> f32 {
+            envelope
+                .iter()
+                .zip(&envelope[lag..])
+                .map(|(a, b)| a * b)
+                .sum::<f32>()
+                / (envelope.len() - lag) as f32
+        };
+        let energy = correlation(0);
+        if energy <= 0.0 {
+            return None;
+        }
+
+        let scores: Vec<f32> = (min_lag - 1..=max_lag + 1)
+            .map(|lag| correlation(lag).max(0.0))
+            .collect();
+        let weighted = |index: usize| -> f32 {
+            let bpm = 60.0 * fps / (min_lag - 1 + index) as f32;
+            let octaves = (bpm / PRIOR_BPM).log2() / PRIOR_OCTAVES;
+            scores[index] * (-0.5 * octaves * octaves).exp()
+        };
+        let best = (1..scores.len() - 1)
+            .max_by(|&a, &b| weighted(a).total_cmp(&weighted(b)))
+            .filter(|&index| scores[index] > 0.0)?;
+
+        // Parabolic interpolation around the peak for sub-frame precision
+        let (left, centre, right) = (scores[best - 1], scores[best], scores[best + 1]);
+        let denominator = 2.0f32.mul_add(-centre, left) + right;
+        let offset = if denominator.abs() > f32::EPSILON {
+            (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        let lag = (min_lag - 1 + best) as f32 + offset;
+        Some(TempoEstimate {
+            bpm: 60.0 * fps / lag,
+            confidence: (centre / energy).clamp(0.0, 1.0),
+        })
+    }
+
+    /// Clears all accumulated analysis.
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.previous.fill(0.0);
+        self.onsets.clear();
+    }
+}
+
+/// Subtracts a moving average of `radius` frames either side.
+#[allow(clippy::cast_precision_loss)]
+fn detrend(values: &[f32], radius: usize) -> Vec<f32> {
+    let mut prefix = Vec::with_capacity(values.len() + 1);
+    prefix.push(0.0f64);
+    for &value in values {
+        prefix.push(prefix[prefix.len() - 1] + f64::from(value));
+    }
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| {
+            let start = i.saturating_sub(radius);
+            let end = (i + radius + 1).min(values.len());
+            #[allow(clippy::cast_possible_truncation)]
+            let mean = ((prefix[end] - prefix[start]) / (end - start) as f64) as f32;
+            (value - mean).max(0.0)
+        })
+        .collect()
+}
@@ -0,0 +1,522 @@
+//! Waveform overview (peak file) generation and caching
+//!
+//! [`WaveformPeaks`] holds min/max pairs per channel at several resolutions:
+//! 256 frames per peak at the finest level, each further level four times
+//! coarser. A UI picks the level closest to its zoom and merges from there,
+//! so drawing never touches the audio file.
+//!
+//! Peaks are cached next to the source as `<file>.peaks`. The cache stores
+//! the source's size and modification time and is rebuilt when they change.
+//!
+//! Cache layout (little endian): magic `AEPK`, version `u16`, channels `u16`,
+//! sample rate `u32`, frames `u64`, source size `u64`, source mtime seconds
+//! `u64` and nanoseconds `u32`, level count `u16`; then per level the frames
+//! per peak `u32` and peak count `u64`, followed by that many frames of
+//! `(min, max)` `i16` pairs for every channel.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::wav::WavReader;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+const MAGIC: &[u8; 4] = b"AEPK";
+const VERSION: u16 = 1;
+/// Frames per peak at the finest level
+pub const BASE_FRAMES_PER_PEAK: u32 = 256;
+/// Ratio between consecutive levels
+pub const LEVEL_FACTOR: u32 = 4;
+const MAX_LEVELS: usize = 8;
+/// Bytes of the cache header, up to and including the level count
+const CACHE_HEADER_LEN: u64 = 42;
+/// Bytes before each level's peaks: frames per peak and the peak count
+const LEVEL_HEADER_LEN: u64 = 12;
+/// Bytes of a stored peak, a quantised minimum and maximum
+const PEAK_BYTES: u64 = 4;
+const READ_FRAMES: usize = 8192;
+
+/// Minimum and maximum sample value over a span.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peak {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Peak {
+    const EMPTY: Self = Self {
+        min: f32::INFINITY,
+        max: f32::NEG_INFINITY,
+    };
+
+    const fn include(&mut self, other: Self) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    const fn or_silence(self) -> Self {
+        if self.min > self.max {
+            Self { min: 0.0, max: 0.0 }
+        } else {
+            self
+        }
+    }
+}
+
+/// Peaks at one resolution, interleaved by channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeakLevel {
+    frames_per_peak: u32,
+    peaks: Vec<Peak>,
+}
+
+impl PeakLevel {
+    #[must_use]
+    pub const fn frames_per_peak(&self) -> u32 {
+        self.frames_per_peak
+    }
+
+    /// Peaks of all channels, interleaved
+    #[must_use]
+    pub fn peaks(&self) -> &[Peak] {
+        &self.peaks
+    }
+}
+
+/// Identity of the source a cache was built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SourceStamp {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+}
+
+impl SourceStamp {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Ok(Self {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+/// Multi-resolution min/max overview of an audio file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaveformPeaks {
+    channels: ChannelCount,
+    sample_rate: SampleRate,
+    frames: u64,
+    levels: Vec<PeakLevel>,
+    stamp: SourceStamp,
+}
+
+impl WaveformPeaks {
+    /// Scans a WAV file.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or isn't a supported WAV file.
+    pub fn scan(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut reader = WavReader::open(path)?;
+        let mut peaks = Self::from_reader(&mut reader)?;
+        peaks.stamp = SourceStamp::of(path)?;
+        Ok(peaks)
+    }
+
+    /// Scans the remaining audio of an open reader.
+    ///
+    /// # Errors
+    /// Returns an error if reading fails.
+    pub fn from_reader<R: Read + Seek>(reader: &mut WavReader<R>) -> Result<Self> {
+        let format = reader.format();
+        let channels = format.channels.count_usize();
+        let mut base = Vec::new();
+        let mut current = vec![Peak::EMPTY; channels];
+        let mut in_peak = 0;
+        let mut frames = 0u64;
+
+        let mut buffer = vec![Sample::SILENCE; READ_FRAMES * channels];
+        loop {
+            let count = reader.read_samples(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            for frame in buffer[..count].chunks_exact(channels) {
+                for (peak, sample) in current.iter_mut().zip(frame) {
+                    let value = sample.value();
+                    peak.include(Peak {
+                        min: value,
+                        max: value,
+                    });
+                }
+                frames += 1;
+                in_peak += 1;
+                if in_peak == BASE_FRAMES_PER_PEAK {
+                    base.extend(current.iter().map(|p| p.or_silence()));
+                    current.fill(Peak::EMPTY);
+                    in_peak = 0;
+                }
+            }
+        }
+        if in_peak > 0 {
+            base.extend(current.iter().map(|p| p.or_silence()));
+        }
+
+        let mut levels = vec![PeakLevel {
+            frames_per_peak: BASE_FRAMES_PER_PEAK,
+            peaks: base,
+        }];
+        while levels.len() < MAX_LEVELS {
+            let finer = &levels[levels.len() - 1];
+            if finer.peaks.len() <= channels {
+                break;
+            }
+            let group = LEVEL_FACTOR as usize * channels;
+            let peaks = finer
+                .peaks
+                .chunks(group)
+                .flat_map(|chunk| {
+                    (0..channels).map(move |channel| {
+                        let mut merged = Peak::EMPTY;
+                        for peak in chunk.iter().skip(channel).step_by(channels) {
+                            merged.include(*peak);
+                        }
+                        merged
+                    })
+                })
+                .collect();
+            levels.push(PeakLevel {
+                frames_per_peak: finer.frames_per_peak * LEVEL_FACTOR,
+                peaks,
+            });
+        }
+
+        Ok(Self {
+            channels: format.channels,
+            sample_rate: format.sample_rate,
+            frames,
+            levels,
+            stamp: SourceStamp {
+                size: 0,
+                modified_secs: 0,
+                modified_nanos: 0,
+            },
+        })
+    }
+
+    /// Loads cached peaks for `path`, scanning and writing the cache if it is
+    /// missing or stale.
+    ///
+    /// Failing to write the cache is logged, not returned.
+    ///
+    /// # Errors
+    /// Returns an error if the source can't be scanned.
+    pub fn load_or_scan(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let cache = Self::cache_path(path);
+        let stamp = SourceStamp::of(path)?;
+        match Self::read_cache(&cache) {
+            Ok(peaks) if peaks.stamp == stamp => return Ok(peaks),
+            Ok(_) => log::debug!("peak cache {} is stale", cache.display()),
+            Err(e) => log::debug!("no usable peak cache {}: {e}", cache.display()),
+        }
+        let peaks = Self::scan(path)?;
+        if let Err(e) = peaks.write_cache(&cache) {
+            log::warn!("failed to write peak cache {}: {e}", cache.display());
+        }
+        Ok(peaks)
+    }
+
+    /// Where the cache for `source` lives
+    #[must_use]
+    pub fn cache_path(source: &Path) -> PathBuf {
+        let mut name = source.as_os_str().to_owned();
+        name.push(".peaks");
+        PathBuf::from(name)
+    }
+
+    #[must_use]
+    pub const fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    #[must_use]
+    pub const fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    /// Length of the source in frames
+    #[must_use]
+    pub const fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// All levels, finest first
+    #[must_use]
+    pub fn levels(&self) -> &[PeakLevel] {
+        &self.levels
+    }
+
+    /// The coarsest level that still has at least one peak per pixel at
+    /// `frames_per_pixel`.
+    #[must_use]
+    pub fn level_for(&self, frames_per_pixel: f64) -> &PeakLevel {
+        self.levels
+            .iter()
+            .rev()
+            .find(|level| f64::from(level.frames_per_peak) <= frames_per_pixel)
+            .unwrap_or(&self.levels[0])
+    }
+
+    /// Renders one channel's frames `start..end` into `pixels` columns.
+    ///
+    /// Columns past the end of the file are silent.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn render(&self, channel: usize, start: u64, end: u64, pixels: usize) -> Vec<Peak> {
+        let channels = self.channels.count_usize();
+        if pixels == 0 || channel >= channels || end <= start {
+            return vec![Peak { min: 0.0, max: 0.0 }; pixels];
+        }
+        let frames_per_pixel = (end - start) as f64 / pixels as f64;
+        let level = self.level_for(frames_per_pixel);
+        let per_peak = f64::from(level.frames_per_peak);
+        let count = level.peaks.len() / channels;
+
+        (0..pixels)
+            .map(|pixel| {
+                let from = (pixel as f64).mul_add(frames_per_pixel, start as f64);
+                let to = from + frames_per_pixel;
+                let first = (from / per_peak).floor() as usize;
+                let last = ((to / per_peak).ceil() as usize).max(first + 1).min(count);
+                let mut merged = Peak::EMPTY;
+                for index in first..last {
+                    merged.include(level.peaks[index * channels + channel]);
+                }
+                merged.or_silence()
+            })
+            .collect()
+    }
+
+    /// Writes the peaks to a cache file.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be written.
+    pub fn write_cache(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        let channels = u16::try_from(self.channels.count())
+            .map_err(|_| AudioEngineError::numeric_conversion("too many channels"))?;
+        let levels = u16::try_from(self.levels.len())
+            .map_err(|_| AudioEngineError::numeric_conversion("too many peak levels"))?;
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&channels.to_le_bytes())?;
+        w.write_all(&self.sample_rate.as_hz().to_le_bytes())?;
+        w.write_all(&self.frames.to_le_bytes())?;
+        w.write_all(&self.stamp.size.to_le_bytes())?;
+        w.write_all(&self.stamp.modified_secs.to_le_bytes())?;
+        w.write_all(&self.stamp.modified_nanos.to_le_bytes())?;
+        w.write_all(&levels.to_le_bytes())?;
+        for level in &self.levels {
+            w.write_all(&level.frames_per_peak.to_le_bytes())?;
+            let count = (level.peaks.len() / self.channels.count_usize()) as u64;
+            w.write_all(&count.to_le_bytes())?;
+            for peak in &level.peaks {
+                w.write_all(&quantize(peak.min).to_le_bytes())?;
+                w.write_all(&quantize(peak.max).to_le_bytes())?;
+            }
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Reads a cache file.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or isn't a valid cache.
+    pub fn read_cache(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        // Every count is checked against what the file holds before
+        // anything is allocated for it
+        let mut remaining = file.metadata()?.len().saturating_sub(CACHE_HEADER_LEN);
+        let mut r = BufReader::new(file);
+        let invalid = || AudioEngineError::UnsupportedFormat {
+            format: "invalid peak cache".to_string(),
+        };
+
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u16(&mut r)? != VERSION {
+            return Err(invalid());
+        }
+        let channels = ChannelCount::try_from(u32::from(read_u16(&mut r)?))?;
+        let sample_rate = SampleRate::try_from(read_u32(&mut r)?)?;
+        let frames = read_u64(&mut r)?;
+        let stamp = SourceStamp {
+            size: read_u64(&mut r)?,
+            modified_secs: read_u64(&mut r)?,
+            modified_nanos: read_u32(&mut r)?,
+        };
+        let level_count = usize::from(read_u16(&mut r)?);
+        if level_count == 0 || level_count > MAX_LEVELS {
+            return Err(invalid());
+        }
+
+        let mut levels = Vec::with_capacity(level_count);
+        for _ in 0..level_count {
+            let frames_per_peak = read_u32(&mut r)?;
+            let count = usize::try_from(read_u64(&mut r)?).map_err(|_| invalid())?;
+            let expected = frames.div_ceil(u64::from(frames_per_peak.max(1)));
+            if frames_per_peak == 0 || count as u64 > expected {
+                return Err(invalid());
+            }
+            let len = count
+                .checked_mul(channels.count_usize())
+                .filter(|&len| {
+                    (len as u64)
+                        .checked_mul(PEAK_BYTES)
+                        .and_then(|bytes| bytes.checked_add(LEVEL_HEADER_LEN))
+                        .is_some_and(|bytes| bytes <= remaining)
+                })
+                .ok_or_else(invalid)?;
+            remaining -= LEVEL_HEADER_LEN + len as u64 * PEAK_BYTES;
+            let mut peaks = Vec::with_capacity(len);
+            for _ in 0..len {
+                let min = dequantize(read_u16(&mut r)?);
+                let max = dequantize(read_u16(&mut r)?);
+                peaks.push(Peak { min, max });
+            }
+            levels.push(PeakLevel {
+                frames_per_peak,
+                peaks,
+            });
+        }
+
+        Ok(Self {
+            channels,
+            sample_rate,
+            frames,
+            levels,
+            stamp,
+        })
+    }
+}
+
+/// Scales to `i16`, stored as its bit pattern.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn quantize(value: f32) -> u16 {
+    ((value.clamp(-1.0, 1.0) * 32767.0).round() as i16) as u16
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn dequantize(bits: u16) -> f32 {
+    f32::from(bits as i16) / 32767.0
+}
+
+fn read_u16(r: &mut impl Read) -> Result<u16> {
+    let mut bytes = [0u8; 2];
+    r.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::io::wav::WavWriter;
+    use crate::types::{AudioFormat, BitDepth};
+
+    #[allow(clippy::cast_precision_loss)]
+    fn peaks() -> WaveformPeaks {
+        let format = AudioFormat::new(SampleRate::Hz48000, ChannelCount::Stereo, BitDepth::F32);
+        let samples: Vec<Sample> = (0..20_000)
+            .map(|index| Sample::new((index as f32 * 0.01).sin() * 0.5))
+            .collect();
+        let mut bytes = Vec::new();
+        let mut writer = WavWriter::new(Cursor::new(&mut bytes), format).expect("header");
+        writer.write_samples(&samples).expect("samples");
+        writer.finalize().expect("finalize");
+        drop(writer);
+        let mut reader = WavReader::new(Cursor::new(bytes)).expect("reads back");
+        WaveformPeaks::from_reader(&mut reader).expect("scans")
+    }
+
+    fn cache_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("audio_engine_{}_{name}.peaks", std::process::id()));
+        fs::write(&path, bytes).expect("writes");
+        path
+    }
+
+    fn cached_bytes(peaks: &WaveformPeaks) -> Vec<u8> {
+        let path = cache_file("source", &[]);
+        peaks.write_cache(&path).expect("writes cache");
+        let bytes = fs::read(&path).expect("reads cache");
+        fs::remove_file(path).ok();
+        bytes
+    }
+
+    fn read(name: &str, bytes: &[u8]) -> Result<WaveformPeaks> {
+        let path = cache_file(name, bytes);
+        let result = WaveformPeaks::read_cache(&path);
+        fs::remove_file(path).ok();
+        result
+    }
+
+    #[test]
+    fn cache_round_trips() {
+        let peaks = peaks();
+        let read = read("round_trip", &cached_bytes(&peaks)).expect("valid cache");
+        assert_eq!(read.frames(), peaks.frames());
+        assert_eq!(read.levels().len(), peaks.levels().len());
+        for (read, scanned) in read.levels().iter().zip(peaks.levels()) {
+            assert_eq!(read.frames_per_peak(), scanned.frames_per_peak());
+            for (read, scanned) in read.peaks().iter().zip(scanned.peaks()) {
+                assert!((read.min - scanned.min).abs() <= 1.0 / 32_767.0);
+                assert!((read.max - scanned.max).abs() <= 1.0 / 32_767.0);
+            }
+        }
+    }
+
+    #[test]
+    fn counts_beyond_the_file_are_rejected_before_allocating() {
+        let bytes = cached_bytes(&peaks());
+
+        // Claim an enormous file, so the peak count passes the frame check
+        let mut huge = bytes.clone();
+        huge[12..20].copy_from_slice(&u64::MAX.to_le_bytes());
+        huge[46..54].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        assert!(read("huge", &huge).is_err());
+        huge[46..54].copy_from_slice(&(u64::MAX / 1024).to_le_bytes());
+        assert!(read("overflow", &huge).is_err());
+
+        assert!(read("truncated", &bytes[..bytes.len() - 1]).is_err());
+        assert!(read("header", &bytes[..30]).is_err());
+    }
+}
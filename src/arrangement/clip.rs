@@ -0,0 +1,530 @@
+//! Clips and the audio they play
+//!
+//! Clip edits are non-destructive: in and out points, gain, the gain
+//! envelope and the fades are all applied as the clip renders, and the
+//! source samples are never touched. Stretching renders a warped copy of
+//! the clip's part of the source alongside it.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::arrangement::warp::{self, WarpMarker};
+use crate::dsp::crossfade::CrossfadeCurve;
+use crate::error::Result;
+use crate::io::wav::WavReader;
+use crate::types::{AudioFormat, Gain, Sample, Tempo, TempoMap, TimeRange, Timestamp};
+
+/// Decoded audio a clip plays from.
+///
+/// Cheap to clone: clones share the samples, so many clips can cut up the
+/// same file.
+#[derive(Clone)]
+pub struct ClipSource {
+    name: String,
+    format: AudioFormat,
+    samples: Arc<[Sample]>,
+}
+
+impl ClipSource {
+    /// Wraps interleaved samples in `format`.
+    #[must_use]
+    pub fn new(name: impl Into<String>, format: AudioFormat, samples: Vec<Sample>) -> Self {
+        Self {
+            name: name.into(),
+            format,
+            samples: samples.into(),
+        }
+    }
+
+    /// Decodes a whole WAV file into memory.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or isn't a supported WAV
+    /// file.
+    pub fn load_wav(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut reader = WavReader::open(path)?;
+        let format = reader.format();
+        let total = usize::try_from(reader.frames()).unwrap_or(usize::MAX);
+        let mut samples = vec![Sample::SILENCE; total * format.channels.count_usize()];
+        let mut read = 0;
+        while read < samples.len() {
+            let count = reader.read_samples(&mut samples[read..])?;
+            if count == 0 {
+                break;
+            }
+            read += count;
+        }
+        samples.truncate(read);
+        Ok(Self::new(path.display().to_string(), format, samples))
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    #[must_use]
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    #[must_use]
+    pub fn frames(&self) -> u64 {
+        (self.samples.len() / self.format.channels.count_usize()) as u64
+    }
+}
+
+impl fmt::Debug for ClipSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClipSource")
+            .field("name", &self.name)
+            .field("format", &self.format)
+            .field("samples", &self.samples.len())
+            .finish()
+    }
+}
+
+/// A fade handle: how long the fade is and its shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Fade {
+    /// Length in frames
+    pub length: u64,
+    pub curve: CrossfadeCurve,
+}
+
+impl Fade {
+    #[must_use]
+    pub const fn new(length: u64, curve: CrossfadeCurve) -> Self {
+        Self { length, curve }
+    }
+
+    /// Gain `frames` into the fade from silence
+    #[allow(clippy::cast_precision_loss)]
+    fn gain(self, frames: u64) -> f32 {
+        self.curve.gains(frames as f32 / self.length as f32).1
+    }
+}
+
+/// Gain breakpoints over a clip's source, interpolated linearly.
+///
+/// Points are placed at source frames rather than clip offsets, so they
+/// stay with the audio when the clip is trimmed. Before the first point
+/// and after the last the gain holds; with no points it is unity.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GainEnvelope {
+    /// Ordered by frame, at most one per frame
+    points: Vec<(u64, Gain)>,
+}
+
+impl GainEnvelope {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { points: Vec::new() }
+    }
+
+    /// Adds a point, replacing any at the same frame.
+    pub fn add_point(&mut self, frame: u64, gain: Gain) {
+        match self.points.binary_search_by_key(&frame, |&(at, _)| at) {
+            Ok(index) => self.points[index].1 = gain,
+            Err(index) => self.points.insert(index, (frame, gain)),
+        }
+    }
+
+    /// Removes and returns the point at `index`
+    pub fn remove_point(&mut self, index: usize) -> Option<(u64, Gain)> {
+        (index < self.points.len()).then(|| self.points.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    /// Points as `(source frame, gain)`, ordered by frame
+    #[must_use]
+    pub fn points(&self) -> &[(u64, Gain)] {
+        &self.points
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Linear gain at a source frame
+    #[must_use]
+    pub fn gain_at(&self, frame: u64) -> f32 {
+        let mut index = self.points.partition_point(|&(at, _)| at <= frame);
+        self.level(frame, &mut index)
+    }
+
+    /// Linear gain at `frame`, given `index`, the number of points at or
+    /// before an earlier frame. `index` is moved on, so rendering frames in
+    /// order costs no search.
+    #[allow(clippy::cast_precision_loss)]
+    fn level(&self, frame: u64, index: &mut usize) -> f32 {
+        while *index < self.points.len() && self.points[*index].0 <= frame {
+            *index += 1;
+        }
+        match (
+            index.checked_sub(1).map(|i| self.points[i]),
+            self.points.get(*index),
+        ) {
+            (None, None) => 1.0,
+            (Some((_, gain)), None) | (None, Some(&(_, gain))) => gain.as_linear(),
+            (Some((from, a)), Some(&(to, b))) => {
+                let t = (frame - from) as f32 / (to - from) as f32;
+                (b.as_linear() - a.as_linear()).mul_add(t, a.as_linear())
+            }
+        }
+    }
+}
+
+/// A stretch of a source placed on the timeline.
+#[derive(Debug, Clone)]
+pub struct Clip {
+    source: ClipSource,
+    position: Timestamp,
+    source_in: u64,
+    length: u64,
+    gain: Gain,
+    envelope: GainEnvelope,
+    fade_in: Fade,
+    fade_out: Fade,
+    /// Complete warp map, empty when the clip isn't stretched
+    warp: Vec<WarpMarker>,
+    /// The warped audio, one frame per clip frame
+    stretched: Option<ClipSource>,
+}
+
+impl Clip {
+    /// Places the whole of `source` at `position`.
+    #[must_use]
+    pub fn new(source: ClipSource, position: Timestamp) -> Self {
+        let length = source.frames();
+        Self {
+            source,
+            position,
+            source_in: 0,
+            length,
+            gain: Gain::UNITY,
+            envelope: GainEnvelope::new(),
+            fade_in: Fade::default(),
+            fade_out: Fade::default(),
+            warp: Vec::new(),
+            stretched: None,
+        }
+    }
+
+    /// Plays only the source frames from `source_in` up to `source_out`,
+    /// clamped to the source. Any stretch is cleared.
+    #[must_use]
+    pub fn with_in_out(mut self, source_in: u64, source_out: u64) -> Self {
+        self.clear_stretch();
+        let frames = self.source.frames();
+        self.source_in = source_in.min(frames);
+        self.length = source_out.clamp(self.source_in, frames) - self.source_in;
+        self.clamp_fades();
+        self
+    }
+
+    #[must_use]
+    pub const fn with_gain(mut self, gain: Gain) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    #[must_use]
+    pub fn with_envelope(mut self, envelope: GainEnvelope) -> Self {
+        self.envelope = envelope;
+        self
+    }
+
+    /// Fades in and out over the given frames, each clamped to the clip
+    #[must_use]
+    pub fn with_fades(mut self, fade_in: u64, fade_out: u64) -> Self {
+        self.fade_in.length = fade_in.min(self.length);
+        self.fade_out.length = fade_out.min(self.length);
+        self
+    }
+
+    /// Sets the shape of both fades
+    #[must_use]
+    pub const fn with_fade_curve(mut self, curve: CrossfadeCurve) -> Self {
+        self.fade_in.curve = curve;
+        self.fade_out.curve = curve;
+        self
+    }
+
+    #[must_use]
+    pub fn with_fade_in(mut self, fade: Fade) -> Self {
+        self.set_fade_in(fade);
+        self
+    }
+
+    #[must_use]
+    pub fn with_fade_out(mut self, fade: Fade) -> Self {
+        self.set_fade_out(fade);
+        self
+    }
+
+    #[must_use]
+    pub const fn source(&self) -> &ClipSource {
+        &self.source
+    }
+
+    /// Where the clip starts on the timeline
+    #[must_use]
+    pub const fn position(&self) -> Timestamp {
+        self.position
+    }
+
+    pub const fn set_position(&mut self, position: Timestamp) {
+        self.position = position;
+    }
+
+    /// First source frame played
+    #[must_use]
+    pub const fn source_in(&self) -> u64 {
+        self.source_in
+    }
+
+    /// One past the last source frame played
+    #[must_use]
+    pub fn source_out(&self) -> u64 {
+        self.warp
+            .last()
+            .map_or(self.source_in + self.length, |marker| marker.source_frame)
+    }
+
+    /// Frames played
+    #[must_use]
+    pub const fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Stretches the clip to `factor` times its source length; 2.0 plays
+    /// at half speed. Pitch is kept.
+    ///
+    /// # Errors
+    /// Returns an error if the factor needs a speed the time stretcher
+    /// can't play.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn set_stretch(&mut self, factor: f64) -> Result<()> {
+        let source_out = self.source_out();
+        let frames = (source_out - self.source_in) as f64 * factor;
+        self.set_warp_markers(&[WarpMarker::new(source_out, (frames.round() as u64).max(1))])
+    }
+
+    /// Warps the clip so each marker's source frame plays at its clip
+    /// frame. The first source frame stays on the first clip frame, and
+    /// after the last marker the source runs on to its out point at the
+    /// last marker's speed.
+    ///
+    /// The warped audio is rendered here, so call this from the editing
+    /// side, not the audio thread.
+    ///
+    /// # Errors
+    /// Returns an error if the markers are out of order, outside the clip,
+    /// or need a speed the time stretcher can't play.
+    pub fn set_warp_markers(&mut self, markers: &[WarpMarker]) -> Result<()> {
+        let map = warp::warp_map(self.source_in, self.source_out(), markers)?;
+        self.stretched = Some(warp::stretch(&self.source, &map));
+        self.length = map[map.len() - 1].clip_frame;
+        self.warp = map;
+        self.clamp_fades();
+        Ok(())
+    }
+
+    /// Conforms a clip recorded at `source_tempo` to the tempo map, so
+    /// every beat of the source lands on a beat of the timeline from the
+    /// clip's position.
+    ///
+    /// # Errors
+    /// Returns an error if the tempos are too far apart for the time
+    /// stretcher.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn conform(&mut self, source_tempo: Tempo, map: &TempoMap) -> Result<()> {
+        let source_out = self.source_out();
+        let beat_frames = source_tempo.beats_to_samples(1.0, self.source.format.sample_rate);
+        let first_beat = map.beats_at(self.position);
+        let start = self.position.as_samples();
+        let beats = ((source_out - self.source_in) as f64 / beat_frames) as u32;
+        let markers: Vec<_> = (1..=beats)
+            .map(|beat| {
+                let source_frame = self.source_in + (f64::from(beat) * beat_frames).round() as u64;
+                let position = map.position_of_beats(first_beat + f64::from(beat));
+                WarpMarker::new(source_frame, position.as_samples().saturating_sub(start))
+            })
+            .filter(|marker| marker.source_frame <= source_out)
+            .collect();
+        self.set_warp_markers(&markers)
+    }
+
+    /// Plays the source at its own speed again.
+    pub fn clear_stretch(&mut self) {
+        if let Some(last) = self.warp.last() {
+            self.length = last.source_frame - self.source_in;
+            self.warp.clear();
+            self.stretched = None;
+            self.clamp_fades();
+        }
+    }
+
+    #[must_use]
+    pub const fn is_stretched(&self) -> bool {
+        self.stretched.is_some()
+    }
+
+    /// The complete warp map, including the markers at the first and last
+    /// frames, or nothing if the clip isn't stretched
+    #[must_use]
+    pub fn warp_markers(&self) -> &[WarpMarker] {
+        &self.warp
+    }
+
+    /// The part of the timeline the clip covers
+    #[must_use]
+    pub fn range(&self) -> TimeRange {
+        TimeRange::new(
+            self.position,
+            Timestamp::from_samples(self.position.as_samples() + self.length),
+        )
+    }
+
+    #[must_use]
+    pub const fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    pub const fn set_gain(&mut self, gain: Gain) {
+        self.gain = gain;
+    }
+
+    #[must_use]
+    pub const fn envelope(&self) -> &GainEnvelope {
+        &self.envelope
+    }
+
+    pub const fn envelope_mut(&mut self) -> &mut GainEnvelope {
+        &mut self.envelope
+    }
+
+    /// Fade in and fade out lengths in frames
+    #[must_use]
+    pub const fn fades(&self) -> (u64, u64) {
+        (self.fade_in.length, self.fade_out.length)
+    }
+
+    #[must_use]
+    pub const fn fade_in(&self) -> Fade {
+        self.fade_in
+    }
+
+    #[must_use]
+    pub const fn fade_out(&self) -> Fade {
+        self.fade_out
+    }
+
+    fn clamp_fades(&mut self) {
+        self.fade_in.length = self.fade_in.length.min(self.length);
+        self.fade_out.length = self.fade_out.length.min(self.length);
+    }
+
+    /// Sets the fade in, its length clamped to the clip
+    pub fn set_fade_in(&mut self, fade: Fade) {
+        self.fade_in = Fade::new(fade.length.min(self.length), fade.curve);
+    }
+
+    /// Sets the fade out, its length clamped to the clip
+    pub fn set_fade_out(&mut self, fade: Fade) {
+        self.fade_out = Fade::new(fade.length.min(self.length), fade.curve);
+    }
+
+    /// Adds the part of the clip that falls in the block starting at
+    /// `start` to `output`, scaled by `gain` on top of the clip's own gain.
+    ///
+    /// Output channel `c` plays source channel `c`, or the source's last
+    /// channel if it has fewer, so mono clips play on every channel.
+    pub(crate) fn render(
+        &self,
+        start: Timestamp,
+        output: &mut [Sample],
+        channels: usize,
+        gain: f32,
+    ) {
+        let block_frames = (output.len() / channels) as u64;
+        let clip_start = self.position.as_samples();
+        let clip_end = clip_start + self.length;
+        let from = start.as_samples().max(clip_start);
+        let to = (start.as_samples() + block_frames).min(clip_end);
+        if from >= to {
+            return;
+        }
+
+        let source_channels = self.source.format.channels.count_usize();
+        // Stretched clips play their warped copy from its first frame
+        let (samples, first_frame) = self.stretched.as_ref().map_or_else(
+            || (self.source.samples(), self.source_in),
+            |stretched| (stretched.samples(), 0),
+        );
+        let envelope_frame = |offset| {
+            if self.warp.is_empty() {
+                self.source_in + offset
+            } else {
+                warp::source_frame(&self.warp, offset)
+            }
+        };
+        let gain = gain * self.gain.as_linear();
+        let mut point = self
+            .envelope
+            .points
+            .partition_point(|&(at, _)| at <= envelope_frame(from - clip_start));
+        for frame in from..to {
+            let offset = frame - clip_start;
+            let level = gain
+                * self.fade_gain(offset)
+                * self.envelope.level(envelope_frame(offset), &mut point);
+            let source_frame = usize::try_from(first_frame + offset).unwrap_or(usize::MAX);
+            let Some(input) =
+                samples.get(source_frame * source_channels..(source_frame + 1) * source_channels)
+            else {
+                break;
+            };
+            let at = usize::try_from(frame - start.as_samples()).unwrap_or(0) * channels;
+            for (channel, out) in output[at..at + channels].iter_mut().enumerate() {
+                let sample = input[channel.min(source_channels - 1)].value();
+                *out = Sample::new(sample.mul_add(level, out.value()));
+            }
+        }
+    }
+
+    /// Fade gain `offset` frames into the clip
+    fn fade_gain(&self, offset: u64) -> f32 {
+        let mut level = 1.0;
+        if offset < self.fade_in.length {
+            level *= self.fade_in.gain(offset);
+        }
+        // Frames after this one, so the last frame of a fade out is silent
+        // like the first frame of a fade in
+        let remaining = self.length - offset - 1;
+        if remaining < self.fade_out.length {
+            level *= self.fade_out.gain(remaining);
+        }
+        level
+    }
+}
@@ -0,0 +1,277 @@
+//! Arrangement of clips on tracks
+//!
+//! The minimal core of an editor: an [`Arrangement`] holds tracks, each
+//! track holds [`Clip`]s, and each clip places a stretch of a
+//! [`ClipSource`] (with its own gain, gain envelope and fades) at a
+//! position on the timeline. [`Arrangement::process`] renders whatever
+//! the [`Transport`] is playing, to the sample, including across loop
+//! jumps.
+//!
+//! Clips can be stretched to fit the tempo, see [`WarpMarker`] and
+//! [`Clip::conform`].
+//!
+//! Sources are decoded into memory up front, so rendering never touches
+//! the disk and can run on the audio thread. The arrangement itself is
+//! owned by whoever renders it; edit a copy and swap it in rather than
+//! sharing one between threads.
+
+mod clip;
+mod warp;
+
+pub use clip::{Clip, ClipSource, Fade, GainEnvelope};
+pub use warp::WarpMarker;
+
+use crate::engine::transport::Transport;
+use crate::error::{AudioEngineError, Result};
+use crate::types::{ChannelCount, Gain, Sample, SampleRate, Timestamp};
+
+/// A named lane of clips with its own gain and mute.
+#[derive(Debug, Clone)]
+pub struct Track {
+    name: String,
+    sample_rate: SampleRate,
+    clips: Vec<Clip>,
+    gain: Gain,
+    muted: bool,
+}
+
+impl Track {
+    #[must_use]
+    pub fn new(name: impl Into<String>, sample_rate: SampleRate) -> Self {
+        Self {
+            name: name.into(),
+            sample_rate,
+            clips: Vec::new(),
+            gain: Gain::UNITY,
+            muted: false,
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Adds a clip, keeping clips ordered by position, and returns its
+    /// index.
+    ///
+    /// Clips may overlap; overlapping clips are summed.
+    ///
+    /// # Errors
+    /// Returns an error if the clip's source has a different sample rate
+    /// than the track.
+    pub fn add_clip(&mut self, clip: Clip) -> Result<usize> {
+        let source_rate = clip.source().format().sample_rate;
+        if source_rate != self.sample_rate {
+            return Err(AudioEngineError::FormatMismatch {
+                expected: format!("{:?}", self.sample_rate),
+                actual: format!("{source_rate:?} in {}", clip.source().name()),
+            });
+        }
+        let index = self
+            .clips
+            .partition_point(|other| other.position() <= clip.position());
+        self.clips.insert(index, clip);
+        Ok(index)
+    }
+
+    /// Removes and returns the clip at `index`
+    pub fn remove_clip(&mut self, index: usize) -> Option<Clip> {
+        (index < self.clips.len()).then(|| self.clips.remove(index))
+    }
+
+    #[must_use]
+    pub fn clips(&self) -> &[Clip] {
+        &self.clips
+    }
+
+    /// Moves a clip, keeping clips ordered. Returns its new index.
+    pub fn move_clip(&mut self, index: usize, position: Timestamp) -> Option<usize> {
+        let mut clip = self.remove_clip(index)?;
+        clip.set_position(position);
+        let index = self
+            .clips
+            .partition_point(|other| other.position() <= position);
+        self.clips.insert(index, clip);
+        Some(index)
+    }
+
+    /// Changes the gain of a clip. Returns false if there is no such clip.
+    pub fn set_clip_gain(&mut self, index: usize, gain: Gain) -> bool {
+        self.clips
+            .get_mut(index)
+            .map(|clip| clip.set_gain(gain))
+            .is_some()
+    }
+
+    /// Replaces the gain envelope of a clip. Returns false if there is no
+    /// such clip.
+    pub fn set_clip_envelope(&mut self, index: usize, envelope: GainEnvelope) -> bool {
+        self.clips
+            .get_mut(index)
+            .map(|clip| *clip.envelope_mut() = envelope)
+            .is_some()
+    }
+
+    /// Changes the fade handles of a clip. Returns false if there is no
+    /// such clip.
+    pub fn set_clip_fades(&mut self, index: usize, fade_in: Fade, fade_out: Fade) -> bool {
+        self.clips
+            .get_mut(index)
+            .map(|clip| {
+                clip.set_fade_in(fade_in);
+                clip.set_fade_out(fade_out);
+            })
+            .is_some()
+    }
+
+    #[must_use]
+    pub const fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    pub const fn set_gain(&mut self, gain: Gain) {
+        self.gain = gain;
+    }
+
+    #[must_use]
+    pub const fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    pub const fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// End of the last clip
+    #[must_use]
+    pub fn end(&self) -> Timestamp {
+        self.clips
+            .iter()
+            .map(|clip| clip.range().end())
+            .max()
+            .unwrap_or(Timestamp::ZERO)
+    }
+
+    /// Adds the block of the track starting at `start` to `output`.
+    fn render(&self, start: Timestamp, output: &mut [Sample], channels: usize) {
+        if self.muted {
+            return;
+        }
+        let block_end = start.as_samples() + (output.len() / channels) as u64;
+        let gain = self.gain.as_linear();
+        for clip in &self.clips {
+            if clip.position().as_samples() >= block_end {
+                // Ordered by position, so no later clip reaches the block
+                break;
+            }
+            clip.render(start, output, channels, gain);
+        }
+    }
+}
+
+/// Tracks of clips, rendered against a timeline.
+#[derive(Debug, Clone)]
+pub struct Arrangement {
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    tracks: Vec<Track>,
+}
+
+impl Arrangement {
+    #[must_use]
+    pub const fn new(sample_rate: SampleRate, channels: ChannelCount) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            tracks: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    #[must_use]
+    pub const fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    /// Adds an empty track and returns its index.
+    pub fn add_track(&mut self, name: impl Into<String>) -> usize {
+        self.tracks.push(Track::new(name, self.sample_rate));
+        self.tracks.len() - 1
+    }
+
+    /// Removes and returns the track at `index`
+    pub fn remove_track(&mut self, index: usize) -> Option<Track> {
+        (index < self.tracks.len()).then(|| self.tracks.remove(index))
+    }
+
+    #[must_use]
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    #[must_use]
+    pub fn track(&self, index: usize) -> Option<&Track> {
+        self.tracks.get(index)
+    }
+
+    pub fn track_mut(&mut self, index: usize) -> Option<&mut Track> {
+        self.tracks.get_mut(index)
+    }
+
+    /// Adds a clip to a track and returns its index on the track.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such track or the clip's sample rate
+    /// doesn't match.
+    pub fn add_clip(&mut self, track: usize, clip: Clip) -> Result<usize> {
+        self.tracks
+            .get_mut(track)
+            .ok_or_else(|| AudioEngineError::configuration(format!("no track {track}")))?
+            .add_clip(clip)
+    }
+
+    /// End of the last clip on any track
+    #[must_use]
+    pub fn end(&self) -> Timestamp {
+        self.tracks
+            .iter()
+            .map(Track::end)
+            .max()
+            .unwrap_or(Timestamp::ZERO)
+    }
+
+    /// Overwrites `output` with the interleaved block of the timeline
+    /// starting at `start`.
+    pub fn render(&self, start: Timestamp, output: &mut [Sample]) {
+        output.fill(Sample::SILENCE);
+        let channels = self.channels.count_usize();
+        for track in &self.tracks {
+            track.render(start, output, channels);
+        }
+    }
+
+    /// Renders one block of what `transport` plays and advances it.
+    ///
+    /// A block that crosses the loop end is rendered in pieces, each from
+    /// its own part of the timeline. While the transport isn't rolling the
+    /// block is silent.
+    pub fn process(&self, transport: &mut Transport, output: &mut [Sample]) {
+        let channels = self.channels.count_usize();
+        let mut done = 0;
+        while done < output.len() / channels {
+            let span = transport.advance(output.len() / channels - done);
+            let piece = &mut output[done * channels..(done + span.frames) * channels];
+            if span.rolling {
+                self.render(span.start, piece);
+            } else {
+                piece.fill(Sample::SILENCE);
+            }
+            done += span.frames;
+        }
+    }
+}
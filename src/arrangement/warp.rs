@@ -0,0 +1,148 @@
+//! Elastic audio: warping a clip's source onto the timeline
+//!
+//! A warp map is a list of [`WarpMarker`]s, each pinning a source frame to
+//! a frame of the clip. Between markers the source plays at a constant
+//! speed. The stretched audio is rendered once, with the WSOLA
+//! [`TimeStretcher`], whenever the map changes, so playback stays as cheap
+//! as for an unstretched clip.
+
+use crate::arrangement::ClipSource;
+use crate::dsp::time_stretch::{MAX_SPEED, MIN_SPEED, TimeStretcher};
+use crate::error::{AudioEngineError, Result};
+use crate::types::Sample;
+
+/// Input frames handed to the stretcher at a time, small enough that speed
+/// changes land close to their marker
+const FEED_FRAMES: usize = 256;
+
+/// Pins a source frame to a frame of the clip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WarpMarker {
+    /// Frame of the source
+    pub source_frame: u64,
+    /// Frames from the start of the clip
+    pub clip_frame: u64,
+}
+
+impl WarpMarker {
+    #[must_use]
+    pub const fn new(source_frame: u64, clip_frame: u64) -> Self {
+        Self {
+            source_frame,
+            clip_frame,
+        }
+    }
+}
+
+/// Builds a complete map from `markers`: anchored at `source_in` on the
+/// first clip frame, and running on to `source_out` at the speed of the
+/// last segment.
+///
+/// # Errors
+/// Returns an error if the markers don't move forward in both source and
+/// clip, fall outside the source range, or need a speed the stretcher
+/// can't play.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+pub fn warp_map(
+    source_in: u64,
+    source_out: u64,
+    markers: &[WarpMarker],
+) -> Result<Vec<WarpMarker>> {
+    let mut map = Vec::with_capacity(markers.len() + 2);
+    map.push(WarpMarker::new(source_in, 0));
+    for &marker in markers {
+        if marker == map[0] {
+            continue;
+        }
+        let last = map[map.len() - 1];
+        if marker.source_frame <= last.source_frame
+            || marker.clip_frame <= last.clip_frame
+            || marker.source_frame > source_out
+        {
+            return Err(AudioEngineError::configuration(format!(
+                "warp marker {}:{} is out of order or outside the clip",
+                marker.source_frame, marker.clip_frame
+            )));
+        }
+        map.push(marker);
+    }
+
+    let last = map[map.len() - 1];
+    if last.source_frame < source_out {
+        let speed = match map.len() {
+            1 => 1.0,
+            len => speed(map[len - 2], last),
+        };
+        let remaining = (source_out - last.source_frame) as f64 / speed;
+        map.push(WarpMarker::new(
+            source_out,
+            last.clip_frame + (remaining.round() as u64).max(1),
+        ));
+    }
+
+    for pair in map.windows(2) {
+        let speed = speed(pair[0], pair[1]);
+        // A little slack for rounding of marker positions
+        if speed < f64::from(MIN_SPEED) * 0.999 || speed > f64::from(MAX_SPEED) * 1.001 {
+            return Err(AudioEngineError::configuration(format!(
+                "warp speed {speed:.3} is outside {MIN_SPEED}..={MAX_SPEED}"
+            )));
+        }
+    }
+    Ok(map)
+}
+
+/// Source frame played `clip_frame` frames into the clip
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+pub fn source_frame(map: &[WarpMarker], clip_frame: u64) -> u64 {
+    let index = map
+        .partition_point(|marker| marker.clip_frame <= clip_frame)
+        .clamp(1, map.len() - 1);
+    let (from, to) = (map[index - 1], map[index]);
+    let offset = clip_frame.saturating_sub(from.clip_frame) as f64 * speed(from, to);
+    from.source_frame + offset.round() as u64
+}
+
+/// Renders the source frames the map covers, warped onto the clip's
+/// frames.
+#[allow(clippy::cast_possible_truncation)]
+pub fn stretch(source: &ClipSource, map: &[WarpMarker]) -> ClipSource {
+    let format = source.format();
+    let channels = format.channels.count_usize();
+    let (first, last) = (map[0], map[map.len() - 1]);
+    let length = usize::try_from(last.clip_frame).unwrap_or(usize::MAX);
+    let from = usize::try_from(first.source_frame).unwrap_or(usize::MAX) * channels;
+    let to = usize::try_from(last.source_frame).unwrap_or(usize::MAX) * channels;
+    let input = &source.samples()[from.min(source.samples().len())..to.min(source.samples().len())];
+
+    let mut stretcher = TimeStretcher::new(format.channels, format.sample_rate);
+    let mut output = Vec::with_capacity(length * channels + stretcher.latency_frames() * 4);
+    let mut index = 1;
+    for chunk in input.chunks(FEED_FRAMES * channels) {
+        // Output frames are clip frames, so pick the speed of the segment
+        // the output has reached
+        let written = (output.len() / channels) as u64;
+        while index < map.len() - 1 && map[index].clip_frame <= written {
+            index += 1;
+        }
+        stretcher.set_speed(speed(map[index - 1], map[index]) as f32);
+        stretcher.process(chunk, &mut output);
+    }
+    stretcher.flush(&mut output);
+    output.resize(length * channels, Sample::SILENCE);
+    ClipSource::new(source.name(), format, output)
+}
+
+/// Source frames played per clip frame between two markers
+#[allow(clippy::cast_precision_loss)]
+fn speed(from: WarpMarker, to: WarpMarker) -> f64 {
+    (to.source_frame - from.source_frame) as f64 / (to.clip_frame - from.clip_frame) as f64
+}
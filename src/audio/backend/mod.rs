@@ -0,0 +1,8 @@
+//! Audio backends
+//!
+//! Hardware devices go through cpal; [`VirtualDevice`] is a software device
+//! driven by the caller, for running the stream path in tests.
+
+pub mod virtual_device;
+
+pub use virtual_device::VirtualDevice;
@@ -0,0 +1,252 @@
+//! Virtual audio device
+//!
+//! [`VirtualDevice`] stands in for a sound card in tests and CI. Streams
+//! are built on it just like on a cpal device, but nothing runs until the
+//! test calls [`advance`](VirtualDevice::advance): each call moves a fake
+//! clock forward one device buffer at a time, feeding the input callback
+//! with frames queued by [`push_input`](VirtualDevice::push_input) and
+//! collecting what the output callback renders for
+//! [`pull_output`](VirtualDevice::pull_output).
+//!
+//! The device is clocked whether or not its streams are playing: a paused
+//! output renders silence and a paused input drops the frames it would
+//! have captured, as hardware does.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::types::{AudioFormat, FrameCount, Sample};
+
+type InputCallback = Box<dyn FnMut(&[f32]) + Send>;
+type OutputCallback = Box<dyn FnMut(&mut [f32]) + Send>;
+
+struct Callback<F> {
+    callback: F,
+    playing: bool,
+}
+
+struct State {
+    input: VecDeque<Sample>,
+    output: VecDeque<Sample>,
+    input_callback: Option<Callback<InputCallback>>,
+    output_callback: Option<Callback<OutputCallback>>,
+    scratch: Vec<f32>,
+    clock: u64,
+    input_shortfall: u64,
+}
+
+/// A programmatically driven audio device.
+///
+/// Clones share the same device, so a test can keep one to drive it after
+/// handing another to an engine.
+#[derive(Clone)]
+pub struct VirtualDevice {
+    format: AudioFormat,
+    buffer_frames: usize,
+    state: Arc<Mutex<State>>,
+}
+
+impl VirtualDevice {
+    /// Creates a device that calls its streams with `buffer_frames` frames
+    /// at a time.
+    #[must_use]
+    pub fn new(format: AudioFormat, buffer_frames: usize) -> Self {
+        let buffer_frames = buffer_frames.max(1);
+        Self {
+            format,
+            buffer_frames,
+            state: Arc::new(Mutex::new(State {
+                input: VecDeque::new(),
+                output: VecDeque::new(),
+                input_callback: None,
+                output_callback: None,
+                scratch: vec![0.0; buffer_frames * format.channels.count_usize()],
+                clock: 0,
+                input_shortfall: 0,
+            })),
+        }
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    #[must_use]
+    pub const fn buffer_frames(&self) -> usize {
+        self.buffer_frames
+    }
+
+    /// Frames the device clock has advanced
+    #[must_use]
+    pub fn now(&self) -> FrameCount {
+        FrameCount::new(self.state.lock().clock)
+    }
+
+    /// Queues interleaved samples to be captured by the input stream.
+    pub fn push_input(&self, samples: &[Sample]) {
+        self.state.lock().input.extend(samples);
+    }
+
+    /// Queued input samples not yet captured
+    #[must_use]
+    pub fn pending_input(&self) -> usize {
+        self.state.lock().input.len()
+    }
+
+    /// Input frames that were padded with silence because nothing had been
+    /// pushed
+    #[must_use]
+    pub fn input_shortfall(&self) -> FrameCount {
+        FrameCount::new(self.state.lock().input_shortfall)
+    }
+
+    /// Takes up to `samples.len()` rendered output samples, returning how
+    /// many were written.
+    pub fn pull_output(&self, samples: &mut [Sample]) -> usize {
+        let mut state = self.state.lock();
+        let count = samples.len().min(state.output.len());
+        for (sample, value) in samples.iter_mut().zip(state.output.drain(..count)) {
+            *sample = value;
+        }
+        drop(state);
+        count
+    }
+
+    /// Takes all rendered output.
+    #[must_use]
+    pub fn take_output(&self) -> Vec<Sample> {
+        self.state.lock().output.drain(..).collect()
+    }
+
+    /// Rendered output samples not yet pulled
+    #[must_use]
+    pub fn pending_output(&self) -> usize {
+        self.state.lock().output.len()
+    }
+
+    /// Runs the device for at least `frames` frames, in whole buffers.
+    ///
+    /// For each buffer the input callback runs before the output callback,
+    /// so audio captured in a buffer can be played out in the same one.
+    pub fn advance(&self, frames: usize) {
+        let channels = self.format.channels.count_usize();
+        let buffer_samples = self.buffer_frames * channels;
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+
+        for _ in 0..frames.div_ceil(self.buffer_frames) {
+            let available = state.input.len().min(buffer_samples);
+            for (slot, sample) in state.scratch.iter_mut().zip(state.input.drain(..available)) {
+                *slot = sample.value();
+            }
+            state.scratch[available..].fill(0.0);
+            state.input_shortfall += ((buffer_samples - available) / channels) as u64;
+            if let Some(input) = &mut state.input_callback
+                && input.playing
+            {
+                (input.callback)(&state.scratch);
+            }
+
+            state.scratch.fill(0.0);
+            if let Some(output) = &mut state.output_callback
+                && output.playing
+            {
+                (output.callback)(&mut state.scratch);
+            }
+            state
+                .output
+                .extend(state.scratch.iter().map(|&value| Sample::new(value)));
+            state.clock += self.buffer_frames as u64;
+        }
+        drop(guard);
+    }
+
+    pub(crate) fn build_input<F>(&self, callback: F) -> VirtualStream
+    where
+        F: FnMut(&[f32]) + Send + 'static,
+    {
+        self.state.lock().input_callback = Some(Callback {
+            callback: Box::new(callback),
+            playing: false,
+        });
+        VirtualStream {
+            device: self.clone(),
+            direction: Direction::Input,
+        }
+    }
+
+    pub(crate) fn build_output<F>(&self, callback: F) -> VirtualStream
+    where
+        F: FnMut(&mut [f32]) + Send + 'static,
+    {
+        self.state.lock().output_callback = Some(Callback {
+            callback: Box::new(callback),
+            playing: false,
+        });
+        VirtualStream {
+            device: self.clone(),
+            direction: Direction::Output,
+        }
+    }
+}
+
+impl fmt::Debug for VirtualDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock();
+        f.debug_struct("VirtualDevice")
+            .field("format", &self.format)
+            .field("buffer_frames", &self.buffer_frames)
+            .field("clock", &state.clock)
+            .field("pending_input", &state.input.len())
+            .field("pending_output", &state.output.len())
+            .field("input_shortfall", &state.input_shortfall)
+            .field("has_input_stream", &state.input_callback.is_some())
+            .field("has_output_stream", &state.output_callback.is_some())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Input,
+    Output,
+}
+
+/// A stream on a [`VirtualDevice`]; dropping it detaches its callback.
+#[derive(Debug)]
+pub(crate) struct VirtualStream {
+    device: VirtualDevice,
+    direction: Direction,
+}
+
+impl VirtualStream {
+    pub(crate) fn set_playing(&self, playing: bool) {
+        let mut state = self.device.state.lock();
+        match self.direction {
+            Direction::Input => {
+                if let Some(input) = &mut state.input_callback {
+                    input.playing = playing;
+                }
+            }
+            Direction::Output => {
+                if let Some(output) = &mut state.output_callback {
+                    output.playing = playing;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for VirtualStream {
+    fn drop(&mut self) {
+        let mut state = self.device.state.lock();
+        match self.direction {
+            Direction::Input => state.input_callback = None,
+            Direction::Output => state.output_callback = None,
+        }
+    }
+}
@@ -331,6 +331,35 @@ impl AudioDeviceManager {
     pub fn host(&self) -> &cpal::Host {
         &self.host
     }
+
+    /// Lists the input devices like [`input_devices`](Self::input_devices),
+    /// on another thread, as enumerating can take a while.
+    ///
+    /// # Errors
+    /// Returns an error if device enumeration fails.
+    #[cfg(feature = "async")]
+    pub async fn input_devices_async(&self) -> Result<Vec<AudioDevice>> {
+        let host = self.host.id();
+        crate::channel::unblock("list-inputs", move || {
+            Self::with_host(host)?.input_devices()
+        })
+        .await
+    }
+
+    /// Lists the output devices like
+    /// [`output_devices`](Self::output_devices), on another thread, as
+    /// enumerating can take a while.
+    ///
+    /// # Errors
+    /// Returns an error if device enumeration fails.
+    #[cfg(feature = "async")]
+    pub async fn output_devices_async(&self) -> Result<Vec<AudioDevice>> {
+        let host = self.host.id();
+        crate::channel::unblock("list-outputs", move || {
+            Self::with_host(host)?.output_devices()
+        })
+        .await
+    }
 }
 
 impl Default for AudioDeviceManager {
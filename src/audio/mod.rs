@@ -1,3 +1,4 @@
+pub mod backend;
 pub mod context;
 ///! Audio device and stream management
 ///
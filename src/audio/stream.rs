@@ -1,37 +1,238 @@
+//! Audio streams on hardware and virtual devices
+//!
+//! A [`StreamHandle`] owns a running stream, and the stream lives exactly as
+//! long as the handle. End it with [`close`](StreamHandle::close), or keep
+//! it running for the rest of the process with
+//! [`detach`](StreamHandle::detach). A handle that is simply dropped still
+//! stops its stream, but logs a warning if the stream was playing, since
+//! that is usually a handle that went out of scope by mistake.
+//!
+//! Handles given a feedback sender with
+//! [`with_feedback`](StreamHandle::with_feedback) report how their stream
+//! ended as [`EngineFeedback::StreamClosed`] or
+//! [`EngineFeedback::StreamDetached`].
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use crate::audio::backend::virtual_device::{VirtualDevice, VirtualStream};
 use crate::audio::device::AudioDevice;
 use crate::buffer::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::channel::{EngineFeedback, RealtimeSender};
 use crate::error::{AudioEngineError, Result};
-use crate::types::{AudioFormat, ChannelCount, Sample, SampleRate};
+use crate::types::{AudioFormat, BufferSize, ChannelCount, DeviceType, Sample, SampleRate};
 use cpal::Stream;
 use cpal::traits::{DeviceTrait, StreamTrait};
 
-/// Hanlde to a running audio stream
+/// Stream on a hardware or a virtual device
+enum Backend {
+    Cpal(Stream),
+    Virtual(VirtualStream),
+}
+
+/// How a stream ended, as reported in [`EngineFeedback::StreamClosed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StreamCloseReason {
+    /// [`StreamHandle::close`] was called
+    Closed,
+    /// The handle was dropped without being closed or detached
+    Dropped,
+}
+
+/// Handle to a running audio stream.
+///
+/// The stream stops when the handle goes away, so keep the handle for as
+/// long as the audio should run and end it with [`close`](Self::close) or
+/// [`detach`](Self::detach).
+///
+/// The handle is deliberately not `Send`: hardware streams must be stopped
+/// on the thread that started them on some hosts, so the handle stays on
+/// the thread that built it. Move the ring buffer ends from
+/// [`AudioOutputStream::into_parts`] or [`AudioInputStream::into_parts`] to
+/// other threads instead.
 pub struct StreamHandle {
-    stream: Stream,
+    stream: Backend,
     format: AudioFormat,
+    kind: DeviceType,
+    playing: Cell<bool>,
+    feedback: Option<RealtimeSender<EngineFeedback>>,
+    /// Set once closed or detached, so drop stays quiet
+    released: bool,
+    _not_send: PhantomData<*const ()>,
 }
 
 impl StreamHandle {
+    const fn new(stream: Backend, format: AudioFormat, kind: DeviceType) -> Self {
+        Self {
+            stream,
+            format,
+            kind,
+            playing: Cell::new(false),
+            feedback: None,
+            released: false,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Reports how the stream ends on `feedback`.
+    #[must_use]
+    pub fn with_feedback(mut self, feedback: RealtimeSender<EngineFeedback>) -> Self {
+        self.feedback = Some(feedback);
+        self
+    }
+
     pub fn play(&self) -> Result<()> {
-        self.stream
-            .play()
-            .map_err(|e| AudioEngineError::DeviceAccess {
+        match &self.stream {
+            Backend::Cpal(stream) => stream.play().map_err(|e| AudioEngineError::DeviceAccess {
                 message: format!("Failed to start stream: {e}"),
-            })
+            })?,
+            Backend::Virtual(stream) => stream.set_playing(true),
+        }
+        self.playing.set(true);
+        Ok(())
     }
 
     pub fn pause(&self) -> Result<()> {
-        self.stream
-            .pause()
-            .map_err(|e| AudioEngineError::DeviceAccess {
-                message: format!("Failed to pause stream: {e}"),
-            })
+        match &self.stream {
+            Backend::Cpal(stream) => {
+                stream.pause().map_err(|e| AudioEngineError::DeviceAccess {
+                    message: format!("Failed to pause stream: {e}"),
+                })?;
+            }
+            Backend::Virtual(stream) => stream.set_playing(false),
+        }
+        self.playing.set(false);
+        Ok(())
     }
 
     #[must_use]
     pub const fn format(&self) -> AudioFormat {
         self.format
     }
+
+    /// Whether this is an input or an output stream
+    #[must_use]
+    pub const fn kind(&self) -> DeviceType {
+        self.kind
+    }
+
+    #[must_use]
+    pub const fn is_playing(&self) -> bool {
+        self.playing.get()
+    }
+
+    /// Stops the stream and releases the device.
+    ///
+    /// # Errors
+    /// Returns an error if the stream couldn't be paused first; it is
+    /// released all the same.
+    pub fn close(mut self) -> Result<()> {
+        let paused = if self.is_playing() {
+            self.pause()
+        } else {
+            Ok(())
+        };
+        self.released = true;
+        self.report(EngineFeedback::StreamClosed {
+            kind: self.kind,
+            reason: StreamCloseReason::Closed,
+        });
+        paused
+    }
+
+    /// Lets the stream run on without the handle, until the process exits.
+    /// There is no way to stop it afterwards, short of ending the process.
+    pub fn detach(self) {
+        self.report(EngineFeedback::StreamDetached { kind: self.kind });
+        // Leaking the handle keeps the stream, and its device, open
+        std::mem::forget(self);
+    }
+
+    fn report(&self, feedback: EngineFeedback) {
+        if let Some(sender) = &self.feedback {
+            let _ = sender.try_send(feedback);
+        }
+    }
+
+    /// Builds an output stream that calls `callback` for every device
+    /// buffer, for callers that generate audio directly in the callback,
+    /// with the time from the call until the buffer reaches the DAC.
+    /// Device buffers are `buffer_size` frames, or the host's default size.
+    pub(crate) fn output<F, E>(
+        device: &AudioDevice,
+        format: AudioFormat,
+        buffer_size: Option<BufferSize>,
+        mut callback: F,
+        err_callback: E,
+    ) -> Result<Self>
+    where
+        F: FnMut(&mut [f32], Duration) + Send + 'static,
+        E: FnMut(cpal::StreamError) + Send + 'static,
+    {
+        let mut config =
+            device
+                .best_config(&format)
+                .ok_or_else(|| AudioEngineError::FormatMismatch {
+                    expected: format.to_string(),
+                    actual: "No compatible configuration".to_string(),
+                })?;
+        if let Some(size) = buffer_size {
+            config.buffer_size = cpal::BufferSize::Fixed(size.as_u32());
+        }
+
+        let stream = device
+            .cpal_device()
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                    let timestamp = info.timestamp();
+                    let latency = timestamp
+                        .playback
+                        .duration_since(&timestamp.callback)
+                        .unwrap_or_default();
+                    callback(data, latency);
+                },
+                err_callback,
+                None,
+            )
+            .map_err(|e| AudioEngineError::DeviceAccess {
+                message: format!("Failed to build output stream: {e}"),
+            })?;
+
+        Ok(Self::new(Backend::Cpal(stream), format, DeviceType::Output))
+    }
+
+    /// Like [`output`](Self::output), on a virtual device, which has no
+    /// latency to the DAC.
+    pub(crate) fn virtual_output<F>(device: &VirtualDevice, mut callback: F) -> Self
+    where
+        F: FnMut(&mut [f32], Duration) + Send + 'static,
+    {
+        Self::new(
+            Backend::Virtual(device.build_output(move |data| callback(data, Duration::ZERO))),
+            device.format(),
+            DeviceType::Output,
+        )
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        if self.is_playing() {
+            log::warn!(
+                "{} stream dropped while playing; close() or detach() its handle to end it on purpose",
+                self.kind
+            );
+        }
+        self.report(EngineFeedback::StreamClosed {
+            kind: self.kind,
+            reason: StreamCloseReason::Dropped,
+        });
+    }
 }
 
 /// Input callback
@@ -123,11 +324,25 @@ impl AudioOutputStream {
             })?;
 
         Ok(Self {
-            handle: StreamHandle { stream, format },
+            handle: StreamHandle::new(Backend::Cpal(stream), format, DeviceType::Output),
             writer,
         })
     }
 
+    /// Creates an output stream on a virtual device.
+    #[must_use]
+    pub fn virtual_output(device: &VirtualDevice, buffer_frames: usize) -> Self {
+        let format = device.format();
+        let buffer_size = buffer_frames * format.channels.count_usize() * 4;
+        let (writer, mut reader) = RingBuffer::<Sample>::new(buffer_size);
+        Self {
+            handle: StreamHandle::virtual_output(device, move |data, _| {
+                output_callback(data, &mut reader);
+            }),
+            writer,
+        }
+    }
+
     pub fn start(&self) -> Result<()> {
         self.handle.play()
     }
@@ -136,6 +351,14 @@ impl AudioOutputStream {
         self.handle.pause()
     }
 
+    /// Stops the stream and releases the device; see [`StreamHandle::close`].
+    ///
+    /// # Errors
+    /// Returns an error if the stream couldn't be paused first.
+    pub fn close(self) -> Result<()> {
+        self.handle.close()
+    }
+
     #[must_use]
     pub fn writer(&mut self) -> &mut RingBufferWriter<Sample> {
         &mut self.writer
@@ -149,6 +372,13 @@ impl AudioOutputStream {
     pub fn available(&self) -> usize {
         self.writer.slots()
     }
+
+    /// Splits the stream into its handle and the writer end of its ring
+    /// buffer, so the samples can be produced on another thread.
+    #[must_use]
+    pub fn into_parts(self) -> (StreamHandle, RingBufferWriter<Sample>) {
+        (self.handle, self.writer)
+    }
 }
 
 pub struct AudioInputStream {
@@ -158,13 +388,27 @@ pub struct AudioInputStream {
 
 impl AudioInputStream {
     pub fn new(device: &AudioDevice, format: AudioFormat, buffer_frames: usize) -> Result<Self> {
-        let config =
+        Self::with_buffer_size(device, format, buffer_frames, None)
+    }
+
+    /// Like [`new`](Self::new), with device buffers of `buffer_size`
+    /// frames rather than the host's default size.
+    pub(crate) fn with_buffer_size(
+        device: &AudioDevice,
+        format: AudioFormat,
+        buffer_frames: usize,
+        buffer_size: Option<BufferSize>,
+    ) -> Result<Self> {
+        let mut config =
             device
                 .best_config(&format)
                 .ok_or_else(|| AudioEngineError::FormatMismatch {
                     expected: format.to_string(),
                     actual: "no compatible configuration".to_string(),
                 })?;
+        if let Some(size) = buffer_size {
+            config.buffer_size = cpal::BufferSize::Fixed(size.as_u32());
+        }
 
         let buffer_size = buffer_frames * format.channels.count_usize();
         let (mut writer, reader) = RingBuffer::<Sample>::new(buffer_size);
@@ -188,11 +432,29 @@ impl AudioInputStream {
             })?;
 
         Ok(Self {
-            handle: StreamHandle { stream, format },
+            handle: StreamHandle::new(Backend::Cpal(stream), format, DeviceType::Input),
             reader,
         })
     }
 
+    /// Creates an input stream on a virtual device.
+    #[must_use]
+    pub fn virtual_input(device: &VirtualDevice, buffer_frames: usize) -> Self {
+        let format = device.format();
+        let buffer_size = buffer_frames * format.channels.count_usize();
+        let (mut writer, reader) = RingBuffer::<Sample>::new(buffer_size);
+        Self {
+            handle: StreamHandle::new(
+                Backend::Virtual(device.build_input(move |data| {
+                    input_callback(data, &mut writer);
+                })),
+                format,
+                DeviceType::Input,
+            ),
+            reader,
+        }
+    }
+
     pub fn start(&self) -> Result<()> {
         self.handle.play()
     }
@@ -201,6 +463,14 @@ impl AudioInputStream {
         self.handle.pause()
     }
 
+    /// Stops the stream and releases the device; see [`StreamHandle::close`].
+    ///
+    /// # Errors
+    /// Returns an error if the stream couldn't be paused first.
+    pub fn close(self) -> Result<()> {
+        self.handle.close()
+    }
+
     #[must_use]
     pub const fn format(&self) -> AudioFormat {
         self.handle.format()
@@ -219,4 +489,11 @@ impl AudioInputStream {
     pub fn available(&self) -> usize {
         self.reader.slots()
     }
+
+    /// Splits the stream into its handle and the reader end of its ring
+    /// buffer, so the samples can be consumed on another thread.
+    #[must_use]
+    pub fn into_parts(self) -> (StreamHandle, RingBufferReader<Sample>) {
+        (self.handle, self.reader)
+    }
 }
@@ -0,0 +1,138 @@
+//! Accounting of pre-allocated memory
+//!
+//! Everything the audio thread touches is allocated while the engine is
+//! configured: effect state, FFT and scratch buffers, node buffers and the
+//! rings between threads. A [`MemoryReport`] lists those allocations, one
+//! [`MemoryEntry`] per effect, node or buffer, so the total can be checked
+//! against a RAM budget before anything starts.
+//!
+//! Sizes count the heap buffers a part owns plus the part itself, not
+//! allocator overhead, so the real footprint is slightly higher.
+
+use std::fmt;
+
+/// What a [`MemoryEntry`] accounts for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryKind {
+    /// An effect and everything it owns
+    Effect,
+    /// A graph node and its output buffer
+    Node,
+    /// A block, scratch or bus buffer
+    Buffer,
+    /// A ring buffer between threads
+    Ring,
+}
+
+impl fmt::Display for MemoryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Effect => write!(f, "effect"),
+            Self::Node => write!(f, "node"),
+            Self::Buffer => write!(f, "buffer"),
+            Self::Ring => write!(f, "ring"),
+        }
+    }
+}
+
+/// One allocation in a [`MemoryReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryEntry {
+    /// Path of the part, e.g. `chain/Pitch Shift (Effect#3)`
+    pub name: String,
+    pub kind: MemoryKind,
+    pub bytes: usize,
+}
+
+/// Pre-allocated memory of an engine, mixer, graph or chain.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    entries: Vec<MemoryEntry>,
+}
+
+impl MemoryReport {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, kind: MemoryKind, bytes: usize) {
+        self.entries.push(MemoryEntry {
+            name: name.into(),
+            kind,
+            bytes,
+        });
+    }
+
+    /// Takes over the entries of `other`, naming them `prefix/name`.
+    pub fn append(&mut self, prefix: &str, other: Self) {
+        self.entries
+            .extend(other.entries.into_iter().map(|entry| MemoryEntry {
+                name: format!("{prefix}/{}", entry.name),
+                ..entry
+            }));
+    }
+
+    #[must_use]
+    pub fn entries(&self) -> &[MemoryEntry] {
+        &self.entries
+    }
+
+    /// Bytes of all entries
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.entries.iter().map(|entry| entry.bytes).sum()
+    }
+
+    /// Bytes of the entries of one kind
+    #[must_use]
+    pub fn total_of(&self, kind: MemoryKind) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.kind == kind)
+            .map(|entry| entry.bytes)
+            .sum()
+    }
+
+    /// Whether everything fits in `budget` bytes
+    #[must_use]
+    pub fn fits(&self, budget: usize) -> bool {
+        self.total() <= budget
+    }
+
+    /// The largest entry, the first candidate for trimming
+    #[must_use]
+    pub fn largest(&self) -> Option<&MemoryEntry> {
+        self.entries.iter().max_by_key(|entry| entry.bytes)
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .entries
+            .iter()
+            .map(|entry| entry.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(5);
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{:<width$}  {:<6}  {:>10}",
+                entry.name,
+                entry.kind.to_string(),
+                entry.bytes
+            )?;
+        }
+        write!(f, "{:<width$}  {:<6}  {:>10}", "total", "", self.total())
+    }
+}
+
+/// Bytes reserved by `buffer`, counting its whole capacity
+#[must_use]
+pub const fn heap_bytes<T>(buffer: &Vec<T>) -> usize {
+    buffer.capacity() * size_of::<T>()
+}
@@ -4,8 +4,11 @@
 //! This module provides
 //! - [`RealtimeBuffer`]: Pre allocated, non resizing buffer for RT contexts
 //! - [`Ring buffer`]: Lock free SPSC ring buffer for RT communications
+//! - [`MemoryReport`]: Accounting of the memory allocated up front
 
+pub mod memory;
 pub mod realtime;
 pub mod ring;
+pub use memory::{MemoryEntry, MemoryKind, MemoryReport};
 pub use realtime::RealtimeBuffer;
 pub use ring::{RingBuffer, RingBufferReader, RingBufferWriter};
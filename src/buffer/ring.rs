@@ -45,6 +45,12 @@ pub struct RingBufferWriter<T> {
 }
 
 impl<T> RingBufferWriter<T> {
+    /// Returns the number of elements the buffer can hold.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.inner.buffer().capacity()
+    }
+
     /// Returns the number of slots available for writing.
     #[must_use]
     pub fn slots(&self) -> usize {
@@ -125,6 +131,12 @@ pub struct RingBufferReader<T> {
 }
 
 impl<T> RingBufferReader<T> {
+    /// Returns the number of elements the buffer can hold.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.inner.buffer().capacity()
+    }
+
     /// Returns the number of elements available for reading.
     #[must_use]
     pub fn slots(&self) -> usize {
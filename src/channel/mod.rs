@@ -2,6 +2,10 @@
 //!
 //! This module provides type-safe wrappers around channels that enforce
 //! real-time safety at the type level.
+//!
+//! With the `async` feature the control ends can also be awaited, and
+//! feedback read as a [`Stream`](futures_core::Stream), from tokio or any
+//! other runtime; nothing waits on a thread of its own.
 
 use flume::{Receiver, Sender, TrySendError};
 use std::fmt;
@@ -26,6 +30,30 @@ pub fn feedback_channel<T>(capacity: usize) -> (RealtimeSender<T>, ControlReceiv
     (RealtimeSender { inner: tx }, ControlReceiver { inner: rx })
 }
 
+/// Grows the queue of a feedback channel to its full capacity, so sends
+/// from the real-time thread never allocate. The queue otherwise grows
+/// the first time it fills.
+///
+/// `filler` makes placeholder messages, which are taken out again;
+/// messages already queued are kept, in order. Call it before the
+/// real-time thread starts sending.
+pub fn reserve_feedback<T>(
+    sender: &RealtimeSender<T>,
+    receiver: &ControlReceiver<T>,
+    mut filler: impl FnMut() -> T,
+) {
+    let pending = receiver.drain();
+    for _ in 0..sender.capacity().unwrap_or(0) {
+        if !sender.try_send(filler()) {
+            break;
+        }
+    }
+    while receiver.try_recv().is_some() {}
+    for message in pending {
+        let _ = sender.try_send(message);
+    }
+}
+
 // ============================================================================
 // Control Thread -> Real-Time Thread
 // ============================================================================
@@ -49,6 +77,19 @@ impl<T> ControlSender<T> {
             .map_err(|_| AudioEngineError::ChannelSendFailed)
     }
 
+    /// Sends a message, waiting without blocking the thread if the
+    /// channel is full.
+    ///
+    /// # Errors
+    /// Returns an error if the receiver has been dropped.
+    #[cfg(feature = "async")]
+    pub async fn send_async(&self, msg: T) -> Result<()> {
+        self.inner
+            .send_async(msg)
+            .await
+            .map_err(|_| AudioEngineError::ChannelSendFailed)
+    }
+
     /// Tries to send a message without blocking.
     ///
     /// # Errors
@@ -150,6 +191,12 @@ impl<T> RealtimeReceiver<T> {
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
     }
+
+    /// Returns the most messages the channel can hold.
+    #[must_use]
+    pub fn capacity(&self) -> Option<usize> {
+        self.inner.capacity()
+    }
 }
 
 impl<T: Send + 'static> RealtimeSafe for RealtimeReceiver<T> {}
@@ -202,6 +249,12 @@ impl<T> RealtimeSender<T> {
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
     }
+
+    /// Returns the most messages the channel can hold.
+    #[must_use]
+    pub fn capacity(&self) -> Option<usize> {
+        self.inner.capacity()
+    }
 }
 
 impl<T: Send + 'static> RealtimeSafe for RealtimeSender<T> {}
@@ -259,6 +312,34 @@ impl<T> ControlReceiver<T> {
             .map_err(|_| AudioEngineError::ChannelRecvFailed)
     }
 
+    /// Receives a message, waiting without blocking the thread if none is
+    /// available.
+    ///
+    /// # Errors
+    /// Returns an error if the sender has been dropped.
+    #[cfg(feature = "async")]
+    pub async fn recv_async(&self) -> Result<T> {
+        self.inner
+            .recv_async()
+            .await
+            .map_err(|_| AudioEngineError::ChannelRecvFailed)
+    }
+
+    /// Messages as they arrive, ending once the sender is dropped.
+    #[cfg(feature = "async")]
+    pub fn stream(&self) -> impl futures_core::Stream<Item = T> + Unpin + '_ {
+        self.inner.stream()
+    }
+
+    /// Like [`stream`](Self::stream), owning the receiver.
+    #[cfg(feature = "async")]
+    pub fn into_stream(self) -> impl futures_core::Stream<Item = T> + Unpin
+    where
+        T: 'static,
+    {
+        self.inner.into_stream()
+    }
+
     /// Drains all available messages.
     #[must_use]
     pub fn drain(&self) -> Vec<T> {
@@ -293,6 +374,26 @@ impl<T> fmt::Debug for ControlReceiver<T> {
     }
 }
 
+/// Runs blocking `work`, such as device enumeration or binding a socket,
+/// on a short-lived thread and waits for it without blocking the caller's.
+#[cfg(feature = "async")]
+pub(crate) async fn unblock<T: Send + 'static>(
+    name: &str,
+    work: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let (sender, receiver) = flume::bounded(1);
+    std::thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            // The caller may have stopped waiting
+            let _ = sender.send(work());
+        })?;
+    receiver
+        .recv_async()
+        .await
+        .map_err(|_| AudioEngineError::ChannelRecvFailed)?
+}
+
 // ============================================================================
 // Control Message Types
 // ============================================================================
@@ -328,6 +429,50 @@ pub enum EngineCommand {
         /// Whether the effect is enabled
         enabled: bool,
     },
+    /// Move the playhead without starting or stopping it
+    Seek(crate::types::Timestamp),
+    /// Stop the transport at a position, which becomes its return point
+    Locate(crate::types::Timestamp),
+    /// Set the loop range, or turn looping off
+    SetLoop(Option<crate::types::TimeRange>),
+    /// Set the punch range, or clear it
+    SetPunch(Option<crate::types::TimeRange>),
+    /// Report the position as SMPTE timecode at a video frame rate, or as
+    /// plain time
+    SetFrameRate(Option<crate::types::FrameRate>),
+    /// Replace the tempo map with one tempo from the start, keeping the
+    /// meter there; sent when following an external clock
+    SetTempo(crate::types::Tempo),
+    /// Move the playhead to a position in quarter notes on the tempo map,
+    /// without starting or stopping it
+    SeekBeats(f64),
+    /// Start a recording pass: play from the pre-roll before the punch-in
+    /// point after a count-in; see
+    /// [`Transport::record`](crate::engine::transport::Transport::record)
+    Record(crate::engine::transport::CountIn),
+    /// Change the metronome's sound, or switch its clicks while playing
+    SetMetronome(crate::engine::metronome::MetronomeSettings),
+    /// Move the crossfader between the input (0.0) and the crossfade
+    /// source (1.0)
+    SetCrossfade(f32),
+    /// Start or stop reporting parameter changes made while the transport
+    /// plays, as [`EngineFeedback::ParamRecorded`], for an
+    /// [`AutomationRecorder`](crate::engine::automation::AutomationRecorder)
+    RecordAutomation(bool),
+    /// Arm the allocation guard; sent by
+    /// [`Engine::prepare`](crate::engine::Engine::prepare)
+    Prepare,
+    /// Fade the output to silence (true) or back (false) over a few
+    /// milliseconds; sent by
+    /// [`Engine::set_buffer_size`](crate::engine::Engine::set_buffer_size)
+    /// around rebuilding the streams
+    Mute(bool),
+    /// Move every effect in the chain to a quality tier; see
+    /// [`Effect::set_quality`](crate::dsp::traits::Effect::set_quality)
+    SetEffectQuality(crate::dsp::quality::EffectQuality),
+    /// Start a new listener's exposure dose; see
+    /// [`Engine::reset_exposure`](crate::engine::Engine::reset_exposure)
+    ResetExposure,
     /// Shutdown the engine
     Shutdown,
 }
@@ -352,6 +497,113 @@ pub enum EngineFeedback {
     Underrun,
     /// Error occurred
     Error(String),
+    /// The audio thread allocated after the engine was prepared
+    AllocationViolation {
+        /// Allocations since the last report
+        count: u64,
+    },
+    /// Progress of an offline render
+    RenderProgress {
+        /// Frames written so far
+        frames: u64,
+        /// Frames the render will write in all
+        total_frames: u64,
+    },
+    /// The streams were rebuilt, or an attempt to rebuild them failed,
+    /// after a stream error or stall; sent by an
+    /// [`EngineWatchdog`](crate::engine::watchdog::EngineWatchdog)
+    StreamRecovery {
+        /// Attempts since the streams last ran cleanly, counting this one
+        attempt: u32,
+        /// Output device the streams were rebuilt on, empty for a virtual
+        /// device
+        device: String,
+        /// What the watchdog was recovering from
+        reason: String,
+        /// Whether the streams were rebuilt
+        recovered: bool,
+    },
+    /// A parameter changed while automation was being recorded
+    ParamRecorded {
+        /// Transport position the change was applied at
+        position: crate::types::Timestamp,
+        /// Effect identifier
+        effect_id: u32,
+        /// Parameter identifier, or
+        /// [`ENABLED`](crate::dsp::automation::ENABLED) for a switch
+        param_id: u32,
+        /// New value; 0.0 or 1.0 for a switch
+        value: f32,
+    },
+    /// An effect failed for good, e.g. the process of an
+    /// [`IsolatedEffect`](crate::dsp::isolated::IsolatedEffect) crashed,
+    /// and was bypassed
+    EffectFailed {
+        /// Effect identifier
+        effect_id: u32,
+    },
+    /// A stream was closed, or its handle dropped, ending the stream; sent
+    /// by a [`StreamHandle`](crate::audio::stream::StreamHandle) given the
+    /// feedback sender
+    StreamClosed {
+        /// Whether it was an input or an output stream
+        kind: crate::types::DeviceType,
+        reason: crate::audio::stream::StreamCloseReason,
+    },
+    /// A stream was detached from its handle and runs on until the process
+    /// exits
+    StreamDetached {
+        /// Whether it is an input or an output stream
+        kind: crate::types::DeviceType,
+    },
+    /// Playback crossed a marker of the file; sent by a
+    /// [`PlaybackHandle`](crate::engine::simple::PlaybackHandle)
+    MarkerReached {
+        /// Index of the marker in the file's
+        /// [`MarkerList`](crate::io::cue::MarkerList)
+        index: usize,
+        position: crate::types::Timestamp,
+        name: String,
+    },
+    /// The device buffer size was raised because callbacks kept overrunning;
+    /// sent by a [`BufferAdapter`](crate::engine::overload::BufferAdapter)
+    BufferSizeChanged {
+        /// Size before, `None` for the host's default
+        previous: Option<crate::types::BufferSize>,
+        size: crate::types::BufferSize,
+        /// Overrunning callbacks that set it off
+        overruns: u32,
+    },
+    /// The effects were moved to another quality tier as CPU headroom
+    /// changed; sent by a
+    /// [`QualityGovernor`](crate::engine::quality::QualityGovernor)
+    QualityChanged {
+        previous: crate::dsp::quality::EffectQuality,
+        quality: crate::dsp::quality::EffectQuality,
+        /// Callback load that set it off
+        load: f32,
+    },
+    /// A network input's jitter buffer moved its target latency as the
+    /// jitter it measures changed; sent by a
+    /// [`JitterBuffer`](crate::io::jitter::JitterBuffer) given the feedback
+    /// sender
+    NetworkLatency {
+        latency: std::time::Duration,
+        /// Interarrival jitter the latency follows
+        jitter: std::time::Duration,
+    },
+    /// A network stream moved to another bitrate as its connection fell
+    /// behind or caught up; sent by a
+    /// [`BitrateGovernor`](crate::engine::bitrate::BitrateGovernor)
+    BitrateChanged {
+        url: String,
+        previous: crate::types::StreamBitrate,
+        bitrate: crate::types::StreamBitrate,
+    },
+    /// The listener's exposure dose neared or reached its limit; sent by
+    /// an engine built
+    /// [`with_exposure`](crate::engine::EngineBuilder::with_exposure)
+    ExposureWarning(crate::analysis::exposure::ExposureWarning),
 }
 
 /// State of the audio engine.
@@ -367,4 +619,17 @@ pub enum EngineState {
     Error,
 }
 
+impl EngineState {
+    /// Lower-case name, as the remote control protocols report it
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Stopped => "stopped",
+            Self::Running => "running",
+            Self::Paused => "paused",
+            Self::Error => "error",
+        }
+    }
+}
+
 impl RealtimeSafe for EngineState {}
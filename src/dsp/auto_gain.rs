@@ -0,0 +1,277 @@
+//! Loudness normalizing auto-gain
+//!
+//! [`AutoGain`] measures the K-weighted short-term loudness (3 s, as in
+//! EBU R128) of its input and slews its gain towards the level that would
+//! bring that loudness to the target, no faster than the configured rate.
+//! Below the gate the gain holds, so pauses and room noise aren't pulled up.
+
+use std::f32::consts::LOG2_10;
+
+use crate::analysis::loudness::{KWeighting, KWeightingState, channel_weights};
+use crate::dsp::params::{ParamId, ParamValue, ParameterInfo};
+use crate::dsp::traits::{Effect, EffectId};
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+pub mod params {
+    use super::ParamId;
+    /// Target loudness in LUFS
+    pub const TARGET: ParamId = ParamId::new(0);
+    /// Largest boost in dB
+    pub const MAX_GAIN: ParamId = ParamId::new(1);
+    /// Largest cut in dB (as a positive number)
+    pub const MAX_CUT: ParamId = ParamId::new(2);
+    /// Maximum gain change rate in dB per second
+    pub const RATE: ParamId = ParamId::new(3);
+    /// Loudness below which the gain is held, in LUFS
+    pub const GATE: ParamId = ParamId::new(4);
+}
+
+const BLOCK_MS: u32 = 100;
+const SHORT_TERM_BLOCKS: usize = 30;
+
+#[derive(Debug)]
+pub struct AutoGain {
+    id: EffectId,
+    enabled: bool,
+    target_lufs: f32,
+    max_gain_db: f32,
+    max_cut_db: f32,
+    rate_db_per_second: f32,
+    gate_lufs: f32,
+    sample_rate: SampleRate,
+    filter: KWeighting,
+    states: [KWeightingState; 8],
+    weights: [f64; 8],
+    block_frames: usize,
+    block_position: usize,
+    block_energy: f64,
+    blocks: [f64; SHORT_TERM_BLOCKS],
+    block_index: usize,
+    blocks_filled: usize,
+    /// Gain the slew is heading for
+    desired_gain_db: f32,
+    gain_db: f32,
+    param_info: Vec<ParameterInfo>,
+}
+
+impl AutoGain {
+    #[must_use]
+    pub fn new(id: EffectId) -> Self {
+        Self::with_target(id, -18.0)
+    }
+
+    #[must_use]
+    pub fn with_target(id: EffectId, target_lufs: f32) -> Self {
+        let param_info = vec![
+            ParameterInfo::new(params::TARGET, "Target")
+                .with_short_name("Target")
+                .with_range(-40.0, 0.0)
+                .with_default(-18.0)
+                .with_unit("LUFS")
+                .with_precision(1),
+            ParameterInfo::new(params::MAX_GAIN, "Max Gain")
+                .with_short_name("Max+")
+                .with_range(0.0, 30.0)
+                .with_default(12.0)
+                .with_unit("dB")
+                .with_precision(1),
+            ParameterInfo::new(params::MAX_CUT, "Max Cut")
+                .with_short_name("Max-")
+                .with_range(0.0, 30.0)
+                .with_default(12.0)
+                .with_unit("dB")
+                .with_precision(1),
+            ParameterInfo::new(params::RATE, "Rate")
+                .with_short_name("Rate")
+                .with_range(0.1, 20.0)
+                .with_default(3.0)
+                .with_unit("dB/s")
+                .with_precision(1),
+            ParameterInfo::new(params::GATE, "Gate")
+                .with_short_name("Gate")
+                .with_range(-70.0, -20.0)
+                .with_default(-50.0)
+                .with_unit("LUFS")
+                .with_precision(1),
+        ];
+
+        let sample_rate = SampleRate::Hz48000;
+        Self {
+            id,
+            enabled: true,
+            target_lufs: target_lufs.clamp(-40.0, 0.0),
+            max_gain_db: 12.0,
+            max_cut_db: 12.0,
+            rate_db_per_second: 3.0,
+            gate_lufs: -50.0,
+            sample_rate,
+            filter: KWeighting::new(sample_rate),
+            states: [KWeightingState::default(); 8],
+            weights: [1.0; 8],
+            block_frames: sample_rate.samples_for_milliseconds(BLOCK_MS) as usize,
+            block_position: 0,
+            block_energy: 0.0,
+            blocks: [0.0; SHORT_TERM_BLOCKS],
+            block_index: 0,
+            blocks_filled: 0,
+            desired_gain_db: 0.0,
+            gain_db: 0.0,
+            param_info,
+        }
+    }
+
+    pub const fn set_target_lufs(&mut self, lufs: f32) {
+        self.target_lufs = lufs.clamp(-40.0, 0.0);
+    }
+
+    pub const fn set_max_gain_db(&mut self, db: f32) {
+        self.max_gain_db = db.clamp(0.0, 30.0);
+    }
+
+    pub const fn set_max_cut_db(&mut self, db: f32) {
+        self.max_cut_db = db.clamp(0.0, 30.0);
+    }
+
+    pub const fn set_rate_db_per_second(&mut self, rate: f32) {
+        self.rate_db_per_second = rate.clamp(0.1, 20.0);
+    }
+
+    pub const fn set_gate_lufs(&mut self, lufs: f32) {
+        self.gate_lufs = lufs.clamp(-70.0, -20.0);
+    }
+
+    #[must_use]
+    pub const fn target_lufs(&self) -> f32 {
+        self.target_lufs
+    }
+
+    /// Gain currently applied, in dB
+    #[must_use]
+    pub const fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    /// Short-term loudness of the input, in LUFS
+    #[must_use]
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    pub fn input_loudness(&self) -> f32 {
+        if self.blocks_filled == 0 {
+            return f32::NEG_INFINITY;
+        }
+        let energy =
+            self.blocks[..self.blocks_filled].iter().sum::<f64>() / self.blocks_filled as f64;
+        if energy > 0.0 {
+            10.0f64.mul_add(energy.log10(), -0.691) as f32
+        } else {
+            f32::NEG_INFINITY
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn finish_block(&mut self) {
+        self.blocks[self.block_index] = self.block_energy / self.block_frames as f64;
+        self.block_index = (self.block_index + 1) % SHORT_TERM_BLOCKS;
+        self.blocks_filled = (self.blocks_filled + 1).min(SHORT_TERM_BLOCKS);
+        self.block_position = 0;
+        self.block_energy = 0.0;
+
+        let loudness = self.input_loudness();
+        if loudness >= self.gate_lufs {
+            self.desired_gain_db =
+                (self.target_lufs - loudness).clamp(-self.max_cut_db, self.max_gain_db);
+        }
+    }
+}
+
+impl Effect for AutoGain {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "Auto Gain"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn reset(&mut self) {
+        for state in &mut self.states {
+            state.reset();
+        }
+        self.block_position = 0;
+        self.block_energy = 0.0;
+        self.blocks = [0.0; SHORT_TERM_BLOCKS];
+        self.block_index = 0;
+        self.blocks_filled = 0;
+        self.desired_gain_db = 0.0;
+        self.gain_db = 0.0;
+    }
+
+    fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.filter = KWeighting::new(sample_rate);
+        self.weights = channel_weights(channels.into());
+        self.block_frames = (sample_rate.samples_for_milliseconds(BLOCK_MS) as usize).max(1);
+        self.reset();
+    }
+
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        if !self.enabled {
+            return;
+        }
+
+        let step = self.rate_db_per_second / self.sample_rate.as_f32();
+        for frame in samples.chunks_exact_mut(channels.count_usize()) {
+            for (channel, sample) in frame.iter().enumerate().take(8) {
+                let weighted =
+                    self.states[channel].process(f64::from(sample.value()), &self.filter);
+                self.block_energy += self.weights[channel] * weighted * weighted;
+            }
+            self.block_position += 1;
+            if self.block_position >= self.block_frames {
+                self.finish_block();
+            }
+
+            let difference = self.desired_gain_db - self.gain_db;
+            self.gain_db += difference.clamp(-step, step);
+            let gain = (self.gain_db * LOG2_10 / 20.0).exp2();
+            for sample in frame {
+                *sample = Sample::new(sample.value() * gain);
+            }
+        }
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &self.param_info
+    }
+
+    fn get_parameter(&self, id: ParamId) -> Option<ParamValue> {
+        match id {
+            params::TARGET => Some(ParamValue::Float(self.target_lufs)),
+            params::MAX_GAIN => Some(ParamValue::Float(self.max_gain_db)),
+            params::MAX_CUT => Some(ParamValue::Float(self.max_cut_db)),
+            params::RATE => Some(ParamValue::Float(self.rate_db_per_second)),
+            params::GATE => Some(ParamValue::Float(self.gate_lufs)),
+            _ => None,
+        }
+    }
+
+    fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool {
+        let value = value.as_float();
+        match id {
+            params::TARGET => self.set_target_lufs(value),
+            params::MAX_GAIN => self.set_max_gain_db(value),
+            params::MAX_CUT => self.set_max_cut_db(value),
+            params::RATE => self.set_rate_db_per_second(value),
+            params::GATE => self.set_gate_lufs(value),
+            _ => return false,
+        }
+        true
+    }
+}
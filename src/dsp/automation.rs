@@ -0,0 +1,385 @@
+//! Sample-accurate parameter automation events
+//!
+//! A [`ParamEventList`] holds parameter changes timestamped in frames from
+//! the start of the next block. [`EffectChain::process_block`] splits the
+//! block at each event so the change lands on its exact frame rather than
+//! on the block start.
+//!
+//! Events for the [`ENABLED`] parameter switch an effect on or off,
+//! through the chain's crossfaded bypass.
+//!
+//! An [`AutomationLane`] holds one parameter's values over the timeline.
+//! [`AutomationLane::events`] reads it back into the events for a block;
+//! lanes are written by an
+//! [`AutomationRecorder`](crate::engine::automation::AutomationRecorder)
+//! from the changes made while the transport plays.
+//!
+//! [`EffectChain::process_block`]: crate::dsp::chain::EffectChain::process_block
+
+use crate::dsp::params::{ParamId, ParamValue};
+use crate::dsp::traits::EffectId;
+use crate::types::Timestamp;
+
+/// Parameter id that events use for an effect's enabled state. Effects
+/// never see it: the chain handles it with [`EffectChain::set_enabled`].
+///
+/// [`EffectChain::set_enabled`]: crate::dsp::chain::EffectChain::set_enabled
+pub const ENABLED: ParamId = ParamId::new(u32::MAX);
+
+/// Events a default [`ParamEventList`] has room for
+const DEFAULT_CAPACITY: usize = 256;
+
+/// A parameter change at a frame offset within a block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamEvent {
+    /// Frames from the start of the block
+    pub offset_frames: u32,
+    pub effect_id: EffectId,
+    pub param_id: ParamId,
+    pub value: ParamValue,
+}
+
+impl ParamEvent {
+    #[must_use]
+    pub const fn new(
+        offset_frames: u32,
+        effect_id: EffectId,
+        param_id: ParamId,
+        value: ParamValue,
+    ) -> Self {
+        Self {
+            offset_frames,
+            effect_id,
+            param_id,
+            value,
+        }
+    }
+
+    /// Switches an effect on or off at a frame offset.
+    #[must_use]
+    pub const fn enabled(offset_frames: u32, effect_id: EffectId, enabled: bool) -> Self {
+        Self::new(offset_frames, effect_id, ENABLED, ParamValue::Bool(enabled))
+    }
+}
+
+/// Events kept in offset order, with a fixed capacity so that adding events
+/// on the audio thread never allocates.
+///
+/// A clone has the same capacity as the original, and a default list room
+/// for 256 events.
+#[derive(Debug)]
+pub struct ParamEventList {
+    events: Vec<ParamEvent>,
+    /// Most events held, which the vector may exceed in allocation
+    capacity: usize,
+}
+
+impl Default for ParamEventList {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl Clone for ParamEventList {
+    fn clone(&self) -> Self {
+        let mut events = Vec::with_capacity(self.capacity);
+        events.extend_from_slice(&self.events);
+        Self {
+            events,
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl ParamEventList {
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            events: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Inserts an event after any others at the same offset.
+    ///
+    /// Returns false, dropping the event, if the list is full.
+    pub fn push(&mut self, event: ParamEvent) -> bool {
+        if self.events.len() >= self.capacity {
+            return false;
+        }
+        let index = self
+            .events
+            .partition_point(|e| e.offset_frames <= event.offset_frames);
+        self.events.insert(index, event);
+        true
+    }
+
+    /// Removes the events that fall inside a block of `frames` and moves the
+    /// remaining ones to be relative to the next block.
+    pub(crate) fn advance(&mut self, frames: u32) {
+        self.events.retain_mut(|event| {
+            if event.offset_frames < frames {
+                false
+            } else {
+                event.offset_frames -= frames;
+                true
+            }
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ParamEvent> {
+        self.events.iter()
+    }
+}
+
+/// A value at a position on the timeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AutomationPoint {
+    pub position: Timestamp,
+    pub value: f32,
+}
+
+impl AutomationPoint {
+    #[must_use]
+    pub const fn new(position: Timestamp, value: f32) -> Self {
+        Self { position, value }
+    }
+}
+
+/// One parameter's values over the timeline, as points in position order.
+///
+/// Values between points are interpolated in a straight line, except on
+/// [`ENABLED`] lanes, which hold each value until the next point.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AutomationLane {
+    pub effect_id: EffectId,
+    pub param_id: ParamId,
+    points: Vec<AutomationPoint>,
+}
+
+impl AutomationLane {
+    #[must_use]
+    pub const fn new(effect_id: EffectId, param_id: ParamId) -> Self {
+        Self {
+            effect_id,
+            param_id,
+            points: Vec::new(),
+        }
+    }
+
+    /// Whether values step from point to point rather than ramp
+    #[must_use]
+    pub fn is_stepped(&self) -> bool {
+        self.param_id == ENABLED
+    }
+
+    /// Adds a point, replacing any already at the same position.
+    pub fn insert(&mut self, point: AutomationPoint) {
+        let index = self.points.partition_point(|p| p.position < point.position);
+        match self.points.get_mut(index) {
+            Some(existing) if existing.position == point.position => *existing = point,
+            _ => self.points.insert(index, point),
+        }
+    }
+
+    /// The value at `position`: the first point's before it and the last
+    /// point's after it, or `None` if the lane is empty.
+    #[must_use]
+    pub fn value_at(&self, position: Timestamp) -> Option<f32> {
+        let index = self.points.partition_point(|p| p.position <= position);
+        let Some(before) = index.checked_sub(1).map(|i| self.points[i]) else {
+            return self.points.first().map(|p| p.value);
+        };
+        match self.points.get(index) {
+            Some(after) if !self.is_stepped() => Some(interpolate(before, *after, position)),
+            _ => Some(before.value),
+        }
+    }
+
+    /// Adds the lane's events for a block of `frames` starting at `start`
+    /// to `events`: one at the block start while the value is ramping, and
+    /// one at each point inside the block.
+    ///
+    /// Returns false if `events` filled up and some were dropped.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn events(&self, start: Timestamp, frames: u32, events: &mut ParamEventList) -> bool {
+        let end = start.as_samples() + u64::from(frames);
+        let first = self.points.partition_point(|p| p.position < start);
+        let ramping = !self.is_stepped()
+            && first > 0
+            && self
+                .points
+                .get(first)
+                .is_some_and(|after| after.position > start);
+        let mut complete = true;
+        if ramping && let Some(value) = self.value_at(start) {
+            complete &= events.push(self.event(0, value));
+        }
+        for point in self.points[first..]
+            .iter()
+            .take_while(|p| p.position.as_samples() < end)
+        {
+            let offset = (point.position.as_samples() - start.as_samples()) as u32;
+            complete &= events.push(self.event(offset, point.value));
+        }
+        complete
+    }
+
+    fn event(&self, offset_frames: u32, value: f32) -> ParamEvent {
+        if self.is_stepped() {
+            ParamEvent::enabled(offset_frames, self.effect_id, value >= 0.5)
+        } else {
+            ParamEvent::new(
+                offset_frames,
+                self.effect_id,
+                self.param_id,
+                ParamValue::Float(value),
+            )
+        }
+    }
+
+    /// Removes the points that don't change how the lane reads back:
+    /// repeats of the value before them and, on ramped lanes, points that
+    /// lie within `tolerance` of the straight line between the points kept
+    /// around them. `tolerance` is a fraction of the lane's range of
+    /// values, so the same setting suits a frequency and a mix amount.
+    pub fn thin(&mut self, tolerance: f32) {
+        let (low, high) = self
+            .points
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), p| {
+                (low.min(p.value), high.max(p.value))
+            });
+        let limit = (high - low).max(0.0) * tolerance.max(0.0);
+        let stepped = self.is_stepped();
+        let points = std::mem::take(&mut self.points);
+        let mut kept: Vec<AutomationPoint> = Vec::with_capacity(points.len());
+        // Start of the run of points dropped since the last one kept
+        let mut dropped = 0;
+        for (i, &point) in points.iter().enumerate() {
+            let Some(&anchor) = kept.last() else {
+                kept.push(point);
+                dropped = i + 1;
+                continue;
+            };
+            let redundant = match points.get(i + 1) {
+                _ if stepped => point.value.to_bits() == anchor.value.to_bits(),
+                Some(&next) => points[dropped..=i]
+                    .iter()
+                    .all(|p| (interpolate(anchor, next, p.position) - p.value).abs() <= limit),
+                // The last point holds the final value
+                None => false,
+            };
+            if !redundant {
+                kept.push(point);
+                dropped = i + 1;
+            }
+        }
+        self.points = kept;
+    }
+
+    #[must_use]
+    pub fn points(&self) -> &[AutomationPoint] {
+        &self.points
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+/// The value at `position` on the line from `from` to `to`
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+fn interpolate(from: AutomationPoint, to: AutomationPoint, position: Timestamp) -> f32 {
+    let span = to
+        .position
+        .as_samples()
+        .saturating_sub(from.position.as_samples());
+    if span == 0 {
+        return to.value;
+    }
+    let t = position
+        .as_samples()
+        .saturating_sub(from.position.as_samples()) as f64
+        / span as f64;
+    (to.value - from.value).mul_add(t as f32, from.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(offset_frames: u32) -> ParamEvent {
+        ParamEvent::new(
+            offset_frames,
+            EffectId::new(1),
+            ParamId::new(0),
+            ParamValue::Float(0.5),
+        )
+    }
+
+    #[test]
+    fn push_keeps_offset_order_up_to_capacity() {
+        let mut list = ParamEventList::with_capacity(3);
+        assert!(list.push(event(20)));
+        assert!(list.push(event(5)));
+        assert!(list.push(event(10)));
+        assert!(!list.push(event(0)));
+        let offsets: Vec<u32> = list.iter().map(|e| e.offset_frames).collect();
+        assert_eq!(offsets, [5, 10, 20]);
+    }
+
+    #[test]
+    fn clone_and_default_have_room() {
+        let mut list = ParamEventList::with_capacity(4);
+        list.push(event(1));
+        let mut clone = list.clone();
+        assert_eq!(clone.capacity(), 4);
+        assert!(clone.push(event(2)));
+
+        let mut list = ParamEventList::default();
+        assert_eq!(list.capacity(), DEFAULT_CAPACITY);
+        assert!(list.push(event(0)));
+    }
+
+    #[test]
+    fn advance_drops_the_block_and_rebases_the_rest() {
+        let mut list = ParamEventList::with_capacity(4);
+        list.push(event(10));
+        list.push(event(300));
+        list.advance(256);
+        let offsets: Vec<u32> = list.iter().map(|e| e.offset_frames).collect();
+        assert_eq!(offsets, [44]);
+    }
+}
@@ -0,0 +1,277 @@
+//! Bit crusher / sample rate reducer
+
+use crate::dsp::params::{ParamId, ParamValue, ParameterInfo, SmoothParam, SmoothingMode};
+use crate::dsp::traits::{Effect, EffectId, SmoothableEffect};
+use crate::types::{BitDepth, ChannelCount, Sample, SampleRate};
+
+pub mod params {
+    use super::ParamId;
+    /// Bit depth, fractional values allowed
+    pub const BITS: ParamId = ParamId::new(0);
+    /// Rate of the sample-and-hold in Hz
+    pub const RATE: ParamId = ParamId::new(1);
+    /// Dry/wet mix
+    pub const MIX: ParamId = ParamId::new(2);
+}
+
+const SMOOTHING_MS: u32 = 10;
+
+/// Reduces bit depth and sample rate for lo-fi effects.
+///
+/// All parameters are smoothed per sample, the hold phase carries over
+/// between blocks, and enabling or disabling fades the effect in and out,
+/// so nothing clicks at buffer boundaries.
+#[derive(Debug)]
+pub struct BitCrusher {
+    id: EffectId,
+    enabled: bool,
+    bits: SmoothParam,
+    rate_hz: SmoothParam,
+    mix: SmoothParam,
+    /// 1.0 while enabled, ramps to 0.0 when disabled
+    active: SmoothParam,
+    sample_rate: SampleRate,
+    hold_phase: f32,
+    held: [f32; 8],
+    param_info: Vec<ParameterInfo>,
+}
+
+impl BitCrusher {
+    #[must_use]
+    pub fn new(id: EffectId) -> Self {
+        Self::with_params(id, 8.0, 8000.0)
+    }
+
+    /// Crusher reducing to a standard bit depth at full sample rate
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn from_bit_depth(id: EffectId, bit_depth: BitDepth) -> Self {
+        Self::with_params(id, bit_depth.bits() as f32, 192_000.0)
+    }
+
+    #[must_use]
+    pub fn with_params(id: EffectId, bits: f32, rate_hz: f32) -> Self {
+        let param_info = vec![
+            ParameterInfo::new(params::BITS, "Bit Depth")
+                .with_short_name("Bits")
+                .with_range(1.0, 24.0)
+                .with_default(8.0)
+                .with_unit("bit")
+                .with_precision(1),
+            ParameterInfo::new(params::RATE, "Sample Rate")
+                .with_short_name("Rate")
+                .with_range(100.0, 192_000.0)
+                .with_default(8000.0)
+                .with_unit("Hz")
+                .with_precision(0),
+            ParameterInfo::new(params::MIX, "Mix")
+                .with_short_name("Mix")
+                .with_range(0.0, 1.0)
+                .with_default(1.0)
+                .with_precision(2),
+        ];
+
+        Self {
+            id,
+            enabled: true,
+            bits: SmoothParam::new(bits.clamp(1.0, 24.0)),
+            rate_hz: SmoothParam::new(rate_hz.clamp(100.0, 192_000.0)),
+            mix: SmoothParam::new(1.0),
+            active: SmoothParam::new(1.0),
+            sample_rate: SampleRate::Hz48000,
+            hold_phase: 1.0,
+            held: [0.0; 8],
+            param_info,
+        }
+    }
+
+    fn smoothing_samples(&self) -> u32 {
+        self.sample_rate.samples_for_milliseconds(SMOOTHING_MS)
+    }
+
+    pub fn set_bits(&mut self, bits: f32) {
+        self.set_bits_over(bits, self.smoothing_samples());
+    }
+
+    pub fn set_rate_hz(&mut self, rate_hz: f32) {
+        self.set_rate_hz_over(rate_hz, self.smoothing_samples());
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.set_mix_over(mix, self.smoothing_samples());
+    }
+
+    fn set_bits_over(&mut self, bits: f32, samples: u32) {
+        self.bits.set_target(bits.clamp(1.0, 24.0), samples);
+    }
+
+    fn set_rate_hz_over(&mut self, rate_hz: f32, samples: u32) {
+        self.rate_hz
+            .set_target(rate_hz.clamp(100.0, 192_000.0), samples);
+    }
+
+    fn set_mix_over(&mut self, mix: f32, samples: u32) {
+        self.mix.set_target(mix.clamp(0.0, 1.0), samples);
+    }
+
+    const fn smoother_mut(&mut self, id: ParamId) -> Option<&mut SmoothParam> {
+        match id {
+            params::BITS => Some(&mut self.bits),
+            params::RATE => Some(&mut self.rate_hz),
+            params::MIX => Some(&mut self.mix),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn bits(&self) -> f32 {
+        self.bits.target()
+    }
+
+    /// The standard bit depth matching the current setting, if any
+    #[must_use]
+    pub fn bit_depth(&self) -> Option<BitDepth> {
+        match self.bits.target() {
+            b if (b - 16.0).abs() < f32::EPSILON => Some(BitDepth::I16),
+            b if (b - 24.0).abs() < f32::EPSILON => Some(BitDepth::I24),
+            _ => None,
+        }
+    }
+
+    /// Human readable bit depth, using [`BitDepth`]'s naming where it applies
+    #[must_use]
+    pub fn bit_depth_label(&self) -> String {
+        self.bit_depth().map_or_else(
+            || format!("{:.1}-bit int", self.bits.target()),
+            |depth| depth.to_string(),
+        )
+    }
+
+    #[must_use]
+    pub const fn rate_hz(&self) -> f32 {
+        self.rate_hz.target()
+    }
+
+    #[must_use]
+    pub const fn mix(&self) -> f32 {
+        self.mix.target()
+    }
+}
+
+impl Effect for BitCrusher {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "Bit Crusher"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        let samples = self.smoothing_samples();
+        self.active
+            .set_target(if enabled { 1.0 } else { 0.0 }, samples);
+    }
+
+    fn reset(&mut self) {
+        self.bits.set_immediate(self.bits.target());
+        self.rate_hz.set_immediate(self.rate_hz.target());
+        self.mix.set_immediate(self.mix.target());
+        self.active
+            .set_immediate(if self.enabled { 1.0 } else { 0.0 });
+        self.hold_phase = 1.0;
+        self.held = [0.0; 8];
+    }
+
+    fn initialize(&mut self, sample_rate: SampleRate, _channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        if !self.enabled && !self.active.is_smoothing() {
+            return;
+        }
+
+        let sample_rate = self.sample_rate.as_f32();
+        for frame in samples.chunks_exact_mut(channels.count_usize()) {
+            let levels = (self.bits.next() - 1.0).exp2();
+            let rate = self.rate_hz.next();
+            let wet = self.mix.next() * self.active.next();
+
+            self.hold_phase += rate / sample_rate;
+            let capture = self.hold_phase >= 1.0;
+            if capture {
+                self.hold_phase -= self.hold_phase.floor();
+            }
+
+            for (sample, held) in frame.iter_mut().zip(self.held.iter_mut()) {
+                let dry = sample.value();
+                if capture {
+                    *held = (dry * levels).round() / levels;
+                }
+                *sample = Sample::new((*held - dry).mul_add(wet, dry));
+            }
+        }
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &self.param_info
+    }
+
+    fn get_parameter(&self, id: ParamId) -> Option<ParamValue> {
+        match id {
+            params::BITS => Some(ParamValue::Float(self.bits.target())),
+            params::RATE => Some(ParamValue::Float(self.rate_hz.target())),
+            params::MIX => Some(ParamValue::Float(self.mix.target())),
+            _ => None,
+        }
+    }
+
+    fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool {
+        match id {
+            params::BITS => {
+                self.set_bits(value.as_float());
+                true
+            }
+            params::RATE => {
+                self.set_rate_hz(value.as_float());
+                true
+            }
+            params::MIX => {
+                self.set_mix(value.as_float());
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl SmoothableEffect for BitCrusher {
+    fn set_parameter_smooth(&mut self, id: ParamId, value: ParamValue, samples: u32) {
+        match id {
+            params::BITS => self.set_bits_over(value.as_float(), samples),
+            params::RATE => self.set_rate_hz_over(value.as_float(), samples),
+            params::MIX => self.set_mix_over(value.as_float(), samples),
+            _ => {}
+        }
+    }
+
+    fn update_smoothing(&mut self) {
+        self.bits.advance(1);
+        self.rate_hz.advance(1);
+        self.mix.advance(1);
+        self.active.advance(1);
+    }
+
+    fn set_smoothing_mode(&mut self, id: ParamId, mode: SmoothingMode) -> bool {
+        self.smoother_mut(id)
+            .map(|smoother| smoother.set_mode(mode))
+            .is_some()
+    }
+}
@@ -0,0 +1,1034 @@
+//! Effect chains
+//!
+//! [`EffectChain`] runs effects one after another. [`ParallelChain`] is an
+//! effect that runs several chains side by side on copies of its input and
+//! mixes them back together, e.g. for parallel compression.
+//!
+//! A chain can be handed big blocks, say 4096 frames, and still follow
+//! automation closely: with a sub-block size set, it runs the effects on
+//! pieces no longer than that, and [`EffectChain::process_block`] also
+//! splits at every event. Each piece gets its own timeline position, so
+//! tempo synced effects stay in step.
+//!
+//! Switching an effect through [`EffectChain::set_enabled`] crossfades
+//! between its input and output instead of cutting over, so switches don't
+//! click. Events made with [`ParamEvent::enabled`] switch effects the same
+//! way on an exact frame, fast enough for stutter and gate effects.
+//!
+//! [`ChainBuilder`] puts a chain together in one expression, for
+//! [`EngineBuilder::chain`](crate::engine::EngineBuilder::chain).
+//!
+//! [`ParamEvent::enabled`]: crate::dsp::automation::ParamEvent::enabled
+
+use std::fmt;
+
+use crate::buffer::memory::heap_bytes;
+use crate::buffer::{MemoryKind, MemoryReport};
+use crate::dsp::automation::{ENABLED, ParamEventList};
+use crate::dsp::denormal::{DenormalPolicy, flush_denormals_slice};
+use crate::dsp::filters::BiquadFilter;
+use crate::dsp::gain::GainEffect;
+use crate::dsp::pan::PanEffect;
+use crate::dsp::params::{ParamId, ParamValue, ParameterInfo, SmoothParam};
+use crate::dsp::preset::{ChainPreset, EffectPreset, PresetReceiver, PresetSender, preset_channel};
+use crate::dsp::quality::EffectQuality;
+use crate::dsp::random::derive_seed;
+use crate::dsp::traits::{Effect, EffectId, ProcessContext};
+use crate::error::{AudioEngineError, Result};
+use crate::types::{ChannelCount, Gain, Pan, Sample, SampleRate};
+
+/// Default time an effect takes to fade in or out when switched
+const BYPASS_FADE_MS: u32 = 5;
+/// Samples of input kept while an effect fades; longer blocks fade in
+/// pieces
+const BYPASS_SCRATCH_SAMPLES: usize = 2048;
+
+/// Crossfade between an effect's input and output while it is switched.
+#[derive(Debug, Clone, Copy)]
+struct BypassFade {
+    /// Whether the effect is switched on, or being switched on
+    enabled: bool,
+    /// Share of the effect's output, 0.0 to 1.0
+    wet: f32,
+    step: f32,
+    remaining: u32,
+}
+
+impl BypassFade {
+    const fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            wet: if enabled { 1.0 } else { 0.0 },
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    const fn is_fading(&self) -> bool {
+        self.remaining > 0
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn start(&mut self, enabled: bool, frames: u32) {
+        let target = if enabled { 1.0 } else { 0.0 };
+        self.enabled = enabled;
+        self.step = (target - self.wet) / frames as f32;
+        self.remaining = frames;
+    }
+
+    fn next(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.wet = if self.remaining == 0 {
+                if self.enabled { 1.0 } else { 0.0 }
+            } else {
+                self.wet + self.step
+            };
+        }
+        self.wet
+    }
+}
+
+/// An ordered list of effects processed one after another in place.
+pub struct EffectChain {
+    effects: Vec<Box<dyn Effect>>,
+    /// Bypass crossfade of each effect, in the same order
+    fades: Vec<BypassFade>,
+    /// Instance name of each effect, in the same order
+    names: Vec<Option<String>>,
+    /// Input of an effect that is fading
+    dry: Vec<Sample>,
+    bypass_fade_ms: u32,
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    presets: Option<PresetReceiver>,
+    denormals: DenormalPolicy,
+    /// Most frames the effects run on at once; 0 runs whole blocks
+    sub_block_frames: usize,
+    /// Timeline position of the current block
+    context: Option<ProcessContext>,
+}
+
+impl EffectChain {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Creates an empty chain with room for `capacity` effects.
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            effects: Vec::with_capacity(capacity),
+            fades: Vec::with_capacity(capacity),
+            names: Vec::with_capacity(capacity),
+            dry: Vec::new(),
+            bypass_fade_ms: BYPASS_FADE_MS,
+            sample_rate: SampleRate::Hz48000,
+            channels: ChannelCount::Stereo,
+            presets: None,
+            denormals: DenormalPolicy::default(),
+            sub_block_frames: 0,
+            context: None,
+        }
+    }
+
+    /// Appends an effect, initializing it with the chain's format.
+    pub fn push(&mut self, mut effect: Box<dyn Effect>) {
+        effect.initialize(self.sample_rate, self.channels);
+        warn_unsupported(effect.as_ref(), self.channels);
+        if self.dry.is_empty() {
+            self.dry = vec![Sample::SILENCE; BYPASS_SCRATCH_SAMPLES];
+        }
+        self.fades.push(BypassFade::new(effect.is_enabled()));
+        self.names.push(None);
+        self.effects.push(effect);
+    }
+
+    /// Appends an effect under an instance name, e.g. `"eq"`, which
+    /// [`NameTable`](crate::dsp::names::NameTable) paths use in place of
+    /// the effect's type name.
+    pub fn push_named(&mut self, name: impl Into<String>, effect: Box<dyn Effect>) {
+        self.push(effect);
+        if let Some(slot) = self.names.last_mut() {
+            *slot = Some(name.into());
+        }
+    }
+
+    /// Names the effect with the given id. Returns false if there is no
+    /// such effect.
+    pub fn set_name(&mut self, id: EffectId, name: impl Into<String>) -> bool {
+        let Some(index) = self.position(id) else {
+            return false;
+        };
+        self.names[index] = Some(name.into());
+        true
+    }
+
+    /// Instance name of the effect with the given id, if it was given one
+    #[must_use]
+    pub fn name(&self, id: EffectId) -> Option<&str> {
+        self.names[self.position(id)?].as_deref()
+    }
+
+    /// Removes the effect with the given id, if present.
+    pub fn remove(&mut self, id: EffectId) -> Option<Box<dyn Effect>> {
+        let index = self.position(id)?;
+        self.fades.remove(index);
+        self.names.remove(index);
+        Some(self.effects.remove(index))
+    }
+
+    /// Returns the index of the effect with the given id.
+    #[must_use]
+    pub fn position(&self, id: EffectId) -> Option<usize> {
+        self.effects.iter().position(|e| e.id() == id)
+    }
+
+    #[must_use]
+    pub fn get(&self, id: EffectId) -> Option<&dyn Effect> {
+        self.effects
+            .iter()
+            .find(|e| e.id() == id)
+            .map(AsRef::as_ref)
+    }
+
+    #[must_use]
+    pub fn get_mut(&mut self, id: EffectId) -> Option<&mut (dyn Effect + 'static)> {
+        self.effects
+            .iter_mut()
+            .find(|e| e.id() == id)
+            .map(AsMut::as_mut)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Effect> {
+        self.effects.iter().map(AsRef::as_ref)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut (dyn Effect + 'static)> {
+        self.effects.iter_mut().map(AsMut::as_mut)
+    }
+
+    #[must_use]
+    pub const fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    #[must_use]
+    pub const fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    /// Initializes every effect for the given format.
+    pub fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        for effect in &mut self.effects {
+            effect.initialize(sample_rate, channels);
+            warn_unsupported(effect.as_ref(), channels);
+        }
+    }
+
+    /// Whether every effect can process `channels`
+    #[must_use]
+    pub fn supports_channels(&self, channels: ChannelCount) -> bool {
+        self.effects
+            .iter()
+            .all(|effect| effect.supports_channels(channels))
+    }
+
+    /// Effects that can't process the chain's channels and are bypassed
+    pub fn unsupported(&self) -> impl Iterator<Item = &dyn Effect> {
+        let channels = self.channels;
+        self.iter()
+            .filter(move |effect| !effect.supports_channels(channels))
+    }
+
+    /// Clears the internal state of every effect, finishing any bypass
+    /// crossfades.
+    pub fn reset(&mut self) {
+        for (effect, fade) in self.effects.iter_mut().zip(&mut self.fades) {
+            effect.set_enabled(fade.enabled);
+            *fade = BypassFade::new(fade.enabled);
+            effect.reset();
+        }
+    }
+
+    /// Restarts the random generators of every effect, each from a seed
+    /// derived from `seed` and its id.
+    pub fn reseed(&mut self, seed: u64) {
+        for effect in &mut self.effects {
+            effect.reseed(derive_seed(seed, u64::from(effect.id().value())));
+        }
+    }
+
+    /// Moves every effect to a quality tier.
+    pub fn set_quality(&mut self, quality: EffectQuality) {
+        for effect in &mut self.effects {
+            effect.set_quality(quality);
+        }
+    }
+
+    /// Passes the timeline position of the coming block to every effect.
+    pub fn set_context(&mut self, context: &ProcessContext) {
+        self.context = Some(*context);
+        for effect in &mut self.effects {
+            effect.set_context(context);
+        }
+    }
+
+    #[must_use]
+    pub const fn denormal_policy(&self) -> DenormalPolicy {
+        self.denormals
+    }
+
+    /// With [`DenormalPolicy::Flush`], each effect's output is flushed
+    /// before the next effect sees it.
+    pub const fn set_denormal_policy(&mut self, policy: DenormalPolicy) {
+        self.denormals = policy;
+    }
+
+    /// Most frames the effects run on at once; 0 when blocks aren't split
+    #[must_use]
+    pub const fn sub_block_frames(&self) -> usize {
+        self.sub_block_frames
+    }
+
+    /// Runs the effects on pieces of at most `frames` frames, so parameter
+    /// smoothing, modulation and the timeline advance within big blocks.
+    /// 0 runs whole blocks.
+    pub const fn set_sub_block_frames(&mut self, frames: usize) {
+        self.sub_block_frames = frames;
+    }
+
+    /// Time an effect takes to fade in or out when switched
+    #[must_use]
+    pub const fn bypass_fade_ms(&self) -> u32 {
+        self.bypass_fade_ms
+    }
+
+    /// Sets how long switched effects take to fade in or out. 0 switches
+    /// them at once.
+    pub const fn set_bypass_fade_ms(&mut self, millis: u32) {
+        self.bypass_fade_ms = millis;
+    }
+
+    /// Runs the interleaved buffer through every effect in order.
+    pub fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        self.receive_presets();
+        self.run_sub_blocks(samples, channels, 0);
+    }
+
+    /// Runs a stretch of the block starting `offset` frames in, split into
+    /// sub-blocks.
+    fn run_sub_blocks(&mut self, samples: &mut [Sample], channels: ChannelCount, offset: usize) {
+        let channel_count = channels.count_usize();
+        let frames = samples.len() / channel_count;
+        let split = self.sub_block_frames > 0 && frames > self.sub_block_frames;
+        if !split && offset == 0 {
+            self.run_effects(samples, channels);
+            return;
+        }
+        let piece = if split {
+            self.sub_block_frames
+        } else {
+            frames.max(1)
+        };
+        for (index, block) in samples.chunks_mut(piece * channel_count).enumerate() {
+            if let Some(context) = &self.context {
+                let context =
+                    context.sub_block(offset + index * piece, block.len() / channel_count);
+                for effect in &mut self.effects {
+                    effect.set_context(&context);
+                }
+            }
+            self.run_effects(block, channels);
+        }
+    }
+
+    fn run_effects(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        for (effect, fade) in self.effects.iter_mut().zip(&mut self.fades) {
+            if !effect.supports_channels(channels) {
+                continue;
+            }
+            if fade.is_fading() {
+                process_fading(effect.as_mut(), fade, &mut self.dry, samples, channels);
+            } else {
+                // Follow switches made on the effect itself
+                if fade.enabled != effect.is_enabled() {
+                    *fade = BypassFade::new(effect.is_enabled());
+                }
+                effect.process(samples, channels);
+            }
+            if self.denormals == DenormalPolicy::Flush {
+                flush_denormals_slice(samples);
+            }
+        }
+    }
+
+    /// Runs the buffer through the chain, applying each event on its exact
+    /// frame. Events for [`ENABLED`] switch their effect with the bypass
+    /// crossfade, starting on that frame.
+    ///
+    /// The block is split at every event offset. Events inside the block are
+    /// removed from `events`; later ones are kept and moved to be relative to
+    /// the next block. Events for unknown effects are dropped.
+    pub fn process_block(
+        &mut self,
+        samples: &mut [Sample],
+        channels: ChannelCount,
+        events: &mut ParamEventList,
+    ) {
+        self.receive_presets();
+        let channel_count = channels.count_usize();
+        let frames = samples.len() / channel_count;
+        let mut position = 0;
+        for event in events.iter() {
+            let offset = usize::try_from(event.offset_frames).unwrap_or(usize::MAX);
+            if offset >= frames {
+                break;
+            }
+            if offset > position {
+                self.run_sub_blocks(
+                    &mut samples[position * channel_count..offset * channel_count],
+                    channels,
+                    position,
+                );
+                position = offset;
+            }
+            if event.param_id == ENABLED {
+                self.set_enabled(event.effect_id, event.value.as_bool());
+            } else {
+                self.set_parameter(event.effect_id, event.param_id, event.value);
+            }
+        }
+        if position < frames {
+            self.run_sub_blocks(&mut samples[position * channel_count..], channels, position);
+        }
+        events.advance(u32::try_from(frames).unwrap_or(u32::MAX));
+    }
+
+    /// Captures the settings of every effect.
+    #[must_use]
+    pub fn preset(&self) -> ChainPreset {
+        ChainPreset {
+            effects: self
+                .effects
+                .iter()
+                .map(|effect| EffectPreset::capture(effect.as_ref()))
+                .collect(),
+        }
+    }
+
+    /// Applies a preset to the effects it names.
+    ///
+    /// Returns false if the preset names an effect that isn't in the chain
+    /// or an effect rejected a parameter; everything else is still applied.
+    pub fn load_preset(&mut self, preset: &ChainPreset) -> bool {
+        let mut ok = true;
+        for effect_preset in &preset.effects {
+            ok &= self
+                .get_mut(effect_preset.id)
+                .is_some_and(|effect| effect.apply_preset(effect_preset));
+        }
+        ok
+    }
+
+    /// Creates the control side of the chain's preset channel.
+    ///
+    /// Presets sent through it are applied whole at the start of the next
+    /// block, so the audio never runs with half of one applied.
+    pub fn preset_sender(&mut self, capacity: usize) -> PresetSender {
+        let (sender, receiver) = preset_channel(capacity);
+        self.presets = Some(receiver);
+        sender
+    }
+
+    fn receive_presets(&mut self) {
+        let Some(presets) = self.presets.take() else {
+            return;
+        };
+        while let Some(preset) = presets.try_recv() {
+            self.load_preset(&preset);
+            presets.give_back(preset);
+        }
+        self.presets = Some(presets);
+    }
+
+    /// Sets a parameter on the effect with the given id.
+    ///
+    /// Returns false if the effect doesn't exist or rejected the parameter.
+    pub fn set_parameter(
+        &mut self,
+        effect_id: EffectId,
+        param_id: ParamId,
+        value: ParamValue,
+    ) -> bool {
+        self.get_mut(effect_id)
+            .is_some_and(|effect| effect.set_parameter(param_id, value))
+    }
+
+    /// Sets a parameter after converting `value` to the kind the effect
+    /// declares and checking it against the parameter's range; see
+    /// [`ParameterInfo::coerce`].
+    ///
+    /// # Errors
+    /// Returns an error if the effect or parameter doesn't exist, the value
+    /// doesn't convert or is out of range, or the effect rejected it.
+    pub fn set_parameter_checked(
+        &mut self,
+        effect_id: EffectId,
+        param_id: ParamId,
+        value: ParamValue,
+    ) -> Result<()> {
+        let effect = self.get_mut(effect_id).ok_or_else(|| {
+            AudioEngineError::configuration(format!("no effect {effect_id} in the chain"))
+        })?;
+        let info = effect
+            .parameters()
+            .iter()
+            .find(|info| info.id == param_id)
+            .ok_or_else(|| {
+                AudioEngineError::configuration(format!(
+                    "{} has no parameter {param_id}",
+                    effect.name()
+                ))
+            })?;
+        let value = info.coerce(value)?;
+        if effect.set_parameter(param_id, value) {
+            Ok(())
+        } else {
+            Err(AudioEngineError::configuration(format!(
+                "{} rejected {param_id} = {value:?}",
+                effect.name()
+            )))
+        }
+    }
+
+    #[must_use]
+    pub fn get_parameter(&self, effect_id: EffectId, param_id: ParamId) -> Option<ParamValue> {
+        self.get(effect_id)?.get_parameter(param_id)
+    }
+
+    /// Switches the effect with the given id on or off, crossfading
+    /// between its input and output over the bypass fade time. The effect
+    /// keeps processing until it has faded out.
+    ///
+    /// Returns false if there is no such effect.
+    pub fn set_enabled(&mut self, effect_id: EffectId, enabled: bool) -> bool {
+        let Some(index) = self.position(effect_id) else {
+            return false;
+        };
+        let frames = self
+            .sample_rate
+            .samples_for_milliseconds(self.bypass_fade_ms);
+        let (effect, fade) = (&mut self.effects[index], &mut self.fades[index]);
+        if !fade.is_fading() {
+            *fade = BypassFade::new(effect.is_enabled());
+        }
+        if fade.enabled == enabled {
+            return true;
+        }
+        if frames == 0 || self.dry.is_empty() {
+            effect.set_enabled(enabled);
+            *fade = BypassFade::new(enabled);
+        } else {
+            effect.set_enabled(true);
+            fade.start(enabled, frames);
+        }
+        true
+    }
+
+    /// Bypasses each effect that has failed since the last call, calling
+    /// `on_failure` with its id; see [`Effect::take_failure`].
+    pub fn bypass_failed(&mut self, mut on_failure: impl FnMut(EffectId)) {
+        for index in 0..self.effects.len() {
+            if self.effects[index].take_failure() {
+                let id = self.effects[index].id();
+                self.set_enabled(id, false);
+                on_failure(id);
+            }
+        }
+    }
+
+    /// Whether the effect with the given id is switched on; an effect
+    /// fading out counts as off.
+    #[must_use]
+    pub fn is_enabled(&self, effect_id: EffectId) -> Option<bool> {
+        let index = self.position(effect_id)?;
+        let fade = &self.fades[index];
+        Some(if fade.is_fading() {
+            fade.enabled
+        } else {
+            self.effects[index].is_enabled()
+        })
+    }
+
+    /// Total latency of all effects in the chain
+    #[must_use]
+    pub fn latency_samples(&self) -> u32 {
+        self.effects.iter().map(|e| e.latency_samples()).sum()
+    }
+
+    /// Longest tail of any effect in the chain
+    #[must_use]
+    pub fn tail_samples(&self) -> u32 {
+        self.effects
+            .iter()
+            .map(|e| e.tail_samples())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Bytes held by the chain's effects and its crossfade buffer
+    #[must_use]
+    pub fn memory_bytes(&self) -> usize {
+        self.effects.iter().map(|e| e.memory_bytes()).sum::<usize>() + heap_bytes(&self.dry)
+    }
+
+    /// Memory held by each effect in the chain
+    #[must_use]
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport::new();
+        for effect in &self.effects {
+            report.add(
+                format!("{} ({})", effect.name(), effect.id()),
+                MemoryKind::Effect,
+                effect.memory_bytes(),
+            );
+        }
+        if !self.dry.is_empty() {
+            report.add(
+                "Bypass crossfade",
+                MemoryKind::Buffer,
+                heap_bytes(&self.dry),
+            );
+        }
+        report
+    }
+}
+
+/// Runs `effect` on `samples` and mixes its output with its input by the
+/// fade, using `dry` for the input. Switches the effect off once it has
+/// faded out.
+fn process_fading(
+    effect: &mut dyn Effect,
+    fade: &mut BypassFade,
+    dry: &mut [Sample],
+    samples: &mut [Sample],
+    channels: ChannelCount,
+) {
+    let channel_count = channels.count_usize();
+    let piece = dry.len() / channel_count * channel_count;
+    if piece == 0 {
+        return;
+    }
+    for block in samples.chunks_mut(piece) {
+        let dry = &mut dry[..block.len()];
+        dry.copy_from_slice(block);
+        effect.process(block, channels);
+        for (out_frame, dry_frame) in block
+            .chunks_exact_mut(channel_count)
+            .zip(dry.chunks_exact(channel_count))
+        {
+            let wet = fade.next();
+            for (out, dry) in out_frame.iter_mut().zip(dry_frame) {
+                *out = Sample::new((out.value() - dry.value()).mul_add(wet, dry.value()));
+            }
+        }
+    }
+    if !fade.is_fading() && !fade.enabled {
+        effect.set_enabled(false);
+    }
+}
+
+fn warn_unsupported(effect: &dyn Effect, channels: ChannelCount) {
+    if !effect.supports_channels(channels) {
+        log::warn!(
+            "{} ({}) can't process {channels:?} audio and is bypassed",
+            effect.name(),
+            effect.id()
+        );
+    }
+}
+
+impl Default for EffectChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for EffectChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EffectChain")
+            .field(
+                "effects",
+                &self.effects.iter().map(|e| e.name()).collect::<Vec<_>>(),
+            )
+            .field("fades", &self.fades)
+            .field("names", &self.names)
+            .field("dry", &self.dry.len())
+            .field("bypass_fade_ms", &self.bypass_fade_ms)
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("presets", &self.presets)
+            .field("denormals", &self.denormals)
+            .field("sub_block_frames", &self.sub_block_frames)
+            .field("context", &self.context)
+            .finish()
+    }
+}
+
+/// Builds an [`EffectChain`] one effect at a time, numbering the effects
+/// in the order they are added.
+///
+/// ```
+/// use audio_engine::dsp::chain::ChainBuilder;
+/// use audio_engine::types::Gain;
+///
+/// let chain = ChainBuilder::new()
+///     .high_pass(80.0, 0.707)
+///     .eq(3_000.0, 1.0, 2.5)
+///     .gain(Gain::from_db(-3.0))
+///     .build();
+/// assert_eq!(chain.len(), 3);
+/// ```
+#[derive(Debug, Default)]
+pub struct ChainBuilder {
+    chain: EffectChain,
+    next_id: u32,
+}
+
+impl ChainBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the effect `build` makes with the next free id.
+    #[must_use]
+    pub fn effect<E: Effect>(mut self, build: impl FnOnce(EffectId) -> E) -> Self {
+        let id = EffectId::new(self.next_id);
+        self.next_id += 1;
+        self.chain.push(Box::new(build(id)));
+        self
+    }
+
+    /// Appends an effect under an instance name; see
+    /// [`EffectChain::push_named`].
+    #[must_use]
+    pub fn named<E: Effect>(
+        mut self,
+        name: impl Into<String>,
+        build: impl FnOnce(EffectId) -> E,
+    ) -> Self {
+        let id = EffectId::new(self.next_id);
+        self.next_id += 1;
+        self.chain.push_named(name, Box::new(build(id)));
+        self
+    }
+
+    #[must_use]
+    pub fn gain(self, gain: Gain) -> Self {
+        self.effect(|id| GainEffect::with_gain(id, gain))
+    }
+
+    #[must_use]
+    pub fn pan(self, pan: Pan) -> Self {
+        self.effect(|id| PanEffect::with_pan(id, pan))
+    }
+
+    /// Appends a peaking EQ band
+    #[must_use]
+    pub fn eq(self, frequency: f32, q: f32, gain_db: f32) -> Self {
+        self.effect(|id| BiquadFilter::peak(id, frequency, q, gain_db))
+    }
+
+    #[must_use]
+    pub fn low_pass(self, frequency: f32, q: f32) -> Self {
+        self.effect(|id| BiquadFilter::low_pass(id, frequency, q))
+    }
+
+    #[must_use]
+    pub fn high_pass(self, frequency: f32, q: f32) -> Self {
+        self.effect(|id| BiquadFilter::high_pass(id, frequency, q))
+    }
+
+    #[must_use]
+    pub fn low_shelf(self, frequency: f32, gain_db: f32) -> Self {
+        self.effect(|id| BiquadFilter::low_shelf(id, frequency, gain_db))
+    }
+
+    #[must_use]
+    pub fn high_shelf(self, frequency: f32, gain_db: f32) -> Self {
+        self.effect(|id| BiquadFilter::high_shelf(id, frequency, gain_db))
+    }
+
+    #[must_use]
+    pub fn build(self) -> EffectChain {
+        self.chain
+    }
+}
+
+impl From<EffectChain> for ChainBuilder {
+    /// Carries on building `chain`, numbering new effects after its own.
+    fn from(chain: EffectChain) -> Self {
+        let next_id = chain
+            .iter()
+            .map(|effect| effect.id().value() + 1)
+            .max()
+            .unwrap_or(0);
+        Self { chain, next_id }
+    }
+}
+
+pub mod params {
+    use crate::dsp::params::ParamId;
+
+    /// Gain in dB of a [`ParallelChain`](super::ParallelChain) branch
+    #[must_use]
+    pub const fn branch_gain(branch: u32) -> ParamId {
+        ParamId::new(branch)
+    }
+}
+
+#[derive(Debug)]
+struct Branch {
+    chain: EffectChain,
+    gain_db: f32,
+    gain: SmoothParam,
+}
+
+/// Splits its input into parallel branches and sums their outputs.
+///
+/// Each branch is an [`EffectChain`] fed a copy of the input; an empty
+/// branch passes the input through, which gives the dry path of a parallel
+/// compressor. Scratch space is allocated up front for `max_block_samples`
+/// samples; longer buffers are processed in pieces of that size.
+///
+/// Branch latencies are not compensated, so branches with different
+/// latencies will comb filter when summed.
+#[derive(Debug)]
+pub struct ParallelChain {
+    id: EffectId,
+    enabled: bool,
+    branches: Vec<Branch>,
+    scratch: Vec<Sample>,
+    sum: Vec<Sample>,
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    param_info: Vec<ParameterInfo>,
+}
+
+impl ParallelChain {
+    #[must_use]
+    pub fn new(id: EffectId, max_block_samples: usize) -> Self {
+        Self {
+            id,
+            enabled: true,
+            branches: Vec::new(),
+            scratch: vec![Sample::SILENCE; max_block_samples],
+            sum: vec![Sample::SILENCE; max_block_samples],
+            sample_rate: SampleRate::Hz48000,
+            channels: ChannelCount::Stereo,
+            param_info: Vec::new(),
+        }
+    }
+
+    /// Adds a branch at unity gain, initializing it with the node's format.
+    ///
+    /// Returns the branch index.
+    ///
+    /// # Panics
+    /// Panics if there are more than `u32::MAX` branches.
+    pub fn add_branch(&mut self, mut chain: EffectChain) -> usize {
+        chain.initialize(self.sample_rate, self.channels);
+        let index = self.branches.len();
+        let number = u32::try_from(index).expect("branch count fits in u32");
+        self.param_info.push(
+            ParameterInfo::new(
+                params::branch_gain(number),
+                format!("Branch {} Gain", index + 1),
+            )
+            .with_short_name(format!("B{}", index + 1))
+            .with_range(-80.0, 12.0)
+            .with_default(0.0)
+            .with_unit("dB")
+            .with_precision(1),
+        );
+        self.branches.push(Branch {
+            chain,
+            gain_db: 0.0,
+            gain: SmoothParam::new(1.0),
+        });
+        index
+    }
+
+    #[must_use]
+    pub const fn branch_count(&self) -> usize {
+        self.branches.len()
+    }
+
+    #[must_use]
+    pub fn branch(&self, index: usize) -> Option<&EffectChain> {
+        self.branches.get(index).map(|b| &b.chain)
+    }
+
+    pub fn branch_mut(&mut self, index: usize) -> Option<&mut EffectChain> {
+        self.branches.get_mut(index).map(|b| &mut b.chain)
+    }
+
+    /// Sets a branch's gain, ramped over 10 ms. Returns false if there is no
+    /// such branch.
+    pub fn set_branch_gain_db(&mut self, index: usize, db: f32) -> bool {
+        let samples = self.sample_rate.samples_for_milliseconds(10);
+        self.branches.get_mut(index).is_some_and(|branch| {
+            branch.gain_db = db.clamp(-80.0, 12.0);
+            branch
+                .gain
+                .set_target(Gain::from_db(branch.gain_db).as_linear(), samples);
+            true
+        })
+    }
+
+    #[must_use]
+    pub fn branch_gain_db(&self, index: usize) -> Option<f32> {
+        self.branches.get(index).map(|b| b.gain_db)
+    }
+
+    fn branch_index(id: ParamId) -> usize {
+        usize::try_from(id.value()).unwrap_or(usize::MAX)
+    }
+}
+
+impl Effect for ParallelChain {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "Parallel"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn reset(&mut self) {
+        for branch in &mut self.branches {
+            branch.chain.reset();
+            branch.gain.set_immediate(branch.gain.target());
+        }
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        for (index, branch) in (0u64..).zip(&mut self.branches) {
+            branch.chain.reseed(derive_seed(seed, index));
+        }
+    }
+
+    fn set_quality(&mut self, quality: EffectQuality) {
+        for branch in &mut self.branches {
+            branch.chain.set_quality(quality);
+        }
+    }
+
+    fn set_context(&mut self, context: &ProcessContext) {
+        for branch in &mut self.branches {
+            branch.chain.set_context(context);
+        }
+    }
+
+    fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        for branch in &mut self.branches {
+            branch.chain.initialize(sample_rate, channels);
+        }
+        self.reset();
+    }
+
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        if !self.enabled || self.branches.is_empty() {
+            return;
+        }
+        let channel_count = channels.count_usize();
+        let piece = self.scratch.len() / channel_count * channel_count;
+        if piece == 0 {
+            return;
+        }
+
+        for block in samples.chunks_mut(piece) {
+            let len = block.len();
+            let sum = &mut self.sum[..len];
+            sum.fill(Sample::SILENCE);
+            for branch in &mut self.branches {
+                let scratch = &mut self.scratch[..len];
+                scratch.copy_from_slice(block);
+                branch.chain.process(scratch, channels);
+                for (out_frame, frame) in sum
+                    .chunks_exact_mut(channel_count)
+                    .zip(scratch.chunks_exact(channel_count))
+                {
+                    let gain = branch.gain.next();
+                    for (out, sample) in out_frame.iter_mut().zip(frame) {
+                        *out = Sample::new(sample.value().mul_add(gain, out.value()));
+                    }
+                }
+            }
+            block.copy_from_slice(sum);
+        }
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &self.param_info
+    }
+
+    fn get_parameter(&self, id: ParamId) -> Option<ParamValue> {
+        self.branch_gain_db(Self::branch_index(id))
+            .map(ParamValue::Float)
+    }
+
+    fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool {
+        self.set_branch_gain_db(Self::branch_index(id), value.as_float())
+    }
+
+    fn latency_samples(&self) -> u32 {
+        self.branches
+            .iter()
+            .map(|b| b.chain.latency_samples())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn tail_samples(&self) -> u32 {
+        self.branches
+            .iter()
+            .map(|b| b.chain.tail_samples())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        let branches: usize = self.branches.iter().map(|b| b.chain.memory_bytes()).sum();
+        size_of_val(self)
+            + heap_bytes(&self.branches)
+            + heap_bytes(&self.scratch)
+            + heap_bytes(&self.sum)
+            + branches
+    }
+}
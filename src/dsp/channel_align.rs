@@ -0,0 +1,424 @@
+//! Inter-channel time alignment
+//!
+//! [`ChannelAlign`] lines the second channel of a stream up with the
+//! first: it delays whichever of the two arrives early by a fraction of a
+//! sample or more, and can invert the second's polarity, so two mics on
+//! one source sum without comb filtering or cancelling. Aligning to the
+//! later channel delays the earlier one; nothing is advanced, so the
+//! effect adds no latency to the later channel.
+//!
+//! Offline, measure the recording and write an aligned copy in one go:
+//!
+//! ```no_run
+//! use audio_engine::dsp::channel_align;
+//!
+//! let alignment = channel_align::align_files("snare_top.wav", "snare_bottom.wav", "snare.wav")?;
+//! println!("{alignment}");
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! Live, as an effect in a chain or a graph node, [`ChannelAlign::new`]
+//! measures its input with an [`AlignmentAnalyzer`] as it plays and
+//! follows the estimate while it is trustworthy (a correlation of at least
+//! 0.3 either way). Setting the delay or polarity by hand stops it
+//! following; [`ChannelAlign::fixed`] applies an estimate made offline.
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::analysis::alignment::{self, AlignmentAnalyzer, ChannelAlignment, PairReader};
+use crate::buffer::memory::heap_bytes;
+use crate::dsp::params::{ParamId, ParamKind, ParamValue, ParameterInfo, SmoothParam};
+use crate::dsp::traits::{Effect, EffectId};
+use crate::error::Result;
+use crate::io::wav::{WavReader, WavWriter};
+use crate::types::{AudioFormat, ChannelCount, Sample, SampleRate};
+
+pub mod params {
+    use super::ParamId;
+    /// How far the second channel lags the first, in ms; negative when it
+    /// leads
+    pub const DELAY: ParamId = ParamId::new(0);
+    /// Inverts the polarity of the second channel
+    pub const INVERT: ParamId = ParamId::new(1);
+    /// Follows the alignment measured from the input
+    pub const AUTO: ParamId = ParamId::new(2);
+}
+
+/// Largest delay either way, in ms
+pub const MAX_DELAY_MS: f32 = 50.0;
+
+/// Estimates less correlated than this either way are not followed
+const MIN_CORRELATION: f32 = 0.3;
+
+/// Time constant of the live measurement
+const AUTO_TIME: Duration = Duration::from_secs(5);
+
+/// Frames read per pass through a file
+const READ_FRAMES: usize = 8192;
+
+/// A delay line read between samples with cubic interpolation.
+#[derive(Debug, Clone, Default)]
+struct DelayLine {
+    buffer: Vec<f32>,
+    /// Index of the newest sample
+    write: usize,
+}
+
+impl DelayLine {
+    /// A line that delays by up to `max_delay` samples.
+    fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![0.0; (max_delay + 4).next_power_of_two()],
+            write: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.buffer.fill(0.0);
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.write = (self.write + 1) & (self.buffer.len() - 1);
+        self.buffer[self.write] = sample;
+    }
+
+    /// The signal `delay` samples before the newest sample, by Hermite
+    /// interpolation between the four nearest.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn read(&self, delay: f32) -> f32 {
+        let mask = self.buffer.len() - 1;
+        let delay = delay.clamp(0.0, (mask - 3) as f32);
+        let whole = delay as usize;
+        let t = delay.fract();
+        let at = |back: usize| self.buffer[self.write.wrapping_sub(back) & mask];
+        // The sample after the newest isn't here yet; repeat the newest
+        let p0 = at(whole.saturating_sub(1));
+        let (p1, p2, p3) = (at(whole), at(whole + 1), at(whole + 2));
+        let c1 = 0.5 * (p2 - p0);
+        let c2 = 2.5f32.mul_add(-p1, p0) + 2.0f32.mul_add(p2, -0.5 * p3);
+        let c3 = 0.5f32.mul_add(p3 - p0, 1.5 * (p1 - p2));
+        c3.mul_add(t, c2).mul_add(t, c1).mul_add(t, p1)
+    }
+}
+
+#[derive(Debug)]
+pub struct ChannelAlign {
+    id: EffectId,
+    enabled: bool,
+    delay_ms: f32,
+    invert: bool,
+    auto: bool,
+    /// How far the second channel lags the first, in samples
+    delay: SmoothParam,
+    /// Gain of the second channel, 1.0 or -1.0
+    polarity: SmoothParam,
+    lines: [DelayLine; 2],
+    analyzer: AlignmentAnalyzer,
+    /// Frames the analyzer had correlated at the last update
+    analyzed: u64,
+    sample_rate: SampleRate,
+    param_info: Vec<ParameterInfo>,
+}
+
+impl ChannelAlign {
+    /// An aligner that measures its input and follows the estimate.
+    #[must_use]
+    pub fn new(id: EffectId) -> Self {
+        let param_info = vec![
+            ParameterInfo::new(params::DELAY, "Delay")
+                .with_short_name("Delay")
+                .with_range(-MAX_DELAY_MS, MAX_DELAY_MS)
+                .with_default(0.0)
+                .with_unit("ms")
+                .with_precision(3),
+            ParameterInfo::new(params::INVERT, "Invert Second")
+                .with_short_name("Inv")
+                .with_default(0.0)
+                .with_kind(ParamKind::Bool),
+            ParameterInfo::new(params::AUTO, "Auto Align")
+                .with_short_name("Auto")
+                .with_default(1.0)
+                .with_kind(ParamKind::Bool),
+        ];
+
+        let sample_rate = SampleRate::Hz48000;
+        let mut align = Self {
+            id,
+            enabled: true,
+            delay_ms: 0.0,
+            invert: false,
+            auto: true,
+            delay: SmoothParam::new(0.0),
+            polarity: SmoothParam::new(1.0),
+            lines: [DelayLine::default(), DelayLine::default()],
+            analyzer: AlignmentAnalyzer::new(sample_rate),
+            analyzed: 0,
+            sample_rate,
+            param_info,
+        };
+        align.initialize(sample_rate, ChannelCount::Stereo);
+        align
+    }
+
+    /// An aligner that applies `alignment`, e.g. from
+    /// [`alignment::analyze_file`].
+    #[must_use]
+    pub fn fixed(id: EffectId, alignment: &ChannelAlignment) -> Self {
+        let mut align = Self::new(id);
+        align.set_delay_ms(alignment.delay_ms);
+        align.set_inverted(alignment.invert_polarity);
+        align.reset();
+        align
+    }
+
+    /// How far the second channel lags the first, in ms
+    #[must_use]
+    pub const fn delay_ms(&self) -> f32 {
+        self.delay_ms
+    }
+
+    /// Sets the delay by hand, which stops following the measurement.
+    pub fn set_delay_ms(&mut self, ms: f32) {
+        self.auto = false;
+        self.apply_delay_ms(ms);
+    }
+
+    #[must_use]
+    pub const fn is_inverted(&self) -> bool {
+        self.invert
+    }
+
+    /// Sets the polarity by hand, which stops following the measurement.
+    pub fn set_inverted(&mut self, invert: bool) {
+        self.auto = false;
+        self.apply_invert(invert);
+    }
+
+    #[must_use]
+    pub const fn is_auto(&self) -> bool {
+        self.auto
+    }
+
+    /// Follows the measurement from now on, or holds the current alignment.
+    pub fn set_auto(&mut self, auto: bool) {
+        if auto && !self.auto {
+            self.analyzer.reset();
+            self.analyzed = 0;
+        }
+        self.auto = auto;
+    }
+
+    /// The alignment measured from the input, `None` while not following
+    /// or before both channels were loud enough to measure
+    #[must_use]
+    pub fn measured(&self) -> Option<ChannelAlignment> {
+        self.analyzer.alignment().filter(|_| self.auto)
+    }
+
+    fn apply_delay_ms(&mut self, ms: f32) {
+        self.delay_ms = ms.clamp(-MAX_DELAY_MS, MAX_DELAY_MS);
+        let samples = self.delay_ms * self.sample_rate.as_f32() / 1000.0;
+        // Slow enough that the brief pitch change of a moving delay is
+        // inaudible
+        let ramp = self.sample_rate.samples_for_milliseconds(100);
+        self.delay.set_target(samples, ramp);
+    }
+
+    fn apply_invert(&mut self, invert: bool) {
+        self.invert = invert;
+        let ramp = self.sample_rate.samples_for_milliseconds(10);
+        self.polarity
+            .set_target(if invert { -1.0 } else { 1.0 }, ramp);
+    }
+
+    /// Moves to the measurement after the analyzer correlated a new frame.
+    fn follow(&mut self) {
+        let frames = self.analyzer.frames();
+        if frames == self.analyzed {
+            return;
+        }
+        self.analyzed = frames;
+        if let Some(alignment) = self.analyzer.alignment()
+            && alignment.correlation.abs() >= MIN_CORRELATION
+        {
+            self.apply_delay_ms(alignment.delay_ms);
+            self.apply_invert(alignment.invert_polarity);
+        }
+    }
+}
+
+impl Effect for ChannelAlign {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "Channel Align"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Clears the delay lines and the measurement, keeping the alignment.
+    fn reset(&mut self) {
+        for line in &mut self.lines {
+            line.clear();
+        }
+        self.delay.set_immediate(self.delay.target());
+        self.polarity.set_immediate(self.polarity.target());
+        self.analyzer.reset();
+        self.analyzed = 0;
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn initialize(&mut self, sample_rate: SampleRate, _channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        let max_delay = (MAX_DELAY_MS * sample_rate.as_f32() / 1000.0).ceil() as usize;
+        self.lines = [DelayLine::new(max_delay), DelayLine::new(max_delay)];
+        self.analyzer = AlignmentAnalyzer::new(sample_rate)
+            .with_max_delay(Duration::from_secs_f32(MAX_DELAY_MS / 1000.0))
+            .with_time_constant(AUTO_TIME);
+        self.apply_delay_ms(self.delay_ms);
+        self.apply_invert(self.invert);
+        self.reset();
+    }
+
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        if !self.enabled || channels == ChannelCount::Mono {
+            return;
+        }
+        if self.auto {
+            self.analyzer.process(samples, channels);
+            self.follow();
+        }
+        let [first_line, second_line] = &mut self.lines;
+        for frame in samples.chunks_exact_mut(channels.count_usize()) {
+            let delay = self.delay.next();
+            let polarity = self.polarity.next();
+            first_line.push(frame[0].value());
+            second_line.push(frame[1].value());
+            // The early channel waits for the late one
+            frame[0] = Sample::new(first_line.read(delay.max(0.0)));
+            frame[1] = Sample::new(second_line.read((-delay).max(0.0)) * polarity);
+        }
+    }
+
+    fn supports_channels(&self, channels: ChannelCount) -> bool {
+        channels != ChannelCount::Mono
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn tail_samples(&self) -> u32 {
+        self.delay.target().abs().ceil() as u32 + 2
+    }
+
+    fn memory_bytes(&self) -> usize {
+        size_of_val(self)
+            + self.analyzer.memory_bytes()
+            + self
+                .lines
+                .iter()
+                .map(|line| heap_bytes(&line.buffer))
+                .sum::<usize>()
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &self.param_info
+    }
+
+    fn get_parameter(&self, id: ParamId) -> Option<ParamValue> {
+        match id {
+            params::DELAY => Some(ParamValue::Float(self.delay_ms)),
+            params::INVERT => Some(ParamValue::Bool(self.invert)),
+            params::AUTO => Some(ParamValue::Bool(self.auto)),
+            _ => None,
+        }
+    }
+
+    fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool {
+        match id {
+            params::DELAY => self.set_delay_ms(value.as_float()),
+            params::INVERT => self.set_inverted(value.as_bool()),
+            params::AUTO => self.set_auto(value.as_bool()),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Measures the alignment of the second channel of the WAV file at `input`
+/// to its first, and writes an aligned copy to `output` in the same
+/// format.
+///
+/// # Errors
+/// Returns an error if the input can't be measured (see
+/// [`alignment::analyze_file`]) or the output can't be written.
+pub fn align_file(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<ChannelAlignment> {
+    let input = input.as_ref();
+    let alignment = alignment::analyze_file(input)?;
+    let mut reader = WavReader::open(input)?;
+    let format = reader.format();
+    let mut writer = WavWriter::create(output, format)?;
+    let mut align = ChannelAlign::fixed(EffectId::new(0), &alignment);
+    align.initialize(format.sample_rate, format.channels);
+    let mut buffer = vec![Sample::SILENCE; READ_FRAMES * format.channels.count_usize()];
+    loop {
+        let count = reader.read_samples(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        align.process(&mut buffer[..count], format.channels);
+        writer.write_samples(&buffer[..count])?;
+    }
+    writer.finalize()?;
+    Ok(alignment)
+}
+
+/// Measures the alignment of the WAV file at `second` to the one at
+/// `first`, and writes both, aligned, as a stereo file at `output`.
+///
+/// Only the first channel of each is used, and the output is as long as
+/// the shorter.
+///
+/// # Errors
+/// Returns an error if the inputs can't be measured (see
+/// [`alignment::analyze_files`]) or the output can't be written.
+pub fn align_files(
+    first: impl AsRef<Path>,
+    second: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+) -> Result<ChannelAlignment> {
+    let (first, second) = (first.as_ref(), second.as_ref());
+    let alignment = alignment::analyze_files(first, second)?;
+    let mut first_reader = WavReader::open(first)?;
+    let mut second_reader = WavReader::open(second)?;
+    let format = AudioFormat {
+        channels: ChannelCount::Stereo,
+        ..first_reader.format()
+    };
+    let mut writer = WavWriter::create(output, format)?;
+    let mut align = ChannelAlign::fixed(EffectId::new(0), &alignment);
+    align.initialize(format.sample_rate, format.channels);
+    let mut pair = PairReader::new(&first_reader, &second_reader);
+    let mut buffer = Vec::with_capacity(READ_FRAMES * 2);
+    while pair.read(&mut first_reader, &mut second_reader)? > 0 {
+        buffer.clear();
+        for (&a, &b) in pair.first.iter().zip(&pair.second) {
+            buffer.extend([a, b]);
+        }
+        align.process(&mut buffer, format.channels);
+        writer.write_samples(&buffer)?;
+    }
+    writer.finalize()?;
+    Ok(alignment)
+}
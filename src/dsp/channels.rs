@@ -0,0 +1,345 @@
+//! Channel-count-generic processing
+//!
+//! Effects that treat channels differently by position, like a panner,
+//! implement [`FrameProcessor`] and let [`process_frames`] walk the
+//! buffer. Each frame arrives as a fixed-size array, so the per-frame code
+//! is compiled once per channel count with the count known, and channel
+//! indexing needs no bounds checks.
+//!
+//! An effect that can't handle a layout says so through
+//! [`Effect::supports_channels`]; chains bypass it rather than let it
+//! process half the channels. [`check_channels`] runs an effect through
+//! mono, stereo and 5.1 to confirm it either processes each cleanly or
+//! reports it unsupported.
+
+use crate::dsp::traits::Effect;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+/// Processes one interleaved frame at a time, for any channel count.
+pub trait FrameProcessor {
+    fn process_frame<const N: usize>(&mut self, frame: &mut [Sample; N]);
+}
+
+/// Runs `processor` over every frame of `samples`.
+pub fn process_frames<P: FrameProcessor>(
+    processor: &mut P,
+    samples: &mut [Sample],
+    channels: ChannelCount,
+) {
+    match channels {
+        ChannelCount::Mono => run::<P, 1>(processor, samples),
+        ChannelCount::Stereo => run::<P, 2>(processor, samples),
+        ChannelCount::Quad => run::<P, 4>(processor, samples),
+        ChannelCount::Surround51 => run::<P, 6>(processor, samples),
+        ChannelCount::Surround71 => run::<P, 8>(processor, samples),
+    }
+}
+
+fn run<P: FrameProcessor, const N: usize>(processor: &mut P, samples: &mut [Sample]) {
+    let (frames, _) = samples.as_chunks_mut::<N>();
+    for frame in frames {
+        processor.process_frame(frame);
+    }
+}
+
+/// Left/right channel pairs of the `N` channel layout, front pair first.
+/// Centre and LFE channels belong to no pair, and mono has none.
+#[must_use]
+pub const fn stereo_pairs<const N: usize>() -> &'static [(usize, usize)] {
+    match N {
+        2 => &[(0, 1)],
+        4 => &[(0, 1), (2, 3)],
+        6 => &[(0, 1), (4, 5)],
+        8 => &[(0, 1), (4, 5), (6, 7)],
+        _ => &[],
+    }
+}
+
+/// How an effect fared with one layout in [`check_channels`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelSupport {
+    /// Processed cleanly
+    Processed,
+    /// The effect reports it can't handle the layout
+    Unsupported,
+    /// The effect accepted the layout but produced non-finite samples
+    Failed,
+}
+
+/// Layouts [`check_channels`] runs an effect through
+pub const CHECKED_LAYOUTS: [ChannelCount; 3] = [
+    ChannelCount::Mono,
+    ChannelCount::Stereo,
+    ChannelCount::Surround51,
+];
+
+/// Frames of test signal per layout
+const CHECK_FRAMES: usize = 256;
+
+/// Runs `effect` on a test signal in mono, stereo and 5.1, returning how
+/// each went. Every layout should come out [`ChannelSupport::Processed`]
+/// or [`ChannelSupport::Unsupported`].
+///
+/// Meant for a fresh effect, e.g. when loading one: it is left
+/// initialized for the last layout and reset. An effect that indexes past
+/// its channels panics here rather than later on the audio thread. It is
+/// public so a host can vet effects written outside this crate before
+/// inserting them; the built-in ones are checked by this module's tests.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn check_channels(
+    effect: &mut dyn Effect,
+    sample_rate: SampleRate,
+) -> [(ChannelCount, ChannelSupport); 3] {
+    CHECKED_LAYOUTS.map(|channels| {
+        if !effect.supports_channels(channels) {
+            return (channels, ChannelSupport::Unsupported);
+        }
+        effect.initialize(sample_rate, channels);
+        effect.reset();
+        let count = channels.count_usize();
+        // A different tone on every channel
+        let mut samples: Vec<Sample> = (0..CHECK_FRAMES * count)
+            .map(|index| {
+                let (frame, channel) = (index / count, index % count);
+                Sample::new((frame as f32 * 0.05 * (channel + 1) as f32).sin() * 0.5)
+            })
+            .collect();
+        effect.process(&mut samples, channels);
+        let support = if samples.iter().all(|s| s.value().is_finite()) {
+            ChannelSupport::Processed
+        } else {
+            ChannelSupport::Failed
+        };
+        (channels, support)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::analysis::SpectrumProfile;
+    use crate::buffer::RingBuffer;
+    use crate::dsp::auto_gain::AutoGain;
+    use crate::dsp::bit_crusher::BitCrusher;
+    use crate::dsp::chain::{EffectChain, ParallelChain};
+    use crate::dsp::channel_align::ChannelAlign;
+    use crate::dsp::dc_blocker::DcBlocker;
+    use crate::dsp::eq_match::EqMatch;
+    use crate::dsp::filters::BiquadFilter;
+    use crate::dsp::gain::GainEffect;
+    use crate::dsp::insert::HardwareInsert;
+    use crate::dsp::isolated::IsolatedEffect;
+    use crate::dsp::pan::PanEffect;
+    use crate::dsp::pitch_shift::PitchShift;
+    use crate::dsp::traits::EffectId;
+    use crate::dsp::trim::Trim;
+    use crate::engine::safety::{SafetyLimiter, SafetySettings};
+    use crate::mixer::ducker::{Ducker, DuckerSettings};
+    use crate::types::{Gain, Pan};
+
+    const ID: EffectId = EffectId::new(1);
+
+    /// A child for [`IsolatedEffect`] that sends the audio straight back
+    const ECHO_CHILD: &str = "
+import struct, sys
+stdin, stdout = sys.stdin.buffer, sys.stdout.buffer
+channels = 1
+while True:
+    kind = stdin.read(1)
+    if kind == b'I':
+        channels = struct.unpack('<II', stdin.read(8))[1]
+    elif kind == b'P':
+        stdin.read(8)
+    elif kind == b'A':
+        frames = struct.unpack('<I', stdin.read(4))[0]
+        stdout.write(stdin.read(4 * frames * channels))
+        stdout.flush()
+    elif kind != b'R':
+        break
+";
+
+    /// An isolated effect echoing through Python, if there is a Python
+    fn echo_effect(latency: u32) -> Option<IsolatedEffect> {
+        let mut command = Command::new("python3");
+        command.args(["-c", ECHO_CHILD]);
+        IsolatedEffect::spawn(ID, "Echo", command)
+            .ok()
+            .map(|effect| effect.with_latency(latency))
+    }
+
+    /// Every built-in effect
+    fn built_in_effects() -> Vec<Box<dyn Effect>> {
+        let mut branch = EffectChain::new();
+        branch.push(Box::new(BiquadFilter::low_pass(ID, 200.0, 0.707)));
+        let mut parallel = ParallelChain::new(ID, CHECK_FRAMES * 8);
+        parallel.add_branch(branch);
+        // Room for a whole 5.1 test signal, which comes back at once
+        let (send, ret) = RingBuffer::new(CHECK_FRAMES * 8);
+        let mut effects: Vec<Box<dyn Effect>> = vec![
+            Box::new(GainEffect::with_gain(ID, Gain::from_db(-6.0))),
+            Box::new(PanEffect::with_pan(ID, Pan::new(0.5))),
+            Box::new(BiquadFilter::low_pass(ID, 1000.0, 0.707)),
+            Box::new(BiquadFilter::peak(ID, 2000.0, 1.0, 6.0)),
+            Box::new(BitCrusher::with_params(ID, 8.0, 11_025.0)),
+            Box::new(Trim::new(ID)),
+            Box::new(DcBlocker::new(ID)),
+            Box::new(AutoGain::new(ID)),
+            Box::new(PitchShift::with_params(ID, 3.0, 0.0)),
+            Box::new(HardwareInsert::new(ID, send, ret)),
+            Box::new(EqMatch::new(ID, SpectrumProfile::tilt(-3.0))),
+            Box::new(ChannelAlign::new(ID)),
+            Box::new(parallel),
+        ];
+        if let Some(echo) = echo_effect(1024) {
+            effects.push(Box::new(echo));
+        }
+        effects
+    }
+
+    /// `frames` frames of a different tone on every channel
+    #[allow(clippy::cast_precision_loss)]
+    fn tones(frames: usize, channels: ChannelCount) -> Vec<Sample> {
+        let count = channels.count_usize();
+        (0..frames * count)
+            .map(|index| {
+                let (frame, channel) = (index / count, index % count);
+                Sample::new((frame as f32 * 0.05 * (channel + 1) as f32).sin() * 0.5)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn built_in_effects_process_or_refuse_every_layout() {
+        for mut effect in built_in_effects() {
+            for (channels, support) in check_channels(effect.as_mut(), SampleRate::Hz48000) {
+                assert!(
+                    matches!(
+                        support,
+                        ChannelSupport::Processed | ChannelSupport::Unsupported
+                    ),
+                    "{} failed in {channels:?}",
+                    effect.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn gain_scales_the_only_mono_channel() {
+        let gain = Gain::from_db(-6.0);
+        let mut effect = GainEffect::with_gain(ID, gain);
+        effect.initialize(SampleRate::Hz48000, ChannelCount::Mono);
+        effect.reset();
+        let input = tones(CHECK_FRAMES, ChannelCount::Mono);
+        let mut output = input.clone();
+        effect.process(&mut output, ChannelCount::Mono);
+        for (input, output) in input.iter().zip(&output) {
+            let expected = input.value() * gain.as_linear();
+            assert!((output.value() - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn pan_refuses_mono() {
+        let mut effect = PanEffect::with_pan(ID, Pan::LEFT);
+        assert_eq!(
+            check_channels(&mut effect, SampleRate::Hz48000)[0],
+            (ChannelCount::Mono, ChannelSupport::Unsupported)
+        );
+    }
+
+    #[test]
+    fn pan_leaves_centre_and_lfe_alone_in_surround() {
+        let channels = ChannelCount::Surround51;
+        let mut effect = PanEffect::with_pan(ID, Pan::LEFT);
+        effect.initialize(SampleRate::Hz48000, channels);
+        effect.reset();
+        let input = tones(CHECK_FRAMES, channels);
+        let mut output = input.clone();
+        effect.process(&mut output, channels);
+        for (input, output) in input.chunks_exact(6).zip(output.chunks_exact(6)) {
+            // Centre and LFE untouched, to the bit
+            assert_eq!(input[2].value().to_bits(), output[2].value().to_bits());
+            assert_eq!(input[3].value().to_bits(), output[3].value().to_bits());
+            // Hard left silences the right of the front and rear pairs
+            assert!(output[1].value().abs() < 1e-6);
+            assert!(output[5].value().abs() < 1e-6);
+            assert!((output[0].value() - input[0].value()).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn isolated_effect_returns_every_channel_delayed() {
+        const LATENCY: usize = 64;
+        let Some(mut effect) = echo_effect(64) else {
+            return;
+        };
+        // Give the interpreter time to start
+        thread::sleep(Duration::from_millis(500));
+        for channels in CHECKED_LAYOUTS {
+            let count = channels.count_usize();
+            effect.initialize(SampleRate::Hz48000, channels);
+            let input = tones(CHECK_FRAMES, channels);
+            let mut output = Vec::new();
+            for block in input.chunks(LATENCY * count) {
+                let mut block = block.to_vec();
+                effect.process(&mut block, channels);
+                output.extend(block);
+                // Time for the round trip through the child
+                thread::sleep(Duration::from_millis(50));
+            }
+            assert!(!effect.has_failed());
+            assert!(output[..LATENCY * count].iter().all(|s| s.value() == 0.0));
+            assert_eq!(
+                output[LATENCY * count..],
+                input[..input.len() - LATENCY * count]
+            );
+        }
+    }
+
+    #[test]
+    fn ducker_turns_every_channel_down_together() {
+        let settings = DuckerSettings::default();
+        for channels in CHECKED_LAYOUTS {
+            let count = channels.count_usize();
+            let mut ducker = Ducker::new(settings, SampleRate::Hz48000);
+            // A loud key on the last channel alone
+            let mut key = vec![Sample::SILENCE; CHECK_FRAMES * 16 * count];
+            for frame in key.chunks_exact_mut(count) {
+                frame[count - 1] = Sample::new(0.9);
+            }
+            let mut signal = vec![Sample::new(0.5); key.len()];
+            ducker.process(&key, &mut signal, channels);
+            let last = &signal[signal.len() - count..];
+            assert!(last[0].value() < 0.5, "not ducked in {channels:?}");
+            assert!(last.iter().all(|s| s.value() == last[0].value()));
+        }
+    }
+
+    #[test]
+    fn safety_limiter_holds_every_channel_under_the_ceiling() {
+        let settings = SafetySettings::default().with_dc_block(false);
+        let ceiling = settings.ceiling.to_linear();
+        for channels in CHECKED_LAYOUTS {
+            let count = channels.count_usize();
+            let mut limiter = SafetyLimiter::new(settings, SampleRate::Hz48000, channels)
+                .expect("the stage is on");
+            // Twice full scale on one channel, a quiet one on the others
+            let mut frame: Vec<f32> = (0..count).map(|_| 0.1).collect();
+            frame[count - 1] = 2.0;
+            limiter.process_frame(&mut frame);
+            assert!(frame.iter().all(|s| s.abs() <= ceiling));
+            // One gain for the whole frame, so the image holds
+            let ratio = frame[0] / frame[count - 1];
+            assert!((ratio - 0.05).abs() < 1e-6 || count == 1);
+            let mut broken = vec![f32::NAN; count];
+            limiter.process_frame(&mut broken);
+            assert!(broken.iter().all(|s| *s == 0.0));
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Crossfade curves and ramps
+
+use std::f32::consts::FRAC_PI_2;
+
+/// Shape of a crossfade between two signals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CrossfadeCurve {
+    /// Gains sum to 1.0, dips by 6 dB in the middle for uncorrelated material
+    Linear,
+    /// Constant power (sin/cos), no dip for uncorrelated material
+    #[default]
+    EqualPower,
+    /// Smoothstep, gentle start and end
+    SCurve,
+}
+
+impl CrossfadeCurve {
+    /// Returns `(outgoing_gain, incoming_gain)` at position `t` in [0.0, 1.0].
+    #[must_use]
+    pub fn gains(self, t: f32) -> (f32, f32) {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => (1.0 - t, t),
+            Self::EqualPower => {
+                let angle = t * FRAC_PI_2;
+                (angle.cos(), angle.sin())
+            }
+            Self::SCurve => {
+                let s = t * t * 2.0f32.mul_add(-t, 3.0);
+                (1.0 - s, s)
+            }
+        }
+    }
+}
+
+/// Sample-by-sample progress of a crossfade.
+#[derive(Debug, Clone, Copy)]
+pub struct Crossfade {
+    curve: CrossfadeCurve,
+    position: f32,
+    increment: f32,
+}
+
+impl Crossfade {
+    #[must_use]
+    pub const fn new(curve: CrossfadeCurve) -> Self {
+        Self {
+            curve,
+            position: 1.0,
+            increment: 0.0,
+        }
+    }
+
+    #[must_use]
+    pub const fn curve(&self) -> CrossfadeCurve {
+        self.curve
+    }
+
+    pub const fn set_curve(&mut self, curve: CrossfadeCurve) {
+        self.curve = curve;
+    }
+
+    /// Starts a new crossfade lasting `samples` frames.
+    ///
+    /// A length of zero completes the fade immediately.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn start(&mut self, samples: u32) {
+        if samples == 0 {
+            self.position = 1.0;
+            self.increment = 0.0;
+        } else {
+            self.position = 0.0;
+            self.increment = 1.0 / samples as f32;
+        }
+    }
+
+    /// Returns true while the fade is in progress.
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        self.position < 1.0
+    }
+
+    /// Progress in [0.0, 1.0]
+    #[must_use]
+    pub const fn position(&self) -> f32 {
+        self.position
+    }
+
+    /// Returns the gains for the current frame and advances by one frame.
+    pub fn next_gains(&mut self) -> (f32, f32) {
+        let gains = self.curve.gains(self.position);
+        if self.is_active() {
+            self.position = (self.position + self.increment).min(1.0);
+        }
+        gains
+    }
+}
+
+impl Default for Crossfade {
+    fn default() -> Self {
+        Self::new(CrossfadeCurve::default())
+    }
+}
@@ -0,0 +1,240 @@
+//! Linkwitz-Riley crossover filter bank
+//!
+//! Each crossover point is a 4th order Linkwitz-Riley split (two cascaded
+//! Butterworth biquads per side). Bands below a split are passed through an
+//! all-pass at every higher crossover frequency, so all bands share the same
+//! phase response and summing them reconstructs the input with a flat
+//! magnitude response.
+
+use crate::buffer::realtime::AudioBuffer;
+use crate::dsp::filters::{BiquadCoeffs, BiquadState, FilterType};
+use crate::error::{AudioEngineError, Result};
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+/// Butterworth Q; two in series give a Linkwitz-Riley response
+const BUTTERWORTH_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Debug, Clone, Copy)]
+struct Split {
+    frequency: f32,
+    low_pass: BiquadCoeffs,
+    high_pass: BiquadCoeffs,
+    all_pass: BiquadCoeffs,
+}
+
+impl Split {
+    fn new(frequency: f32, fs: f32) -> Self {
+        Self {
+            frequency,
+            low_pass: BiquadCoeffs::design(FilterType::LowPass, frequency, BUTTERWORTH_Q, 0.0, fs),
+            high_pass: BiquadCoeffs::design(
+                FilterType::HighPass,
+                frequency,
+                BUTTERWORTH_Q,
+                0.0,
+                fs,
+            ),
+            all_pass: BiquadCoeffs::design(FilterType::AllPass, frequency, BUTTERWORTH_Q, 0.0, fs),
+        }
+    }
+}
+
+/// Filter state for one channel of one split.
+#[derive(Debug, Clone, Copy, Default)]
+struct SplitState {
+    low: [BiquadState; 2],
+    high: [BiquadState; 2],
+}
+
+impl SplitState {
+    /// Returns `(low, high)` for one input sample.
+    fn process(&mut self, input: f32, split: &Split) -> (f32, f32) {
+        let low = self.low[0].process(input, &split.low_pass);
+        let low = self.low[1].process(low, &split.low_pass);
+        let high = self.high[0].process(input, &split.high_pass);
+        let high = self.high[1].process(high, &split.high_pass);
+        (low, high)
+    }
+}
+
+/// Splits an interleaved signal into frequency bands.
+///
+/// `N` crossover frequencies produce `N + 1` bands, lowest first.
+#[derive(Debug, Clone)]
+pub struct CrossoverBank {
+    splits: Vec<Split>,
+    channels: ChannelCount,
+    sample_rate: SampleRate,
+    /// `[channel][split]`
+    split_states: Vec<SplitState>,
+    /// `[channel][band][split]`, only used where `split > band`
+    all_pass_states: Vec<BiquadState>,
+}
+
+impl CrossoverBank {
+    /// Creates a bank with the given crossover frequencies in Hz.
+    ///
+    /// # Errors
+    /// Returns an error if no frequencies are given, or they aren't strictly
+    /// ascending and between 20 Hz and Nyquist.
+    pub fn new(
+        frequencies: &[f32],
+        sample_rate: SampleRate,
+        channels: ChannelCount,
+    ) -> Result<Self> {
+        if frequencies.is_empty() {
+            return Err(AudioEngineError::configuration(
+                "crossover bank needs at least one frequency",
+            ));
+        }
+        let fs = sample_rate.as_f32();
+        Self::validate(frequencies, fs)?;
+
+        let splits = frequencies.len();
+        let channel_count = channels.count_usize();
+        Ok(Self {
+            splits: frequencies.iter().map(|&f| Split::new(f, fs)).collect(),
+            channels,
+            sample_rate,
+            split_states: vec![SplitState::default(); channel_count * splits],
+            all_pass_states: vec![BiquadState::default(); channel_count * (splits + 1) * splits],
+        })
+    }
+
+    fn validate(frequencies: &[f32], fs: f32) -> Result<()> {
+        let nyquist = fs * 0.49;
+        let in_range = frequencies.iter().all(|&f| (20.0..nyquist).contains(&f));
+        let ascending = frequencies.windows(2).all(|pair| pair[0] < pair[1]);
+        if in_range && ascending {
+            Ok(())
+        } else {
+            Err(AudioEngineError::configuration(
+                "crossover frequencies must be ascending and between 20 Hz and Nyquist",
+            ))
+        }
+    }
+
+    /// Number of output bands
+    #[must_use]
+    pub const fn band_count(&self) -> usize {
+        self.splits.len() + 1
+    }
+
+    #[must_use]
+    pub const fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    #[must_use]
+    pub const fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    /// Crossover frequencies in Hz, lowest first
+    pub fn frequencies(&self) -> impl Iterator<Item = f32> + '_ {
+        self.splits.iter().map(|s| s.frequency)
+    }
+
+    /// Moves one crossover point.
+    ///
+    /// # Errors
+    /// Returns an error if `index` is out of range or the new frequency would
+    /// break the ascending order.
+    pub fn set_frequency(&mut self, index: usize, frequency: f32) -> Result<()> {
+        if index >= self.splits.len() {
+            return Err(AudioEngineError::configuration(format!(
+                "crossover index {index} out of range"
+            )));
+        }
+        let mut frequencies: Vec<f32> = self.frequencies().collect();
+        frequencies[index] = frequency;
+        let fs = self.sample_rate.as_f32();
+        Self::validate(&frequencies, fs)?;
+        self.splits[index] = Split::new(frequency, fs);
+        Ok(())
+    }
+
+    /// Clears all filter state.
+    pub fn reset(&mut self) {
+        for state in &mut self.split_states {
+            *state = SplitState::default();
+        }
+        for state in &mut self.all_pass_states {
+            state.reset();
+        }
+    }
+
+    /// Splits `input` into `outputs`, one buffer per band.
+    ///
+    /// Every output must have the bank's channel count and at least as many
+    /// frames as `input`.
+    ///
+    /// # Errors
+    /// Returns an error if the number of outputs doesn't match
+    /// [`band_count`](Self::band_count) or an output has the wrong shape.
+    pub fn split(&mut self, input: &[Sample], outputs: &mut [AudioBuffer]) -> Result<()> {
+        let channels = self.channels.count_usize();
+        let frames = input.len() / channels;
+        if outputs.len() != self.band_count() {
+            return Err(AudioEngineError::configuration(format!(
+                "crossover bank produces {} bands, got {} outputs",
+                self.band_count(),
+                outputs.len()
+            )));
+        }
+        for output in outputs.iter() {
+            if output.channels() != self.channels {
+                return Err(AudioEngineError::ChannelCountMismatch {
+                    source_count: self.channels,
+                    target_count: output.channels(),
+                });
+            }
+            if output.frames() < frames {
+                return Err(AudioEngineError::BufferOverflow {
+                    attempted: frames,
+                    capacity: output.frames(),
+                });
+            }
+        }
+
+        let splits = self.splits.len();
+        for (frame_index, frame) in input.chunks_exact(channels).enumerate() {
+            for (ch, sample) in frame.iter().enumerate() {
+                let index = frame_index * channels + ch;
+                let split_states = &mut self.split_states[ch * splits..(ch + 1) * splits];
+                let all_pass_states = &mut self.all_pass_states
+                    [ch * (splits + 1) * splits..(ch + 1) * (splits + 1) * splits];
+
+                let mut rest = sample.value();
+                for (band, (split, state)) in self.splits.iter().zip(split_states).enumerate() {
+                    let (mut low, high) = state.process(rest, split);
+                    rest = high;
+                    // Match the phase shift the higher bands get from later splits
+                    for (later, ap_state) in self.splits[band + 1..]
+                        .iter()
+                        .zip(&mut all_pass_states[band * splits + band + 1..(band + 1) * splits])
+                    {
+                        low = ap_state.process(low, &later.all_pass);
+                    }
+                    outputs[band].samples_mut()[index] = Sample::new(low);
+                }
+                outputs[splits].samples_mut()[index] = Sample::new(rest);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sums band buffers back into an interleaved output.
+    ///
+    /// Writes `output.len()` samples; bands shorter than that contribute silence.
+    pub fn sum_bands(bands: &[AudioBuffer], output: &mut [Sample]) {
+        for (index, sample) in output.iter_mut().enumerate() {
+            let value = bands
+                .iter()
+                .filter_map(|band| band.samples().get(index))
+                .map(|s| s.value())
+                .sum();
+            *sample = Sample::new(value);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! DC blocking filter
+
+use std::f32::consts::TAU;
+
+use crate::dsp::denormal::flush_denormals;
+use crate::dsp::params::{ParamId, ParamValue, ParameterInfo, SmoothParam, SmoothingMode};
+use crate::dsp::traits::{Effect, EffectId, SmoothableEffect};
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+pub mod params {
+    use super::ParamId;
+    /// Cutoff frequency in Hz
+    pub const CUTOFF: ParamId = ParamId::new(0);
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct DcState {
+    x1: f32,
+    y1: f32,
+}
+
+/// One-pole high pass: `y[n] = x[n] - x[n-1] + r * y[n-1]`
+#[derive(Debug)]
+pub struct DcBlocker {
+    id: EffectId,
+    enabled: bool,
+    cutoff_hz: SmoothParam,
+    r: f32,
+    sample_rate: SampleRate,
+    states: [DcState; 8],
+    param_info: Vec<ParameterInfo>,
+}
+
+impl DcBlocker {
+    #[must_use]
+    pub fn new(id: EffectId) -> Self {
+        Self::with_cutoff(id, 10.0)
+    }
+
+    #[must_use]
+    pub fn with_cutoff(id: EffectId, cutoff_hz: f32) -> Self {
+        let param_info = vec![
+            ParameterInfo::new(params::CUTOFF, "Cutoff")
+                .with_short_name("Cutoff")
+                .with_range(1.0, 40.0)
+                .with_default(10.0)
+                .with_unit("Hz")
+                .with_precision(1),
+        ];
+
+        let mut blocker = Self {
+            id,
+            enabled: true,
+            cutoff_hz: SmoothParam::new(cutoff_hz.clamp(1.0, 40.0)),
+            r: 0.0,
+            sample_rate: SampleRate::Hz48000,
+            states: [DcState::default(); 8],
+            param_info,
+        };
+        blocker.update_coefficient();
+        blocker
+    }
+
+    pub fn set_cutoff(&mut self, cutoff_hz: f32) {
+        self.set_cutoff_over(cutoff_hz, 0);
+    }
+
+    fn set_cutoff_over(&mut self, cutoff_hz: f32, samples: u32) {
+        self.cutoff_hz
+            .set_target(cutoff_hz.clamp(1.0, 40.0), samples);
+        self.update_coefficient();
+    }
+
+    #[must_use]
+    pub const fn cutoff(&self) -> f32 {
+        self.cutoff_hz.current()
+    }
+
+    fn update_coefficient(&mut self) {
+        self.r = 1.0 - (TAU * self.cutoff_hz.current() / self.sample_rate.as_f32());
+    }
+}
+
+impl Effect for DcBlocker {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "DC Blocker"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn reset(&mut self) {
+        self.states = [DcState::default(); 8];
+        self.cutoff_hz.set_immediate(self.cutoff_hz.target());
+        self.update_coefficient();
+    }
+
+    fn initialize(&mut self, sample_rate: SampleRate, _channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.update_coefficient();
+    }
+
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        if !self.enabled {
+            return;
+        }
+
+        for frame in samples.chunks_exact_mut(channels.count_usize()) {
+            if self.cutoff_hz.is_smoothing() {
+                let _ = self.cutoff_hz.next();
+                self.update_coefficient();
+            }
+            for (sample, state) in frame.iter_mut().zip(self.states.iter_mut()) {
+                let x = sample.value();
+                let y = flush_denormals(self.r.mul_add(state.y1, x - state.x1));
+                state.x1 = x;
+                state.y1 = y;
+                *sample = Sample::new(y);
+            }
+        }
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &self.param_info
+    }
+
+    fn get_parameter(&self, id: ParamId) -> Option<ParamValue> {
+        match id {
+            params::CUTOFF => Some(ParamValue::Float(self.cutoff())),
+            _ => None,
+        }
+    }
+
+    fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool {
+        match id {
+            params::CUTOFF => {
+                self.set_cutoff(value.as_float());
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl SmoothableEffect for DcBlocker {
+    fn set_parameter_smooth(&mut self, id: ParamId, value: ParamValue, samples: u32) {
+        if id == params::CUTOFF {
+            self.set_cutoff_over(value.as_float(), samples);
+        }
+    }
+
+    fn update_smoothing(&mut self) {
+        if self.cutoff_hz.is_smoothing() {
+            self.cutoff_hz.advance(1);
+            self.update_coefficient();
+        }
+    }
+
+    fn set_smoothing_mode(&mut self, id: ParamId, mode: SmoothingMode) -> bool {
+        if id == params::CUTOFF {
+            self.cutoff_hz.set_mode(mode);
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cutoff_ramps_when_smoothed() {
+        let mut blocker = DcBlocker::with_cutoff(EffectId::new(1), 10.0);
+        blocker.set_parameter_smooth(params::CUTOFF, ParamValue::Float(30.0), 100);
+        let start = blocker.r;
+        let mut block = [Sample::SILENCE; 100];
+        blocker.process(&mut block[..50], ChannelCount::Mono);
+        assert!((blocker.cutoff() - 20.0).abs() < 0.01);
+        assert!(blocker.r < start);
+        for _ in 0..50 {
+            blocker.update_smoothing();
+        }
+        assert_eq!(blocker.cutoff(), 30.0);
+
+        // Plain sets still take effect at once
+        blocker.set_cutoff(5.0);
+        assert_eq!(blocker.cutoff(), 5.0);
+        assert!(!blocker.set_smoothing_mode(ParamId::new(9), SmoothingMode::Exponential));
+    }
+}
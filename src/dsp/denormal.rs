@@ -0,0 +1,44 @@
+//! Denormal protection
+//!
+//! Recursive filters decaying towards silence end up in the subnormal float
+//! range, where x86 arithmetic gets dramatically slower. Flushing tiny values
+//! to zero in feedback paths keeps long silent passages cheap.
+
+use crate::types::Sample;
+
+/// How processing treats subnormal floats.
+///
+/// Hardware flush-to-zero and denormals-are-zero modes differ between CPUs
+/// and can only be switched with unsafe intrinsics, so the crate doesn't
+/// touch them. [`Flush`](Self::Flush) instead flushes in software at fixed
+/// points, which gives the same result whatever mode the host left the FPU
+/// in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DenormalPolicy {
+    /// Only the feedback paths of recursive filters are flushed
+    #[default]
+    FeedbackOnly,
+    /// Every effect's output is flushed as well
+    Flush,
+}
+
+/// Magnitudes below this are flushed to zero (about -300 dBFS)
+pub const DENORMAL_THRESHOLD: f32 = 1.0e-15;
+
+/// Returns zero if `value` is small enough to become subnormal.
+#[inline]
+#[must_use]
+pub fn flush_denormals(value: f32) -> f32 {
+    if value.abs() < DENORMAL_THRESHOLD {
+        0.0
+    } else {
+        value
+    }
+}
+
+/// Flushes every sample in the slice.
+pub fn flush_denormals_slice(samples: &mut [Sample]) {
+    for sample in samples {
+        *sample = Sample::new(flush_denormals(sample.value()));
+    }
+}
@@ -0,0 +1,308 @@
+//! Spectrum matching EQ
+//!
+//! [`EqMatch`] is a ten band parametric EQ, one peaking band per octave
+//! from 31.25 Hz to 16 kHz, whose gains make its input's long-term
+//! spectrum match a target [`SpectrumProfile`]: a reference mix measured
+//! with [`spectrum::analyze_file`], or a plain tilt from
+//! [`SpectrumProfile::tilt`]. Only the shape is matched; the gains average
+//! 0 dB, so the loudness stays about the same.
+//!
+//! Offline, measure the track too and apply the correction as it is:
+//!
+//! ```no_run
+//! use audio_engine::dsp::chain::ChainBuilder;
+//! use audio_engine::dsp::eq_match::{self, EqMatch};
+//!
+//! let correction = eq_match::match_files("mix.wav", "reference.wav", 12.0)?;
+//! let chain = ChainBuilder::new()
+//!     .effect(|id| EqMatch::fixed(id, correction))
+//!     .build();
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! Live, on a track or the master, [`EqMatch::new`] measures its input as
+//! it plays, averaging over the adaptation time (10 s by default), and
+//! moves the gains as the average moves. Freezing it holds the gains it
+//! has learnt. The amount scales the whole correction down, e.g. to 50%
+//! for a gentler match.
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::analysis::spectrum::{self, BAND_CENTERS, BANDS, SpectrumAnalyzer, SpectrumProfile};
+use crate::dsp::filters::BiquadFilter;
+use crate::dsp::params::{ParamId, ParamKind, ParamValue, ParameterInfo};
+use crate::dsp::traits::{Effect, EffectId};
+use crate::error::Result;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+pub mod params {
+    use super::ParamId;
+    /// Share of the correction applied, in percent
+    pub const AMOUNT: ParamId = ParamId::new(0);
+    /// Largest boost or cut of a band in dB
+    pub const MAX_GAIN: ParamId = ParamId::new(1);
+    /// Time the input spectrum is averaged over, in seconds
+    pub const ADAPT: ParamId = ParamId::new(2);
+    /// Holds the current correction instead of adapting
+    pub const FREEZE: ParamId = ParamId::new(3);
+}
+
+/// Q of an octave wide peaking band
+const BAND_Q: f32 = 1.414;
+
+#[derive(Debug)]
+pub struct EqMatch {
+    id: EffectId,
+    enabled: bool,
+    /// `None` for a fixed correction
+    target: Option<SpectrumProfile>,
+    amount: f32,
+    max_gain_db: f32,
+    adapt_seconds: f32,
+    frozen: bool,
+    sample_rate: SampleRate,
+    analyzer: SpectrumAnalyzer,
+    /// Frames the analyzer had averaged at the last update
+    analyzed: u64,
+    /// Full correction, before the amount scales it
+    correction: [f32; BANDS],
+    bands: [BiquadFilter; BANDS],
+    param_info: Vec<ParameterInfo>,
+}
+
+impl EqMatch {
+    /// An EQ that adapts to make its input match `target`.
+    #[must_use]
+    pub fn new(id: EffectId, target: SpectrumProfile) -> Self {
+        let mut eq = Self::fixed(id, [0.0; BANDS]);
+        eq.target = Some(target);
+        eq
+    }
+
+    /// An EQ that applies `correction`, in dB per band, e.g. from
+    /// [`SpectrumProfile::correction`].
+    #[must_use]
+    pub fn fixed(id: EffectId, correction: [f32; BANDS]) -> Self {
+        let param_info = vec![
+            ParameterInfo::new(params::AMOUNT, "Amount")
+                .with_short_name("Amount")
+                .with_range(0.0, 100.0)
+                .with_default(100.0)
+                .with_unit("%")
+                .with_precision(0),
+            ParameterInfo::new(params::MAX_GAIN, "Max Gain")
+                .with_short_name("Max")
+                .with_range(0.0, 24.0)
+                .with_default(12.0)
+                .with_unit("dB")
+                .with_precision(1),
+            ParameterInfo::new(params::ADAPT, "Adapt Time")
+                .with_short_name("Adapt")
+                .with_range(1.0, 120.0)
+                .with_default(10.0)
+                .with_unit("s")
+                .with_precision(1),
+            ParameterInfo::new(params::FREEZE, "Freeze")
+                .with_short_name("Freeze")
+                .with_default(0.0)
+                .with_kind(ParamKind::Bool),
+        ];
+
+        let sample_rate = SampleRate::Hz48000;
+        let mut eq = Self {
+            id,
+            enabled: true,
+            target: None,
+            amount: 100.0,
+            max_gain_db: 12.0,
+            adapt_seconds: 10.0,
+            frozen: false,
+            sample_rate,
+            analyzer: SpectrumAnalyzer::new(sample_rate)
+                .with_time_constant(Duration::from_secs(10), sample_rate),
+            analyzed: 0,
+            correction,
+            bands: BAND_CENTERS.map(|hz| BiquadFilter::peak(id, hz, BAND_Q, 0.0)),
+            param_info,
+        };
+        eq.apply_correction();
+        eq
+    }
+
+    /// The spectrum the EQ adapts towards, `None` for a fixed correction
+    #[must_use]
+    pub const fn target(&self) -> Option<&SpectrumProfile> {
+        self.target.as_ref()
+    }
+
+    /// Adapts towards `target` from now on.
+    pub const fn set_target(&mut self, target: SpectrumProfile) {
+        self.target = Some(target);
+    }
+
+    /// Applies `correction` and stops adapting.
+    pub fn set_correction(&mut self, correction: [f32; BANDS]) {
+        self.target = None;
+        self.correction = correction;
+        self.apply_correction();
+    }
+
+    /// Gain of each band, in dB, with the amount applied
+    #[must_use]
+    pub fn gains_db(&self) -> [f32; BANDS] {
+        self.correction.map(|db| db * self.amount / 100.0)
+    }
+
+    /// Long-term spectrum of the input so far, `None` for a fixed
+    /// correction or before the input was loud enough to measure
+    #[must_use]
+    pub fn input_profile(&self) -> Option<SpectrumProfile> {
+        self.target.and_then(|_| self.analyzer.profile())
+    }
+
+    pub fn set_amount(&mut self, percent: f32) {
+        self.amount = percent.clamp(0.0, 100.0);
+        self.apply_correction();
+    }
+
+    pub fn set_max_gain_db(&mut self, db: f32) {
+        self.max_gain_db = db.clamp(0.0, 24.0);
+        self.correction = self
+            .correction
+            .map(|gain| gain.clamp(-self.max_gain_db, self.max_gain_db));
+        self.apply_correction();
+    }
+
+    pub fn set_adapt_seconds(&mut self, seconds: f32) {
+        self.adapt_seconds = seconds.clamp(1.0, 120.0);
+        self.analyzer.set_time_constant(
+            Duration::from_secs_f32(self.adapt_seconds),
+            self.sample_rate,
+        );
+    }
+
+    pub const fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    #[must_use]
+    pub const fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    fn apply_correction(&mut self) {
+        let gains = self.gains_db();
+        for (band, gain) in self.bands.iter_mut().zip(gains) {
+            band.set_gain_db(gain);
+        }
+    }
+
+    /// Moves the correction after the analyzer averaged a new frame.
+    fn adapt(&mut self) {
+        let frames = self.analyzer.frames();
+        if frames == self.analyzed {
+            return;
+        }
+        self.analyzed = frames;
+        if let Some((input, target)) = self.analyzer.profile().zip(self.target) {
+            self.correction = input.correction(&target, self.max_gain_db);
+            self.apply_correction();
+        }
+    }
+}
+
+impl Effect for EqMatch {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "EQ Match"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Clears the filters and the input average, keeping the correction
+    /// learnt so far.
+    fn reset(&mut self) {
+        for band in &mut self.bands {
+            band.reset();
+        }
+        self.analyzer.reset();
+        self.analyzed = 0;
+    }
+
+    fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.analyzer = SpectrumAnalyzer::new(sample_rate)
+            .with_time_constant(Duration::from_secs_f32(self.adapt_seconds), sample_rate);
+        for band in &mut self.bands {
+            band.initialize(sample_rate, channels);
+        }
+        self.reset();
+    }
+
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        if !self.enabled {
+            return;
+        }
+        if self.target.is_some() && !self.frozen {
+            self.analyzer.process(samples, channels);
+            self.adapt();
+        }
+        for band in &mut self.bands {
+            band.process(samples, channels);
+        }
+    }
+
+    fn memory_bytes(&self) -> usize {
+        size_of_val(self) + self.analyzer.memory_bytes()
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &self.param_info
+    }
+
+    fn get_parameter(&self, id: ParamId) -> Option<ParamValue> {
+        match id {
+            params::AMOUNT => Some(ParamValue::Float(self.amount)),
+            params::MAX_GAIN => Some(ParamValue::Float(self.max_gain_db)),
+            params::ADAPT => Some(ParamValue::Float(self.adapt_seconds)),
+            params::FREEZE => Some(ParamValue::Bool(self.frozen)),
+            _ => None,
+        }
+    }
+
+    fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool {
+        match id {
+            params::AMOUNT => self.set_amount(value.as_float()),
+            params::MAX_GAIN => self.set_max_gain_db(value.as_float()),
+            params::ADAPT => self.set_adapt_seconds(value.as_float()),
+            params::FREEZE => self.set_frozen(value.as_bool()),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// The correction that makes the WAV file at `source` match the one at
+/// `reference`, limited to `max_db` per band.
+///
+/// # Errors
+/// Returns an error if either file can't be measured; see
+/// [`spectrum::analyze_file`].
+pub fn match_files(
+    source: impl AsRef<Path>,
+    reference: impl AsRef<Path>,
+    max_db: f32,
+) -> Result<[f32; BANDS]> {
+    let target = spectrum::analyze_file(reference)?;
+    Ok(spectrum::analyze_file(source)?.correction(&target, max_db))
+}
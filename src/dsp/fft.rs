@@ -0,0 +1,119 @@
+//! Radix-2 complex FFT
+//!
+//! Twiddles and the bit-reversal table are computed once in [`Fft::new`], so
+//! transforms themselves don't allocate and are safe to run on the audio thread.
+
+use std::f32::consts::TAU;
+
+use crate::buffer::memory::heap_bytes;
+use crate::error::{AudioEngineError, Result};
+
+/// In-place FFT of a fixed power-of-two size on split real/imaginary buffers.
+#[derive(Debug, Clone)]
+pub struct Fft {
+    size: usize,
+    cos: Vec<f32>,
+    sin: Vec<f32>,
+    bit_reverse: Vec<usize>,
+}
+
+impl Fft {
+    /// Prepares a transform of `size` points.
+    ///
+    /// # Errors
+    /// Returns an error if `size` is not a power of two of at least 2.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(size: usize) -> Result<Self> {
+        if size < 2 || !size.is_power_of_two() {
+            return Err(AudioEngineError::configuration(format!(
+                "FFT size {size} must be a power of two"
+            )));
+        }
+        let bits = size.trailing_zeros();
+        let (sin, cos) = (0..size / 2)
+            .map(|k| (TAU * k as f32 / size as f32).sin_cos())
+            .unzip();
+        Ok(Self {
+            size,
+            cos,
+            sin,
+            bit_reverse: (0..size)
+                .map(|i| i.reverse_bits() >> (usize::BITS - bits))
+                .collect(),
+        })
+    }
+
+    #[must_use]
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Bytes of the twiddle and bit-reversal tables
+    #[must_use]
+    pub const fn memory_bytes(&self) -> usize {
+        heap_bytes(&self.cos) + heap_bytes(&self.sin) + heap_bytes(&self.bit_reverse)
+    }
+
+    /// Forward transform (no scaling).
+    ///
+    /// # Panics
+    /// Panics if either buffer is shorter than the FFT size.
+    pub fn forward(&self, re: &mut [f32], im: &mut [f32]) {
+        self.transform(re, im, -1.0);
+    }
+
+    /// Inverse transform, scaled by `1 / size` so a round trip is identity.
+    ///
+    /// # Panics
+    /// Panics if either buffer is shorter than the FFT size.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn inverse(&self, re: &mut [f32], im: &mut [f32]) {
+        self.transform(re, im, 1.0);
+        let scale = 1.0 / self.size as f32;
+        for (r, i) in re[..self.size].iter_mut().zip(&mut im[..self.size]) {
+            *r *= scale;
+            *i *= scale;
+        }
+    }
+
+    fn transform(&self, re: &mut [f32], im: &mut [f32], direction: f32) {
+        let n = self.size;
+        let (re, im) = (&mut re[..n], &mut im[..n]);
+        for (i, &j) in self.bit_reverse.iter().enumerate() {
+            if i < j {
+                re.swap(i, j);
+                im.swap(i, j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= n {
+            let half = len / 2;
+            let stride = n / len;
+            for start in (0..n).step_by(len) {
+                for k in 0..half {
+                    let w_re = self.cos[k * stride];
+                    let w_im = direction * self.sin[k * stride];
+                    let a = start + k;
+                    let b = a + half;
+                    let t_re = re[b].mul_add(w_re, -(im[b] * w_im));
+                    let t_im = re[b].mul_add(w_im, im[b] * w_re);
+                    re[b] = re[a] - t_re;
+                    im[b] = im[a] - t_im;
+                    re[a] += t_re;
+                    im[a] += t_im;
+                }
+            }
+            len *= 2;
+        }
+    }
+}
+
+/// Fills `window` with a periodic Hann window.
+#[allow(clippy::cast_precision_loss)]
+pub fn hann_window(window: &mut [f32]) {
+    let len = window.len() as f32;
+    for (i, w) in window.iter_mut().enumerate() {
+        *w = 0.5f32.mul_add(-(TAU * i as f32 / len).cos(), 0.5);
+    }
+}
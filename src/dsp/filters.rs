@@ -1,8 +1,9 @@
 //! Biquad filter implementation
 use std::f32::consts::PI;
 
-use crate::dsp::params::{ParamId, ParamValue, ParameterInfo, SmoothParam};
-use crate::dsp::traits::{Effect, EffectId};
+use crate::dsp::denormal::flush_denormals;
+use crate::dsp::params::{ParamId, ParamValue, ParameterInfo, SmoothParam, SmoothingMode};
+use crate::dsp::traits::{Effect, EffectId, SmoothableEffect};
 use crate::types::{ChannelCount, Sample, SampleRate};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -11,6 +12,7 @@ pub enum FilterType {
     HighPass,
     BandPass,
     Notch,
+    AllPass,
     Peak,
     LowShelf,
     HighShelf,
@@ -24,7 +26,7 @@ pub mod params {
 }
 
 #[derive(Debug, Clone, Copy, Default)]
-struct BiquadCoeffs {
+pub(crate) struct BiquadCoeffs {
     b0: f32,
     b1: f32,
     b2: f32,
@@ -32,8 +34,115 @@ struct BiquadCoeffs {
     a2: f32,
 }
 
+impl BiquadCoeffs {
+    /// Computes normalized coefficients (RBJ cookbook) for the given response.
+    pub(crate) fn design(
+        filter_type: FilterType,
+        frequency: f32,
+        q: f32,
+        gain: f32,
+        fs: f32,
+    ) -> Self {
+        let freq = frequency.clamp(20.0, fs * 0.49);
+
+        let omega = 2.0 * PI * freq / fs;
+        let sin_omega = omega.sin();
+        let cos_omega = omega.cos();
+        let alpha = sin_omega / (2.0 * q);
+
+        let (b0, b1, b2, a0, a1, a2) = match filter_type {
+            FilterType::LowPass => {
+                let b1 = 1.0 - cos_omega;
+                let b0 = b1 / 2.0;
+                let b2 = b0;
+                let a0 = 1.0 + alpha;
+                let a1 = -2.0 * cos_omega;
+                let a2 = 1.0 - alpha;
+                (b0, b1, b2, a0, a1, a2)
+            }
+            FilterType::HighPass => {
+                let b1 = -(1.0 + cos_omega);
+                let b0 = (1.0 + cos_omega) / 2.0;
+                let b2 = b0;
+                let a0 = 1.0 + alpha;
+                let a1 = -2.0 * cos_omega;
+                let a2 = 1.0 - alpha;
+                (b0, b1, b2, a0, a1, a2)
+            }
+            FilterType::BandPass => {
+                let b0 = alpha;
+                let b1 = 0.0;
+                let b2 = -alpha;
+                let a0 = 1.0 + alpha;
+                let a1 = -2.0 * cos_omega;
+                let a2 = 1.0 - alpha;
+                (b0, b1, b2, a0, a1, a2)
+            }
+            FilterType::Notch => {
+                let b0 = 1.0;
+                let b1 = -2.0 * cos_omega;
+                let b2 = 1.0;
+                let a0 = 1.0 + alpha;
+                let a1 = -2.0 * cos_omega;
+                let a2 = 1.0 - alpha;
+                (b0, b1, b2, a0, a1, a2)
+            }
+            FilterType::AllPass => {
+                let b0 = 1.0 - alpha;
+                let b1 = -2.0 * cos_omega;
+                let b2 = 1.0 + alpha;
+                let a0 = 1.0 + alpha;
+                let a1 = -2.0 * cos_omega;
+                let a2 = 1.0 - alpha;
+                (b0, b1, b2, a0, a1, a2)
+            }
+            FilterType::Peak => {
+                let a = 10.0_f32.powf(gain / 40.0);
+                let b0 = 1.0 + alpha * a;
+                let b1 = -2.0 * cos_omega;
+                let b2 = 1.0 - alpha * a;
+                let a0 = 1.0 + alpha / a;
+                let a1 = -2.0 * cos_omega;
+                let a2 = 1.0 - alpha / a;
+                (b0, b1, b2, a0, a1, a2)
+            }
+            FilterType::LowShelf => {
+                let a = 10.0_f32.powf(gain / 40.0);
+                let sqrt_a = a.sqrt();
+                let b0 = a * ((a + 1.0) - (a - 1.0) * cos_omega + 2.0 * sqrt_a * alpha);
+                let b1 = 2.0 * a * ((a - 1.0) - (a + 1.0) * cos_omega);
+                let b2 = a * ((a + 1.0) - (a - 1.0) * cos_omega - 2.0 * sqrt_a * alpha);
+                let a0 = (a + 1.0) + (a - 1.0) * cos_omega + 2.0 * sqrt_a * alpha;
+                let a1 = -2.0 * ((a - 1.0) + (a + 1.0) * cos_omega);
+                let a2 = (a + 1.0) + (a - 1.0) * cos_omega - 2.0 * sqrt_a * alpha;
+                (b0, b1, b2, a0, a1, a2)
+            }
+            FilterType::HighShelf => {
+                let a = 10.0_f32.powf(gain / 40.0);
+                let sqrt_a = a.sqrt();
+                let b0 = a * ((a + 1.0) + (a - 1.0) * cos_omega + 2.0 * sqrt_a * alpha);
+                let b1 = -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_omega);
+                let b2 = a * ((a + 1.0) + (a - 1.0) * cos_omega - 2.0 * sqrt_a * alpha);
+                let a0 = (a + 1.0) - (a - 1.0) * cos_omega + 2.0 * sqrt_a * alpha;
+                let a1 = 2.0 * ((a - 1.0) - (a + 1.0) * cos_omega);
+                let a2 = (a + 1.0) - (a - 1.0) * cos_omega - 2.0 * sqrt_a * alpha;
+                (b0, b1, b2, a0, a1, a2)
+            }
+        };
+
+        let a0_inv = 1.0 / a0;
+        Self {
+            b0: b0 * a0_inv,
+            b1: b1 * a0_inv,
+            b2: b2 * a0_inv,
+            a1: a1 * a0_inv,
+            a2: a2 * a0_inv,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default)]
-struct BiquadState {
+pub(crate) struct BiquadState {
     x1: f32,
     x2: f32,
     y1: f32,
@@ -41,10 +150,12 @@ struct BiquadState {
 }
 
 impl BiquadState {
-    fn process(&mut self, input: f32, coeffs: &BiquadCoeffs) -> f32 {
-        let output = coeffs.b0 * input + coeffs.b1 * self.x1 + coeffs.b2 * self.x2
-            - coeffs.a1 * self.y1
-            - coeffs.a2 * self.y2;
+    pub(crate) fn process(&mut self, input: f32, coeffs: &BiquadCoeffs) -> f32 {
+        let output = flush_denormals(
+            coeffs.b0 * input + coeffs.b1 * self.x1 + coeffs.b2 * self.x2
+                - coeffs.a1 * self.y1
+                - coeffs.a2 * self.y2,
+        );
 
         self.x2 = self.x1;
         self.x1 = input;
@@ -54,7 +165,7 @@ impl BiquadState {
         output
     }
 
-    fn reset(&mut self) {
+    pub(crate) fn reset(&mut self) {
         self.x1 = 0.0;
         self.x2 = 0.0;
         self.y1 = 0.0;
@@ -147,6 +258,11 @@ impl BiquadFilter {
         Self::with_params(id, FilterType::BandPass, frequency, q, 0.0)
     }
 
+    #[must_use]
+    pub fn all_pass(id: EffectId, frequency: f32, q: f32) -> Self {
+        Self::with_params(id, FilterType::AllPass, frequency, q, 0.0)
+    }
+
     pub fn peak(id: EffectId, frequency: f32, q: f32, gain_db: f32) -> Self {
         Self::with_params(id, FilterType::Peak, frequency, q, gain_db)
     }
@@ -161,115 +277,43 @@ impl BiquadFilter {
 
     pub fn set_frequency(&mut self, frequency: f32) {
         let samples = self.sample_rate.samples_for_milliseconds(10);
+        self.set_frequency_over(frequency, samples);
+    }
+
+    pub fn set_q(&mut self, q: f32) {
+        let samples = self.sample_rate.samples_for_milliseconds(10);
+        self.set_q_over(q, samples);
+    }
+
+    pub fn set_gain_db(&mut self, db: f32) {
+        let samples = self.sample_rate.samples_for_milliseconds(10);
+        self.set_gain_db_over(db, samples);
+    }
+
+    fn set_frequency_over(&mut self, frequency: f32, samples: u32) {
         self.frequency
             .set_target(frequency.clamp(20.0, 20000.0), samples);
         self.coeffs_dirty = true;
     }
 
-    pub fn set_q(&mut self, q: f32) {
-        let samples = self.sample_rate.samples_for_milliseconds(10);
+    fn set_q_over(&mut self, q: f32, samples: u32) {
         self.q.set_target(q.clamp(0.1, 20.0), samples);
         self.coeffs_dirty = true;
     }
 
-    pub fn set_gain_db(&mut self, db: f32) {
-        let samples = self.sample_rate.samples_for_milliseconds(10);
+    fn set_gain_db_over(&mut self, db: f32, samples: u32) {
         self.gain_db.set_target(db.clamp(-24.0, 24.0), samples);
         self.coeffs_dirty = true;
     }
 
     pub fn update_coefficients(&mut self) {
-        let fs = f32::from(u16::try_from(self.sample_rate.as_hz()).unwrap_or(48000));
-
-        let freq = self.frequency.current().clamp(20.0, fs * 0.49);
-        let q = self.q.current();
-        let gain = self.gain_db.current();
-
-        let omega = 2.0 * PI * freq / fs;
-        let sin_omega = omega.sin();
-        let cos_omega = omega.cos();
-        let alpha = sin_omega / (2.0 * q);
-
-        let (b0, b1, b2, a0, a1, a2) = match self.filter_type {
-            FilterType::LowPass => {
-                let b1 = 1.0 - cos_omega;
-                let b0 = b1 / 2.0;
-                let b2 = b0;
-                let a0 = 1.0 + alpha;
-                let a1 = -2.0 * cos_omega;
-                let a2 = 1.0 - alpha;
-                (b0, b1, b2, a0, a1, a2)
-            }
-            FilterType::HighPass => {
-                let b1 = -(1.0 + cos_omega);
-                let b0 = (1.0 + cos_omega) / 2.0;
-                let b2 = b0;
-                let a0 = 1.0 + alpha;
-                let a1 = -2.0 * cos_omega;
-                let a2 = 1.0 - alpha;
-                (b0, b1, b2, a0, a1, a2)
-            }
-            FilterType::BandPass => {
-                let b0 = alpha;
-                let b1 = 0.0;
-                let b2 = -alpha;
-                let a0 = 1.0 + alpha;
-                let a1 = -2.0 * cos_omega;
-                let a2 = 1.0 - alpha;
-                (b0, b1, b2, a0, a1, a2)
-            }
-            FilterType::Notch => {
-                let b0 = 1.0;
-                let b1 = -2.0 * cos_omega;
-                let b2 = 1.0;
-                let a0 = 1.0 + alpha;
-                let a1 = -2.0 * cos_omega;
-                let a2 = 1.0 - alpha;
-                (b0, b1, b2, a0, a1, a2)
-            }
-            FilterType::Peak => {
-                let a = 10.0_f32.powf(gain / 40.0);
-                let b0 = 1.0 + alpha * a;
-                let b1 = -2.0 * cos_omega;
-                let b2 = 1.0 - alpha * a;
-                let a0 = 1.0 + alpha / a;
-                let a1 = -2.0 * cos_omega;
-                let a2 = 1.0 - alpha / a;
-                (b0, b1, b2, a0, a1, a2)
-            }
-            FilterType::LowShelf => {
-                let a = 10.0_f32.powf(gain / 40.0);
-                let sqrt_a = a.sqrt();
-                let b0 = a * ((a + 1.0) - (a - 1.0) * cos_omega + 2.0 * sqrt_a * alpha);
-                let b1 = 2.0 * a * ((a - 1.0) - (a + 1.0) * cos_omega);
-                let b2 = a * ((a + 1.0) - (a - 1.0) * cos_omega - 2.0 * sqrt_a * alpha);
-                let a0 = (a + 1.0) + (a - 1.0) * cos_omega + 2.0 * sqrt_a * alpha;
-                let a1 = -2.0 * ((a - 1.0) + (a + 1.0) * cos_omega);
-                let a2 = (a + 1.0) + (a - 1.0) * cos_omega - 2.0 * sqrt_a * alpha;
-                (b0, b1, b2, a0, a1, a2)
-            }
-            FilterType::HighShelf => {
-                let a = 10.0_f32.powf(gain / 40.0);
-                let sqrt_a = a.sqrt();
-                let b0 = a * ((a + 1.0) + (a - 1.0) * cos_omega + 2.0 * sqrt_a * alpha);
-                let b1 = -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_omega);
-                let b2 = a * ((a + 1.0) + (a - 1.0) * cos_omega - 2.0 * sqrt_a * alpha);
-                let a0 = (a + 1.0) - (a - 1.0) * cos_omega + 2.0 * sqrt_a * alpha;
-                let a1 = 2.0 * ((a - 1.0) - (a + 1.0) * cos_omega);
-                let a2 = (a + 1.0) - (a - 1.0) * cos_omega - 2.0 * sqrt_a * alpha;
-                (b0, b1, b2, a0, a1, a2)
-            }
-        };
-
-        let a0_inv = 1.0 / a0;
-        self.coeffs = BiquadCoeffs {
-            b0: b0 * a0_inv,
-            b1: b1 * a0_inv,
-            b2: b2 * a0_inv,
-            a1: a1 * a0_inv,
-            a2: a2 * a0_inv,
-        };
-
+        self.coeffs = BiquadCoeffs::design(
+            self.filter_type,
+            self.frequency.current(),
+            self.q.current(),
+            self.gain_db.current(),
+            self.sample_rate.as_f32(),
+        );
         self.coeffs_dirty = false;
     }
 }
@@ -285,6 +329,7 @@ impl Effect for BiquadFilter {
             FilterType::HighPass => "High Pass",
             FilterType::BandPass => "Band Pass",
             FilterType::Notch => "Notch",
+            FilterType::AllPass => "All Pass",
             FilterType::Peak => "Peak",
             FilterType::LowShelf => "Low Shelf",
             FilterType::HighShelf => "High Shelf",
@@ -371,3 +416,33 @@ impl Effect for BiquadFilter {
         }
     }
 }
+
+impl SmoothableEffect for BiquadFilter {
+    fn set_parameter_smooth(&mut self, id: ParamId, value: ParamValue, samples: u32) {
+        match id {
+            params::FREQUENCY => self.set_frequency_over(value.as_float(), samples),
+            params::Q => self.set_q_over(value.as_float(), samples),
+            params::GAIN_DB => self.set_gain_db_over(value.as_float(), samples),
+            _ => {}
+        }
+    }
+
+    fn update_smoothing(&mut self) {
+        if self.frequency.is_smoothing() || self.q.is_smoothing() || self.gain_db.is_smoothing() {
+            self.frequency.advance(1);
+            self.q.advance(1);
+            self.gain_db.advance(1);
+            self.update_coefficients();
+        }
+    }
+
+    fn set_smoothing_mode(&mut self, id: ParamId, mode: SmoothingMode) -> bool {
+        match id {
+            params::FREQUENCY => self.frequency.set_mode(mode),
+            params::Q => self.q.set_mode(mode),
+            params::GAIN_DB => self.gain_db.set_mode(mode),
+            _ => return false,
+        }
+        true
+    }
+}
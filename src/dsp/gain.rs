@@ -1,7 +1,7 @@
 //! Gain effect
 
-use crate::dsp::params::{ParamId, ParamValue, ParameterInfo, SmoothParam};
-use crate::dsp::traits::{Effect, EffectId};
+use crate::dsp::params::{ParamId, ParamValue, ParameterInfo, SmoothParam, SmoothingMode};
+use crate::dsp::traits::{Effect, EffectId, SmoothableEffect};
 use crate::types::{ChannelCount, Gain, Sample, SampleRate};
 
 pub mod params {
@@ -45,8 +45,12 @@ impl GainEffect {
     }
 
     pub fn set_gain_db(&mut self, db: f32) {
-        let gain = Gain::from_db(db);
         let samples = self.sample_rate.samples_for_milliseconds(10);
+        self.set_gain_db_over(db, samples);
+    }
+
+    fn set_gain_db_over(&mut self, db: f32, samples: u32) {
+        let gain = Gain::from_db(db);
         self.gain.set_target(gain.as_linear(), samples);
     }
 
@@ -113,3 +117,23 @@ impl Effect for GainEffect {
         }
     }
 }
+
+impl SmoothableEffect for GainEffect {
+    fn set_parameter_smooth(&mut self, id: ParamId, value: ParamValue, samples: u32) {
+        if id == params::GAIN_DB {
+            self.set_gain_db_over(value.as_float(), samples);
+        }
+    }
+
+    fn update_smoothing(&mut self) {
+        self.gain.advance(1);
+    }
+
+    fn set_smoothing_mode(&mut self, id: ParamId, mode: SmoothingMode) -> bool {
+        if id == params::GAIN_DB {
+            self.gain.set_mode(mode);
+            return true;
+        }
+        false
+    }
+}
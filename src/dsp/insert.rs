@@ -0,0 +1,437 @@
+//! External hardware inserts
+//!
+//! A [`HardwareInsert`] sits in a chain like any other effect, but sends
+//! the audio out to physical outputs and plays back what arrives on
+//! physical inputs, so an outboard compressor or reverb can be patched into
+//! a bus. The round trip through the converters and the processor delays
+//! the return, so the insert measures it with a latency ping: a single
+//! click is sent, and the frames until it comes back become the insert's
+//! [`latency_samples`](Effect::latency_samples), which the host uses to
+//! keep the chain time-aligned.
+//!
+//! A ping runs when the insert is initialized, and again on request. The
+//! insert is muted while pinging.
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::audio::device::AudioDevice;
+use crate::audio::stream::{AudioInputStream, AudioOutputStream, StreamHandle};
+use crate::buffer::{RingBufferReader, RingBufferWriter};
+use crate::dsp::params::{ParamId, ParamKind, ParamValue, ParameterInfo, SmoothParam};
+use crate::dsp::traits::{Effect, EffectId};
+use crate::error::Result;
+use crate::types::{AudioFormat, ChannelCount, Gain, Sample, SampleRate};
+
+pub mod params {
+    use crate::dsp::params::ParamId;
+    pub const SEND_GAIN_DB: ParamId = ParamId::new(0);
+    pub const RETURN_GAIN_DB: ParamId = ParamId::new(1);
+    /// Setting it to true starts a latency ping
+    pub const PING: ParamId = ParamId::new(2);
+    /// Measured round trip in frames; read only
+    pub const LATENCY: ParamId = ParamId::new(3);
+}
+
+/// Level of the click sent by a ping
+const PING_LEVEL: f32 = 0.5;
+/// A returned sample above this level is taken as the click
+const PING_THRESHOLD: f32 = 0.05;
+/// How long a ping waits for the click before giving up
+const PING_TIMEOUT_MS: u32 = 1000;
+/// Stored in the shared latency until a ping succeeds
+const UNMEASURED: u32 = u32::MAX;
+
+/// Control thread view of an insert's latency measurement.
+#[derive(Debug, Clone)]
+pub struct InsertLatency {
+    frames: Arc<AtomicU32>,
+    pinging: Arc<AtomicBool>,
+}
+
+impl InsertLatency {
+    fn new() -> Self {
+        Self {
+            frames: Arc::new(AtomicU32::new(UNMEASURED)),
+            pinging: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Round trip from the last successful ping, in frames
+    #[must_use]
+    pub fn frames(&self) -> Option<u32> {
+        let frames = self.frames.load(Ordering::Acquire);
+        (frames != UNMEASURED).then_some(frames)
+    }
+
+    #[must_use]
+    pub fn is_pinging(&self) -> bool {
+        self.pinging.load(Ordering::Acquire)
+    }
+}
+
+/// Streams carrying an insert's send and return. Keep them alive, and
+/// started, for as long as the insert is in use.
+pub struct InsertStreams {
+    pub send: StreamHandle,
+    pub ret: StreamHandle,
+}
+
+impl InsertStreams {
+    /// Starts both streams.
+    ///
+    /// # Errors
+    /// Returns an error if either stream can't be started.
+    pub fn start(&self) -> Result<()> {
+        self.ret.play()?;
+        self.send.play()
+    }
+
+    /// Pauses both streams.
+    ///
+    /// # Errors
+    /// Returns an error if either stream can't be paused.
+    pub fn pause(&self) -> Result<()> {
+        self.send.pause()?;
+        self.ret.pause()
+    }
+
+    /// Closes both streams, once the insert is out of use.
+    ///
+    /// # Errors
+    /// Returns an error if either stream couldn't be paused; both are
+    /// closed all the same.
+    pub fn close(self) -> Result<()> {
+        let send = self.send.close();
+        let ret = self.ret.close();
+        send.and(ret)
+    }
+}
+
+impl fmt::Debug for InsertStreams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InsertStreams")
+            .field("send", &self.send.format())
+            .field("ret", &self.ret.format())
+            .finish()
+    }
+}
+
+/// Where a ping is up to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ping {
+    Idle,
+    /// Waiting for the click, `elapsed` frames after it was sent
+    Waiting {
+        elapsed: u32,
+    },
+}
+
+/// Routes a chain out through external hardware and back.
+pub struct HardwareInsert {
+    id: EffectId,
+    enabled: bool,
+    send: RingBufferWriter<Sample>,
+    ret: RingBufferReader<Sample>,
+    send_gain: SmoothParam,
+    return_gain: SmoothParam,
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    auto_ping: bool,
+    ping: Ping,
+    latency: u32,
+    shared: InsertLatency,
+    param_info: Vec<ParameterInfo>,
+}
+
+impl HardwareInsert {
+    /// Creates an insert on existing ring buffers: `send` is read by
+    /// whatever plays the physical outputs and `ret` is filled from the
+    /// physical inputs, both interleaved in the chain's channel layout.
+    #[must_use]
+    pub fn new(
+        id: EffectId,
+        send: RingBufferWriter<Sample>,
+        ret: RingBufferReader<Sample>,
+    ) -> Self {
+        let param_info = vec![
+            ParameterInfo::new(params::SEND_GAIN_DB, "Send Level")
+                .with_short_name("Send")
+                .with_range(-80.0, 24.0)
+                .with_default(0.0)
+                .with_unit("dB")
+                .with_precision(1),
+            ParameterInfo::new(params::RETURN_GAIN_DB, "Return Level")
+                .with_short_name("Return")
+                .with_range(-80.0, 24.0)
+                .with_default(0.0)
+                .with_unit("dB")
+                .with_precision(1),
+            ParameterInfo::new(params::PING, "Ping")
+                .with_short_name("Ping")
+                .with_default(0.0)
+                .with_kind(ParamKind::Bool),
+            ParameterInfo::new(params::LATENCY, "Latency")
+                .with_short_name("Latency")
+                .with_range(0.0, f32::from(u16::MAX))
+                .with_default(0.0)
+                .with_unit("smp")
+                .with_kind(ParamKind::Int),
+        ];
+
+        Self {
+            id,
+            enabled: true,
+            send,
+            ret,
+            send_gain: SmoothParam::new(1.0),
+            return_gain: SmoothParam::new(1.0),
+            sample_rate: SampleRate::Hz48000,
+            channels: ChannelCount::Stereo,
+            auto_ping: true,
+            ping: Ping::Idle,
+            latency: 0,
+            shared: InsertLatency::new(),
+            param_info,
+        }
+    }
+
+    /// Opens an output stream for the send and an input stream for the
+    /// return, both in `format`, and creates an insert on them.
+    ///
+    /// # Errors
+    /// Returns an error if either stream can't be created.
+    pub fn open(
+        id: EffectId,
+        output: &AudioDevice,
+        input: &AudioDevice,
+        format: AudioFormat,
+        buffer_frames: usize,
+    ) -> Result<(Self, InsertStreams)> {
+        let (send_handle, send) =
+            AudioOutputStream::new(output, format, buffer_frames)?.into_parts();
+        let (ret_handle, ret) =
+            AudioInputStream::new(input, format, buffer_frames * 4)?.into_parts();
+        let streams = InsertStreams {
+            send: send_handle,
+            ret: ret_handle,
+        };
+        Ok((Self::new(id, send, ret), streams))
+    }
+
+    /// Whether initializing the insert starts a ping; on by default
+    #[must_use]
+    pub const fn with_auto_ping(mut self, auto_ping: bool) -> Self {
+        self.auto_ping = auto_ping;
+        self
+    }
+
+    /// Uses a known round trip instead of pinging.
+    #[must_use]
+    pub fn with_latency(mut self, frames: u32) -> Self {
+        self.auto_ping = false;
+        self.set_latency(frames);
+        self
+    }
+
+    /// Handle for reading the measured latency from another thread
+    #[must_use]
+    pub fn latency_handle(&self) -> InsertLatency {
+        self.shared.clone()
+    }
+
+    /// Sends a click and measures how long it takes to come back. The
+    /// insert is muted until it does, or for a second at most.
+    pub fn ping(&mut self) {
+        // Drop whole frames of stale return, so the click isn't matched
+        // against audio sent before it
+        let channels = self.channels.count_usize();
+        self.ret.discard(self.ret.slots() / channels * channels);
+        self.ping = Ping::Waiting { elapsed: 0 };
+        self.shared.pinging.store(true, Ordering::Release);
+    }
+
+    #[must_use]
+    pub const fn is_pinging(&self) -> bool {
+        matches!(self.ping, Ping::Waiting { .. })
+    }
+
+    pub fn set_send_gain_db(&mut self, db: f32) {
+        let samples = self.sample_rate.samples_for_milliseconds(10);
+        self.send_gain
+            .set_target(Gain::from_db(db).as_linear(), samples);
+    }
+
+    pub fn set_return_gain_db(&mut self, db: f32) {
+        let samples = self.sample_rate.samples_for_milliseconds(10);
+        self.return_gain
+            .set_target(Gain::from_db(db).as_linear(), samples);
+    }
+
+    #[must_use]
+    pub fn send_gain_db(&self) -> f32 {
+        Gain::new(self.send_gain.target()).as_db()
+    }
+
+    #[must_use]
+    pub fn return_gain_db(&self) -> f32 {
+        Gain::new(self.return_gain.target()).as_db()
+    }
+
+    fn set_latency(&mut self, frames: u32) {
+        self.latency = frames;
+        self.shared.frames.store(frames, Ordering::Release);
+    }
+
+    /// Sends the click, then looks for it in the return. Output is silent.
+    fn process_ping(&mut self, samples: &mut [Sample], elapsed: u32) {
+        let channel_count = self.channels.count_usize();
+        samples.fill(Sample::SILENCE);
+        if elapsed == 0 {
+            samples[..channel_count].fill(Sample::new(PING_LEVEL));
+        }
+        self.send.push_slice(samples);
+        let read = self.ret.pop_slice(samples);
+        samples[read..].fill(Sample::SILENCE);
+
+        let found = samples
+            .chunks_exact(channel_count)
+            .position(|frame| frame.iter().any(|s| s.value().abs() > PING_THRESHOLD));
+        samples.fill(Sample::SILENCE);
+        #[allow(clippy::cast_possible_truncation)]
+        let frames = (samples.len() / channel_count) as u32;
+        if let Some(frame) = found {
+            #[allow(clippy::cast_possible_truncation)]
+            self.set_latency(elapsed + frame as u32);
+            self.ping = Ping::Idle;
+        } else if elapsed + frames > self.sample_rate.samples_for_milliseconds(PING_TIMEOUT_MS) {
+            // Nothing came back: keep the previous measurement
+            self.ping = Ping::Idle;
+        } else {
+            self.ping = Ping::Waiting {
+                elapsed: elapsed + frames,
+            };
+        }
+        if self.ping == Ping::Idle {
+            self.shared.pinging.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl Effect for HardwareInsert {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "Hardware Insert"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn reset(&mut self) {
+        self.send_gain.set_immediate(self.send_gain.target());
+        self.return_gain.set_immediate(self.return_gain.target());
+    }
+
+    fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        self.reset();
+        if self.auto_ping {
+            self.ping();
+        }
+    }
+
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        if !self.enabled {
+            // Keep the return from backing up while bypassed
+            let channel_count = channels.count_usize();
+            self.ret
+                .discard(self.ret.slots() / channel_count * channel_count);
+            return;
+        }
+        if let Ping::Waiting { elapsed } = self.ping {
+            self.process_ping(samples, elapsed);
+            return;
+        }
+
+        for sample in samples.iter_mut() {
+            *sample = Sample::new(sample.value() * self.send_gain.next());
+        }
+        self.send.push_slice(samples);
+        let read = self.ret.pop_slice(samples);
+        samples[read..].fill(Sample::SILENCE);
+        for sample in samples.iter_mut() {
+            *sample = Sample::new(sample.value() * self.return_gain.next());
+        }
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &self.param_info
+    }
+
+    fn get_parameter(&self, id: ParamId) -> Option<ParamValue> {
+        match id {
+            params::SEND_GAIN_DB => Some(ParamValue::Float(self.send_gain_db())),
+            params::RETURN_GAIN_DB => Some(ParamValue::Float(self.return_gain_db())),
+            params::PING => Some(ParamValue::Bool(self.is_pinging())),
+            params::LATENCY => Some(ParamValue::Int(
+                i32::try_from(self.latency).unwrap_or(i32::MAX),
+            )),
+            _ => None,
+        }
+    }
+
+    fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool {
+        match id {
+            params::SEND_GAIN_DB => {
+                self.set_send_gain_db(value.as_float());
+                true
+            }
+            params::RETURN_GAIN_DB => {
+                self.set_return_gain_db(value.as_float());
+                true
+            }
+            params::PING => {
+                if value.as_bool() {
+                    self.ping();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn latency_samples(&self) -> u32 {
+        self.latency
+    }
+
+    /// Counts both rings, since the insert is the only user of them.
+    fn memory_bytes(&self) -> usize {
+        size_of_val(self) + (self.send.capacity() + self.ret.capacity()) * size_of::<Sample>()
+    }
+}
+
+impl fmt::Debug for HardwareInsert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HardwareInsert")
+            .field("id", &self.id)
+            .field("enabled", &self.enabled)
+            .field("send_gain", &self.send_gain)
+            .field("return_gain", &self.return_gain)
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("auto_ping", &self.auto_ping)
+            .field("ping", &self.ping)
+            .field("latency", &self.latency)
+            .finish_non_exhaustive()
+    }
+}
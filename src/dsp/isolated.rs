@@ -0,0 +1,522 @@
+//! Out-of-process effects
+//!
+//! An [`IsolatedEffect`] runs its processing in a child process, such as
+//! a host for a third-party plugin, so a plugin that crashes or locks up
+//! takes down only its own process. The effect then passes its input
+//! through, the chain bypasses it, and the engine reports
+//! [`EngineFeedback::EffectFailed`].
+//!
+//! Audio travels over the child's stdin and stdout. Shared memory would
+//! save a copy, but mapping it takes unsafe code, which the crate doesn't
+//! allow. The pipes are served by a worker thread, never the audio
+//! thread, so the round trip delays the output by the effect's
+//! [`latency_samples`](Effect::latency_samples), which must cover at least
+//! one block.
+//!
+//! The child reads messages from stdin, all numbers little-endian:
+//!
+//! | Message | Layout |
+//! |---------|--------|
+//! | Initialize | `b'I'`, sample rate `u32`, channel count `u32` |
+//! | Audio | `b'A'`, frame count `u32`, interleaved `f32` samples |
+//! | Parameter | `b'P'`, parameter id `u32`, value `f32` |
+//! | Reset | `b'R'` |
+//!
+//! and answers every audio message with as many `f32` samples on stdout.
+//! Its stderr is passed through.
+//!
+//! ```no_run
+//! use std::process::Command;
+//!
+//! use audio_engine::dsp::isolated::IsolatedEffect;
+//! use audio_engine::dsp::traits::EffectId;
+//!
+//! let mut host = Command::new("plugin-host");
+//! host.arg("Reverb.clap");
+//! let reverb = IsolatedEffect::spawn(EffectId::new(1), "Reverb", host)?.with_latency(512);
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! [`EngineFeedback::EffectFailed`]: crate::channel::EngineFeedback::EffectFailed
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::buffer::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::dsp::params::{ParamId, ParamValue, ParameterInfo};
+use crate::dsp::traits::{Effect, EffectId};
+use crate::error::{AudioEngineError, Result};
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+/// Default delay through the child, in frames
+const DEFAULT_LATENCY_FRAMES: u32 = 1024;
+/// Default time the child may leave the output starved before it counts
+/// as hung
+const DEFAULT_HANG_TIMEOUT_MS: u32 = 1000;
+/// Most frames sent to the child in one audio message
+const MAX_CHUNK_FRAMES: usize = 1024;
+/// Parameter changes and resets that can wait for the worker
+const CONTROL_CAPACITY: usize = 256;
+/// How long the worker sleeps with nothing to send
+const IDLE_INTERVAL: Duration = Duration::from_millis(1);
+/// How often the supervisor checks on the child
+const SUPERVISE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Why an isolated effect failed
+const RUNNING: u8 = 0;
+const HUNG: u8 = 1;
+const EXITED: u8 = 2;
+const BROKEN_PIPE: u8 = 3;
+
+/// A message for the child that the audio thread queues.
+#[derive(Debug, Clone, Copy)]
+enum Control {
+    Parameter(ParamId, f32),
+    Reset,
+}
+
+/// Rings between the audio thread and the worker, made for one format.
+struct Link {
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    send: RingBufferReader<Sample>,
+    ret: RingBufferWriter<Sample>,
+}
+
+/// State shared by the effect and its threads.
+#[derive(Debug)]
+struct Shared {
+    name: String,
+    stop: AtomicBool,
+    failure: AtomicU8,
+}
+
+impl Shared {
+    /// Records the first failure; later ones are consequences of it.
+    fn fail(&self, reason: u8) {
+        let _ = self
+            .failure
+            .compare_exchange(RUNNING, reason, Ordering::AcqRel, Ordering::Acquire);
+    }
+
+    fn has_failed(&self) -> bool {
+        self.failure.load(Ordering::Acquire) != RUNNING
+    }
+}
+
+/// An effect processed by a child process.
+pub struct IsolatedEffect {
+    id: EffectId,
+    enabled: bool,
+    latency: u32,
+    hang_timeout_ms: u32,
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    send: Option<RingBufferWriter<Sample>>,
+    ret: Option<RingBufferReader<Sample>>,
+    controls: RingBufferWriter<Control>,
+    links: flume::Sender<Link>,
+    /// Frames in a row the child left without output
+    starved: u32,
+    reported: bool,
+    shared: Arc<Shared>,
+    param_info: Vec<ParameterInfo>,
+    values: Vec<ParamValue>,
+    worker: Option<JoinHandle<()>>,
+    supervisor: Option<JoinHandle<()>>,
+}
+
+impl IsolatedEffect {
+    /// Starts `command` as the effect's process, with its stdin and stdout
+    /// taken for the audio.
+    ///
+    /// # Errors
+    /// Returns an error if the process or the threads serving it can't be
+    /// started.
+    pub fn spawn(id: EffectId, name: impl Into<String>, mut command: Command) -> Result<Self> {
+        let name = name.into();
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| AudioEngineError::configuration(format!("can't start {name}: {e}")))?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            let _ = child.kill();
+            return Err(AudioEngineError::configuration(format!(
+                "{name} has no pipes"
+            )));
+        };
+
+        let shared = Arc::new(Shared {
+            name,
+            stop: AtomicBool::new(false),
+            failure: AtomicU8::new(RUNNING),
+        });
+        let (controls, control_reader) = RingBuffer::new(CONTROL_CAPACITY);
+        let (links, link_receiver) = flume::unbounded();
+        let supervisor = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name("isolated-supervisor".to_string())
+                .spawn(move || supervise(&mut child, &shared))
+        };
+        let supervisor = supervisor.map_err(|e| {
+            shared.stop.store(true, Ordering::Release);
+            AudioEngineError::configuration(format!("can't start supervisor: {e}"))
+        })?;
+        let worker = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name("isolated-worker".to_string())
+                .spawn(move || {
+                    let mut worker = Worker {
+                        stdin,
+                        stdout,
+                        controls: control_reader,
+                        links: link_receiver,
+                        link: None,
+                        samples: vec![Sample::SILENCE; MAX_CHUNK_FRAMES * 8],
+                        bytes: Vec::new(),
+                    };
+                    if let Err(e) = worker.run(&shared) {
+                        if !shared.stop.load(Ordering::Acquire) && !shared.has_failed() {
+                            log::error!("{}: {e}", shared.name);
+                        }
+                        shared.fail(BROKEN_PIPE);
+                    }
+                })
+        };
+        let worker = worker.map_err(|e| {
+            shared.stop.store(true, Ordering::Release);
+            AudioEngineError::configuration(format!("can't start worker: {e}"))
+        })?;
+
+        Ok(Self {
+            id,
+            enabled: true,
+            latency: DEFAULT_LATENCY_FRAMES,
+            hang_timeout_ms: DEFAULT_HANG_TIMEOUT_MS,
+            sample_rate: SampleRate::Hz48000,
+            channels: ChannelCount::Stereo,
+            send: None,
+            ret: None,
+            controls,
+            links,
+            starved: 0,
+            reported: false,
+            shared,
+            param_info: Vec::new(),
+            values: Vec::new(),
+            worker: Some(worker),
+            supervisor: Some(supervisor),
+        })
+    }
+
+    /// Sets the delay through the child, which must cover the largest
+    /// block the chain processes. Takes effect when the effect is next
+    /// initialized.
+    #[must_use]
+    pub const fn with_latency(mut self, frames: u32) -> Self {
+        self.latency = frames;
+        self
+    }
+
+    /// Sets how long the child may leave the output starved before it
+    /// counts as hung and is killed.
+    #[must_use]
+    pub const fn with_hang_timeout_ms(mut self, ms: u32) -> Self {
+        self.hang_timeout_ms = ms;
+        self
+    }
+
+    /// Declares the child's parameters. Changes are forwarded to it, and
+    /// the last value set is what the effect reports.
+    #[must_use]
+    pub fn with_parameters(mut self, parameters: Vec<ParameterInfo>) -> Self {
+        self.values = parameters
+            .iter()
+            .map(|info| ParamValue::Float(info.default))
+            .collect();
+        self.param_info = parameters;
+        self
+    }
+
+    /// Whether the child has crashed, exited or hung
+    #[must_use]
+    pub fn has_failed(&self) -> bool {
+        self.shared.has_failed()
+    }
+
+    fn control(&mut self, control: Control) {
+        if !self.has_failed() && self.controls.push(control).is_err() {
+            log::warn!("{}: control queue full", self.shared.name);
+        }
+    }
+}
+
+impl Effect for IsolatedEffect {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.shared.name
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn reset(&mut self) {
+        self.control(Control::Reset);
+    }
+
+    fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        let latency = self.latency as usize;
+        let capacity = (latency * 4).max(MAX_CHUNK_FRAMES * 8) * channels.count_usize();
+        let (send, send_reader) = RingBuffer::new(capacity);
+        let (mut ret_writer, ret) = RingBuffer::new(capacity);
+        // Silence for the audio thread to play while the first block
+        // makes the round trip
+        ret_writer.push_slice(&vec![Sample::SILENCE; latency * channels.count_usize()]);
+        let link = Link {
+            sample_rate,
+            channels,
+            send: send_reader,
+            ret: ret_writer,
+        };
+        if self.links.send(link).is_ok() {
+            self.send = Some(send);
+            self.ret = Some(ret);
+        }
+        self.starved = 0;
+    }
+
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        let (Some(send), Some(ret)) = (&mut self.send, &mut self.ret) else {
+            return;
+        };
+        let channel_count = channels.count_usize();
+        if self.shared.has_failed() {
+            return;
+        }
+        if !self.enabled {
+            // Keep the return from backing up while bypassed
+            ret.discard(ret.slots() / channel_count * channel_count);
+            return;
+        }
+
+        send.push_slice(samples);
+        let read = ret.pop_slice(samples);
+        samples[read..].fill(Sample::SILENCE);
+        if read == samples.len() {
+            self.starved = 0;
+            return;
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let missing = ((samples.len() - read) / channel_count) as u32;
+        self.starved = self.starved.saturating_add(missing);
+        if self.starved
+            > self
+                .sample_rate
+                .samples_for_milliseconds(self.hang_timeout_ms)
+        {
+            self.shared.fail(HUNG);
+        }
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &self.param_info
+    }
+
+    fn get_parameter(&self, id: ParamId) -> Option<ParamValue> {
+        let index = self.param_info.iter().position(|info| info.id == id)?;
+        Some(self.values[index])
+    }
+
+    fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool {
+        let Some(index) = self.param_info.iter().position(|info| info.id == id) else {
+            return false;
+        };
+        self.values[index] = value;
+        self.control(Control::Parameter(id, value.as_float()));
+        true
+    }
+
+    fn latency_samples(&self) -> u32 {
+        self.latency
+    }
+
+    fn take_failure(&mut self) -> bool {
+        if self.reported || !self.shared.has_failed() {
+            return false;
+        }
+        self.reported = true;
+        true
+    }
+
+    /// Counts the rings, since the effect is the only user of them.
+    fn memory_bytes(&self) -> usize {
+        let rings = self.send.as_ref().map_or(0, RingBufferWriter::capacity)
+            + self.ret.as_ref().map_or(0, RingBufferReader::capacity);
+        size_of_val(self)
+            + rings * size_of::<Sample>()
+            + self.controls.capacity() * size_of::<Control>()
+    }
+}
+
+impl Drop for IsolatedEffect {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        // The supervisor kills the child, which unblocks the worker
+        for thread in [self.supervisor.take(), self.worker.take()]
+            .into_iter()
+            .flatten()
+        {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for IsolatedEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IsolatedEffect")
+            .field("id", &self.id)
+            .field("name", &self.shared.name)
+            .field("enabled", &self.enabled)
+            .field("latency", &self.latency)
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("failed", &self.has_failed())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Watches the child until the effect is dropped or the child fails, then
+/// kills it.
+fn supervise(child: &mut Child, shared: &Shared) {
+    loop {
+        thread::sleep(SUPERVISE_INTERVAL);
+        if shared.stop.load(Ordering::Acquire) {
+            break;
+        }
+        match shared.failure.load(Ordering::Acquire) {
+            HUNG => {
+                log::error!("{}: stopped responding, killing it", shared.name);
+                break;
+            }
+            RUNNING => {}
+            _ => break,
+        }
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                log::error!("{}: process exited ({status})", shared.name);
+                shared.fail(EXITED);
+                break;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::error!("{}: can't check on process: {e}", shared.name);
+                shared.fail(EXITED);
+                break;
+            }
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Moves audio and controls between the rings and the child's pipes.
+struct Worker {
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    controls: RingBufferReader<Control>,
+    links: flume::Receiver<Link>,
+    link: Option<Link>,
+    samples: Vec<Sample>,
+    bytes: Vec<u8>,
+}
+
+impl Worker {
+    fn run(&mut self, shared: &Shared) -> std::io::Result<()> {
+        while !shared.stop.load(Ordering::Acquire) && !shared.has_failed() {
+            while let Ok(link) = self.links.try_recv() {
+                self.bytes.clear();
+                self.bytes.push(b'I');
+                self.bytes
+                    .extend_from_slice(&link.sample_rate.as_hz().to_le_bytes());
+                self.bytes
+                    .extend_from_slice(&link.channels.count().to_le_bytes());
+                self.stdin.write_all(&self.bytes)?;
+                self.link = Some(link);
+            }
+            while let Ok(control) = self.controls.pop() {
+                self.bytes.clear();
+                match control {
+                    Control::Parameter(id, value) => {
+                        self.bytes.push(b'P');
+                        self.bytes.extend_from_slice(&id.value().to_le_bytes());
+                        self.bytes.extend_from_slice(&value.to_le_bytes());
+                    }
+                    Control::Reset => self.bytes.push(b'R'),
+                }
+                self.stdin.write_all(&self.bytes)?;
+            }
+            if !self.exchange()? {
+                thread::sleep(IDLE_INTERVAL);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the child the next chunk of input and passes on its answer.
+    /// Returns false if there was no input.
+    fn exchange(&mut self) -> std::io::Result<bool> {
+        let Some(link) = &mut self.link else {
+            return Ok(false);
+        };
+        let channel_count = link.channels.count_usize();
+        let frames = (link.send.slots() / channel_count).min(MAX_CHUNK_FRAMES);
+        if frames == 0 {
+            return Ok(false);
+        }
+        let len = frames * channel_count;
+        if self.samples.len() < len {
+            self.samples.resize(len, Sample::SILENCE);
+        }
+        let samples = &mut self.samples[..len];
+        link.send.pop_slice(samples);
+
+        self.bytes.clear();
+        self.bytes.push(b'A');
+        #[allow(clippy::cast_possible_truncation)]
+        self.bytes.extend_from_slice(&(frames as u32).to_le_bytes());
+        for sample in samples.iter() {
+            self.bytes.extend_from_slice(&sample.value().to_le_bytes());
+        }
+        self.stdin.write_all(&self.bytes)?;
+
+        self.bytes.resize(len * size_of::<f32>(), 0);
+        self.stdout.read_exact(&mut self.bytes)?;
+        for (sample, bytes) in samples
+            .iter_mut()
+            .zip(self.bytes.chunks_exact(size_of::<f32>()))
+        {
+            *sample = Sample::new(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+        }
+        link.ret.push_slice(samples);
+        Ok(true)
+    }
+}
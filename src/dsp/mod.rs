@@ -1,7 +1,30 @@
 //! Digital Signal Processing
 
+pub mod auto_gain;
+pub mod automation;
+pub mod bit_crusher;
+pub mod chain;
+pub mod channel_align;
+pub mod channels;
+pub mod crossfade;
+pub mod crossover;
+pub mod dc_blocker;
+pub mod denormal;
+pub mod eq_match;
+pub mod fft;
 pub mod filters;
 pub mod gain;
+pub mod insert;
+pub mod isolated;
+pub mod modulation;
+pub mod names;
 pub mod pan;
 pub mod params;
+pub mod pitch_shift;
+pub mod preset;
+pub mod quality;
+pub mod random;
+pub mod scrub;
+pub mod time_stretch;
 pub mod traits;
+pub mod trim;
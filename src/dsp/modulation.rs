@@ -0,0 +1,494 @@
+//! Modulation matrix
+//!
+//! Routes modulation sources (LFOs, envelope followers, random sample and hold)
+//! with a depth to any `(EffectId, ParamId)` in an [`EffectChain`].
+//! Sources are evaluated once per block and all storage is allocated up front,
+//! so [`ModulationMatrix::process`] is safe to call on the RT thread.
+
+use std::f32::consts::TAU;
+use std::fmt;
+
+use crate::dsp::chain::EffectChain;
+use crate::dsp::params::{ParamId, ParamValue};
+use crate::dsp::random::{Rng, derive_seed};
+use crate::dsp::traits::{EffectId, ProcessContext};
+use crate::error::{AudioEngineError, Result};
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModSourceId(u32);
+
+impl ModSourceId {
+    #[must_use]
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    const fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for ModSourceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ModSource#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+}
+
+/// Low frequency oscillator, bipolar output in [-1.0, 1.0]
+#[derive(Debug, Clone, Copy)]
+pub struct Lfo {
+    shape: LfoShape,
+    rate_hz: f32,
+    phase: f32,
+    /// Cycle length in quarter notes when synced to the tempo
+    sync_beats: Option<f64>,
+}
+
+impl Lfo {
+    #[must_use]
+    pub const fn new(shape: LfoShape, rate_hz: f32) -> Self {
+        Self {
+            shape,
+            rate_hz,
+            phase: 0.0,
+            sync_beats: None,
+        }
+    }
+
+    /// Syncs the rate to the tempo, one cycle every `beats` quarter notes
+    /// (4.0 for a bar of 4/4, 0.5 for an eighth note).
+    #[must_use]
+    pub const fn with_sync(mut self, beats: f64) -> Self {
+        self.sync_beats = Some(beats);
+        self
+    }
+
+    /// Syncs to the tempo, or runs free at the set rate with `None`
+    pub const fn set_sync(&mut self, beats: Option<f64>) {
+        self.sync_beats = beats;
+    }
+
+    /// Follows the tempo of `context` if synced. While the transport
+    /// rolls the phase is locked to the timeline, so the cycle restarts on
+    /// the beat after a seek or loop.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn sync_to(&mut self, context: &ProcessContext) {
+        let Some(beats) = self.sync_beats.filter(|beats| *beats > 0.0) else {
+            return;
+        };
+        if let Some(bpm) = context.tempo_bpm {
+            self.rate_hz = (f64::from(bpm) / 60.0 / beats) as f32;
+        }
+        if context.playing
+            && let Some(phase) = context.beat_phase(beats)
+        {
+            self.phase = phase;
+        }
+    }
+
+    /// Sets the starting phase (0.0 - 1.0)
+    #[must_use]
+    pub fn with_phase(mut self, phase: f32) -> Self {
+        self.phase = phase.rem_euclid(1.0);
+        self
+    }
+
+    pub const fn set_rate(&mut self, rate_hz: f32) {
+        self.rate_hz = rate_hz;
+    }
+
+    fn value(&self) -> f32 {
+        match self.shape {
+            LfoShape::Sine => (self.phase * TAU).sin(),
+            LfoShape::Triangle => 4.0f32.mul_add((self.phase - 0.5).abs(), -1.0),
+            LfoShape::Saw => 2.0f32.mul_add(self.phase, -1.0),
+            LfoShape::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self, seconds: f32) {
+        self.phase = self.rate_hz.mul_add(seconds, self.phase).rem_euclid(1.0);
+    }
+}
+
+/// Follows the peak level of the processed block, unipolar output in [0.0, 1.0]
+#[derive(Debug, Clone, Copy)]
+pub struct EnvelopeFollower {
+    attack_seconds: f32,
+    release_seconds: f32,
+    level: f32,
+}
+
+impl EnvelopeFollower {
+    #[must_use]
+    pub fn new(attack_ms: f32, release_ms: f32) -> Self {
+        Self {
+            attack_seconds: (attack_ms / 1000.0).max(1e-4),
+            release_seconds: (release_ms / 1000.0).max(1e-4),
+            level: 0.0,
+        }
+    }
+
+    const fn value(&self) -> f32 {
+        self.level
+    }
+
+    fn advance(&mut self, peak: f32, seconds: f32) {
+        let time = if peak > self.level {
+            self.attack_seconds
+        } else {
+            self.release_seconds
+        };
+        let coeff = 1.0 - (-seconds / time).exp();
+        self.level = ((peak - self.level).mul_add(coeff, self.level)).clamp(0.0, 1.0);
+    }
+}
+
+/// Random values held for one period, bipolar output in [-1.0, 1.0]
+#[derive(Debug, Clone, Copy)]
+pub struct SampleAndHold {
+    rate_hz: f32,
+    phase: f32,
+    value: f32,
+    rng: Rng,
+}
+
+impl SampleAndHold {
+    #[must_use]
+    pub const fn new(rate_hz: f32, seed: u32) -> Self {
+        Self {
+            rate_hz,
+            phase: 0.0,
+            value: 0.0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Restarts the random sequence from `seed`
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Rng::from_seed(seed);
+        self.phase = 0.0;
+        self.value = 0.0;
+    }
+
+    const fn value(&self) -> f32 {
+        self.value
+    }
+
+    fn advance(&mut self, seconds: f32) {
+        self.phase += self.rate_hz * seconds;
+        if self.phase >= 1.0 {
+            self.phase = self.phase.rem_euclid(1.0);
+            self.value = self.rng.next_bipolar();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ModSource {
+    Lfo(Lfo),
+    Envelope(EnvelopeFollower),
+    SampleAndHold(SampleAndHold),
+}
+
+impl ModSource {
+    /// Current output of the source
+    #[must_use]
+    pub fn value(&self) -> f32 {
+        match self {
+            Self::Lfo(lfo) => lfo.value(),
+            Self::Envelope(env) => env.value(),
+            Self::SampleAndHold(sh) => sh.value(),
+        }
+    }
+
+    fn advance(&mut self, peak: f32, seconds: f32) {
+        match self {
+            Self::Lfo(lfo) => lfo.advance(seconds),
+            Self::Envelope(env) => env.advance(peak, seconds),
+            Self::SampleAndHold(sh) => sh.advance(seconds),
+        }
+    }
+}
+
+impl From<Lfo> for ModSource {
+    fn from(value: Lfo) -> Self {
+        Self::Lfo(value)
+    }
+}
+
+impl From<EnvelopeFollower> for ModSource {
+    fn from(value: EnvelopeFollower) -> Self {
+        Self::Envelope(value)
+    }
+}
+
+impl From<SampleAndHold> for ModSource {
+    fn from(value: SampleAndHold) -> Self {
+        Self::SampleAndHold(value)
+    }
+}
+
+/// A routing from a source to a parameter.
+///
+/// `depth` is a fraction of the parameter's full range, so a depth of 0.5
+/// with a bipolar source swings the parameter by half its range either way.
+#[derive(Debug, Clone, Copy)]
+pub struct ModRoute {
+    pub source: ModSourceId,
+    pub effect_id: EffectId,
+    pub param_id: ParamId,
+    pub depth: f32,
+}
+
+impl ModRoute {
+    #[must_use]
+    pub const fn new(
+        source: ModSourceId,
+        effect_id: EffectId,
+        param_id: ParamId,
+        depth: f32,
+    ) -> Self {
+        Self {
+            source,
+            effect_id,
+            param_id,
+            depth,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ModTarget {
+    effect_id: EffectId,
+    param_id: ParamId,
+    base: f32,
+    min: f32,
+    max: f32,
+    offset: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RouteSlot {
+    source: usize,
+    target: usize,
+    depth: f32,
+}
+
+#[derive(Debug)]
+pub struct ModulationMatrix {
+    sources: Vec<ModSource>,
+    values: Vec<f32>,
+    targets: Vec<ModTarget>,
+    routes: Vec<RouteSlot>,
+    sample_rate: SampleRate,
+}
+
+impl ModulationMatrix {
+    /// Creates a matrix with fixed capacity for sources and routes.
+    #[must_use]
+    pub fn new(max_sources: usize, max_routes: usize) -> Self {
+        Self {
+            sources: Vec::with_capacity(max_sources),
+            values: Vec::with_capacity(max_sources),
+            targets: Vec::with_capacity(max_routes),
+            routes: Vec::with_capacity(max_routes),
+            sample_rate: SampleRate::Hz48000,
+        }
+    }
+
+    pub const fn initialize(&mut self, sample_rate: SampleRate) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Restarts every random source from a seed derived from `seed` and the
+    /// source's index.
+    pub fn reseed(&mut self, seed: u64) {
+        for (index, source) in (0u64..).zip(&mut self.sources) {
+            if let ModSource::SampleAndHold(sh) = source {
+                sh.reseed(derive_seed(seed, index));
+            }
+        }
+    }
+
+    /// Syncs tempo synced LFOs to the timeline position of the coming
+    /// block.
+    pub fn set_context(&mut self, context: &ProcessContext) {
+        for source in &mut self.sources {
+            if let ModSource::Lfo(lfo) = source {
+                lfo.sync_to(context);
+            }
+        }
+    }
+
+    /// Adds a modulation source.
+    ///
+    /// # Errors
+    /// Returns an error if the matrix is already at its source capacity.
+    pub fn add_source(&mut self, source: impl Into<ModSource>) -> Result<ModSourceId> {
+        if self.sources.len() == self.sources.capacity() {
+            return Err(AudioEngineError::configuration(format!(
+                "modulation matrix is full ({} sources)",
+                self.sources.capacity()
+            )));
+        }
+        let id = u32::try_from(self.sources.len())
+            .map_err(|_| AudioEngineError::numeric_conversion("too many modulation sources"))?;
+        self.sources.push(source.into());
+        self.values.push(0.0);
+        Ok(ModSourceId(id))
+    }
+
+    #[must_use]
+    pub fn source(&self, id: ModSourceId) -> Option<&ModSource> {
+        self.sources.get(id.index())
+    }
+
+    pub fn source_mut(&mut self, id: ModSourceId) -> Option<&mut ModSource> {
+        self.sources.get_mut(id.index())
+    }
+
+    /// Routes a source to an effect parameter in `chain`.
+    ///
+    /// The parameter's current value becomes the unmodulated base value.
+    /// Returns the route index.
+    ///
+    /// # Errors
+    /// Returns an error if the source, effect or parameter doesn't exist,
+    /// or the matrix is at its route capacity.
+    pub fn add_route(&mut self, chain: &EffectChain, route: ModRoute) -> Result<usize> {
+        if route.source.index() >= self.sources.len() {
+            return Err(AudioEngineError::configuration(format!(
+                "unknown modulation source {}",
+                route.source
+            )));
+        }
+        if self.routes.len() == self.routes.capacity() {
+            return Err(AudioEngineError::configuration(format!(
+                "modulation matrix is full ({} routes)",
+                self.routes.capacity()
+            )));
+        }
+
+        let existing = self
+            .targets
+            .iter()
+            .position(|t| t.effect_id == route.effect_id && t.param_id == route.param_id);
+        let target = if let Some(index) = existing {
+            index
+        } else {
+            let effect = chain.get(route.effect_id).ok_or_else(|| {
+                AudioEngineError::configuration(format!("{} is not in the chain", route.effect_id))
+            })?;
+            let info = effect
+                .parameters()
+                .iter()
+                .find(|p| p.id == route.param_id)
+                .ok_or_else(|| {
+                    AudioEngineError::configuration(format!(
+                        "{} has no parameter {}",
+                        route.effect_id, route.param_id
+                    ))
+                })?;
+            let base = effect
+                .get_parameter(route.param_id)
+                .map_or(info.default, |v| v.as_float());
+            self.targets.push(ModTarget {
+                effect_id: route.effect_id,
+                param_id: route.param_id,
+                base,
+                min: info.min,
+                max: info.max,
+                offset: 0.0,
+            });
+            self.targets.len() - 1
+        };
+
+        self.routes.push(RouteSlot {
+            source: route.source.index(),
+            target,
+            depth: route.depth,
+        });
+        Ok(self.routes.len() - 1)
+    }
+
+    /// Changes the depth of an existing route.
+    pub fn set_depth(&mut self, route: usize, depth: f32) -> bool {
+        self.routes.get_mut(route).is_some_and(|slot| {
+            slot.depth = depth;
+            true
+        })
+    }
+
+    /// Changes the unmodulated value of a modulated parameter.
+    pub fn set_base(&mut self, effect_id: EffectId, param_id: ParamId, value: f32) -> bool {
+        self.targets
+            .iter_mut()
+            .find(|t| t.effect_id == effect_id && t.param_id == param_id)
+            .is_some_and(|target| {
+                target.base = value.clamp(target.min, target.max);
+                true
+            })
+    }
+
+    #[must_use]
+    pub const fn route_count(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Advances every source by one block and applies the routed values to `chain`.
+    ///
+    /// `input` is the block the envelope followers track.
+    pub fn process(&mut self, chain: &mut EffectChain, input: &[Sample], channels: ChannelCount) {
+        let frames = input.len() / channels.count_usize();
+        if frames == 0 {
+            return;
+        }
+        let seconds = block_seconds(frames, self.sample_rate);
+        let peak = input.iter().fold(0.0f32, |acc, s| acc.max(s.value().abs()));
+
+        for (source, value) in self.sources.iter_mut().zip(self.values.iter_mut()) {
+            *value = source.value();
+            source.advance(peak, seconds);
+        }
+
+        for target in &mut self.targets {
+            target.offset = 0.0;
+        }
+        for route in &self.routes {
+            let target = &mut self.targets[route.target];
+            target.offset = route
+                .depth
+                .mul_add(self.values[route.source], target.offset);
+        }
+
+        for target in &self.targets {
+            let range = target.max - target.min;
+            let value = target
+                .offset
+                .mul_add(range, target.base)
+                .clamp(target.min, target.max);
+            chain.set_parameter(target.effect_id, target.param_id, ParamValue::Float(value));
+        }
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn block_seconds(frames: usize, sample_rate: SampleRate) -> f32 {
+    frames as f32 / sample_rate.as_f32()
+}
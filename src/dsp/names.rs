@@ -0,0 +1,199 @@
+//! Effect and parameter names
+//!
+//! A [`NameTable`] maps paths such as `"master/eq/band1/freq"` to the
+//! effect and parameter they name, so remotes and configuration files can
+//! say which parameter they mean without knowing numeric ids. Tables are
+//! built once, while the engine is configured, usually from a chain with
+//! [`NameTable::add_chain`]: each effect is named by its instance name, or
+//! else its type name, and each parameter by its name, made lowercase with
+//! everything other than letters and digits turned into `_`. Paths under
+//! other names can be added with [`NameTable::insert_param`].
+//!
+//! With the `serde` feature, tables convert to and from JSON and TOML.
+
+use std::collections::BTreeMap;
+
+use crate::dsp::chain::EffectChain;
+use crate::dsp::params::ParamId;
+use crate::dsp::traits::EffectId;
+
+/// Separates the parts of a path
+pub const SEPARATOR: char = '/';
+
+/// A parameter of a particular effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParamAddress {
+    pub effect_id: EffectId,
+    pub param_id: ParamId,
+}
+
+impl ParamAddress {
+    #[must_use]
+    pub const fn new(effect_id: EffectId, param_id: ParamId) -> Self {
+        Self {
+            effect_id,
+            param_id,
+        }
+    }
+}
+
+/// `name` as one part of a path: lowercase, with each run of characters
+/// other than letters and digits turned into a single `_`.
+///
+/// `"Swap L/R"` becomes `"swap_l_r"`.
+#[must_use]
+pub fn slug(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('_') {
+            slug.push('_');
+        }
+    }
+    if slug.ends_with('_') {
+        slug.pop();
+    }
+    slug
+}
+
+/// `path` without leading, trailing or doubled separators
+fn normalize(path: &str) -> String {
+    path.split(SEPARATOR)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Paths of effects and parameters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NameTable {
+    #[cfg_attr(feature = "serde", serde(default))]
+    effects: BTreeMap<String, EffectId>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    params: BTreeMap<String, ParamAddress>,
+}
+
+impl NameTable {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The table with [`add_chain`](Self::add_chain) applied.
+    #[must_use]
+    pub fn with_chain(mut self, prefix: &str, chain: &EffectChain) -> Self {
+        self.add_chain(prefix, chain);
+        self
+    }
+
+    /// Names every effect in `chain` and each of its parameters, under
+    /// `prefix` if it isn't empty. Effects without an instance name that
+    /// share a type name are numbered from the second on: `gain`,
+    /// `gain_2`.
+    pub fn add_chain(&mut self, prefix: &str, chain: &EffectChain) {
+        let prefix = normalize(prefix);
+        for effect in chain.iter() {
+            let path = chain.name(effect.id()).map_or_else(
+                || self.unused(&join(&prefix, &slug(effect.name()))),
+                |name| join(&prefix, &normalize(name)),
+            );
+            for info in effect.parameters() {
+                self.insert_param(
+                    &join(&path, &slug(&info.name)),
+                    ParamAddress::new(effect.id(), info.id),
+                );
+            }
+            self.insert_effect(&path, effect.id());
+        }
+    }
+
+    /// `path`, or the first of `path_2`, `path_3`... not naming an effect
+    fn unused(&self, path: &str) -> String {
+        let mut candidate = path.to_string();
+        let mut number = 1;
+        while self.effects.contains_key(&candidate) {
+            number += 1;
+            candidate = format!("{path}_{number}");
+        }
+        candidate
+    }
+
+    /// Names an effect, returning the effect the path named before.
+    pub fn insert_effect(&mut self, path: &str, effect_id: EffectId) -> Option<EffectId> {
+        let previous = self.effects.insert(normalize(path), effect_id);
+        if previous.is_some_and(|previous| previous != effect_id) {
+            log::warn!("Effect path {path} renamed from {previous:?} to {effect_id}");
+        }
+        previous
+    }
+
+    /// Names a parameter, returning the parameter the path named before.
+    pub fn insert_param(&mut self, path: &str, address: ParamAddress) -> Option<ParamAddress> {
+        let previous = self.params.insert(normalize(path), address);
+        if previous.is_some_and(|previous| previous != address) {
+            log::warn!("Parameter path {path} renamed from {previous:?} to {address:?}");
+        }
+        previous
+    }
+
+    /// The effect at `path`
+    #[must_use]
+    pub fn effect(&self, path: &str) -> Option<EffectId> {
+        self.effects.get(&normalize(path)).copied()
+    }
+
+    /// The parameter at `path`
+    #[must_use]
+    pub fn param(&self, path: &str) -> Option<ParamAddress> {
+        self.params.get(&normalize(path)).copied()
+    }
+
+    /// A path naming `effect_id`
+    #[must_use]
+    pub fn effect_path(&self, effect_id: EffectId) -> Option<&str> {
+        self.effects
+            .iter()
+            .find(|&(_, &id)| id == effect_id)
+            .map(|(path, _)| path.as_str())
+    }
+
+    /// A path naming `address`
+    #[must_use]
+    pub fn param_path(&self, address: ParamAddress) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|&(_, &named)| named == address)
+            .map(|(path, _)| path.as_str())
+    }
+
+    /// Effect paths, in order
+    pub fn effects(&self) -> impl Iterator<Item = (&str, EffectId)> {
+        self.effects.iter().map(|(path, &id)| (path.as_str(), id))
+    }
+
+    /// Parameter paths, in order
+    pub fn params(&self) -> impl Iterator<Item = (&str, ParamAddress)> {
+        self.params
+            .iter()
+            .map(|(path, &address)| (path.as_str(), address))
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty() && self.params.is_empty()
+    }
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{prefix}{SEPARATOR}{name}")
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::dsp::preset::serialization::text_formats!(NameTable, "name table");
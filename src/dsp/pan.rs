@@ -1,7 +1,13 @@
 //! Pan effect
-
-use crate::dsp::params::{ParamId, ParamValue, ParameterInfo, SmoothParam};
-use crate::dsp::traits::{Effect, EffectId};
+//!
+//! Pans every left/right pair of the layout together: the front pair in
+//! stereo, and the rear and side pairs too in quad and surround. Centre
+//! and LFE channels are left alone. Mono has nothing to pan, so the effect
+//! reports it unsupported.
+
+use crate::dsp::channels::{FrameProcessor, process_frames, stereo_pairs};
+use crate::dsp::params::{ParamId, ParamValue, ParameterInfo, SmoothParam, SmoothingMode};
+use crate::dsp::traits::{Effect, EffectId, SmoothableEffect};
 use crate::types::{ChannelCount, Pan, Sample, SampleRate};
 
 pub mod params {
@@ -46,6 +52,10 @@ impl PanEffect {
 
     pub fn set_pan(&mut self, pan: Pan) {
         let samples = self.sample_rate.samples_for_milliseconds(10);
+        self.set_pan_over(pan, samples);
+    }
+
+    fn set_pan_over(&mut self, pan: Pan, samples: u32) {
         self.pan.set_target(pan.values(), samples);
     }
 
@@ -80,20 +90,14 @@ impl Effect for PanEffect {
     }
 
     fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
-        if !self.enabled {
+        if !self.enabled || !self.supports_channels(channels) {
             return;
         }
+        process_frames(self, samples, channels);
+    }
 
-        let channel_count = channels.count_usize();
-        for frame in samples.chunks_exact_mut(channel_count) {
-            let pan = Pan::new(self.pan.next());
-            let (left_gain, right_gain) = pan.gains();
-
-            if let [left, right] = frame {
-                *left = Sample::new(left.value() * left_gain.as_linear());
-                *right = Sample::new(right.value() * right_gain.as_linear());
-            }
-        }
+    fn supports_channels(&self, channels: ChannelCount) -> bool {
+        channels != ChannelCount::Mono
     }
 
     fn parameters(&self) -> &[ParameterInfo] {
@@ -117,3 +121,33 @@ impl Effect for PanEffect {
         }
     }
 }
+
+impl FrameProcessor for PanEffect {
+    fn process_frame<const N: usize>(&mut self, frame: &mut [Sample; N]) {
+        let (left_gain, right_gain) = Pan::new(self.pan.next()).gains();
+        for &(left, right) in stereo_pairs::<N>() {
+            frame[left] = Sample::new(frame[left].value() * left_gain.as_linear());
+            frame[right] = Sample::new(frame[right].value() * right_gain.as_linear());
+        }
+    }
+}
+
+impl SmoothableEffect for PanEffect {
+    fn set_parameter_smooth(&mut self, id: ParamId, value: ParamValue, samples: u32) {
+        if id == params::PAN {
+            self.set_pan_over(Pan::new(value.as_float()), samples);
+        }
+    }
+
+    fn update_smoothing(&mut self) {
+        self.pan.advance(1);
+    }
+
+    fn set_smoothing_mode(&mut self, id: ParamId, mode: SmoothingMode) -> bool {
+        if id == params::PAN {
+            self.pan.set_mode(mode);
+            return true;
+        }
+        false
+    }
+}
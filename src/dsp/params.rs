@@ -1,8 +1,11 @@
 use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
 
+use crate::error::{AudioEngineError, Result};
 use crate::types::{Decibels, Gain};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParamId(u32);
 
 impl ParamId {
@@ -30,6 +33,7 @@ impl fmt::Display for ParamId {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ParamValue {
     Float(f32),
     Int(i32),
@@ -38,7 +42,194 @@ pub enum ParamValue {
     Gain(Gain),
 }
 
+/// Which [`ParamValue`] variant a parameter takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParamKind {
+    #[default]
+    Float,
+    Int,
+    Bool,
+    Decibels,
+    Gain,
+}
+
+impl fmt::Display for ParamKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Float => "float",
+            Self::Int => "int",
+            Self::Bool => "bool",
+            Self::Decibels => "decibels",
+            Self::Gain => "gain",
+        })
+    }
+}
+
+/// How a fractional value becomes an integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Rounding {
+    /// To the nearest integer, halves away from zero
+    #[default]
+    Nearest,
+    /// Towards negative infinity
+    Down,
+    /// Towards positive infinity
+    Up,
+    /// Towards zero, dropping the fraction
+    TowardZero,
+}
+
+impl Rounding {
+    #[must_use]
+    pub const fn apply(self, value: f32) -> f32 {
+        match self {
+            Self::Nearest => value.round(),
+            Self::Down => value.floor(),
+            Self::Up => value.ceil(),
+            Self::TowardZero => value.trunc(),
+        }
+    }
+}
+
 impl ParamValue {
+    /// The variant this value is
+    #[must_use]
+    pub const fn kind(&self) -> ParamKind {
+        match self {
+            Self::Float(_) => ParamKind::Float,
+            Self::Int(_) => ParamKind::Int,
+            Self::Bool(_) => ParamKind::Bool,
+            Self::Decibels(_) => ParamKind::Decibels,
+            Self::Gain(_) => ParamKind::Gain,
+        }
+    }
+
+    /// The value as a number: switches are 0.0 or 1.0, decibels their dB
+    /// value and gains their linear value.
+    ///
+    /// # Errors
+    /// Returns an error if the value isn't finite.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn to_f32(&self) -> Result<f32> {
+        let value = match *self {
+            Self::Float(value) => value,
+            Self::Int(value) => value as f32,
+            Self::Bool(value) => f32::from(u8::from(value)),
+            Self::Decibels(value) => value.value(),
+            Self::Gain(value) => value.as_linear(),
+        };
+        if value.is_finite() {
+            Ok(value)
+        } else {
+            Err(AudioEngineError::numeric_conversion(format!(
+                "parameter value {value} is not finite"
+            )))
+        }
+    }
+
+    /// The value as an integer, with floats rounded by `rounding` and
+    /// switches 0 or 1.
+    ///
+    /// # Errors
+    /// Returns an error for decibels and gains, which aren't counts, and
+    /// for floats that aren't finite or don't fit an `i32`.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    pub fn to_i32(&self, rounding: Rounding) -> Result<i32> {
+        match *self {
+            Self::Int(value) => Ok(value),
+            Self::Bool(value) => Ok(i32::from(value)),
+            Self::Float(value) => {
+                let rounded = rounding.apply(value);
+                // i32::MAX isn't exact as an f32; 2^31 is the first value past it
+                if rounded.is_finite() && rounded >= i32::MIN as f32 && rounded < 2_147_483_648.0 {
+                    Ok(rounded as i32)
+                } else {
+                    Err(AudioEngineError::numeric_conversion(format!(
+                        "{value} doesn't fit an integer parameter"
+                    )))
+                }
+            }
+            Self::Decibels(_) | Self::Gain(_) => Err(self.mismatch(ParamKind::Int)),
+        }
+    }
+
+    /// The value as a switch. Floats and ints must be 0 or 1; floats in
+    /// between are on from 0.5.
+    ///
+    /// # Errors
+    /// Returns an error for decibels and gains and for numbers outside 0
+    /// to 1.
+    pub fn to_bool(&self) -> Result<bool> {
+        match *self {
+            Self::Bool(value) => Ok(value),
+            Self::Int(value @ (0 | 1)) => Ok(value == 1),
+            Self::Float(value) if (0.0..=1.0).contains(&value) => Ok(value >= 0.5),
+            Self::Int(_) | Self::Float(_) => Err(AudioEngineError::numeric_conversion(format!(
+                "{self:?} is not a switch value (0 or 1)"
+            ))),
+            Self::Decibels(_) | Self::Gain(_) => Err(self.mismatch(ParamKind::Bool)),
+        }
+    }
+
+    /// The value as a level in dB: numbers are taken as dB and gains
+    /// converted. Levels are clamped like [`Decibels::new`].
+    ///
+    /// # Errors
+    /// Returns an error for switches and numbers that aren't finite.
+    pub fn to_decibels(&self) -> Result<Decibels> {
+        match *self {
+            Self::Decibels(value) => Ok(value),
+            Self::Gain(value) => Ok(value.to_decibels()),
+            Self::Float(_) | Self::Int(_) => Ok(Decibels::new(self.to_f32()?)),
+            Self::Bool(_) => Err(self.mismatch(ParamKind::Decibels)),
+        }
+    }
+
+    /// The value as a gain: numbers are taken as linear gain and decibels
+    /// converted.
+    ///
+    /// # Errors
+    /// Returns an error for switches and for numbers that are negative or
+    /// not finite.
+    pub fn to_gain(&self) -> Result<Gain> {
+        match *self {
+            Self::Gain(value) => Ok(value),
+            Self::Decibels(value) => Ok(value.to_gain()),
+            Self::Float(_) | Self::Int(_) => {
+                let linear = self.to_f32()?;
+                if linear < 0.0 {
+                    return Err(AudioEngineError::numeric_conversion(format!(
+                        "gain {linear} is negative"
+                    )));
+                }
+                Ok(Gain::from_linear_clamped(linear))
+            }
+            Self::Bool(_) => Err(self.mismatch(ParamKind::Gain)),
+        }
+    }
+
+    /// The value as `kind`, rounding floats to ints by `rounding`.
+    ///
+    /// # Errors
+    /// Returns an error if the value has no sensible `kind` equivalent;
+    /// see the `to_` methods.
+    pub fn convert(&self, kind: ParamKind, rounding: Rounding) -> Result<Self> {
+        Ok(match kind {
+            ParamKind::Float => Self::Float(self.to_f32()?),
+            ParamKind::Int => Self::Int(self.to_i32(rounding)?),
+            ParamKind::Bool => Self::Bool(self.to_bool()?),
+            ParamKind::Decibels => Self::Decibels(self.to_decibels()?),
+            ParamKind::Gain => Self::Gain(self.to_gain()?),
+        })
+    }
+
+    fn mismatch(self, kind: ParamKind) -> AudioEngineError {
+        AudioEngineError::numeric_conversion(format!("{self:?} can't be converted to {kind}"))
+    }
+
+    /// The value as a number, however lossy; see [`to_f32`](Self::to_f32)
+    /// for a checked conversion.
     #[must_use]
     pub fn as_float(&self) -> f32 {
         match self {
@@ -56,6 +247,8 @@ impl ParamValue {
         }
     }
 
+    /// The value truncated to an integer, however lossy; see
+    /// [`to_i32`](Self::to_i32) for a checked conversion.
     #[must_use]
     pub fn as_int(&self) -> i32 {
         match self {
@@ -67,6 +260,8 @@ impl ParamValue {
         }
     }
 
+    /// The value as a switch, however lossy; see
+    /// [`to_bool`](Self::to_bool) for a checked conversion.
     #[must_use]
     pub fn as_bool(&self) -> bool {
         match self {
@@ -119,6 +314,8 @@ pub struct ParameterInfo {
     pub default: f32,
     pub unit: String,
     pub precision: u8,
+    /// Variant the effect takes and reports
+    pub kind: ParamKind,
 }
 
 impl ParameterInfo {
@@ -135,6 +332,7 @@ impl ParameterInfo {
             default: 0.5,
             unit: String::new(),
             precision: 2,
+            kind: ParamKind::Float,
         }
     }
 
@@ -169,6 +367,41 @@ impl ParameterInfo {
         self
     }
 
+    /// Declares the variant the parameter takes. Switches are also given
+    /// the range 0 to 1 and ints a precision of 0.
+    #[must_use]
+    pub const fn with_kind(mut self, kind: ParamKind) -> Self {
+        self.kind = kind;
+        match kind {
+            ParamKind::Bool => {
+                self.min = 0.0;
+                self.max = 1.0;
+                self.precision = 0;
+            }
+            ParamKind::Int => self.precision = 0,
+            ParamKind::Float | ParamKind::Decibels | ParamKind::Gain => {}
+        }
+        self
+    }
+
+    /// `value` as the parameter's [`kind`](Self::kind), checked against
+    /// its range. Floats are rounded to the nearest int.
+    ///
+    /// # Errors
+    /// Returns an error if the value can't be converted or its number,
+    /// see [`ParamValue::to_f32`], is outside the range.
+    pub fn coerce(&self, value: ParamValue) -> Result<ParamValue> {
+        let converted = value.convert(self.kind, Rounding::Nearest)?;
+        let number = converted.to_f32()?;
+        if self.kind != ParamKind::Bool && !(self.min..=self.max).contains(&number) {
+            return Err(AudioEngineError::numeric_conversion(format!(
+                "{} = {number} is outside {} to {}",
+                self.name, self.min, self.max
+            )));
+        }
+        Ok(converted)
+    }
+
     #[must_use]
     pub fn normalize(&self, value: f32) -> f32 {
         if (self.max - self.min).abs() < f32::EPSILON {
@@ -198,12 +431,114 @@ impl ParameterInfo {
     }
 }
 
+/// How a [`SmoothParam`] moves towards a new target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SmoothingMode {
+    /// Constant step per sample, arriving exactly at the end of the ramp
+    #[default]
+    Linear,
+    /// One-pole approach: quick at first, then settling. After the ramp
+    /// length only 0.1% of the distance is left and the value snaps to the
+    /// target.
+    Exponential,
+}
+
+/// Fraction of the distance left at the end of an exponential ramp
+const EXPONENTIAL_RESIDUAL: f32 = 0.001;
+
+/// How finely parameter ramps are evaluated, traded against CPU.
+///
+/// The setting is global: every [`SmoothParam`] picks it up when a ramp
+/// starts, so all effects, the mixer and the engine follow it alike.
+/// Ramps already running finish with the quality they started with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SmoothingQuality {
+    /// Samples between updates while a ramp runs: 1 moves every sample,
+    /// a block length moves once per block and holds in between
+    pub interval: u32,
+    /// Multiplies every ramp length; below 1.0 ramps finish sooner
+    pub time_scale: f32,
+}
+
+impl SmoothingQuality {
+    /// Every sample, full ramp lengths
+    pub const HIGH: Self = Self {
+        interval: 1,
+        time_scale: 1.0,
+    };
+    /// Updates every 16 samples
+    pub const MEDIUM: Self = Self {
+        interval: 16,
+        time_scale: 1.0,
+    };
+    /// Updates every 64 samples over half-length ramps, for low-power
+    /// devices
+    pub const LOW: Self = Self {
+        interval: 64,
+        time_scale: 0.5,
+    };
+
+    #[must_use]
+    pub const fn with_interval(mut self, samples: u32) -> Self {
+        self.interval = samples;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_time_scale(mut self, scale: f32) -> Self {
+        self.time_scale = scale;
+        self
+    }
+
+    /// Length of a ramp asked to take `samples`
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn scale(self, samples: u32) -> u32 {
+        if (self.time_scale - 1.0).abs() < f32::EPSILON || samples == 0 {
+            return samples;
+        }
+        ((samples as f32 * self.time_scale.max(0.0)).round() as u32).max(1)
+    }
+}
+
+impl Default for SmoothingQuality {
+    fn default() -> Self {
+        Self::HIGH
+    }
+}
+
+static QUALITY_INTERVAL: AtomicU32 = AtomicU32::new(1);
+static QUALITY_TIME_SCALE: AtomicU32 = AtomicU32::new(1.0f32.to_bits());
+
+/// Sets the quality of every parameter ramp started from now on.
+pub fn set_smoothing_quality(quality: SmoothingQuality) {
+    QUALITY_INTERVAL.store(quality.interval.max(1), Ordering::Relaxed);
+    QUALITY_TIME_SCALE.store(quality.time_scale.to_bits(), Ordering::Relaxed);
+}
+
+#[must_use]
+pub fn smoothing_quality() -> SmoothingQuality {
+    SmoothingQuality {
+        interval: QUALITY_INTERVAL.load(Ordering::Relaxed),
+        time_scale: f32::from_bits(QUALITY_TIME_SCALE.load(Ordering::Relaxed)),
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct SmoothParam {
     current: f32,
     target: f32,
     increment: f32,
+    coefficient: f32,
     samples_remaining: u32,
+    mode: SmoothingMode,
+    /// Samples between updates, from the quality when the ramp started
+    interval: u32,
+    /// Samples left until the next update
+    held: u32,
 }
 
 impl SmoothParam {
@@ -213,20 +548,56 @@ impl SmoothParam {
             current: initial,
             target: initial,
             increment: 0.0,
+            coefficient: 0.0,
             samples_remaining: 0,
+            mode: SmoothingMode::Linear,
+            interval: 1,
+            held: 0,
         }
     }
 
+    #[must_use]
+    pub const fn with_mode(mut self, mode: SmoothingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    #[must_use]
+    pub const fn mode(&self) -> SmoothingMode {
+        self.mode
+    }
+
+    /// Changes the smoothing mode; a ramp in progress finishes in the old mode.
+    pub const fn set_mode(&mut self, mode: SmoothingMode) {
+        self.mode = mode;
+    }
+
+    /// Ramps to `target` over `samples`, scaled and stepped by the global
+    /// [`SmoothingQuality`].
+    #[allow(clippy::cast_precision_loss)]
     pub fn set_target(&mut self, target: f32, samples: u32) {
+        let quality = smoothing_quality();
+        let samples = quality.scale(samples);
         self.target = target;
+        self.interval = quality.interval;
+        self.held = 0;
         if samples == 0 {
             self.current = target;
             self.increment = 0.0;
             self.samples_remaining = 0;
-        } else {
-            self.increment = (target - self.current) / samples as f32;
-            self.samples_remaining = samples;
+            return;
+        }
+        match self.mode {
+            SmoothingMode::Linear => {
+                self.increment = (target - self.current) / samples as f32;
+                self.coefficient = 0.0;
+            }
+            SmoothingMode::Exponential => {
+                self.increment = 0.0;
+                self.coefficient = EXPONENTIAL_RESIDUAL.powf(1.0 / samples as f32);
+            }
         }
+        self.samples_remaining = samples;
     }
 
     pub fn set_immediate(&mut self, value: f32) {
@@ -234,6 +605,7 @@ impl SmoothParam {
         self.target = value;
         self.increment = 0.0;
         self.samples_remaining = 0;
+        self.held = 0;
     }
 
     #[must_use]
@@ -253,8 +625,21 @@ impl SmoothParam {
 
     #[must_use]
     pub fn next(&mut self) -> f32 {
+        if self.interval > 1 {
+            // Move a whole interval at once, then hold
+            if self.held == 0 && self.samples_remaining > 0 {
+                self.held = self.interval.min(self.samples_remaining);
+                self.advance(self.held);
+            }
+            self.held = self.held.saturating_sub(1);
+            return self.current;
+        }
         if self.samples_remaining > 0 {
-            self.current += self.increment;
+            if self.coefficient > 0.0 {
+                self.current = (self.current - self.target).mul_add(self.coefficient, self.target);
+            } else {
+                self.current += self.increment;
+            }
             self.samples_remaining -= 1;
             if self.samples_remaining == 0 {
                 self.current = self.target;
@@ -263,10 +648,16 @@ impl SmoothParam {
         self.current
     }
 
+    #[allow(clippy::cast_precision_loss)]
     pub fn advance(&mut self, samples: u32) {
         if self.samples_remaining > 0 {
             let advance = samples.min(self.samples_remaining);
-            self.current += self.increment * advance as f32;
+            if self.coefficient > 0.0 {
+                self.current = (self.current - self.target)
+                    .mul_add(self.coefficient.powf(advance as f32), self.target);
+            } else {
+                self.current += self.increment * advance as f32;
+            }
             self.samples_remaining -= advance;
             if self.samples_remaining == 0 {
                 self.current = self.target;
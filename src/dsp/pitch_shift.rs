@@ -0,0 +1,521 @@
+//! Phase vocoder pitch shifter
+//!
+//! Each channel is analysed with a 2048 point FFT at 4x overlap. Bins are
+//! moved by the pitch ratio and resynthesised with their phases advanced at
+//! the shifted frequency. With formant preservation on, the spectral envelope
+//! (estimated by cepstral liftering) is divided out before the shift and
+//! reapplied afterwards, so voices keep their character.
+//!
+//! Estimating the envelope takes two more FFTs a frame. Below
+//! [`EffectQuality::High`] it is re-estimated only every few frames, which
+//! holds up while the formants move slowly.
+
+use std::f32::consts::TAU;
+
+use crate::buffer::memory::heap_bytes;
+use crate::dsp::fft::{Fft, hann_window};
+use crate::dsp::params::{
+    ParamId, ParamKind, ParamValue, ParameterInfo, SmoothParam, SmoothingMode,
+};
+use crate::dsp::quality::EffectQuality;
+use crate::dsp::traits::{Effect, EffectId, SmoothableEffect};
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+pub mod params {
+    use super::ParamId;
+    /// Transposition in semitones
+    pub const SEMITONES: ParamId = ParamId::new(0);
+    /// Fine transposition in cents
+    pub const CENTS: ParamId = ParamId::new(1);
+    /// Formant preservation on/off
+    pub const PRESERVE_FORMANTS: ParamId = ParamId::new(2);
+}
+
+const FFT_SIZE: usize = 2048;
+const OVERSAMPLING: usize = 4;
+const HOP: usize = FFT_SIZE / OVERSAMPLING;
+const BINS: usize = FFT_SIZE / 2 + 1;
+const LATENCY: usize = FFT_SIZE - HOP;
+/// Cepstral coefficients kept for the spectral envelope
+const LIFTER: usize = 40;
+
+#[derive(Debug, Clone)]
+struct ChannelState {
+    input: Vec<f32>,
+    output: Vec<f32>,
+    accumulator: Vec<f32>,
+    last_phase: Vec<f32>,
+    phase_sum: Vec<f32>,
+    /// Spectral envelope, kept between frames at lower qualities
+    envelope: Vec<f32>,
+    position: usize,
+    /// Frames since the envelope was estimated
+    envelope_age: u32,
+}
+
+impl ChannelState {
+    fn new() -> Self {
+        Self {
+            input: vec![0.0; FFT_SIZE],
+            output: vec![0.0; FFT_SIZE],
+            accumulator: vec![0.0; 2 * FFT_SIZE],
+            last_phase: vec![0.0; BINS],
+            phase_sum: vec![0.0; BINS],
+            envelope: vec![1.0; BINS],
+            position: LATENCY,
+            envelope_age: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.input.fill(0.0);
+        self.output.fill(0.0);
+        self.accumulator.fill(0.0);
+        self.last_phase.fill(0.0);
+        self.phase_sum.fill(0.0);
+        self.envelope.fill(1.0);
+        self.position = LATENCY;
+        self.envelope_age = 0;
+    }
+
+    const fn memory_bytes(&self) -> usize {
+        heap_bytes(&self.input)
+            + heap_bytes(&self.output)
+            + heap_bytes(&self.accumulator)
+            + heap_bytes(&self.last_phase)
+            + heap_bytes(&self.phase_sum)
+            + heap_bytes(&self.envelope)
+    }
+}
+
+/// Scratch buffers shared by all channels
+#[derive(Debug, Clone)]
+struct Scratch {
+    re: Vec<f32>,
+    im: Vec<f32>,
+    cep_re: Vec<f32>,
+    cep_im: Vec<f32>,
+    magnitude: Vec<f32>,
+    frequency: Vec<f32>,
+    synth_magnitude: Vec<f32>,
+    synth_frequency: Vec<f32>,
+}
+
+impl Scratch {
+    fn new() -> Self {
+        Self {
+            re: vec![0.0; FFT_SIZE],
+            im: vec![0.0; FFT_SIZE],
+            cep_re: vec![0.0; FFT_SIZE],
+            cep_im: vec![0.0; FFT_SIZE],
+            magnitude: vec![0.0; BINS],
+            frequency: vec![0.0; BINS],
+            synth_magnitude: vec![0.0; BINS],
+            synth_frequency: vec![0.0; BINS],
+        }
+    }
+
+    const fn memory_bytes(&self) -> usize {
+        heap_bytes(&self.re)
+            + heap_bytes(&self.im)
+            + heap_bytes(&self.cep_re)
+            + heap_bytes(&self.cep_im)
+            + heap_bytes(&self.magnitude)
+            + heap_bytes(&self.frequency)
+            + heap_bytes(&self.synth_magnitude)
+            + heap_bytes(&self.synth_frequency)
+    }
+}
+
+#[derive(Debug)]
+pub struct PitchShift {
+    id: EffectId,
+    enabled: bool,
+    semitones: SmoothParam,
+    cents: SmoothParam,
+    preserve_formants: bool,
+    quality: EffectQuality,
+    ratio: f32,
+    sample_rate: SampleRate,
+    fft: Fft,
+    window: Vec<f32>,
+    states: Vec<ChannelState>,
+    scratch: Scratch,
+    param_info: Vec<ParameterInfo>,
+}
+
+impl PitchShift {
+    #[must_use]
+    pub fn new(id: EffectId) -> Self {
+        Self::with_params(id, 0.0, 0.0)
+    }
+
+    /// # Panics
+    /// Never; the FFT size is a fixed power of two.
+    #[must_use]
+    pub fn with_params(id: EffectId, semitones: f32, cents: f32) -> Self {
+        let param_info = vec![
+            ParameterInfo::new(params::SEMITONES, "Semitones")
+                .with_short_name("Semi")
+                .with_range(-24.0, 24.0)
+                .with_default(0.0)
+                .with_unit("st")
+                .with_precision(0),
+            ParameterInfo::new(params::CENTS, "Cents")
+                .with_short_name("Cents")
+                .with_range(-100.0, 100.0)
+                .with_default(0.0)
+                .with_unit("ct")
+                .with_precision(0),
+            ParameterInfo::new(params::PRESERVE_FORMANTS, "Preserve Formants")
+                .with_short_name("Formant")
+                .with_default(0.0)
+                .with_kind(ParamKind::Bool),
+        ];
+
+        let mut window = vec![0.0; FFT_SIZE];
+        hann_window(&mut window);
+
+        let mut shifter = Self {
+            id,
+            enabled: true,
+            semitones: SmoothParam::new(0.0),
+            cents: SmoothParam::new(0.0),
+            preserve_formants: false,
+            quality: EffectQuality::High,
+            ratio: 1.0,
+            sample_rate: SampleRate::Hz48000,
+            fft: Fft::new(FFT_SIZE).expect("FFT size is a power of two"),
+            window,
+            states: vec![ChannelState::new(); 2],
+            scratch: Scratch::new(),
+            param_info,
+        };
+        shifter.set_semitones(semitones);
+        shifter.set_cents(cents);
+        shifter
+    }
+
+    pub fn set_semitones(&mut self, semitones: f32) {
+        self.set_semitones_over(semitones, 0);
+    }
+
+    fn set_semitones_over(&mut self, semitones: f32, samples: u32) {
+        self.semitones
+            .set_target(semitones.clamp(-24.0, 24.0), samples);
+        self.update_ratio();
+    }
+
+    pub fn set_cents(&mut self, cents: f32) {
+        self.set_cents_over(cents, 0);
+    }
+
+    fn set_cents_over(&mut self, cents: f32, samples: u32) {
+        self.cents.set_target(cents.clamp(-100.0, 100.0), samples);
+        self.update_ratio();
+    }
+
+    pub const fn set_preserve_formants(&mut self, preserve: bool) {
+        self.preserve_formants = preserve;
+    }
+
+    /// Frequency ratio applied to the input
+    #[must_use]
+    pub const fn ratio(&self) -> f32 {
+        self.ratio
+    }
+
+    #[must_use]
+    pub const fn quality(&self) -> EffectQuality {
+        self.quality
+    }
+
+    /// Frames the spectral envelope is kept for at the current quality
+    const fn envelope_interval(&self) -> u32 {
+        match self.quality {
+            EffectQuality::Low => 4,
+            EffectQuality::Medium => 2,
+            EffectQuality::High => 1,
+        }
+    }
+
+    fn update_ratio(&mut self) {
+        let semitones = self.cents.current().mul_add(0.01, self.semitones.current());
+        self.ratio = (semitones / 12.0).exp2();
+    }
+
+    const fn is_smoothing(&self) -> bool {
+        self.semitones.is_smoothing() || self.cents.is_smoothing()
+    }
+
+    /// Runs one analysis/resynthesis frame for a channel.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn process_frame(&mut self, channel: usize) {
+        let bin_hz = self.sample_rate.as_f32() / FFT_SIZE as f32;
+        let expected = TAU * HOP as f32 / FFT_SIZE as f32;
+        let oversampling = OVERSAMPLING as f32;
+        let envelope_interval = self.envelope_interval();
+        let state = &mut self.states[channel];
+        let s = &mut self.scratch;
+
+        for (k, (re, im)) in s.re.iter_mut().zip(s.im.iter_mut()).enumerate() {
+            *re = state.input[k] * self.window[k];
+            *im = 0.0;
+        }
+        self.fft.forward(&mut s.re, &mut s.im);
+
+        // Analysis: magnitude and true frequency of each bin
+        for k in 0..BINS {
+            let (re, im) = (s.re[k], s.im[k]);
+            let phase = im.atan2(re);
+            let mut delta = (k as f32).mul_add(-expected, phase - state.last_phase[k]);
+            state.last_phase[k] = phase;
+            delta -= TAU * (delta / TAU).round();
+            let deviation = oversampling * delta / TAU;
+            s.magnitude[k] = 2.0 * re.hypot(im);
+            s.frequency[k] = (k as f32 + deviation) * bin_hz;
+        }
+
+        if self.preserve_formants {
+            if state.envelope_age == 0 {
+                spectral_envelope(&self.fft, s, &mut state.envelope);
+            }
+            state.envelope_age = (state.envelope_age + 1) % envelope_interval;
+        }
+
+        // Move bins to their shifted position
+        s.synth_magnitude.fill(0.0);
+        s.synth_frequency.fill(0.0);
+        for k in 0..BINS {
+            let target = (k as f32 * self.ratio).round() as usize;
+            if target >= BINS {
+                break;
+            }
+            let magnitude = if self.preserve_formants {
+                s.magnitude[k] / state.envelope[k] * state.envelope[target]
+            } else {
+                s.magnitude[k]
+            };
+            s.synth_magnitude[target] += magnitude;
+            s.synth_frequency[target] = s.frequency[k] * self.ratio;
+        }
+
+        // Synthesis: accumulate phase at the shifted frequencies
+        for k in 0..BINS {
+            let deviation = s.synth_frequency[k] / bin_hz - k as f32;
+            state.phase_sum[k] +=
+                (TAU * deviation).mul_add(1.0 / oversampling, k as f32 * expected);
+            let (sin, cos) = state.phase_sum[k].sin_cos();
+            s.re[k] = s.synth_magnitude[k] * cos;
+            s.im[k] = s.synth_magnitude[k] * sin;
+        }
+        s.re[BINS..].fill(0.0);
+        s.im[BINS..].fill(0.0);
+        self.fft.inverse(&mut s.re, &mut s.im);
+
+        let gain = 4.0 / oversampling;
+        for (k, acc) in state.accumulator[..FFT_SIZE].iter_mut().enumerate() {
+            *acc += gain * self.window[k] * s.re[k];
+        }
+        state.output[..HOP].copy_from_slice(&state.accumulator[..HOP]);
+        state.accumulator.copy_within(HOP.., 0);
+        let len = state.accumulator.len();
+        state.accumulator[len - HOP..].fill(0.0);
+        state.input.copy_within(HOP.., 0);
+    }
+}
+
+/// Estimates the spectral envelope of `scratch.magnitude` into `envelope`.
+fn spectral_envelope(fft: &Fft, s: &mut Scratch, envelope: &mut [f32]) {
+    for k in 0..BINS {
+        let log_magnitude = (s.magnitude[k] + 1e-9).ln();
+        s.cep_re[k] = log_magnitude;
+        if k > 0 && k < FFT_SIZE - k {
+            s.cep_re[FFT_SIZE - k] = log_magnitude;
+        }
+    }
+    s.cep_im.fill(0.0);
+    fft.inverse(&mut s.cep_re, &mut s.cep_im);
+
+    // Keep only the slowly varying part of the log spectrum
+    s.cep_re[LIFTER..=FFT_SIZE - LIFTER].fill(0.0);
+    s.cep_im.fill(0.0);
+    fft.forward(&mut s.cep_re, &mut s.cep_im);
+
+    for (envelope, &log_envelope) in envelope.iter_mut().zip(&s.cep_re) {
+        *envelope = log_envelope.exp().max(1e-9);
+    }
+}
+
+impl Effect for PitchShift {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "Pitch Shift"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn reset(&mut self) {
+        for state in &mut self.states {
+            state.reset();
+        }
+        self.semitones.set_immediate(self.semitones.target());
+        self.cents.set_immediate(self.cents.target());
+        self.update_ratio();
+    }
+
+    fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.states = vec![ChannelState::new(); channels.count_usize()];
+    }
+
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        if !self.enabled {
+            return;
+        }
+
+        let channel_count = channels.count_usize().min(self.states.len());
+        for frame in samples.chunks_exact_mut(channels.count_usize()) {
+            // The ratio only takes effect at the next hop, but keeps moving
+            // every frame so ramps last as long as they were asked to
+            if self.is_smoothing() {
+                let _ = self.semitones.next();
+                let _ = self.cents.next();
+                self.update_ratio();
+            }
+            for (channel, sample) in frame.iter_mut().enumerate().take(channel_count) {
+                let state = &mut self.states[channel];
+                let position = state.position;
+                state.input[position] = sample.value();
+                *sample = Sample::new(state.output[position - LATENCY]);
+                state.position += 1;
+                if state.position >= FFT_SIZE {
+                    state.position = LATENCY;
+                    self.process_frame(channel);
+                }
+            }
+        }
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &self.param_info
+    }
+
+    fn get_parameter(&self, id: ParamId) -> Option<ParamValue> {
+        match id {
+            params::SEMITONES => Some(ParamValue::Float(self.semitones.current())),
+            params::CENTS => Some(ParamValue::Float(self.cents.current())),
+            params::PRESERVE_FORMANTS => Some(ParamValue::Bool(self.preserve_formants)),
+            _ => None,
+        }
+    }
+
+    fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool {
+        match id {
+            params::SEMITONES => {
+                self.set_semitones(value.as_float());
+                true
+            }
+            params::CENTS => {
+                self.set_cents(value.as_float());
+                true
+            }
+            params::PRESERVE_FORMANTS => {
+                self.set_preserve_formants(value.as_bool());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn set_quality(&mut self, quality: EffectQuality) {
+        self.quality = quality;
+        for state in &mut self.states {
+            state.envelope_age = 0;
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn latency_samples(&self) -> u32 {
+        LATENCY as u32
+    }
+
+    fn memory_bytes(&self) -> usize {
+        let states: usize = self.states.iter().map(ChannelState::memory_bytes).sum();
+        size_of_val(self)
+            + self.fft.memory_bytes()
+            + heap_bytes(&self.window)
+            + heap_bytes(&self.states)
+            + states
+            + self.scratch.memory_bytes()
+    }
+}
+
+impl SmoothableEffect for PitchShift {
+    fn set_parameter_smooth(&mut self, id: ParamId, value: ParamValue, samples: u32) {
+        match id {
+            params::SEMITONES => self.set_semitones_over(value.as_float(), samples),
+            params::CENTS => self.set_cents_over(value.as_float(), samples),
+            _ => {}
+        }
+    }
+
+    fn update_smoothing(&mut self) {
+        if self.is_smoothing() {
+            self.semitones.advance(1);
+            self.cents.advance(1);
+            self.update_ratio();
+        }
+    }
+
+    /// Formant preservation is a switch and isn't smoothed.
+    fn set_smoothing_mode(&mut self, id: ParamId, mode: SmoothingMode) -> bool {
+        match id {
+            params::SEMITONES => self.semitones.set_mode(mode),
+            params::CENTS => self.cents.set_mode(mode),
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ratio_ramps_when_smoothed() {
+        let mut shifter = PitchShift::new(EffectId::new(1));
+        shifter.initialize(SampleRate::Hz48000, ChannelCount::Mono);
+        shifter.set_parameter_smooth(params::SEMITONES, ParamValue::Float(12.0), 1000);
+        let mut block = [Sample::SILENCE; 500];
+        shifter.process(&mut block, ChannelCount::Mono);
+        assert!((shifter.ratio() - 2.0f32.sqrt()).abs() < 1e-3);
+        for _ in 0..500 {
+            shifter.update_smoothing();
+        }
+        assert!((shifter.ratio() - 2.0).abs() < 1e-6);
+        assert_eq!(
+            shifter.get_parameter(params::SEMITONES),
+            Some(ParamValue::Float(12.0))
+        );
+
+        shifter.set_parameter_smooth(params::CENTS, ParamValue::Float(-100.0), 1000);
+        shifter.reset();
+        assert!((shifter.ratio() - 2.0f32.powf(11.0 / 12.0)).abs() < 1e-6);
+        assert!(!shifter.set_smoothing_mode(params::PRESERVE_FORMANTS, SmoothingMode::Linear));
+    }
+}
@@ -0,0 +1,192 @@
+//! Effect and chain presets
+//!
+//! A preset records an effect's enabled state and the value of every
+//! parameter it reports. With the `serde` feature, presets convert to and
+//! from JSON and TOML.
+//!
+//! Presets can be applied directly on the thread that owns the chain, or
+//! handed to a running chain through a [`PresetSender`]: the audio thread
+//! applies each preset whole between two blocks and hands it back so it is
+//! freed on the control thread.
+
+use crate::channel::{
+    ControlReceiver, ControlSender, RealtimeReceiver, RealtimeSender, control_channel,
+    feedback_channel,
+};
+use crate::dsp::params::{ParamId, ParamValue};
+use crate::dsp::traits::{Effect, EffectId};
+use crate::error::Result;
+
+/// Settings of a single effect.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EffectPreset {
+    /// Effect the preset was captured from, used to match it within a chain
+    pub id: EffectId,
+    /// Effect name, for display
+    pub name: String,
+    pub enabled: bool,
+    pub parameters: Vec<(ParamId, ParamValue)>,
+}
+
+impl EffectPreset {
+    /// Captures the current settings of `effect`.
+    #[must_use]
+    pub fn capture(effect: &dyn Effect) -> Self {
+        Self {
+            id: effect.id(),
+            name: effect.name().to_string(),
+            enabled: effect.is_enabled(),
+            parameters: effect
+                .parameters()
+                .iter()
+                .filter_map(|info| Some((info.id, effect.get_parameter(info.id)?)))
+                .collect(),
+        }
+    }
+
+    /// Value stored for a parameter
+    #[must_use]
+    pub fn parameter(&self, id: ParamId) -> Option<ParamValue> {
+        self.parameters
+            .iter()
+            .find(|(param, _)| *param == id)
+            .map(|&(_, value)| value)
+    }
+}
+
+/// Settings of every effect in a chain.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChainPreset {
+    pub effects: Vec<EffectPreset>,
+}
+
+impl ChainPreset {
+    /// Preset for the effect with the given id
+    #[must_use]
+    pub fn effect(&self, id: EffectId) -> Option<&EffectPreset> {
+        self.effects.iter().find(|preset| preset.id == id)
+    }
+}
+
+#[cfg(feature = "serde")]
+pub(crate) mod serialization {
+    use super::{ChainPreset, EffectPreset};
+    use crate::error::AudioEngineError;
+
+    pub fn invalid(what: &str, e: impl std::fmt::Display) -> AudioEngineError {
+        AudioEngineError::configuration(format!("invalid {what}: {e}"))
+    }
+
+    /// Adds JSON and TOML conversions to a serializable type; `$what`
+    /// names it in error messages.
+    macro_rules! text_formats {
+        ($ty:ty, $what:literal) => {
+            impl $ty {
+                /// Serializes to pretty-printed JSON.
+                ///
+                /// # Errors
+                /// Returns an error if a value can't be represented.
+                pub fn to_json(&self) -> $crate::error::Result<String> {
+                    serde_json::to_string_pretty(self)
+                        .map_err(|e| $crate::dsp::preset::serialization::invalid($what, e))
+                }
+
+                /// Parses JSON.
+                ///
+                /// # Errors
+                #[doc = concat!("Returns an error if the text isn't a valid ", $what, ".")]
+                pub fn from_json(text: &str) -> $crate::error::Result<Self> {
+                    serde_json::from_str(text)
+                        .map_err(|e| $crate::dsp::preset::serialization::invalid($what, e))
+                }
+
+                /// Serializes to TOML.
+                ///
+                /// # Errors
+                /// Returns an error if a value can't be represented.
+                pub fn to_toml(&self) -> $crate::error::Result<String> {
+                    toml::to_string_pretty(self)
+                        .map_err(|e| $crate::dsp::preset::serialization::invalid($what, e))
+                }
+
+                /// Parses TOML.
+                ///
+                /// # Errors
+                #[doc = concat!("Returns an error if the text isn't a valid ", $what, ".")]
+                pub fn from_toml(text: &str) -> $crate::error::Result<Self> {
+                    toml::from_str(text)
+                        .map_err(|e| $crate::dsp::preset::serialization::invalid($what, e))
+                }
+            }
+        };
+    }
+    pub(crate) use text_formats;
+
+    text_formats!(EffectPreset, "preset");
+    text_formats!(ChainPreset, "preset");
+}
+
+/// Control side of a chain's preset channel.
+///
+/// Created by [`EffectChain::preset_sender`](crate::dsp::chain::EffectChain::preset_sender).
+#[derive(Debug)]
+pub struct PresetSender {
+    presets: ControlSender<Box<ChainPreset>>,
+    returned: ControlReceiver<Box<ChainPreset>>,
+}
+
+impl PresetSender {
+    /// Queues a preset for the chain to apply at the start of its next block.
+    ///
+    /// Presets the chain has finished with are freed here.
+    ///
+    /// # Errors
+    /// Returns an error if the queue is full or the chain has been dropped.
+    pub fn send(&self, preset: ChainPreset) -> Result<()> {
+        self.collect();
+        self.presets.try_send(Box::new(preset))
+    }
+
+    /// Frees presets the chain has finished with.
+    pub fn collect(&self) {
+        while self.returned.try_recv().is_some() {}
+    }
+}
+
+/// Audio thread side of a chain's preset channel.
+#[derive(Debug)]
+pub(crate) struct PresetReceiver {
+    presets: RealtimeReceiver<Box<ChainPreset>>,
+    returned: RealtimeSender<Box<ChainPreset>>,
+}
+
+impl PresetReceiver {
+    pub(crate) fn try_recv(&self) -> Option<Box<ChainPreset>> {
+        self.presets.try_recv()
+    }
+
+    /// Hands an applied preset back to be freed on the control thread.
+    ///
+    /// If the return queue is full it is dropped here instead.
+    pub(crate) fn give_back(&self, preset: Box<ChainPreset>) {
+        let _ = self.returned.try_send(preset);
+    }
+}
+
+/// Creates a connected preset channel.
+pub(crate) fn preset_channel(capacity: usize) -> (PresetSender, PresetReceiver) {
+    let (presets, receiver) = control_channel(capacity);
+    let (returned, returned_receiver) = feedback_channel(capacity);
+    (
+        PresetSender {
+            presets,
+            returned: returned_receiver,
+        },
+        PresetReceiver {
+            presets: receiver,
+            returned,
+        },
+    )
+}
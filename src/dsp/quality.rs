@@ -0,0 +1,77 @@
+//! Effect quality tiers
+//!
+//! Some effects can trade sound quality for processing time, e.g. by
+//! re-estimating a spectral envelope less often. [`EffectQuality`] names
+//! the tiers; [`Effect::set_quality`](crate::dsp::traits::Effect::set_quality)
+//! moves an effect between them, and chains and graphs pass it on to every
+//! effect they hold. Effects without tiers ignore it.
+//!
+//! The [`QualityGovernor`](crate::engine::quality::QualityGovernor) steps
+//! an engine's effects down a tier as CPU headroom runs out and back up
+//! once it returns.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{AudioEngineError, Result};
+
+/// How much processing an effect may spend, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EffectQuality {
+    Low,
+    Medium,
+    #[default]
+    High,
+}
+
+impl EffectQuality {
+    /// Every tier, from lowest to highest
+    pub const ALL: [Self; 3] = [Self::Low, Self::Medium, Self::High];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+
+    /// The tier below, `None` at the lowest
+    #[must_use]
+    pub const fn lower(self) -> Option<Self> {
+        match self {
+            Self::Low => None,
+            Self::Medium => Some(Self::Low),
+            Self::High => Some(Self::Medium),
+        }
+    }
+
+    /// The tier above, `None` at the highest
+    #[must_use]
+    pub const fn higher(self) -> Option<Self> {
+        match self {
+            Self::Low => Some(Self::Medium),
+            Self::Medium => Some(Self::High),
+            Self::High => None,
+        }
+    }
+}
+
+impl fmt::Display for EffectQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for EffectQuality {
+    type Err = AudioEngineError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|quality| quality.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| AudioEngineError::configuration(format!("unknown effect quality {s:?}")))
+    }
+}
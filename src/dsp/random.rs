@@ -0,0 +1,99 @@
+//! Seeded random numbers
+//!
+//! Everything random in the crate draws from an explicitly seeded [`Rng`],
+//! so the same seeds always give the same output. [`derive_seed`] turns
+//! one master seed into independent seeds for each effect or source.
+
+/// Used in place of a zero seed, which would leave xorshift stuck at zero
+const ZERO_SEED_REPLACEMENT: u32 = 0x9E37_79B9;
+
+/// A small xorshift generator, cheap enough for the audio thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    #[must_use]
+    pub const fn new(seed: u32) -> Self {
+        Self {
+            state: if seed == 0 {
+                ZERO_SEED_REPLACEMENT
+            } else {
+                seed
+            },
+        }
+    }
+
+    /// Seeds from the low 32 bits of a derived seed
+    #[must_use]
+    pub fn from_seed(seed: u64) -> Self {
+        Self::new(u32::try_from(seed & u64::from(u32::MAX)).unwrap_or(0))
+    }
+
+    pub const fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    /// Uniform value in [0.0, 1.0]
+    pub fn next_unit(&mut self) -> f32 {
+        // Top 16 bits are exactly representable as f32
+        let bits = u16::try_from(self.next_u32() >> 16).unwrap_or(u16::MAX);
+        f32::from(bits) / f32::from(u16::MAX)
+    }
+
+    /// Uniform value in [-1.0, 1.0]
+    pub fn next_bipolar(&mut self) -> f32 {
+        self.next_unit().mul_add(2.0, -1.0)
+    }
+}
+
+/// Derives the seed for `stream` (an effect id, a node index, ...) from a
+/// master seed with the splitmix64 finaliser, so neighbouring streams get
+/// unrelated sequences.
+#[must_use]
+pub const fn derive_seed(master: u64, stream: u64) -> u64 {
+    let mut z = master ^ stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Scales the pink noise filter output to unity RMS
+const PINK_NOISE_SCALE: f32 = 0.07;
+
+/// Pink noise, falling 3 dB per octave: Paul Kellet's refined filter over
+/// the white noise of an [`Rng`], scaled to unity RMS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PinkNoise {
+    rng: Rng,
+    state: [f32; 7],
+}
+
+impl PinkNoise {
+    #[must_use]
+    pub const fn new(rng: Rng) -> Self {
+        Self {
+            rng,
+            state: [0.0; 7],
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let white = self.rng.next_bipolar();
+
+        let b = &mut self.state;
+        b[0] = 0.998_86f32.mul_add(b[0], white * 0.055_517_9);
+        b[1] = 0.993_32f32.mul_add(b[1], white * 0.075_075_9);
+        b[2] = 0.969_00f32.mul_add(b[2], white * 0.153_852);
+        b[3] = 0.866_50f32.mul_add(b[3], white * 0.310_485_6);
+        b[4] = 0.550_00f32.mul_add(b[4], white * 0.532_952_2);
+        b[5] = (-0.761_6f32).mul_add(b[5], -white * 0.016_898_0);
+        let value = white.mul_add(0.536_2, b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6]);
+        b[6] = white * 0.115_926;
+        value * PINK_NOISE_SCALE
+    }
+}
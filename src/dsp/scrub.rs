@@ -0,0 +1,218 @@
+//! Scrub playback
+//!
+//! [`Scrubber`] lets an editor locate material by ear, like rocking tape
+//! past the heads. The control thread streams the positions of a scrub
+//! wheel or a dragged playhead; every new position starts a short windowed
+//! grain that plays from the previous position towards it at the speed the
+//! playhead moved. Playback is varispeed, so pitch follows speed: slow
+//! drags sound low, fast ones high, and backwards drags play in reverse.
+//! When the positions stop coming the last grains fade out and the output
+//! falls silent.
+
+use crate::channel::{ControlSender, RealtimeReceiver, control_channel};
+use crate::dsp::fft::hann_window;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+/// Grains that can play at once; a new grain replaces the oldest
+const MAX_GRAINS: usize = 8;
+/// Slower movements than this (in times normal speed) play nothing
+const MIN_RATE: f64 = 0.01;
+
+/// Messages from the control side of a [`Scrubber`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScrubCommand {
+    /// The scrub point moved to this frame of the material
+    Position(f64),
+    /// The scrub wheel was let go; the next position starts afresh instead
+    /// of playing the jump to it
+    Release,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Grain {
+    /// Read position in frames
+    position: f64,
+    /// Frames advanced per output frame; negative plays backwards
+    rate: f64,
+    /// Frames played so far
+    age: usize,
+}
+
+/// Plays material around a moving scrub point.
+#[derive(Debug)]
+pub struct Scrubber {
+    channels: usize,
+    sample_rate: SampleRate,
+    max_rate: f32,
+    window: Vec<f32>,
+    grains: [Option<Grain>; MAX_GRAINS],
+    /// Last scrub position and the frame it arrived on
+    last: Option<(f64, u64)>,
+    clock: u64,
+    commands: Option<RealtimeReceiver<ScrubCommand>>,
+}
+
+impl Scrubber {
+    /// Creates a scrubber with 80 ms grains and speeds up to 4x.
+    #[must_use]
+    pub fn new(channels: ChannelCount, sample_rate: SampleRate) -> Self {
+        Self {
+            channels: channels.count_usize(),
+            sample_rate,
+            max_rate: 4.0,
+            window: window(sample_rate, 80),
+            grains: [None; MAX_GRAINS],
+            last: None,
+            clock: 0,
+            commands: None,
+        }
+    }
+
+    /// Sets the grain length. Longer grains sound smoother but lag further
+    /// behind the scrub point.
+    #[must_use]
+    pub fn with_window_ms(mut self, millis: u32) -> Self {
+        self.window = window(self.sample_rate, millis);
+        self
+    }
+
+    /// Sets the fastest playback speed, in times normal speed
+    #[must_use]
+    pub const fn with_max_rate(mut self, rate: f32) -> Self {
+        self.max_rate = rate;
+        self
+    }
+
+    /// Creates the control side of the scrubber's command channel.
+    ///
+    /// Commands sent through it are picked up at the start of each block.
+    pub fn command_sender(&mut self, capacity: usize) -> ControlSender<ScrubCommand> {
+        let (tx, rx) = control_channel(capacity);
+        self.commands = Some(rx);
+        tx
+    }
+
+    /// The last scrub position, or `None` if released
+    #[must_use]
+    pub fn position(&self) -> Option<f64> {
+        self.last.map(|(position, _)| position)
+    }
+
+    /// Returns true while any grain is still playing.
+    #[must_use]
+    pub fn is_playing(&self) -> bool {
+        self.grains.iter().any(Option::is_some)
+    }
+
+    /// Applies a command immediately.
+    pub fn apply(&mut self, command: ScrubCommand) {
+        match command {
+            ScrubCommand::Position(position) => self.scrub_to(position),
+            ScrubCommand::Release => self.last = None,
+        }
+    }
+
+    /// Stops every grain and forgets the scrub position.
+    pub const fn reset(&mut self) {
+        self.grains = [None; MAX_GRAINS];
+        self.last = None;
+    }
+
+    /// Renders the grains into `output`, reading from the interleaved
+    /// `material` the positions refer to. Reads outside the material are
+    /// silence.
+    pub fn process(&mut self, material: &[Sample], output: &mut [Sample]) {
+        while let Some(command) = self.commands.as_ref().and_then(RealtimeReceiver::try_recv) {
+            self.apply(command);
+        }
+
+        let channels = self.channels;
+        let material_frames = material.len() / channels;
+        let length = self.window.len();
+        for frame in output.chunks_exact_mut(channels) {
+            frame.fill(Sample::SILENCE);
+            let mut weight = 0.0;
+            for slot in &mut self.grains {
+                let Some(grain) = slot else {
+                    continue;
+                };
+                let level = self.window[grain.age];
+                weight += level;
+                add_frame(frame, material, material_frames, grain.position, level);
+                grain.position += grain.rate;
+                grain.age += 1;
+                if grain.age >= length {
+                    *slot = None;
+                }
+            }
+            // Overlapping grains are normalised to unity; a lone grain keeps
+            // its window so it starts and ends without a click
+            if weight > 1.0 {
+                for sample in frame.iter_mut() {
+                    *sample = Sample::new(sample.value() / weight);
+                }
+            }
+        }
+        self.clock += (output.len() / channels) as u64;
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn scrub_to(&mut self, position: f64) {
+        let Some((previous, at)) = self.last.replace((position, self.clock)) else {
+            return;
+        };
+        // After a pause, treat the move as taking one window
+        let elapsed = (self.clock - at).clamp(1, self.window.len() as u64);
+        let max_rate = f64::from(self.max_rate);
+        let rate = ((position - previous) / elapsed as f64).clamp(-max_rate, max_rate);
+        if rate.abs() < MIN_RATE {
+            return;
+        }
+
+        let grain = Grain {
+            position: previous,
+            rate,
+            age: 0,
+        };
+        let slot = self
+            .grains
+            .iter()
+            .position(Option::is_none)
+            .or_else(|| {
+                self.grains
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(_, grain)| grain.map_or(0, |g| g.age))
+                    .map(|(index, _)| index)
+            })
+            .unwrap_or(0);
+        self.grains[slot] = Some(grain);
+    }
+}
+
+/// Adds the frame at a fractional `position`, linearly interpolated and
+/// scaled by `gain`
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn add_frame(out: &mut [Sample], material: &[Sample], frames: usize, position: f64, gain: f32) {
+    if position < 0.0 {
+        return;
+    }
+    let index = position.floor() as usize;
+    if index + 1 >= frames {
+        return;
+    }
+    let fraction = (position - position.floor()) as f32;
+    let channels = out.len();
+    let first = &material[index * channels..(index + 1) * channels];
+    let second = &material[(index + 1) * channels..(index + 2) * channels];
+    for ((sample, a), b) in out.iter_mut().zip(first).zip(second) {
+        let value = (b.value() - a.value()).mul_add(fraction, a.value());
+        *sample = Sample::new(value.mul_add(gain, sample.value()));
+    }
+}
+
+fn window(sample_rate: SampleRate, millis: u32) -> Vec<f32> {
+    let mut window = vec![0.0; (sample_rate.samples_for_milliseconds(millis) as usize).max(2)];
+    hann_window(&mut window);
+    window
+}
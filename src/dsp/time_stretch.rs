@@ -0,0 +1,205 @@
+//! WSOLA time stretching
+//!
+//! [`TimeStretcher`] changes the duration of a signal without changing its
+//! pitch. It is a streaming processor for file playback and offline work,
+//! not an [`Effect`](crate::dsp::traits::Effect): the amount of output per
+//! call depends on the speed, and its buffers grow with the input handed in.
+//!
+//! Windowed segments are overlap-added at a fixed synthesis hop while the
+//! read position advances by `hop * speed`. Each segment's start is nudged
+//! within a small tolerance to the position that best continues the previous
+//! segment (waveform-similarity overlap-add), which avoids phasing on tonal
+//! material.
+
+use crate::dsp::fft::hann_window;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+/// Slowest supported speed
+pub const MIN_SPEED: f32 = 0.25;
+/// Fastest supported speed
+pub const MAX_SPEED: f32 = 4.0;
+
+/// Only every Nth frame takes part in the similarity search
+const SEARCH_DECIMATION: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct TimeStretcher {
+    channels: usize,
+    speed: f32,
+    window_frames: usize,
+    hop_frames: usize,
+    tolerance_frames: usize,
+    window: Vec<f32>,
+    /// Buffered interleaved input that future segments may still read
+    input: Vec<Sample>,
+    /// Read position in frames relative to the start of `input`
+    read_position: f64,
+    /// Start of the previous segment relative to the start of `input`
+    previous_start: Option<usize>,
+    /// Overlap-add accumulator, one window long
+    accumulator: Vec<f32>,
+}
+
+impl TimeStretcher {
+    /// Creates a stretcher with 40 ms windows and a 10 ms search tolerance.
+    #[must_use]
+    pub fn new(channels: ChannelCount, sample_rate: SampleRate) -> Self {
+        Self::with_window_ms(channels, sample_rate, 40, 10)
+    }
+
+    /// Creates a stretcher with the given window length and search tolerance.
+    #[must_use]
+    pub fn with_window_ms(
+        channels: ChannelCount,
+        sample_rate: SampleRate,
+        window_ms: u32,
+        tolerance_ms: u32,
+    ) -> Self {
+        let channels = channels.count_usize();
+        let window_frames = (sample_rate.samples_for_milliseconds(window_ms) as usize).max(16) & !1;
+        let mut window = vec![0.0; window_frames];
+        hann_window(&mut window);
+        Self {
+            channels,
+            speed: 1.0,
+            window_frames,
+            hop_frames: window_frames / 2,
+            tolerance_frames: sample_rate.samples_for_milliseconds(tolerance_ms) as usize,
+            window,
+            input: Vec::new(),
+            read_position: 0.0,
+            previous_start: None,
+            accumulator: vec![0.0; window_frames * channels],
+        }
+    }
+
+    /// Playback speed; 2.0 plays twice as fast, 0.5 at half speed.
+    #[must_use]
+    pub const fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Sets the speed, clamped to [`MIN_SPEED`]..=[`MAX_SPEED`]. Takes effect
+    /// from the next segment.
+    pub const fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// Delay in output frames between input and output at speed 1.0
+    #[must_use]
+    pub const fn latency_frames(&self) -> usize {
+        self.window_frames + self.tolerance_frames
+    }
+
+    /// Clears all buffered audio.
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.read_position = 0.0;
+        self.previous_start = None;
+        self.accumulator.fill(0.0);
+    }
+
+    /// Feeds interleaved input and appends whatever output is ready.
+    pub fn process(&mut self, input: &[Sample], output: &mut Vec<Sample>) {
+        self.input.extend_from_slice(input);
+        while self.step(output) {}
+        self.discard_consumed();
+    }
+
+    /// Pushes out the remaining buffered audio by padding with silence.
+    pub fn flush(&mut self, output: &mut Vec<Sample>) {
+        let padding = vec![Sample::SILENCE; self.latency_frames() * 2 * self.channels];
+        self.process(&padding, output);
+        self.reset();
+    }
+
+    const fn buffered_frames(&self) -> usize {
+        self.input.len() / self.channels
+    }
+
+    /// Produces one hop of output if enough input is buffered.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn step(&mut self, output: &mut Vec<Sample>) -> bool {
+        let nominal = self.read_position.round() as usize;
+        if nominal + self.tolerance_frames + self.window_frames > self.buffered_frames() {
+            return false;
+        }
+        let start = self.previous_start.map_or(nominal, |previous| {
+            self.best_start(previous + self.hop_frames, nominal)
+        });
+
+        let channels = self.channels;
+        let segment = &self.input[start * channels..(start + self.window_frames) * channels];
+        for ((acc, sample), weight) in self.accumulator.iter_mut().zip(segment).zip(
+            self.window
+                .iter()
+                .flat_map(|&w| std::iter::repeat_n(w, channels)),
+        ) {
+            *acc = sample.value().mul_add(weight, *acc);
+        }
+
+        let hop = self.hop_frames * channels;
+        output.extend(self.accumulator[..hop].iter().map(|&v| Sample::new(v)));
+        self.accumulator.copy_within(hop.., 0);
+        let len = self.accumulator.len();
+        self.accumulator[len - hop..].fill(0.0);
+
+        self.previous_start = Some(start);
+        self.read_position += self.hop_frames as f64 * f64::from(self.speed);
+        true
+    }
+
+    /// Finds the segment start near `nominal` whose opening best matches the
+    /// natural continuation of the previous segment at `target`.
+    fn best_start(&self, target: usize, nominal: usize) -> usize {
+        let first = nominal.saturating_sub(self.tolerance_frames);
+        let last = nominal + self.tolerance_frames;
+        let mut best = (nominal, f32::MIN);
+        for candidate in first..=last {
+            let score = self.similarity(target, candidate);
+            if score > best.1 {
+                best = (candidate, score);
+            }
+        }
+        best.0
+    }
+
+    /// Cross-correlation over one hop of the channel sum.
+    fn similarity(&self, a: usize, b: usize) -> f32 {
+        let channels = self.channels;
+        let frame_sum = |frame: usize| -> f32 {
+            self.input[frame * channels..(frame + 1) * channels]
+                .iter()
+                .map(|s| s.value())
+                .sum()
+        };
+        (0..self.hop_frames)
+            .step_by(SEARCH_DECIMATION)
+            .map(|i| frame_sum(a + i) * frame_sum(b + i))
+            .sum()
+    }
+
+    /// Drops input that no future segment can reach.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn discard_consumed(&mut self) {
+        let nominal = self.read_position.round();
+        let reachable = (nominal as usize).saturating_sub(self.tolerance_frames);
+        let keep_from = self.previous_start.map_or(reachable, |previous| {
+            reachable.min(previous + self.hop_frames)
+        });
+        if keep_from < self.window_frames * 4 {
+            return;
+        }
+        self.input.drain(..keep_from * self.channels);
+        self.read_position -= keep_from as f64;
+        self.previous_start = self.previous_start.map(|p| p - keep_from);
+    }
+}
@@ -1,9 +1,14 @@
-use crate::types::{ChannelCount, Sample, SampleRate};
+use crate::types::{
+    ChannelCount, MusicalTime, Sample, SampleRate, TempoMap, TimeSignature, Timestamp,
+};
 use std::fmt;
 
-use super::params::{ParamId, ParamValue, ParameterInfo};
+use super::params::{ParamId, ParamValue, ParameterInfo, SmoothingMode};
+use super::preset::EffectPreset;
+use super::quality::EffectQuality;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EffectId(u32);
 
 impl EffectId {
@@ -41,18 +46,83 @@ pub trait Effect: Send + 'static {
     fn parameters(&self) -> &[ParameterInfo];
     fn get_parameter(&self, id: ParamId) -> Option<ParamValue>;
     fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool;
+    /// Whether the effect can process `channels`. Chains bypass effects
+    /// that can't, and say which when they are initialized; see
+    /// [`check_channels`](crate::dsp::channels::check_channels).
+    fn supports_channels(&self, channels: ChannelCount) -> bool {
+        let _ = channels;
+        true
+    }
     fn latency_samples(&self) -> u32 {
         0
     }
     fn tail_samples(&self) -> u32 {
         0
     }
+    /// Bytes the effect holds: its own size plus the buffers it allocated
+    /// up front, such as delay lines, FFT frames and scratch space. Effects
+    /// with heap buffers override this.
+    fn memory_bytes(&self) -> usize {
+        size_of_val(self)
+    }
+    /// Restarts any random generators (noise, dither) from `seed`, so
+    /// processing the same input again gives the same output.
+    fn reseed(&mut self, seed: u64) {
+        let _ = seed;
+    }
+    /// Moves the effect to a quality tier, trading sound for processing
+    /// time. Effects without tiers ignore it.
+    fn set_quality(&mut self, quality: EffectQuality) {
+        let _ = quality;
+    }
+    /// Tells the effect where the coming block falls on the timeline, so
+    /// tempo synced delays and LFOs can follow the tempo map.
+    fn set_context(&mut self, context: &ProcessContext) {
+        let _ = context;
+    }
+    /// Whether the effect has failed for good since the last call, e.g.
+    /// an out-of-process effect whose process died. A failure is reported
+    /// once; the chain then bypasses the effect. See
+    /// [`EffectChain::bypass_failed`](crate::dsp::chain::EffectChain::bypass_failed).
+    fn take_failure(&mut self) -> bool {
+        false
+    }
+
+    /// Applies a preset's enabled state and parameter values.
+    ///
+    /// Returns false if any parameter was rejected; the others are still
+    /// applied.
+    fn apply_preset(&mut self, preset: &EffectPreset) -> bool {
+        self.set_enabled(preset.enabled);
+        let mut ok = true;
+        for &(id, value) in &preset.parameters {
+            ok &= self.set_parameter(id, value);
+        }
+        ok
+    }
 }
 
+/// An effect whose parameters ramp to new values instead of jumping.
+///
+/// [`AutoGain`](crate::dsp::auto_gain::AutoGain) and
+/// [`EqMatch`](crate::dsp::eq_match::EqMatch) don't implement it: their
+/// parameters set a target the effect already moves towards gradually, at
+/// its own rate for auto gain and through the gain ramps of the band
+/// filters for EQ match.
 pub trait SmoothableEffect: Effect {
+    /// Sets a parameter, ramping to it over `samples` samples.
     fn set_parameter_smooth(&mut self, id: ParamId, value: ParamValue, samples: u32);
+    /// Moves every ramp in progress forward by one sample without processing
+    /// audio, e.g. to keep ramps running while the effect is bypassed.
     fn update_smoothing(&mut self);
+    /// Chooses how a parameter ramps. Returns false if the parameter isn't
+    /// smoothed.
+    fn set_smoothing_mode(&mut self, id: ParamId, mode: SmoothingMode) -> bool;
 }
+/// Where a block falls on the timeline.
+///
+/// The musical fields are `None` unless the context was built from a
+/// [`TempoMap`].
 #[derive(Debug, Clone, Copy)]
 pub struct ProcessContext {
     pub sample_rate: SampleRate,
@@ -60,6 +130,12 @@ pub struct ProcessContext {
     pub frames: usize,
     pub position_samples: u64,
     pub tempo_bpm: Option<f32>,
+    /// Whether the transport is rolling
+    pub playing: bool,
+    pub signature: Option<TimeSignature>,
+    pub musical_time: Option<MusicalTime>,
+    /// Quarter notes from the start of the timeline
+    pub beats: Option<f64>,
 }
 
 impl ProcessContext {
@@ -71,6 +147,62 @@ impl ProcessContext {
             frames,
             position_samples: 0,
             tempo_bpm: None,
+            playing: false,
+            signature: None,
+            musical_time: None,
+            beats: None,
         }
     }
+
+    /// Places the block at `position` on the timeline of `map`.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn with_timeline(mut self, map: &TempoMap, position: Timestamp, playing: bool) -> Self {
+        self.position_samples = position.as_samples();
+        self.tempo_bpm = Some(map.tempo_at(position).bpm() as f32);
+        self.playing = playing;
+        self.signature = Some(map.signature_at(position));
+        self.musical_time = Some(map.musical_time_at(position));
+        self.beats = Some(map.beats_at(position));
+        self
+    }
+
+    /// The context of a sub-block starting `offset` frames into this block
+    /// and `frames` long. The tempo is taken to hold through the block.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn sub_block(&self, offset: usize, frames: usize) -> Self {
+        let mut context = *self;
+        context.frames = frames;
+        if !self.playing || offset == 0 {
+            return context;
+        }
+        context.position_samples += offset as u64;
+        if let Some(samples_per_beat) = self.samples_per_beat() {
+            let beats = offset as f64 / samples_per_beat;
+            context.beats = self.beats.map(|start| start + beats);
+            context.musical_time = self
+                .musical_time
+                .zip(self.signature)
+                .map(|(time, signature)| time.advanced(beats, signature.bar_beats()));
+        }
+        context
+    }
+
+    /// Length of a quarter note in samples at the current tempo
+    #[must_use]
+    pub fn samples_per_beat(&self) -> Option<f64> {
+        self.tempo_bpm
+            .map(|bpm| 60.0 / f64::from(bpm) * f64::from(self.sample_rate.as_hz()))
+    }
+
+    /// Phase (0.0 - 1.0) of a cycle `beats_per_cycle` quarter notes long
+    /// that started with the timeline, so synced LFOs line up with bars
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn beat_phase(&self, beats_per_cycle: f64) -> Option<f32> {
+        self.beats
+            .filter(|_| beats_per_cycle > 0.0)
+            .map(|beats| (beats / beats_per_cycle).rem_euclid(1.0) as f32)
+    }
 }
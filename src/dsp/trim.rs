@@ -0,0 +1,371 @@
+//! Trim / channel strip utilities
+//!
+//! [`Trim`] covers the basic gain staging steps of a channel strip: input
+//! gain, polarity invert, mute and solo per channel, and left/right swap.
+//! Every change is ramped, so toggling any of them doesn't click.
+
+use crate::dsp::params::{
+    ParamId, ParamKind, ParamValue, ParameterInfo, SmoothParam, SmoothingMode,
+};
+use crate::dsp::traits::{Effect, EffectId, SmoothableEffect};
+use crate::types::{ChannelCount, Gain, Sample, SampleRate};
+
+/// Most channels a trim addresses individually
+pub const MAX_CHANNELS: usize = 8;
+
+pub mod params {
+    use super::ParamId;
+    /// Gain in dB, applied to every channel
+    pub const GAIN_DB: ParamId = ParamId::new(0);
+    /// Swap the first two channels
+    pub const SWAP: ParamId = ParamId::new(1);
+
+    const INVERT_BASE: u32 = 16;
+    const MUTE_BASE: u32 = 32;
+    const SOLO_BASE: u32 = 48;
+
+    /// Polarity invert of a channel
+    #[must_use]
+    pub const fn invert(channel: u8) -> ParamId {
+        ParamId::new(INVERT_BASE + channel as u32)
+    }
+
+    /// Mute of a channel
+    #[must_use]
+    pub const fn mute(channel: u8) -> ParamId {
+        ParamId::new(MUTE_BASE + channel as u32)
+    }
+
+    /// Solo of a channel; while any channel is soloed the others are silent
+    #[must_use]
+    pub const fn solo(channel: u8) -> ParamId {
+        ParamId::new(SOLO_BASE + channel as u32)
+    }
+
+    /// Splits a per-channel parameter into its kind base and channel.
+    pub(super) const fn channel_param(id: ParamId) -> Option<(u32, usize)> {
+        let value = id.value();
+        let base = value & !15;
+        let channel = (value & 15) as usize;
+        if channel < super::MAX_CHANNELS
+            && (base == INVERT_BASE || base == MUTE_BASE || base == SOLO_BASE)
+        {
+            Some((base, channel))
+        } else {
+            None
+        }
+    }
+
+    pub(super) const fn is_invert(base: u32) -> bool {
+        base == INVERT_BASE
+    }
+
+    pub(super) const fn is_mute(base: u32) -> bool {
+        base == MUTE_BASE
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct ChannelFlags {
+    invert: bool,
+    mute: bool,
+    solo: bool,
+}
+
+#[derive(Debug)]
+pub struct Trim {
+    id: EffectId,
+    enabled: bool,
+    gain_db: f32,
+    swap: bool,
+    flags: [ChannelFlags; MAX_CHANNELS],
+    /// Combined gain, polarity and mute/solo factor per channel
+    channel_gains: [SmoothParam; MAX_CHANNELS],
+    /// 0.0 straight through, 1.0 fully swapped
+    swap_amount: SmoothParam,
+    sample_rate: SampleRate,
+    param_info: Vec<ParameterInfo>,
+}
+
+impl Trim {
+    #[must_use]
+    pub fn new(id: EffectId) -> Self {
+        let mut param_info = vec![
+            ParameterInfo::new(params::GAIN_DB, "Gain")
+                .with_short_name("Gain")
+                .with_range(-24.0, 24.0)
+                .with_default(0.0)
+                .with_unit("dB")
+                .with_precision(1),
+            ParameterInfo::new(params::SWAP, "Swap L/R")
+                .with_short_name("Swap")
+                .with_default(0.0)
+                .with_kind(ParamKind::Bool),
+        ];
+        for channel in 0..MAX_CHANNELS {
+            let number = channel + 1;
+            #[allow(clippy::cast_possible_truncation)]
+            let channel = channel as u8;
+            for (id, name, short) in [
+                (params::invert(channel), "Invert", "Inv"),
+                (params::mute(channel), "Mute", "M"),
+                (params::solo(channel), "Solo", "S"),
+            ] {
+                param_info.push(
+                    ParameterInfo::new(id, format!("{name} {number}"))
+                        .with_short_name(format!("{short}{number}"))
+                        .with_default(0.0)
+                        .with_kind(ParamKind::Bool),
+                );
+            }
+        }
+
+        Self {
+            id,
+            enabled: true,
+            gain_db: 0.0,
+            swap: false,
+            flags: [ChannelFlags::default(); MAX_CHANNELS],
+            channel_gains: [SmoothParam::new(1.0); MAX_CHANNELS],
+            swap_amount: SmoothParam::new(0.0),
+            sample_rate: SampleRate::Hz48000,
+            param_info,
+        }
+    }
+
+    pub fn set_gain_db(&mut self, db: f32) {
+        self.gain_db = db.clamp(-24.0, 24.0);
+        self.update_targets(self.smoothing_samples());
+    }
+
+    #[must_use]
+    pub const fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    pub fn set_swap(&mut self, swap: bool) {
+        self.swap = swap;
+        let samples = self.smoothing_samples();
+        self.swap_amount
+            .set_target(if swap { 1.0 } else { 0.0 }, samples);
+    }
+
+    #[must_use]
+    pub const fn is_swapped(&self) -> bool {
+        self.swap
+    }
+
+    /// Sets polarity invert for a channel; out of range channels are ignored.
+    pub fn set_invert(&mut self, channel: usize, invert: bool) {
+        if let Some(flags) = self.flags.get_mut(channel) {
+            flags.invert = invert;
+            self.update_targets(self.smoothing_samples());
+        }
+    }
+
+    /// Mutes or unmutes a channel; out of range channels are ignored.
+    pub fn set_mute(&mut self, channel: usize, mute: bool) {
+        if let Some(flags) = self.flags.get_mut(channel) {
+            flags.mute = mute;
+            self.update_targets(self.smoothing_samples());
+        }
+    }
+
+    /// Solos or unsolos a channel; out of range channels are ignored.
+    pub fn set_solo(&mut self, channel: usize, solo: bool) {
+        if let Some(flags) = self.flags.get_mut(channel) {
+            flags.solo = solo;
+            self.update_targets(self.smoothing_samples());
+        }
+    }
+
+    #[must_use]
+    pub fn is_inverted(&self, channel: usize) -> bool {
+        self.flags.get(channel).is_some_and(|f| f.invert)
+    }
+
+    #[must_use]
+    pub fn is_muted(&self, channel: usize) -> bool {
+        self.flags.get(channel).is_some_and(|f| f.mute)
+    }
+
+    #[must_use]
+    pub fn is_soloed(&self, channel: usize) -> bool {
+        self.flags.get(channel).is_some_and(|f| f.solo)
+    }
+
+    /// Returns true if the channel is currently audible, taking solo into account.
+    #[must_use]
+    pub fn is_audible(&self, channel: usize) -> bool {
+        let any_solo = self.flags.iter().any(|f| f.solo);
+        self.flags
+            .get(channel)
+            .is_some_and(|f| !f.mute && (!any_solo || f.solo))
+    }
+
+    fn smoothing_samples(&self) -> u32 {
+        self.sample_rate.samples_for_milliseconds(10)
+    }
+
+    fn update_targets(&mut self, samples: u32) {
+        let gain = Gain::from_db(self.gain_db).as_linear();
+        for channel in 0..MAX_CHANNELS {
+            let polarity = if self.flags[channel].invert {
+                -1.0
+            } else {
+                1.0
+            };
+            let target = if self.is_audible(channel) {
+                gain * polarity
+            } else {
+                0.0
+            };
+            self.channel_gains[channel].set_target(target, samples);
+        }
+    }
+}
+
+impl Effect for Trim {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "Trim"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn reset(&mut self) {
+        for gain in &mut self.channel_gains {
+            gain.set_immediate(gain.target());
+        }
+        self.swap_amount.set_immediate(self.swap_amount.target());
+    }
+
+    fn initialize(&mut self, sample_rate: SampleRate, _channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        if !self.enabled {
+            return;
+        }
+
+        let channel_count = channels.count_usize().min(MAX_CHANNELS);
+        for frame in samples.chunks_exact_mut(channels.count_usize()) {
+            let swap = self.swap_amount.next();
+            if channel_count >= 2 && swap > 0.0 {
+                let (left, right) = (frame[0].value(), frame[1].value());
+                frame[0] = Sample::new((right - left).mul_add(swap, left));
+                frame[1] = Sample::new((left - right).mul_add(swap, right));
+            }
+            for (sample, gain) in frame
+                .iter_mut()
+                .zip(self.channel_gains.iter_mut())
+                .take(channel_count)
+            {
+                *sample = Sample::new(sample.value() * gain.next());
+            }
+        }
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &self.param_info
+    }
+
+    fn get_parameter(&self, id: ParamId) -> Option<ParamValue> {
+        match id {
+            params::GAIN_DB => Some(ParamValue::Float(self.gain_db)),
+            params::SWAP => Some(ParamValue::Bool(self.swap)),
+            _ => {
+                let (base, channel) = params::channel_param(id)?;
+                let flags = self.flags[channel];
+                Some(ParamValue::Bool(if params::is_invert(base) {
+                    flags.invert
+                } else if params::is_mute(base) {
+                    flags.mute
+                } else {
+                    flags.solo
+                }))
+            }
+        }
+    }
+
+    fn set_parameter(&mut self, id: ParamId, value: ParamValue) -> bool {
+        match id {
+            params::GAIN_DB => self.set_gain_db(value.as_float()),
+            params::SWAP => self.set_swap(value.as_bool()),
+            _ => {
+                let Some((base, channel)) = params::channel_param(id) else {
+                    return false;
+                };
+                if params::is_invert(base) {
+                    self.set_invert(channel, value.as_bool());
+                } else if params::is_mute(base) {
+                    self.set_mute(channel, value.as_bool());
+                } else {
+                    self.set_solo(channel, value.as_bool());
+                }
+            }
+        }
+        true
+    }
+}
+
+impl SmoothableEffect for Trim {
+    fn set_parameter_smooth(&mut self, id: ParamId, value: ParamValue, samples: u32) {
+        match id {
+            params::GAIN_DB => self.gain_db = value.as_float().clamp(-24.0, 24.0),
+            params::SWAP => {
+                self.swap = value.as_bool();
+                let target = if self.swap { 1.0 } else { 0.0 };
+                self.swap_amount.set_target(target, samples);
+                return;
+            }
+            _ => {
+                let Some((base, channel)) = params::channel_param(id) else {
+                    return;
+                };
+                let flags = &mut self.flags[channel];
+                if params::is_invert(base) {
+                    flags.invert = value.as_bool();
+                } else if params::is_mute(base) {
+                    flags.mute = value.as_bool();
+                } else {
+                    flags.solo = value.as_bool();
+                }
+            }
+        }
+        self.update_targets(samples);
+    }
+
+    fn update_smoothing(&mut self) {
+        for gain in &mut self.channel_gains {
+            gain.advance(1);
+        }
+        self.swap_amount.advance(1);
+    }
+
+    /// The gain, invert, mute and solo parameters all drive the same
+    /// per-channel ramps, so setting the mode for any of them sets it for all.
+    fn set_smoothing_mode(&mut self, id: ParamId, mode: SmoothingMode) -> bool {
+        if id == params::SWAP {
+            self.swap_amount.set_mode(mode);
+        } else if id == params::GAIN_DB || params::channel_param(id).is_some() {
+            for gain in &mut self.channel_gains {
+                gain.set_mode(mode);
+            }
+        } else {
+            return false;
+        }
+        true
+    }
+}
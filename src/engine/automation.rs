@@ -0,0 +1,128 @@
+//! Automation recording
+//!
+//! While [`EngineCommand::RecordAutomation`] is on and the transport
+//! plays, the audio thread reports each parameter change it applies, from
+//! MIDI, OSC, a remote or the host's own controls alike, as
+//! [`EngineFeedback::ParamRecorded`]. An [`AutomationRecorder`] on the
+//! control thread collects those into [`AutomationLane`]s, one per
+//! parameter, and thins them when the pass is done, so a fader held still
+//! for a minute leaves two points rather than thousands.
+//!
+//! ```no_run
+//! use audio_engine::channel::EngineCommand;
+//! use audio_engine::engine::Engine;
+//! use audio_engine::engine::automation::AutomationRecorder;
+//!
+//! let mut engine = Engine::builder().build()?;
+//! let mut recorder = AutomationRecorder::new();
+//! engine.start()?;
+//! engine.send(EngineCommand::RecordAutomation(true))?;
+//! // ... each time round the control loop:
+//! while let Some(feedback) = engine.feedback().try_recv() {
+//!     recorder.capture(&feedback);
+//! }
+//! engine.send(EngineCommand::RecordAutomation(false))?;
+//! let lanes = recorder.finish();
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! [`EngineCommand::RecordAutomation`]: crate::channel::EngineCommand::RecordAutomation
+//! [`EngineFeedback::ParamRecorded`]: crate::channel::EngineFeedback::ParamRecorded
+
+use crate::channel::EngineFeedback;
+use crate::dsp::automation::{AutomationLane, AutomationPoint};
+use crate::dsp::params::ParamId;
+use crate::dsp::traits::EffectId;
+
+/// Default thinning tolerance, as a fraction of a lane's range of values
+pub const DEFAULT_TOLERANCE: f32 = 0.005;
+
+/// Collects recorded parameter changes into lanes.
+#[derive(Debug, Clone)]
+pub struct AutomationRecorder {
+    lanes: Vec<AutomationLane>,
+    tolerance: f32,
+}
+
+impl Default for AutomationRecorder {
+    fn default() -> Self {
+        Self {
+            lanes: Vec::new(),
+            tolerance: DEFAULT_TOLERANCE,
+        }
+    }
+}
+
+impl AutomationRecorder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the tolerance [`finish`](Self::finish) thins lanes with; see
+    /// [`AutomationLane::thin`].
+    #[must_use]
+    pub const fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Adds the change in `feedback` to its parameter's lane. Returns
+    /// whether `feedback` was a recorded change.
+    pub fn capture(&mut self, feedback: &EngineFeedback) -> bool {
+        let EngineFeedback::ParamRecorded {
+            position,
+            effect_id,
+            param_id,
+            value,
+        } = *feedback
+        else {
+            return false;
+        };
+        let (effect_id, param_id) = (EffectId::new(effect_id), ParamId::new(param_id));
+        let index = self
+            .lanes
+            .iter()
+            .position(|lane| lane.effect_id == effect_id && lane.param_id == param_id)
+            .unwrap_or_else(|| {
+                self.lanes.push(AutomationLane::new(effect_id, param_id));
+                self.lanes.len() - 1
+            });
+        self.lanes[index].insert(AutomationPoint::new(position, value));
+        true
+    }
+
+    /// The lane recorded so far for a parameter, not yet thinned
+    #[must_use]
+    pub fn lane(&self, effect_id: EffectId, param_id: ParamId) -> Option<&AutomationLane> {
+        self.lanes
+            .iter()
+            .find(|lane| lane.effect_id == effect_id && lane.param_id == param_id)
+    }
+
+    /// Lanes recorded so far, in the order their parameters first moved
+    #[must_use]
+    pub fn lanes(&self) -> &[AutomationLane] {
+        &self.lanes
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.lanes.is_empty()
+    }
+
+    /// Ends the pass, returning its lanes thinned and leaving the recorder
+    /// empty for the next.
+    pub fn finish(&mut self) -> Vec<AutomationLane> {
+        let mut lanes = std::mem::take(&mut self.lanes);
+        for lane in &mut lanes {
+            lane.thin(self.tolerance);
+        }
+        lanes
+    }
+
+    /// Throws the pass away.
+    pub fn clear(&mut self) {
+        self.lanes.clear();
+    }
+}
@@ -0,0 +1,285 @@
+//! Stream bitrate under network pressure
+//!
+//! A stream whose connection can't keep up with its bitrate first queues
+//! audio and then drops it. A [`BitrateGovernor`] watches an
+//! [`AdaptiveStream`], such as an
+//! [`IcecastSource`](crate::io::icecast::IcecastSource): how much audio is
+//! queued for its encoder, and how fast the encoded stream goes out. When
+//! the queue builds up, or the stream sends slower than its bitrate while
+//! audio waits, it steps the bitrate down to the next rung of a ladder of
+//! common bitrates. Once the queue has stayed nearly empty, with the
+//! stream keeping up, for a while it steps back up, one rung at a time.
+//! Each step is reported as [`EngineFeedback::BitrateChanged`].
+//!
+//! The steps stay within the [`BitrateSettings`] bounds, and the gap
+//! between the thresholds and the long wait before raising keep a
+//! borderline link from flapping between two rates.
+//!
+//! ```no_run
+//! use audio_engine::engine::Engine;
+//! use audio_engine::engine::bitrate::{AdaptiveStream, BitrateGovernor, BitrateSettings};
+//!
+//! fn keep_streaming(engine: &Engine, stream: &mut impl AdaptiveStream) {
+//!     let mut governor = BitrateGovernor::new(BitrateSettings::default());
+//!     loop {
+//!         // Write the next block to the stream, then
+//!         if let Some(change) = governor.poll(stream, engine) {
+//!             println!("streaming at {} now", change.bitrate);
+//!         }
+//!         std::thread::sleep(std::time::Duration::from_millis(10));
+//!     }
+//! }
+//! ```
+//!
+//! [`EngineFeedback::BitrateChanged`]: crate::channel::EngineFeedback::BitrateChanged
+
+use std::time::{Duration, Instant};
+
+use crate::channel::EngineFeedback;
+use crate::engine::Engine;
+use crate::error::Result;
+use crate::io::NetworkOutput;
+use crate::types::StreamBitrate;
+
+/// Bitrates the governor steps between, in kbps, all valid for MP3 and
+/// Opus
+const LADDER_KBPS: [u32; 9] = [32, 48, 64, 96, 128, 160, 192, 256, 320];
+
+/// A network stream whose bitrate can change while it runs.
+pub trait AdaptiveStream {
+    /// The output streamed to, with the bitrate in use
+    fn output(&self) -> &NetworkOutput;
+
+    /// Moves the stream to `bitrate`.
+    ///
+    /// # Errors
+    /// Returns an error if the codec can't encode at `bitrate` or the
+    /// encoder can't be restarted; the stream then goes on as it was.
+    fn set_bitrate(&mut self, bitrate: StreamBitrate) -> Result<()>;
+
+    /// Share of the send queue filled, from 0 to 1
+    fn backlog(&self) -> f32;
+
+    /// Encoded bytes sent so far
+    fn bytes_sent(&self) -> u64;
+
+    /// Samples dropped so far because the queue was full
+    fn dropped(&self) -> u64;
+}
+
+/// When a [`BitrateGovernor`] steps the bitrate down and up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitrateSettings {
+    /// Lowest bitrate to step down to
+    pub min: StreamBitrate,
+    /// Highest bitrate to step up to
+    pub max: StreamBitrate,
+    /// Peak backlog within a window at which the bitrate steps down
+    pub lower_above: f32,
+    /// Peak backlog every window must stay under for
+    /// [`raise_after`](Self::raise_after) before the bitrate steps up
+    pub raise_below: f32,
+    /// Share of the bitrate the stream must send at to count as keeping
+    /// up
+    pub throughput_margin: f32,
+    pub window: Duration,
+    pub raise_after: Duration,
+}
+
+impl Default for BitrateSettings {
+    fn default() -> Self {
+        Self {
+            min: StreamBitrate::from_kbps(64),
+            max: StreamBitrate::KBPS_320,
+            lower_above: 0.25,
+            raise_below: 0.05,
+            throughput_margin: 0.8,
+            window: Duration::from_secs(2),
+            raise_after: Duration::from_secs(30),
+        }
+    }
+}
+
+impl BitrateSettings {
+    /// Sets the lowest and highest bitrate.
+    #[must_use]
+    pub const fn with_bounds(mut self, min: StreamBitrate, max: StreamBitrate) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// Sets the backlogs the bitrate steps down at and up below.
+    #[must_use]
+    pub const fn with_thresholds(mut self, lower_above: f32, raise_below: f32) -> Self {
+        self.lower_above = lower_above;
+        self.raise_below = raise_below;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_throughput_margin(mut self, margin: f32) -> Self {
+        self.throughput_margin = margin;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_raise_after(mut self, duration: Duration) -> Self {
+        self.raise_after = duration;
+        self
+    }
+
+    /// The rung below `bitrate`, if it is still within the bounds
+    fn lower(&self, bitrate: StreamBitrate) -> Option<StreamBitrate> {
+        LADDER_KBPS
+            .iter()
+            .rev()
+            .map(|&kbps| StreamBitrate::from_kbps(kbps))
+            .find(|rung| rung.as_bps() < bitrate.as_bps())
+            .filter(|rung| rung.as_bps() >= self.min.as_bps())
+    }
+
+    /// The rung above `bitrate`, if it is still within the bounds
+    fn higher(&self, bitrate: StreamBitrate) -> Option<StreamBitrate> {
+        LADDER_KBPS
+            .iter()
+            .map(|&kbps| StreamBitrate::from_kbps(kbps))
+            .find(|rung| rung.as_bps() > bitrate.as_bps())
+            .filter(|rung| rung.as_bps() <= self.max.as_bps())
+    }
+}
+
+/// A bitrate change made by [`BitrateGovernor::poll`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitrateChange {
+    pub previous: StreamBitrate,
+    pub bitrate: StreamBitrate,
+    /// Peak backlog of the window that set it off
+    pub backlog: f32,
+    /// Bits per second sent during that window
+    pub throughput: f64,
+}
+
+/// Steps a stream's bitrate down when its connection falls behind and
+/// back up once it keeps up again.
+#[derive(Debug)]
+pub struct BitrateGovernor {
+    settings: BitrateSettings,
+    window_start: Instant,
+    /// Highest backlog sampled this window
+    peak: f32,
+    /// Stream counters as of the window start
+    bytes_sent: u64,
+    dropped: u64,
+    /// Start of the run of calm windows
+    calm_since: Option<Instant>,
+    changes: u32,
+}
+
+impl BitrateGovernor {
+    #[must_use]
+    pub fn new(settings: BitrateSettings) -> Self {
+        Self {
+            settings,
+            window_start: Instant::now(),
+            peak: 0.0,
+            bytes_sent: 0,
+            dropped: 0,
+            calm_since: None,
+            changes: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &BitrateSettings {
+        &self.settings
+    }
+
+    /// Bitrate changes made so far
+    #[must_use]
+    pub const fn changes(&self) -> u32 {
+        self.changes
+    }
+
+    /// Samples the stream's backlog and, at the end of each window, steps
+    /// its bitrate down if it fell behind, or up after enough calm
+    /// windows, reporting the change on `engine`'s feedback channel. Call
+    /// it regularly from the thread that writes to the stream.
+    pub fn poll(
+        &mut self,
+        stream: &mut impl AdaptiveStream,
+        engine: &Engine,
+    ) -> Option<BitrateChange> {
+        let now = Instant::now();
+        self.peak = self.peak.max(stream.backlog());
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < self.settings.window {
+            return None;
+        }
+
+        let (backlog, started) = (self.peak, self.window_start);
+        let sent = stream.bytes_sent().saturating_sub(self.bytes_sent);
+        let dropped = stream.dropped() > self.dropped;
+        self.restart_window(stream, now);
+        #[allow(clippy::cast_precision_loss)]
+        let throughput = sent as f64 * 8.0 / elapsed.as_secs_f64();
+        let current = stream.output().audio_bitrate;
+        let keeping_up =
+            throughput >= f64::from(self.settings.throughput_margin) * f64::from(current.as_bps());
+        let bitrate = if dropped
+            || backlog >= self.settings.lower_above
+            || (!keeping_up && backlog >= self.settings.raise_below)
+        {
+            self.calm_since = None;
+            self.settings.lower(current)
+        } else if backlog < self.settings.raise_below && keeping_up {
+            let calm_since = *self.calm_since.get_or_insert(started);
+            if now.duration_since(calm_since) < self.settings.raise_after {
+                return None;
+            }
+            self.calm_since = None;
+            self.settings.higher(current)
+        } else {
+            self.calm_since = None;
+            None
+        }?;
+
+        let url = stream.output().url.to_string();
+        if let Err(e) = stream.set_bitrate(bitrate) {
+            log::error!("Bitrate governor: moving {url} to {bitrate} failed: {e}");
+            return None;
+        }
+        // The new encoder starts with an empty queue
+        self.restart_window(stream, Instant::now());
+        self.changes += 1;
+        log::info!(
+            "Bitrate governor: {url} {current} -> {bitrate} at {backlog:.2} backlog, \
+             {:.0} kbps sent",
+            throughput / 1000.0
+        );
+        let _ = engine.report(EngineFeedback::BitrateChanged {
+            url,
+            previous: current,
+            bitrate,
+        });
+        Some(BitrateChange {
+            previous: current,
+            bitrate,
+            backlog,
+            throughput,
+        })
+    }
+
+    fn restart_window(&mut self, stream: &impl AdaptiveStream, now: Instant) {
+        self.window_start = now;
+        self.peak = 0.0;
+        self.bytes_sent = stream.bytes_sent();
+        self.dropped = stream.dropped();
+    }
+}
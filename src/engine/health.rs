@@ -0,0 +1,344 @@
+//! Health checks
+//!
+//! [`Engine::health`](super::Engine::health) sums up whether an engine is
+//! doing its job, for the liveness and readiness probes of a server
+//! deployment: the state of its streams, whether the audio thread is still
+//! being called, how full the input ring is and how often it ran dry over
+//! the last minute. The audio thread keeps the counts, so a report doesn't
+//! depend on how often, or from how many places, it is asked for.
+//!
+//! The engine doesn't own network streams or disk writers, so the host
+//! adds those with [`HealthReport::with_network`] and
+//! [`HealthReport::with_disk_backlog`], e.g. from
+//! [`RecordingWriter::backlog`](crate::recorder::RecordingWriter::backlog).
+//!
+//! ```no_run
+//! use audio_engine::engine::Engine;
+//!
+//! let mut engine = Engine::builder().build()?;
+//! engine.start()?;
+//! let report = engine.health().with_network(true);
+//! if !report.is_ready() {
+//!     eprintln!("{report}");
+//! }
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::channel::EngineState;
+
+/// Underruns a minute above which the engine is degraded
+const DEGRADED_UNDERRUNS_PER_MINUTE: f64 = 1.0;
+/// Underruns a minute above which the engine is unhealthy
+const UNHEALTHY_UNDERRUNS_PER_MINUTE: f64 = 60.0;
+/// Disk writer buffer fill above which the engine is degraded
+const DEGRADED_BACKLOG: f32 = 0.5;
+/// Disk writer buffer fill above which the engine is unhealthy
+const UNHEALTHY_BACKLOG: f32 = 0.9;
+/// Share of a callback's duration spent processing it above which the
+/// callback overran
+const OVERRUN_LOAD: f32 = 0.9;
+/// Seconds of underruns the rate is taken over, one bucket each
+const UNDERRUN_WINDOW: usize = 60;
+
+/// Counters the audio thread keeps for health checks.
+#[derive(Debug)]
+pub(crate) struct HealthCounters {
+    /// What the times below count from
+    epoch: Instant,
+    /// Milliseconds from the epoch to the last callback
+    progress_at: AtomicU64,
+    underruns: AtomicU64,
+    /// Underruns in each of the last seconds, by second modulo the window
+    recent: [AtomicU64; UNDERRUN_WINDOW],
+    /// The second since the epoch each bucket of `recent` counts
+    recent_second: [AtomicU64; UNDERRUN_WINDOW],
+    /// Callbacks that took too long of their duration
+    overruns: AtomicU64,
+    /// Share of the last callback's duration spent processing it, as `f32`
+    /// bits
+    load: AtomicU32,
+    /// Share of the input ring filled before the last read, as `f32` bits
+    input_fill: AtomicU32,
+}
+
+impl Default for HealthCounters {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            progress_at: AtomicU64::new(0),
+            underruns: AtomicU64::new(0),
+            recent: std::array::from_fn(|_| AtomicU64::new(0)),
+            recent_second: std::array::from_fn(|_| AtomicU64::new(0)),
+            overruns: AtomicU64::new(0),
+            load: AtomicU32::new(0),
+            input_fill: AtomicU32::new(0),
+        }
+    }
+}
+
+impl HealthCounters {
+    /// Records a callback that spent `load` of its duration processing.
+    pub(crate) fn callback(&self, load: f32) {
+        self.mark_progress();
+        self.load.store(load.to_bits(), Ordering::Relaxed);
+        if load >= OVERRUN_LOAD {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts now as progress, e.g. when the streams start, so the time
+    /// before their first callback isn't taken for a stall.
+    pub(crate) fn mark_progress(&self) {
+        let now = u64::try_from(self.epoch.elapsed().as_millis()).unwrap_or(u64::MAX);
+        self.progress_at.store(now, Ordering::Relaxed);
+    }
+
+    #[allow(clippy::cast_possible_truncation)] // the bucket is below the window
+    pub(crate) fn underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+        // Only the audio thread writes the buckets
+        let second = self.epoch.elapsed().as_secs();
+        let bucket = (second % UNDERRUN_WINDOW as u64) as usize;
+        if self.recent_second[bucket].load(Ordering::Relaxed) != second {
+            self.recent[bucket].store(0, Ordering::Relaxed);
+            self.recent_second[bucket].store(second, Ordering::Relaxed);
+        }
+        self.recent[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Time since the audio thread was last called, or since the streams
+    /// last started
+    pub(crate) fn since_progress(&self) -> Duration {
+        let progress_at = Duration::from_millis(self.progress_at.load(Ordering::Relaxed));
+        self.epoch.elapsed().saturating_sub(progress_at)
+    }
+
+    /// Underruns in the last minute
+    pub(crate) fn recent_underruns(&self) -> u64 {
+        let second = self.epoch.elapsed().as_secs();
+        self.recent
+            .iter()
+            .zip(&self.recent_second)
+            .filter(|(_, at)| {
+                second.saturating_sub(at.load(Ordering::Relaxed)) < UNDERRUN_WINDOW as u64
+            })
+            .map(|(count, _)| count.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Records `slots` of the input ring's `capacity` filled.
+    pub(crate) fn set_input_fill(&self, slots: usize, capacity: usize) {
+        #[allow(clippy::cast_precision_loss)]
+        let fill = slots as f32 / capacity.max(1) as f32;
+        self.input_fill.store(fill.to_bits(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn load(&self) -> f32 {
+        f32::from_bits(self.load.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn input_fill(&self) -> f32 {
+        f32::from_bits(self.input_fill.load(Ordering::Relaxed))
+    }
+}
+
+/// Overall verdict of a [`HealthReport`], from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HealthStatus {
+    Healthy,
+    /// Working, with problems worth looking at
+    Degraded,
+    /// Not producing audio as it should
+    Unhealthy,
+}
+
+impl HealthStatus {
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Healthy => "healthy",
+            Self::Degraded => "degraded",
+            Self::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// State of one of the engine's streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StreamStatus {
+    /// Open and running
+    Playing,
+    /// Open and paused
+    Paused,
+    /// Not open, e.g. after rebuilding it failed
+    Missing,
+}
+
+impl fmt::Display for StreamStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Playing => "playing",
+            Self::Paused => "paused",
+            Self::Missing => "missing",
+        })
+    }
+}
+
+/// What [`Engine::health`](super::Engine::health) found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    pub state: EngineState,
+    pub output: StreamStatus,
+    /// `None` when the engine runs without input
+    pub input: Option<StreamStatus>,
+    /// Whether the audio thread has been called lately; always true while
+    /// stopped
+    pub processing: bool,
+    /// Share of the input ring filled, from 0 to 1, as of the last block
+    pub input_fill: Option<f32>,
+    /// Underruns since the engine was built
+    pub underruns: u64,
+    /// Underruns in the last minute
+    pub underrun_rate: f64,
+    /// Time since the audio thread was last called, or since the streams
+    /// started if it hasn't been yet
+    pub since_callback: Duration,
+    /// Whether the host's network streams are connected, if it has any
+    pub network: Option<bool>,
+    /// Share of the host's disk writer buffer waiting to be written, from
+    /// 0 to 1, if it has one
+    pub disk_backlog: Option<f32>,
+}
+
+impl HealthReport {
+    /// Adds whether the host's network streams are connected.
+    #[must_use]
+    pub const fn with_network(mut self, connected: bool) -> Self {
+        self.network = Some(connected);
+        self
+    }
+
+    /// Adds how full the host's disk writer buffer is, from 0 to 1.
+    #[must_use]
+    pub const fn with_disk_backlog(mut self, backlog: f32) -> Self {
+        self.disk_backlog = Some(backlog);
+        self
+    }
+
+    /// The worst of the problems found
+    #[must_use]
+    pub fn status(&self) -> HealthStatus {
+        self.problems()
+            .iter()
+            .map(|(status, _)| *status)
+            .max()
+            .unwrap_or(HealthStatus::Healthy)
+    }
+
+    /// Every problem found, with how bad it is
+    #[must_use]
+    pub fn problems(&self) -> Vec<(HealthStatus, String)> {
+        let mut problems = Vec::new();
+        let running = self.state == EngineState::Running;
+        match self.output {
+            StreamStatus::Missing => {
+                problems.push((HealthStatus::Unhealthy, "no output stream".to_string()));
+            }
+            StreamStatus::Paused if running => {
+                problems.push((HealthStatus::Unhealthy, "output stream paused".to_string()));
+            }
+            _ => {}
+        }
+        if !self.processing {
+            problems.push((
+                HealthStatus::Unhealthy,
+                format!("audio thread not called for {:?}", self.since_callback),
+            ));
+        }
+        if self.input == Some(StreamStatus::Missing) {
+            problems.push((HealthStatus::Degraded, "no input stream".to_string()));
+        }
+        if self.underrun_rate >= UNHEALTHY_UNDERRUNS_PER_MINUTE {
+            problems.push((
+                HealthStatus::Unhealthy,
+                format!("{:.0} underruns a minute", self.underrun_rate),
+            ));
+        } else if self.underrun_rate >= DEGRADED_UNDERRUNS_PER_MINUTE {
+            problems.push((
+                HealthStatus::Degraded,
+                format!("{:.1} underruns a minute", self.underrun_rate),
+            ));
+        }
+        if self.network == Some(false) {
+            problems.push((HealthStatus::Degraded, "network disconnected".to_string()));
+        }
+        if let Some(backlog) = self.disk_backlog {
+            let status = if backlog >= UNHEALTHY_BACKLOG {
+                Some(HealthStatus::Unhealthy)
+            } else if backlog >= DEGRADED_BACKLOG {
+                Some(HealthStatus::Degraded)
+            } else {
+                None
+            };
+            if let Some(status) = status {
+                problems.push((
+                    status,
+                    format!("disk writer {:.0}% behind", backlog * 100.0),
+                ));
+            }
+        }
+        problems
+    }
+
+    /// For a liveness probe: false if the engine can't produce audio and
+    /// should be restarted
+    #[must_use]
+    pub fn is_live(&self) -> bool {
+        self.output != StreamStatus::Missing && self.processing
+    }
+
+    /// For a readiness probe: true if the engine is running and not
+    /// unhealthy
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        self.state == EngineState::Running && self.status() != HealthStatus::Unhealthy
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (engine {}, output {}",
+            self.status(),
+            self.state.name(),
+            self.output
+        )?;
+        if let Some(input) = self.input {
+            write!(f, ", input {input}")?;
+        }
+        write!(f, ", {} underruns)", self.underruns)?;
+        for (status, problem) in self.problems() {
+            write!(f, "; {status}: {problem}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,428 @@
+//! Event history
+//!
+//! An [`EventLog`] keeps the last few thousand notable things that
+//! happened to an engine, each with the wall-clock time it was logged at,
+//! so support tooling can dump "what happened in the last five minutes"
+//! after a glitch is reported. Feed it the engine's feedback with
+//! [`observe`](EventLog::observe), which keeps state changes, underruns,
+//! stream errors, closures and recoveries, buffer size changes and failed
+//! effects and skips levels and positions. Events the engine doesn't see, such as a device
+//! being unplugged or a network stream reconnecting, go in with
+//! [`record`](EventLog::record).
+//!
+//! Underruns come in bursts, so ones close together are logged as a
+//! single entry with a count.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use audio_engine::engine::Engine;
+//! use audio_engine::engine::history::EventLog;
+//!
+//! let mut engine = Engine::builder().build()?;
+//! let mut log = EventLog::default();
+//! engine.start()?;
+//! while let Some(feedback) = engine.feedback().try_recv() {
+//!     log.observe(&feedback);
+//! }
+//! for event in log.within(Duration::from_secs(300)) {
+//!     println!("{event}");
+//! }
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::analysis::exposure::ExposureLimit;
+use crate::audio::stream::StreamCloseReason;
+use crate::channel::{EngineFeedback, EngineState};
+use crate::dsp::quality::EffectQuality;
+use crate::scheduler::UtcDateTime;
+use crate::types::{BufferSize, DeviceType, StreamBitrate};
+
+/// Events an [`EventLog`] holds by default
+const DEFAULT_CAPACITY: usize = 4096;
+
+/// Something that happened to the engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineEvent {
+    StateChanged(EngineState),
+    /// Underruns close together, counted
+    Underruns(u32),
+    StreamError(String),
+    StreamClosed {
+        kind: DeviceType,
+        reason: StreamCloseReason,
+    },
+    StreamDetached {
+        kind: DeviceType,
+    },
+    /// A recovery attempt by the
+    /// [`EngineWatchdog`](crate::engine::watchdog::EngineWatchdog)
+    StreamRecovery {
+        attempt: u32,
+        /// Output device, empty for a virtual device
+        device: String,
+        reason: String,
+        recovered: bool,
+    },
+    EffectFailed {
+        effect_id: u32,
+    },
+    /// Allocations on the audio thread after it was prepared
+    AllocationViolation {
+        count: u64,
+    },
+    /// The device buffer size was raised after callbacks kept overrunning
+    BufferSizeChanged {
+        /// `None` for the host's default
+        previous: Option<BufferSize>,
+        size: BufferSize,
+        overruns: u32,
+    },
+    /// The effects moved to another quality tier with the CPU headroom
+    QualityChanged {
+        previous: EffectQuality,
+        quality: EffectQuality,
+    },
+    /// A network stream moved to another bitrate with its connection
+    BitrateChanged {
+        url: String,
+        previous: StreamBitrate,
+        bitrate: StreamBitrate,
+    },
+    /// The listener's exposure dose neared or reached its limit
+    Exposure {
+        limit: ExposureLimit,
+        /// Dose at the time, in percent of the full dose
+        dose_percent: u32,
+    },
+    /// A device appeared, went away or changed, as the host saw it
+    Device {
+        name: String,
+        message: String,
+    },
+    /// A network stream lost its connection or got it back
+    NetworkReconnect {
+        url: String,
+        attempt: u32,
+        connected: bool,
+    },
+    /// Anything else the host wants in the history
+    Note(String),
+}
+
+impl EngineEvent {
+    /// The event kept for `feedback`, if it is one worth keeping
+    #[must_use]
+    pub fn from_feedback(feedback: &EngineFeedback) -> Option<Self> {
+        Some(match feedback {
+            EngineFeedback::StateChanged(state) => Self::StateChanged(*state),
+            EngineFeedback::Underrun => Self::Underruns(1),
+            EngineFeedback::Error(message) => Self::StreamError(message.clone()),
+            EngineFeedback::StreamClosed { kind, reason } => Self::StreamClosed {
+                kind: *kind,
+                reason: *reason,
+            },
+            EngineFeedback::StreamDetached { kind } => Self::StreamDetached { kind: *kind },
+            EngineFeedback::StreamRecovery {
+                attempt,
+                device,
+                reason,
+                recovered,
+            } => Self::StreamRecovery {
+                attempt: *attempt,
+                device: device.clone(),
+                reason: reason.clone(),
+                recovered: *recovered,
+            },
+            EngineFeedback::EffectFailed { effect_id } => Self::EffectFailed {
+                effect_id: *effect_id,
+            },
+            EngineFeedback::AllocationViolation { count } => {
+                Self::AllocationViolation { count: *count }
+            }
+            EngineFeedback::BufferSizeChanged {
+                previous,
+                size,
+                overruns,
+            } => Self::BufferSizeChanged {
+                previous: *previous,
+                size: *size,
+                overruns: *overruns,
+            },
+            EngineFeedback::QualityChanged {
+                previous, quality, ..
+            } => Self::QualityChanged {
+                previous: *previous,
+                quality: *quality,
+            },
+            EngineFeedback::BitrateChanged {
+                url,
+                previous,
+                bitrate,
+            } => Self::BitrateChanged {
+                url: url.clone(),
+                previous: *previous,
+                bitrate: *bitrate,
+            },
+            EngineFeedback::ExposureWarning(warning) => Self::Exposure {
+                limit: warning.limit,
+                // Saturates on the way to a u32
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                dose_percent: (warning.dose * 100.0).round() as u32,
+            },
+            EngineFeedback::Levels { .. }
+            | EngineFeedback::Position(_)
+            | EngineFeedback::RenderProgress { .. }
+            | EngineFeedback::ParamRecorded { .. }
+            | EngineFeedback::MarkerReached { .. }
+            | EngineFeedback::NetworkLatency { .. } => return None,
+        })
+    }
+}
+
+impl fmt::Display for EngineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StateChanged(state) => write!(f, "engine {}", state.name()),
+            Self::Underruns(1) => write!(f, "underrun"),
+            Self::Underruns(count) => write!(f, "{count} underruns"),
+            Self::StreamError(message) => write!(f, "stream error: {message}"),
+            Self::StreamClosed { kind, reason } => {
+                let how = match reason {
+                    StreamCloseReason::Closed => "closed",
+                    StreamCloseReason::Dropped => "dropped",
+                };
+                write!(f, "{kind} stream {how}")
+            }
+            Self::StreamDetached { kind } => write!(f, "{kind} stream detached"),
+            Self::StreamRecovery {
+                attempt,
+                device,
+                reason,
+                recovered,
+            } => {
+                let outcome = if *recovered { "recovered" } else { "failed" };
+                write!(f, "recovery {attempt} ({reason})")?;
+                if !device.is_empty() {
+                    write!(f, " on {device}")?;
+                }
+                write!(f, " {outcome}")
+            }
+            Self::EffectFailed { effect_id } => write!(f, "effect {effect_id} failed"),
+            Self::AllocationViolation { count } => {
+                write!(f, "{count} allocations on the audio thread")
+            }
+            Self::BufferSizeChanged {
+                previous,
+                size,
+                overruns,
+            } => {
+                write!(f, "buffer size raised ")?;
+                if let Some(previous) = previous {
+                    write!(f, "from {previous} ")?;
+                }
+                write!(f, "to {size} after {overruns} overruns")
+            }
+            Self::QualityChanged { previous, quality } => {
+                let how = if quality < previous {
+                    "lowered"
+                } else {
+                    "raised"
+                };
+                write!(f, "effect quality {how} from {previous} to {quality}")
+            }
+            Self::BitrateChanged {
+                url,
+                previous,
+                bitrate,
+            } => {
+                let how = if bitrate.as_bps() < previous.as_bps() {
+                    "lowered"
+                } else {
+                    "raised"
+                };
+                write!(f, "bitrate of {url} {how} from {previous} to {bitrate}")
+            }
+            Self::Exposure {
+                limit,
+                dose_percent,
+            } => write!(f, "exposure limit {limit} at {dose_percent}% dose"),
+            Self::Device { name, message } => write!(f, "device {name}: {message}"),
+            Self::NetworkReconnect {
+                url,
+                attempt,
+                connected,
+            } => {
+                let outcome = if *connected { "connected" } else { "failed" };
+                write!(f, "reconnect {attempt} to {url} {outcome}")
+            }
+            Self::Note(note) => f.write_str(note),
+        }
+    }
+}
+
+/// An event and when it was logged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedEvent {
+    /// Wall-clock time it was logged at; for coalesced underruns, the first
+    pub time: SystemTime,
+    pub event: EngineEvent,
+    /// Monotonic time it was logged at, for age queries
+    instant: Instant,
+}
+
+impl LoggedEvent {
+    /// How long ago it was logged
+    #[must_use]
+    pub fn age(&self) -> Duration {
+        self.instant.elapsed()
+    }
+}
+
+impl fmt::Display for LoggedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.subsec_millis());
+        write!(
+            f,
+            "{} {millis:03}ms {}",
+            UtcDateTime::from_system_time(self.time),
+            self.event
+        )
+    }
+}
+
+/// Bounded history of engine events, oldest dropped first.
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    events: VecDeque<LoggedEvent>,
+    capacity: usize,
+    /// Underruns within this of the first one of an entry are counted
+    /// into it
+    underrun_window: Duration,
+    /// Events dropped to make room
+    evicted: u64,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl EventLog {
+    /// Creates a log holding up to `capacity` events (at least one).
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            underrun_window: Duration::from_secs(1),
+            evicted: 0,
+        }
+    }
+
+    /// Sets how close together underruns are counted into one entry; zero
+    /// logs each on its own.
+    #[must_use]
+    pub const fn with_underrun_window(mut self, window: Duration) -> Self {
+        self.underrun_window = window;
+        self
+    }
+
+    /// Logs `feedback` if it is an event worth keeping.
+    pub fn observe(&mut self, feedback: &EngineFeedback) {
+        if let Some(event) = EngineEvent::from_feedback(feedback) {
+            self.record(event);
+        }
+    }
+
+    /// Logs an event now.
+    pub fn record(&mut self, event: EngineEvent) {
+        let now = Instant::now();
+        if let EngineEvent::Underruns(count) = event
+            && let Some(LoggedEvent {
+                event: EngineEvent::Underruns(total),
+                instant,
+                ..
+            }) = self.events.back_mut()
+            && now.duration_since(*instant) < self.underrun_window
+        {
+            *total = total.saturating_add(count);
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+            self.evicted += 1;
+        }
+        self.events.push_back(LoggedEvent {
+            time: SystemTime::now(),
+            event,
+            instant: now,
+        });
+    }
+
+    /// Every event held, oldest first
+    #[must_use]
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &LoggedEvent> {
+        self.events.iter()
+    }
+
+    /// Events logged within `age` of now, oldest first
+    #[must_use]
+    pub fn within(&self, age: Duration) -> impl DoubleEndedIterator<Item = &LoggedEvent> {
+        let now = Instant::now();
+        // Events are in logging order, so the recent ones are a suffix
+        let start = self
+            .events
+            .partition_point(|logged| now.duration_since(logged.instant) > age);
+        self.events.range(start..)
+    }
+
+    /// Events logged within `age` of now, one per line, for a support
+    /// report
+    #[must_use]
+    pub fn dump(&self, age: Duration) -> String {
+        let mut out = String::new();
+        for logged in self.within(age) {
+            let _ = writeln!(out, "{logged}");
+        }
+        if self.evicted > 0 {
+            let _ = writeln!(out, "({} older events dropped)", self.evicted);
+        }
+        out
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    #[must_use]
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Events dropped so far to make room for newer ones
+    #[must_use]
+    pub const fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.evicted = 0;
+    }
+}
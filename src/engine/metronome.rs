@@ -0,0 +1,223 @@
+//! Metronome
+//!
+//! A [`Metronome`] clicks on every quarter note of the tempo map while the
+//! transport rolls, with a higher click on the first beat of each bar. It
+//! always clicks through a count-in, even when switched off, since the
+//! count-in is nothing but clicks; see
+//! [`Transport::record`](crate::engine::transport::Transport::record).
+//!
+//! In an [`Engine`](crate::engine::Engine) the clicks are added after the
+//! effect chain, so effects never colour them; set them with
+//! [`EngineCommand::SetMetronome`](crate::channel::EngineCommand::SetMetronome).
+
+use crate::engine::transport::TransportSpan;
+use crate::types::{ChannelCount, Gain, Sample, SampleRate, TempoMap, Timestamp};
+
+/// How far below a whole beat a position may fall and still count as on it
+const BEAT_EPSILON: f64 = 1e-9;
+
+/// Sound of a [`Metronome`] and whether it clicks while playing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetronomeSettings {
+    /// Click while the transport rolls, not only through a count-in
+    pub enabled: bool,
+    pub level: Gain,
+    /// Pitch of the click on the first beat of a bar
+    pub accent_hz: f32,
+    /// Pitch of the other clicks
+    pub beat_hz: f32,
+    pub click_ms: f32,
+}
+
+impl Default for MetronomeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            level: Gain::from_db(-6.0),
+            accent_hz: 1760.0,
+            beat_hz: 880.0,
+            click_ms: 30.0,
+        }
+    }
+}
+
+impl MetronomeSettings {
+    #[must_use]
+    pub const fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_level(mut self, level: Gain) -> Self {
+        self.level = level;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_pitches(mut self, accent_hz: f32, beat_hz: f32) -> Self {
+        self.accent_hz = accent_hz;
+        self.beat_hz = beat_hz;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_click_ms(mut self, ms: f32) -> Self {
+        self.click_ms = ms;
+        self
+    }
+}
+
+/// Click generator following a transport.
+///
+/// Doesn't allocate, so it runs on the audio thread.
+#[derive(Debug, Clone)]
+pub struct Metronome {
+    settings: MetronomeSettings,
+    sample_rate: SampleRate,
+    phase: f32,
+    /// Phase step per frame of the click sounding
+    increment: f32,
+    /// Frames left of the click sounding
+    remaining: u32,
+    length: u32,
+}
+
+impl Metronome {
+    #[must_use]
+    pub const fn new(settings: MetronomeSettings, sample_rate: SampleRate) -> Self {
+        Self {
+            settings,
+            sample_rate,
+            phase: 0.0,
+            increment: 0.0,
+            remaining: 0,
+            length: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> MetronomeSettings {
+        self.settings
+    }
+
+    /// Changes the settings; a click already sounding finishes as it was.
+    pub const fn set_settings(&mut self, settings: MetronomeSettings) {
+        self.settings = settings;
+    }
+
+    /// Stops any click sounding.
+    pub const fn reset(&mut self) {
+        self.remaining = 0;
+    }
+
+    /// Adds the clicks falling in `span` to `output`, interleaved with
+    /// `channels` and as long as the span.
+    pub fn process(
+        &mut self,
+        span: &TransportSpan,
+        tempo_map: &TempoMap,
+        output: &mut [Sample],
+        channels: ChannelCount,
+    ) {
+        let channel_count = channels.count_usize();
+        let frames = span.frames.min(output.len() / channel_count);
+        let mut from = 0;
+        let mut search = 0;
+        while from < frames {
+            let click = self
+                .next_click(span, tempo_map, search)
+                .filter(|&(offset, _)| offset < frames);
+            let until = click.map_or(frames, |(offset, _)| offset);
+            self.render(
+                &mut output[from * channel_count..until * channel_count],
+                channel_count,
+            );
+            if let Some((offset, accent)) = click {
+                self.trigger(accent);
+                search = offset + 1;
+            }
+            from = until;
+        }
+    }
+
+    /// Frame offset in `span`, at or after `from`, of the next click, and
+    /// whether it starts a bar
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn next_click(
+        &self,
+        span: &TransportSpan,
+        tempo_map: &TempoMap,
+        from: usize,
+    ) -> Option<(usize, bool)> {
+        if span.count_in > 0 {
+            // Count back from the downbeat the count-in ends on
+            let beat = tempo_map
+                .tempo_at(span.start)
+                .beats_to_samples(1.0, self.sample_rate);
+            let left = span.count_in.checked_sub(from as u64)? as f64;
+            let beats = (left / beat + BEAT_EPSILON).floor();
+            if beats < 1.0 {
+                return None;
+            }
+            let offset = span.count_in - (beats * beat).round() as u64;
+            let bar = tempo_map
+                .signature_at(span.start)
+                .bar_beats()
+                .round()
+                .max(1.0);
+            return Some((usize::try_from(offset).ok()?, beats % bar == 0.0));
+        }
+        if !(span.rolling && self.settings.enabled) {
+            return None;
+        }
+        let position = span.start.as_samples() + from as u64;
+        let beats = tempo_map.beats_at(Timestamp::from_samples(position));
+        let mut beat = (beats - BEAT_EPSILON).ceil();
+        let mut at = tempo_map.position_of_beats(beat);
+        if at.as_samples() < position {
+            beat += 1.0;
+            at = tempo_map.position_of_beats(beat);
+        }
+        let offset = usize::try_from(at.as_samples() - span.start.as_samples()).ok()?;
+        Some((offset, tempo_map.musical_time_at(at).beat == 0))
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn trigger(&mut self, accent: bool) {
+        let rate = self.sample_rate.as_f32();
+        let pitch = if accent {
+            self.settings.accent_hz
+        } else {
+            self.settings.beat_hz
+        };
+        self.phase = 0.0;
+        self.increment = std::f32::consts::TAU * pitch / rate;
+        self.length = (self.settings.click_ms.max(0.0) * rate / 1000.0) as u32;
+        self.remaining = self.length;
+    }
+
+    /// Adds the sounding click to whole frames of `output`.
+    #[allow(clippy::cast_precision_loss)]
+    fn render(&mut self, output: &mut [Sample], channel_count: usize) {
+        let level = self.settings.level.as_linear();
+        for frame in output.chunks_exact_mut(channel_count) {
+            if self.remaining == 0 {
+                return;
+            }
+            // Linear decay to silence over the click
+            let envelope = self.remaining as f32 / self.length as f32;
+            let value = self.phase.sin() * envelope * level;
+            self.phase = (self.phase + self.increment) % std::f32::consts::TAU;
+            self.remaining -= 1;
+            for sample in frame {
+                *sample = Sample::new(sample.value() + value);
+            }
+        }
+    }
+}
@@ -0,0 +1,1363 @@
+//! Top-level audio engine
+//!
+//! [`Engine`] ties the other parts of the crate together: it opens an
+//! input and an output stream, runs an [`EffectChain`] in the output
+//! device's callback, and exposes the command and feedback channels that
+//! control it from other threads.
+//!
+//! ```no_run
+//! use audio_engine::channel::EngineCommand;
+//! use audio_engine::engine::Engine;
+//! use audio_engine::types::Gain;
+//!
+//! let mut engine = Engine::builder().build()?;
+//! engine.start()?;
+//! engine.send(EngineCommand::SetGain(Gain::from_db(-6.0)))?;
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! [`EngineBuilder::input`], [`EngineBuilder::output`] and
+//! [`EngineBuilder::chain`] pick the devices and put the chain together in
+//! the same expression.
+//!
+//! The audio thread also keeps a [`Transport`], moved by the transport
+//! commands ([`EngineCommand::Seek`], [`EngineCommand::Locate`],
+//! [`EngineCommand::SetLoop`]) and reported back as
+//! [`EngineFeedback::Position`] while it rolls.
+//!
+//! Parameter moves made while the transport plays can be captured into
+//! automation lanes; see [`automation`].
+//!
+//! [`EngineCommand::Record`] starts a recording pass with a count-in and
+//! pre-roll, clicked by the [`Metronome`].
+//!
+//! [`EngineBuilder::with_crossfade_source`] adds a second source that
+//! [`EngineCommand::SetCrossfade`] fades the input over to.
+//!
+//! With big device buffers, [`EngineBuilder::with_sub_block_frames`] keeps
+//! the effects running on short sub-blocks, so automation and modulation
+//! stay close to sample accurate without paying per-block overhead on
+//! every few frames.
+//!
+//! [`EngineBuilder::render_offline`] runs the same processing without a
+//! device, bouncing a file to a file faster than real time, and
+//! [`EngineBuilder::render_offline_to`] to any [`RenderSink`], such as an
+//! encoder streaming to a server.
+//!
+//! For scripts, [`play_file`] and [`record_to`] wrap all of this in a
+//! blocking call; see [`simple`].
+//!
+//! [`Engine::memory_report`] lists the memory allocated up front for the
+//! audio thread, per effect and buffer, for checking the engine fits a RAM
+//! budget.
+//!
+//! [`Engine::prepare`] finishes allocating before the engine starts and
+//! arms the runtime allocation guard on the audio thread.
+//!
+//! For tests, [`EngineBuilder::with_virtual_device`] runs the engine on a
+//! [`VirtualDevice`] that the test clocks by hand.
+//!
+//! The input device's samples pass through a ring buffer into the output
+//! callback. Both devices are expected to run off the same clock; if they
+//! drift apart the engine reports underruns or drops input.
+//!
+//! [`Engine::rebuild_streams`] reopens the streams after a device error
+//! without losing the chain's state, and an [`EngineWatchdog`] does so
+//! by itself, falling back to other devices if it has to; see
+//! [`watchdog`]. An [`EventLog`](history::EventLog) keeps a timestamped
+//! history of what happened to the engine for support reports; see
+//! [`history`].
+//!
+//! [`Engine::health`] sums up the engine's state for liveness and
+//! readiness probes; see [`health`].
+//!
+//! [`Engine::set_buffer_size`] changes the device buffer size, and a
+//! [`BufferAdapter`] raises it by itself while callbacks keep overrunning;
+//! see [`overload`].
+//!
+//! [`Engine::set_effect_quality`] trades the effects' sound for processing
+//! time, and a [`QualityGovernor`] steps it down and back up with the CPU
+//! headroom; see [`quality`]. A [`BitrateGovernor`] does the same for a
+//! network stream's bitrate as its connection falls behind; see
+//! [`bitrate`].
+//!
+//! [`Engine::set_now_playing`] announces the title on air to the streams
+//! added with [`Engine::add_metadata_sink`]; see
+//! [`metadata`](crate::io::metadata).
+//!
+//! Every output frame passes a safety limiter and DC blocker last, so a
+//! runaway chain can't blast the speakers; [`EngineBuilder::with_safety`]
+//! sets it up; see [`safety`]. [`EngineBuilder::with_exposure`] meters
+//! the sound exposure dose of whoever listens to the output; see
+//! [`exposure`](crate::analysis::exposure).
+//!
+//! [`EngineBuilder::with_output_timestamps`] reports when each output
+//! buffer reaches the DAC, for syncing video to the audio; see
+//! [`presentation`].
+
+pub mod automation;
+pub mod bitrate;
+pub mod health;
+pub mod history;
+pub mod metronome;
+mod offline;
+pub mod overload;
+pub mod presentation;
+mod processor;
+pub mod quality;
+pub mod safety;
+pub mod scene;
+pub mod simple;
+pub mod transport;
+pub mod watchdog;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::analysis::exposure::ExposureSettings;
+use crate::audio::backend::VirtualDevice;
+use crate::audio::device::{AudioDevice, AudioDeviceManager};
+use crate::audio::stream::{AudioInputStream, StreamConfig, StreamHandle};
+use crate::buffer::{MemoryReport, RingBufferReader, RingBufferWriter};
+use crate::channel::{
+    ControlReceiver, ControlSender, EngineCommand, EngineFeedback, EngineState, RealtimeReceiver,
+    RealtimeSender, control_channel, feedback_channel, reserve_feedback,
+};
+use crate::dsp::chain::{ChainBuilder, EffectChain};
+use crate::dsp::crossfade::CrossfadeCurve;
+use crate::dsp::denormal::DenormalPolicy;
+use crate::dsp::quality::EffectQuality;
+use crate::error::{AudioEngineError, Result};
+use crate::io::metadata::{MetadataSink, NowPlaying};
+use crate::io::{FileOutput, InputSource, OutputTarget, SignalGenerator, SignalSource};
+use crate::types::{
+    AudioFormat, BufferSize, ChannelCount, DeviceId, Sample, SampleRate, Tempo, TempoMap,
+    TimeSignature,
+};
+
+pub use automation::AutomationRecorder;
+pub use bitrate::{AdaptiveStream, BitrateGovernor, BitrateSettings};
+pub use health::{HealthReport, HealthStatus, StreamStatus};
+pub use metronome::{Metronome, MetronomeSettings};
+pub use offline::{OfflineRender, RenderPacing, RenderSink};
+pub use overload::{BufferAdapter, BufferChange, OverloadSettings};
+pub use presentation::OutputTimestamp;
+pub use quality::{QualityChange, QualityGovernor, QualitySettings};
+pub use safety::SafetySettings;
+pub use scene::{Scene, TransportScene};
+pub use simple::{PlaybackHandle, Progress, play_file, record_to, record_to_with};
+pub use transport::{CountIn, Transport, TransportSpan, TransportState};
+pub use watchdog::{EngineWatchdog, WatchdogSettings};
+
+use health::HealthCounters;
+use processor::{EngineProcessor, MUTE_MS};
+use scene::{SceneRecall, SceneSender, scene_channel};
+
+/// Device buffers of input the ring between the input and output callbacks
+/// can hold
+const INPUT_RING_BUFFERS: usize = 4;
+/// Scene recalls that can be waiting for the audio thread
+const SCENE_CAPACITY: usize = 4;
+/// A running engine whose audio thread isn't called for this long has
+/// stalled
+const STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Configures and builds an [`Engine`].
+#[derive(Debug)]
+pub struct EngineBuilder {
+    config: StreamConfig,
+    input_device: Option<AudioDevice>,
+    output_device: Option<AudioDevice>,
+    virtual_device: Option<VirtualDevice>,
+    use_input: bool,
+    chain: EffectChain,
+    command_capacity: usize,
+    feedback_capacity: usize,
+    meter_interval_ms: u32,
+    seed: Option<u64>,
+    denormals: DenormalPolicy,
+    tempo_map: Option<TempoMap>,
+    crossfade: Option<(RingBufferReader<Sample>, CrossfadeCurve)>,
+    tap: Option<RingBufferWriter<Sample>>,
+    timestamps: Option<RingBufferWriter<OutputTimestamp>>,
+    sub_block_frames: Option<usize>,
+    safety: SafetySettings,
+    exposure: Option<ExposureSettings>,
+    /// Device buffer size, `None` for the host's default
+    buffer_size: Option<BufferSize>,
+    /// Set by [`input`](Self::input), checked when the engine is built
+    input_source: Option<InputSource>,
+    /// Generates the input in place of a device, from an
+    /// [`InputSource::Signal`]
+    signal: Option<SignalGenerator>,
+    /// Set by [`output`](Self::output), checked when the engine is built
+    output_target: Option<OutputTarget>,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self {
+            config: StreamConfig::default(),
+            input_device: None,
+            output_device: None,
+            virtual_device: None,
+            use_input: true,
+            chain: EffectChain::new(),
+            command_capacity: 64,
+            feedback_capacity: 256,
+            meter_interval_ms: 50,
+            seed: None,
+            denormals: DenormalPolicy::FeedbackOnly,
+            tempo_map: None,
+            crossfade: None,
+            tap: None,
+            timestamps: None,
+            sub_block_frames: None,
+            safety: SafetySettings::default(),
+            exposure: None,
+            buffer_size: None,
+            input_source: None,
+            signal: None,
+            output_target: None,
+        }
+    }
+}
+
+impl EngineBuilder {
+    #[must_use]
+    pub const fn with_config(mut self, config: StreamConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_sample_rate(mut self, sample_rate: SampleRate) -> Self {
+        self.config.sample_rate = sample_rate;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_channels(mut self, channels: ChannelCount) -> Self {
+        self.config.channels = channels;
+        self
+    }
+
+    /// Frames per processing block, which also sizes the stream buffers
+    #[must_use]
+    pub const fn with_buffer_frames(mut self, frames: usize) -> Self {
+        self.config.buffer_frames = frames;
+        self
+    }
+
+    /// Asks the host for device buffers of `size` frames rather than its
+    /// default size; see [`Engine::set_buffer_size`]. Ignored on a virtual
+    /// device.
+    #[must_use]
+    pub const fn with_buffer_size(mut self, size: BufferSize) -> Self {
+        self.buffer_size = Some(size);
+        self
+    }
+
+    /// Runs the chain's effects on sub-blocks of at most `frames` frames,
+    /// however big the processing blocks are. Overrides the chain's own
+    /// setting; see [`EffectChain::set_sub_block_frames`].
+    #[must_use]
+    pub const fn with_sub_block_frames(mut self, frames: usize) -> Self {
+        self.sub_block_frames = Some(frames);
+        self
+    }
+
+    /// Sets the sample rate and channel count. Processing is always in
+    /// 32 bit float, so the bit depth is left to the devices.
+    #[must_use]
+    pub const fn format(mut self, format: AudioFormat) -> Self {
+        self.config.sample_rate = format.sample_rate;
+        self.config.channels = format.channels;
+        self
+    }
+
+    /// Where the engine takes audio from: a device, looked up by name when
+    /// the engine is built, or a generated [`InputSource::Signal`] in place
+    /// of one, such as [`InputSource::silence`] to run without input or a
+    /// sine to test the chain. Files are played through
+    /// [`render_offline`](Self::render_offline) instead; other sources
+    /// fail to build. A format set on a device input becomes the engine's.
+    #[must_use]
+    pub fn input(mut self, source: InputSource) -> Self {
+        if let InputSource::Device(config) = &source
+            && let Some(format) = config.format
+        {
+            self = self.format(format);
+        }
+        self.input_device = None;
+        self.use_input = true;
+        self.input_source = Some(source);
+        self
+    }
+
+    /// Where the engine plays to: a device, looked up by name when the
+    /// engine is built. Files and streams are written through
+    /// [`render_offline`](Self::render_offline) and
+    /// [`render_offline_to`](Self::render_offline_to) instead; other
+    /// targets fail to build. A format set on the device output becomes
+    /// the engine's.
+    #[must_use]
+    pub fn output(mut self, target: OutputTarget) -> Self {
+        if let OutputTarget::Device(config) = &target
+            && let Some(format) = config.format
+        {
+            self = self.format(format);
+        }
+        self.output_device = None;
+        self.output_target = Some(target);
+        self
+    }
+
+    /// Uses `device` instead of the default input device
+    #[must_use]
+    pub fn with_input_device(mut self, device: AudioDevice) -> Self {
+        self.input_device = Some(device);
+        self.input_source = None;
+        self.use_input = true;
+        self
+    }
+
+    /// Uses `device` instead of the default output device
+    #[must_use]
+    pub fn with_output_device(mut self, device: AudioDevice) -> Self {
+        self.output_device = Some(device);
+        self.output_target = None;
+        self
+    }
+
+    /// Runs on a virtual device instead of hardware, for both input and
+    /// output. The device's format and buffer size replace the stream
+    /// configuration.
+    #[must_use]
+    pub fn with_virtual_device(mut self, device: VirtualDevice) -> Self {
+        self.config = StreamConfig::new(
+            device.format().sample_rate,
+            device.format().channels,
+            device.buffer_frames(),
+        );
+        self.virtual_device = Some(device);
+        self
+    }
+
+    /// Runs the chain on silence instead of opening an input device
+    #[must_use]
+    pub fn without_input(mut self) -> Self {
+        self.input_device = None;
+        self.input_source = None;
+        self.use_input = false;
+        self
+    }
+
+    /// The chain run on every block
+    #[must_use]
+    pub fn with_chain(mut self, chain: EffectChain) -> Self {
+        self.chain = chain;
+        self
+    }
+
+    /// Adds effects to the chain run on every block:
+    ///
+    /// ```no_run
+    /// use audio_engine::engine::Engine;
+    /// use audio_engine::io::{InputSource, OutputTarget};
+    /// use audio_engine::types::Gain;
+    ///
+    /// let mut engine = Engine::builder()
+    ///     .input(InputSource::default_device())
+    ///     .output(OutputTarget::default_device())
+    ///     .chain(|c| c.high_pass(80.0, 0.707).eq(3_000.0, 1.0, 2.5).gain(Gain::from_db(-3.0)))
+    ///     .build()?;
+    /// engine.start()?;
+    /// # Ok::<(), audio_engine::error::AudioEngineError>(())
+    /// ```
+    #[must_use]
+    pub fn chain(mut self, build: impl FnOnce(ChainBuilder) -> ChainBuilder) -> Self {
+        let chain = std::mem::take(&mut self.chain);
+        self.chain = build(ChainBuilder::from(chain)).build();
+        self
+    }
+
+    #[must_use]
+    pub const fn with_command_capacity(mut self, capacity: usize) -> Self {
+        self.command_capacity = capacity;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_feedback_capacity(mut self, capacity: usize) -> Self {
+        self.feedback_capacity = capacity;
+        self
+    }
+
+    /// How often level feedback is sent
+    #[must_use]
+    pub const fn with_meter_interval_ms(mut self, millis: u32) -> Self {
+        self.meter_interval_ms = millis;
+        self
+    }
+
+    /// Makes processing repeatable: the chain's random generators (noise,
+    /// dither, random modulation) are seeded from `seed` when the engine is
+    /// built and again on every stop, and denormals are flushed after every
+    /// effect whatever the CPU's floating point mode. The same input then
+    /// renders bit-identically across runs.
+    ///
+    /// Processing runs on the one audio thread, so there is no thread
+    /// partitioning to pin down.
+    #[must_use]
+    pub const fn deterministic(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self.denormals = DenormalPolicy::Flush;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_denormal_policy(mut self, policy: DenormalPolicy) -> Self {
+        self.denormals = policy;
+        self
+    }
+
+    /// Sets the tempo and meter changes the effects sync to. Without one
+    /// the engine runs at 120 BPM in 4/4.
+    #[must_use]
+    pub fn with_tempo_map(mut self, tempo_map: TempoMap) -> Self {
+        self.tempo_map = Some(tempo_map);
+        self
+    }
+
+    /// Adds a second source that [`EngineCommand::SetCrossfade`] fades the
+    /// input over to, e.g. a file player's ring buffer, so playback can
+    /// hand over to live input and back without clicks. The source must
+    /// be interleaved in the engine's format; when it runs dry it is
+    /// silence.
+    #[must_use]
+    pub fn with_crossfade_source(
+        mut self,
+        source: RingBufferReader<Sample>,
+        curve: CrossfadeCurve,
+    ) -> Self {
+        self.crossfade = Some((source, curve));
+        self
+    }
+
+    /// Copies the processed audio into `tap` as it is played, after the
+    /// chain and the metronome but before the master gain and pan, e.g. to
+    /// record what the chain makes with the output muted. Blocks that don't
+    /// fit are dropped, so drain the ring at least once per buffer.
+    #[must_use]
+    pub fn with_tap(mut self, tap: RingBufferWriter<Sample>) -> Self {
+        self.tap = Some(tap);
+        self
+    }
+
+    /// Pushes an [`OutputTimestamp`] into `timestamps` for every device
+    /// buffer, with the host time its first frame reaches the DAC, so
+    /// video can be synced to the audio; see [`presentation`]. Timestamps
+    /// that don't fit are dropped, so drain the ring regularly.
+    #[must_use]
+    pub fn with_output_timestamps(mut self, timestamps: RingBufferWriter<OutputTimestamp>) -> Self {
+        self.timestamps = Some(timestamps);
+        self
+    }
+
+    /// Sets the safety limiter and DC blocker every output frame passes
+    /// last, after the master gain, pan and mute; see [`safety`]. It is on
+    /// by default, with a ceiling of -1 dBFS, and no command bypasses it.
+    #[must_use]
+    pub const fn with_safety(mut self, settings: SafetySettings) -> Self {
+        self.safety = settings;
+        self
+    }
+
+    /// Meters the sound exposure dose of whoever listens to the output,
+    /// after the safety stage, and reports
+    /// [`EngineFeedback::ExposureWarning`] as it nears and reaches the
+    /// limit of `settings`. [`Engine::reset_exposure`] starts a new
+    /// listener's dose.
+    #[must_use]
+    pub const fn with_exposure(mut self, settings: ExposureSettings) -> Self {
+        self.exposure = Some(settings);
+        self
+    }
+
+    /// Builds the engine and plays the file at `path` through it, on the
+    /// output device, at the file's sample rate and channel count; see
+    /// [`simple`]. WAV, AIFF and CAF files can always be played, others
+    /// need the `symphonia` feature.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be opened or its sample rate
+    /// isn't supported, or the engine can't be built or started.
+    pub fn play_file(self, path: impl AsRef<std::path::Path>) -> Result<PlaybackHandle> {
+        PlaybackHandle::start(self, path.as_ref())
+    }
+
+    /// Builds the engine and records `duration` of what its chain makes of
+    /// the input into a WAV file at `path`, calling `on_progress` along the
+    /// way; see [`simple`]. Returns the frames recorded.
+    ///
+    /// # Errors
+    /// Returns an error if the engine has no input, can't be built or
+    /// started, or the file can't be written.
+    pub fn record_to(
+        self,
+        path: impl AsRef<std::path::Path>,
+        duration: Duration,
+        on_progress: impl FnMut(Progress),
+    ) -> Result<u64> {
+        simple::record(self, path.as_ref(), duration, on_progress)
+    }
+
+    /// Sets up an offline render of `input` through the chain to `output`.
+    ///
+    /// No device is opened. The engine runs at the input file's sample rate
+    /// and channel count, whatever the configured ones, in blocks of the
+    /// configured buffer size. WAV, AIFF, CAF and raw PCM files can be
+    /// read, and written too; the output can as well be an MP3 file with the
+    /// `mp3` feature or an Opus file with the `opus` feature. A generated
+    /// sweep is rendered at the configured format instead.
+    ///
+    /// # Errors
+    /// Returns an error if the input isn't a file or sweep or can't be
+    /// opened, or
+    /// the output can't be created or asks for a different sample rate or
+    /// channel count.
+    pub fn render_offline(self, input: &InputSource, output: &FileOutput) -> Result<OfflineRender> {
+        self.render_offline_with(input, |format| OfflineRender::file_sink(output, format))
+    }
+
+    /// Sets up an offline render of `input` through the chain to `sink`,
+    /// such as an encoder feeding a network upload. As with
+    /// [`render_offline`](Self::render_offline), only WAV, AIFF, CAF and
+    /// raw PCM files and sweeps can be read.
+    ///
+    /// # Errors
+    /// Returns an error if the input isn't a file or sweep or can't be
+    /// opened, or the sink refuses the input's format.
+    pub fn render_offline_to(
+        self,
+        input: &InputSource,
+        mut sink: impl RenderSink + 'static,
+    ) -> Result<OfflineRender> {
+        self.render_offline_with(input, |format| {
+            sink.start(format)?;
+            Ok(Box::new(sink))
+        })
+    }
+
+    fn render_offline_with(
+        mut self,
+        input: &InputSource,
+        open: impl FnOnce(AudioFormat) -> Result<Box<dyn RenderSink>>,
+    ) -> Result<OfflineRender> {
+        // Renders are bounced as made; the safety stage guards devices
+        self.safety = SafetySettings::disabled();
+        self.exposure = None;
+        self.timestamps = None;
+        let (config, feedback_capacity) = (self.config.clone(), self.feedback_capacity);
+        OfflineRender::new(
+            input,
+            open,
+            &config,
+            feedback_capacity,
+            |config, commands, feedback, reader| {
+                self.into_processor(Some(reader), commands, feedback, config)
+            },
+        )
+    }
+
+    /// Opens the devices and builds the streams. The engine starts stopped.
+    ///
+    /// If no input device was given and there is no default input, the
+    /// engine runs without input.
+    ///
+    /// # Errors
+    /// Returns an error if there is no output device or a stream can't be
+    /// created.
+    pub fn build(mut self) -> Result<Engine> {
+        let (input_name, output_name) = self.target_devices()?;
+        let (commands, command_receiver) = control_channel(self.command_capacity);
+        let (feedback_sender, feedback) = feedback_channel(self.feedback_capacity);
+        let (scenes, scene_receiver) = scene_channel(SCENE_CAPACITY);
+        let scene = Scene::new("Current").with_effects(self.chain.preset());
+        let sender = feedback_sender.clone();
+        let config = self.config.clone();
+        let format = config.to_audio_format();
+        let buffer_size = self.buffer_size.take();
+        let ring_frames = ring_frames(config.buffer_frames, buffer_size);
+
+        if let Some(device) = self.virtual_device.take() {
+            let (input, reader) = if self.use_input {
+                let (handle, reader) =
+                    AudioInputStream::virtual_input(&device, ring_frames).into_parts();
+                (Some(handle.with_feedback(sender.clone())), Some(reader))
+            } else {
+                (None, None)
+            };
+            let processor = self
+                .into_processor(reader, command_receiver, feedback_sender, &config)
+                .with_scenes(scene_receiver);
+            let (processor, memory, health) = share(processor);
+            let output = StreamHandle::virtual_output(&device, run(&processor))
+                .with_feedback(sender.clone());
+            let devices = Devices::Virtual {
+                input: input.is_some(),
+                device,
+            };
+            return Ok(Engine {
+                format,
+                input,
+                output: Some(output),
+                processor,
+                devices,
+                ring_frames,
+                commands,
+                feedback,
+                scenes,
+                scene,
+                state: EngineState::Stopped,
+                memory,
+                sender,
+                prepared: false,
+                health,
+                buffer_size: None,
+                quality: EffectQuality::High,
+                metadata: Announcer::default(),
+            });
+        }
+
+        let manager = AudioDeviceManager::new();
+
+        let input_device = match (self.input_device.take(), input_name) {
+            (Some(device), _) => Some(device),
+            (None, Some(name)) => Some(manager.find_input(&name)?),
+            (None, None) if self.use_input => manager
+                .default_input()
+                .inspect_err(|e| log::warn!("Running without input: {e}"))
+                .ok(),
+            (None, None) => None,
+        };
+        let output_device = match (self.output_device.take(), output_name) {
+            (Some(device), _) => device,
+            (None, Some(name)) => manager.find_output(&name)?,
+            (None, None) => manager.default_output()?,
+        };
+
+        let (input, reader) = input_device
+            .as_ref()
+            .map(|device| open_input(device, format, ring_frames, buffer_size, &sender))
+            .transpose()?
+            .unzip();
+
+        let processor = self
+            .into_processor(reader, command_receiver, feedback_sender, &config)
+            .with_scenes(scene_receiver);
+        let (processor, memory, health) = share(processor);
+        let output = open_output(&output_device, format, buffer_size, &processor, &sender)?;
+
+        Ok(Engine {
+            format,
+            input,
+            output: Some(output),
+            processor,
+            devices: Devices::Hardware {
+                input: input_device.map(|device| device.name().to_string()),
+                output: output_device.name().to_string(),
+            },
+            ring_frames,
+            commands,
+            feedback,
+            scenes,
+            scene,
+            state: EngineState::Stopped,
+            memory,
+            sender,
+            prepared: false,
+            health,
+            buffer_size,
+            quality: EffectQuality::High,
+            metadata: Announcer::default(),
+        })
+    }
+
+    /// Checks the targets set with [`input`](Self::input) and
+    /// [`output`](Self::output), returning the names of the devices they
+    /// ask for, or `None` for the default ones.
+    fn target_devices(&mut self) -> Result<(Option<String>, Option<String>)> {
+        let input = match self.input_source.take() {
+            None => None,
+            Some(InputSource::Device(config)) => (config.device_id != DeviceId::default_input())
+                .then(|| config.device_id.as_str().to_string()),
+            Some(InputSource::Signal(signal)) => {
+                self.use_input = false;
+                self.signal = Some(signal);
+                None
+            }
+            Some(source) => {
+                return Err(AudioEngineError::configuration(format!(
+                    "the engine takes input from a device, not {source}"
+                )));
+            }
+        };
+        let output = match self.output_target.take() {
+            None => None,
+            Some(OutputTarget::Device(config)) => (config.device_id != DeviceId::default_output())
+                .then(|| config.device_id.as_str().to_string()),
+            Some(target) => {
+                return Err(AudioEngineError::configuration(format!(
+                    "the engine plays to a device, not {target}; render to files and streams offline"
+                )));
+            }
+        };
+        Ok((input, output))
+    }
+
+    /// Builds the audio thread side with the builder's processing settings.
+    fn into_processor(
+        self,
+        input: Option<RingBufferReader<Sample>>,
+        commands: RealtimeReceiver<EngineCommand>,
+        feedback: RealtimeSender<EngineFeedback>,
+        config: &StreamConfig,
+    ) -> EngineProcessor {
+        let tempo_map = self.tempo_map.unwrap_or_else(|| {
+            TempoMap::new(config.sample_rate, Tempo::default(), TimeSignature::COMMON)
+        });
+        let mut chain = self.chain;
+        if let Some(frames) = self.sub_block_frames {
+            chain.set_sub_block_frames(frames);
+        }
+        let processor = EngineProcessor::new(
+            chain,
+            input,
+            commands,
+            feedback,
+            config,
+            self.meter_interval_ms,
+        )
+        .with_determinism(self.seed, self.denormals)
+        .with_tempo_map(tempo_map)
+        .with_safety(self.safety)
+        .with_exposure(self.exposure);
+        let processor = match self.signal {
+            Some(signal) => processor.with_signal(SignalSource::new(signal, config.sample_rate)),
+            None => processor,
+        };
+        let processor = match self.tap {
+            Some(tap) => processor.with_tap(tap),
+            None => processor,
+        };
+        let processor = match self.timestamps {
+            Some(timestamps) => processor.with_output_timestamps(timestamps),
+            None => processor,
+        };
+        match self.crossfade {
+            Some((source, curve)) => processor.with_crossfade_source(source, curve),
+            None => processor,
+        }
+    }
+}
+
+/// Where an engine's streams run, kept so they can be rebuilt.
+#[derive(Debug)]
+enum Devices {
+    /// Hardware devices by name, looked up again on every rebuild
+    Hardware {
+        input: Option<String>,
+        output: String,
+    },
+    Virtual {
+        device: VirtualDevice,
+        input: bool,
+    },
+}
+
+/// Puts the processor behind the lock the stream callbacks share, with
+/// what the engine keeps of it.
+fn share(
+    processor: EngineProcessor,
+) -> (
+    Arc<Mutex<EngineProcessor>>,
+    MemoryReport,
+    Arc<HealthCounters>,
+) {
+    let memory = processor.memory_report();
+    let health = processor.health();
+    (Arc::new(Mutex::new(processor)), memory, health)
+}
+
+/// Frames the input ring holds: a few device buffers, or processing blocks
+/// if the device buffer size is left to the host.
+fn ring_frames(buffer_frames: usize, buffer_size: Option<BufferSize>) -> usize {
+    buffer_frames.max(buffer_size.map_or(0, BufferSize::as_usize)) * INPUT_RING_BUFFERS
+}
+
+/// The title on air and the streams it is announced to.
+#[derive(Default)]
+struct Announcer {
+    now_playing: Option<NowPlaying>,
+    sinks: Vec<Box<dyn MetadataSink>>,
+}
+
+/// The audio thread's state, shared by the output callback and the
+/// control thread, which only takes it while the streams are rebuilt.
+type SharedProcessor = Arc<Mutex<EngineProcessor>>;
+
+/// Output callback running `processor`, or rendering silence while the
+/// control thread holds it.
+fn run(processor: &SharedProcessor) -> impl FnMut(&mut [f32], Duration) + Send + 'static {
+    let processor = Arc::clone(processor);
+    move |data, latency| match processor.try_lock() {
+        Some(mut processor) => processor.process(data, latency),
+        None => data.fill(0.0),
+    }
+}
+
+/// Opens the input stream on a hardware device, reporting its closing on
+/// the feedback channel.
+fn open_input(
+    device: &AudioDevice,
+    format: AudioFormat,
+    ring_frames: usize,
+    buffer_size: Option<BufferSize>,
+    sender: &RealtimeSender<EngineFeedback>,
+) -> Result<(StreamHandle, RingBufferReader<Sample>)> {
+    let stream = AudioInputStream::with_buffer_size(device, format, ring_frames, buffer_size)?;
+    let (handle, reader) = stream.into_parts();
+    Ok((handle.with_feedback(sender.clone()), reader))
+}
+
+/// Opens the output stream on a hardware device, reporting stream errors
+/// and its closing on the feedback channel.
+fn open_output(
+    device: &AudioDevice,
+    format: AudioFormat,
+    buffer_size: Option<BufferSize>,
+    processor: &SharedProcessor,
+    sender: &RealtimeSender<EngineFeedback>,
+) -> Result<StreamHandle> {
+    let errors = sender.clone();
+    let output = StreamHandle::output(device, format, buffer_size, run(processor), move |err| {
+        log::error!("Engine output stream error: {err}");
+        let _ = errors.try_send(EngineFeedback::Error(err.to_string()));
+    })?;
+    Ok(output.with_feedback(sender.clone()))
+}
+
+/// A running audio engine: input, effect chain and output.
+pub struct Engine {
+    format: AudioFormat,
+    input: Option<StreamHandle>,
+    /// `None` only after a rebuild failed to open the output
+    output: Option<StreamHandle>,
+    processor: SharedProcessor,
+    devices: Devices,
+    ring_frames: usize,
+    commands: ControlSender<EngineCommand>,
+    feedback: ControlReceiver<EngineFeedback>,
+    scenes: SceneSender,
+    /// Settings as of the last command sent through [`send`](Engine::send)
+    scene: Scene,
+    state: EngineState,
+    /// What the audio thread was given when the engine was built
+    memory: MemoryReport,
+    /// The audio thread's feedback sender, for reserving the queue and
+    /// reporting stream errors and recoveries
+    sender: RealtimeSender<EngineFeedback>,
+    prepared: bool,
+    /// Counted by the audio thread for health checks
+    health: Arc<HealthCounters>,
+    /// Device buffer size, `None` for the host's default
+    buffer_size: Option<BufferSize>,
+    /// Quality tier last sent to the chain
+    quality: EffectQuality,
+    /// Streams that get now-playing updates
+    metadata: Announcer,
+}
+
+impl Engine {
+    #[must_use]
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    /// Makes every allocation the audio thread could need, then arms the
+    /// allocation guard.
+    ///
+    /// Buffers for the largest block, the effects' state and the rings are
+    /// allocated when the engine is built; this also grows the feedback
+    /// and scene queues to their full size, which would otherwise happen
+    /// on the audio thread the first time they fill. From then on the audio
+    /// thread runs inside a [`RealtimeScope`], and allocations the
+    /// application's allocator reports through [`record_allocation`] come
+    /// back as [`EngineFeedback::AllocationViolation`].
+    ///
+    /// [`RealtimeScope`]: crate::markers::guard::RealtimeScope
+    /// [`record_allocation`]: crate::markers::guard::record_allocation
+    ///
+    /// # Errors
+    /// Returns an error if the engine is running or the command queue is
+    /// full.
+    pub fn prepare(&mut self) -> Result<()> {
+        if self.state == EngineState::Running {
+            return Err(AudioEngineError::pipeline_state(
+                "prepare the engine before starting it",
+            ));
+        }
+        reserve_feedback(&self.sender, &self.feedback, || EngineFeedback::Underrun);
+        self.scenes.reserve();
+        self.commands.try_send(EngineCommand::Prepare)?;
+        self.prepared = true;
+        Ok(())
+    }
+
+    #[must_use]
+    pub const fn is_prepared(&self) -> bool {
+        self.prepared
+    }
+
+    /// Starts the streams and processing.
+    ///
+    /// # Errors
+    /// Returns an error if the command queue is full or a stream fails to
+    /// start.
+    pub fn start(&mut self) -> Result<()> {
+        self.commands.try_send(EngineCommand::Start)?;
+        if let Some(input) = &self.input {
+            input.play()?;
+        }
+        self.output()?.play()?;
+        self.health.mark_progress();
+        self.state = EngineState::Running;
+        Ok(())
+    }
+
+    /// Starts the streams like [`start`](Self::start), as a recording
+    /// pass: the transport moves back by the pre-roll from the punch-in
+    /// point and rolls after the count-in.
+    ///
+    /// # Errors
+    /// Returns an error if the command queue is full or a stream fails to
+    /// start.
+    pub fn record(&mut self, count_in: CountIn) -> Result<()> {
+        self.commands.try_send(EngineCommand::Record(count_in))?;
+        if let Some(input) = &self.input {
+            input.play()?;
+        }
+        self.output()?.play()?;
+        self.health.mark_progress();
+        self.state = EngineState::Running;
+        Ok(())
+    }
+
+    /// Stops the streams. The chain is reset before processing resumes.
+    ///
+    /// # Errors
+    /// Returns an error if a stream fails to pause or the command queue is
+    /// full.
+    pub fn stop(&mut self) -> Result<()> {
+        // Stop both streams even if one of them fails
+        let output_paused = self.output.as_ref().map_or(Ok(()), StreamHandle::pause);
+        let input_paused = self.input.as_ref().map_or(Ok(()), StreamHandle::pause);
+        self.state = EngineState::Stopped;
+        output_paused?;
+        input_paused?;
+        self.commands.try_send(EngineCommand::Stop)
+    }
+
+    /// Closes both streams, output first, logging rather than returning
+    /// failures so the other one is still closed.
+    fn close_streams(&mut self) {
+        for stream in [self.output.take(), self.input.take()]
+            .into_iter()
+            .flatten()
+        {
+            let kind = stream.kind();
+            if let Err(e) = stream.close() {
+                log::warn!("Failed to close the {kind} stream: {e}");
+            }
+        }
+    }
+
+    fn output(&self) -> Result<&StreamHandle> {
+        self.output
+            .as_ref()
+            .ok_or_else(|| AudioEngineError::DeviceAccess {
+                message: "no output stream: rebuilding it failed".to_string(),
+            })
+    }
+
+    /// Rebuilds the streams on the same devices, looking them up again by
+    /// name, e.g. after a stream error. The chain, transport and queued
+    /// commands carry on where they were, and a running engine keeps
+    /// running. If the input can't be reopened the engine carries on
+    /// without it.
+    ///
+    /// # Errors
+    /// Returns an error if the output device is gone or its stream can't
+    /// be built or started; the engine then has no output until a later
+    /// rebuild succeeds.
+    pub fn rebuild_streams(&mut self) -> Result<()> {
+        // Close the old streams first: some hosts won't open a device twice
+        self.close_streams();
+        let (format, ring_frames) = (self.format, self.ring_frames);
+        let output = match &self.devices {
+            Devices::Virtual { device, input } => {
+                let reader = input.then(|| {
+                    let (handle, reader) =
+                        AudioInputStream::virtual_input(device, ring_frames).into_parts();
+                    self.input = Some(handle.with_feedback(self.sender.clone()));
+                    reader
+                });
+                self.processor.lock().set_input(reader);
+                StreamHandle::virtual_output(device, run(&self.processor))
+                    .with_feedback(self.sender.clone())
+            }
+            Devices::Hardware { input, output } => {
+                let manager = AudioDeviceManager::new();
+                let reader = input.as_deref().and_then(|name| {
+                    manager
+                        .find_input(name)
+                        .and_then(|device| {
+                            let buffer_size = self.buffer_size;
+                            open_input(&device, format, ring_frames, buffer_size, &self.sender)
+                        })
+                        .inspect_err(|e| log::warn!("Carrying on without input {name}: {e}"))
+                        .ok()
+                        .map(|(handle, reader)| {
+                            self.input = Some(handle);
+                            reader
+                        })
+                });
+                self.processor.lock().set_input(reader);
+                let device = manager.find_output(output)?;
+                open_output(
+                    &device,
+                    format,
+                    self.buffer_size,
+                    &self.processor,
+                    &self.sender,
+                )?
+            }
+        };
+        self.output = Some(output);
+        if self.state == EngineState::Running {
+            if let Some(input) = &self.input {
+                input.play()?;
+            }
+            self.output()?.play()?;
+        }
+        Ok(())
+    }
+
+    /// Moves the output to another device, by name, and rebuilds the
+    /// streams; see [`rebuild_streams`](Self::rebuild_streams).
+    ///
+    /// # Errors
+    /// Returns an error if the engine runs on a virtual device, or if the
+    /// streams can't be rebuilt on the new device.
+    pub fn switch_output_device(&mut self, name: &str) -> Result<()> {
+        match &mut self.devices {
+            Devices::Hardware { output, .. } => name.clone_into(output),
+            Devices::Virtual { .. } => {
+                return Err(AudioEngineError::configuration(
+                    "a virtual device's output can't be switched",
+                ));
+            }
+        }
+        self.rebuild_streams()
+    }
+
+    /// Name of the output device, or `None` on a virtual device
+    #[must_use]
+    pub fn output_device(&self) -> Option<&str> {
+        match &self.devices {
+            Devices::Hardware { output, .. } => Some(output),
+            Devices::Virtual { .. } => None,
+        }
+    }
+
+    /// Device buffer size asked of the host, `None` for its default
+    #[must_use]
+    pub const fn buffer_size(&self) -> Option<BufferSize> {
+        self.buffer_size
+    }
+
+    /// Rebuilds the streams with device buffers of `size` frames; see
+    /// [`rebuild_streams`](Self::rebuild_streams). A running engine fades
+    /// out before the streams are closed and back in once they run again.
+    ///
+    /// # Errors
+    /// Returns an error if the engine runs on a virtual device, the command
+    /// queue is full or the streams can't be rebuilt with the new size.
+    pub fn set_buffer_size(&mut self, size: BufferSize) -> Result<()> {
+        if matches!(self.devices, Devices::Virtual { .. }) {
+            return Err(AudioEngineError::configuration(
+                "a virtual device's buffer size can't be changed",
+            ));
+        }
+        let running = self.state == EngineState::Running;
+        if running {
+            self.commands.try_send(EngineCommand::Mute(true))?;
+            // Let the fade play out, through the last of the old buffers
+            let buffer = self
+                .buffer_size
+                .map_or(self.ring_frames / INPUT_RING_BUFFERS, BufferSize::as_usize);
+            let fade = self.format.sample_rate.samples_for_milliseconds(MUTE_MS);
+            let frames = u64::try_from(buffer).unwrap_or(u64::MAX) + u64::from(fade);
+            thread::sleep(Duration::from_nanos(
+                frames.saturating_mul(self.format.sample_rate.period_nanos()),
+            ));
+        }
+        self.ring_frames = self.ring_frames.max(ring_frames(0, Some(size)));
+        self.buffer_size = Some(size);
+        let rebuilt = self.rebuild_streams();
+        if running {
+            self.commands.try_send(EngineCommand::Mute(false))?;
+        }
+        rebuilt
+    }
+
+    /// Share of the last callback's duration spent processing it; near 1
+    /// the callbacks start to overrun
+    #[must_use]
+    pub fn cpu_load(&self) -> f32 {
+        self.health.load()
+    }
+
+    /// Callbacks so far that spent nearly all of their duration processing
+    #[must_use]
+    pub fn overruns(&self) -> u64 {
+        self.health.overruns()
+    }
+
+    /// Quality tier the chain's effects run at
+    #[must_use]
+    pub const fn effect_quality(&self) -> EffectQuality {
+        self.quality
+    }
+
+    /// Moves every effect in the chain to `quality`.
+    ///
+    /// # Errors
+    /// Returns an error if the command queue is full.
+    pub fn set_effect_quality(&mut self, quality: EffectQuality) -> Result<()> {
+        self.send(EngineCommand::SetEffectQuality(quality))
+    }
+
+    /// Starts a new listener's exposure dose, e.g. when a kiosk session
+    /// ends. Does nothing unless the engine was built
+    /// [`with_exposure`](EngineBuilder::with_exposure).
+    ///
+    /// # Errors
+    /// Returns an error if the command queue is full.
+    pub fn reset_exposure(&mut self) -> Result<()> {
+        self.send(EngineCommand::ResetExposure)
+    }
+
+    /// Sends now-playing updates to `sink`, e.g. an
+    /// [`IcecastMetadata`](crate::io::IcecastMetadata) handle, starting
+    /// with the current title if one is set.
+    ///
+    /// # Errors
+    /// Returns an error if the current title can't be delivered; the sink
+    /// is added anyway.
+    pub fn add_metadata_sink(&mut self, sink: impl MetadataSink + 'static) -> Result<()> {
+        let mut sink = Box::new(sink);
+        let sent = self
+            .metadata
+            .now_playing
+            .as_ref()
+            .map_or(Ok(()), |now| sink.set_now_playing(now));
+        self.metadata.sinks.push(sink);
+        sent
+    }
+
+    /// Title last set with [`set_now_playing`](Self::set_now_playing)
+    #[must_use]
+    pub const fn now_playing(&self) -> Option<&NowPlaying> {
+        self.metadata.now_playing.as_ref()
+    }
+
+    /// Announces what is on air to every metadata sink, each in its
+    /// stream's own format; see [`metadata`](crate::io::metadata).
+    ///
+    /// # Errors
+    /// Returns the first error a sink returned. The other sinks are
+    /// still updated.
+    pub fn set_now_playing(&mut self, title: &str, artist: Option<&str>) -> Result<()> {
+        let mut now = NowPlaying::new(title);
+        now.artist = artist.map(str::to_string);
+        let mut sent = Ok(());
+        for sink in &mut self.metadata.sinks {
+            if let Err(e) = sink.set_now_playing(&now) {
+                log::warn!("Now-playing update failed: {e}");
+                if sent.is_ok() {
+                    sent = Err(e);
+                }
+            }
+        }
+        self.metadata.now_playing = Some(now);
+        sent
+    }
+
+    /// Checks the streams and what the audio thread has counted, for
+    /// liveness and readiness probes. Underrun rates are over the last
+    /// minute however often this is called.
+    #[must_use]
+    pub fn health(&self) -> HealthReport {
+        let running = self.state == EngineState::Running;
+        let since_callback = self.health.since_progress();
+        #[allow(clippy::cast_precision_loss)]
+        let underrun_rate = self.health.recent_underruns() as f64;
+        let stream_status = |stream: &StreamHandle| {
+            if stream.is_playing() {
+                StreamStatus::Playing
+            } else {
+                StreamStatus::Paused
+            }
+        };
+        let wants_input = match &self.devices {
+            Devices::Hardware { input, .. } => input.is_some(),
+            Devices::Virtual { input, .. } => *input,
+        };
+        HealthReport {
+            state: self.state,
+            output: self
+                .output
+                .as_ref()
+                .map_or(StreamStatus::Missing, stream_status),
+            input: self.input.as_ref().map_or_else(
+                || wants_input.then_some(StreamStatus::Missing),
+                |input| Some(stream_status(input)),
+            ),
+            // A stopped engine isn't called, which isn't a stall
+            processing: !running || since_callback < STALL_TIMEOUT,
+            input_fill: self.input.is_some().then(|| self.health.input_fill()),
+            underruns: self.health.underruns(),
+            underrun_rate,
+            since_callback,
+            network: None,
+            disk_backlog: None,
+        }
+    }
+
+    /// Sends feedback from the control thread, alongside the audio
+    /// thread's. Returns false if the queue is full.
+    pub(crate) fn report(&self, feedback: EngineFeedback) -> bool {
+        self.sender.try_send(feedback)
+    }
+
+    /// Queues a command for the audio thread.
+    ///
+    /// # Errors
+    /// Returns an error if the command queue is full.
+    pub fn send(&mut self, command: EngineCommand) -> Result<()> {
+        let tracked = command.clone();
+        self.commands.try_send(command)?;
+        if let EngineCommand::SetEffectQuality(quality) = tracked {
+            self.quality = quality;
+        }
+        self.scene.track(&tracked);
+        Ok(())
+    }
+
+    /// Captures the engine's settings: master gain and pan, effect
+    /// parameters and transport ranges.
+    ///
+    /// The engine keeps track of the commands sent through
+    /// [`send`](Self::send) and [`recall`](Self::recall); commands sent
+    /// through a cloned [`commands`](Self::commands) sender aren't seen.
+    /// Attach a mixer's and routing matrix's settings with
+    /// [`Scene::with_mixer`] and [`Scene::with_routing`].
+    #[must_use]
+    pub fn scene(&self, name: impl Into<String>) -> Scene {
+        self.scene.clone().with_name(name)
+    }
+
+    /// Moves the engine to a scene, ramping over `transition`. The mixer
+    /// and routing parts of the scene are left to their owners'
+    /// `recall`.
+    ///
+    /// # Errors
+    /// Returns an error if too many recalls are waiting for the audio
+    /// thread.
+    pub fn recall(&mut self, scene: &Scene, transition: Duration) -> Result<()> {
+        let frames = self
+            .format
+            .sample_rate
+            .samples_for_milliseconds(u32::try_from(transition.as_millis()).unwrap_or(u32::MAX));
+        let mut engine_scene = scene.clone();
+        engine_scene.mixer = None;
+        engine_scene.routing = None;
+        self.scenes.send(SceneRecall::new(engine_scene, frames))?;
+        self.scene.merge_engine(scene);
+        Ok(())
+    }
+
+    /// Sender for commands, which can be cloned onto other threads
+    #[must_use]
+    pub const fn commands(&self) -> &ControlSender<EngineCommand> {
+        &self.commands
+    }
+
+    /// Levels, state changes, underruns and stream errors from the audio
+    /// thread
+    #[must_use]
+    pub const fn feedback(&self) -> &ControlReceiver<EngineFeedback> {
+        &self.feedback
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// The state last requested with [`start`](Self::start) or
+    /// [`stop`](Self::stop)
+    #[must_use]
+    pub const fn state(&self) -> EngineState {
+        self.state
+    }
+
+    #[must_use]
+    pub const fn has_input(&self) -> bool {
+        self.input.is_some()
+    }
+
+    /// Memory allocated for the audio thread when the engine was built:
+    /// every effect in the chain, the block buffers and the rings. Check
+    /// it against the RAM budget before [`start`](Self::start); nothing
+    /// more is allocated once processing runs.
+    #[must_use]
+    pub const fn memory_report(&self) -> &MemoryReport {
+        &self.memory
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.close_streams();
+    }
+}
+
+impl std::fmt::Debug for Engine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Engine")
+            .field("format", &self.format)
+            .field("input", &self.input.is_some())
+            .field("commands", &self.commands)
+            .field("feedback", &self.feedback)
+            .field("scene", &self.scene.name)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,192 @@
+//! Buffer size adaptation under overload
+//!
+//! When processing takes up nearly all of each callback's duration, the
+//! output glitches. A [`BufferAdapter`] watches the engine's callback
+//! overruns ([`Engine::overruns`]) and, once they keep coming, steps the
+//! device buffer up to the next larger [`BufferSize`] with
+//! [`Engine::set_buffer_size`], giving up some latency rather than
+//! glitching. Each step is reported as
+//! [`EngineFeedback::BufferSizeChanged`].
+//!
+//! An engine left on the host's default buffer size is taken to be at the
+//! default [`BufferSize`], so its first step is to the size after that.
+//!
+//! ```no_run
+//! use audio_engine::engine::Engine;
+//! use audio_engine::engine::overload::{BufferAdapter, OverloadSettings};
+//!
+//! let mut engine = Engine::builder().build()?;
+//! let mut adapter = BufferAdapter::new(OverloadSettings::default());
+//! engine.start()?;
+//! loop {
+//!     if let Some(change) = adapter.poll(&mut engine) {
+//!         println!("buffer size now {}", change.size);
+//!     }
+//!     std::thread::sleep(std::time::Duration::from_millis(100));
+//! }
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! [`EngineFeedback::BufferSizeChanged`]: crate::channel::EngineFeedback::BufferSizeChanged
+
+use std::time::{Duration, Instant};
+
+use crate::channel::{EngineFeedback, EngineState};
+use crate::engine::Engine;
+use crate::types::BufferSize;
+
+/// When a [`BufferAdapter`] raises the buffer size, and how far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverloadSettings {
+    /// Overruns within [`window`](Self::window) that raise the buffer
+    /// size; zero never does
+    pub overrun_limit: u32,
+    pub window: Duration,
+    /// Largest size to step up to
+    pub max_size: BufferSize,
+    /// How long a new size runs before overruns count again
+    pub settle: Duration,
+}
+
+impl Default for OverloadSettings {
+    fn default() -> Self {
+        Self {
+            overrun_limit: 20,
+            window: Duration::from_secs(2),
+            max_size: BufferSize::SIZE_4096,
+            settle: Duration::from_secs(5),
+        }
+    }
+}
+
+impl OverloadSettings {
+    #[must_use]
+    pub const fn with_overrun_limit(mut self, limit: u32, window: Duration) -> Self {
+        self.overrun_limit = limit;
+        self.window = window;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_max_size(mut self, size: BufferSize) -> Self {
+        self.max_size = size;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+}
+
+/// A buffer size change made by [`BufferAdapter::poll`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferChange {
+    /// Size before, `None` for the host's default
+    pub previous: Option<BufferSize>,
+    pub size: BufferSize,
+    /// Overruns within the window that set it off
+    pub overruns: u32,
+}
+
+/// Raises an engine's buffer size while its callbacks keep overrunning.
+#[derive(Debug)]
+pub struct BufferAdapter {
+    settings: OverloadSettings,
+    /// Engine overruns as of the last poll
+    seen: u64,
+    window_start: Instant,
+    window_overruns: u32,
+    /// Overruns don't count before this
+    settle_until: Option<Instant>,
+    changes: u32,
+}
+
+impl BufferAdapter {
+    #[must_use]
+    pub fn new(settings: OverloadSettings) -> Self {
+        Self {
+            settings,
+            seen: 0,
+            window_start: Instant::now(),
+            window_overruns: 0,
+            settle_until: None,
+            changes: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &OverloadSettings {
+        &self.settings
+    }
+
+    /// Buffer size changes made so far
+    #[must_use]
+    pub const fn changes(&self) -> u32 {
+        self.changes
+    }
+
+    /// Counts the overruns since the last poll and raises the buffer size
+    /// if there were too many within the window, reporting the change on
+    /// the engine's feedback channel. Call it regularly from the control
+    /// thread.
+    pub fn poll(&mut self, engine: &mut Engine) -> Option<BufferChange> {
+        let now = Instant::now();
+        let overruns = engine.overruns();
+        let new = u32::try_from(overruns.saturating_sub(self.seen)).unwrap_or(u32::MAX);
+        self.seen = overruns;
+        if engine.state() != EngineState::Running
+            || self.settings.overrun_limit == 0
+            || self.settle_until.is_some_and(|at| now < at)
+        {
+            self.restart_window(now);
+            return None;
+        }
+        if now.duration_since(self.window_start) > self.settings.window {
+            self.restart_window(now);
+        }
+        self.window_overruns = self.window_overruns.saturating_add(new);
+        if self.window_overruns < self.settings.overrun_limit {
+            return None;
+        }
+
+        let overruns = self.window_overruns;
+        self.restart_window(now);
+        self.settle_until = Some(now + self.settings.settle);
+        let previous = engine.buffer_size();
+        let Some(size) = previous
+            .unwrap_or_default()
+            .next_larger()
+            .filter(|size| size.as_u32() <= self.settings.max_size.as_u32())
+        else {
+            log::warn!(
+                "Buffer adapter: {overruns} overruns, but the buffer size is at its largest"
+            );
+            return None;
+        };
+        if let Err(e) = engine.set_buffer_size(size) {
+            log::error!("Buffer adapter: raising the buffer size to {size} failed: {e}");
+            return None;
+        }
+        // The rebuild's own overruns don't count against the new size
+        self.seen = engine.overruns();
+        self.changes += 1;
+        log::warn!("Buffer adapter: buffer size raised to {size} after {overruns} overruns");
+        let _ = engine.report(EngineFeedback::BufferSizeChanged {
+            previous,
+            size,
+            overruns,
+        });
+        Some(BufferChange {
+            previous,
+            size,
+            overruns,
+        })
+    }
+
+    const fn restart_window(&mut self, now: Instant) {
+        self.window_start = now;
+        self.window_overruns = 0;
+    }
+}
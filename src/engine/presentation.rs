@@ -0,0 +1,106 @@
+//! When output audio is heard, for audio/video sync
+//!
+//! A video player showing frames against the engine's audio needs to know
+//! when each sample leaves the speakers, not when it was rendered: the
+//! device buffers and converters between the output callback and the DAC
+//! add a latency of their own. An engine built with
+//! [`EngineBuilder::with_output_timestamps`] pushes an [`OutputTimestamp`]
+//! for every device buffer it fills, pairing the buffer's first frame with
+//! the host [`Instant`] it is predicted to reach the DAC, from the callback
+//! time and the latency the host reports for the stream.
+//!
+//! Any later frame plays a whole number of sample periods after that, so
+//! [`OutputTimestamp::presentation_of`] places frames, and transport
+//! positions, with sample accuracy between timestamps.
+//!
+//! ```no_run
+//! use audio_engine::buffer::RingBuffer;
+//! use audio_engine::engine::Engine;
+//! use audio_engine::engine::presentation::OutputTimestamp;
+//!
+//! let (writer, mut timestamps) = RingBuffer::<OutputTimestamp>::new(64);
+//! let mut engine = Engine::builder().with_output_timestamps(writer).build()?;
+//! engine.start()?;
+//! let rate = engine.format().sample_rate;
+//! while let Ok(stamp) = timestamps.pop() {
+//!     if let Some(position) = stamp.position {
+//!         // Show the video frame for `position` at `stamp.presentation`
+//!         println!("{:?} plays at {:?}", position, stamp.presentation_of(stamp.frame + 480, rate));
+//!     }
+//! }
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! Virtual devices have no DAC, so their timestamps carry no latency and
+//! present each buffer when it is rendered.
+//!
+//! [`EngineBuilder::with_output_timestamps`]: super::EngineBuilder::with_output_timestamps
+
+use std::time::{Duration, Instant};
+
+use crate::types::{SampleRate, Timestamp};
+
+/// When the first frame of an output buffer reaches the DAC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputTimestamp {
+    /// Frames the engine had output before this buffer
+    pub frame: u64,
+    /// Frames in the buffer
+    pub frames: u32,
+    /// Host time the buffer's first frame is predicted to be played at
+    pub presentation: Instant,
+    /// Time from the output callback to the DAC, as the host reports it
+    pub latency: Duration,
+    /// Transport position heard at the first frame, after the effect
+    /// chain's own latency, or `None` while the transport is stopped
+    pub position: Option<Timestamp>,
+}
+
+impl OutputTimestamp {
+    /// Host time output frame `frame` is played at. Frames before this
+    /// buffer are placed as if the stream ran without a gap since.
+    #[must_use]
+    pub fn presentation_of(&self, frame: u64, sample_rate: SampleRate) -> Instant {
+        self.offset(frame >= self.frame, frame.abs_diff(self.frame), sample_rate)
+    }
+
+    /// Host time the transport plays `position` at, if it is rolling and
+    /// keeps rolling without a seek or loop until then
+    #[must_use]
+    pub fn presentation_of_position(
+        &self,
+        position: Timestamp,
+        sample_rate: SampleRate,
+    ) -> Option<Instant> {
+        let start = self.position?;
+        Some(self.offset(position >= start, position.diff(start), sample_rate))
+    }
+
+    /// Host time the buffer after this one starts playing
+    #[must_use]
+    pub fn end(&self, sample_rate: SampleRate) -> Instant {
+        self.presentation_of(self.frame + u64::from(self.frames), sample_rate)
+    }
+
+    /// The presentation time moved `frames` later, or earlier if not
+    /// `later`; unmoved if the host clock can't reach that far back
+    fn offset(&self, later: bool, frames: u64, sample_rate: SampleRate) -> Instant {
+        let shift = frames_duration(frames, u64::from(sample_rate.as_hz()));
+        if later {
+            self.presentation + shift
+        } else {
+            self.presentation
+                .checked_sub(shift)
+                .unwrap_or(self.presentation)
+        }
+    }
+}
+
+/// Duration of `frames` at `rate` Hz, exact to the nanosecond
+const fn frames_duration(frames: u64, rate: u64) -> Duration {
+    let secs = frames / rate;
+    let nanos = (frames % rate) * 1_000_000_000 / rate;
+    // Below a second's worth of nanoseconds
+    #[allow(clippy::cast_possible_truncation)]
+    Duration::new(secs, nanos as u32)
+}
@@ -0,0 +1,687 @@
+//! Real-time side of the engine
+//!
+//! [`EngineProcessor`] runs inside the output device callback. For every
+//! device buffer it applies pending commands, pulls the same number of
+//! frames from the input ring (or generates them, for a test signal),
+//! runs the effect chain, applies the master gain and pan, passes it
+//! through the [safety stage](super::safety), meters the listener's
+//! exposure dose if asked to, advances the transport and meters the
+//! result. Before the chain runs, the effects are told where the block
+//! falls on the tempo map. Callbacks, the share of each callback's
+//! duration spent processing it, underruns and the input ring's fill are
+//! counted for
+//! [`Engine::health`](super::Engine::health) and the
+//! [`BufferAdapter`](super::overload::BufferAdapter).
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::analysis::exposure::{ExposureMeter, ExposureSettings};
+use crate::audio::stream::StreamConfig;
+use crate::buffer::memory::heap_bytes;
+use crate::buffer::{MemoryKind, MemoryReport, RingBufferReader, RingBufferWriter};
+use crate::channel::{
+    EngineCommand, EngineFeedback, EngineState, RealtimeReceiver, RealtimeSender,
+};
+use crate::dsp::automation::ENABLED;
+use crate::dsp::chain::EffectChain;
+use crate::dsp::crossfade::CrossfadeCurve;
+use crate::dsp::denormal::{DenormalPolicy, flush_denormals_slice};
+use crate::dsp::params::{ParamId, ParamValue, SmoothParam};
+use crate::dsp::traits::{EffectId, ProcessContext};
+use crate::engine::health::HealthCounters;
+use crate::engine::metronome::{Metronome, MetronomeSettings};
+use crate::engine::presentation::OutputTimestamp;
+use crate::engine::safety::{SafetyLimiter, SafetySettings};
+use crate::engine::scene::{SceneRecall, SceneReceiver};
+use crate::engine::transport::Transport;
+use crate::graph::Source;
+use crate::io::SignalSource;
+use crate::markers::guard::{self, RealtimeScope};
+use crate::mixer::Crossfader;
+use crate::types::{
+    AudioFormat, BitDepth, ChannelCount, Decibels, Pan, Sample, SampleRate, Tempo, TempoMap,
+    TimeSignature, Timestamp,
+};
+
+/// Ramp length for master gain and pan changes, in milliseconds
+const SMOOTHING_MS: u32 = 10;
+/// Ramp length for [`EngineCommand::Mute`], in milliseconds
+pub const MUTE_MS: u32 = 20;
+
+fn ring_bytes(ring: &RingBufferReader<Sample>) -> usize {
+    ring.capacity() * size_of::<Sample>()
+}
+
+/// Counts an underrun and reports it.
+fn underrun(health: &HealthCounters, feedback: &RealtimeSender<EngineFeedback>) {
+    health.underrun();
+    let _ = feedback.try_send(EngineFeedback::Underrun);
+}
+
+/// A second source, blended with the input by a crossfader.
+#[derive(Debug)]
+struct CrossfadeInput {
+    crossfader: Crossfader,
+    source: RingBufferReader<Sample>,
+    block: Vec<Sample>,
+    mixed: Vec<Sample>,
+}
+
+/// Audio thread state of an [`Engine`](super::Engine).
+#[derive(Debug)]
+pub struct EngineProcessor {
+    chain: EffectChain,
+    transport: Transport,
+    tempo_map: TempoMap,
+    metronome: Metronome,
+    input: Option<RingBufferReader<Sample>>,
+    /// Generates the input when there is no input ring
+    signal: Option<SignalSource>,
+    crossfade: Option<CrossfadeInput>,
+    /// Gets a copy of every processed block, before the master gain
+    tap: Option<RingBufferWriter<Sample>>,
+    /// Gets when each device buffer reaches the DAC
+    timestamps: Option<RingBufferWriter<OutputTimestamp>>,
+    /// Frames output so far
+    output_frames: u64,
+    commands: RealtimeReceiver<EngineCommand>,
+    feedback: RealtimeSender<EngineFeedback>,
+    scenes: Option<SceneReceiver>,
+    /// Scene transition in progress
+    recall: Option<Box<SceneRecall>>,
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    block: Vec<Sample>,
+    state: EngineState,
+    /// Whether a whole block has been read from the input yet; until then
+    /// a short read is the input starting up, not an underrun
+    primed: bool,
+    gain: SmoothParam,
+    /// Fades the output out and back in for [`EngineCommand::Mute`]
+    mute: SmoothParam,
+    left: SmoothParam,
+    right: SmoothParam,
+    /// Last stage before the device, `None` if turned off
+    safety: Option<SafetyLimiter>,
+    /// Dose meter on the protected output, `None` unless asked for
+    exposure: Option<ExposureMeter>,
+    meter_interval: usize,
+    meter_frames: usize,
+    input_peak: f32,
+    output_peak: f32,
+    /// Seed the chain is restarted from on every stop, in deterministic
+    /// mode
+    seed: Option<u64>,
+    denormals: DenormalPolicy,
+    /// Whether processing runs under the allocation guard
+    guarded: bool,
+    /// Guard violations already reported
+    violations: u64,
+    /// Whether parameter changes are reported while the transport plays
+    recording_automation: bool,
+    health: Arc<HealthCounters>,
+}
+
+impl EngineProcessor {
+    /// `config.buffer_frames` is the most frames processed at once; larger
+    /// device buffers are split.
+    pub fn new(
+        mut chain: EffectChain,
+        input: Option<RingBufferReader<Sample>>,
+        commands: RealtimeReceiver<EngineCommand>,
+        feedback: RealtimeSender<EngineFeedback>,
+        config: &StreamConfig,
+        meter_interval_ms: u32,
+    ) -> Self {
+        let (sample_rate, channels) = (config.sample_rate, config.channels);
+        chain.initialize(sample_rate, channels);
+        Self {
+            chain,
+            transport: Transport::new(sample_rate),
+            metronome: Metronome::new(MetronomeSettings::default(), sample_rate),
+            tempo_map: TempoMap::new(sample_rate, Tempo::default(), TimeSignature::COMMON),
+            input,
+            signal: None,
+            crossfade: None,
+            tap: None,
+            timestamps: None,
+            output_frames: 0,
+            commands,
+            feedback,
+            scenes: None,
+            recall: None,
+            sample_rate,
+            channels,
+            block: vec![Sample::SILENCE; config.buffer_frames.max(1) * channels.count_usize()],
+            state: EngineState::Stopped,
+            primed: false,
+            gain: SmoothParam::new(1.0),
+            mute: SmoothParam::new(1.0),
+            left: SmoothParam::new(1.0),
+            right: SmoothParam::new(1.0),
+            safety: SafetyLimiter::new(SafetySettings::default(), sample_rate, channels),
+            exposure: None,
+            meter_interval: sample_rate.samples_for_milliseconds(meter_interval_ms) as usize,
+            meter_frames: 0,
+            input_peak: 0.0,
+            output_peak: 0.0,
+            seed: None,
+            denormals: DenormalPolicy::default(),
+            guarded: false,
+            violations: 0,
+            recording_automation: false,
+            health: Arc::default(),
+        }
+    }
+
+    /// Applies the engine's determinism settings to the processor and its
+    /// chain.
+    #[must_use]
+    pub fn with_determinism(mut self, seed: Option<u64>, denormals: DenormalPolicy) -> Self {
+        self.seed = seed;
+        self.denormals = denormals;
+        self.chain.set_denormal_policy(denormals);
+        if let Some(seed) = seed {
+            self.chain.reseed(seed);
+        }
+        self
+    }
+
+    /// Generates the input with `signal` while there is no input ring. The
+    /// signal starts again from the beginning every time the engine stops.
+    #[must_use]
+    pub fn with_signal(mut self, mut signal: SignalSource) -> Self {
+        signal.initialize(self.sample_rate, self.channels);
+        if let Some(seed) = self.seed {
+            signal.reseed(seed);
+        }
+        self.signal = Some(signal);
+        self
+    }
+
+    /// Replaces the default tempo map of 120 BPM in 4/4.
+    #[must_use]
+    pub fn with_tempo_map(mut self, tempo_map: TempoMap) -> Self {
+        self.tempo_map = tempo_map;
+        self
+    }
+
+    /// Adds a second source, interleaved in the engine's format, that
+    /// [`EngineCommand::SetCrossfade`] fades the input over to.
+    #[must_use]
+    pub fn with_crossfade_source(
+        mut self,
+        source: RingBufferReader<Sample>,
+        curve: CrossfadeCurve,
+    ) -> Self {
+        self.crossfade = Some(CrossfadeInput {
+            crossfader: Crossfader::new(self.channels, self.sample_rate).with_curve(curve),
+            source,
+            block: vec![Sample::SILENCE; self.block.len()],
+            mixed: vec![Sample::SILENCE; self.block.len()],
+        });
+        self
+    }
+
+    /// Copies every processed block into `tap`, after the chain and the
+    /// metronome but before the master gain and pan. Blocks that don't fit
+    /// are dropped.
+    #[must_use]
+    pub fn with_tap(mut self, tap: RingBufferWriter<Sample>) -> Self {
+        self.tap = Some(tap);
+        self
+    }
+
+    /// Pushes an [`OutputTimestamp`] into `timestamps` for every device
+    /// buffer. Timestamps that don't fit are dropped.
+    #[must_use]
+    pub fn with_output_timestamps(mut self, timestamps: RingBufferWriter<OutputTimestamp>) -> Self {
+        self.timestamps = Some(timestamps);
+        self
+    }
+
+    /// Replaces the default safety stage after the master gain.
+    #[must_use]
+    pub fn with_safety(mut self, settings: SafetySettings) -> Self {
+        self.safety = SafetyLimiter::new(settings, self.sample_rate, self.channels);
+        self
+    }
+
+    /// Meters the listener's exposure dose on the output, after the
+    /// safety stage.
+    #[must_use]
+    pub fn with_exposure(mut self, settings: Option<ExposureSettings>) -> Self {
+        let format = AudioFormat::new(self.sample_rate, self.channels, BitDepth::F32);
+        self.exposure = settings.map(|settings| ExposureMeter::new(settings, format));
+        self
+    }
+
+    /// Replaces the input ring, after the input stream was rebuilt. The
+    /// new input starts up again before short reads count as underruns.
+    pub(crate) fn set_input(&mut self, input: Option<RingBufferReader<Sample>>) {
+        self.input = input;
+        self.primed = false;
+    }
+
+    /// Accepts scene recalls from [`Engine::recall`](super::Engine::recall).
+    pub(crate) fn with_scenes(mut self, scenes: SceneReceiver) -> Self {
+        self.scenes = Some(scenes);
+        self
+    }
+
+    /// What the processor counts for the engine's health checks
+    pub(crate) fn health(&self) -> Arc<HealthCounters> {
+        Arc::clone(&self.health)
+    }
+
+    /// Longest tail of any effect in the chain
+    #[must_use]
+    pub fn tail_samples(&self) -> u32 {
+        self.chain.tail_samples()
+    }
+
+    /// Memory the audio thread holds: the chain, block buffers, the rings
+    /// from the input and crossfade source and to the tap, and the message
+    /// queues.
+    #[must_use]
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport::new();
+        report.add("block", MemoryKind::Buffer, heap_bytes(&self.block));
+        if let Some(input) = &self.input {
+            report.add("input", MemoryKind::Ring, ring_bytes(input));
+        }
+        if let Some(crossfade) = &self.crossfade {
+            let bytes = heap_bytes(&crossfade.block) + heap_bytes(&crossfade.mixed);
+            report.add("crossfade/block", MemoryKind::Buffer, bytes);
+            report.add(
+                "crossfade/source",
+                MemoryKind::Ring,
+                ring_bytes(&crossfade.source),
+            );
+        }
+        if let Some(tap) = &self.tap {
+            report.add(
+                "tap",
+                MemoryKind::Ring,
+                tap.capacity() * size_of::<Sample>(),
+            );
+        }
+        if let Some(timestamps) = &self.timestamps {
+            report.add(
+                "timestamps",
+                MemoryKind::Ring,
+                timestamps.capacity() * size_of::<OutputTimestamp>(),
+            );
+        }
+        let commands = self.commands.capacity().unwrap_or(0) * size_of::<EngineCommand>();
+        report.add("commands", MemoryKind::Ring, commands);
+        let feedback = self.feedback.capacity().unwrap_or(0) * size_of::<EngineFeedback>();
+        report.add("feedback", MemoryKind::Ring, feedback);
+        report.append("chain", self.chain.memory_report());
+        report
+    }
+
+    /// Fills one device buffer of interleaved samples, which the host
+    /// plays `latency` from now.
+    ///
+    /// Once the engine is prepared this runs inside a [`RealtimeScope`],
+    /// and allocations recorded meanwhile are reported back.
+    pub fn process(&mut self, output: &mut [f32], latency: Duration) {
+        let started = Instant::now();
+        if self.guarded {
+            let scope = RealtimeScope::enter();
+            self.run(output, started, latency);
+            drop(scope);
+            let violations = guard::violations();
+            if violations > self.violations {
+                let _ = self.feedback.try_send(EngineFeedback::AllocationViolation {
+                    count: violations - self.violations,
+                });
+                self.violations = violations;
+            }
+        } else {
+            self.run(output, started, latency);
+        }
+        // The callback has the buffer's duration to fill it
+        #[allow(clippy::cast_precision_loss)]
+        let duration =
+            output.len() as f32 / (self.channels.count() * self.sample_rate.as_hz()) as f32;
+        let load = started.elapsed().as_secs_f32() / duration.max(f32::EPSILON);
+        self.health.callback(load);
+    }
+
+    /// Fills the first `len` samples of the block from the input ring, or
+    /// the test signal if there is no ring.
+    fn read_input(&mut self, len: usize) {
+        let channel_count = self.channels.count_usize();
+        let block = &mut self.block[..len];
+        match &mut self.input {
+            Some(input) => {
+                // Only read whole frames so channels stay aligned
+                let available = input.slots() / channel_count * channel_count;
+                self.health.set_input_fill(input.slots(), input.capacity());
+                let read = input.pop_slice(&mut block[..available.min(len)]);
+                block[read..].fill(Sample::SILENCE);
+                if read == block.len() {
+                    self.primed = true;
+                } else if self.primed {
+                    underrun(&self.health, &self.feedback);
+                }
+            }
+            None => match &mut self.signal {
+                Some(signal) => signal.render(block, self.channels),
+                None => block.fill(Sample::SILENCE),
+            },
+        }
+    }
+
+    fn run(&mut self, output: &mut [f32], started: Instant, latency: Duration) {
+        self.begin(output.len(), started + latency, latency);
+        let feedback = &self.feedback;
+        self.chain.bypass_failed(|id| {
+            let _ = feedback.try_send(EngineFeedback::EffectFailed {
+                effect_id: id.value(),
+            });
+        });
+        let channel_count = self.channels.count_usize();
+        let step = self.block.len();
+
+        for out in output.chunks_mut(step) {
+            if self.state != EngineState::Running {
+                out.fill(0.0);
+                // Don't let stale input pile up while nothing consumes it
+                if let Some(input) = &mut self.input {
+                    let stale = input.slots();
+                    input.discard(stale);
+                }
+                if let Some(crossfade) = &mut self.crossfade {
+                    let stale = crossfade.source.slots();
+                    crossfade.source.discard(stale);
+                }
+                continue;
+            }
+
+            self.read_input(out.len());
+            let block = &mut self.block[..out.len()];
+            if let Some(crossfade) = &mut self.crossfade {
+                let len = block.len();
+                let available = crossfade.source.slots() / channel_count * channel_count;
+                let other = &mut crossfade.block[..len];
+                let read = crossfade.source.pop_slice(&mut other[..available.min(len)]);
+                other[read..].fill(Sample::SILENCE);
+                let mixed = &mut crossfade.mixed[..len];
+                crossfade.crossfader.process(block, other, mixed);
+                block.copy_from_slice(mixed);
+            }
+            if self.denormals == DenormalPolicy::Flush {
+                flush_denormals_slice(block);
+            }
+            self.input_peak = peak(block).max(self.input_peak);
+
+            let context =
+                ProcessContext::new(self.sample_rate, self.channels, out.len() / channel_count)
+                    .with_timeline(
+                        &self.tempo_map,
+                        self.transport.position(),
+                        self.transport.is_playing(),
+                    );
+            self.chain.set_context(&context);
+            if let Some(recall) = &mut self.recall
+                && recall.advance(&mut self.chain, out.len() / channel_count)
+                && let (Some(recall), Some(scenes)) = (self.recall.take(), &self.scenes)
+            {
+                scenes.give_back(recall);
+            }
+            self.chain.process(block, self.channels);
+            let frames = out.len() / channel_count;
+            let mut done = 0;
+            while done < frames {
+                let span = self.transport.advance(frames - done);
+                let samples =
+                    &mut block[done * channel_count..(done + span.frames) * channel_count];
+                self.metronome
+                    .process(&span, &self.tempo_map, samples, self.channels);
+                done += span.frames;
+            }
+            if let Some(tap) = &mut self.tap
+                && tap.slots() >= block.len()
+            {
+                tap.push_slice(block);
+            }
+
+            let stereo = self.channels == ChannelCount::Stereo;
+            for (out_frame, frame) in out
+                .chunks_exact_mut(channel_count)
+                .zip(block.chunks_exact(channel_count))
+            {
+                let gain = self.gain.next() * self.mute.next();
+                let (left, right) = (self.left.next(), self.right.next());
+                for (i, (out, sample)) in out_frame.iter_mut().zip(frame).enumerate() {
+                    let pan = match (stereo, i) {
+                        (true, 0) => left,
+                        (true, _) => right,
+                        (false, _) => 1.0,
+                    };
+                    *out = sample.value() * gain * pan;
+                }
+            }
+            self.protect(out);
+
+            self.meter(frames);
+        }
+    }
+
+    /// Runs the safety stage over a finished output block and takes its
+    /// peak, so the meters show what reaches the device.
+    fn protect(&mut self, out: &mut [f32]) {
+        for frame in out.chunks_exact_mut(self.channels.count_usize()) {
+            if let Some(safety) = &mut self.safety {
+                safety.process_frame(frame);
+            }
+            if let Some(warning) = self.exposure.as_mut().and_then(|m| m.process_frame(frame)) {
+                let _ = self
+                    .feedback
+                    .try_send(EngineFeedback::ExposureWarning(warning));
+            }
+            self.output_peak = frame.iter().fold(self.output_peak, |p, s| p.max(s.abs()));
+        }
+    }
+
+    /// Applies pending commands, then reports when the device buffer of
+    /// `samples` about to be filled will be heard, and what the transport
+    /// plays at its start.
+    fn begin(&mut self, samples: usize, presentation: Instant, latency: Duration) {
+        self.receive_commands();
+        let frames = samples / self.channels.count_usize();
+        let frame = self.output_frames;
+        self.output_frames += frames as u64;
+        let Some(timestamps) = &mut self.timestamps else {
+            return;
+        };
+        let position = self.transport.is_playing().then(|| {
+            self.transport
+                .position()
+                .sub_samples(u64::from(self.chain.latency_samples()))
+        });
+        let _ = timestamps.push(OutputTimestamp {
+            frame,
+            frames: u32::try_from(frames).unwrap_or(u32::MAX),
+            presentation,
+            latency,
+            position,
+        });
+    }
+
+    /// Counts `frames` towards the next meter report, sending the levels
+    /// and position when it is due.
+    fn meter(&mut self, frames: usize) {
+        self.meter_frames += frames;
+        if self.meter_frames >= self.meter_interval {
+            let _ = self.feedback.try_send(EngineFeedback::Levels {
+                input_db: Decibels::from_linear(self.input_peak),
+                output_db: Decibels::from_linear(self.output_peak),
+            });
+            if self.transport.is_playing() {
+                let _ = self
+                    .feedback
+                    .try_send(EngineFeedback::Position(self.transport.time()));
+            }
+            self.meter_frames = 0;
+            self.input_peak = 0.0;
+            self.output_peak = 0.0;
+        }
+    }
+
+    fn receive_commands(&mut self) {
+        while let Some(command) = self.commands.try_recv() {
+            self.apply(&command);
+        }
+        let Some(scenes) = self.scenes.take() else {
+            return;
+        };
+        while let Some(mut recall) = scenes.try_recv() {
+            // A newer scene takes over from wherever the last one got to
+            self.begin_recall(&mut recall);
+            if let Some(previous) = self.recall.replace(recall) {
+                scenes.give_back(previous);
+            }
+        }
+        self.scenes = Some(scenes);
+    }
+
+    fn begin_recall(&mut self, recall: &mut SceneRecall) {
+        let ramp = recall.frames();
+        let scene = recall.scene();
+        self.gain.set_target(scene.master_gain.as_linear(), ramp);
+        let (left, right) = pan_gains(scene.master_pan);
+        self.left.set_target(left, ramp);
+        self.right.set_target(right, ramp);
+        self.transport.set_loop(scene.transport.loop_range);
+        self.transport.set_punch(scene.transport.punch_range);
+        recall.begin(&mut self.chain);
+    }
+
+    fn apply(&mut self, command: &EngineCommand) {
+        let ramp = self.sample_rate.samples_for_milliseconds(SMOOTHING_MS);
+        match *command {
+            EngineCommand::Start | EngineCommand::Resume => {
+                self.transport.play();
+                self.set_state(EngineState::Running);
+            }
+            EngineCommand::Pause => {
+                self.transport.pause();
+                self.set_state(EngineState::Paused);
+            }
+            EngineCommand::Stop | EngineCommand::Shutdown => {
+                self.transport.stop();
+                self.metronome.reset();
+                self.chain.reset();
+                if let Some(safety) = &mut self.safety {
+                    safety.reset();
+                }
+                if let Some(seed) = self.seed {
+                    self.chain.reseed(seed);
+                }
+                if let Some(signal) = &mut self.signal {
+                    signal.reset();
+                }
+                self.primed = false;
+                self.set_state(EngineState::Stopped);
+            }
+            EngineCommand::Seek(position) => self.transport.seek(position),
+            EngineCommand::Locate(position) => self.transport.locate(position),
+            EngineCommand::SetLoop(range) => self.transport.set_loop(range),
+            EngineCommand::SetPunch(range) => self.transport.set_punch(range),
+            EngineCommand::SetFrameRate(rate) => self.transport.set_frame_rate(rate),
+            EngineCommand::SetTempo(tempo) => {
+                // Neither step allocates: the first segment stays
+                self.tempo_map.clear_changes();
+                self.tempo_map.set_tempo(Timestamp::ZERO, tempo);
+            }
+            EngineCommand::Record(count_in) => {
+                self.transport.record(count_in, &self.tempo_map);
+                self.set_state(EngineState::Running);
+            }
+            EngineCommand::SetMetronome(settings) => self.metronome.set_settings(settings),
+            EngineCommand::SeekBeats(beats) => {
+                self.transport.seek(self.tempo_map.position_of_beats(beats));
+            }
+            EngineCommand::SetCrossfade(position) => {
+                if let Some(crossfade) = &mut self.crossfade {
+                    crossfade.crossfader.set_position(position);
+                }
+            }
+            EngineCommand::Prepare => {
+                self.guarded = true;
+                self.violations = guard::violations();
+            }
+            EngineCommand::SetGain(gain) => self.gain.set_target(gain.as_linear(), ramp),
+            EngineCommand::SetPan(pan) => {
+                let (left, right) = pan_gains(pan);
+                self.left.set_target(left, ramp);
+                self.right.set_target(right, ramp);
+            }
+            EngineCommand::SetEffectParam {
+                effect_id,
+                param_id,
+                value,
+            } => {
+                if let Some(effect) = self.chain.get_mut(EffectId::new(effect_id)) {
+                    effect.set_parameter(ParamId::new(param_id), ParamValue::Float(value));
+                    self.record(effect_id, param_id, value);
+                }
+            }
+            EngineCommand::SetEffectEnabled { effect_id, enabled } => {
+                if self.chain.set_enabled(EffectId::new(effect_id), enabled) {
+                    self.record(effect_id, ENABLED.value(), f32::from(u8::from(enabled)));
+                }
+            }
+            EngineCommand::RecordAutomation(recording) => self.recording_automation = recording,
+            EngineCommand::Mute(muted) => {
+                let frames = self.sample_rate.samples_for_milliseconds(MUTE_MS);
+                self.mute.set_target(if muted { 0.0 } else { 1.0 }, frames);
+            }
+            EngineCommand::SetEffectQuality(quality) => self.chain.set_quality(quality),
+            EngineCommand::ResetExposure => {
+                if let Some(exposure) = &mut self.exposure {
+                    exposure.reset();
+                }
+            }
+        }
+    }
+
+    /// Reports a parameter change if automation is being recorded.
+    fn record(&self, effect_id: u32, param_id: u32, value: f32) {
+        if self.recording_automation && self.transport.is_playing() {
+            let _ = self.feedback.try_send(EngineFeedback::ParamRecorded {
+                position: self.transport.position(),
+                effect_id,
+                param_id,
+                value,
+            });
+        }
+    }
+
+    fn set_state(&mut self, state: EngineState) {
+        if self.state != state {
+            self.state = state;
+            let _ = self.feedback.try_send(EngineFeedback::StateChanged(state));
+        }
+    }
+}
+
+/// Constant power pan gains, scaled so the centre position is unity
+fn pan_gains(pan: Pan) -> (f32, f32) {
+    let (left, right) = pan.gains();
+    (
+        left.as_linear() * std::f32::consts::SQRT_2,
+        right.as_linear() * std::f32::consts::SQRT_2,
+    )
+}
+
+fn peak(samples: &[Sample]) -> f32 {
+    samples
+        .iter()
+        .fold(0.0f32, |peak, sample| peak.max(sample.value().abs()))
+}
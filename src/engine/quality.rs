@@ -0,0 +1,199 @@
+//! Effect quality under CPU pressure
+//!
+//! A live set that runs out of CPU drops out. A [`QualityGovernor`] watches
+//! the engine's callback load ([`Engine::cpu_load`]) and overruns, and
+//! before it comes to that steps the chain's effects down an
+//! [`EffectQuality`] tier with [`Engine::set_effect_quality`]. Once the
+//! load has stayed low for a while it steps them back up, one tier at a
+//! time. Each step is reported as [`EngineFeedback::QualityChanged`].
+//!
+//! The load is sampled on every poll, and the peak of each window decides,
+//! so poll a few times a window.
+//!
+//! ```no_run
+//! use audio_engine::engine::Engine;
+//! use audio_engine::engine::quality::{QualityGovernor, QualitySettings};
+//!
+//! let mut engine = Engine::builder().build()?;
+//! let mut governor = QualityGovernor::new(QualitySettings::default());
+//! engine.start()?;
+//! loop {
+//!     if let Some(change) = governor.poll(&mut engine) {
+//!         println!("effect quality now {}", change.quality);
+//!     }
+//!     std::thread::sleep(std::time::Duration::from_millis(100));
+//! }
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! [`EngineFeedback::QualityChanged`]: crate::channel::EngineFeedback::QualityChanged
+
+use std::time::{Duration, Instant};
+
+use crate::channel::{EngineFeedback, EngineState};
+use crate::dsp::quality::EffectQuality;
+use crate::engine::Engine;
+
+/// When a [`QualityGovernor`] steps the effect quality down and up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualitySettings {
+    /// Peak load within a window at which the quality steps down
+    pub lower_above: f32,
+    /// Peak load every window must stay under for
+    /// [`raise_after`](Self::raise_after) before the quality steps up
+    pub raise_below: f32,
+    pub window: Duration,
+    pub raise_after: Duration,
+    /// Lowest tier to step down to
+    pub floor: EffectQuality,
+}
+
+impl Default for QualitySettings {
+    fn default() -> Self {
+        Self {
+            lower_above: 0.8,
+            raise_below: 0.5,
+            window: Duration::from_secs(1),
+            raise_after: Duration::from_secs(10),
+            floor: EffectQuality::Low,
+        }
+    }
+}
+
+impl QualitySettings {
+    /// Sets the loads the quality steps down at and up below.
+    #[must_use]
+    pub const fn with_thresholds(mut self, lower_above: f32, raise_below: f32) -> Self {
+        self.lower_above = lower_above;
+        self.raise_below = raise_below;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_raise_after(mut self, duration: Duration) -> Self {
+        self.raise_after = duration;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_floor(mut self, floor: EffectQuality) -> Self {
+        self.floor = floor;
+        self
+    }
+}
+
+/// A quality change made by [`QualityGovernor::poll`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityChange {
+    pub previous: EffectQuality,
+    pub quality: EffectQuality,
+    /// Peak load of the window that set it off
+    pub load: f32,
+}
+
+/// Steps an engine's effect quality down under CPU pressure and back up
+/// once it eases.
+#[derive(Debug)]
+pub struct QualityGovernor {
+    settings: QualitySettings,
+    window_start: Instant,
+    /// Highest load sampled this window
+    peak: f32,
+    /// Engine overruns as of the window start
+    overruns: u64,
+    /// Start of the run of calm windows
+    calm_since: Option<Instant>,
+    changes: u32,
+}
+
+impl QualityGovernor {
+    #[must_use]
+    pub fn new(settings: QualitySettings) -> Self {
+        Self {
+            settings,
+            window_start: Instant::now(),
+            peak: 0.0,
+            overruns: 0,
+            calm_since: None,
+            changes: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &QualitySettings {
+        &self.settings
+    }
+
+    /// Quality changes made so far
+    #[must_use]
+    pub const fn changes(&self) -> u32 {
+        self.changes
+    }
+
+    /// Samples the load and, at the end of each window, steps the quality
+    /// down if it peaked too high or the callbacks overran, or up after
+    /// enough calm windows, reporting the change on the engine's feedback
+    /// channel. Call it regularly from the control thread.
+    pub fn poll(&mut self, engine: &mut Engine) -> Option<QualityChange> {
+        let now = Instant::now();
+        if engine.state() != EngineState::Running {
+            self.restart_window(engine, now);
+            self.calm_since = None;
+            return None;
+        }
+        self.peak = self.peak.max(engine.cpu_load());
+        if now.duration_since(self.window_start) < self.settings.window {
+            return None;
+        }
+
+        let (load, started) = (self.peak, self.window_start);
+        let overran = engine.overruns() > self.overruns;
+        self.restart_window(engine, now);
+        let current = engine.effect_quality();
+        let quality = if load >= self.settings.lower_above || overran {
+            self.calm_since = None;
+            current
+                .lower()
+                .filter(|lower| *lower >= self.settings.floor)
+        } else if load < self.settings.raise_below {
+            let calm_since = *self.calm_since.get_or_insert(started);
+            if now.duration_since(calm_since) < self.settings.raise_after {
+                return None;
+            }
+            self.calm_since = None;
+            current.higher()
+        } else {
+            self.calm_since = None;
+            None
+        }?;
+
+        if let Err(e) = engine.set_effect_quality(quality) {
+            log::error!("Quality governor: moving the effects to {quality} quality failed: {e}");
+            return None;
+        }
+        self.changes += 1;
+        log::info!("Quality governor: effect quality {current} -> {quality} at {load:.2} load");
+        let _ = engine.report(EngineFeedback::QualityChanged {
+            previous: current,
+            quality,
+            load,
+        });
+        Some(QualityChange {
+            previous: current,
+            quality,
+            load,
+        })
+    }
+
+    fn restart_window(&mut self, engine: &Engine, now: Instant) {
+        self.window_start = now;
+        self.peak = 0.0;
+        self.overruns = engine.overruns();
+    }
+}
@@ -0,0 +1,180 @@
+//! Output safety stage
+//!
+//! A chain that blows up, a gain typed in as 40 instead of 0.4 or a
+//! feedback loop in development can send full-scale noise to the speakers
+//! and whoever is wearing headphones. The engine therefore runs every
+//! output frame through a [`SafetySettings`] stage after everything else,
+//! the master fader, pan and mute included: a DC blocker, then a peak
+//! limiter with an instant attack that holds the output under a ceiling,
+//! then a hard clip at the ceiling. Samples that aren't finite are
+//! silenced.
+//!
+//! The stage can't be bypassed by command while the engine runs; it is
+//! configured once with [`EngineBuilder::with_safety`], and only turned off
+//! there. Offline renders leave it out, since they don't play to a
+//! device. A mixer's monitor output, which can feed a second device as a
+//! cue, runs its own stage, set with [`Mixer::set_cue_safety`].
+//!
+//! ```no_run
+//! use audio_engine::engine::{Engine, SafetySettings};
+//! use audio_engine::types::Decibels;
+//!
+//! let engine = Engine::builder()
+//!     .with_safety(SafetySettings::default().with_ceiling(Decibels::new(-6.0)))
+//!     .build()?;
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! [`EngineBuilder::with_safety`]: super::EngineBuilder::with_safety
+//! [`Mixer::set_cue_safety`]: crate::mixer::Mixer::set_cue_safety
+
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+use crate::dsp::denormal::flush_denormals;
+use crate::types::{ChannelCount, Decibels, SampleRate};
+
+/// Cutoff of the DC blocker, in Hz, low enough to leave the audible band
+/// alone
+const DC_CUTOFF_HZ: f32 = 5.0;
+
+/// How the engine protects its output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SafetySettings {
+    /// Whether the stage runs at all; on by default
+    pub enabled: bool,
+    /// Highest level that reaches the device, -1 dBFS by default
+    pub ceiling: Decibels,
+    /// How fast the limiter lets go once the level drops, 50 ms by default
+    pub release: Duration,
+    /// Whether DC is removed before limiting; on by default
+    pub dc_block: bool,
+}
+
+impl Default for SafetySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ceiling: Decibels::new(-1.0),
+            release: Duration::from_millis(50),
+            dc_block: true,
+        }
+    }
+}
+
+impl SafetySettings {
+    /// Leaves the output unprotected, e.g. for measurements that need the
+    /// signal untouched.
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the highest output level, clamped to 0 dBFS.
+    #[must_use]
+    pub fn with_ceiling(mut self, ceiling: Decibels) -> Self {
+        self.ceiling = Decibels::new(ceiling.value().min(0.0));
+        self
+    }
+
+    #[must_use]
+    pub const fn with_release(mut self, release: Duration) -> Self {
+        self.release = release;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_dc_block(mut self, dc_block: bool) -> Self {
+        self.dc_block = dc_block;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct DcState {
+    x1: f32,
+    y1: f32,
+}
+
+/// The safety stage on the audio thread, one state per output channel.
+#[derive(Debug)]
+pub(crate) struct SafetyLimiter {
+    /// Linear ceiling
+    ceiling: f32,
+    /// Per-frame factor the gain reduction decays by
+    release: f32,
+    /// DC blocker coefficient, `None` without DC blocking
+    dc: Option<f32>,
+    states: Vec<DcState>,
+    /// Gain applied to the last frame
+    gain: f32,
+}
+
+impl SafetyLimiter {
+    /// The stage for `settings`, or `None` if it is turned off.
+    pub(crate) fn new(
+        settings: SafetySettings,
+        sample_rate: SampleRate,
+        channels: ChannelCount,
+    ) -> Option<Self> {
+        if !settings.enabled {
+            return None;
+        }
+        let rate = sample_rate.as_f32();
+        let release_frames = settings.release.as_secs_f32() * rate;
+        let release = if release_frames > 1.0 {
+            (-release_frames.recip()).exp()
+        } else {
+            0.0
+        };
+        Some(Self {
+            ceiling: settings.ceiling.to_linear().min(1.0),
+            release,
+            dc: settings.dc_block.then(|| 1.0 - TAU * DC_CUTOFF_HZ / rate),
+            states: vec![DcState::default(); channels.count_usize()],
+            gain: 1.0,
+        })
+    }
+
+    /// Forgets the signal so far.
+    pub(crate) fn reset(&mut self) {
+        self.states.fill(DcState::default());
+        self.gain = 1.0;
+    }
+
+    /// Makes one interleaved output frame safe to play.
+    pub(crate) fn process_frame(&mut self, frame: &mut [f32]) {
+        let mut peak = 0.0_f32;
+        for (sample, state) in frame.iter_mut().zip(&mut self.states) {
+            if !sample.is_finite() {
+                // Whatever produced it, the filter mustn't keep it
+                *sample = 0.0;
+                *state = DcState::default();
+            }
+            if let Some(r) = self.dc {
+                let x = *sample;
+                let y = flush_denormals(r.mul_add(state.y1, x - state.x1));
+                state.x1 = x;
+                state.y1 = y;
+                *sample = y;
+            }
+            peak = peak.max(sample.abs());
+        }
+
+        // Instant attack, exponential release, linked across channels so
+        // the image doesn't shift
+        let released = self.release.mul_add(self.gain - 1.0, 1.0);
+        self.gain = if peak * released > self.ceiling {
+            self.ceiling / peak
+        } else {
+            released
+        };
+        for sample in frame {
+            *sample = (*sample * self.gain).clamp(-self.ceiling, self.ceiling);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Scenes: snapshots of the whole engine
+//!
+//! A [`Scene`] holds the engine's master gain and pan, the settings of
+//! every effect in its chain and the transport's loop and punch ranges,
+//! and optionally a [`MixerScene`] and a [`RoutingScene`] captured from a
+//! mixer and routing matrix kept alongside the engine. With the `serde`
+//! feature, scenes convert to and from JSON and TOML.
+//!
+//! [`Engine::recall`](super::Engine::recall) hands a scene to the audio
+//! thread, which glides to it over the transition time: master gain and
+//! pan ramp, and continuous effect parameters are interpolated block by
+//! block. Switches and stepped parameters change at the start of the
+//! transition.
+
+use crate::channel::{
+    ControlReceiver, ControlSender, EngineCommand, RealtimeReceiver, RealtimeSender,
+    control_channel, feedback_channel, reserve_feedback,
+};
+use crate::dsp::chain::EffectChain;
+use crate::dsp::params::{ParamId, ParamValue};
+use crate::dsp::preset::ChainPreset;
+use crate::dsp::traits::EffectId;
+use crate::error::Result;
+use crate::mixer::{MixerScene, RoutingScene};
+use crate::types::{Gain, Pan, TimeRange};
+
+/// Loop and punch ranges of the transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransportScene {
+    pub loop_range: Option<TimeRange>,
+    pub punch_range: Option<TimeRange>,
+}
+
+/// A snapshot of the engine's settings.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Scene {
+    pub name: String,
+    pub master_gain: Gain,
+    pub master_pan: Pan,
+    /// Settings of the engine's effect chain
+    pub effects: ChainPreset,
+    pub transport: TransportScene,
+    pub mixer: Option<MixerScene>,
+    pub routing: Option<RoutingScene>,
+}
+
+impl Scene {
+    /// An empty scene at unity gain, centred, with no effects.
+    #[must_use]
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            master_gain: Gain::UNITY,
+            master_pan: Pan::CENTER,
+            effects: ChainPreset::default(),
+            transport: TransportScene::default(),
+            mixer: None,
+            routing: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    #[must_use]
+    pub fn with_effects(mut self, effects: ChainPreset) -> Self {
+        self.effects = effects;
+        self
+    }
+
+    #[must_use]
+    pub fn with_mixer(mut self, mixer: MixerScene) -> Self {
+        self.mixer = Some(mixer);
+        self
+    }
+
+    #[must_use]
+    pub fn with_routing(mut self, routing: RoutingScene) -> Self {
+        self.routing = Some(routing);
+        self
+    }
+
+    /// Updates the scene with the effect of a command sent to the engine.
+    pub(crate) fn track(&mut self, command: &EngineCommand) {
+        match *command {
+            EngineCommand::SetGain(gain) => self.master_gain = gain,
+            EngineCommand::SetPan(pan) => self.master_pan = pan,
+            EngineCommand::SetLoop(range) => self.transport.loop_range = range,
+            EngineCommand::SetPunch(range) => self.transport.punch_range = range,
+            EngineCommand::SetEffectParam {
+                effect_id,
+                param_id,
+                value,
+            } => {
+                let param_id = ParamId::new(param_id);
+                if let Some(preset) = self.effect_mut(effect_id) {
+                    match preset.parameters.iter_mut().find(|(id, _)| *id == param_id) {
+                        Some((_, stored)) => *stored = ParamValue::Float(value),
+                        None => preset.parameters.push((param_id, ParamValue::Float(value))),
+                    }
+                }
+            }
+            EngineCommand::SetEffectEnabled { effect_id, enabled } => {
+                if let Some(preset) = self.effect_mut(effect_id) {
+                    preset.enabled = enabled;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Takes over the engine's part of `scene`: everything but the mixer
+    /// and routing. Effects the scene doesn't mention keep their settings.
+    pub(crate) fn merge_engine(&mut self, scene: &Self) {
+        self.master_gain = scene.master_gain;
+        self.master_pan = scene.master_pan;
+        self.transport = scene.transport;
+        for preset in &scene.effects.effects {
+            if let Some(existing) = self.effect_mut(preset.id.value()) {
+                existing.clone_from(preset);
+            }
+        }
+    }
+
+    fn effect_mut(&mut self, id: u32) -> Option<&mut crate::dsp::preset::EffectPreset> {
+        self.effects
+            .effects
+            .iter_mut()
+            .find(|preset| preset.id == EffectId::new(id))
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new("Scene")
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::dsp::preset::serialization::text_formats!(Scene, "scene");
+
+/// A scene on its way to the audio thread, and its transition once there.
+#[derive(Debug)]
+pub(crate) struct SceneRecall {
+    scene: Scene,
+    frames: u32,
+    elapsed: u32,
+    /// Value of each interpolated parameter when the transition began, in
+    /// the order of the scene's effect settings; `None` for parameters that
+    /// switch at once
+    starts: Vec<Option<f32>>,
+}
+
+impl SceneRecall {
+    /// Room for every parameter is reserved here, so beginning the
+    /// transition on the audio thread doesn't allocate.
+    pub(crate) fn new(scene: Scene, frames: u32) -> Self {
+        let count = scene
+            .effects
+            .effects
+            .iter()
+            .map(|preset| preset.parameters.len())
+            .sum();
+        Self {
+            scene,
+            frames,
+            elapsed: 0,
+            starts: Vec::with_capacity(count),
+        }
+    }
+
+    pub(crate) const fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub(crate) const fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Applies the switches and stepped parameters, and notes where the
+    /// continuous ones start from.
+    pub(crate) fn begin(&mut self, chain: &mut EffectChain) {
+        self.starts.clear();
+        for preset in &self.scene.effects.effects {
+            chain.set_enabled(preset.id, preset.enabled);
+            let mut effect = chain.get_mut(preset.id);
+            for &(id, value) in &preset.parameters {
+                let start = effect.as_deref_mut().and_then(|effect| match value {
+                    ParamValue::Float(_) | ParamValue::Decibels(_) | ParamValue::Gain(_) => {
+                        effect.get_parameter(id).map(|current| current.as_float())
+                    }
+                    ParamValue::Int(_) | ParamValue::Bool(_) => {
+                        effect.set_parameter(id, value);
+                        None
+                    }
+                });
+                self.starts.push(start);
+            }
+        }
+    }
+
+    /// Moves the transition on by `frames`. Returns true once it is done.
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn advance(&mut self, chain: &mut EffectChain, frames: usize) -> bool {
+        let frames = u32::try_from(frames).unwrap_or(u32::MAX);
+        self.elapsed = self.elapsed.saturating_add(frames).min(self.frames);
+        let t = if self.frames == 0 {
+            1.0
+        } else {
+            self.elapsed as f32 / self.frames as f32
+        };
+        let mut starts = self.starts.iter();
+        for preset in &self.scene.effects.effects {
+            let mut effect = chain.get_mut(preset.id);
+            for &(id, value) in &preset.parameters {
+                let (Some(Some(start)), Some(effect)) = (starts.next(), effect.as_deref_mut())
+                else {
+                    continue;
+                };
+                let level = (value.as_float() - start).mul_add(t, *start);
+                effect.set_parameter(id, ParamValue::Float(level));
+            }
+        }
+        self.elapsed == self.frames
+    }
+}
+
+/// Control side of the engine's scene channel.
+#[derive(Debug)]
+pub(crate) struct SceneSender {
+    recalls: ControlSender<Box<SceneRecall>>,
+    returned: ControlReceiver<Box<SceneRecall>>,
+    /// The audio thread's end of the return queue, for reserving it
+    give_back: RealtimeSender<Box<SceneRecall>>,
+}
+
+impl SceneSender {
+    /// Queues a recall, freeing the ones the audio thread has finished
+    /// with.
+    pub(crate) fn send(&self, recall: SceneRecall) -> Result<()> {
+        while self.returned.try_recv().is_some() {}
+        self.recalls.try_send(Box::new(recall))
+    }
+
+    /// Grows the return queue, so handing finished recalls back doesn't
+    /// allocate on the audio thread.
+    pub(crate) fn reserve(&self) {
+        reserve_feedback(&self.give_back, &self.returned, || {
+            Box::new(SceneRecall::new(Scene::default(), 0))
+        });
+    }
+}
+
+/// Audio thread side of the engine's scene channel.
+#[derive(Debug)]
+pub(crate) struct SceneReceiver {
+    recalls: RealtimeReceiver<Box<SceneRecall>>,
+    returned: RealtimeSender<Box<SceneRecall>>,
+}
+
+impl SceneReceiver {
+    pub(crate) fn try_recv(&self) -> Option<Box<SceneRecall>> {
+        self.recalls.try_recv()
+    }
+
+    /// Hands a finished recall back to be freed on the control thread.
+    pub(crate) fn give_back(&self, recall: Box<SceneRecall>) {
+        let _ = self.returned.try_send(recall);
+    }
+}
+
+/// Creates a connected scene channel.
+pub(crate) fn scene_channel(capacity: usize) -> (SceneSender, SceneReceiver) {
+    let (recalls, receiver) = control_channel(capacity);
+    let (returned, returned_receiver) = feedback_channel(capacity);
+    (
+        SceneSender {
+            recalls,
+            returned: returned_receiver,
+            give_back: returned.clone(),
+        },
+        SceneReceiver {
+            recalls: receiver,
+            returned,
+        },
+    )
+}
@@ -0,0 +1,498 @@
+//! Blocking playback and recording
+//!
+//! For scripts and examples that just want to play a file or record a few
+//! seconds, [`play_file`] and [`record_to`] set up a whole [`Engine`] on
+//! the default devices and hide the streams, rings and threads behind it.
+//! Nothing here is real-time safe; call it from an ordinary thread.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use audio_engine::engine::simple::{play_file, record_to};
+//!
+//! record_to("take.wav", Duration::from_secs(5))?;
+//! play_file("take.wav")?.wait_with(|progress| {
+//!     println!("{:.1} s", progress.seconds);
+//! })?;
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! Cue markers in a WAV file being played are reported as
+//! [`EngineFeedback::MarkerReached`] as playback crosses them; see
+//! [`PlaybackHandle::wait_with_markers`].
+//!
+//! [`EngineFeedback::MarkerReached`]: crate::channel::EngineFeedback::MarkerReached
+//!
+//! [`EngineBuilder::play_file`] and [`EngineBuilder::record_to`] do the
+//! same through a configured builder, e.g. with a chain or on other
+//! devices.
+//!
+//! Files are played through the engine's crossfade source, which is
+//! turned all the way over to them, so the chain, master gain and meters
+//! apply as usual. A recording is what the chain makes of the input, taken
+//! before the master gain, which is turned down so the input isn't
+//! monitored.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::buffer::{RingBuffer, RingBufferWriter};
+use crate::channel::{EngineCommand, EngineFeedback};
+use crate::dsp::crossfade::CrossfadeCurve;
+use crate::engine::{Engine, EngineBuilder};
+use crate::error::{AudioEngineError, Result};
+use crate::io::PcmReader;
+use crate::io::cue::{CueMarker, MarkerCursor, MarkerList};
+use crate::io::input::AudioFileFormat;
+use crate::io::streamer::StreamSource;
+use crate::io::wav::WavWriter;
+use crate::types::{ChannelCount, Gain, Sample, SampleRate, Timestamp};
+
+/// Seconds of audio buffered between the file and the engine, and between
+/// the engine and a recording
+const BUFFER_SECONDS: u32 = 1;
+
+/// Frames read from a file at a time
+const CHUNK_FRAMES: usize = 4096;
+
+/// How often progress is reported
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the threads wait for room or for audio
+const IDLE_WAIT: Duration = Duration::from_millis(10);
+
+/// How far playback or recording has got.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Seconds played or recorded
+    pub seconds: f64,
+    /// Seconds in all, if known
+    pub total_seconds: Option<f64>,
+}
+
+impl Progress {
+    /// Share done, from 0 to 1, if the total is known
+    #[must_use]
+    pub fn fraction(&self) -> Option<f64> {
+        self.total_seconds.map(|total| {
+            if total > 0.0 {
+                (self.seconds / total).min(1.0)
+            } else {
+                1.0
+            }
+        })
+    }
+}
+
+/// Plays the file at `path` on the default output device. Playback starts
+/// right away; the handle waits for it to end or stops it.
+///
+/// # Errors
+/// Returns an error if the file can't be opened or played at its sample
+/// rate, or the engine can't be built or started.
+pub fn play_file(path: impl AsRef<Path>) -> Result<PlaybackHandle> {
+    Engine::builder().play_file(path)
+}
+
+/// Records `duration` from the default input device into a WAV file at
+/// `path`, blocking until it is done. Returns the frames recorded.
+///
+/// # Errors
+/// Returns an error if there is no input device, the engine can't be built
+/// or started, or the file can't be written.
+pub fn record_to(path: impl AsRef<Path>, duration: Duration) -> Result<u64> {
+    record_to_with(path, duration, |_| {})
+}
+
+/// Records like [`record_to`], calling `on_progress` along the way.
+///
+/// # Errors
+/// Returns an error if there is no input device, the engine can't be built
+/// or started, or the file can't be written.
+pub fn record_to_with(
+    path: impl AsRef<Path>,
+    duration: Duration,
+    on_progress: impl FnMut(Progress),
+) -> Result<u64> {
+    Engine::builder().record_to(path, duration, on_progress)
+}
+
+/// State the file reader shares with the handle
+#[derive(Default)]
+struct ReaderState {
+    stop: AtomicBool,
+    /// Set once everything read has been played, or reading failed
+    done: AtomicBool,
+    /// Frames handed to the engine
+    pushed: AtomicU64,
+    /// Frames handed over but not played yet
+    buffered: AtomicU64,
+}
+
+/// A file playing through an engine.
+pub struct PlaybackHandle {
+    engine: Engine,
+    shared: Arc<ReaderState>,
+    reader: Option<JoinHandle<Result<()>>>,
+    sample_rate: SampleRate,
+    total_frames: Option<u64>,
+    markers: MarkerCursor,
+}
+
+impl PlaybackHandle {
+    /// Opens the file and starts playing it through the engine `builder`
+    /// makes, at the file's sample rate and channel count.
+    pub(super) fn start(builder: EngineBuilder, path: &Path) -> Result<Self> {
+        let (source, total_frames) = open_source(path)?;
+        let markers = match crate::io::FileInput::new(path).format() {
+            Some(AudioFileFormat::Wav) => MarkerList::read_wav(path)?,
+            _ => MarkerList::new(),
+        };
+        let sample_rate = SampleRate::try_from(source.sample_rate_hz())?;
+        let channels = source.channels();
+        let (writer, reader) = RingBuffer::new(ring_samples(sample_rate, channels, BUFFER_SECONDS));
+        let mut engine = builder
+            .with_sample_rate(sample_rate)
+            .with_channels(channels)
+            .without_input()
+            .with_crossfade_source(reader, CrossfadeCurve::Linear)
+            .build()?;
+        engine.send(EngineCommand::SetCrossfade(1.0))?;
+
+        let shared = Arc::new(ReaderState::default());
+        let file = FileReader {
+            source,
+            ring: writer,
+            channels: channels.count_usize(),
+            shared: Arc::clone(&shared),
+        };
+        let reader = thread::Builder::new()
+            .name("play-file".to_string())
+            .spawn(move || file.run())?;
+        let mut handle = Self {
+            engine,
+            shared,
+            reader: Some(reader),
+            sample_rate,
+            total_frames,
+            markers: MarkerCursor::new(markers),
+        };
+        handle.engine.start()?;
+        Ok(handle)
+    }
+
+    /// The engine playing the file, e.g. to change its gain
+    pub const fn engine(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    #[must_use]
+    pub fn progress(&self) -> Progress {
+        let rate = f64::from(self.sample_rate.as_hz());
+        #[allow(clippy::cast_precision_loss)]
+        Progress {
+            seconds: self.played_frames() as f64 / rate,
+            total_seconds: self.total_frames.map(|frames| frames as f64 / rate),
+        }
+    }
+
+    /// Markers in the file, read from its cue points
+    #[must_use]
+    pub const fn markers(&self) -> &MarkerList {
+        self.markers.markers()
+    }
+
+    /// Replaces the markers reported during playback. Markers before the
+    /// current position aren't reported.
+    pub fn set_markers(&mut self, markers: MarkerList) {
+        self.markers = MarkerCursor::new(markers);
+        self.markers
+            .seek(Timestamp::from_samples(self.played_frames()));
+    }
+
+    /// Markers playback has crossed since the last call, each also sent
+    /// as [`EngineFeedback::MarkerReached`].
+    pub fn crossed_markers(&mut self) -> Vec<CueMarker> {
+        let crossed = self
+            .markers
+            .advance(Timestamp::from_samples(self.played_frames()));
+        let mut reached = Vec::with_capacity(crossed.len());
+        for index in crossed {
+            let Some(marker) = self.markers.markers().get(index) else {
+                continue;
+            };
+            self.engine.report(EngineFeedback::MarkerReached {
+                index,
+                position: marker.position,
+                name: marker.name.clone(),
+            });
+            reached.push(marker.clone());
+        }
+        reached
+    }
+
+    /// Whether the whole file has been played, or reading it failed
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.shared.done.load(Ordering::Acquire)
+    }
+
+    /// Blocks until the file has played to the end.
+    ///
+    /// # Errors
+    /// Returns an error if the file couldn't be read or the engine fails
+    /// to stop.
+    pub fn wait(self) -> Result<()> {
+        self.wait_with(|_| {})
+    }
+
+    /// Blocks until the file has played to the end, calling `on_progress`
+    /// along the way.
+    ///
+    /// # Errors
+    /// Returns an error if the file couldn't be read or the engine fails
+    /// to stop.
+    pub fn wait_with(self, on_progress: impl FnMut(Progress)) -> Result<()> {
+        self.wait_with_markers(on_progress, |_| {})
+    }
+
+    /// Blocks until the file has played to the end, calling `on_progress`
+    /// along the way and `on_marker` for each marker crossed.
+    ///
+    /// # Errors
+    /// Returns an error if the file couldn't be read or the engine fails
+    /// to stop.
+    pub fn wait_with_markers(
+        mut self,
+        mut on_progress: impl FnMut(Progress),
+        mut on_marker: impl FnMut(&CueMarker),
+    ) -> Result<()> {
+        loop {
+            for marker in self.crossed_markers() {
+                on_marker(&marker);
+            }
+            // Nobody else reads the feedback; keep it from filling up
+            while self.engine.feedback().try_recv().is_some() {}
+            on_progress(self.progress());
+            if self.is_finished() {
+                break;
+            }
+            thread::sleep(PROGRESS_INTERVAL);
+        }
+        self.finish()
+    }
+
+    /// Stops playback before the end of the file.
+    ///
+    /// # Errors
+    /// Returns an error if the file couldn't be read or the engine fails
+    /// to stop.
+    pub fn stop(mut self) -> Result<()> {
+        self.finish()
+    }
+
+    /// Frames handed to the engine and played
+    fn played_frames(&self) -> u64 {
+        self.shared
+            .pushed
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.shared.buffered.load(Ordering::Relaxed))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.shared.stop.store(true, Ordering::Release);
+        let stopped = self.engine.stop();
+        let read = match self.reader.take() {
+            Some(reader) => reader
+                .join()
+                .map_err(|_| AudioEngineError::configuration("file reader panicked"))?,
+            None => Ok(()),
+        };
+        read?;
+        stopped
+    }
+}
+
+impl Drop for PlaybackHandle {
+    fn drop(&mut self) {
+        if self.reader.is_some()
+            && let Err(e) = self.finish()
+        {
+            log::error!("Playback ended with an error: {e}");
+        }
+    }
+}
+
+impl fmt::Debug for PlaybackHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PlaybackHandle")
+            .field("engine", &self.engine)
+            .field("progress", &self.progress())
+            .field("finished", &self.is_finished())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Records `duration` of what the chain of the engine `builder` makes of
+/// its input into a WAV file. Returns the frames recorded.
+pub(super) fn record(
+    builder: EngineBuilder,
+    path: &Path,
+    duration: Duration,
+    mut on_progress: impl FnMut(Progress),
+) -> Result<u64> {
+    let format = builder.config.to_audio_format();
+    let (tap, mut recorded) = RingBuffer::new(ring_samples(
+        format.sample_rate,
+        format.channels,
+        BUFFER_SECONDS,
+    ));
+    let mut engine = builder.with_tap(tap).build()?;
+    if !engine.has_input() {
+        return Err(AudioEngineError::configuration(
+            "there is no input device to record from",
+        ));
+    }
+    let mut wav = WavWriter::create(path, format)?;
+    engine.send(EngineCommand::SetGain(Gain::SILENCE))?;
+    engine.start()?;
+
+    let rate = f64::from(format.sample_rate.as_hz());
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let total = (duration.as_secs_f64() * rate).round() as u64;
+    #[allow(clippy::cast_precision_loss)]
+    let progress = |frames: u64| Progress {
+        seconds: frames as f64 / rate,
+        total_seconds: Some(total as f64 / rate),
+    };
+    let channels = format.channels.count_usize();
+    let mut block = vec![Sample::SILENCE; CHUNK_FRAMES * channels];
+    let mut written = 0u64;
+    let mut reported = std::time::Instant::now();
+    on_progress(progress(0));
+    while written < total {
+        while engine.feedback().try_recv().is_some() {}
+        let wanted = usize::try_from(total - written)
+            .unwrap_or(usize::MAX)
+            .min(CHUNK_FRAMES);
+        let read = recorded.pop_slice(&mut block[..wanted * channels]);
+        if read == 0 {
+            thread::sleep(IDLE_WAIT);
+        } else {
+            wav.write_samples(&block[..read])?;
+            written += (read / channels) as u64;
+        }
+        if reported.elapsed() >= PROGRESS_INTERVAL {
+            on_progress(progress(written));
+            reported = std::time::Instant::now();
+        }
+    }
+    engine.stop()?;
+    wav.finalize()?;
+    on_progress(progress(written));
+    Ok(written)
+}
+
+/// Samples of `seconds` in a format
+const fn ring_samples(sample_rate: SampleRate, channels: ChannelCount, seconds: u32) -> usize {
+    (sample_rate.as_hz() * seconds) as usize * channels.count_usize()
+}
+
+/// Opens a file to play, with its length in frames if known. WAV, AIFF
+/// and CAF files are read directly, Opus files need the `opus` feature and
+/// the rest the `symphonia` feature.
+fn open_source(path: &Path) -> Result<(Box<dyn StreamSource>, Option<u64>)> {
+    let input = crate::io::FileInput::new(path);
+    match input.format() {
+        Some(AudioFileFormat::Wav | AudioFileFormat::Aiff | AudioFileFormat::Caf) => {
+            let reader = PcmReader::open_input(&input)?;
+            let frames = reader.frames();
+            return Ok((Box::new(reader), Some(frames)));
+        }
+        #[cfg(feature = "opus")]
+        Some(AudioFileFormat::Opus) => {
+            let decoder = crate::io::opus::OpusFileDecoder::open(path)?;
+            let frames = decoder.frames();
+            return Ok((Box::new(decoder), frames));
+        }
+        _ => {}
+    }
+    #[cfg(feature = "symphonia")]
+    {
+        let decoder = crate::io::decode::AudioFileDecoder::open(&input)?;
+        let frames = decoder.frames();
+        Ok((Box::new(decoder), frames))
+    }
+    #[cfg(not(feature = "symphonia"))]
+    {
+        Err(AudioEngineError::UnsupportedFormat {
+            format: input.format().map_or_else(
+                || input.extension().unwrap_or("no extension").to_string(),
+                |format| format.to_string(),
+            ),
+        })
+    }
+}
+
+/// Reads a file into the engine's crossfade source.
+struct FileReader {
+    source: Box<dyn StreamSource>,
+    ring: RingBufferWriter<Sample>,
+    channels: usize,
+    shared: Arc<ReaderState>,
+}
+
+impl FileReader {
+    fn run(mut self) -> Result<()> {
+        let read = self.read();
+        if read.is_ok() {
+            // Let the engine play what is still buffered
+            while !self.stopped() && self.buffered() > 0 {
+                thread::sleep(IDLE_WAIT);
+            }
+        }
+        self.shared.done.store(true, Ordering::Release);
+        read
+    }
+
+    fn read(&mut self) -> Result<()> {
+        let mut chunk = vec![Sample::SILENCE; CHUNK_FRAMES * self.channels];
+        loop {
+            let read = self.source.read_samples(&mut chunk)?;
+            if read == 0 {
+                return Ok(());
+            }
+            let mut sent = 0;
+            while sent < read {
+                if self.stopped() {
+                    return Ok(());
+                }
+                let pushed = self.ring.push_slice(&chunk[sent..read]);
+                sent += pushed;
+                if pushed == 0 {
+                    self.buffered();
+                    thread::sleep(IDLE_WAIT);
+                }
+            }
+            self.shared
+                .pushed
+                .fetch_add((read / self.channels) as u64, Ordering::Relaxed);
+            self.buffered();
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.shared.stop.load(Ordering::Acquire)
+    }
+
+    /// Publishes and returns the frames not played yet
+    fn buffered(&self) -> u64 {
+        let buffered = ((self.ring.capacity() - self.ring.slots()) / self.channels) as u64;
+        self.shared.buffered.store(buffered, Ordering::Relaxed);
+        buffered
+    }
+}
@@ -0,0 +1,343 @@
+//! Transport
+//!
+//! [`Transport`] keeps the playhead of an engine: whether it is rolling,
+//! where it is on the timeline, and the loop and punch ranges. It moves
+//! only when frames are processed, so its position is exact to the sample
+//! rather than following the wall clock.
+//!
+//! A block may cross the end of the loop. [`advance`](Transport::advance)
+//! then hands the block back in spans, one per contiguous run of the
+//! timeline, so the caller can render each span from the right place.
+//!
+//! [`record`](Transport::record) starts a recording pass: the playhead
+//! moves back from the punch-in point by the pre-roll, so the performer
+//! hears what leads up to it, and holds there through the count-in, bars
+//! in which only the [`Metronome`](crate::engine::metronome::Metronome)
+//! sounds.
+//!
+//! With a video frame rate set, [`time`](Transport::time) carries the
+//! position as SMPTE timecode too, counted at 23.976, 24, 25, 29.97 drop
+//! frame or 30 frames per second the way an editor counts it.
+
+use crate::types::{
+    FrameRate, SampleRate, TempoMap, TimeRange, Timecode, Timestamp, TransportPosition,
+};
+
+/// Whether the transport is rolling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TransportState {
+    #[default]
+    Stopped,
+    Playing,
+    Paused,
+    /// Holding still through a count-in, then playing
+    CountingIn,
+}
+
+/// Lead-in to a recording pass, in bars of the meter where it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CountIn {
+    /// Bars of click only, before the transport rolls
+    pub bars: u32,
+    /// Bars played before the punch-in point
+    pub pre_roll_bars: u32,
+}
+
+impl CountIn {
+    #[must_use]
+    pub const fn new(bars: u32, pre_roll_bars: u32) -> Self {
+        Self {
+            bars,
+            pre_roll_bars,
+        }
+    }
+}
+
+/// A contiguous run of frames returned by [`Transport::advance`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportSpan {
+    /// Timeline position of the first frame
+    pub start: Timestamp,
+    /// Frames in the span
+    pub frames: usize,
+    /// Whether the transport was rolling; if not, `start` stays put
+    pub rolling: bool,
+    /// Whether the span ends at the loop end, so the next one starts at
+    /// the loop start
+    pub wrapped: bool,
+    /// Count-in frames left at the start of the span; zero unless
+    /// counting in, when the span ends with the count-in or before it
+    pub count_in: u64,
+}
+
+/// Playhead, loop and punch state of an engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transport {
+    sample_rate: SampleRate,
+    state: TransportState,
+    position: Timestamp,
+    /// Where [`stop`](Self::stop) returns to
+    return_position: Timestamp,
+    loop_range: Option<TimeRange>,
+    punch: Option<TimeRange>,
+    /// Count-in frames left
+    count_in: u64,
+    /// Video frame rate the position is shown in as timecode
+    frame_rate: Option<FrameRate>,
+}
+
+impl Transport {
+    #[must_use]
+    pub const fn new(sample_rate: SampleRate) -> Self {
+        Self {
+            sample_rate,
+            state: TransportState::Stopped,
+            position: Timestamp::ZERO,
+            return_position: Timestamp::ZERO,
+            loop_range: None,
+            punch: None,
+            count_in: 0,
+            frame_rate: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    pub const fn set_sample_rate(&mut self, sample_rate: SampleRate) {
+        self.sample_rate = sample_rate;
+    }
+
+    #[must_use]
+    pub const fn state(&self) -> TransportState {
+        self.state
+    }
+
+    #[must_use]
+    pub const fn is_playing(&self) -> bool {
+        matches!(self.state, TransportState::Playing)
+    }
+
+    #[must_use]
+    pub const fn is_counting_in(&self) -> bool {
+        matches!(self.state, TransportState::CountingIn)
+    }
+
+    /// Count-in frames left before the transport rolls
+    #[must_use]
+    pub const fn count_in_remaining(&self) -> u64 {
+        self.count_in
+    }
+
+    /// Position of the next frame to be processed
+    #[must_use]
+    pub const fn position(&self) -> Timestamp {
+        self.position
+    }
+
+    /// The position as a time code, with SMPTE timecode if a frame rate
+    /// is set
+    #[must_use]
+    pub fn time(&self) -> TransportPosition {
+        let time = TransportPosition::from_timestamp(self.position, self.sample_rate);
+        self.timecode()
+            .map_or(time, |timecode| time.with_timecode(timecode))
+    }
+
+    /// The position as SMPTE timecode at the frame rate, if one is set
+    #[must_use]
+    pub const fn timecode(&self) -> Option<Timecode> {
+        match self.frame_rate {
+            Some(rate) => Some(Timecode::from_timestamp(
+                self.position,
+                self.sample_rate,
+                rate,
+            )),
+            None => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn frame_rate(&self) -> Option<FrameRate> {
+        self.frame_rate
+    }
+
+    /// Shows the position as timecode at `rate`, the project's video
+    /// frame rate, or as plain time with `None`
+    pub const fn set_frame_rate(&mut self, rate: Option<FrameRate>) {
+        self.frame_rate = rate;
+    }
+
+    #[must_use]
+    pub const fn loop_range(&self) -> Option<TimeRange> {
+        self.loop_range
+    }
+
+    #[must_use]
+    pub const fn punch_range(&self) -> Option<TimeRange> {
+        self.punch
+    }
+
+    /// Starts rolling from the current position, cutting any count-in
+    /// short. Playing from stopped remembers the position for
+    /// [`stop`](Self::stop) to return to.
+    pub const fn play(&mut self) {
+        if matches!(self.state, TransportState::Stopped) {
+            self.return_position = self.position;
+        }
+        self.state = TransportState::Playing;
+        self.count_in = 0;
+    }
+
+    /// Holds still for `frames`, then rolls from the current position.
+    pub const fn play_after(&mut self, frames: u64) {
+        self.play();
+        if frames > 0 {
+            self.state = TransportState::CountingIn;
+            self.count_in = frames;
+        }
+    }
+
+    /// Starts a recording pass: moves back `count_in.pre_roll_bars` from
+    /// the punch-in point, or from the current position without a punch
+    /// range, and rolls from there after `count_in.bars` of count-in.
+    /// [`stop`](Self::stop) returns to the position before the pass.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn record(&mut self, count_in: CountIn, tempo_map: &TempoMap) {
+        let was_stopped = matches!(self.state, TransportState::Stopped);
+        let target = self.punch.map_or(self.position, TimeRange::start);
+        let pre_roll =
+            f64::from(count_in.pre_roll_bars) * tempo_map.signature_at(target).bar_beats();
+        let start = tempo_map.position_of_beats(tempo_map.beats_at(target) - pre_roll);
+        let beats = f64::from(count_in.bars) * tempo_map.signature_at(start).bar_beats();
+        let frames = tempo_map
+            .tempo_at(start)
+            .beats_to_samples(beats, self.sample_rate)
+            .round() as u64;
+        if was_stopped {
+            self.return_position = self.position;
+        }
+        self.position = start;
+        self.state = TransportState::Paused;
+        self.play_after(frames);
+    }
+
+    /// Stops rolling and keeps the position.
+    pub const fn pause(&mut self) {
+        if self.is_playing() || self.is_counting_in() {
+            self.state = TransportState::Paused;
+            self.count_in = 0;
+        }
+    }
+
+    /// Stops rolling and returns to where playback last started.
+    pub const fn stop(&mut self) {
+        self.state = TransportState::Stopped;
+        self.position = self.return_position;
+        self.count_in = 0;
+    }
+
+    /// Moves the playhead without changing whether it rolls.
+    pub const fn seek(&mut self, position: Timestamp) {
+        self.position = position;
+    }
+
+    /// Stops at `position`, which becomes the point
+    /// [`stop`](Self::stop) returns to.
+    pub const fn locate(&mut self, position: Timestamp) {
+        self.state = TransportState::Stopped;
+        self.count_in = 0;
+        self.position = position;
+        self.return_position = position;
+    }
+
+    /// Sets the loop range, or turns looping off with `None`. Empty ranges
+    /// turn looping off as well.
+    pub fn set_loop(&mut self, range: Option<TimeRange>) {
+        self.loop_range = range.filter(|range| !range.is_empty());
+    }
+
+    /// Sets the range recording punches in and out of.
+    pub fn set_punch(&mut self, range: Option<TimeRange>) {
+        self.punch = range.filter(|range| !range.is_empty());
+    }
+
+    /// Whether the transport is rolling inside the punch range
+    #[must_use]
+    pub fn is_punched_in(&self) -> bool {
+        self.is_playing()
+            && self
+                .punch
+                .is_some_and(|punch| punch.contains(self.position))
+    }
+
+    /// Moves the playhead over up to `frames` processed frames and returns
+    /// the run covered.
+    ///
+    /// The run stops short at the loop end, after which the playhead is
+    /// back at the loop start; call again for the rest of the block. It
+    /// also stops short at the punch boundaries, so a recorder can switch
+    /// on the exact frame, and at the end of a count-in, after which the
+    /// transport rolls. A transport that isn't rolling covers all `frames`
+    /// at once without moving.
+    pub fn advance(&mut self, frames: usize) -> TransportSpan {
+        let start = self.position;
+        if self.is_counting_in() {
+            let count_in = self.count_in;
+            let length = count_in.min(frames as u64);
+            self.count_in -= length;
+            if self.count_in == 0 {
+                self.state = TransportState::Playing;
+            }
+            return TransportSpan {
+                start,
+                frames: usize::try_from(length).unwrap_or(frames),
+                rolling: false,
+                wrapped: false,
+                count_in,
+            };
+        }
+        if !self.is_playing() {
+            return TransportSpan {
+                start,
+                frames,
+                rolling: false,
+                wrapped: false,
+                count_in: 0,
+            };
+        }
+
+        let (mut length, mut wrapped) = match self.loop_range {
+            Some(range)
+                if start < range.end()
+                    && start.as_samples() + frames as u64 >= range.end().as_samples() =>
+            {
+                (range.end().as_samples() - start.as_samples(), true)
+            }
+            _ => (frames as u64, false),
+        };
+        if let Some(punch) = self.punch {
+            for edge in [punch.start(), punch.end()] {
+                if edge > start && edge.as_samples() < start.as_samples() + length {
+                    length = edge.as_samples() - start.as_samples();
+                    wrapped = false;
+                }
+            }
+        }
+
+        self.position = match (wrapped, self.loop_range) {
+            (true, Some(range)) => range.start(),
+            _ => Timestamp::from_samples(start.as_samples() + length),
+        };
+        TransportSpan {
+            start,
+            frames: usize::try_from(length).unwrap_or(frames),
+            rolling: true,
+            wrapped,
+            count_in: 0,
+        }
+    }
+}
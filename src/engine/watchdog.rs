@@ -0,0 +1,318 @@
+//! Stream supervision and recovery
+//!
+//! Devices get unplugged, drivers restart and streams die with them. An
+//! [`EngineWatchdog`] watches the feedback the host passes to
+//! [`observe`](EngineWatchdog::observe) for the signs: a stream error, a
+//! burst of underruns, or a running engine that has gone quiet because
+//! its callback stopped. [`poll`](EngineWatchdog::poll) then rebuilds the
+//! streams with [`Engine::rebuild_streams`], waiting between attempts, and
+//! after [`WatchdogSettings::retries`] failed attempts moves the output to
+//! the next fallback device, ending with the system default. Each attempt
+//! is reported as [`EngineFeedback::StreamRecovery`].
+//!
+//! ```no_run
+//! use audio_engine::engine::Engine;
+//! use audio_engine::engine::watchdog::{EngineWatchdog, WatchdogSettings};
+//!
+//! let mut engine = Engine::builder().build()?;
+//! let mut watchdog =
+//!     EngineWatchdog::new(WatchdogSettings::default().with_fallback_output("USB Audio"));
+//! engine.start()?;
+//! loop {
+//!     while let Some(feedback) = engine.feedback().try_recv() {
+//!         watchdog.observe(&feedback);
+//!     }
+//!     watchdog.poll(&mut engine);
+//!     std::thread::sleep(std::time::Duration::from_millis(50));
+//! }
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! [`EngineFeedback::StreamRecovery`]: crate::channel::EngineFeedback::StreamRecovery
+
+use std::time::{Duration, Instant};
+
+use crate::audio::device::AudioDeviceManager;
+use crate::channel::{EngineFeedback, EngineState};
+use crate::engine::Engine;
+
+/// When an [`EngineWatchdog`] steps in, and how hard it tries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WatchdogSettings {
+    /// Rebuilds on a device before moving to the next fallback
+    pub retries: u32,
+    /// Wait between attempts
+    pub retry_delay: Duration,
+    /// How long a running engine may go without feedback before its
+    /// stream counts as stalled
+    pub stall_timeout: Duration,
+    /// Underruns within [`underrun_window`](Self::underrun_window) that
+    /// count as a failure; zero never does
+    pub underrun_limit: u32,
+    pub underrun_window: Duration,
+    /// How long the streams must run cleanly after a recovery before
+    /// earlier failures are forgotten
+    pub stable_after: Duration,
+    /// Output devices to move to, by name, in order, before the default
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fallback_outputs: Vec<String>,
+}
+
+impl Default for WatchdogSettings {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            retry_delay: Duration::from_millis(500),
+            stall_timeout: Duration::from_secs(2),
+            underrun_limit: 50,
+            underrun_window: Duration::from_secs(1),
+            stable_after: Duration::from_secs(10),
+            fallback_outputs: Vec::new(),
+        }
+    }
+}
+
+impl WatchdogSettings {
+    #[must_use]
+    pub const fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_underrun_limit(mut self, limit: u32, window: Duration) -> Self {
+        self.underrun_limit = limit;
+        self.underrun_window = window;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_stable_after(mut self, duration: Duration) -> Self {
+        self.stable_after = duration;
+        self
+    }
+
+    /// Adds an output device to fall back to, after those already added
+    #[must_use]
+    pub fn with_fallback_output(mut self, name: impl Into<String>) -> Self {
+        self.fallback_outputs.push(name.into());
+        self
+    }
+}
+
+/// One recovery attempt made by [`EngineWatchdog::poll`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recovery {
+    /// Attempts since the streams last ran cleanly, counting this one
+    pub attempt: u32,
+    /// Output device the streams were rebuilt on, `None` for a virtual
+    /// device
+    pub device: Option<String>,
+    pub reason: String,
+    pub recovered: bool,
+}
+
+/// Watches an engine's feedback and rebuilds its streams when they fail.
+#[derive(Debug)]
+pub struct EngineWatchdog {
+    settings: WatchdogSettings,
+    /// What needs recovering from, if anything
+    failure: Option<String>,
+    /// Last feedback from the audio thread
+    heard: Instant,
+    window_start: Instant,
+    window_underruns: u32,
+    /// Attempts since the streams last ran cleanly
+    attempts: u32,
+    /// Attempts on the current device
+    device_attempts: u32,
+    /// Next entry of the fallback list; past its end means the default
+    fallback: usize,
+    /// No attempt before this
+    retry_at: Option<Instant>,
+    recovered_at: Option<Instant>,
+    recoveries: u64,
+}
+
+impl EngineWatchdog {
+    #[must_use]
+    pub fn new(settings: WatchdogSettings) -> Self {
+        let now = Instant::now();
+        Self {
+            settings,
+            failure: None,
+            heard: now,
+            window_start: now,
+            window_underruns: 0,
+            attempts: 0,
+            device_attempts: 0,
+            fallback: 0,
+            retry_at: None,
+            recovered_at: None,
+            recoveries: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &WatchdogSettings {
+        &self.settings
+    }
+
+    /// What the watchdog is recovering from, if anything
+    #[must_use]
+    pub fn failure(&self) -> Option<&str> {
+        self.failure.as_deref()
+    }
+
+    /// Successful recoveries so far
+    #[must_use]
+    pub const fn recoveries(&self) -> u64 {
+        self.recoveries
+    }
+
+    /// Takes in one piece of feedback from the engine.
+    pub fn observe(&mut self, feedback: &EngineFeedback) {
+        let now = Instant::now();
+        match feedback {
+            // Sent from the control thread, so no sign of the stream's health
+            EngineFeedback::StreamRecovery { .. }
+            | EngineFeedback::RenderProgress { .. }
+            | EngineFeedback::StreamClosed { .. }
+            | EngineFeedback::StreamDetached { .. }
+            | EngineFeedback::MarkerReached { .. }
+            | EngineFeedback::BufferSizeChanged { .. }
+            | EngineFeedback::QualityChanged { .. }
+            | EngineFeedback::NetworkLatency { .. }
+            | EngineFeedback::BitrateChanged { .. } => {
+                return;
+            }
+            EngineFeedback::Error(message) => self.fail(format!("stream error: {message}")),
+            EngineFeedback::Underrun if self.settings.underrun_limit > 0 => {
+                if now.duration_since(self.window_start) > self.settings.underrun_window {
+                    self.window_start = now;
+                    self.window_underruns = 0;
+                }
+                self.window_underruns += 1;
+                if self.window_underruns >= self.settings.underrun_limit {
+                    self.window_underruns = 0;
+                    self.fail(format!(
+                        "{} underruns within {:?}",
+                        self.settings.underrun_limit, self.settings.underrun_window
+                    ));
+                }
+            }
+            _ => {}
+        }
+        self.heard = now;
+    }
+
+    /// Checks for a stalled stream and makes a recovery attempt if one is
+    /// needed and due, reporting it on the engine's feedback channel. Call
+    /// it regularly from the control thread, after passing on the
+    /// engine's feedback.
+    pub fn poll(&mut self, engine: &mut Engine) -> Option<Recovery> {
+        let now = Instant::now();
+        if engine.state() != EngineState::Running {
+            // A stopped engine sends nothing, which isn't a stall
+            self.heard = now;
+        } else if now.duration_since(self.heard) > self.settings.stall_timeout {
+            self.heard = now;
+            self.fail(format!("no feedback for {:?}", self.settings.stall_timeout));
+        }
+        if self.failure.is_none() {
+            if self
+                .recovered_at
+                .is_some_and(|at| now.duration_since(at) >= self.settings.stable_after)
+            {
+                self.attempts = 0;
+                self.device_attempts = 0;
+                self.recovered_at = None;
+            }
+            return None;
+        }
+        if self.retry_at.is_some_and(|at| now < at) {
+            return None;
+        }
+        Some(self.attempt(engine, now))
+    }
+
+    /// Starts recovering from `reason`, unless already recovering.
+    fn fail(&mut self, reason: String) {
+        if self.failure.is_none() {
+            log::warn!("Engine watchdog: {reason}");
+            self.failure = Some(reason);
+            self.recovered_at = None;
+        }
+    }
+
+    fn attempt(&mut self, engine: &mut Engine, now: Instant) -> Recovery {
+        let reason = self.failure.clone().unwrap_or_default();
+        self.attempts += 1;
+        self.device_attempts += 1;
+        // A virtual device has nowhere to fall back to
+        let escalate =
+            self.device_attempts > self.settings.retries.max(1) && engine.output_device().is_some();
+        let result = if escalate {
+            self.device_attempts = 1;
+            let fallback = self.settings.fallback_outputs.get(self.fallback).cloned();
+            self.fallback += 1;
+            fallback
+                .map_or_else(
+                    || {
+                        AudioDeviceManager::new()
+                            .default_output()
+                            .map(|device| device.name().to_string())
+                    },
+                    Ok,
+                )
+                .and_then(|name| {
+                    log::warn!("Engine watchdog: falling back to output {name}");
+                    engine.switch_output_device(&name)
+                })
+        } else {
+            engine.rebuild_streams()
+        };
+        let recovered = match result {
+            Ok(()) => {
+                log::info!("Engine watchdog: streams rebuilt after {reason}");
+                self.failure = None;
+                self.retry_at = None;
+                self.recovered_at = Some(now);
+                self.heard = now;
+                self.recoveries += 1;
+                true
+            }
+            Err(e) => {
+                log::error!("Engine watchdog: rebuilding the streams failed: {e}");
+                self.retry_at = Some(now + self.settings.retry_delay);
+                false
+            }
+        };
+        let recovery = Recovery {
+            attempt: self.attempts,
+            device: engine.output_device().map(str::to_string),
+            reason,
+            recovered,
+        };
+        let _ = engine.report(EngineFeedback::StreamRecovery {
+            attempt: recovery.attempt,
+            device: recovery.device.clone().unwrap_or_default(),
+            reason: recovery.reason.clone(),
+            recovered,
+        });
+        recovery
+    }
+}
@@ -0,0 +1,423 @@
+//! Audio routing graph
+//!
+//! Where an [`EffectChain`](crate::dsp::chain::EffectChain) runs effects in
+//! a line, a graph connects nodes arbitrarily: sources, effects, mixers and
+//! outputs, with a gain on every connection. That covers sends (a
+//! connection from a track into a bus at the send level), buses (a
+//! [`Node::Mixer`] with an effect after it) and mixers with any number of
+//! inputs.
+//!
+//! The graph is split in two. [`Graph`] lives on the control thread: it
+//! owns the topology, validates connections and, on
+//! [`commit`](Graph::commit), sorts the nodes into a processing order. The
+//! [`GraphProcessor`] lives on the audio thread and runs that order. Each
+//! commit reaches the processor as a single message that it swaps in
+//! between two blocks, so the audio thread never sees half an edit and
+//! never allocates or frees; replaced schedules and removed nodes travel
+//! back to the control thread to be dropped there.
+
+mod processor;
+mod schedule;
+
+pub use processor::GraphProcessor;
+
+use std::fmt;
+
+use crate::buffer::memory::heap_bytes;
+use crate::buffer::{MemoryKind, MemoryReport};
+use crate::channel::{ControlReceiver, ControlSender, control_channel, feedback_channel};
+use crate::dsp::traits::Effect;
+use crate::error::{AudioEngineError, Result};
+use crate::types::{ChannelCount, Gain, Sample, SampleRate};
+
+use processor::{GraphUpdate, NodeState};
+
+/// Nodes a graph holds unless created with [`Graph::with_max_nodes`]
+const DEFAULT_MAX_NODES: usize = 256;
+/// Commits that can be waiting for the audio thread
+const UPDATE_CAPACITY: usize = 16;
+
+/// Identifies a node within its graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    #[must_use]
+    pub const fn new(id: u32) -> Self {
+        Self(id)
+    }
+
+    #[must_use]
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    const fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Node#{}", self.0)
+    }
+}
+
+/// Generates audio for a [`Node::Source`].
+pub trait Source: Send + 'static {
+    /// Called on the control thread before the source is first run.
+    fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        let _ = (sample_rate, channels);
+    }
+
+    fn reset(&mut self) {}
+
+    /// Restarts any random generators from `seed`; see
+    /// [`Effect::reseed`].
+    fn reseed(&mut self, seed: u64) {
+        let _ = seed;
+    }
+
+    /// Overwrites `output` with the next interleaved samples.
+    fn render(&mut self, output: &mut [Sample], channels: ChannelCount);
+
+    /// Bytes the source holds, including buffers it allocated up front;
+    /// see [`Effect::memory_bytes`].
+    fn memory_bytes(&self) -> usize {
+        size_of_val(self)
+    }
+}
+
+/// A node in the graph.
+///
+/// Every node except inputs and sources starts each block from the sum of
+/// its incoming connections, each scaled by the connection's gain.
+pub enum Node {
+    /// The block passed to [`GraphProcessor::process`]
+    Input,
+    /// Generated audio; takes no inputs
+    Source(Box<dyn Source>),
+    /// Runs an effect on the sum of its inputs
+    Effect(Box<dyn Effect>),
+    /// The sum of its inputs, e.g. a bus
+    Mixer,
+    /// Written back to the processed block; several outputs are summed
+    Output,
+}
+
+impl Node {
+    #[must_use]
+    pub const fn kind(&self) -> NodeKind {
+        match self {
+            Self::Input => NodeKind::Input,
+            Self::Source(_) => NodeKind::Source,
+            Self::Effect(_) => NodeKind::Effect,
+            Self::Mixer => NodeKind::Mixer,
+            Self::Output => NodeKind::Output,
+        }
+    }
+
+    /// Bytes the node's source or effect holds
+    #[must_use]
+    pub fn memory_bytes(&self) -> usize {
+        match self {
+            Self::Source(source) => source.memory_bytes(),
+            Self::Effect(effect) => effect.memory_bytes(),
+            Self::Input | Self::Mixer | Self::Output => 0,
+        }
+    }
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Effect(effect) => f
+                .debug_tuple("Effect")
+                .field(&effect.name())
+                .field(&effect.id())
+                .finish(),
+            other => write!(f, "{:?}", other.kind()),
+        }
+    }
+}
+
+/// What a node does, without its state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    Input,
+    Source,
+    Effect,
+    Mixer,
+    Output,
+}
+
+impl NodeKind {
+    /// Whether connections may lead into the node
+    #[must_use]
+    pub const fn accepts_inputs(self) -> bool {
+        !matches!(self, Self::Input | Self::Source)
+    }
+
+    /// Whether connections may lead out of the node
+    #[must_use]
+    pub const fn has_output(self) -> bool {
+        !matches!(self, Self::Output)
+    }
+}
+
+/// A weighted connection between two nodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Connection {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub gain: Gain,
+}
+
+/// Control thread side of an audio graph.
+///
+/// Edits only take effect on the audio thread at the next
+/// [`commit`](Self::commit).
+pub struct Graph {
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    block_samples: usize,
+    max_nodes: usize,
+    nodes: Vec<Option<NodeKind>>,
+    /// Bytes each node took on the audio thread when it was added
+    node_bytes: Vec<usize>,
+    connections: Vec<Connection>,
+    added: Vec<(usize, NodeState)>,
+    removed: Vec<usize>,
+    updates: ControlSender<Box<GraphUpdate>>,
+    returned: ControlReceiver<Box<GraphUpdate>>,
+}
+
+impl Graph {
+    /// Creates an empty graph and the processor that runs it.
+    ///
+    /// Nodes are initialized with `sample_rate` and `channels`, and each
+    /// gets a buffer of `max_block_frames`; longer blocks are processed in
+    /// pieces.
+    #[must_use]
+    pub fn new(
+        sample_rate: SampleRate,
+        channels: ChannelCount,
+        max_block_frames: usize,
+    ) -> (Self, GraphProcessor) {
+        Self::with_max_nodes(sample_rate, channels, max_block_frames, DEFAULT_MAX_NODES)
+    }
+
+    /// Like [`new`](Self::new), with room for `max_nodes` nodes.
+    #[must_use]
+    pub fn with_max_nodes(
+        sample_rate: SampleRate,
+        channels: ChannelCount,
+        max_block_frames: usize,
+        max_nodes: usize,
+    ) -> (Self, GraphProcessor) {
+        let (updates, update_receiver) = control_channel(UPDATE_CAPACITY);
+        let (give_back, returned) = feedback_channel(UPDATE_CAPACITY);
+        let block_samples = max_block_frames.max(1) * channels.count_usize();
+        let graph = Self {
+            sample_rate,
+            channels,
+            block_samples,
+            max_nodes,
+            nodes: Vec::with_capacity(max_nodes),
+            node_bytes: Vec::with_capacity(max_nodes),
+            connections: Vec::new(),
+            added: Vec::new(),
+            removed: Vec::new(),
+            updates,
+            returned,
+        };
+        let processor = GraphProcessor::new(
+            max_nodes,
+            block_samples,
+            channels,
+            update_receiver,
+            give_back,
+        );
+        (graph, processor)
+    }
+
+    /// Adds a node, initializing it with the graph's format.
+    ///
+    /// # Errors
+    /// Returns an error if the graph is full.
+    pub fn add_node(&mut self, mut node: Node) -> Result<NodeId> {
+        let index = match self.nodes.iter().position(Option::is_none) {
+            Some(index) => index,
+            None if self.nodes.len() < self.max_nodes => {
+                self.nodes.push(None);
+                self.node_bytes.push(0);
+                self.nodes.len() - 1
+            }
+            None => {
+                return Err(AudioEngineError::configuration(format!(
+                    "graph is full ({} nodes)",
+                    self.nodes.len()
+                )));
+            }
+        };
+        let id = u32::try_from(index)
+            .map_err(|_| AudioEngineError::numeric_conversion("node index exceeds u32"))?;
+        match &mut node {
+            Node::Source(source) => source.initialize(self.sample_rate, self.channels),
+            Node::Effect(effect) => effect.initialize(self.sample_rate, self.channels),
+            Node::Input | Node::Mixer | Node::Output => {}
+        }
+
+        self.nodes[index] = Some(node.kind());
+        let buffer = vec![Sample::SILENCE; self.block_samples];
+        self.node_bytes[index] = size_of::<NodeState>() + heap_bytes(&buffer) + node.memory_bytes();
+        self.added.push((index, NodeState { node, buffer }));
+        Ok(NodeId::new(id))
+    }
+
+    /// Removes a node and its connections. Returns false if there is no such
+    /// node.
+    pub fn remove_node(&mut self, id: NodeId) -> bool {
+        if self.kind(id).is_none() {
+            return false;
+        }
+        self.nodes[id.index()] = None;
+        self.connections.retain(|c| c.from != id && c.to != id);
+        // A node that never reached the audio thread is simply dropped
+        if let Some(position) = self
+            .added
+            .iter()
+            .position(|(index, _)| *index == id.index())
+        {
+            self.added.remove(position);
+        } else {
+            self.removed.push(id.index());
+        }
+        true
+    }
+
+    /// Connects `from` into `to` at `gain`, or changes the gain of an
+    /// existing connection.
+    ///
+    /// # Errors
+    /// Returns an error if either node doesn't exist, the nodes can't be
+    /// connected that way, or the connection would create a cycle.
+    pub fn connect(&mut self, from: NodeId, to: NodeId, gain: Gain) -> Result<()> {
+        let (Some(from_kind), Some(to_kind)) = (self.kind(from), self.kind(to)) else {
+            return Err(AudioEngineError::configuration(format!(
+                "can't connect {from} to {to}: no such node"
+            )));
+        };
+        if !from_kind.has_output() || !to_kind.accepts_inputs() {
+            return Err(AudioEngineError::configuration(format!(
+                "can't connect {from_kind:?} {from} to {to_kind:?} {to}"
+            )));
+        }
+        if let Some(connection) = self
+            .connections
+            .iter_mut()
+            .find(|c| c.from == from && c.to == to)
+        {
+            connection.gain = gain;
+            return Ok(());
+        }
+        if from == to || schedule::reaches(&self.connections, to, from) {
+            return Err(AudioEngineError::configuration(format!(
+                "connecting {from} to {to} would create a cycle"
+            )));
+        }
+        self.connections.push(Connection { from, to, gain });
+        Ok(())
+    }
+
+    /// Removes a connection. Returns false if there was none.
+    pub fn disconnect(&mut self, from: NodeId, to: NodeId) -> bool {
+        let before = self.connections.len();
+        self.connections.retain(|c| c.from != from || c.to != to);
+        self.connections.len() != before
+    }
+
+    /// Kind of a node, or `None` if it doesn't exist
+    #[must_use]
+    pub fn kind(&self, id: NodeId) -> Option<NodeKind> {
+        self.nodes.get(id.index()).copied().flatten()
+    }
+
+    #[must_use]
+    pub fn node_count(&self) -> usize {
+        self.nodes.iter().flatten().count()
+    }
+
+    #[must_use]
+    pub fn connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+    /// Memory the graph's processor holds: its node table and every node
+    /// with its output buffer.
+    #[must_use]
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport::new();
+        report.add(
+            "nodes",
+            MemoryKind::Buffer,
+            self.max_nodes * size_of::<Option<NodeState>>(),
+        );
+        for (index, (kind, bytes)) in self.nodes.iter().zip(&self.node_bytes).enumerate() {
+            if let (Some(kind), Ok(id)) = (kind, u32::try_from(index)) {
+                report.add(
+                    format!("{} ({kind:?})", NodeId::new(id)),
+                    MemoryKind::Node,
+                    *bytes,
+                );
+            }
+        }
+        report
+    }
+
+    /// Sends the current topology to the audio thread, where it replaces
+    /// the previous one at the start of the next block.
+    ///
+    /// Schedules and nodes the audio thread has finished with are freed
+    /// here.
+    ///
+    /// # Errors
+    /// Returns an error if too many commits are waiting for the audio
+    /// thread; the edits are kept for the next commit.
+    pub fn commit(&mut self) -> Result<()> {
+        self.collect();
+        if self.updates.len() >= UPDATE_CAPACITY {
+            return Err(AudioEngineError::RingBufferFull { count: 1 });
+        }
+        let update = GraphUpdate {
+            schedule: schedule::compile(&self.nodes, &self.connections),
+            freed: Vec::with_capacity(self.removed.len()),
+            added: std::mem::take(&mut self.added),
+            removed: std::mem::take(&mut self.removed),
+        };
+        self.updates.try_send(Box::new(update))
+    }
+
+    /// Frees schedules and nodes the audio thread has finished with.
+    pub fn collect(&self) {
+        while self.returned.try_recv().is_some() {}
+    }
+}
+
+impl fmt::Debug for Graph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Graph")
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("block_samples", &self.block_samples)
+            .field("max_nodes", &self.max_nodes)
+            .field("nodes", &self.nodes)
+            .field("node_bytes", &self.node_bytes)
+            .field("connections", &self.connections)
+            .field("added", &self.added.len())
+            .field("removed", &self.removed)
+            .field("updates", &self.updates)
+            .field("returned", &self.returned)
+            .finish()
+    }
+}
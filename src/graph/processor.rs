@@ -0,0 +1,278 @@
+//! Audio thread side of a graph
+
+use std::fmt;
+
+use crate::buffer::memory::heap_bytes;
+use crate::channel::{RealtimeReceiver, RealtimeSender};
+use crate::dsp::params::{ParamId, ParamValue, ParameterInfo};
+use crate::dsp::quality::EffectQuality;
+use crate::dsp::random::derive_seed;
+use crate::dsp::traits::{Effect, EffectId};
+use crate::graph::Node;
+use crate::graph::schedule::Schedule;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+/// A node and its output buffer.
+#[derive(Debug)]
+pub struct NodeState {
+    pub node: Node,
+    pub buffer: Vec<Sample>,
+}
+
+/// One commit, sent to the audio thread and back.
+///
+/// On the way there it carries the new nodes and schedule; on the way back
+/// the old schedule and the removed nodes, to be freed on the control
+/// thread.
+#[derive(Debug)]
+pub struct GraphUpdate {
+    pub schedule: Schedule,
+    pub added: Vec<(usize, NodeState)>,
+    pub removed: Vec<usize>,
+    /// Has room for every removed node, so filling it doesn't allocate
+    pub freed: Vec<NodeState>,
+}
+
+/// Runs a [`Graph`](super::Graph) on the audio thread.
+///
+/// It is an [`Effect`], so a graph can sit in an effect chain or run as
+/// an engine's whole processing. Latency of effects in the graph is not
+/// compensated.
+pub struct GraphProcessor {
+    id: EffectId,
+    enabled: bool,
+    slots: Vec<Option<NodeState>>,
+    schedule: Schedule,
+    block_samples: usize,
+    channels: ChannelCount,
+    updates: RealtimeReceiver<Box<GraphUpdate>>,
+    returned: RealtimeSender<Box<GraphUpdate>>,
+}
+
+impl GraphProcessor {
+    pub(crate) fn new(
+        max_nodes: usize,
+        block_samples: usize,
+        channels: ChannelCount,
+        updates: RealtimeReceiver<Box<GraphUpdate>>,
+        returned: RealtimeSender<Box<GraphUpdate>>,
+    ) -> Self {
+        Self {
+            id: EffectId::new(0),
+            enabled: true,
+            slots: (0..max_nodes).map(|_| None).collect(),
+            schedule: Schedule::default(),
+            block_samples,
+            channels,
+            updates,
+            returned,
+        }
+    }
+
+    /// Sets the id the processor reports as an effect
+    #[must_use]
+    pub const fn with_id(mut self, id: EffectId) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Nodes currently scheduled
+    #[must_use]
+    pub const fn node_count(&self) -> usize {
+        self.schedule.steps.len()
+    }
+
+    fn receive_updates(&mut self) {
+        while let Some(mut update) = self.updates.try_recv() {
+            // Removals first, so a slot freed and reused in the same commit
+            // hands its old node back instead of dropping it here
+            for &index in &update.removed {
+                if let Some(state) = self.slots[index].take() {
+                    update.freed.push(state);
+                }
+            }
+            for (index, state) in update.added.drain(..) {
+                self.slots[index] = Some(state);
+            }
+            std::mem::swap(&mut self.schedule, &mut update.schedule);
+            let _ = self.returned.try_send(update);
+        }
+    }
+
+    fn run(&mut self, block: &mut [Sample], channels: ChannelCount) {
+        let len = block.len();
+        for step in &self.schedule.steps {
+            let Some(mut state) = self.slots[step.node].take() else {
+                continue;
+            };
+            let buffer = &mut state.buffer[..len];
+            match &mut state.node {
+                Node::Input => buffer.copy_from_slice(block),
+                Node::Source(source) => source.render(buffer, channels),
+                node => {
+                    buffer.fill(Sample::SILENCE);
+                    for &(input, gain) in &step.inputs {
+                        if let Some(input) = &self.slots[input] {
+                            mix(buffer, &input.buffer[..len], gain);
+                        }
+                    }
+                    if let Node::Effect(effect) = node {
+                        effect.process(buffer, channels);
+                    }
+                }
+            }
+            self.slots[step.node] = Some(state);
+        }
+
+        block.fill(Sample::SILENCE);
+        for &output in &self.schedule.outputs {
+            if let Some(output) = &self.slots[output] {
+                mix(block, &output.buffer[..len], 1.0);
+            }
+        }
+    }
+
+    fn nodes_mut(&mut self) -> impl Iterator<Item = &mut Node> {
+        self.slots.iter_mut().flatten().map(|state| &mut state.node)
+    }
+}
+
+impl Effect for GraphProcessor {
+    fn id(&self) -> EffectId {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        "Graph"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn reset(&mut self) {
+        for node in self.nodes_mut() {
+            match node {
+                Node::Source(source) => source.reset(),
+                Node::Effect(effect) => effect.reset(),
+                Node::Input | Node::Mixer | Node::Output => {}
+            }
+        }
+    }
+
+    /// Reseeds each node from its index, so the seeds don't depend on the
+    /// order nodes reached the audio thread.
+    fn reseed(&mut self, seed: u64) {
+        for (index, slot) in (0u64..).zip(&mut self.slots) {
+            let node_seed = derive_seed(seed, index);
+            match slot.as_mut().map(|state| &mut state.node) {
+                Some(Node::Source(source)) => source.reseed(node_seed),
+                Some(Node::Effect(effect)) => effect.reseed(node_seed),
+                _ => {}
+            }
+        }
+    }
+
+    /// Moves the effect nodes added so far to `quality`.
+    fn set_quality(&mut self, quality: EffectQuality) {
+        for node in self.nodes_mut() {
+            if let Node::Effect(effect) = node {
+                effect.set_quality(quality);
+            }
+        }
+    }
+
+    /// Re-initializes the nodes added so far. Nodes added later are
+    /// initialized with the format the graph was created with.
+    fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        let frames = self.block_samples / self.channels.count_usize();
+        self.channels = channels;
+        self.block_samples = frames * channels.count_usize();
+        let block_samples = self.block_samples;
+        for state in self.slots.iter_mut().flatten() {
+            state.buffer.resize(block_samples, Sample::SILENCE);
+            match &mut state.node {
+                Node::Source(source) => source.initialize(sample_rate, channels),
+                Node::Effect(effect) => effect.initialize(sample_rate, channels),
+                Node::Input | Node::Mixer | Node::Output => {}
+            }
+        }
+    }
+
+    /// Runs the graph on `samples`: input nodes read the block and the sum
+    /// of the output nodes replaces it. Without an output node the block
+    /// comes back silent.
+    fn process(&mut self, samples: &mut [Sample], channels: ChannelCount) {
+        self.receive_updates();
+        if !self.enabled {
+            return;
+        }
+        let channel_count = channels.count_usize();
+        let piece = self.block_samples / channel_count * channel_count;
+        if piece == 0 {
+            return;
+        }
+        for block in samples.chunks_mut(piece) {
+            self.run(block, channels);
+        }
+    }
+
+    fn parameters(&self) -> &[ParameterInfo] {
+        &[]
+    }
+
+    fn get_parameter(&self, _id: ParamId) -> Option<ParamValue> {
+        None
+    }
+
+    fn set_parameter(&mut self, _id: ParamId, _value: ParamValue) -> bool {
+        false
+    }
+
+    fn tail_samples(&self) -> u32 {
+        self.slots
+            .iter()
+            .flatten()
+            .filter_map(|state| match &state.node {
+                Node::Effect(effect) => Some(effect.tail_samples()),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        let nodes: usize = self
+            .slots
+            .iter()
+            .flatten()
+            .map(|state| heap_bytes(&state.buffer) + state.node.memory_bytes())
+            .sum();
+        size_of_val(self) + heap_bytes(&self.slots) + nodes
+    }
+}
+
+impl fmt::Debug for GraphProcessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GraphProcessor")
+            .field("id", &self.id)
+            .field("enabled", &self.enabled)
+            .field("slots", &self.slots.iter().flatten().count())
+            .field("schedule", &self.schedule)
+            .field("block_samples", &self.block_samples)
+            .field("channels", &self.channels)
+            .field("updates", &self.updates)
+            .field("returned", &self.returned)
+            .finish()
+    }
+}
+
+fn mix(output: &mut [Sample], input: &[Sample], gain: f32) {
+    for (out, sample) in output.iter_mut().zip(input) {
+        *out = Sample::new(sample.value().mul_add(gain, out.value()));
+    }
+}
@@ -0,0 +1,82 @@
+//! Processing order for a graph
+//!
+//! Compiled on the control thread and run by the processor without
+//! further lookups.
+
+use crate::graph::{Connection, NodeId, NodeKind};
+
+/// A node to run and the nodes it sums its input from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub node: usize,
+    /// Source node index and linear gain of each incoming connection
+    pub inputs: Vec<(usize, f32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Schedule {
+    /// Every node, ordered so each runs after all of its inputs
+    pub steps: Vec<Step>,
+    /// Output nodes, summed into the processed block
+    pub outputs: Vec<usize>,
+}
+
+/// Orders the nodes with Kahn's algorithm. Ties go to the lower index, so
+/// the same graph always compiles to the same schedule.
+///
+/// Connections are checked for cycles as they are made, so every node ends
+/// up in the schedule.
+pub fn compile(nodes: &[Option<NodeKind>], connections: &[Connection]) -> Schedule {
+    let mut pending: Vec<usize> = vec![0; nodes.len()];
+    for connection in connections {
+        pending[connection.to.index()] += 1;
+    }
+
+    let mut ready: Vec<usize> = (0..nodes.len())
+        .rev()
+        .filter(|&index| nodes[index].is_some() && pending[index] == 0)
+        .collect();
+    let mut schedule = Schedule::default();
+    while let Some(index) = ready.pop() {
+        let inputs = connections
+            .iter()
+            .filter(|c| c.to.index() == index)
+            .map(|c| (c.from.index(), c.gain.as_linear()))
+            .collect();
+        schedule.steps.push(Step {
+            node: index,
+            inputs,
+        });
+        if nodes[index] == Some(NodeKind::Output) {
+            schedule.outputs.push(index);
+        }
+
+        for connection in connections.iter().filter(|c| c.from.index() == index) {
+            let to = connection.to.index();
+            pending[to] -= 1;
+            if pending[to] == 0 {
+                // Keep the lowest index on top of the stack
+                let at = ready.partition_point(|&other| other > to);
+                ready.insert(at, to);
+            }
+        }
+    }
+    schedule
+}
+
+/// Whether `to` can be reached from `from` by following connections
+pub fn reaches(connections: &[Connection], from: NodeId, to: NodeId) -> bool {
+    let mut stack = vec![from];
+    let mut visited = Vec::new();
+    while let Some(node) = stack.pop() {
+        if node == to {
+            return true;
+        }
+        if visited.contains(&node) {
+            continue;
+        }
+        visited.push(node);
+        stack.extend(connections.iter().filter(|c| c.from == node).map(|c| c.to));
+    }
+    false
+}
@@ -0,0 +1,424 @@
+//! AIFF file reading and writing
+//!
+//! [`AiffWriter`] streams interleaved samples to an AIFF file the way
+//! [`WavWriter`](crate::io::wav::WavWriter) does to a WAV file: the header
+//! goes out first with placeholder sizes and is patched when the writer is
+//! finalized (or dropped). Integer samples are written as plain big-endian
+//! AIFF; float samples need AIFF-C, with its `fl32`/`fl64` compression
+//! types.
+//!
+//! [`PcmReader::open_aiff`] reads AIFF and uncompressed AIFF-C files,
+//! including little-endian `sowt` ones, back as [`Sample`]s.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::pcm::{ByteOrder, PcmReader, write_ordered};
+use crate::types::{AudioFormat, BitDepth, ChannelCount, Sample, SampleRate};
+
+/// AIFF-C version the `FVER` chunk names
+const AIFC_VERSION: u32 = 0xA280_5140;
+
+/// Header length up to the sample data, for plain AIFF
+const AIFF_HEADER_LEN: u32 = 54;
+
+/// Header length up to the sample data, for AIFF-C
+const AIFC_HEADER_LEN: u32 = 72;
+
+/// Streams interleaved samples into an AIFF file.
+#[derive(Debug)]
+pub struct AiffWriter<W: Write + Seek> {
+    writer: W,
+    format: AudioFormat,
+    data_bytes: u32,
+    finalized: bool,
+}
+
+impl AiffWriter<BufWriter<File>> {
+    /// Creates (or truncates) an AIFF file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be created or the header can't be written.
+    pub fn create(path: impl AsRef<Path>, format: AudioFormat) -> Result<Self> {
+        let file = File::create(path)?;
+        Self::new(BufWriter::new(file), format)
+    }
+}
+
+impl<W: Write + Seek> AiffWriter<W> {
+    /// Wraps a writer and writes the AIFF header.
+    ///
+    /// # Errors
+    /// Returns an error if the header can't be written.
+    pub fn new(writer: W, format: AudioFormat) -> Result<Self> {
+        let mut aiff = Self {
+            writer,
+            format,
+            data_bytes: 0,
+            finalized: false,
+        };
+        aiff.write_header()?;
+        Ok(aiff)
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Number of complete frames written so far
+    #[must_use]
+    pub fn frames_written(&self) -> u64 {
+        u64::from(self.data_bytes / self.format.frame_size())
+    }
+
+    const fn header_len(&self) -> u32 {
+        if self.format.bit_depth.is_float() {
+            AIFC_HEADER_LEN
+        } else {
+            AIFF_HEADER_LEN
+        }
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let format = self.format;
+        let channels = u16::try_from(format.channels.count())
+            .map_err(|_| AudioEngineError::numeric_conversion("too many channels for AIFF"))?;
+        let bits = u16::try_from(format.bit_depth.bits())
+            .map_err(|_| AudioEngineError::numeric_conversion("bit depth out of range"))?;
+        let header_len = self.header_len();
+        let pad = self.data_bytes % 2;
+        let form_size = header_len - 8 + self.data_bytes + pad;
+        let frames = self.data_bytes / format.frame_size();
+
+        let w = &mut self.writer;
+        w.write_all(b"FORM")?;
+        w.write_all(&form_size.to_be_bytes())?;
+        if header_len == AIFC_HEADER_LEN {
+            let compression = match format.bit_depth {
+                BitDepth::F64 => b"fl64",
+                _ => b"fl32",
+            };
+            w.write_all(b"AIFC")?;
+            w.write_all(b"FVER")?;
+            w.write_all(&4u32.to_be_bytes())?;
+            w.write_all(&AIFC_VERSION.to_be_bytes())?;
+            w.write_all(b"COMM")?;
+            w.write_all(&24u32.to_be_bytes())?;
+            w.write_all(&channels.to_be_bytes())?;
+            w.write_all(&frames.to_be_bytes())?;
+            w.write_all(&bits.to_be_bytes())?;
+            w.write_all(&encode_rate(format.sample_rate.as_hz()))?;
+            w.write_all(compression)?;
+            // Empty compression name, padded to an even length
+            w.write_all(&[0, 0])?;
+        } else {
+            w.write_all(b"AIFF")?;
+            w.write_all(b"COMM")?;
+            w.write_all(&18u32.to_be_bytes())?;
+            w.write_all(&channels.to_be_bytes())?;
+            w.write_all(&frames.to_be_bytes())?;
+            w.write_all(&bits.to_be_bytes())?;
+            w.write_all(&encode_rate(format.sample_rate.as_hz()))?;
+        }
+        w.write_all(b"SSND")?;
+        w.write_all(&(8 + self.data_bytes).to_be_bytes())?;
+        // Offset and block size, both unused
+        w.write_all(&[0; 8])?;
+        Ok(())
+    }
+
+    /// Appends interleaved samples, converting to the file's bit depth.
+    ///
+    /// # Errors
+    /// Returns an error on I/O failure or if the file would exceed the 4 GiB
+    /// limit of its chunk sizes.
+    pub fn write_samples(&mut self, samples: &[Sample]) -> Result<()> {
+        let bytes_per_sample = self.format.bit_depth.bytes_per_sample();
+        let added = u32::try_from(samples.len())
+            .ok()
+            .and_then(|len| len.checked_mul(bytes_per_sample))
+            .and_then(|bytes| bytes.checked_add(self.data_bytes))
+            .filter(|&total| total < u32::MAX - self.header_len())
+            .ok_or_else(|| AudioEngineError::configuration("AIFF file would exceed 4 GiB"))?;
+
+        for &sample in samples {
+            write_ordered(
+                &mut self.writer,
+                sample,
+                self.format.bit_depth,
+                ByteOrder::Big,
+            )?;
+        }
+        self.data_bytes = added;
+        self.finalized = false;
+        Ok(())
+    }
+
+    /// Pads the sample data to an even length, patches the header sizes
+    /// and flushes.
+    ///
+    /// The writer is left positioned at the end of the sample data, so
+    /// further writes are still allowed until the next call.
+    ///
+    /// # Errors
+    /// Returns an error on I/O failure.
+    pub fn finalize(&mut self) -> Result<()> {
+        let data_end = u64::from(self.header_len() + self.data_bytes);
+        self.writer.seek(SeekFrom::Start(data_end))?;
+        if self.data_bytes % 2 == 1 {
+            // AIFF chunks are word aligned
+            self.writer.write_all(&[0])?;
+        }
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.seek(SeekFrom::Start(data_end))?;
+        self.writer.flush()?;
+        self.finalized = true;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for AiffWriter<W> {
+    fn drop(&mut self) {
+        if !self.finalized
+            && let Err(e) = self.finalize()
+        {
+            log::error!("Failed to finalize AIFF file: {e}");
+        }
+    }
+}
+
+impl PcmReader<BufReader<File>> {
+    /// Opens the AIFF or AIFF-C file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or isn't a supported AIFF file.
+    pub fn open_aiff(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        Self::new_aiff(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> PcmReader<R> {
+    /// Parses an AIFF header and positions the reader at the first sample.
+    ///
+    /// # Errors
+    /// Returns an error if the stream isn't an AIFF file, is compressed, or
+    /// its sample rate, channel count or sample size isn't supported by the
+    /// engine.
+    pub fn new_aiff(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        let compressed = match (&header[..4], &header[8..]) {
+            (b"FORM", b"AIFF") => false,
+            (b"FORM", b"AIFC") => true,
+            _ => {
+                return Err(AudioEngineError::UnsupportedFormat {
+                    format: "not an AIFF file".to_string(),
+                });
+            }
+        };
+
+        // The sample data may come before the COMM chunk
+        let mut common = None;
+        let mut sound = None;
+        let end = reader.seek(SeekFrom::End(0))?;
+        let mut offset = 12;
+        while offset + 8 <= end && (common.is_none() || sound.is_none()) {
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut chunk = [0u8; 8];
+            reader.read_exact(&mut chunk)?;
+            let size = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+            match &chunk[..4] {
+                b"COMM" => {
+                    let mut comm = vec![0u8; usize::try_from(size).unwrap_or(0)];
+                    reader.read_exact(&mut comm)?;
+                    common = Some(parse_comm(&comm, compressed)?);
+                }
+                b"SSND" => {
+                    let mut ssnd = [0u8; 4];
+                    reader.read_exact(&mut ssnd)?;
+                    let skip = u64::from(u32::from_be_bytes(ssnd));
+                    let start = offset + 16 + skip;
+                    sound = Some((start, u64::from(size).saturating_sub(8 + skip)));
+                }
+                _ => {}
+            }
+            offset += 8 + u64::from(size) + u64::from(size % 2);
+        }
+
+        let (Some((format, byte_order, frames)), Some((start, bytes))) = (common, sound) else {
+            return Err(AudioEngineError::UnsupportedFormat {
+                format: "AIFF file without COMM and SSND chunks".to_string(),
+            });
+        };
+        let bytes = bytes.min(u64::from(frames) * u64::from(format.frame_size()));
+        Self::region(reader, format, byte_order, start, bytes)
+    }
+}
+
+/// Parses a COMM chunk into the format, the byte order and the frame count.
+fn parse_comm(comm: &[u8], compressed: bool) -> Result<(AudioFormat, ByteOrder, u32)> {
+    let unsupported = |what: String| AudioEngineError::UnsupportedFormat { format: what };
+    if comm.len() < 18 || (compressed && comm.len() < 22) {
+        return Err(unsupported("truncated AIFF COMM chunk".to_string()));
+    }
+    let channels = ChannelCount::try_from(u32::from(u16::from_be_bytes([comm[0], comm[1]])))?;
+    let frames = u32::from_be_bytes([comm[2], comm[3], comm[4], comm[5]]);
+    let bits = u16::from_be_bytes([comm[6], comm[7]]);
+    let mut rate = [0u8; 10];
+    rate.copy_from_slice(&comm[8..18]);
+    let sample_rate = SampleRate::try_from(decode_rate(rate))?;
+    let compression: &[u8] = if compressed { &comm[18..22] } else { b"NONE" };
+    let (bit_depth, byte_order) = match (compression, bits) {
+        (b"NONE" | b"twos", 16) => (BitDepth::I16, ByteOrder::Big),
+        (b"NONE" | b"twos", 24) => (BitDepth::I24, ByteOrder::Big),
+        (b"NONE" | b"twos", 32) => (BitDepth::I32, ByteOrder::Big),
+        (b"sowt", 16) => (BitDepth::I16, ByteOrder::Little),
+        (b"sowt", 24) => (BitDepth::I24, ByteOrder::Little),
+        (b"sowt", 32) => (BitDepth::I32, ByteOrder::Little),
+        (b"fl32" | b"FL32", _) => (BitDepth::F32, ByteOrder::Big),
+        (b"fl64" | b"FL64", _) => (BitDepth::F64, ByteOrder::Big),
+        _ => {
+            return Err(unsupported(format!(
+                "AIFF compression {} with {bits} bits",
+                String::from_utf8_lossy(compression)
+            )));
+        }
+    };
+    Ok((
+        AudioFormat::new(sample_rate, channels, bit_depth),
+        byte_order,
+        frames,
+    ))
+}
+
+/// Encodes a sample rate as the 80 bit extended float AIFF stores it in.
+fn encode_rate(hz: u32) -> [u8; 10] {
+    let mut bytes = [0u8; 10];
+    if hz == 0 {
+        return bytes;
+    }
+    let shift = hz.leading_zeros();
+    // Exponent bias of 16383, and the mantissa's integer bit at the top
+    let exponent = u16::try_from(16383 + 31 - shift).unwrap_or(u16::MAX);
+    let mantissa = u64::from(hz << shift) << 32;
+    bytes[..2].copy_from_slice(&exponent.to_be_bytes());
+    bytes[2..].copy_from_slice(&mantissa.to_be_bytes());
+    bytes
+}
+
+/// Decodes an 80 bit extended float sample rate, rounded down to whole Hz.
+fn decode_rate(bytes: [u8; 10]) -> u32 {
+    let exponent = i32::from(u16::from_be_bytes([bytes[0], bytes[1]]) & 0x7FFF);
+    let mut mantissa = [0u8; 8];
+    mantissa.copy_from_slice(&bytes[2..]);
+    let mantissa = u64::from_be_bytes(mantissa);
+    let shift = 16383 + 63 - exponent;
+    if !(0..64).contains(&shift) {
+        return 0;
+    }
+    u32::try_from(mantissa >> shift).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[allow(clippy::cast_precision_loss)]
+    fn ramp(len: usize) -> Vec<Sample> {
+        (0..len)
+            .map(|index| Sample::new((index as f32 * 0.1).sin() * 0.8))
+            .collect()
+    }
+
+    fn encode(format: AudioFormat, samples: &[Sample]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut writer = AiffWriter::new(Cursor::new(&mut bytes), format).expect("header");
+        writer.write_samples(samples).expect("samples");
+        writer.finalize().expect("finalize");
+        drop(writer);
+        bytes
+    }
+
+    fn read_all(bytes: Vec<u8>, len: usize) -> (AudioFormat, Vec<Sample>) {
+        let mut reader = PcmReader::new_aiff(Cursor::new(bytes)).expect("reads back");
+        let mut read = vec![Sample::SILENCE; len];
+        assert_eq!(reader.read_samples(&mut read).expect("samples"), len);
+        (reader.format(), read)
+    }
+
+    #[test]
+    fn round_trips_every_bit_depth() {
+        // Within two steps of the integer formats; floats go through AIFC
+        for (bit_depth, tolerance) in [
+            (BitDepth::I16, 2.0 / 32_768.0),
+            (BitDepth::I24, 2.0 / 8_388_608.0),
+            (BitDepth::I32, 1e-7),
+            (BitDepth::F32, 0.0),
+        ] {
+            let format = AudioFormat::new(SampleRate::Hz44100, ChannelCount::Stereo, bit_depth);
+            let samples = ramp(200);
+            let (read_format, read) = read_all(encode(format, &samples), 200);
+            assert_eq!(read_format, format);
+            for (written, read) in samples.iter().zip(&read) {
+                assert!(
+                    (written.value() - read.value()).abs() <= tolerance,
+                    "{bit_depth:?}: {} read as {}",
+                    written.value(),
+                    read.value()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sample_rates_survive_the_extended_float() {
+        for rate in SampleRate::ALL {
+            assert_eq!(decode_rate(encode_rate(rate.as_hz())), rate.as_hz());
+        }
+        // 44100 Hz as every other AIFF writer stores it
+        assert_eq!(
+            encode_rate(44_100),
+            [0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(decode_rate([0; 10]), 0);
+        assert_eq!(decode_rate([0x7F, 0xFF, 0x80, 0, 0, 0, 0, 0, 0, 0]), 0);
+    }
+
+    #[test]
+    fn finds_sound_data_before_the_common_chunk() {
+        let format = AudioFormat::new(SampleRate::Hz48000, ChannelCount::Mono, BitDepth::I16);
+        let samples = ramp(10);
+        let aiff = encode(format, &samples);
+        // FORM header, then COMM of 8 + 18 bytes, then SSND
+        let mut swapped = aiff[..12].to_vec();
+        swapped.extend_from_slice(&aiff[38..]);
+        swapped.extend_from_slice(&aiff[12..38]);
+        let (read_format, read) = read_all(swapped, 10);
+        assert_eq!(read_format, format);
+        for (written, read) in samples.iter().zip(&read) {
+            assert!((written.value() - read.value()).abs() <= 2.0 / 32_768.0);
+        }
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_aiff() {
+        let mut bytes = b"FORM\0\0\0\0ILBM".to_vec();
+        bytes.extend_from_slice(&[0; 32]);
+        assert!(PcmReader::new_aiff(Cursor::new(bytes)).is_err());
+
+        let format = AudioFormat::new(SampleRate::Hz48000, ChannelCount::Mono, BitDepth::I16);
+        let aiff = encode(format, &ramp(10));
+        // A truncated COMM chunk and a missing SSND chunk
+        let mut truncated = aiff[..12].to_vec();
+        truncated.extend_from_slice(b"COMM\0\0\0\x04\0\x01\0\0");
+        assert!(PcmReader::new_aiff(Cursor::new(truncated)).is_err());
+        assert!(PcmReader::new_aiff(Cursor::new(aiff[..38].to_vec())).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Broadcast Wave metadata
+//!
+//! [`BroadcastInfo`] describes a recording the way broadcast tools expect
+//! to find it in a WAV file: a `bext` chunk (EBU Tech 3285) with the
+//! description, originator, origination date and time, the time reference
+//! of the first sample and the coding history, and an `iXML` chunk naming
+//! the tracks. [`WavWriter::create_broadcast`](crate::io::wav::WavWriter::create_broadcast)
+//! writes both ahead of the sample data, where readers look first.
+//!
+//! Unless set, the origination is when the file is created and the time
+//! reference its time of day, so takes line up on a timeline by wall clock.
+
+use std::fmt::Write as _;
+use std::time::SystemTime;
+
+use crate::scheduler::UtcDateTime;
+use crate::types::{AudioFormat, Timestamp};
+
+/// Fixed part of the `bext` chunk, before the coding history
+const BEXT_FIXED_LEN: usize = 602;
+
+/// `bext` version written; version 1 adds the UMID, left empty
+const BEXT_VERSION: u16 = 1;
+
+/// iXML version written
+const IXML_VERSION: &str = "2.10";
+
+/// Broadcast Wave metadata for a WAV file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BroadcastInfo {
+    /// Free description, up to 256 characters
+    pub description: String,
+    /// Who made the recording, up to 32 characters
+    pub originator: String,
+    /// The originator's reference for it, up to 32 characters
+    pub originator_reference: String,
+    /// When it was made; `None` for when the file is created
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub origination: Option<UtcDateTime>,
+    /// Samples since midnight of the first sample; `None` for the
+    /// origination's time of day
+    pub time_reference: Option<Timestamp>,
+    /// Lines of coding history, oldest first, e.g.
+    /// `A=PCM,F=48000,W=24,M=stereo,T=audio_engine`
+    pub coding_history: Vec<String>,
+    /// Names of the tracks, one per channel, for the `iXML` chunk
+    pub track_names: Vec<String>,
+}
+
+impl BroadcastInfo {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    #[must_use]
+    pub fn with_originator(mut self, originator: impl Into<String>) -> Self {
+        self.originator = originator.into();
+        self
+    }
+
+    #[must_use]
+    pub fn with_originator_reference(mut self, reference: impl Into<String>) -> Self {
+        self.originator_reference = reference.into();
+        self
+    }
+
+    #[must_use]
+    pub const fn with_origination(mut self, origination: UtcDateTime) -> Self {
+        self.origination = Some(origination);
+        self
+    }
+
+    /// Sets the time reference, the position of the first sample in
+    /// samples since midnight.
+    #[must_use]
+    pub const fn with_time_reference(mut self, time_reference: Timestamp) -> Self {
+        self.time_reference = Some(time_reference);
+        self
+    }
+
+    /// Appends a line to the coding history.
+    #[must_use]
+    pub fn with_coding_history(mut self, line: impl Into<String>) -> Self {
+        self.coding_history.push(line.into());
+        self
+    }
+
+    /// Names the tracks, one per channel.
+    #[must_use]
+    pub fn with_track_names<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.track_names = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// The info as it is written for a file created at `now`: the
+    /// origination and time reference filled in if they weren't set.
+    #[must_use]
+    pub fn resolved(&self, format: AudioFormat, now: SystemTime) -> Self {
+        let origination = self
+            .origination
+            .unwrap_or_else(|| UtcDateTime::from_system_time(now));
+        let time_reference = self.time_reference.unwrap_or_else(|| {
+            let seconds = u64::from(origination.hour) * 3600
+                + u64::from(origination.minute) * 60
+                + u64::from(origination.second);
+            Timestamp::from_samples(seconds * u64::from(format.sample_rate.as_hz()))
+        });
+        Self {
+            origination: Some(origination),
+            time_reference: Some(time_reference),
+            ..self.clone()
+        }
+    }
+
+    /// The `bext` and, with track names, `iXML` chunks, headers included,
+    /// each padded to an even length. Unresolved fields are written as
+    /// for a file created now.
+    #[must_use]
+    pub fn chunks(&self, format: AudioFormat) -> Vec<u8> {
+        let info = self.resolved(format, SystemTime::now());
+        let mut out = Vec::new();
+        append_chunk(&mut out, *b"bext", &info.bext());
+        if !info.track_names.is_empty() {
+            append_chunk(&mut out, *b"iXML", info.ixml(format).as_bytes());
+        }
+        out
+    }
+
+    /// The `bext` chunk's payload
+    fn bext(&self) -> Vec<u8> {
+        let mut bext = Vec::with_capacity(BEXT_FIXED_LEN);
+        append_text(&mut bext, &self.description, 256);
+        append_text(&mut bext, &self.originator, 32);
+        append_text(&mut bext, &self.originator_reference, 32);
+        let origination = self
+            .origination
+            .unwrap_or_else(|| UtcDateTime::from_system_time(SystemTime::UNIX_EPOCH));
+        let date = format!(
+            "{:04}-{:02}-{:02}",
+            origination.year, origination.month, origination.day
+        );
+        let time = format!(
+            "{:02}:{:02}:{:02}",
+            origination.hour, origination.minute, origination.second
+        );
+        append_text(&mut bext, &date, 10);
+        append_text(&mut bext, &time, 8);
+        let reference = self.time_reference.unwrap_or(Timestamp::ZERO).as_samples();
+        bext.extend_from_slice(&reference.to_le_bytes());
+        bext.extend_from_slice(&BEXT_VERSION.to_le_bytes());
+        // UMID, loudness and reserved fields
+        bext.resize(BEXT_FIXED_LEN, 0);
+        for line in &self.coding_history {
+            bext.extend(line.bytes().filter(u8::is_ascii));
+            bext.extend_from_slice(b"\r\n");
+        }
+        bext
+    }
+
+    /// The `iXML` chunk's payload
+    fn ixml(&self, format: AudioFormat) -> String {
+        let mut xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<BWFXML>\n\
+             <IXML_VERSION>{IXML_VERSION}</IXML_VERSION>\n"
+        );
+        if !self.description.is_empty() {
+            let _ = writeln!(xml, "<NOTE>{}</NOTE>", escape(&self.description));
+        }
+        let tracks = self.track_names.len().min(format.channels.count_usize());
+        let _ = writeln!(xml, "<TRACK_LIST>\n<TRACK_COUNT>{tracks}</TRACK_COUNT>");
+        for (index, name) in self.track_names.iter().take(tracks).enumerate() {
+            let _ = writeln!(
+                xml,
+                "<TRACK><CHANNEL_INDEX>{0}</CHANNEL_INDEX><INTERLEAVE_INDEX>{0}</INTERLEAVE_INDEX>\
+                 <NAME>{1}</NAME></TRACK>",
+                index + 1,
+                escape(name)
+            );
+        }
+        xml.push_str("</TRACK_LIST>\n</BWFXML>\n");
+        xml
+    }
+}
+
+/// Appends a RIFF chunk, padded to an even length.
+fn append_chunk(out: &mut Vec<u8>, id: [u8; 4], payload: &[u8]) {
+    out.extend_from_slice(&id);
+    out.extend_from_slice(
+        &u32::try_from(payload.len())
+            .unwrap_or(u32::MAX)
+            .to_le_bytes(),
+    );
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Appends `text` as an ASCII field of `len` bytes, cut off or padded with
+/// zeros.
+fn append_text(out: &mut Vec<u8>, text: &str, len: usize) {
+    let start = out.len();
+    out.extend(text.bytes().filter(u8::is_ascii).take(len));
+    out.resize(start + len, 0);
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,454 @@
+//! Block cache for file inputs
+//!
+//! A [`FileCache`] sits between a track's decode thread and its file. The
+//! file is read in fixed-size blocks on a loader thread of the cache's own;
+//! [`read`](FileCache::read) only copies from blocks already in memory and
+//! queues the ones it is missing, so a seek or a loop jump never blocks the
+//! caller on disk I/O. It returns fewer frames instead, and the caller
+//! tries again on its next pass.
+//!
+//! The transport can tell the cache where playback is about to go with
+//! [`hint`](FileCache::hint): the blocks after a seek target are loaded
+//! ahead of time, and the blocks of a loop region are pinned so that
+//! looping doesn't evict and reload them on every pass. Everything else is
+//! evicted least recently used first once the cache is full.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Seek};
+use std::ops::Range;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use flume::{Receiver, Sender};
+use parking_lot::Mutex;
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::pcm::PcmReader;
+use crate::io::wav::WavReader;
+use crate::types::{AudioFormat, Sample};
+
+/// Requests that can be queued for the loader thread
+const REQUEST_CAPACITY: usize = 256;
+
+/// Where audio for a [`FileCache`] comes from.
+pub trait BlockSource: Send + 'static {
+    fn format(&self) -> AudioFormat;
+
+    /// Total number of frames
+    fn frames(&self) -> u64;
+
+    /// Reads interleaved samples starting at frame `start` into `out`,
+    /// returning how many samples were read.
+    ///
+    /// # Errors
+    /// Returns an error if the source can't be read.
+    fn read_at(&mut self, start: u64, out: &mut [Sample]) -> Result<usize>;
+}
+
+impl<R: Read + Seek + Send + 'static> BlockSource for WavReader<R> {
+    fn format(&self) -> AudioFormat {
+        Self::format(self)
+    }
+
+    fn frames(&self) -> u64 {
+        Self::frames(self)
+    }
+
+    fn read_at(&mut self, start: u64, out: &mut [Sample]) -> Result<usize> {
+        self.seek_frame(start)?;
+        let mut read = 0;
+        while read < out.len() {
+            let count = self.read_samples(&mut out[read..])?;
+            if count == 0 {
+                break;
+            }
+            read += count;
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek + Send + 'static> BlockSource for PcmReader<R> {
+    fn format(&self) -> AudioFormat {
+        Self::format(self)
+    }
+
+    fn frames(&self) -> u64 {
+        Self::frames(self)
+    }
+
+    fn read_at(&mut self, start: u64, out: &mut [Sample]) -> Result<usize> {
+        self.seek_frame(start)?;
+        let mut read = 0;
+        while read < out.len() {
+            let count = self.read_samples(&mut out[read..])?;
+            if count == 0 {
+                break;
+            }
+            read += count;
+        }
+        Ok(read)
+    }
+}
+
+/// Sizes a [`FileCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheSettings {
+    /// Frames per block
+    pub block_frames: usize,
+    /// Blocks kept in memory, pinned loop blocks included
+    pub max_blocks: usize,
+    /// Blocks loaded ahead of each read and seek target
+    pub read_ahead_blocks: usize,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            block_frames: 32_768,
+            max_blocks: 64,
+            read_ahead_blocks: 4,
+        }
+    }
+}
+
+impl CacheSettings {
+    #[must_use]
+    pub const fn with_block_frames(mut self, frames: usize) -> Self {
+        self.block_frames = frames;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_max_blocks(mut self, blocks: usize) -> Self {
+        self.max_blocks = blocks;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_read_ahead(mut self, blocks: usize) -> Self {
+        self.read_ahead_blocks = blocks;
+        self
+    }
+}
+
+/// Where playback is about to go, from the transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefetchHint {
+    /// Playback will jump to this frame
+    Seek(u64),
+    /// Playback will loop over these frames; their blocks stay cached
+    /// until the loop is cleared
+    Loop { start: u64, end: u64 },
+    /// The loop region no longer applies
+    ClearLoop,
+}
+
+/// Hit and miss counts of a [`FileCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// Reads served entirely from memory
+    pub hits: u64,
+    /// Reads cut short by a block that wasn't loaded yet
+    pub misses: u64,
+    /// Blocks loaded from the source
+    pub loads: u64,
+    /// Blocks dropped to make room
+    pub evictions: u64,
+    /// Blocks the source failed to read
+    pub errors: u64,
+}
+
+struct Block {
+    samples: Vec<Sample>,
+    last_used: u64,
+}
+
+struct CacheState {
+    blocks: HashMap<u64, Block>,
+    /// Blocks queued for the loader and not loaded yet
+    requested: Vec<u64>,
+    pinned: Range<u64>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+/// Random access block cache over one file.
+pub struct FileCache {
+    format: AudioFormat,
+    frames: u64,
+    settings: CacheSettings,
+    shared: Arc<Mutex<CacheState>>,
+    requests: Sender<u64>,
+    loader: Option<JoinHandle<()>>,
+}
+
+impl FileCache {
+    /// Opens a WAV file behind a cache.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be opened or the loader thread
+    /// can't be started.
+    pub fn open_wav(path: impl AsRef<std::path::Path>, settings: CacheSettings) -> Result<Self> {
+        Self::new(WavReader::open(path)?, settings)
+    }
+
+    /// Starts a loader thread reading blocks from `source`.
+    ///
+    /// # Errors
+    /// Returns an error if the block size or cache size is zero, or the
+    /// loader thread can't be started.
+    pub fn new(source: impl BlockSource, settings: CacheSettings) -> Result<Self> {
+        if settings.block_frames == 0 || settings.max_blocks == 0 {
+            return Err(AudioEngineError::configuration(
+                "file cache needs a non-zero block size and cache size",
+            ));
+        }
+        let format = source.format();
+        let frames = source.frames();
+        let shared = Arc::new(Mutex::new(CacheState {
+            blocks: HashMap::with_capacity(settings.max_blocks),
+            requested: Vec::new(),
+            pinned: 0..0,
+            clock: 0,
+            stats: CacheStats::default(),
+        }));
+        let (requests, receiver) = flume::bounded(REQUEST_CAPACITY);
+        let loader = Loader {
+            source,
+            settings,
+            shared: Arc::clone(&shared),
+        };
+        let loader = thread::Builder::new()
+            .name("file-cache".to_string())
+            .spawn(move || loader.run(&receiver))
+            .map_err(|e| AudioEngineError::configuration(format!("can't start loader: {e}")))?;
+        Ok(Self {
+            format,
+            frames,
+            settings,
+            shared,
+            requests,
+            loader: Some(loader),
+        })
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Total number of frames in the file
+    #[must_use]
+    pub const fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> CacheSettings {
+        self.settings
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> CacheStats {
+        self.shared.lock().stats
+    }
+
+    /// Copies interleaved samples starting at `frame` into `out`, returning
+    /// how many frames were copied.
+    ///
+    /// Stops early at the end of the file or at the first block that isn't
+    /// loaded; that block and the ones after it are queued, and a later
+    /// read will find them. Never waits for the file.
+    pub fn read(&self, frame: u64, out: &mut [Sample]) -> usize {
+        let channels = self.format.channels.count_usize();
+        let block_frames = self.settings.block_frames as u64;
+        let wanted = (out.len() / channels)
+            .min(usize::try_from(self.frames.saturating_sub(frame)).unwrap_or(usize::MAX));
+
+        let mut shared = self.shared.lock();
+        shared.clock += 1;
+        let clock = shared.clock;
+        let mut copied = 0;
+        while copied < wanted {
+            let position = frame + copied as u64;
+            let index = position / block_frames;
+            let Some(block) = shared.blocks.get_mut(&index) else {
+                break;
+            };
+            block.last_used = clock;
+            let offset = usize::try_from(position % block_frames).unwrap_or(0) * channels;
+            let count =
+                (block.samples.len().saturating_sub(offset) / channels).min(wanted - copied);
+            if count == 0 {
+                break;
+            }
+            out[copied * channels..(copied + count) * channels]
+                .copy_from_slice(&block.samples[offset..offset + count * channels]);
+            copied += count;
+        }
+        if copied == wanted {
+            shared.stats.hits += 1;
+        } else {
+            shared.stats.misses += 1;
+        }
+        let next = frame + copied as u64;
+        self.request_from(&mut shared, next);
+        drop(shared);
+        copied
+    }
+
+    /// Prepares the cache for where playback is going next.
+    pub fn hint(&self, hint: PrefetchHint) {
+        let mut shared = self.shared.lock();
+        match hint {
+            PrefetchHint::Seek(frame) => self.request_from(&mut shared, frame),
+            PrefetchHint::Loop { start, end } => {
+                let block_frames = self.settings.block_frames as u64;
+                let first = start / block_frames;
+                // Leave room for read-ahead outside the loop
+                let room = self
+                    .settings
+                    .max_blocks
+                    .saturating_sub(self.settings.read_ahead_blocks)
+                    .max(1) as u64;
+                let last = end
+                    .min(self.frames)
+                    .div_ceil(block_frames)
+                    .min(first + room);
+                shared.pinned = first..last.max(first);
+                for index in first..last {
+                    self.request(&mut shared, index);
+                }
+            }
+            PrefetchHint::ClearLoop => shared.pinned = 0..0,
+        }
+        drop(shared);
+    }
+
+    /// Whether the blocks covering `frames` are all in memory
+    #[must_use]
+    pub fn is_cached(&self, frames: Range<u64>) -> bool {
+        let block_frames = self.settings.block_frames as u64;
+        let end = frames.end.min(self.frames);
+        if frames.start >= end {
+            return true;
+        }
+        let shared = self.shared.lock();
+        (frames.start / block_frames..end.div_ceil(block_frames))
+            .all(|index| shared.blocks.contains_key(&index))
+    }
+
+    /// Queues the block holding `frame` and the read-ahead after it.
+    fn request_from(&self, shared: &mut CacheState, frame: u64) {
+        let first = frame / self.settings.block_frames as u64;
+        for index in first..=first + self.settings.read_ahead_blocks as u64 {
+            self.request(shared, index);
+        }
+    }
+
+    fn request(&self, shared: &mut CacheState, index: u64) {
+        let block_frames = self.settings.block_frames as u64;
+        if index * block_frames >= self.frames
+            || shared.blocks.contains_key(&index)
+            || shared.requested.contains(&index)
+        {
+            return;
+        }
+        // A full queue drops the request; the next read asks again
+        if self.requests.try_send(index).is_ok() {
+            shared.requested.push(index);
+        }
+    }
+}
+
+impl Drop for FileCache {
+    fn drop(&mut self) {
+        // Disconnect the loader so it finishes, then wait for it
+        let (disconnected, _) = flume::bounded(0);
+        self.requests = disconnected;
+        if let Some(loader) = self.loader.take()
+            && loader.join().is_err()
+        {
+            log::error!("File cache loader panicked");
+        }
+    }
+}
+
+impl fmt::Debug for FileCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shared = self.shared.lock();
+        f.debug_struct("FileCache")
+            .field("format", &self.format)
+            .field("frames", &self.frames)
+            .field("settings", &self.settings)
+            .field("cached_blocks", &shared.blocks.len())
+            .field("pinned", &shared.pinned)
+            .field("stats", &shared.stats)
+            .field("requests", &self.requests.len())
+            .field("loader", &self.loader.is_some())
+            .finish()
+    }
+}
+
+/// Loader thread state
+struct Loader<S> {
+    source: S,
+    settings: CacheSettings,
+    shared: Arc<Mutex<CacheState>>,
+}
+
+impl<S: BlockSource> Loader<S> {
+    fn run(mut self, requests: &Receiver<u64>) {
+        let channels = self.source.format().channels.count_usize();
+        let block_samples = self.settings.block_frames * channels;
+        while let Ok(index) = requests.recv() {
+            let mut samples = vec![Sample::SILENCE; block_samples];
+            let start = index * self.settings.block_frames as u64;
+            // The file is read without holding the lock
+            let read = self.source.read_at(start, &mut samples);
+
+            let mut shared = self.shared.lock();
+            shared.requested.retain(|&requested| requested != index);
+            match read {
+                Ok(count) => {
+                    samples.truncate(count - count % channels);
+                    shared.stats.loads += 1;
+                    let clock = shared.clock;
+                    shared.blocks.insert(
+                        index,
+                        Block {
+                            samples,
+                            last_used: clock,
+                        },
+                    );
+                    self.evict(&mut shared, index);
+                }
+                Err(e) => {
+                    shared.stats.errors += 1;
+                    log::error!("File cache failed to read block {index}: {e}");
+                }
+            }
+            drop(shared);
+        }
+    }
+
+    /// Drops least recently used blocks until the cache fits, keeping
+    /// pinned blocks and the block just loaded.
+    fn evict(&self, shared: &mut CacheState, loaded: u64) {
+        while shared.blocks.len() > self.settings.max_blocks {
+            let victim = shared
+                .blocks
+                .iter()
+                .filter(|&(&index, _)| index != loaded && !shared.pinned.contains(&index))
+                .min_by_key(|&(&index, block)| (block.last_used, index))
+                .map(|(&index, _)| index);
+            let Some(victim) = victim else {
+                break;
+            };
+            shared.blocks.remove(&victim);
+            shared.stats.evictions += 1;
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! CAF file reading and writing
+//!
+//! [`CafWriter`] streams interleaved samples to a Core Audio Format file as
+//! little-endian linear PCM. The `data` chunk is written with the "size
+//! unknown" marker CAF allows for a last chunk and patched with the real
+//! size when the writer is finalized (or dropped), so even a file that was
+//! never finalized stays readable. Unlike WAV and AIFF there is no 4 GiB
+//! limit.
+//!
+//! [`PcmReader::open_caf`] reads linear PCM CAF files of either byte order
+//! back as [`Sample`]s.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::pcm::{ByteOrder, PcmReader};
+use crate::io::wav::write_sample;
+use crate::types::{AudioFormat, BitDepth, ChannelCount, Sample, SampleRate};
+
+/// `desc` format flag for float samples
+const FLAG_FLOAT: u32 = 1;
+
+/// `desc` format flag for little-endian samples
+const FLAG_LITTLE_ENDIAN: u32 = 2;
+
+/// Header length up to the sample data
+const HEADER_LEN: u64 = 68;
+
+/// Streams interleaved samples into a CAF file.
+#[derive(Debug)]
+pub struct CafWriter<W: Write + Seek> {
+    writer: W,
+    format: AudioFormat,
+    data_bytes: u64,
+    finalized: bool,
+}
+
+impl CafWriter<BufWriter<File>> {
+    /// Creates (or truncates) a CAF file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be created or the header can't be written.
+    pub fn create(path: impl AsRef<Path>, format: AudioFormat) -> Result<Self> {
+        let file = File::create(path)?;
+        Self::new(BufWriter::new(file), format)
+    }
+}
+
+impl<W: Write + Seek> CafWriter<W> {
+    /// Wraps a writer and writes the CAF header.
+    ///
+    /// # Errors
+    /// Returns an error if the header can't be written.
+    pub fn new(writer: W, format: AudioFormat) -> Result<Self> {
+        let mut caf = Self {
+            writer,
+            format,
+            data_bytes: 0,
+            finalized: false,
+        };
+        caf.write_header(-1)?;
+        Ok(caf)
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Number of complete frames written so far
+    #[must_use]
+    pub fn frames_written(&self) -> u64 {
+        self.data_bytes / u64::from(self.format.frame_size())
+    }
+
+    fn write_header(&mut self, data_size: i64) -> Result<()> {
+        let format = self.format;
+        let flags = if format.bit_depth.is_float() {
+            FLAG_FLOAT | FLAG_LITTLE_ENDIAN
+        } else {
+            FLAG_LITTLE_ENDIAN
+        };
+
+        let w = &mut self.writer;
+        w.write_all(b"caff")?;
+        // File version 1, no flags
+        w.write_all(&1u16.to_be_bytes())?;
+        w.write_all(&0u16.to_be_bytes())?;
+        w.write_all(b"desc")?;
+        w.write_all(&32i64.to_be_bytes())?;
+        w.write_all(&f64::from(format.sample_rate.as_hz()).to_be_bytes())?;
+        w.write_all(b"lpcm")?;
+        w.write_all(&flags.to_be_bytes())?;
+        w.write_all(&format.frame_size().to_be_bytes())?;
+        // Frames per packet
+        w.write_all(&1u32.to_be_bytes())?;
+        w.write_all(&format.channels.count().to_be_bytes())?;
+        w.write_all(&format.bit_depth.bits().to_be_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&data_size.to_be_bytes())?;
+        // Edit count
+        w.write_all(&0u32.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Appends interleaved samples, converting to the file's bit depth.
+    ///
+    /// # Errors
+    /// Returns an error on I/O failure.
+    pub fn write_samples(&mut self, samples: &[Sample]) -> Result<()> {
+        for &sample in samples {
+            write_sample(&mut self.writer, sample, self.format.bit_depth)?;
+        }
+        self.data_bytes +=
+            samples.len() as u64 * u64::from(self.format.bit_depth.bytes_per_sample());
+        self.finalized = false;
+        Ok(())
+    }
+
+    /// Patches the size of the data chunk and flushes.
+    ///
+    /// The writer is left positioned at the end of the sample data, so
+    /// further writes are still allowed until the next call.
+    ///
+    /// # Errors
+    /// Returns an error on I/O failure.
+    pub fn finalize(&mut self) -> Result<()> {
+        let data_size = i64::try_from(self.data_bytes + 4)
+            .map_err(|_| AudioEngineError::numeric_conversion("CAF data too large"))?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header(data_size)?;
+        self.writer
+            .seek(SeekFrom::Start(HEADER_LEN + self.data_bytes))?;
+        self.writer.flush()?;
+        self.finalized = true;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for CafWriter<W> {
+    fn drop(&mut self) {
+        if !self.finalized
+            && let Err(e) = self.finalize()
+        {
+            log::error!("Failed to finalize CAF file: {e}");
+        }
+    }
+}
+
+impl PcmReader<BufReader<File>> {
+    /// Opens the CAF file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or isn't a supported CAF file.
+    pub fn open_caf(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        Self::new_caf(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> PcmReader<R> {
+    /// Parses a CAF header and positions the reader at the first sample.
+    ///
+    /// # Errors
+    /// Returns an error if the stream isn't a CAF file, isn't linear PCM,
+    /// or its sample rate, channel count or sample size isn't supported by
+    /// the engine.
+    pub fn new_caf(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != b"caff" {
+            return Err(AudioEngineError::UnsupportedFormat {
+                format: "not a CAF file".to_string(),
+            });
+        }
+
+        let mut description = None;
+        loop {
+            let mut chunk = [0u8; 12];
+            reader.read_exact(&mut chunk)?;
+            let mut size = [0u8; 8];
+            size.copy_from_slice(&chunk[4..]);
+            let size = i64::from_be_bytes(size);
+            match &chunk[..4] {
+                b"desc" => {
+                    let mut desc = [0u8; 32];
+                    reader.read_exact(&mut desc)?;
+                    description = Some(parse_desc(&desc)?);
+                    reader.seek(SeekFrom::Current(size - 32))?;
+                }
+                b"data" => {
+                    let (format, byte_order) =
+                        description.ok_or_else(|| AudioEngineError::UnsupportedFormat {
+                            format: "CAF data chunk before desc chunk".to_string(),
+                        })?;
+                    // Skip the edit count
+                    let start = reader.stream_position()? + 4;
+                    // A size of -1 runs to the end of the file
+                    let bytes = u64::try_from(size - 4).unwrap_or(u64::MAX);
+                    return Self::region(reader, format, byte_order, start, bytes);
+                }
+                _ => {
+                    reader.seek(SeekFrom::Current(size))?;
+                }
+            }
+        }
+    }
+}
+
+/// Parses a `desc` chunk into the format and byte order of the samples.
+fn parse_desc(desc: &[u8; 32]) -> Result<(AudioFormat, ByteOrder)> {
+    let unsupported = |what: String| AudioEngineError::UnsupportedFormat { format: what };
+    let read_u32 =
+        |at: usize| u32::from_be_bytes([desc[at], desc[at + 1], desc[at + 2], desc[at + 3]]);
+    if &desc[8..12] != b"lpcm" {
+        return Err(unsupported(format!(
+            "CAF format {}",
+            String::from_utf8_lossy(&desc[8..12])
+        )));
+    }
+    let mut rate = [0u8; 8];
+    rate.copy_from_slice(&desc[..8]);
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let sample_rate = SampleRate::try_from(f64::from_be_bytes(rate).round() as u32)?;
+    let flags = read_u32(12);
+    let channels = ChannelCount::try_from(read_u32(24))?;
+    let bits = read_u32(28);
+    let bit_depth = match (flags & FLAG_FLOAT != 0, bits) {
+        (false, 16) => BitDepth::I16,
+        (false, 24) => BitDepth::I24,
+        (false, 32) => BitDepth::I32,
+        (true, 32) => BitDepth::F32,
+        (true, 64) => BitDepth::F64,
+        (float, bits) => {
+            let kind = if float { "float" } else { "integer" };
+            return Err(unsupported(format!("CAF {kind} samples of {bits} bits")));
+        }
+    };
+    let format = AudioFormat::new(sample_rate, channels, bit_depth);
+    if read_u32(16) != format.frame_size() {
+        return Err(unsupported("packed or padded CAF samples".to_string()));
+    }
+    let byte_order = if flags & FLAG_LITTLE_ENDIAN == 0 {
+        ByteOrder::Big
+    } else {
+        ByteOrder::Little
+    };
+    Ok((format, byte_order))
+}
@@ -0,0 +1,271 @@
+//! Named markers in audio files
+//!
+//! A [`MarkerList`] holds named positions in a file, kept in order. It is
+//! written into a WAV file as `cue ` and `LIST/adtl` label chunks with
+//! [`WavWriter::add_markers`] (or [`FileOutput::with_markers`] when
+//! rendering), and read back from one with [`MarkerList::read_wav`].
+//!
+//! During playback a [`MarkerCursor`] turns the position as it moves on
+//! into the markers crossed on the way; [`PlaybackHandle`] uses one to
+//! report them as [`EngineFeedback::MarkerReached`].
+//!
+//! [`FileOutput::with_markers`]: crate::io::FileOutput::with_markers
+//! [`PlaybackHandle`]: crate::engine::simple::PlaybackHandle
+//! [`EngineFeedback::MarkerReached`]: crate::channel::EngineFeedback::MarkerReached
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::wav::WavWriter;
+use crate::recorder::Marker;
+use crate::types::Timestamp;
+
+/// A named position in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CueMarker {
+    /// Position from the start of the file
+    pub position: Timestamp,
+    pub name: String,
+}
+
+/// Named markers, in order of position.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarkerList {
+    markers: Vec<CueMarker>,
+}
+
+impl MarkerList {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a marker, after any already at the same position.
+    pub fn add(&mut self, position: Timestamp, name: impl Into<String>) {
+        let at = self
+            .markers
+            .partition_point(|marker| marker.position <= position);
+        self.markers.insert(
+            at,
+            CueMarker {
+                position,
+                name: name.into(),
+            },
+        );
+    }
+
+    #[must_use]
+    pub fn with_marker(mut self, position: Timestamp, name: impl Into<String>) -> Self {
+        self.add(position, name);
+        self
+    }
+
+    /// Removes and returns the marker at `index`, if there is one.
+    pub fn remove(&mut self, index: usize) -> Option<CueMarker> {
+        (index < self.markers.len()).then(|| self.markers.remove(index))
+    }
+
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<&CueMarker> {
+        self.markers.get(index)
+    }
+
+    /// The first marker with `name`
+    #[must_use]
+    pub fn find(&self, name: &str) -> Option<&CueMarker> {
+        self.markers.iter().find(|marker| marker.name == name)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, CueMarker> {
+        self.markers.iter()
+    }
+
+    /// Index of the first marker at or after `position`
+    #[must_use]
+    pub fn index_from(&self, position: Timestamp) -> usize {
+        self.markers
+            .partition_point(|marker| marker.position < position)
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.markers.len()
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+
+    /// Reads the cue points of the WAV file at `path`, named by their
+    /// labels. Cue points without a label are named by their number.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or isn't a WAV file.
+    pub fn read_wav(path: impl AsRef<Path>) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+            return Err(AudioEngineError::UnsupportedFormat {
+                format: "not a RIFF/WAVE file".to_string(),
+            });
+        }
+
+        let end = reader.seek(SeekFrom::End(0))?;
+        let mut offset = 12;
+        // (cue id, position in frames)
+        let mut cues = Vec::new();
+        let mut labels = Vec::new();
+        while offset + 8 <= end {
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut chunk = [0u8; 8];
+            reader.read_exact(&mut chunk)?;
+            let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+            if matches!(&chunk[..4], b"cue " | b"LIST") {
+                let mut payload = vec![0u8; usize::try_from(size).unwrap_or(0)];
+                reader.read_exact(&mut payload)?;
+                if &chunk[..4] == b"cue " {
+                    // Count, then id, play order, chunk id, chunk start,
+                    // block start and sample offset for each point
+                    cues.extend(payload.get(4..).unwrap_or_default().chunks_exact(24).map(
+                        |point| {
+                            let read = |at: usize| {
+                                u32::from_le_bytes([
+                                    point[at],
+                                    point[at + 1],
+                                    point[at + 2],
+                                    point[at + 3],
+                                ])
+                            };
+                            (read(0), read(20))
+                        },
+                    ));
+                } else if payload.starts_with(b"adtl") {
+                    read_labels(&payload[4..], &mut labels);
+                }
+            }
+            offset += 8 + u64::from(size) + u64::from(size % 2);
+        }
+
+        let mut list = Self::new();
+        for (id, position) in cues {
+            let name = labels
+                .iter()
+                .find(|(label_id, _)| *label_id == id)
+                .map_or_else(|| format!("Marker {id}"), |(_, name)| name.clone());
+            list.add(Timestamp::from_samples(u64::from(position)), name);
+        }
+        Ok(list)
+    }
+}
+
+impl<'a> IntoIterator for &'a MarkerList {
+    type Item = &'a CueMarker;
+    type IntoIter = std::slice::Iter<'a, CueMarker>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.markers.iter()
+    }
+}
+
+impl FromIterator<CueMarker> for MarkerList {
+    fn from_iter<I: IntoIterator<Item = CueMarker>>(iter: I) -> Self {
+        let mut markers: Vec<_> = iter.into_iter().collect();
+        markers.sort_by_key(|marker| marker.position);
+        Self { markers }
+    }
+}
+
+/// The markers of a recorded take
+impl From<&[Marker]> for MarkerList {
+    fn from(markers: &[Marker]) -> Self {
+        markers
+            .iter()
+            .map(|marker| CueMarker {
+                position: Timestamp::from_samples(marker.position),
+                name: marker.name.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Reads the `labl` entries of a `LIST/adtl` chunk, past its list type.
+fn read_labels(mut list: &[u8], labels: &mut Vec<(u32, String)>) {
+    while list.len() >= 12 {
+        let size = u32::from_le_bytes([list[4], list[5], list[6], list[7]]) as usize;
+        let Some(body) = list.get(8..8 + size) else {
+            return;
+        };
+        if &list[..4] == b"labl" && body.len() >= 4 {
+            let id = u32::from_le_bytes([body[0], body[1], body[2], body[3]]);
+            let text = String::from_utf8_lossy(&body[4..]);
+            labels.push((id, text.trim_end_matches('\0').to_string()));
+        }
+        list = list.get(8 + size + size % 2..).unwrap_or_default();
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Adds every marker in `markers` as a cue point, written out on the
+    /// next [`finalize`](Self::finalize).
+    ///
+    /// # Errors
+    /// Returns an error if a marker is past the 2^32 frames a cue point can
+    /// address.
+    pub fn add_markers(&mut self, markers: &MarkerList) -> Result<()> {
+        for marker in markers {
+            let position = u32::try_from(marker.position.as_samples()).map_err(|_| {
+                AudioEngineError::numeric_conversion("marker too far into a WAV file")
+            })?;
+            self.add_cue(position, marker.name.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Follows a playback position through a [`MarkerList`], reporting the
+/// markers it crosses.
+#[derive(Debug, Clone, Default)]
+pub struct MarkerCursor {
+    markers: MarkerList,
+    /// Index of the next marker to be crossed
+    next: usize,
+}
+
+impl MarkerCursor {
+    /// Starts at the start of the file.
+    #[must_use]
+    pub const fn new(markers: MarkerList) -> Self {
+        Self { markers, next: 0 }
+    }
+
+    #[must_use]
+    pub const fn markers(&self) -> &MarkerList {
+        &self.markers
+    }
+
+    /// Moves to `position`, returning the index of each marker at or
+    /// after the last position and before this one. Moving backwards, e.g.
+    /// after a seek or loop, crosses nothing and rearms the markers after
+    /// `position`.
+    pub fn advance(&mut self, position: Timestamp) -> std::ops::Range<usize> {
+        let reached = self.markers.index_from(position);
+        if reached < self.next {
+            self.next = reached;
+            return reached..reached;
+        }
+        let crossed = self.next..reached;
+        self.next = reached;
+        crossed
+    }
+
+    /// Jumps to `position` without crossing anything on the way.
+    pub fn seek(&mut self, position: Timestamp) {
+        self.next = self.markers.index_from(position);
+    }
+}
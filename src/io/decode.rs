@@ -0,0 +1,323 @@
+//! Audio file decoding
+//!
+//! [`AudioFileDecoder`] decodes the file of a [`FileInput`] with symphonia
+//! into interleaved [`Sample`]s at the file's own sample rate, whichever
+//! [`AudioFileFormat`] it is in. It starts at the input's
+//! `start_position` and, if the input loops, goes back to the start of the
+//! file at its end instead of running out. Speed changes are left to the
+//! reader, e.g. a [`TimeStretcher`](crate::dsp::time_stretch::TimeStretcher).
+//!
+//! ```no_run
+//! use audio_engine::io::FileInput;
+//! use audio_engine::io::decode::AudioFileDecoder;
+//! use audio_engine::types::Sample;
+//!
+//! let mut decoder = AudioFileDecoder::open(&FileInput::new("set.flac").with_loop())?;
+//! let mut block = vec![Sample::SILENCE; 512 * decoder.channels().count_usize()];
+//! let read = decoder.read_samples(&mut block)?;
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! Needs the `symphonia` feature.
+
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use symphonia::core::audio::{Channels, SampleBuffer};
+use symphonia::core::codecs::{CODEC_TYPE_NULL, Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::input::{AudioFileFormat, FileInput};
+use crate::types::{AudioFormat, BitDepth, ChannelCount, Sample, SampleRate};
+
+/// Decodes an audio file into interleaved samples.
+pub struct AudioFileDecoder {
+    path: PathBuf,
+    file_format: AudioFileFormat,
+    reader: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    sample_rate: u32,
+    channels: ChannelCount,
+    /// Length in frames, if the container says
+    frames: Option<u64>,
+    looping: bool,
+    /// Decoded samples not yet read, from `pending_start` on
+    pending: Vec<Sample>,
+    pending_start: usize,
+    decoded: Option<SampleBuffer<f32>>,
+    /// Frames still to drop after an accurate seek landed early
+    skip: u64,
+    /// Frame the next sample read belongs to
+    position: u64,
+    finished: bool,
+}
+
+impl AudioFileDecoder {
+    /// Opens the file of `input` and seeks to its start position.
+    ///
+    /// # Errors
+    /// Returns an error if the file doesn't exist, isn't in one of the
+    /// [`AudioFileFormat`]s, has no audio track the decoder knows, or has
+    /// more channels than a [`ChannelCount`] allows.
+    pub fn open(input: &FileInput) -> Result<Self> {
+        let file_format = input
+            .format()
+            .ok_or_else(|| AudioEngineError::UnsupportedFormat {
+                format: input.extension().unwrap_or("no extension").to_string(),
+            })?;
+        let file = File::open(&input.path).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => AudioEngineError::FileNotFound {
+                path: input.path.clone(),
+            },
+            _ => e.into(),
+        })?;
+        let stream = MediaSourceStream::new(Box::new(file), MediaSourceStreamOptions::default());
+        let mut hint = Hint::new();
+        hint.with_extension(file_format.extension());
+        let format_options = FormatOptions {
+            enable_gapless: true,
+            ..FormatOptions::default()
+        };
+        let probed = symphonia::default::get_probe()
+            .format(&hint, stream, &format_options, &MetadataOptions::default())
+            .map_err(|e| decode_error(&input.path, e))?;
+        let reader = probed.format;
+        let track = reader
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| AudioEngineError::UnsupportedFormat {
+                format: format!("{} has no audio track", input.path.display()),
+            })?;
+        let params = &track.codec_params;
+        let decoder = symphonia::default::get_codecs()
+            .make(params, &DecoderOptions::default())
+            .map_err(|e| decode_error(&input.path, e))?;
+        let sample_rate =
+            params
+                .sample_rate
+                .ok_or_else(|| AudioEngineError::UnsupportedFormat {
+                    format: format!("{} has no sample rate", input.path.display()),
+                })?;
+        let channel_count = params.channels.map_or(0, Channels::count);
+        let channels = ChannelCount::try_from(u32::try_from(channel_count).unwrap_or(u32::MAX))?;
+
+        let mut decoder = Self {
+            path: input.path.clone(),
+            file_format,
+            track_id: track.id,
+            frames: params.n_frames,
+            reader,
+            decoder,
+            sample_rate,
+            channels,
+            looping: input.looping,
+            pending: Vec::new(),
+            pending_start: 0,
+            decoded: None,
+            skip: 0,
+            position: 0,
+            finished: false,
+        };
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let start = (input.start_position.max(0.0) * f64::from(sample_rate)) as u64;
+        if start > 0 {
+            decoder.seek_frame(start)?;
+        }
+        Ok(decoder)
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[must_use]
+    pub const fn file_format(&self) -> AudioFileFormat {
+        self.file_format
+    }
+
+    /// The file's sample rate in Hz, which the samples are read at
+    #[must_use]
+    pub const fn sample_rate_hz(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[must_use]
+    pub const fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    /// The format the samples are read in.
+    ///
+    /// # Errors
+    /// Returns an error if the file's sample rate isn't a [`SampleRate`].
+    pub fn format(&self) -> Result<AudioFormat> {
+        Ok(AudioFormat {
+            sample_rate: SampleRate::try_from(self.sample_rate)?,
+            channels: self.channels,
+            bit_depth: BitDepth::F32,
+        })
+    }
+
+    /// Length of the file in frames, if the container records it
+    #[must_use]
+    pub const fn frames(&self) -> Option<u64> {
+        self.frames
+    }
+
+    /// Frame of the file the next sample read comes from
+    #[must_use]
+    pub const fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Whether the end of a file that doesn't loop has been read
+    #[must_use]
+    pub const fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Moves to a frame of the file, sample accurately.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be seeked.
+    pub fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        // The formats decoded here all count timestamps in frames
+        let seeked = self
+            .reader
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::TimeStamp {
+                    ts: frame,
+                    track_id: self.track_id,
+                },
+            )
+            .map_err(|e| decode_error(&self.path, e))?;
+        self.decoder.reset();
+        self.pending.clear();
+        self.pending_start = 0;
+        self.skip = seeked.required_ts.saturating_sub(seeked.actual_ts);
+        self.position = seeked.required_ts;
+        self.finished = false;
+        Ok(())
+    }
+
+    /// Fills `out` with the next interleaved samples, whole frames only.
+    /// Returns the number of samples written, which is short of `out` only
+    /// at the end of a file that doesn't loop, and zero once it is over.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or decoded.
+    pub fn read_samples(&mut self, out: &mut [Sample]) -> Result<usize> {
+        let channel_count = self.channels.count_usize();
+        let wanted = out.len() / channel_count * channel_count;
+        let mut written = 0;
+        while written < wanted {
+            if self.pending_start == self.pending.len() && !self.decode_next()? {
+                break;
+            }
+            let available = &self.pending[self.pending_start..];
+            let count = available.len().min(wanted - written);
+            out[written..written + count].copy_from_slice(&available[..count]);
+            self.pending_start += count;
+            self.position += (count / channel_count) as u64;
+            written += count;
+        }
+        Ok(written)
+    }
+
+    /// Decodes the next packet of the track into the pending samples.
+    /// Returns false at the end of a file that doesn't loop.
+    fn decode_next(&mut self) -> Result<bool> {
+        loop {
+            if self.finished {
+                return Ok(false);
+            }
+            let packet = match self.reader.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                    // Nothing read since the last wrap means an empty file, which
+                    // would loop forever
+                    if self.looping && self.position > 0 {
+                        self.seek_frame(0)?;
+                        continue;
+                    }
+                    self.finished = true;
+                    return Ok(false);
+                }
+                Err(SymphoniaError::ResetRequired) => {
+                    self.decoder.reset();
+                    continue;
+                }
+                Err(e) => return Err(decode_error(&self.path, e)),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let buffer = match self.decoder.decode(&packet) {
+                Ok(buffer) => buffer,
+                Err(SymphoniaError::DecodeError(message)) => {
+                    log::warn!("Skipping bad packet in {}: {message}", self.path.display());
+                    continue;
+                }
+                Err(e) => return Err(decode_error(&self.path, e)),
+            };
+            let needed = buffer.capacity() * buffer.spec().channels.count();
+            let decoded = match &mut self.decoded {
+                Some(decoded) if decoded.capacity() >= needed => decoded,
+                slot => slot.insert(SampleBuffer::new(buffer.capacity() as u64, *buffer.spec())),
+            };
+            decoded.copy_interleaved_ref(buffer);
+
+            let channel_count = self.channels.count_usize();
+            let samples = decoded.samples();
+            let skip = usize::try_from(self.skip)
+                .unwrap_or(usize::MAX)
+                .min(samples.len() / channel_count);
+            self.skip -= skip as u64;
+            self.pending.clear();
+            self.pending.extend(
+                samples[skip * channel_count..]
+                    .iter()
+                    .map(|&value| Sample::new(value)),
+            );
+            self.pending_start = 0;
+            if !self.pending.is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for AudioFileDecoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AudioFileDecoder")
+            .field("path", &self.path)
+            .field("file_format", &self.file_format)
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("position", &self.position)
+            .field("looping", &self.looping)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) fn decode_error(path: &Path, error: SymphoniaError) -> AudioEngineError {
+    match error {
+        SymphoniaError::IoError(e) => e.into(),
+        SymphoniaError::Unsupported(what) => AudioEngineError::UnsupportedFormat {
+            format: format!("{}: {what}", path.display()),
+        },
+        e => AudioEngineError::UnsupportedFormat {
+            format: format!("{}: {e}", path.display()),
+        },
+    }
+}
@@ -0,0 +1,316 @@
+//! External encoder processes
+//!
+//! The MP3 and Opus encoders, the SRT and WHIP senders and the RTMP
+//! publisher run a command line encoder and feed it little-endian PCM, 16
+//! bit unless asked otherwise, on its stdin. [`EncoderProcess`] holds what
+//! they share: the process, a ring buffer the audio side pushes into
+//! without blocking, and a worker thread that drains the ring into the
+//! process.
+
+use std::fmt;
+use std::io::ErrorKind;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::buffer::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::error::{AudioEngineError, Result};
+#[cfg(any(feature = "srt", feature = "whip"))]
+use crate::io::output::OpusSettings;
+use crate::io::pcm::PcmWriter;
+use crate::types::{AudioFormat, BitDepth, Sample};
+
+/// Seconds of audio the ring buffer holds for the encoder
+const BUFFER_SECONDS: usize = 2;
+
+/// How long the worker waits when there is nothing to encode
+const IDLE_WAIT: Duration = Duration::from_millis(5);
+
+/// Where the encoder writes what it encodes
+pub enum EncoderOutput {
+    /// A file named in the encoder's arguments
+    #[cfg_attr(
+        not(any(feature = "mp3", feature = "opus", feature = "srt", feature = "whip")),
+        allow(dead_code)
+    )]
+    File,
+    /// The encoder's stdout, handed back to the caller
+    #[cfg_attr(
+        not(any(feature = "mp3", feature = "opus", feature = "rtmp")),
+        allow(dead_code)
+    )]
+    Pipe,
+}
+
+/// State the worker shares with the writing side
+#[derive(Default)]
+struct Shared {
+    /// Set once no more samples are coming
+    done: AtomicBool,
+    /// Set if the encoder stopped taking samples
+    failed: AtomicBool,
+    /// Samples dropped because the buffer was full
+    dropped: AtomicU64,
+}
+
+/// A running encoder process and the thread feeding it.
+pub struct EncoderProcess {
+    program: &'static str,
+    buffer: RingBufferWriter<Sample>,
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<Result<()>>>,
+}
+
+impl EncoderProcess {
+    /// Starts `program` with `args` on input in `format`. With
+    /// [`EncoderOutput::Pipe`] its stdout is returned too.
+    #[cfg_attr(
+        not(any(feature = "mp3", feature = "opus", feature = "whip", feature = "rtmp")),
+        allow(dead_code)
+    )]
+    pub fn spawn(
+        program: &'static str,
+        args: &[String],
+        format: AudioFormat,
+        output: &EncoderOutput,
+    ) -> Result<(Self, Option<ChildStdout>)> {
+        Self::spawn_with_depth(program, args, format, output, BitDepth::I16)
+    }
+
+    /// Like [`spawn`](Self::spawn), feeding the encoder little-endian PCM
+    /// of `bit_depth` instead of 16 bit.
+    pub fn spawn_with_depth(
+        program: &'static str,
+        args: &[String],
+        format: AudioFormat,
+        output: &EncoderOutput,
+        bit_depth: BitDepth,
+    ) -> Result<(Self, Option<ChildStdout>)> {
+        let stdout = match output {
+            EncoderOutput::File => Stdio::null(),
+            EncoderOutput::Pipe => Stdio::piped(),
+        };
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(stdout)
+            .spawn()
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => AudioEngineError::configuration(format!(
+                    "encoding needs `{program}` on the PATH"
+                )),
+                _ => e.into(),
+            })?;
+        let Some(stdin) = child.stdin.take() else {
+            return Err(AudioEngineError::configuration("encoder has no stdin"));
+        };
+        let stdout = child.stdout.take();
+
+        let capacity =
+            format.sample_rate.as_hz() as usize * BUFFER_SECONDS * format.channels.count_usize();
+        let (writer, reader) = RingBuffer::new(capacity);
+        let shared = Arc::new(Shared::default());
+        let worker = Worker {
+            program,
+            buffer: reader,
+            pcm: PcmWriter::new(stdin, bit_depth),
+            child,
+            shared: Arc::clone(&shared),
+            block: vec![Sample::SILENCE; capacity / 8],
+        };
+        let worker = thread::Builder::new()
+            .name(format!("{program}-encoder"))
+            .spawn(move || worker.run())?;
+        let process = Self {
+            program,
+            buffer: writer,
+            shared,
+            worker: Some(worker),
+        };
+        Ok((process, stdout))
+    }
+
+    /// Samples dropped so far because the encoder fell behind
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Share of the ring queued for the encoder, from 0 to 1; samples are
+    /// dropped once it reaches 1
+    #[cfg_attr(
+        not(any(feature = "mp3", feature = "opus", feature = "rtmp")),
+        allow(dead_code)
+    )]
+    pub fn backlog(&self) -> f32 {
+        let capacity = self.buffer.capacity().max(1);
+        #[allow(clippy::cast_precision_loss)]
+        let backlog = (capacity - self.buffer.slots().min(capacity)) as f32 / capacity as f32;
+        backlog
+    }
+
+    /// Whether the encoder stopped taking samples, e.g. because it exited
+    pub fn has_failed(&self) -> bool {
+        self.shared.failed.load(Ordering::Relaxed)
+    }
+
+    /// Queues samples without blocking, returning how many were queued.
+    /// The rest are dropped.
+    pub fn write(&mut self, samples: &[Sample]) -> usize {
+        let pushed = self.buffer.push_slice(samples);
+        if pushed < samples.len() {
+            self.shared
+                .dropped
+                .fetch_add((samples.len() - pushed) as u64, Ordering::Relaxed);
+        }
+        pushed
+    }
+
+    /// Queues all of `samples`, waiting for room rather than dropping any.
+    pub fn write_all(&mut self, samples: &[Sample]) -> Result<()> {
+        let mut remaining = samples;
+        while !remaining.is_empty() {
+            if self.has_failed() {
+                return Err(AudioEngineError::configuration(format!(
+                    "{} stopped encoding",
+                    self.program
+                )));
+            }
+            let pushed = self.buffer.push_slice(remaining);
+            if pushed == 0 {
+                thread::sleep(IDLE_WAIT);
+            }
+            remaining = &remaining[pushed..];
+        }
+        Ok(())
+    }
+
+    /// Encodes what is still queued and waits for the encoder to exit.
+    pub fn close(&mut self) -> Result<()> {
+        self.shared.done.store(true, Ordering::Release);
+        match self.worker.take() {
+            Some(worker) => worker.join().map_err(|_| {
+                AudioEngineError::configuration(format!("{} worker panicked", self.program))
+            })?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for EncoderProcess {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::error!("{} failed: {e}", self.program);
+        }
+    }
+}
+
+impl fmt::Debug for EncoderProcess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncoderProcess")
+            .field("program", &self.program)
+            .field("dropped", &self.dropped())
+            .field("failed", &self.has_failed())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Worker thread state
+struct Worker {
+    program: &'static str,
+    buffer: RingBufferReader<Sample>,
+    pcm: PcmWriter<ChildStdin>,
+    child: Child,
+    shared: Arc<Shared>,
+    block: Vec<Sample>,
+}
+
+impl Worker {
+    fn run(mut self) -> Result<()> {
+        let encoded = self.encode();
+        if encoded.is_err() {
+            self.shared.failed.store(true, Ordering::Relaxed);
+        }
+        // Closing stdin ends the stream; the encoder then writes the rest
+        // of its output and exits
+        let Self {
+            pcm,
+            mut child,
+            program,
+            ..
+        } = self;
+        let flushed = pcm.into_inner().map(drop);
+        let status = child.wait()?;
+        encoded?;
+        flushed?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(AudioEngineError::configuration(format!(
+                "{program} exited with {status}"
+            )))
+        }
+    }
+
+    /// Feeds queued samples to the encoder until the writing side is done
+    /// and the buffer is empty.
+    fn encode(&mut self) -> Result<()> {
+        loop {
+            // Read before the buffer so nothing pushed before `done` is missed
+            let done = self.shared.done.load(Ordering::Acquire);
+            let count = self.buffer.pop_slice(&mut self.block);
+            if count > 0 {
+                self.pcm.write_samples(&self.block[..count])?;
+            } else if done {
+                return Ok(());
+            } else {
+                thread::sleep(IDLE_WAIT);
+            }
+        }
+    }
+}
+
+/// ffmpeg options reading little-endian PCM of `bit_depth`, 16 or 24 bit,
+/// in `format` from stdin.
+#[cfg(any(feature = "srt", feature = "whip", feature = "rtmp"))]
+pub fn ffmpeg_input_args(format: AudioFormat, bit_depth: BitDepth) -> Vec<String> {
+    let sample_format = if bit_depth == BitDepth::I24 {
+        "s24le"
+    } else {
+        "s16le"
+    };
+    let mut args: Vec<String> = ["-hide_banner", "-nostdin", "-loglevel", "error", "-f"]
+        .map(str::to_string)
+        .into();
+    args.push(sample_format.to_string());
+    args.push("-ar".to_string());
+    args.push(format.sample_rate.as_hz().to_string());
+    args.push("-ac".to_string());
+    args.push(format.channels.count().to_string());
+    args.extend(["-i", "-"].map(str::to_string));
+    args
+}
+
+/// ffmpeg options encoding Opus with `settings`. Opus runs at 48 kHz, so
+/// other rates are resampled.
+#[cfg(any(feature = "srt", feature = "whip"))]
+pub fn ffmpeg_opus_args(settings: OpusSettings) -> Vec<String> {
+    let bitrate = format!("{}k", settings.bitrate.as_kbps());
+    let frame = settings.frame_size.as_ms().to_string();
+    let complexity = settings.complexity.to_string();
+    [
+        "-c:a",
+        "libopus",
+        "-b:a",
+        bitrate.as_str(),
+        "-frame_duration",
+        frame.as_str(),
+        "-compression_level",
+        complexity.as_str(),
+        "-ar",
+        "48000",
+    ]
+    .map(str::to_string)
+    .into()
+}
@@ -0,0 +1,277 @@
+//! Test signal generation
+//!
+//! A [`SignalSource`] generates the signal a [`SignalGenerator`] describes:
+//! tones, band-limited square, saw and triangle waves, white, pink and
+//! brown noise, and sweeps. It is a graph [`Source`], and the engine runs
+//! one in place of a device when built with an
+//! [`InputSource::Signal`](crate::io::InputSource::Signal), so a chain can
+//! be tested end to end without any hardware. Offline renders and
+//! previews take sweeps as input, which end.
+//!
+//! Square and saw waves are corrected with `PolyBLEP` and triangle waves
+//! with `PolyBLAMP` around their corners, which keeps the aliasing of a
+//! naive waveform out of measurements. Noise comes from a seeded
+//! [`Rng`], so the same seed always gives the same noise.
+//!
+//! ```
+//! use audio_engine::graph::Source;
+//! use audio_engine::io::SignalSource;
+//! use audio_engine::io::input::SignalGenerator;
+//! use audio_engine::types::{ChannelCount, Sample, SampleRate};
+//!
+//! let mut source = SignalSource::new(
+//!     SignalGenerator::Triangle { frequency_hz: 440.0 },
+//!     SampleRate::Hz48000,
+//! );
+//! let mut block = vec![Sample::SILENCE; 512];
+//! source.render(&mut block, ChannelCount::Stereo);
+//! ```
+
+use std::f64::consts::TAU;
+
+use crate::dsp::random::{PinkNoise, Rng};
+use crate::graph::Source;
+use crate::io::input::{SignalGenerator, SweepCurve};
+use crate::types::{ChannelCount, Gain, Sample, SampleRate};
+
+/// Seed of the noise unless reseeded
+const DEFAULT_SEED: u32 = 0x9E37_79B9;
+
+/// Brown noise: how much of each white sample is added to the walk, and
+/// the scale that brings the walk up to about the level of white noise
+const BROWN_STEP: f32 = 0.02;
+const BROWN_SCALE: f32 = 3.5;
+
+/// Highest frequency a sweep reaches, as a fraction of the sample rate
+const SWEEP_LIMIT: f64 = 0.45;
+
+/// Generates a test signal, the same on every channel.
+#[derive(Debug, Clone)]
+pub struct SignalSource {
+    signal: SignalGenerator,
+    gain: Gain,
+    sample_rate: SampleRate,
+    seed: u32,
+    /// Position in the waveform's cycle, from 0 to 1
+    phase: f64,
+    /// Frames generated since the start
+    position: u64,
+    white: Rng,
+    pink: PinkNoise,
+    /// Where the brown noise's random walk has got to
+    brown: f32,
+}
+
+impl SignalSource {
+    #[must_use]
+    pub const fn new(signal: SignalGenerator, sample_rate: SampleRate) -> Self {
+        Self {
+            signal,
+            gain: Gain::UNITY,
+            sample_rate,
+            seed: DEFAULT_SEED,
+            phase: 0.0,
+            position: 0,
+            white: Rng::new(DEFAULT_SEED),
+            pink: PinkNoise::new(Rng::new(DEFAULT_SEED)),
+            brown: 0.0,
+        }
+    }
+
+    /// Scales the signal, which otherwise peaks around full scale.
+    #[must_use]
+    pub const fn with_gain(mut self, gain: Gain) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    #[must_use]
+    pub const fn signal(&self) -> SignalGenerator {
+        self.signal
+    }
+
+    #[must_use]
+    pub const fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    /// Frames generated since the start or the last reset
+    #[must_use]
+    pub const fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Whether a signal that ends, a sweep, has ended
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.signal
+            .frames(self.sample_rate)
+            .is_some_and(|frames| self.position >= frames)
+    }
+
+    /// The next sample of the signal, before the gain
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn next_sample(&mut self) -> f32 {
+        let value = match self.signal {
+            SignalGenerator::Silence => 0.0,
+            SignalGenerator::Sine { frequency_hz } => {
+                let value = (TAU * self.phase).sin() as f32;
+                self.advance(f64::from(frequency_hz));
+                value
+            }
+            SignalGenerator::Square { frequency_hz } => {
+                let step = self.step(f64::from(frequency_hz));
+                let value = square(self.phase, step);
+                self.advance(f64::from(frequency_hz));
+                value
+            }
+            SignalGenerator::Saw { frequency_hz } => {
+                let step = self.step(f64::from(frequency_hz));
+                let value = saw(self.phase, step);
+                self.advance(f64::from(frequency_hz));
+                value
+            }
+            SignalGenerator::Triangle { frequency_hz } => {
+                let step = self.step(f64::from(frequency_hz));
+                let value = triangle(self.phase, step);
+                self.advance(f64::from(frequency_hz));
+                value
+            }
+            SignalGenerator::WhiteNoise => self.white.next_bipolar(),
+            SignalGenerator::PinkNoise => self.pink.next_sample(),
+            SignalGenerator::BrownNoise => {
+                let white = self.white.next_bipolar();
+                self.brown = BROWN_STEP.mul_add(white, self.brown) / (1.0 + BROWN_STEP);
+                self.brown * BROWN_SCALE
+            }
+            SignalGenerator::Sweep { .. } => self.sweep(),
+        };
+        self.position += 1;
+        value
+    }
+
+    /// The sweep's next sample, or silence once it has ended
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn sweep(&mut self) -> f32 {
+        let SignalGenerator::Sweep {
+            start_hz,
+            end_hz,
+            curve,
+            ..
+        } = self.signal
+        else {
+            return 0.0;
+        };
+        let Some(frames) = self.signal.frames(self.sample_rate) else {
+            return 0.0;
+        };
+        if self.position >= frames {
+            return 0.0;
+        }
+        let limit = f64::from(self.sample_rate.as_hz()) * SWEEP_LIMIT;
+        let start = f64::from(start_hz).clamp(1.0, limit);
+        let end = f64::from(end_hz).clamp(1.0, limit);
+        let progress = self.position as f64 / frames as f64;
+        let frequency = match curve {
+            SweepCurve::Linear => (end - start).mul_add(progress, start),
+            SweepCurve::Exponential => start * (end / start).powf(progress),
+        };
+        let value = (TAU * self.phase).sin() as f32;
+        self.advance(frequency);
+        value
+    }
+
+    /// Cycles per sample at `frequency_hz`
+    fn step(&self, frequency_hz: f64) -> f64 {
+        (frequency_hz / f64::from(self.sample_rate.as_hz())).clamp(0.0, 0.5)
+    }
+
+    fn advance(&mut self, frequency_hz: f64) {
+        self.phase = (self.phase + self.step(frequency_hz)).fract();
+    }
+}
+
+impl Source for SignalSource {
+    fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        let _ = channels;
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    /// Starts the signal again from the beginning, with the same noise.
+    fn reset(&mut self) {
+        self.phase = 0.0;
+        self.position = 0;
+        self.white = Rng::new(self.seed);
+        self.pink = PinkNoise::new(Rng::new(self.seed));
+        self.brown = 0.0;
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.seed = u32::try_from(seed & u64::from(u32::MAX)).unwrap_or(0);
+        self.white = Rng::new(self.seed);
+        self.pink = PinkNoise::new(Rng::new(self.seed));
+    }
+
+    fn render(&mut self, output: &mut [Sample], channels: ChannelCount) {
+        let gain = self.gain.as_linear();
+        for frame in output.chunks_mut(channels.count_usize().max(1)) {
+            frame.fill(Sample::new(gain * self.next_sample()));
+        }
+    }
+}
+
+/// The `PolyBLEP` residual `t` cycles after a step of +2, for a waveform
+/// moving `step` cycles per sample
+fn blep(t: f64, step: f64) -> f64 {
+    if t < step {
+        let t = t / step - 1.0;
+        -t * t
+    } else if t > 1.0 - step {
+        let t = (t - 1.0) / step + 1.0;
+        t * t
+    } else {
+        0.0
+    }
+}
+
+/// The `PolyBLAMP` residual `t` cycles after the slope rises by 8 per cycle
+fn blamp(t: f64, step: f64) -> f64 {
+    if t < step {
+        let t = t / step - 1.0;
+        -t * t * t / 3.0
+    } else if t > 1.0 - step {
+        let t = (t - 1.0) / step + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
+/// High for the first half of the cycle, then low
+#[allow(clippy::cast_possible_truncation)]
+fn square(phase: f64, step: f64) -> f32 {
+    let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+    (naive + blep(phase, step) - blep((phase + 0.5).fract(), step)) as f32
+}
+
+/// Rising from zero through the cycle, dropping halfway through
+#[allow(clippy::cast_possible_truncation)]
+fn saw(phase: f64, step: f64) -> f32 {
+    let t = (phase + 0.5).fract();
+    (2.0f64.mul_add(t, -1.0) - blep(t, step)) as f32
+}
+
+/// Rising from zero to a peak a quarter of the way through the cycle, and
+/// a trough three quarters of the way
+#[allow(clippy::cast_possible_truncation)]
+fn triangle(phase: f64, step: f64) -> f32 {
+    let naive = match phase * 4.0 {
+        y if y >= 3.0 => y - 4.0,
+        y if y > 1.0 => 2.0 - y,
+        y => y,
+    };
+    let trough = (phase + 0.25).fract();
+    let peak = (phase + 0.75).fract();
+    (4.0 * step).mul_add(blamp(trough, step) - blamp(peak, step), naive) as f32
+}
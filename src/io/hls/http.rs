@@ -0,0 +1,160 @@
+//! Minimal HTTP/1.1 client for playlists and segments
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use super::playlist::resolve;
+use crate::error::{AudioEngineError, Result};
+use crate::types::StreamUrl;
+
+const USER_AGENT: &str = concat!("audio_engine/", env!("CARGO_PKG_VERSION"));
+
+/// Redirects followed before giving up
+const MAX_REDIRECTS: usize = 5;
+
+/// Largest body read, well beyond any playlist or segment
+const MAX_BODY: usize = 64 * 1024 * 1024;
+
+/// Fetches `url` and returns its body.
+///
+/// # Errors
+/// Returns an error if the url isn't a plain `http://` one, the server
+/// can't be reached within `timeout`, or it answers with anything but a
+/// success after following redirects.
+pub fn get(url: &str, timeout: Duration) -> Result<Vec<u8>> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        match get_once(&url, timeout)? {
+            Response::Body(body) => return Ok(body),
+            Response::Redirect(location) => url = resolve(&url, &location),
+        }
+    }
+    Err(AudioEngineError::NetworkConnection {
+        message: format!("{url} redirects more than {MAX_REDIRECTS} times"),
+    })
+}
+
+enum Response {
+    Body(Vec<u8>),
+    Redirect(String),
+}
+
+fn get_once(url: &str, timeout: Duration) -> Result<Response> {
+    if url.starts_with("https://") {
+        return Err(AudioEngineError::configuration(format!(
+            "{url}: HTTPS isn't supported, serve the stream over http:// or through a TLS proxy"
+        )));
+    }
+    let parsed = StreamUrl::parse(url)?;
+    let address = parsed.to_socket_addr()?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let request = format!(
+        "GET /{} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: {USER_AGENT}\r\nAccept: */*\r\n\
+         Connection: close\r\n\r\n",
+        parsed.path(),
+        parsed.host(),
+        parsed.port()
+    );
+    stream.write_all(request.as_bytes())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status: u16 = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| AudioEngineError::NetworkConnection {
+            message: format!("{url}: not an HTTP response: {:?}", line.trim()),
+        })?;
+    let mut length = None;
+    let mut chunked = false;
+    let mut location = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => length = value.parse::<usize>().ok(),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            "location" => location = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    match status {
+        200..=299 => {}
+        300..=399 if location.is_some() => {
+            return Ok(Response::Redirect(location.unwrap_or_default()));
+        }
+        _ => {
+            return Err(AudioEngineError::NetworkConnection {
+                message: format!("{url}: HTTP {status}"),
+            });
+        }
+    }
+    // The body grows as it arrives rather than trusting the length up front
+    let mut body = Vec::new();
+    if chunked {
+        read_chunked(&mut reader, &mut body)?;
+    } else if let Some(length) = length {
+        if length > MAX_BODY {
+            return Err(too_large(url));
+        }
+        reader.take(length as u64).read_to_end(&mut body)?;
+        if body.len() < length {
+            return Err(AudioEngineError::NetworkConnection {
+                message: format!("{url}: body ended after {} of {length} bytes", body.len()),
+            });
+        }
+    } else {
+        reader.take(MAX_BODY as u64 + 1).read_to_end(&mut body)?;
+        if body.len() > MAX_BODY {
+            return Err(too_large(url));
+        }
+    }
+    Ok(Response::Body(body))
+}
+
+/// Reads a chunked body to its last, empty chunk.
+fn read_chunked(reader: &mut impl BufRead, body: &mut Vec<u8>) -> Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size =
+            usize::from_str_radix(size, 16).map_err(|_| AudioEngineError::NetworkConnection {
+                message: format!("bad chunk size {size:?}"),
+            })?;
+        if size == 0 {
+            return Ok(());
+        }
+        let start = body.len();
+        let end = start
+            .checked_add(size)
+            .filter(|&end| end <= MAX_BODY)
+            .ok_or_else(|| AudioEngineError::NetworkConnection {
+                message: format!("chunked body over {MAX_BODY} bytes"),
+            })?;
+        body.resize(end, 0);
+        reader.read_exact(&mut body[start..])?;
+        // The line break after the chunk
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+}
+
+fn too_large(url: &str) -> AudioEngineError {
+    AudioEngineError::NetworkConnection {
+        message: format!("{url}: body over {MAX_BODY} bytes"),
+    }
+}
@@ -0,0 +1,694 @@
+//! HTTP Live Streaming input
+//!
+//! An [`HlsStream`] plays the `http://` url of an HLS [`NetworkInput`]. It
+//! fetches the playlist (following a master playlist to its default audio
+//! rendition, or else its highest bandwidth variant), and a worker thread
+//! downloads the segments in order, decodes them and fills a buffer that
+//! [`read`](HlsStream::read) plays from. Reads play silence until
+//! `buffer_ms` of audio is buffered, at the start and again after an
+//! underrun.
+//!
+//! A live playlist is refreshed every target duration and played from
+//! about three target durations behind its end, as RFC 8216 asks; an
+//! on-demand one (`#EXT-X-ENDLIST`) is played from the start to the end.
+//! Segments that fail to download are retried while `auto_reconnect` is
+//! on, and skipped after that.
+//!
+//! Every segment is decoded on its own and converted to the format asked
+//! for, so `#EXT-X-DISCONTINUITY` may change the codec, sample rate or
+//! channels. After a discontinuity, a skipped segment, or the playlist
+//! starting over, the audio fades in briefly rather than clicking. Sample
+//! rates are converted by linear interpolation, so ask for the stream's own
+//! rate where it is known.
+//!
+//! MP3 segments are decoded with symphonia. AAC, MPEG-TS and fragmented
+//! MP4 segments are handed to `ffmpeg`, which must then be on the PATH.
+//! Encrypted segments and `https://` urls aren't supported.
+//!
+//! ```no_run
+//! use audio_engine::io::NetworkInput;
+//! use audio_engine::io::hls::HlsStream;
+//! use audio_engine::types::{AudioFormat, Sample, StreamUrl};
+//!
+//! let input = NetworkInput::new(StreamUrl::parse("http://radio.example/live.m3u8")?)
+//!     .with_buffer_ms(3000);
+//! let mut stream = HlsStream::open(&input, AudioFormat::default())?;
+//! let mut block = vec![Sample::SILENCE; 1024];
+//! stream.read(&mut block);
+//! println!("{}", stream.stats());
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! Needs the `symphonia` feature.
+
+mod http;
+pub mod playlist;
+
+use std::fmt;
+use std::io::{Cursor, ErrorKind, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use symphonia::core::audio::{Channels, SampleBuffer};
+use symphonia::core::codecs::{CODEC_TYPE_NULL, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::buffer::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::error::{AudioEngineError, Result};
+use crate::io::decode::decode_error;
+use crate::io::input::NetworkInput;
+use crate::types::{AudioFormat, NetworkProtocol, Sample};
+use playlist::{MediaPlaylist, Playlist, Segment, resolve};
+
+/// How long a playlist or segment download may stall
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Downloads tried per segment or playlist refresh when reconnecting
+const FETCH_ATTEMPTS: usize = 3;
+
+/// Wait before trying a failed download again
+const RETRY_WAIT: Duration = Duration::from_millis(500);
+
+/// Target durations a live stream is played behind its end
+const LIVE_EDGE_TARGETS: u32 = 3;
+
+/// Longest the worker sleeps before checking whether to stop
+const IDLE_WAIT: Duration = Duration::from_millis(20);
+
+/// Fade in after a break in the audio
+const FADE_MS: u32 = 10;
+
+/// Decodes segments symphonia can't
+const FFMPEG_PROGRAM: &str = "ffmpeg";
+
+/// Statistics of an HLS stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HlsStats {
+    /// Segments played
+    pub segments: u64,
+    /// Breaks in the audio: discontinuities, skipped segments and restarts
+    pub discontinuities: u64,
+    /// Failed downloads and decodes
+    pub errors: u64,
+    /// Times the buffer ran dry and playing paused to fill it again
+    pub underruns: u64,
+    /// Audio buffered now
+    pub buffered: Duration,
+    /// Whether the playlist is still growing
+    pub live: bool,
+}
+
+impl fmt::Display for HlsStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} segments, {} discontinuities, {} errors, {} underruns, {:.1} s buffered{}",
+            self.segments,
+            self.discontinuities,
+            self.errors,
+            self.underruns,
+            self.buffered.as_secs_f64(),
+            if self.live { ", live" } else { "" }
+        )
+    }
+}
+
+/// State the worker shares with the reading side
+#[derive(Default)]
+struct Shared {
+    stop: AtomicBool,
+    /// Set once the worker has pushed everything it ever will
+    finished: AtomicBool,
+    /// Set if it stopped because of an error
+    failed: AtomicBool,
+    live: AtomicBool,
+    segments: AtomicU64,
+    discontinuities: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// Plays an HLS stream through a buffer filled on a worker thread.
+pub struct HlsStream {
+    input: NetworkInput,
+    format: AudioFormat,
+    /// Url of the media playlist played
+    media_url: String,
+    buffer: RingBufferReader<Sample>,
+    shared: Arc<Shared>,
+    /// Samples buffered before playing starts
+    preroll: usize,
+    priming: bool,
+    underruns: u64,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl HlsStream {
+    /// Fetches the playlist of `input` and starts buffering its segments,
+    /// converted to `format`.
+    ///
+    /// # Errors
+    /// Returns an error if the url isn't an `http://` one, the playlist
+    /// can't be fetched or parsed, its segments are encrypted, it has
+    /// none, or the worker thread can't be started.
+    pub fn open(input: &NetworkInput, format: AudioFormat) -> Result<Self> {
+        if input.url.protocol() != NetworkProtocol::HLS {
+            return Err(AudioEngineError::configuration(format!(
+                "{} isn't an HLS url",
+                input.url
+            )));
+        }
+        let (media_url, playlist) = load_media_playlist(input.url.as_str())?;
+        if playlist.segments.is_empty() && playlist.ended {
+            return Err(AudioEngineError::UnsupportedFormat {
+                format: format!("{media_url} has no segments"),
+            });
+        }
+
+        let channels = format.channels.count_usize();
+        let preroll =
+            format.sample_rate.samples_for_milliseconds(input.buffer_ms) as usize * channels;
+        // Room for the pre-roll and a second more, so the worker can take
+        // a segment in while the reader catches up
+        let one_second = format.sample_rate.as_hz() as usize * channels;
+        let (writer, reader) = RingBuffer::new(preroll + one_second);
+
+        let shared = Arc::new(Shared {
+            live: AtomicBool::new(!playlist.ended),
+            ..Shared::default()
+        });
+        let worker = Worker {
+            media_url: media_url.clone(),
+            next: start_sequence(&playlist),
+            playlist,
+            broken: false,
+            retry: input.auto_reconnect,
+            format,
+            buffer: writer,
+            shared: Arc::clone(&shared),
+            init: None,
+            refreshed: Instant::now(),
+            stale: false,
+        };
+        let worker = thread::Builder::new()
+            .name("hls-input".to_string())
+            .spawn(move || worker.run())?;
+        log::info!("Playing HLS from {media_url}");
+        Ok(Self {
+            input: input.clone(),
+            format,
+            media_url,
+            buffer: reader,
+            shared,
+            preroll: preroll.max(channels),
+            priming: true,
+            underruns: 0,
+            worker: Some(worker),
+        })
+    }
+
+    #[must_use]
+    pub const fn input(&self) -> &NetworkInput {
+        &self.input
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Url of the media playlist played, after following a master
+    /// playlist
+    #[must_use]
+    pub fn media_url(&self) -> &str {
+        &self.media_url
+    }
+
+    /// Fills `out` with the next interleaved samples, returning how many
+    /// came from the stream. The rest are silence: while the buffer fills
+    /// up to `buffer_ms` at the start and after an underrun, and after
+    /// the end of a stream that ended.
+    pub fn read(&mut self, out: &mut [Sample]) -> usize {
+        let finished = self.shared.finished.load(Ordering::Acquire);
+        if self.priming {
+            if self.buffer.slots() < self.preroll && !finished {
+                out.fill(Sample::SILENCE);
+                return 0;
+            }
+            self.priming = false;
+        }
+        let read = self.buffer.pop_slice(out);
+        if read < out.len() {
+            out[read..].fill(Sample::SILENCE);
+            if !finished {
+                self.underruns += 1;
+                self.priming = true;
+            }
+        }
+        read
+    }
+
+    /// Whether the stream ended and everything buffered has been read
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.shared.finished.load(Ordering::Acquire) && self.buffer.is_empty()
+    }
+
+    /// Whether the worker gave up, e.g. because the playlist couldn't be
+    /// refreshed without `auto_reconnect`
+    #[must_use]
+    pub fn has_failed(&self) -> bool {
+        self.shared.failed.load(Ordering::Acquire)
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> HlsStats {
+        let channels = self.format.channels.count_usize();
+        #[allow(clippy::cast_precision_loss)]
+        let buffered =
+            (self.buffer.slots() / channels) as f64 / f64::from(self.format.sample_rate.as_hz());
+        HlsStats {
+            segments: self.shared.segments.load(Ordering::Relaxed),
+            discontinuities: self.shared.discontinuities.load(Ordering::Relaxed),
+            errors: self.shared.errors.load(Ordering::Relaxed),
+            underruns: self.underruns,
+            buffered: Duration::from_secs_f64(buffered),
+            live: self.shared.live.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for HlsStream {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take()
+            && worker.join().is_err()
+        {
+            log::error!("HLS worker panicked");
+        }
+    }
+}
+
+impl fmt::Debug for HlsStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HlsStream")
+            .field("url", &self.input.url)
+            .field("media_url", &self.media_url)
+            .field("format", &self.format)
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Downloads, decodes and buffers segments, and refreshes live playlists.
+struct Worker {
+    media_url: String,
+    playlist: MediaPlaylist,
+    /// Sequence number of the next segment to play
+    next: u64,
+    /// Whether the audio broke off before the next segment
+    broken: bool,
+    retry: bool,
+    format: AudioFormat,
+    buffer: RingBufferWriter<Sample>,
+    shared: Arc<Shared>,
+    /// The last initialization section and its url
+    init: Option<(String, Vec<u8>)>,
+    refreshed: Instant,
+    /// Whether the last refresh brought no new segments
+    stale: bool,
+}
+
+impl Worker {
+    fn run(mut self) {
+        while !self.shared.stop.load(Ordering::Relaxed) {
+            if let Some(segment) = self.playlist.segment(self.next).cloned() {
+                self.play(&segment);
+                self.next = segment.sequence + 1;
+                continue;
+            }
+            if self.playlist.ended {
+                break;
+            }
+            // Half the target duration after a refresh that brought
+            // nothing new (RFC 8216 6.3.4)
+            let interval = if self.stale {
+                self.playlist.target_duration / 2
+            } else {
+                self.playlist.target_duration
+            };
+            let due = self.refreshed + interval.max(IDLE_WAIT);
+            let now = Instant::now();
+            if now < due {
+                thread::sleep((due - now).min(IDLE_WAIT));
+                continue;
+            }
+            if let Err(e) = self.refresh() {
+                self.shared.errors.fetch_add(1, Ordering::Relaxed);
+                log::warn!("Refreshing {} failed: {e}", self.media_url);
+                if !self.retry {
+                    self.shared.failed.store(true, Ordering::Release);
+                    break;
+                }
+            }
+        }
+        self.shared.live.store(false, Ordering::Relaxed);
+        self.shared.finished.store(true, Ordering::Release);
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        self.refreshed = Instant::now();
+        let text = self.fetch(&self.media_url)?;
+        let Playlist::Media(playlist) = Playlist::parse(&String::from_utf8_lossy(&text))? else {
+            return Err(AudioEngineError::UnsupportedFormat {
+                format: format!("{} turned into a master playlist", self.media_url),
+            });
+        };
+        self.stale = playlist.end_sequence() <= self.playlist.end_sequence();
+        if self.next < playlist.media_sequence {
+            log::warn!(
+                "Skipping {} HLS segments that left the playlist of {}",
+                playlist.media_sequence - self.next,
+                self.media_url
+            );
+            self.next = playlist.media_sequence;
+            self.broken = true;
+        } else if self.next > playlist.end_sequence() {
+            // The numbering started over, e.g. because the server restarted
+            log::warn!("HLS playlist {} started over", self.media_url);
+            self.next = start_sequence(&playlist);
+            self.broken = true;
+        }
+        self.shared.live.store(!playlist.ended, Ordering::Relaxed);
+        self.playlist = playlist;
+        Ok(())
+    }
+
+    fn play(&mut self, segment: &Segment) {
+        let mut samples = match self.load(segment) {
+            Ok(samples) => samples,
+            Err(e) => {
+                self.shared.errors.fetch_add(1, Ordering::Relaxed);
+                log::warn!("Skipping HLS segment {}: {e}", segment.uri);
+                self.broken = true;
+                return;
+            }
+        };
+        if segment.discontinuity || self.broken {
+            self.shared.discontinuities.fetch_add(1, Ordering::Relaxed);
+            let channels = self.format.channels.count_usize();
+            let frames = self.format.sample_rate.samples_for_milliseconds(FADE_MS) as usize;
+            fade_in(&mut samples, channels, frames);
+            self.broken = false;
+        }
+        self.shared.segments.fetch_add(1, Ordering::Relaxed);
+        self.push(&samples);
+    }
+
+    /// Downloads and decodes a segment.
+    fn load(&mut self, segment: &Segment) -> Result<Vec<Sample>> {
+        let mut bytes = Vec::new();
+        if let Some(map) = &segment.map {
+            let url = resolve(&self.media_url, map);
+            if self.init.as_ref().is_none_or(|(cached, _)| *cached != url) {
+                let init = self.fetch(&url)?;
+                self.init = Some((url, init));
+            }
+            if let Some((_, init)) = &self.init {
+                bytes.extend_from_slice(init);
+            }
+        }
+        bytes.extend(self.fetch(&resolve(&self.media_url, &segment.uri))?);
+        decode_segment(&bytes, &segment.uri, self.format)
+    }
+
+    /// Downloads `url`, trying again a few times when reconnecting.
+    fn fetch(&self, url: &str) -> Result<Vec<u8>> {
+        let attempts = if self.retry { FETCH_ATTEMPTS } else { 1 };
+        let mut attempt = 1;
+        loop {
+            match http::get(url, FETCH_TIMEOUT) {
+                Err(e) if attempt < attempts && !self.shared.stop.load(Ordering::Relaxed) => {
+                    log::debug!("Fetching {url} failed, trying again: {e}");
+                    thread::sleep(RETRY_WAIT);
+                    attempt += 1;
+                }
+                fetched => return fetched,
+            }
+        }
+    }
+
+    /// Pushes whole frames as room frees up, until all are in or the
+    /// stream stops.
+    fn push(&mut self, samples: &[Sample]) {
+        let channels = self.format.channels.count_usize();
+        let mut rest = samples;
+        while !rest.is_empty() && !self.shared.stop.load(Ordering::Relaxed) {
+            let room = self.buffer.slots() / channels * channels;
+            let pushed = self.buffer.push_slice(&rest[..rest.len().min(room)]);
+            if pushed == 0 {
+                thread::sleep(IDLE_WAIT);
+            }
+            rest = &rest[pushed..];
+        }
+    }
+}
+
+/// Fetches the playlist at `url`, following a master playlist to the
+/// media playlist of its audio.
+fn load_media_playlist(url: &str) -> Result<(String, MediaPlaylist)> {
+    let mut url = url.to_string();
+    // A master playlist, then the media playlist it points to
+    for _ in 0..2 {
+        let text = http::get(&url, FETCH_TIMEOUT)?;
+        match Playlist::parse(&String::from_utf8_lossy(&text))? {
+            Playlist::Media(playlist) => return Ok((url, playlist)),
+            Playlist::Master(master) => {
+                let uri =
+                    master
+                        .audio_uri()
+                        .ok_or_else(|| AudioEngineError::UnsupportedFormat {
+                            format: format!("{url} lists no variants"),
+                        })?;
+                url = resolve(&url, uri);
+            }
+        }
+    }
+    Err(AudioEngineError::UnsupportedFormat {
+        format: format!("{url} is a master playlist inside a master playlist"),
+    })
+}
+
+/// Where to start playing: the start of an on-demand playlist, or far
+/// enough behind the end of a live one to ride out slow downloads
+fn start_sequence(playlist: &MediaPlaylist) -> u64 {
+    if playlist.ended {
+        return playlist.media_sequence;
+    }
+    let behind = playlist.target_duration * LIVE_EDGE_TARGETS;
+    let mut held = Duration::ZERO;
+    let mut start = playlist.end_sequence();
+    for segment in playlist.segments.iter().rev() {
+        if held >= behind {
+            break;
+        }
+        held += segment.duration;
+        start = segment.sequence;
+    }
+    start
+}
+
+/// Decodes a segment into samples in `format`, with symphonia if it can
+/// and `ffmpeg` if not.
+fn decode_segment(bytes: &[u8], uri: &str, format: AudioFormat) -> Result<Vec<Sample>> {
+    let extension = Path::new(uri.split(['?', '#']).next().unwrap_or(uri))
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    // Symphonia is only built with the MP3 decoder among streaming codecs
+    if matches!(
+        extension.as_deref(),
+        Some("aac" | "ts" | "m4s" | "mp4" | "m4a")
+    ) {
+        return decode_with_ffmpeg(bytes, uri, format);
+    }
+    match decode_with_symphonia(bytes.to_vec(), extension.as_deref()) {
+        Ok((samples, channels, rate)) => Ok(convert(&samples, channels, rate, format)),
+        Err(SymphoniaError::Unsupported(_)) => decode_with_ffmpeg(bytes, uri, format),
+        Err(e) => Err(decode_error(Path::new(uri), e)),
+    }
+}
+
+/// Decodes a whole segment, returning its interleaved samples, channel
+/// count and sample rate.
+fn decode_with_symphonia(
+    bytes: Vec<u8>,
+    extension: Option<&str>,
+) -> std::result::Result<(Vec<f32>, usize, u32), SymphoniaError> {
+    let stream = MediaSourceStream::new(
+        Box::new(Cursor::new(bytes)),
+        MediaSourceStreamOptions::default(),
+    );
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let mut reader = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+    let track = reader
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(SymphoniaError::Unsupported("no audio track"))?;
+    let track_id = track.id;
+    let mut channels = track.codec_params.channels.map_or(1, Channels::count);
+    let mut rate = track.codec_params.sample_rate.unwrap_or(0);
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut samples = Vec::new();
+    let mut interleaved: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => {
+                decoder.reset();
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let buffer = match decoder.decode(&packet) {
+            Ok(buffer) => buffer,
+            Err(SymphoniaError::DecodeError(message)) => {
+                log::debug!("Skipping bad packet in HLS segment: {message}");
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let spec = *buffer.spec();
+        channels = spec.channels.count();
+        rate = spec.rate;
+        let needed = buffer.capacity() * channels;
+        let interleaved = match &mut interleaved {
+            Some(interleaved) if interleaved.capacity() >= needed => interleaved,
+            slot => slot.insert(SampleBuffer::new(buffer.capacity() as u64, spec)),
+        };
+        interleaved.copy_interleaved_ref(buffer);
+        samples.extend_from_slice(interleaved.samples());
+    }
+    Ok((samples, channels.max(1), rate))
+}
+
+/// Decodes a segment with `ffmpeg`, which also converts it to `format`.
+fn decode_with_ffmpeg(bytes: &[u8], uri: &str, format: AudioFormat) -> Result<Vec<Sample>> {
+    let mut child = Command::new(FFMPEG_PROGRAM)
+        .args(["-v", "error", "-i", "pipe:0", "-f", "f32le", "-ac"])
+        .arg(format.channels.count().to_string())
+        .arg("-ar")
+        .arg(format.sample_rate.as_hz().to_string())
+        .arg("pipe:1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => AudioEngineError::configuration(format!(
+                "decoding AAC and MPEG-TS segments needs `{FFMPEG_PROGRAM}` on the PATH"
+            )),
+            _ => e.into(),
+        })?;
+    let Some(mut stdin) = child.stdin.take() else {
+        return Err(AudioEngineError::configuration("decoder has no stdin"));
+    };
+    // Write on another thread while reading, so neither pipe fills up
+    let output = thread::scope(|scope| {
+        scope.spawn(move || {
+            // A decoder that quits early closes the pipe; its status says why
+            let _ = stdin.write_all(bytes);
+        });
+        child.wait_with_output()
+    })?;
+    if !output.status.success() {
+        return Err(AudioEngineError::UnsupportedFormat {
+            format: format!(
+                "{FFMPEG_PROGRAM} exited with {} decoding {uri}",
+                output.status
+            ),
+        });
+    }
+    Ok(output
+        .stdout
+        .chunks_exact(4)
+        .map(|bytes| Sample::new(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])))
+        .collect())
+}
+
+/// Converts interleaved samples with `channels` channels at `rate` Hz to
+/// `format`: mono is spread over every channel, anything to mono is
+/// averaged, and other layouts keep the channels they share. Rates are
+/// converted by linear interpolation.
+fn convert(samples: &[f32], channels: usize, rate: u32, format: AudioFormat) -> Vec<Sample> {
+    let out_channels = format.channels.count_usize();
+    let out_rate = format.sample_rate.as_hz();
+    let frames = samples.len() / channels;
+    if frames == 0 || rate == 0 {
+        return Vec::new();
+    }
+    let value = |frame: usize, channel: usize| {
+        let frame = &samples[frame * channels..(frame + 1) * channels];
+        if out_channels == 1 && channels > 1 {
+            #[allow(clippy::cast_precision_loss)]
+            let count = channels as f32;
+            frame.iter().sum::<f32>() / count
+        } else {
+            frame[channel % channels]
+        }
+    };
+    let out_frames = usize::try_from(frames as u64 * u64::from(out_rate) / u64::from(rate))
+        .unwrap_or(usize::MAX);
+    let step = f64::from(rate) / f64::from(out_rate);
+    let mut out = Vec::with_capacity(out_frames * out_channels);
+    for index in 0..out_frames {
+        #[allow(clippy::cast_precision_loss)]
+        let position = index as f64 * step;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let before = (position as usize).min(frames - 1);
+        let after = (before + 1).min(frames - 1);
+        #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+        let fraction = (position - before as f64) as f32;
+        for channel in 0..out_channels {
+            let from = value(before, channel);
+            let to = value(after, channel);
+            out.push(Sample::new((to - from).mul_add(fraction, from)));
+        }
+    }
+    out
+}
+
+/// Ramps the first `frames` frames up from silence.
+fn fade_in(samples: &mut [Sample], channels: usize, frames: usize) {
+    for (index, frame) in samples.chunks_exact_mut(channels).take(frames).enumerate() {
+        #[allow(clippy::cast_precision_loss)]
+        let gain = index as f32 / frames as f32;
+        for sample in frame {
+            *sample = Sample::new(f32::from(*sample) * gain);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! M3U8 playlists (RFC 8216)
+
+use std::time::Duration;
+
+use crate::error::{AudioEngineError, Result};
+
+/// A playlist of either kind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Playlist {
+    Master(MasterPlaylist),
+    Media(MediaPlaylist),
+}
+
+/// Lists the renditions of a stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MasterPlaylist {
+    pub variants: Vec<Variant>,
+    /// Alternative audio renditions
+    pub audio: Vec<Rendition>,
+}
+
+/// One `#EXT-X-STREAM-INF` entry of a master playlist.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variant {
+    pub uri: String,
+    /// Peak bits per second
+    pub bandwidth: u64,
+    pub codecs: Option<String>,
+    /// Group of the audio renditions it plays with
+    pub audio_group: Option<String>,
+}
+
+/// One `#EXT-X-MEDIA:TYPE=AUDIO` entry of a master playlist.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rendition {
+    pub uri: String,
+    pub group: String,
+    pub name: String,
+    pub default: bool,
+}
+
+/// Lists the segments of one rendition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaPlaylist {
+    /// Longest segment duration
+    pub target_duration: Duration,
+    /// Sequence number of the first segment
+    pub media_sequence: u64,
+    pub segments: Vec<Segment>,
+    /// Whether `#EXT-X-ENDLIST` closed it: a finished stream or on demand
+    pub ended: bool,
+}
+
+/// A media segment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Segment {
+    pub sequence: u64,
+    pub uri: String,
+    pub duration: Duration,
+    /// Whether the encoding may change from the segment before
+    pub discontinuity: bool,
+    /// Initialization section to decode it with, for fragmented MP4
+    pub map: Option<String>,
+}
+
+impl Playlist {
+    /// Parses an M3U8 playlist.
+    ///
+    /// # Errors
+    /// Returns an error if the text doesn't start with `#EXTM3U`, or its
+    /// segments are encrypted.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some("#EXTM3U") {
+            return Err(AudioEngineError::UnsupportedFormat {
+                format: "playlist doesn't start with #EXTM3U".to_string(),
+            });
+        }
+        if text.contains("#EXT-X-STREAM-INF") {
+            Ok(Self::Master(parse_master(lines)))
+        } else {
+            parse_media(lines).map(Self::Media)
+        }
+    }
+}
+
+impl MasterPlaylist {
+    /// The playlist of the audio to play: the default audio rendition if
+    /// there is one, or else the variant with the highest bandwidth.
+    #[must_use]
+    pub fn audio_uri(&self) -> Option<&str> {
+        self.audio
+            .iter()
+            .find(|rendition| rendition.default)
+            .or_else(|| self.audio.first())
+            .map(|rendition| rendition.uri.as_str())
+            .or_else(|| {
+                self.variants
+                    .iter()
+                    .max_by_key(|variant| variant.bandwidth)
+                    .map(|variant| variant.uri.as_str())
+            })
+    }
+}
+
+impl MediaPlaylist {
+    /// Sequence number one past the last segment
+    #[must_use]
+    pub const fn end_sequence(&self) -> u64 {
+        self.media_sequence + self.segments.len() as u64
+    }
+
+    /// The segment with sequence number `sequence`, if listed
+    #[must_use]
+    pub fn segment(&self, sequence: u64) -> Option<&Segment> {
+        let index = sequence.checked_sub(self.media_sequence)?;
+        self.segments.get(usize::try_from(index).ok()?)
+    }
+
+    /// Duration of all the segments listed
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.segments.iter().map(|segment| segment.duration).sum()
+    }
+}
+
+fn parse_master<'a>(lines: impl Iterator<Item = &'a str>) -> MasterPlaylist {
+    let mut playlist = MasterPlaylist::default();
+    let mut pending: Option<Variant> = None;
+    for line in lines {
+        if let Some(list) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            let mut variant = Variant::default();
+            for (name, value) in attributes(list) {
+                match name {
+                    "BANDWIDTH" => variant.bandwidth = value.parse().unwrap_or_default(),
+                    "CODECS" => variant.codecs = Some(value.to_string()),
+                    "AUDIO" => variant.audio_group = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            pending = Some(variant);
+        } else if let Some(list) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let mut rendition = Rendition::default();
+            let mut audio = false;
+            for (name, value) in attributes(list) {
+                match name {
+                    "TYPE" => audio = value == "AUDIO",
+                    "URI" => rendition.uri = value.to_string(),
+                    "GROUP-ID" => rendition.group = value.to_string(),
+                    "NAME" => rendition.name = value.to_string(),
+                    "DEFAULT" => rendition.default = value == "YES",
+                    _ => {}
+                }
+            }
+            // Without a URI the audio is muxed into the variants
+            if audio && !rendition.uri.is_empty() {
+                playlist.audio.push(rendition);
+            }
+        } else if !line.starts_with('#')
+            && let Some(mut variant) = pending.take()
+        {
+            variant.uri = line.to_string();
+            playlist.variants.push(variant);
+        }
+    }
+    playlist
+}
+
+fn parse_media<'a>(lines: impl Iterator<Item = &'a str>) -> Result<MediaPlaylist> {
+    let mut playlist = MediaPlaylist {
+        target_duration: Duration::ZERO,
+        media_sequence: 0,
+        segments: Vec::new(),
+        ended: false,
+    };
+    let mut duration = Duration::ZERO;
+    let mut discontinuity = false;
+    let mut map = None;
+    for line in lines {
+        if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            playlist.target_duration = Duration::from_secs(value.parse().unwrap_or(0));
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            playlist.media_sequence = value.parse().unwrap_or(0);
+        } else if let Some(value) = line.strip_prefix("#EXTINF:") {
+            let seconds = value.split(',').next().unwrap_or_default();
+            duration =
+                Duration::try_from_secs_f64(seconds.parse().unwrap_or(0.0)).unwrap_or_default();
+        } else if line == "#EXT-X-DISCONTINUITY" {
+            discontinuity = true;
+        } else if line == "#EXT-X-ENDLIST" {
+            playlist.ended = true;
+        } else if let Some(list) = line.strip_prefix("#EXT-X-MAP:") {
+            map = attributes(list)
+                .find(|&(name, _)| name == "URI")
+                .map(|(_, uri)| uri.to_string());
+        } else if let Some(list) = line.strip_prefix("#EXT-X-KEY:") {
+            let method = attributes(list)
+                .find(|&(name, _)| name == "METHOD")
+                .map_or("NONE", |(_, method)| method);
+            if method != "NONE" {
+                return Err(AudioEngineError::UnsupportedFormat {
+                    format: format!("HLS segments encrypted with {method}"),
+                });
+            }
+        } else if !line.starts_with('#') {
+            playlist.segments.push(Segment {
+                sequence: playlist.media_sequence + playlist.segments.len() as u64,
+                uri: line.to_string(),
+                duration,
+                discontinuity,
+                map: map.clone(),
+            });
+            duration = Duration::ZERO;
+            discontinuity = false;
+        }
+    }
+    Ok(playlist)
+}
+
+/// The `NAME=value` pairs of an attribute list, with quotes taken off
+/// quoted values.
+fn attributes(list: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        let (name, after) = rest.split_once('=')?;
+        let (value, next) = after.strip_prefix('"').map_or_else(
+            || after.split_once(',').unwrap_or((after, "")),
+            |quoted| {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let next = quoted[end..].trim_start_matches('"');
+                (&quoted[..end], next.strip_prefix(',').unwrap_or(next))
+            },
+        );
+        rest = next;
+        Some((name.trim(), value))
+    })
+}
+
+/// Resolves `uri` against the url of the playlist it appeared in.
+#[must_use]
+pub fn resolve(base: &str, uri: &str) -> String {
+    if uri.contains("://") {
+        return uri.to_string();
+    }
+    let scheme_end = base.find("://").map_or(0, |at| at + 3);
+    let path_start = base[scheme_end..]
+        .find('/')
+        .map_or(base.len(), |at| scheme_end + at);
+    if uri.starts_with('/') {
+        return format!("{}{uri}", &base[..path_start]);
+    }
+    let base = base.split(['?', '#']).next().unwrap_or(base);
+    let directory_end = base[path_start..]
+        .rfind('/')
+        .map_or(base.len(), |at| path_start + at);
+    format!("{}/{uri}", &base[..directory_end])
+}
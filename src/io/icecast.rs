@@ -0,0 +1,950 @@
+//! Icecast and Shoutcast source client
+//!
+//! An [`IcecastSource`] sends a live stream to a server for its listeners.
+//! It encodes with the MP3 or Opus encoder of the [`NetworkOutput`]'s
+//! codec, so [`write`](IcecastSource::write) only pushes into the
+//! encoder's ring buffer and is safe to call from the device callback. A
+//! sender thread takes the encoded frames, or Ogg pages for Opus, and
+//! sends them on the source connection.
+//!
+//! The url picks the server:
+//!
+//! - `icecast://host:8000/mount` (or `http://`) logs in to an Icecast
+//!   mount with the output's [`credentials`](NetworkOutput::credentials),
+//!   usually the user `source`.
+//! - `shoutcast://host:8000` logs in to a Shoutcast server on the port
+//!   above the listener port, with the password alone. Shoutcast only
+//!   takes MP3.
+//!
+//! The first connection is made by [`connect`](IcecastSource::connect),
+//! which fails if the server refuses the login. A connection lost after
+//! that is retried with exponential backoff, as the output's
+//! [`ReconnectPolicy`](crate::io::output::ReconnectPolicy) says; the audio
+//! encoded in the meantime is dropped, since a live stream can't catch
+//! up. Each loss and attempt is reported as a [`ConnectionEvent`].
+//!
+//! [`set_metadata`](IcecastSource::set_metadata) updates the now-playing
+//! title through the server's admin interface, and again after every
+//! reconnect; the output's [`now_playing`](NetworkOutput::now_playing) is
+//! sent as soon as the source connects. The server interleaves it with the
+//! audio as ICY metadata. Servers apply it to MP3 streams; Ogg streams
+//! carry their titles in the stream itself, so for Opus the update may not
+//! reach listeners. [`metadata`](IcecastSource::metadata) hands out an
+//! [`IcecastMetadata`] handle that updates the title from elsewhere, e.g.
+//! as an engine's [`MetadataSink`].
+//!
+//! [`set_bitrate`](IcecastSource::set_bitrate) moves a running stream to
+//! another bitrate, which a
+//! [`BitrateGovernor`](crate::engine::bitrate::BitrateGovernor) does by
+//! itself as the connection falls behind or catches up.
+//!
+//! Needs the `mp3` or `opus` feature for the codec, and TLS (`https://`) is
+//! not supported.
+//!
+//! ```no_run
+//! use audio_engine::io::NetworkOutput;
+//! use audio_engine::io::icecast::IcecastSource;
+//! use audio_engine::types::{AudioFormat, Sample, StreamUrl};
+//!
+//! let output = NetworkOutput::new(StreamUrl::parse("icecast://localhost:8000/live")?)
+//!     .with_credentials("source", "hackme");
+//! let mut source = IcecastSource::connect(&output, AudioFormat::default())?;
+//! source.set_metadata("Artist - Title")?;
+//! let block = vec![Sample::SILENCE; 960];
+//! loop {
+//!     source.write(&block);
+//!     while let Some(event) = source.try_event() {
+//!         println!("{event}");
+//!     }
+//!     std::thread::sleep(std::time::Duration::from_millis(10));
+//! }
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+
+use std::fmt::{self, Write as _};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::process::ChildStdout;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use flume::{Receiver, Sender};
+use parking_lot::Mutex;
+
+use crate::engine::RenderSink;
+use crate::engine::bitrate::AdaptiveStream;
+use crate::error::{AudioEngineError, Result};
+use crate::io::metadata::{MetadataSink, NowPlaying};
+#[cfg(feature = "mp3")]
+use crate::io::mp3::Mp3Encoder;
+#[cfg(feature = "opus")]
+use crate::io::opus::OpusEncoder;
+use crate::io::output::{NetworkOutput, StreamCodec};
+use crate::types::{AudioFormat, NetworkProtocol, Sample, StreamBitrate};
+
+/// Longest a connection attempt or metadata update may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes of MP3 read from the encoder at a time
+const CHUNK_BYTES: usize = 4096;
+
+/// Connection events that can wait to be picked up
+const EVENT_CAPACITY: usize = 64;
+
+/// Sent to servers as the client's name
+const USER_AGENT: &str = concat!("audio_engine/", env!("CARGO_PKG_VERSION"));
+
+/// Length of an Ogg page header before its segment table
+const OGG_HEADER_LEN: usize = 27;
+
+/// Server a source logs in to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Server {
+    Icecast,
+    Shoutcast,
+}
+
+/// A change in an [`IcecastSource`]'s connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionEvent {
+    /// Reconnect attempt, counted from 1; zero when the connection was
+    /// lost
+    pub attempt: u32,
+    pub connected: bool,
+    /// Why the connection was lost or the attempt failed
+    pub error: Option<String>,
+}
+
+impl fmt::Display for ConnectionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.attempt, self.connected) {
+            (0, _) => write!(f, "connection lost")?,
+            (attempt, true) => write!(f, "reconnected on attempt {attempt}")?,
+            (attempt, false) => write!(f, "reconnect attempt {attempt} failed")?,
+        }
+        if let Some(error) = &self.error {
+            write!(f, ": {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The encoder for the stream's codec
+enum StreamEncoder {
+    #[cfg(feature = "mp3")]
+    Mp3(Mp3Encoder),
+    #[cfg(feature = "opus")]
+    Opus(OpusEncoder),
+}
+
+impl StreamEncoder {
+    fn start(output: &NetworkOutput, format: AudioFormat) -> Result<(Self, ChildStdout)> {
+        match output.codec {
+            #[cfg(feature = "mp3")]
+            StreamCodec::Mp3 => {
+                Mp3Encoder::for_stream(output, format).map(|(e, out)| (Self::Mp3(e), out))
+            }
+            #[cfg(feature = "opus")]
+            StreamCodec::Opus(_) => {
+                OpusEncoder::for_stream(output, format).map(|(e, out)| (Self::Opus(e), out))
+            }
+            #[cfg(not(feature = "mp3"))]
+            StreamCodec::Mp3 => Err(AudioEngineError::configuration(
+                "can't stream MP3 without the mp3 feature",
+            )),
+            #[cfg(not(feature = "opus"))]
+            StreamCodec::Opus(_) => Err(AudioEngineError::configuration(
+                "can't stream Opus without the opus feature",
+            )),
+            codec @ (StreamCodec::L16 | StreamCodec::L24 | StreamCodec::Aac) => {
+                Err(AudioEngineError::configuration(format!(
+                    "Icecast and Shoutcast stream MP3 or Opus, not {codec}"
+                )))
+            }
+        }
+    }
+
+    fn write(&mut self, samples: &[Sample]) -> usize {
+        match self {
+            #[cfg(feature = "mp3")]
+            Self::Mp3(encoder) => encoder.write(samples),
+            #[cfg(feature = "opus")]
+            Self::Opus(encoder) => encoder.write(samples),
+        }
+    }
+
+    fn sink(&mut self) -> &mut dyn RenderSink {
+        match self {
+            #[cfg(feature = "mp3")]
+            Self::Mp3(encoder) => encoder,
+            #[cfg(feature = "opus")]
+            Self::Opus(encoder) => encoder,
+        }
+    }
+
+    fn dropped(&self) -> u64 {
+        match self {
+            #[cfg(feature = "mp3")]
+            Self::Mp3(encoder) => encoder.dropped(),
+            #[cfg(feature = "opus")]
+            Self::Opus(encoder) => encoder.dropped(),
+        }
+    }
+
+    fn backlog(&self) -> f32 {
+        match self {
+            #[cfg(feature = "mp3")]
+            Self::Mp3(encoder) => encoder.backlog(),
+            #[cfg(feature = "opus")]
+            Self::Opus(encoder) => encoder.backlog(),
+        }
+    }
+
+    fn has_failed(&self) -> bool {
+        match self {
+            #[cfg(feature = "mp3")]
+            Self::Mp3(encoder) => encoder.has_failed(),
+            #[cfg(feature = "opus")]
+            Self::Opus(encoder) => encoder.has_failed(),
+        }
+    }
+}
+
+/// State the sender thread shares with the source
+#[derive(Default)]
+struct Shared {
+    connected: AtomicBool,
+    bytes_sent: AtomicU64,
+    reconnects: AtomicU64,
+    /// Now-playing title, sent again after a reconnect
+    title: Mutex<Option<String>>,
+}
+
+/// Sends a live stream to an Icecast or Shoutcast server.
+pub struct IcecastSource {
+    output: NetworkOutput,
+    server: Server,
+    format: AudioFormat,
+    encoder: StreamEncoder,
+    shared: Arc<Shared>,
+    events: Receiver<ConnectionEvent>,
+    /// Hands the sender the output of an encoder started by
+    /// [`set_bitrate`](Self::set_bitrate)
+    swaps: Sender<ChildStdout>,
+    sender: Option<JoinHandle<()>>,
+}
+
+impl IcecastSource {
+    /// Starts the encoder for `output`'s codec on samples in `format` and
+    /// logs in to the server.
+    ///
+    /// # Errors
+    /// Returns an error if the url isn't an Icecast or Shoutcast one, the
+    /// login is missing, the codec's feature isn't enabled or the server
+    /// doesn't take it, the encoder can't be started, or the server can't
+    /// be reached or refuses the login.
+    pub fn connect(output: &NetworkOutput, format: AudioFormat) -> Result<Self> {
+        let server = match output.url.protocol() {
+            NetworkProtocol::Icecast => Server::Icecast,
+            NetworkProtocol::HLS if output.url.as_str().starts_with("http://") => Server::Icecast,
+            NetworkProtocol::Shoutcast => Server::Shoutcast,
+            _ => {
+                return Err(AudioEngineError::configuration(format!(
+                    "{} isn't an Icecast or Shoutcast url",
+                    output.url
+                )));
+            }
+        };
+        if output.credentials.is_none() {
+            return Err(AudioEngineError::configuration(format!(
+                "{} needs credentials to send a stream",
+                output.url
+            )));
+        }
+        if server == Server::Shoutcast && output.codec != StreamCodec::Mp3 {
+            return Err(AudioEngineError::configuration(format!(
+                "Shoutcast streams MP3, not {}",
+                output.codec
+            )));
+        }
+
+        let (encoder, stdout) = StreamEncoder::start(output, format)?;
+        let link = open_link(output, server)?;
+        log::info!("Streaming to {}", output.url);
+        let shared = Arc::new(Shared {
+            connected: AtomicBool::new(true),
+            ..Shared::default()
+        });
+        let (events_sender, events) = flume::bounded(EVENT_CAPACITY);
+        let (swaps, swapped) = flume::unbounded();
+        let worker = SenderThread {
+            output: output.clone(),
+            server,
+            encoded: BufReader::new(stdout),
+            swapped,
+            link: Some(link),
+            shared: Arc::clone(&shared),
+            events: events_sender,
+            headers: Vec::new(),
+            audio_started: false,
+            attempt: 0,
+            retry_at: None,
+            gave_up: false,
+        };
+        let sender = thread::Builder::new()
+            .name("icecast-source".to_string())
+            .spawn(move || worker.run())?;
+        let source = Self {
+            output: output.clone(),
+            server,
+            format,
+            encoder,
+            shared,
+            events,
+            swaps,
+            sender: Some(sender),
+        };
+        if let Some(now) = &output.now_playing
+            && let Err(e) = source.set_metadata(&now.stream_title())
+        {
+            log::warn!("Sending the title to {} failed: {e}", output.url);
+        }
+        Ok(source)
+    }
+
+    #[must_use]
+    pub const fn output(&self) -> &NetworkOutput {
+        &self.output
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Queues interleaved samples for encoding without blocking, returning
+    /// how many were queued. The rest are dropped.
+    pub fn write(&mut self, samples: &[Sample]) -> usize {
+        self.encoder.write(samples)
+    }
+
+    /// Whether the source is connected to the server
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        self.shared.connected.load(Ordering::Relaxed)
+    }
+
+    /// Encoded bytes sent so far
+    #[must_use]
+    pub fn bytes_sent(&self) -> u64 {
+        self.shared.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Successful reconnects so far
+    #[must_use]
+    pub fn reconnects(&self) -> u64 {
+        self.shared.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Samples dropped so far because the encoder fell behind
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.encoder.dropped()
+    }
+
+    /// Share of the encoder's ring waiting to be encoded, from 0 to 1. It
+    /// fills up when the connection can't keep up with the bitrate.
+    #[must_use]
+    pub fn backlog(&self) -> f32 {
+        self.encoder.backlog()
+    }
+
+    /// Whether the encoder stopped taking samples, e.g. because it exited
+    #[must_use]
+    pub fn has_failed(&self) -> bool {
+        self.encoder.has_failed()
+    }
+
+    /// Moves the stream to `bitrate` without dropping the connection.
+    ///
+    /// A new encoder takes over the samples written from now on, and the
+    /// old one finishes what it was given in the background, so the
+    /// listeners hear no gap. An MP3 stream just changes frame size; an
+    /// Opus stream starts a new chained Ogg stream, which players follow.
+    /// The `icy-br` the server announced stays as it was.
+    ///
+    /// # Errors
+    /// Returns an error if the codec can't encode at `bitrate` or the new
+    /// encoder can't be started; the stream then goes on as it was.
+    pub fn set_bitrate(&mut self, bitrate: StreamBitrate) -> Result<()> {
+        if bitrate == self.output.audio_bitrate {
+            return Ok(());
+        }
+        let output = self.output.clone().with_audio_bitrate(bitrate);
+        let (encoder, stdout) = StreamEncoder::start(&output, self.format)?;
+        // The sender moves on to the new encoder once the old one's output
+        // ends
+        if self.swaps.send(stdout).is_err() {
+            return Err(AudioEngineError::configuration(format!(
+                "the sender for {} has stopped",
+                self.output.url
+            )));
+        }
+        let mut previous = std::mem::replace(&mut self.encoder, encoder);
+        let url = self.output.url.clone();
+        thread::Builder::new()
+            .name("icecast-encoder-finish".to_string())
+            .spawn(move || {
+                if let Err(e) = previous.sink().finish() {
+                    log::warn!("Finishing the previous encoder for {url} failed: {e}");
+                }
+            })?;
+        log::info!(
+            "Streaming to {} at {bitrate} instead of {}",
+            self.output.url,
+            self.output.audio_bitrate
+        );
+        self.output.audio_bitrate = bitrate;
+        Ok(())
+    }
+
+    /// The next change in the connection, if any
+    #[must_use]
+    pub fn try_event(&self) -> Option<ConnectionEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Sets the now-playing title. It is sent now if the source is
+    /// connected, and again after every reconnect.
+    ///
+    /// # Errors
+    /// Returns an error if the server can't be reached or refuses the
+    /// update.
+    pub fn set_metadata(&self, title: &str) -> Result<()> {
+        self.metadata().set_title(title)
+    }
+
+    /// A handle that sets the now-playing title while the source streams.
+    #[must_use]
+    pub fn metadata(&self) -> IcecastMetadata {
+        IcecastMetadata {
+            output: self.output.clone(),
+            server: self.server,
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// Encodes and sends what is still queued, then closes the connection.
+    ///
+    /// # Errors
+    /// Returns an error if the encoder failed or exited unsuccessfully.
+    pub fn finish(mut self) -> Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let encoded = self.encoder.sink().finish();
+        // The encoder closing its output ends the sender
+        if let Some(sender) = self.sender.take() {
+            sender
+                .join()
+                .map_err(|_| AudioEngineError::configuration("icecast sender thread panicked"))?;
+        }
+        encoded
+    }
+}
+
+impl Drop for IcecastSource {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::error!("Streaming to {} failed: {e}", self.output.url);
+        }
+    }
+}
+
+impl fmt::Debug for IcecastSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IcecastSource")
+            .field("url", &self.output.url)
+            .field("format", &self.format)
+            .field("connected", &self.is_connected())
+            .field("bytes_sent", &self.bytes_sent())
+            .field("dropped", &self.dropped())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AdaptiveStream for IcecastSource {
+    fn output(&self) -> &NetworkOutput {
+        &self.output
+    }
+
+    fn set_bitrate(&mut self, bitrate: StreamBitrate) -> Result<()> {
+        Self::set_bitrate(self, bitrate)
+    }
+
+    fn backlog(&self) -> f32 {
+        self.encoder.backlog()
+    }
+
+    fn bytes_sent(&self) -> u64 {
+        self.shared.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    fn dropped(&self) -> u64 {
+        self.encoder.dropped()
+    }
+}
+
+/// Sets the now-playing title of an [`IcecastSource`]; see
+/// [`IcecastSource::metadata`].
+#[derive(Clone)]
+pub struct IcecastMetadata {
+    output: NetworkOutput,
+    server: Server,
+    shared: Arc<Shared>,
+}
+
+impl IcecastMetadata {
+    /// Sets the now-playing title, as
+    /// [`IcecastSource::set_metadata`] does.
+    ///
+    /// # Errors
+    /// Returns an error if the server can't be reached or refuses the
+    /// update.
+    pub fn set_title(&self, title: &str) -> Result<()> {
+        *self.shared.title.lock() = Some(title.to_string());
+        if self.shared.connected.load(Ordering::Relaxed) {
+            update_metadata(&self.output, self.server, title)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl MetadataSink for IcecastMetadata {
+    /// Sends `now` as `Artist - Title`.
+    fn set_now_playing(&mut self, now: &NowPlaying) -> Result<()> {
+        self.set_title(&now.stream_title())
+    }
+}
+
+impl fmt::Debug for IcecastMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IcecastMetadata")
+            .field("url", &self.output.url)
+            .field("title", &*self.shared.title.lock())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RenderSink for IcecastSource {
+    fn start(&mut self, format: AudioFormat) -> Result<()> {
+        self.encoder.sink().start(format)
+    }
+
+    /// Waits for room rather than dropping; render with
+    /// [`RenderPacing::RealTime`](crate::engine::RenderPacing::RealTime) so
+    /// the stream goes out as fast as it plays
+    fn write(&mut self, samples: &[Sample]) -> Result<()> {
+        self.encoder.sink().write(samples)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.close()
+    }
+
+    fn describe(&self) -> String {
+        self.output.url.to_string()
+    }
+}
+
+/// Takes the encoder's output and sends it on, reconnecting as needed.
+struct SenderThread {
+    output: NetworkOutput,
+    server: Server,
+    encoded: BufReader<ChildStdout>,
+    /// Output of encoders that replace the current one
+    swapped: Receiver<ChildStdout>,
+    link: Option<TcpStream>,
+    shared: Arc<Shared>,
+    events: Sender<ConnectionEvent>,
+    /// Ogg header pages, sent again at the start of every connection
+    headers: Vec<u8>,
+    audio_started: bool,
+    /// Reconnect attempts since the connection was lost
+    attempt: u32,
+    retry_at: Option<Instant>,
+    gave_up: bool,
+}
+
+impl SenderThread {
+    fn run(mut self) {
+        let mut unit = Vec::new();
+        loop {
+            match self.next_unit(&mut unit) {
+                Ok(true) => {}
+                Ok(false) => match self.swapped.try_recv() {
+                    Ok(next) => {
+                        // A new Ogg stream brings its own header pages
+                        self.encoded = BufReader::new(next);
+                        self.headers.clear();
+                        self.audio_started = false;
+                        continue;
+                    }
+                    Err(_) => break,
+                },
+                Err(e) => {
+                    log::error!(
+                        "Reading the encoded stream for {} failed: {e}",
+                        self.output.url
+                    );
+                    break;
+                }
+            }
+            if self.link.is_none() {
+                self.reconnect();
+            }
+            if let Some(link) = &mut self.link {
+                match link.write_all(&unit) {
+                    Ok(()) => {
+                        self.shared
+                            .bytes_sent
+                            .fetch_add(unit.len() as u64, Ordering::Relaxed);
+                    }
+                    Err(e) => self.lost(&e.to_string()),
+                }
+            }
+        }
+        if let Some(link) = self.link.take() {
+            let _ = link.shutdown(Shutdown::Both);
+        }
+        self.shared.connected.store(false, Ordering::Relaxed);
+    }
+
+    /// Reads the next Ogg page, or chunk of MP3, into `unit`. Returns false
+    /// once the encoder has finished.
+    fn next_unit(&mut self, unit: &mut Vec<u8>) -> std::io::Result<bool> {
+        unit.clear();
+        if matches!(self.output.codec, StreamCodec::Mp3) {
+            unit.resize(CHUNK_BYTES, 0);
+            let read = loop {
+                match self.encoded.read(unit) {
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    read => break read?,
+                }
+            };
+            unit.truncate(read);
+            return Ok(read > 0);
+        }
+
+        unit.resize(OGG_HEADER_LEN, 0);
+        match self.encoded.read_exact(unit) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            read => read?,
+        }
+        if &unit[..4] != b"OggS" {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "encoder output lost Ogg page sync",
+            ));
+        }
+        let segments = usize::from(unit[26]);
+        unit.resize(OGG_HEADER_LEN + segments, 0);
+        self.encoded.read_exact(&mut unit[OGG_HEADER_LEN..])?;
+        let body: usize = unit[OGG_HEADER_LEN..]
+            .iter()
+            .map(|&len| usize::from(len))
+            .sum();
+        let start = unit.len();
+        unit.resize(start + body, 0);
+        self.encoded.read_exact(&mut unit[start..])?;
+
+        // Header pages come first, at granule position zero
+        let granule = u64::from_le_bytes(unit[6..14].try_into().unwrap_or_default());
+        if !self.audio_started && granule == 0 {
+            self.headers.extend_from_slice(unit);
+        } else {
+            self.audio_started = true;
+        }
+        Ok(true)
+    }
+
+    fn lost(&mut self, error: &str) {
+        log::warn!("Lost the connection to {}: {error}", self.output.url);
+        self.link = None;
+        self.shared.connected.store(false, Ordering::Relaxed);
+        self.attempt = 0;
+        self.retry_at = Some(Instant::now() + self.output.reconnect.delay(1));
+        self.event(0, false, Some(error.to_string()));
+    }
+
+    /// Makes a reconnect attempt if one is due.
+    fn reconnect(&mut self) {
+        let now = Instant::now();
+        if self.gave_up || self.retry_at.is_some_and(|at| now < at) {
+            return;
+        }
+        self.attempt += 1;
+        let policy = self.output.reconnect;
+        if !policy.allows(self.attempt) {
+            log::error!(
+                "Giving up on {} after {} reconnect attempts",
+                self.output.url,
+                self.attempt - 1
+            );
+            self.gave_up = true;
+            return;
+        }
+        let connected = open_link(&self.output, self.server).and_then(|mut link| {
+            link.write_all(&self.headers)?;
+            Ok(link)
+        });
+        match connected {
+            Ok(link) => {
+                log::info!(
+                    "Reconnected to {} on attempt {}",
+                    self.output.url,
+                    self.attempt
+                );
+                self.link = Some(link);
+                self.shared.connected.store(true, Ordering::Relaxed);
+                self.shared.reconnects.fetch_add(1, Ordering::Relaxed);
+                self.event(self.attempt, true, None);
+                self.attempt = 0;
+                self.retry_at = None;
+                let title = self.shared.title.lock().clone();
+                if let Some(title) = title
+                    && let Err(e) = update_metadata(&self.output, self.server, &title)
+                {
+                    log::warn!("Resending the title to {} failed: {e}", self.output.url);
+                }
+            }
+            Err(e) => {
+                log::warn!(
+                    "Reconnect attempt {} to {} failed: {e}",
+                    self.attempt,
+                    self.output.url
+                );
+                self.retry_at = Some(now + policy.delay(self.attempt + 1));
+                self.event(self.attempt, false, Some(e.to_string()));
+            }
+        }
+    }
+
+    fn event(&self, attempt: u32, connected: bool, error: Option<String>) {
+        let _ = self.events.try_send(ConnectionEvent {
+            attempt,
+            connected,
+            error,
+        });
+    }
+}
+
+/// Connects to the server's source port and logs in.
+fn open_link(output: &NetworkOutput, server: Server) -> Result<TcpStream> {
+    let mut address = output.url.to_socket_addr()?;
+    if server == Server::Shoutcast {
+        address.set_port(address.port().saturating_add(1));
+    }
+    let mut link = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    link.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    link.set_write_timeout(Some(Duration::from_millis(u64::from(
+        output.buffer_ms.max(100),
+    ))))?;
+    link.set_nodelay(true)?;
+    match server {
+        Server::Icecast => icecast_login(&mut link, output)?,
+        Server::Shoutcast => shoutcast_login(&mut link, output)?,
+    }
+    Ok(link)
+}
+
+fn icecast_login(link: &mut TcpStream, output: &NetworkOutput) -> Result<()> {
+    let url = &output.url;
+    let content_type = output.codec.content_type();
+    let mut request = format!(
+        "PUT /{} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: {USER_AGENT}\r\n",
+        url.path(),
+        url.host(),
+        url.port()
+    );
+    let _ = write!(
+        request,
+        "Authorization: Basic {}\r\nContent-Type: {content_type}\r\n",
+        basic_auth(output)
+    );
+    let info = &output.info;
+    let _ = write!(
+        request,
+        "Ice-Public: {}\r\nIce-Bitrate: {}\r\n",
+        u8::from(info.public),
+        output.audio_bitrate.as_kbps()
+    );
+    for (header, value) in [
+        ("Ice-Name", &info.name),
+        ("Ice-Description", &info.description),
+        ("Ice-Genre", &info.genre),
+        ("Ice-Url", &info.url),
+    ] {
+        if let Some(value) = value {
+            let _ = write!(request, "{header}: {}\r\n", header_value(value));
+        }
+    }
+    request.push_str("Expect: 100-continue\r\n\r\n");
+    link.write_all(request.as_bytes())?;
+
+    let status = read_response(link)?;
+    match status {
+        100 | 200 => Ok(()),
+        401 => Err(login_error(output, "wrong username or password")),
+        403 => Err(login_error(output, "mount in use or not allowed")),
+        status => Err(login_error(output, &format!("server answered {status}"))),
+    }
+}
+
+fn shoutcast_login(link: &mut TcpStream, output: &NetworkOutput) -> Result<()> {
+    let password = output
+        .credentials
+        .as_ref()
+        .map_or("", |credentials| credentials.password.as_str());
+    link.write_all(format!("{password}\r\n").as_bytes())?;
+    let mut reply = String::new();
+    BufReader::new(&*link).read_line(&mut reply)?;
+    if !reply.trim_start().starts_with("OK") {
+        return Err(login_error(output, reply.trim()));
+    }
+    let info = &output.info;
+    let mut headers = String::new();
+    for (header, value) in [
+        ("icy-name", &info.name),
+        ("icy-genre", &info.genre),
+        ("icy-url", &info.url),
+    ] {
+        if let Some(value) = value {
+            let _ = write!(headers, "{header}:{}\r\n", header_value(value));
+        }
+    }
+    let _ = write!(
+        headers,
+        "icy-pub:{}\r\nicy-br:{}\r\ncontent-type:audio/mpeg\r\n\r\n",
+        u8::from(info.public),
+        output.audio_bitrate.as_kbps()
+    );
+    link.write_all(headers.as_bytes())?;
+    Ok(())
+}
+
+/// Sends the now-playing title through the server's admin interface.
+fn update_metadata(output: &NetworkOutput, server: Server, title: &str) -> Result<()> {
+    let url = &output.url;
+    let request = match server {
+        Server::Icecast => format!(
+            "GET /admin/metadata?mount=/{}&mode=updinfo&song={} HTTP/1.0\r\n\
+             Host: {}:{}\r\nUser-Agent: {USER_AGENT}\r\nAuthorization: Basic {}\r\n\r\n",
+            percent_encode(url.path()),
+            percent_encode(title),
+            url.host(),
+            url.port(),
+            basic_auth(output)
+        ),
+        // Shoutcast 1 only answers browsers here
+        Server::Shoutcast => format!(
+            "GET /admin.cgi?pass={}&mode=updinfo&song={} HTTP/1.0\r\n\
+             User-Agent: Mozilla/5.0 ({USER_AGENT})\r\n\r\n",
+            percent_encode(
+                output
+                    .credentials
+                    .as_ref()
+                    .map_or("", |credentials| credentials.password.as_str())
+            ),
+            percent_encode(title)
+        ),
+    };
+    let mut link = TcpStream::connect_timeout(&url.to_socket_addr()?, CONNECT_TIMEOUT)?;
+    link.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    link.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+    link.write_all(request.as_bytes())?;
+    match read_response(&link)? {
+        200 => Ok(()),
+        status => Err(AudioEngineError::NetworkConnection {
+            message: format!("{url} refused the metadata update with {status}"),
+        }),
+    }
+}
+
+/// Reads an HTTP response's status line and headers, returning the status.
+fn read_response(link: &TcpStream) -> Result<u16> {
+    let mut reader = BufReader::new(link);
+    let mut reply = String::new();
+    reader.read_line(&mut reply)?;
+    let status = reply
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| AudioEngineError::NetworkConnection {
+            message: format!("not an HTTP response: {:?}", reply.trim()),
+        })?;
+    // Headers, if any, up to the blank line; the server says no more
+    loop {
+        reply.clear();
+        if reader.read_line(&mut reply)? == 0 || reply.trim().is_empty() {
+            return Ok(status);
+        }
+    }
+}
+
+fn login_error(output: &NetworkOutput, reason: &str) -> AudioEngineError {
+    AudioEngineError::NetworkConnection {
+        message: format!("{} refused the source login: {reason}", output.url),
+    }
+}
+
+/// The `user:password` of `output`, base64 encoded
+fn basic_auth(output: &NetworkOutput) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let login = output
+        .credentials
+        .as_ref()
+        .map(|credentials| format!("{}:{}", credentials.username, credentials.password))
+        .unwrap_or_default();
+    let mut encoded = String::with_capacity(login.len().div_ceil(3) * 4);
+    for chunk in login.as_bytes().chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(char::from(
+                    ALPHABET[(bits >> (18 - 6 * index)) as usize & 63],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// `text` with everything but unreserved URL characters percent-encoded
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~' | b'/') {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+/// `value` without line breaks, which would end the header early
+fn header_value(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
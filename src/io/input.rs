@@ -3,7 +3,9 @@
 use std::fmt;
 use std::path::PathBuf;
 
-use crate::types::{AudioFormat, DeviceId, NetworkProtocol, StreamUrl};
+use crate::io::output::StreamCodec;
+use crate::io::rtp::RtpSettings;
+use crate::types::{AudioFormat, DeviceId, NetworkProtocol, SampleRate, StreamUrl};
 
 /// Audio input source
 ///
@@ -55,6 +57,17 @@ impl InputSource {
         Self::Signal(SignalGenerator::Sine { frequency_hz })
     }
 
+    /// Creates an exponential sine sweep generator
+    #[must_use]
+    pub fn sweep(start_hz: f32, end_hz: f32, duration_seconds: f32) -> Self {
+        Self::Signal(SignalGenerator::Sweep {
+            start_hz,
+            end_hz,
+            duration_seconds,
+            curve: SweepCurve::Exponential,
+        })
+    }
+
     /// Returns a description of the input source
     #[must_use]
     pub fn description(&self) -> String {
@@ -114,6 +127,10 @@ pub struct FileInput {
     pub looping: bool,
     /// Starting positions in seconds
     pub start_position: f64,
+    /// Playback speed without pitch change (1.0 = normal)
+    pub speed: f32,
+    /// Format of a headerless raw PCM file, which can't be read without it
+    pub raw_format: Option<AudioFormat>,
 }
 
 impl FileInput {
@@ -124,6 +141,8 @@ impl FileInput {
             path: path.into(),
             looping: false,
             start_position: 0.0,
+            speed: 1.0,
+            raw_format: None,
         }
     }
 
@@ -141,6 +160,23 @@ impl FileInput {
         self
     }
 
+    /// Sets the playback speed, time-stretched so the pitch is unchanged.
+    ///
+    /// See [`TimeStretcher`](crate::dsp::time_stretch::TimeStretcher).
+    #[must_use]
+    pub const fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Sets the format of a headerless raw PCM file. Samples are read
+    /// little-endian.
+    #[must_use]
+    pub const fn with_raw_format(mut self, format: AudioFormat) -> Self {
+        self.raw_format = Some(format);
+        self
+    }
+
     /// Returns the file extension
     #[must_use]
     pub fn extension(&self) -> Option<&str> {
@@ -164,6 +200,14 @@ pub enum AudioFileFormat {
     Flac,
     /// Ogg vorbis
     Ogg,
+    /// Ogg opus
+    Opus,
+    /// Audio interchange file format, including AIFF-C
+    Aiff,
+    /// Core audio format
+    Caf,
+    /// Headerless PCM, read in a format given separately
+    Raw,
 }
 
 impl AudioFileFormat {
@@ -175,6 +219,10 @@ impl AudioFileFormat {
             "mp3" => Some(Self::Mp3),
             "flac" => Some(Self::Flac),
             "ogg" | "oga" => Some(Self::Ogg),
+            "opus" => Some(Self::Opus),
+            "aif" | "aiff" | "aifc" => Some(Self::Aiff),
+            "caf" => Some(Self::Caf),
+            "raw" | "pcm" => Some(Self::Raw),
             _ => None,
         }
     }
@@ -187,6 +235,10 @@ impl AudioFileFormat {
             Self::Mp3 => "audio/mpeg",
             Self::Flac => "audio/flac",
             Self::Ogg => "audio/ogg",
+            Self::Opus => "audio/opus",
+            Self::Aiff => "audio/aiff",
+            Self::Caf => "audio/x-caf",
+            Self::Raw => "application/octet-stream",
         }
     }
 
@@ -198,6 +250,10 @@ impl AudioFileFormat {
             Self::Mp3 => "mp3",
             Self::Flac => "flac",
             Self::Ogg => "ogg",
+            Self::Opus => "opus",
+            Self::Aiff => "aiff",
+            Self::Caf => "caf",
+            Self::Raw => "raw",
         }
     }
 }
@@ -209,6 +265,10 @@ impl fmt::Display for AudioFileFormat {
             Self::Mp3 => write!(f, "MP3"),
             Self::Flac => write!(f, "FLAC"),
             Self::Ogg => write!(f, "OGG"),
+            Self::Opus => write!(f, "Opus"),
+            Self::Aiff => write!(f, "AIFF"),
+            Self::Caf => write!(f, "CAF"),
+            Self::Raw => write!(f, "raw PCM"),
         }
     }
 }
@@ -222,6 +282,10 @@ pub struct NetworkInput {
     pub buffer_ms: u32,
     /// Reconnect on failure
     pub auto_reconnect: bool,
+    /// Codec the stream is encoded with
+    pub codec: StreamCodec,
+    /// Jitter buffer and report settings for RTP urls
+    pub rtp: RtpSettings,
 }
 impl NetworkInput {
     /// Creates a new network input
@@ -231,6 +295,8 @@ impl NetworkInput {
             url,
             buffer_ms: 1000,
             auto_reconnect: true,
+            codec: StreamCodec::default(),
+            rtp: RtpSettings::default(),
         }
     }
 
@@ -246,6 +312,20 @@ impl NetworkInput {
         self.auto_reconnect = false;
         self
     }
+
+    /// Sets the codec
+    #[must_use]
+    pub const fn with_codec(mut self, codec: StreamCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Sets the RTP jitter buffer and report settings
+    #[must_use]
+    pub const fn with_rtp(mut self, rtp: RtpSettings) -> Self {
+        self.rtp = rtp;
+        self
+    }
     /// Returns the protocol
     #[must_use]
     pub fn protocol(&self) -> NetworkProtocol {
@@ -254,7 +334,10 @@ impl NetworkInput {
 }
 
 /// Signal generator that is used for testing
-#[derive(Debug, Clone, Copy)]
+///
+/// This only describes the signal; a
+/// [`SignalSource`](crate::io::SignalSource) generates it.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SignalGenerator {
     /// Generates Silence
     Silence,
@@ -265,11 +348,67 @@ pub enum SignalGenerator {
     },
     /// Generates white noise
     WhiteNoise,
-    /// Generates a square wave
+    /// Generates a band-limited square wave
     Square {
         /// Frequency in hz
         frequency_hz: f32,
     },
+    /// Generates a band-limited rising sawtooth wave
+    Saw {
+        /// Frequency in Hz
+        frequency_hz: f32,
+    },
+    /// Generates a band-limited triangle wave
+    Triangle {
+        /// Frequency in Hz
+        frequency_hz: f32,
+    },
+    /// Generates pink noise, falling 3 dB per octave
+    PinkNoise,
+    /// Generates brown noise, falling 6 dB per octave
+    BrownNoise,
+    /// Sweeps a sine from one frequency to another once, then falls
+    /// silent
+    Sweep {
+        /// Frequency at the start, in Hz
+        start_hz: f32,
+        /// Frequency at the end, in Hz
+        end_hz: f32,
+        /// Length of the sweep in seconds
+        duration_seconds: f32,
+        /// How the frequency moves between the two
+        curve: SweepCurve,
+    },
+}
+
+impl SignalGenerator {
+    /// Frames the signal lasts at `sample_rate`, or `None` if it never
+    /// ends
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn frames(&self, sample_rate: SampleRate) -> Option<u64> {
+        match *self {
+            Self::Sweep {
+                duration_seconds, ..
+            } => Some(
+                (f64::from(duration_seconds.max(0.0)) * f64::from(sample_rate.as_hz())).round()
+                    as u64,
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// How a [`SignalGenerator::Sweep`] moves from its start frequency to its
+/// end frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SweepCurve {
+    /// The same number of Hz every second, a linear chirp
+    Linear,
+    /// The same number of octaves every second, for equal energy per
+    /// octave
+    #[default]
+    Exponential,
 }
 
 impl fmt::Display for SignalGenerator {
@@ -279,6 +418,16 @@ impl fmt::Display for SignalGenerator {
             Self::Sine { frequency_hz } => write!(f, "Sine {frequency_hz}Hz"),
             Self::WhiteNoise => write!(f, "White Noise"),
             Self::Square { frequency_hz } => write!(f, "Square {frequency_hz}Hz"),
+            Self::Saw { frequency_hz } => write!(f, "Saw {frequency_hz}Hz"),
+            Self::Triangle { frequency_hz } => write!(f, "Triangle {frequency_hz}Hz"),
+            Self::PinkNoise => write!(f, "Pink Noise"),
+            Self::BrownNoise => write!(f, "Brown Noise"),
+            Self::Sweep {
+                start_hz,
+                end_hz,
+                duration_seconds,
+                ..
+            } => write!(f, "Sweep {start_hz}Hz-{end_hz}Hz over {duration_seconds}s"),
         }
     }
 }
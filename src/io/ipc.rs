@@ -0,0 +1,405 @@
+//! Inter-process audio
+//!
+//! A shared audio bus carries interleaved audio from one process to
+//! another, e.g. from an engine process to a separate UI process that
+//! meters and records it, with one block of latency. The
+//! [`SharedBusWriter`] creates the bus under a name and a
+//! [`SharedBusReader`] in the other process opens it by that name.
+//!
+//! The audio sits in a ring in a file under `/dev/shm`, which is memory
+//! shared between processes, or the temporary directory where there is
+//! none. Mapping it would need unsafe code, which the crate doesn't allow,
+//! so both sides copy blocks in and out with positional reads and writes.
+//! After each block the writer signals the reader with a datagram holding
+//! the block's position, which is what wakes the reader, and the reader
+//! answers with how far it has read, so the writer never overwrites audio
+//! not yet read. Without a reader the writer writes on regardless, and a
+//! reader that falls more than the ring behind skips ahead.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use audio_engine::io::ipc::{SharedBusReader, SharedBusWriter};
+//! use audio_engine::types::{AudioFormat, Sample};
+//!
+//! // In the engine process
+//! let mut writer = SharedBusWriter::create("main-out", AudioFormat::default(), 4096)?;
+//! writer.write(&[Sample::SILENCE; 1024])?;
+//!
+//! // In the UI process
+//! let mut reader = SharedBusReader::open("main-out")?;
+//! let mut block = vec![Sample::SILENCE; 1024];
+//! let read = reader.read(&mut block, Duration::from_millis(100))?;
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! Unix only.
+
+use std::fs::{File, OpenOptions};
+use std::io::ErrorKind;
+use std::os::unix::fs::FileExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::{AudioEngineError, Result};
+use crate::types::{AudioFormat, BitDepth, ChannelCount, Sample, SampleRate};
+
+/// Identifies a bus file, and the layout version
+const MAGIC: [u8; 8] = *b"AEBUS\0\0\x01";
+/// Bytes before the ring; the header itself takes 24
+const HEADER_LEN: u64 = 64;
+const SAMPLE_BYTES: usize = size_of::<f32>();
+/// Writer to reader: block start and frame count
+const SIGNAL_LEN: usize = 16;
+/// Reader to writer: frames read so far
+const ACK_LEN: usize = 8;
+
+/// Paths of a bus's ring file and its two sockets
+#[derive(Debug, Clone)]
+struct BusPaths {
+    ring: PathBuf,
+    /// Where the reader receives block signals
+    signal: PathBuf,
+    /// Where the writer receives read positions
+    ack: PathBuf,
+}
+
+impl BusPaths {
+    fn new(name: &str) -> Result<Self> {
+        if name.is_empty() || name.contains(['/', '\0']) {
+            return Err(AudioEngineError::configuration(format!(
+                "invalid bus name {name:?}"
+            )));
+        }
+        let shm = Path::new("/dev/shm");
+        let dir = if shm.is_dir() {
+            shm.to_path_buf()
+        } else {
+            std::env::temp_dir()
+        };
+        let path = |suffix: &str| dir.join(format!("audio_engine-{name}.{suffix}"));
+        Ok(Self {
+            ring: path("bus"),
+            signal: path("signal"),
+            ack: path("ack"),
+        })
+    }
+}
+
+/// Binds a datagram socket at `path`, replacing one left behind.
+fn bind(path: &Path) -> Result<UnixDatagram> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    Ok(UnixDatagram::bind(path)?)
+}
+
+/// Byte offset in the ring file of a frame position, and how many of
+/// `frames` frames fit before the ring wraps
+fn ring_span(position: u64, frames: usize, capacity: u64, channels: usize) -> (u64, usize) {
+    let index = position % capacity;
+    let before_wrap = usize::try_from(capacity - index).unwrap_or(usize::MAX);
+    let offset = HEADER_LEN + index * (channels * SAMPLE_BYTES) as u64;
+    (offset, frames.min(before_wrap))
+}
+
+/// Writing end of a shared audio bus.
+#[derive(Debug)]
+pub struct SharedBusWriter {
+    paths: BusPaths,
+    file: File,
+    socket: UnixDatagram,
+    format: AudioFormat,
+    capacity: u64,
+    /// Frames written so far
+    position: u64,
+    /// Frames the reader has read, once it has said
+    read: Option<u64>,
+    bytes: Vec<u8>,
+}
+
+impl SharedBusWriter {
+    /// Creates the bus `name`, replacing one left behind, with a ring of
+    /// `capacity_frames` frames of `format` (the bit depth is always 32
+    /// bit float).
+    ///
+    /// # Errors
+    /// Returns an error if the name isn't a plain file name, the capacity
+    /// is zero, or the ring file or socket can't be created.
+    pub fn create(name: &str, format: AudioFormat, capacity_frames: usize) -> Result<Self> {
+        if capacity_frames == 0 {
+            return Err(AudioEngineError::configuration(
+                "a shared bus needs room for at least one frame",
+            ));
+        }
+        let paths = BusPaths::new(name)?;
+        let channels = format.channels.count_usize();
+        let capacity = capacity_frames as u64;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&paths.ring)?;
+        file.set_len(HEADER_LEN + capacity * (channels * SAMPLE_BYTES) as u64)?;
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&format.sample_rate.as_hz().to_le_bytes());
+        header.extend_from_slice(&format.channels.count().to_le_bytes());
+        header.extend_from_slice(&capacity.to_le_bytes());
+        file.write_all_at(&header, 0)?;
+        let socket = bind(&paths.ack)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            paths,
+            file,
+            socket,
+            format: AudioFormat {
+                bit_depth: BitDepth::F32,
+                ..format
+            },
+            capacity,
+            position: 0,
+            read: None,
+            bytes: vec![0; capacity_frames.min(8192) * channels * SAMPLE_BYTES],
+        })
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Frames written so far
+    #[must_use]
+    pub const fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Whether a reader has said how far it has read
+    #[must_use]
+    pub const fn has_reader(&self) -> bool {
+        self.read.is_some()
+    }
+
+    /// Writes whole frames of `samples` to the ring and signals the reader.
+    /// Returns the number of samples written, short of `samples` if the
+    /// reader is too far behind to make room.
+    ///
+    /// # Errors
+    /// Returns an error if the ring file can't be written.
+    pub fn write(&mut self, samples: &[Sample]) -> Result<usize> {
+        let mut ack = [0u8; ACK_LEN];
+        while self.socket.recv(&mut ack).is_ok_and(|len| len == ACK_LEN) {
+            let read = u64::from_le_bytes(ack);
+            self.read = Some(self.read.map_or(read, |known| known.max(read)));
+        }
+        let channels = self.format.channels.count_usize();
+        let free = self.read.map_or(self.capacity, |read| {
+            self.capacity
+                .saturating_sub(self.position.saturating_sub(read))
+        });
+        let frames = (samples.len() / channels).min(usize::try_from(free).unwrap_or(usize::MAX));
+        let start = self.position;
+
+        let mut done = 0;
+        while done < frames {
+            let (offset, span) = ring_span(self.position, frames - done, self.capacity, channels);
+            let span = span.min(self.bytes.len() / (channels * SAMPLE_BYTES));
+            let chunk = &samples[done * channels..(done + span) * channels];
+            let bytes = &mut self.bytes[..chunk.len() * SAMPLE_BYTES];
+            for (sample, out) in chunk.iter().zip(bytes.chunks_exact_mut(SAMPLE_BYTES)) {
+                out.copy_from_slice(&sample.value().to_le_bytes());
+            }
+            self.file.write_all_at(bytes, offset)?;
+            self.position += span as u64;
+            done += span;
+        }
+
+        if frames > 0 {
+            let mut signal = [0u8; SIGNAL_LEN];
+            signal[..8].copy_from_slice(&start.to_le_bytes());
+            signal[8..].copy_from_slice(&(frames as u64).to_le_bytes());
+            // Fails while no reader is listening, which is fine, but a
+            // reader that has gone no longer holds the writer back
+            if self.socket.send_to(&signal, &self.paths.signal).is_err() {
+                self.read = None;
+            }
+        }
+        Ok(frames * channels)
+    }
+}
+
+impl Drop for SharedBusWriter {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.paths.ring);
+        let _ = std::fs::remove_file(&self.paths.ack);
+    }
+}
+
+/// Reading end of a shared audio bus.
+#[derive(Debug)]
+pub struct SharedBusReader {
+    paths: BusPaths,
+    file: File,
+    socket: UnixDatagram,
+    format: AudioFormat,
+    capacity: u64,
+    /// Frames read so far, once the first signal says where to start
+    position: Option<u64>,
+    /// Frames the writer has written, as far as its signals say
+    written: u64,
+    /// Frames skipped because the reader fell behind
+    skipped: u64,
+    bytes: Vec<u8>,
+}
+
+impl SharedBusReader {
+    /// Opens the bus `name`, created by a [`SharedBusWriter`]. Reading
+    /// starts with the next block the writer writes.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such bus, its file isn't a bus, or
+    /// the socket can't be created.
+    pub fn open(name: &str) -> Result<Self> {
+        let paths = BusPaths::new(name)?;
+        let file = File::open(&paths.ring).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => AudioEngineError::configuration(format!("no bus named {name}")),
+            _ => e.into(),
+        })?;
+        let mut header = [0u8; 24];
+        file.read_exact_at(&mut header, 0)?;
+        if header[..8] != MAGIC {
+            return Err(AudioEngineError::UnsupportedFormat {
+                format: format!("{} isn't an audio bus", paths.ring.display()),
+            });
+        }
+        let word = |at: usize| {
+            u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+        };
+        let sample_rate = SampleRate::try_from(word(8))?;
+        let channels = ChannelCount::try_from(word(12))?;
+        let mut capacity = [0u8; 8];
+        capacity.copy_from_slice(&header[16..]);
+        let capacity = u64::from_le_bytes(capacity);
+        let socket = bind(&paths.signal)?;
+        let block = usize::try_from(capacity).unwrap_or(usize::MAX).min(8192);
+
+        Ok(Self {
+            file,
+            socket,
+            format: AudioFormat {
+                sample_rate,
+                channels,
+                bit_depth: BitDepth::F32,
+            },
+            capacity,
+            position: None,
+            written: 0,
+            skipped: 0,
+            bytes: vec![0; block * channels.count_usize() * SAMPLE_BYTES],
+            paths,
+        })
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Frames the reader skipped because it fell more than the ring behind
+    #[must_use]
+    pub const fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Frames written but not yet read
+    #[must_use]
+    pub fn available(&self) -> u64 {
+        self.position
+            .map_or(0, |position| self.written.saturating_sub(position))
+    }
+
+    /// Fills `out` with whole frames of what the writer has written,
+    /// waiting up to `timeout` for a block if none is waiting. Returns the
+    /// number of samples read, zero if nothing arrived in time.
+    ///
+    /// # Errors
+    /// Returns an error if the ring file can't be read or the socket fails.
+    pub fn read(&mut self, out: &mut [Sample], timeout: Duration) -> Result<usize> {
+        self.receive(false)?;
+        if self.available() == 0 && !timeout.is_zero() {
+            self.socket.set_read_timeout(Some(timeout))?;
+            self.receive(true)?;
+        }
+        let Some(mut position) = self.position else {
+            return Ok(0);
+        };
+        if self.written - position > self.capacity {
+            // Overwritten already
+            self.skipped += self.written - self.capacity - position;
+            position = self.written - self.capacity;
+        }
+
+        let channels = self.format.channels.count_usize();
+        let frames = (out.len() / channels)
+            .min(usize::try_from(self.written - position).unwrap_or(usize::MAX));
+        let mut done = 0;
+        while done < frames {
+            let (offset, span) = ring_span(position, frames - done, self.capacity, channels);
+            let span = span.min(self.bytes.len() / (channels * SAMPLE_BYTES));
+            let bytes = &mut self.bytes[..span * channels * SAMPLE_BYTES];
+            self.file.read_exact_at(bytes, offset)?;
+            let chunk = &mut out[done * channels..(done + span) * channels];
+            for (sample, bytes) in chunk.iter_mut().zip(bytes.chunks_exact(SAMPLE_BYTES)) {
+                *sample = Sample::new(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+            }
+            position += span as u64;
+            done += span;
+        }
+        self.position = Some(position);
+        if frames > 0 {
+            let _ = self
+                .socket
+                .send_to(&position.to_le_bytes(), &self.paths.ack);
+        }
+        Ok(frames * channels)
+    }
+
+    /// Takes in the writer's signals: all waiting ones, after blocking for
+    /// the first if `wait`.
+    fn receive(&mut self, wait: bool) -> Result<()> {
+        let mut signal = [0u8; SIGNAL_LEN];
+        self.socket.set_nonblocking(!wait)?;
+        loop {
+            match self.socket.recv(&mut signal) {
+                Ok(SIGNAL_LEN) => {
+                    let mut word = [0u8; 8];
+                    word.copy_from_slice(&signal[..8]);
+                    let start = u64::from_le_bytes(word);
+                    word.copy_from_slice(&signal[8..]);
+                    let frames = u64::from_le_bytes(word);
+                    // Join at the start of the first block signalled
+                    self.position.get_or_insert(start);
+                    self.written = self.written.max(start + frames);
+                }
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            }
+            self.socket.set_nonblocking(true)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SharedBusReader {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.paths.signal);
+    }
+}
@@ -0,0 +1,630 @@
+//! Adaptive jitter buffer for packet audio
+//!
+//! A network receiver hands each packet it decodes, with its sequence
+//! number and timestamp, to a [`JitterInput`]; the audio thread reads the
+//! stream back in order from the matching [`JitterBuffer`]. In between:
+//!
+//! - Packets that arrive out of order are put back in order while they are
+//!   within half the target latency. A packet that doesn't turn up by then
+//!   counts as lost.
+//! - A single lost packet is concealed by playing the one before it back
+//!   and forth, fading out, and fading the next one in. Longer losses
+//!   become silence.
+//! - The interarrival jitter is measured as in RFC 3550, and the target
+//!   latency follows it: [`jitter_factor`](JitterSettings::jitter_factor)
+//!   times the jitter plus a packet, within
+//!   [`min_latency`](JitterSettings::min_latency) and
+//!   [`max_latency`](JitterSettings::max_latency).
+//! - The reader moves towards the target by dropping or repeating one frame
+//!   in every hundred, which is too little to hear, rather than jumping.
+//!   When the buffer runs dry it plays silence until it has filled up to
+//!   the target again.
+//!
+//! A buffer given a feedback sender with
+//! [`with_feedback`](JitterBuffer::with_feedback) reports each move of the
+//! target by a millisecond or more as [`EngineFeedback::NetworkLatency`].
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use audio_engine::io::jitter::{JitterBuffer, JitterSettings};
+//! use audio_engine::types::{AudioFormat, Sample};
+//!
+//! let settings = JitterSettings::default().with_min_latency(Duration::from_millis(5));
+//! let (mut input, mut buffer) = JitterBuffer::new(AudioFormat::default(), settings)?;
+//!
+//! // Network thread: 1 ms packets, the second one late
+//! let packet = vec![Sample::new(0.25); 96];
+//! input.push(0, 0, &packet);
+//! input.push(2, 96, &packet);
+//! input.push(1, 48, &packet);
+//!
+//! // Audio thread
+//! let mut block = vec![Sample::SILENCE; 128];
+//! buffer.read(&mut block);
+//! println!("{}", buffer.stats());
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! [`EngineFeedback::NetworkLatency`]: crate::channel::EngineFeedback::NetworkLatency
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::buffer::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::channel::{EngineFeedback, RealtimeSender};
+use crate::error::{AudioEngineError, Result};
+use crate::types::{AudioFormat, Sample};
+
+/// Packets held for reordering before the oldest is given up on
+const MAX_HELD: usize = 64;
+
+/// The reader drops or repeats one frame in this many while it adapts
+const ADAPT_PERIOD: usize = 100;
+
+/// Longest fade into a packet after a loss, in milliseconds
+const FADE_MS: u32 = 3;
+
+/// Audio the ring holds on top of twice the longest latency
+const HEADROOM_MS: u32 = 100;
+
+/// Jitter is kept in microseconds
+const MICROS: f64 = 1_000_000.0;
+
+/// Latency bounds of a [`JitterBuffer`] and how closely it follows jitter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JitterSettings {
+    /// Latency kept however steady the stream is
+    pub min_latency: Duration,
+    /// Latency never exceeded however bad the jitter gets
+    pub max_latency: Duration,
+    /// Multiple of the measured jitter kept buffered on top of a packet
+    pub jitter_factor: f32,
+}
+
+impl Default for JitterSettings {
+    fn default() -> Self {
+        Self {
+            min_latency: Duration::from_millis(10),
+            max_latency: Duration::from_millis(250),
+            jitter_factor: 4.0,
+        }
+    }
+}
+
+impl JitterSettings {
+    #[must_use]
+    pub const fn with_min_latency(mut self, latency: Duration) -> Self {
+        self.min_latency = latency;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_max_latency(mut self, latency: Duration) -> Self {
+        self.max_latency = latency;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_jitter_factor(mut self, factor: f32) -> Self {
+        self.jitter_factor = factor;
+        self
+    }
+
+    /// Checks the bounds make sense.
+    ///
+    /// # Errors
+    /// Returns an error if the maximum latency is zero or below the
+    /// minimum, or the jitter factor is negative or not finite.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_latency.is_zero() || self.max_latency < self.min_latency {
+            return Err(AudioEngineError::configuration(format!(
+                "jitter buffer latency bounds {:?} to {:?} are empty",
+                self.min_latency, self.max_latency
+            )));
+        }
+        if !self.jitter_factor.is_finite() || self.jitter_factor < 0.0 {
+            return Err(AudioEngineError::configuration(format!(
+                "jitter factor must be zero or more, not {}",
+                self.jitter_factor
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Statistics of a [`JitterBuffer`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JitterStats {
+    pub packets: u64,
+    /// Packets that never arrived, or arrived too late to reorder
+    pub lost: u64,
+    /// Lost packets filled in from the one before
+    pub concealed: u64,
+    /// Packets that arrived after their turn, or twice
+    pub late: u64,
+    /// Times the buffer ran dry and playing paused to fill it again
+    pub underruns: u64,
+    /// Frames repeated to grow the latency
+    pub stretched: u64,
+    /// Frames dropped to shrink the latency, or because the buffer was full
+    pub skipped: u64,
+    /// Interarrival jitter (RFC 3550)
+    pub jitter: Duration,
+    /// Latency the buffer is moving towards
+    pub target: Duration,
+    /// Audio buffered now
+    pub buffered: Duration,
+}
+
+impl fmt::Display for JitterStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} packets, {} lost ({} concealed), {} late, {:.1} ms jitter, \
+             {:.1} ms buffered of {:.1} ms, {} underruns",
+            self.packets,
+            self.lost,
+            self.concealed,
+            self.late,
+            self.jitter.as_secs_f64() * 1000.0,
+            self.buffered.as_secs_f64() * 1000.0,
+            self.target.as_secs_f64() * 1000.0,
+            self.underruns
+        )
+    }
+}
+
+/// State the network side shares with the reading side
+#[derive(Default)]
+struct Shared {
+    packets: AtomicU64,
+    lost: AtomicU64,
+    concealed: AtomicU64,
+    late: AtomicU64,
+    /// Frames dropped because the ring was full
+    overflowed: AtomicU64,
+    /// Interarrival jitter in microseconds
+    jitter: AtomicU32,
+    /// Samples the reader aims to keep buffered
+    target: AtomicUsize,
+}
+
+/// A packet waiting for its turn
+struct Held {
+    arrived: Instant,
+    timestamp: u64,
+    samples: Vec<Sample>,
+}
+
+/// The network side of a [`JitterBuffer`]: takes packets in any order.
+pub struct JitterInput {
+    buffer: RingBufferWriter<Sample>,
+    shared: Arc<Shared>,
+    settings: JitterSettings,
+    channels: usize,
+    rate: f64,
+    started: Instant,
+    held: BTreeMap<u64, Held>,
+    /// Sequence number of the next packet to play
+    next: Option<u64>,
+    /// Timestamp the next packet should carry
+    next_timestamp: Option<u64>,
+    /// Relative transit time of the last packet, in seconds
+    transit: Option<f64>,
+    /// Interarrival jitter in seconds
+    jitter: f64,
+    /// Frames in the last packet played
+    packet_frames: usize,
+    /// The last packet played, repeated to conceal a loss
+    last: Vec<Sample>,
+    /// Frames of silence or concealment plus the packet being released
+    scratch: Vec<Sample>,
+    fade_frames: usize,
+    max_gap_frames: u64,
+}
+
+impl JitterInput {
+    /// Takes in a packet of interleaved `samples` that arrived just now.
+    /// `sequence` counts packets and `timestamp` frames, both without
+    /// wrapping: extend 16 or 32 bit counters first.
+    pub fn push(&mut self, sequence: u64, timestamp: u64, samples: &[Sample]) {
+        self.push_at(sequence, timestamp, samples, Instant::now());
+    }
+
+    /// Like [`push`](Self::push), for a packet that arrived at `arrived`.
+    pub fn push_at(&mut self, sequence: u64, timestamp: u64, samples: &[Sample], arrived: Instant) {
+        self.shared.packets.fetch_add(1, Ordering::Relaxed);
+        self.update_jitter(timestamp, arrived);
+        let next = *self.next.get_or_insert(sequence);
+        if sequence < next || self.held.contains_key(&sequence) {
+            self.shared.late.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let whole = samples.len() - samples.len() % self.channels;
+        self.held.insert(
+            sequence,
+            Held {
+                arrived,
+                timestamp,
+                samples: samples[..whole].to_vec(),
+            },
+        );
+        self.release(arrived);
+    }
+
+    /// Plays the held packets whose wait for the ones before them is over.
+    /// Call it every few milliseconds while no packets arrive.
+    pub fn flush(&mut self) {
+        self.release(Instant::now());
+    }
+
+    /// Forgets the stream so far, e.g. when its source changes. Packets
+    /// already passed to the reader still play.
+    pub fn reset(&mut self) {
+        self.held.clear();
+        self.next = None;
+        self.next_timestamp = None;
+        self.transit = None;
+        self.last.clear();
+    }
+
+    /// Interarrival jitter measured so far
+    #[must_use]
+    pub fn jitter(&self) -> Duration {
+        Duration::from_secs_f64(self.jitter)
+    }
+
+    /// Updates the jitter estimate (RFC 3550 A.8) and the target latency.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn update_jitter(&mut self, timestamp: u64, arrived: Instant) {
+        let transit =
+            arrived.duration_since(self.started).as_secs_f64() - timestamp as f64 / self.rate;
+        if let Some(previous) = self.transit {
+            self.jitter += ((transit - previous).abs() - self.jitter) / 16.0;
+            let micros = (self.jitter * MICROS).min(f64::from(u32::MAX));
+            self.shared.jitter.store(micros as u32, Ordering::Relaxed);
+        }
+        self.transit = Some(transit);
+
+        let packet = self.packet_frames as f64 / self.rate;
+        let wanted = f64::from(self.settings.jitter_factor).mul_add(self.jitter, packet);
+        let latency = wanted.clamp(
+            self.settings.min_latency.as_secs_f64(),
+            self.settings.max_latency.as_secs_f64(),
+        );
+        let frames = (latency * self.rate).round() as usize;
+        self.shared
+            .target
+            .store(frames.max(1) * self.channels, Ordering::Relaxed);
+    }
+
+    fn target(&self) -> Duration {
+        let frames = self.shared.target.load(Ordering::Relaxed) / self.channels;
+        #[allow(clippy::cast_precision_loss)]
+        Duration::from_secs_f64(frames as f64 / self.rate)
+    }
+
+    fn release(&mut self, now: Instant) {
+        let wait = self.target() / 2;
+        while let Some((&sequence, oldest)) = self.held.first_key_value() {
+            let Some(next) = self.next else {
+                break;
+            };
+            let overdue = now.duration_since(oldest.arrived) >= wait;
+            if sequence != next && !overdue && self.held.len() <= MAX_HELD {
+                break;
+            }
+            let Some((_, held)) = self.held.pop_first() else {
+                break;
+            };
+            let missing = sequence - next;
+            self.shared.lost.fetch_add(missing, Ordering::Relaxed);
+            self.next = Some(sequence + 1);
+            self.deliver(&held, missing);
+        }
+    }
+
+    /// Sends `held` to the reader after whatever fills in for the `missing`
+    /// packets before it.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn deliver(&mut self, held: &Held, missing: u64) {
+        let channels = self.channels;
+        let gap = self.next_timestamp.map_or(0, |expected| {
+            held.timestamp
+                .saturating_sub(expected)
+                .min(self.max_gap_frames) as usize
+        });
+        self.scratch.clear();
+        if gap > 0 {
+            if missing == 1 && !self.last.is_empty() {
+                // Play the packet before backwards, then forwards and so
+                // on, so it joins on without a step, fading out over the gap
+                self.shared.concealed.fetch_add(1, Ordering::Relaxed);
+                let frames = self.last.chunks_exact(channels);
+                let repeated = frames.clone().rev().chain(frames).cycle().take(gap);
+                for (index, frame) in repeated.enumerate() {
+                    let gain = 1.0 - index as f32 / gap as f32;
+                    self.scratch.extend(
+                        frame
+                            .iter()
+                            .map(|sample| Sample::new(sample.value() * gain)),
+                    );
+                }
+            } else {
+                self.scratch.resize(gap * channels, Sample::SILENCE);
+            }
+        }
+        let start = self.scratch.len();
+        self.scratch.extend_from_slice(&held.samples);
+        if gap > 0 {
+            let fade = self.fade_frames.min(held.samples.len() / channels).max(1);
+            for (index, frame) in self.scratch[start..]
+                .chunks_exact_mut(channels)
+                .take(fade)
+                .enumerate()
+            {
+                let gain = index as f32 / fade as f32;
+                for sample in frame {
+                    *sample = Sample::new(sample.value() * gain);
+                }
+            }
+        }
+
+        if self.buffer.slots() >= self.scratch.len() {
+            self.buffer.push_slice(&self.scratch);
+        } else {
+            let frames = (self.scratch.len() / channels) as u64;
+            self.shared.overflowed.fetch_add(frames, Ordering::Relaxed);
+        }
+        let frames = held.samples.len() / channels;
+        if frames > 0 {
+            self.packet_frames = frames;
+            self.last.clear();
+            self.last.extend_from_slice(&held.samples);
+        }
+        self.next_timestamp = Some(held.timestamp + frames as u64);
+    }
+}
+
+impl fmt::Debug for JitterInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JitterInput")
+            .field("settings", &self.settings)
+            .field("held", &self.held.len())
+            .field("next", &self.next)
+            .field("jitter", &self.jitter())
+            .finish_non_exhaustive()
+    }
+}
+
+/// How the reader is moving the latency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Adapt {
+    Hold,
+    Grow,
+    Shrink,
+}
+
+/// The audio thread side of a jitter buffer: plays the stream back in
+/// order at a latency that follows the jitter.
+pub struct JitterBuffer {
+    buffer: RingBufferReader<Sample>,
+    shared: Arc<Shared>,
+    format: AudioFormat,
+    channels: usize,
+    /// Whether the buffer is filling up to the target before playing
+    priming: bool,
+    underruns: u64,
+    stretched: u64,
+    skipped: u64,
+    feedback: Option<RealtimeSender<EngineFeedback>>,
+    /// Target at the last report, in samples
+    reported: usize,
+}
+
+impl JitterBuffer {
+    /// Creates a jitter buffer for a stream in `format`, returning the
+    /// side packets are pushed to and the side the stream is read from.
+    ///
+    /// # Errors
+    /// Returns an error if the settings are invalid; see
+    /// [`JitterSettings::validate`].
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn new(format: AudioFormat, settings: JitterSettings) -> Result<(JitterInput, Self)> {
+        settings.validate()?;
+        let channels = format.channels.count_usize();
+        let rate = f64::from(format.sample_rate.as_hz());
+        let max_frames = (settings.max_latency.as_secs_f64() * rate).round() as usize;
+        let headroom = format.sample_rate.samples_for_milliseconds(HEADROOM_MS) as usize;
+        let (writer, reader) = RingBuffer::new((max_frames * 2 + headroom) * channels);
+
+        let shared = Arc::new(Shared::default());
+        let min_frames = (settings.min_latency.as_secs_f64() * rate).round() as usize;
+        let target = min_frames.max(1) * channels;
+        shared.target.store(target, Ordering::Relaxed);
+        let input = JitterInput {
+            buffer: writer,
+            shared: Arc::clone(&shared),
+            settings,
+            channels,
+            rate,
+            started: Instant::now(),
+            held: BTreeMap::new(),
+            next: None,
+            next_timestamp: None,
+            transit: None,
+            jitter: 0.0,
+            packet_frames: 0,
+            last: Vec::new(),
+            scratch: Vec::new(),
+            fade_frames: format.sample_rate.samples_for_milliseconds(FADE_MS) as usize,
+            max_gap_frames: max_frames as u64,
+        };
+        let buffer = Self {
+            buffer: reader,
+            shared,
+            format,
+            channels,
+            priming: true,
+            underruns: 0,
+            stretched: 0,
+            skipped: 0,
+            feedback: None,
+            reported: target,
+        };
+        Ok((input, buffer))
+    }
+
+    /// Reports moves of the target latency on `feedback`.
+    #[must_use]
+    pub fn with_feedback(mut self, feedback: RealtimeSender<EngineFeedback>) -> Self {
+        self.feedback = Some(feedback);
+        self
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Fills `out` with the next interleaved samples, returning how many
+    /// came from the stream. The rest are silence: while the buffer fills
+    /// up to the target latency at the start and after an underrun.
+    pub fn read(&mut self, out: &mut [Sample]) -> usize {
+        let target = self.shared.target.load(Ordering::Relaxed);
+        let available = self.buffer.slots();
+        if self.priming {
+            if available < target {
+                out.fill(Sample::SILENCE);
+                self.report(target);
+                return 0;
+            }
+            self.priming = false;
+        }
+
+        // Hold within a quarter of the target either way
+        let slack = (target / 4).max(self.channels);
+        let adapt = if available > target + slack {
+            Adapt::Shrink
+        } else if available + slack < target {
+            Adapt::Grow
+        } else {
+            Adapt::Hold
+        };
+        let channels = self.channels;
+        let period = ADAPT_PERIOD * channels;
+        let mut read = 0;
+        let len = out.len();
+        while read < len {
+            let chunk = &mut out[read..(read + period).min(len)];
+            let popped = match adapt {
+                Adapt::Shrink => {
+                    let popped = self.buffer.pop_slice(chunk);
+                    if popped == chunk.len() && self.buffer.slots() >= channels {
+                        self.skipped += (self.buffer.discard(channels) / channels) as u64;
+                    }
+                    popped
+                }
+                Adapt::Grow if chunk.len() > channels => {
+                    let (body, last) = chunk.split_at_mut(chunk.len() - channels);
+                    let popped = self.buffer.pop_slice(body);
+                    if popped == body.len() {
+                        last.copy_from_slice(&body[body.len() - channels..]);
+                        self.stretched += 1;
+                        popped + channels
+                    } else {
+                        popped
+                    }
+                }
+                // Holding, or a chunk too short to stretch
+                Adapt::Hold | Adapt::Grow => self.buffer.pop_slice(chunk),
+            };
+            read += popped;
+            if popped < chunk.len() {
+                break;
+            }
+        }
+        if read < out.len() {
+            out[read..].fill(Sample::SILENCE);
+            self.underruns += 1;
+            self.priming = true;
+        }
+        self.report(target);
+        read
+    }
+
+    /// Audio buffered now
+    #[must_use]
+    pub fn latency(&self) -> Duration {
+        self.samples_duration(self.buffer.slots())
+    }
+
+    /// Latency the buffer is moving towards
+    #[must_use]
+    pub fn target_latency(&self) -> Duration {
+        self.samples_duration(self.shared.target.load(Ordering::Relaxed))
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> JitterStats {
+        JitterStats {
+            packets: self.shared.packets.load(Ordering::Relaxed),
+            lost: self.shared.lost.load(Ordering::Relaxed),
+            concealed: self.shared.concealed.load(Ordering::Relaxed),
+            late: self.shared.late.load(Ordering::Relaxed),
+            underruns: self.underruns,
+            stretched: self.stretched,
+            skipped: self.skipped + self.shared.overflowed.load(Ordering::Relaxed),
+            jitter: self.jitter(),
+            target: self.target_latency(),
+            buffered: self.latency(),
+        }
+    }
+
+    fn jitter(&self) -> Duration {
+        Duration::from_micros(u64::from(self.shared.jitter.load(Ordering::Relaxed)))
+    }
+
+    fn samples_duration(&self, samples: usize) -> Duration {
+        let frames = samples / self.channels;
+        #[allow(clippy::cast_precision_loss)]
+        Duration::from_secs_f64(frames as f64 / f64::from(self.format.sample_rate.as_hz()))
+    }
+
+    /// Reports the target if it moved by a millisecond or more since the
+    /// last report.
+    fn report(&mut self, target: usize) {
+        let Some(feedback) = &self.feedback else {
+            return;
+        };
+        let step = self.format.sample_rate.samples_for_milliseconds(1) as usize * self.channels;
+        if target.abs_diff(self.reported) < step {
+            return;
+        }
+        let sent = feedback.try_send(EngineFeedback::NetworkLatency {
+            latency: self.samples_duration(target),
+            jitter: self.jitter(),
+        });
+        if sent {
+            self.reported = target;
+        }
+    }
+}
+
+impl fmt::Debug for JitterBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JitterBuffer")
+            .field("format", &self.format)
+            .field("priming", &self.priming)
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,253 @@
+//! Now-playing metadata for network streams
+//!
+//! A [`NowPlaying`] holds the title and artist of what is on air. Each
+//! streaming protocol carries it differently, so it encodes to each
+//! [`MetadataFormat`]:
+//!
+//! - [`Icy`](MetadataFormat::Icy): the `StreamTitle='Artist - Title';`
+//!   block Icecast and Shoutcast servers interleave with the audio for
+//!   their listeners.
+//! - [`Id3`](MetadataFormat::Id3): an ID3v2.4 tag with `TIT2` and `TPE1`
+//!   frames, the timed metadata HLS packagers put in front of a segment.
+//! - [`Amf`](MetadataFormat::Amf): an AMF0 `@setDataFrame` `onMetaData`
+//!   data message, as RTMP servers expect.
+//!
+//! [`NetworkOutput::metadata_format`] picks the one for an output's url,
+//! and [`NetworkOutput::with_now_playing`] sets what a stream starts with.
+//!
+//! A [`MetadataSink`] takes updates while a stream runs: an
+//! [`IcecastMetadata`](crate::io::icecast::IcecastMetadata) handle sends
+//! them to an Icecast or Shoutcast server, an
+//! [`RtmpMetadata`](crate::io::rtmp::RtmpMetadata) handle to an RTMP
+//! server, and a closure can hand the encoded bytes to a packager. Sinks added to an engine with
+//! [`Engine::add_metadata_sink`](crate::engine::Engine::add_metadata_sink)
+//! all get [`Engine::set_now_playing`](crate::engine::Engine::set_now_playing).
+//!
+//! ```
+//! use audio_engine::io::metadata::{MetadataFormat, NowPlaying};
+//!
+//! let now = NowPlaying::new("Blue in Green").with_artist("Miles Davis");
+//! assert_eq!(now.stream_title(), "Miles Davis - Blue in Green");
+//! assert!(now.encode(MetadataFormat::Id3).starts_with(b"ID3"));
+//! ```
+//!
+//! [`NetworkOutput::metadata_format`]: crate::io::NetworkOutput::metadata_format
+//! [`NetworkOutput::with_now_playing`]: crate::io::NetworkOutput::with_now_playing
+
+use std::fmt;
+
+use crate::error::Result;
+use crate::types::NetworkProtocol;
+
+/// ICY blocks count their length in units of this many bytes
+const ICY_UNIT: usize = 16;
+/// Longest ICY block, as its length byte allows
+const ICY_MAX: usize = u8::MAX as usize * ICY_UNIT;
+/// ID3 sizes are 28 bit syncsafe integers
+const ID3_MAX: usize = (1 << 28) - 1;
+/// ID3 text encoding byte for UTF-8
+const ID3_UTF8: u8 = 3;
+/// AMF0 type markers
+const AMF_STRING: u8 = 0x02;
+const AMF_ECMA_ARRAY: u8 = 0x08;
+const AMF_OBJECT_END: u8 = 0x09;
+const AMF_LONG_STRING: u8 = 0x0C;
+
+/// What is on air.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NowPlaying {
+    pub title: String,
+    pub artist: Option<String>,
+}
+
+impl NowPlaying {
+    #[must_use]
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            artist: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_artist(mut self, artist: impl Into<String>) -> Self {
+        self.artist = Some(artist.into());
+        self
+    }
+
+    /// The title as players show it, `Artist - Title` when the artist is
+    /// known
+    #[must_use]
+    pub fn stream_title(&self) -> String {
+        match &self.artist {
+            Some(artist) if !artist.is_empty() => format!("{artist} - {}", self.title),
+            _ => self.title.clone(),
+        }
+    }
+
+    /// The bytes that carry this in `format`. Titles too long for the
+    /// format are cut short at a character boundary.
+    #[must_use]
+    pub fn encode(&self, format: MetadataFormat) -> Vec<u8> {
+        match format {
+            MetadataFormat::Icy => self.icy_block(),
+            MetadataFormat::Id3 => self.id3_tag(),
+            MetadataFormat::Amf => self.amf_data(),
+        }
+    }
+
+    /// A length byte in 16 byte units, then the text padded with zeros
+    fn icy_block(&self) -> Vec<u8> {
+        let title = self.stream_title();
+        let title = truncate(&title, ICY_MAX - "StreamTitle='';".len());
+        let text = format!("StreamTitle='{title}';");
+        let units = text.len().div_ceil(ICY_UNIT);
+        let mut block = Vec::with_capacity(1 + units * ICY_UNIT);
+        // At most 255 units, as truncated above
+        #[allow(clippy::cast_possible_truncation)]
+        block.push(units as u8);
+        block.extend_from_slice(text.as_bytes());
+        block.resize(1 + units * ICY_UNIT, 0);
+        block
+    }
+
+    fn id3_tag(&self) -> Vec<u8> {
+        let mut frames = Vec::new();
+        id3_text_frame(&mut frames, *b"TIT2", &self.title);
+        if let Some(artist) = &self.artist {
+            id3_text_frame(&mut frames, *b"TPE1", artist);
+        }
+        let mut tag = Vec::with_capacity(10 + frames.len());
+        // Version 2.4.0, no flags
+        tag.extend_from_slice(b"ID3\x04\x00\x00");
+        tag.extend_from_slice(&syncsafe(frames.len()));
+        tag.extend_from_slice(&frames);
+        tag
+    }
+
+    fn amf_data(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        amf_string(&mut data, "@setDataFrame");
+        amf_string(&mut data, "onMetaData");
+        let mut entries = vec![("title", self.title.as_str())];
+        if let Some(artist) = &self.artist {
+            entries.push(("artist", artist));
+        }
+        data.push(AMF_ECMA_ARRAY);
+        data.extend_from_slice(&u32::try_from(entries.len()).unwrap_or(0).to_be_bytes());
+        for (key, value) in entries {
+            amf_key(&mut data, key);
+            amf_string(&mut data, value);
+        }
+        amf_key(&mut data, "");
+        data.push(AMF_OBJECT_END);
+        data
+    }
+}
+
+impl fmt::Display for NowPlaying {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.stream_title())
+    }
+}
+
+/// How a protocol carries now-playing metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MetadataFormat {
+    /// ICY `StreamTitle` blocks, for Icecast and Shoutcast
+    Icy,
+    /// ID3v2.4 tags, for HLS segments
+    Id3,
+    /// AMF0 `onMetaData` data messages, for RTMP
+    Amf,
+}
+
+impl MetadataFormat {
+    /// The format `protocol` carries, if any. RTP, SRT and RIST carry no
+    /// now-playing metadata of their own.
+    #[must_use]
+    pub const fn for_protocol(protocol: NetworkProtocol) -> Option<Self> {
+        match protocol {
+            NetworkProtocol::Icecast | NetworkProtocol::Shoutcast => Some(Self::Icy),
+            NetworkProtocol::HLS => Some(Self::Id3),
+            NetworkProtocol::RTMP => Some(Self::Amf),
+            NetworkProtocol::RTP | NetworkProtocol::SRT | NetworkProtocol::RIST => None,
+        }
+    }
+}
+
+impl fmt::Display for MetadataFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Icy => write!(f, "ICY"),
+            Self::Id3 => write!(f, "ID3"),
+            Self::Amf => write!(f, "AMF"),
+        }
+    }
+}
+
+/// Takes now-playing updates for a running stream.
+///
+/// Closures taking a [`NowPlaying`] are sinks too, e.g. to hand
+/// [`NowPlaying::encode`]'s bytes to an HLS packager.
+pub trait MetadataSink: Send {
+    /// Publishes `now` on the stream.
+    ///
+    /// # Errors
+    /// Returns an error if the update couldn't be delivered.
+    fn set_now_playing(&mut self, now: &NowPlaying) -> Result<()>;
+}
+
+impl<F: FnMut(&NowPlaying) -> Result<()> + Send> MetadataSink for F {
+    fn set_now_playing(&mut self, now: &NowPlaying) -> Result<()> {
+        self(now)
+    }
+}
+
+/// `text` cut to at most `max` bytes without splitting a character
+fn truncate(text: &str, max: usize) -> &str {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// `size` as four bytes of seven bits each
+fn syncsafe(size: usize) -> [u8; 4] {
+    let size = size.min(ID3_MAX);
+    // Each byte is masked to seven bits
+    #[allow(clippy::cast_possible_truncation)]
+    [21, 14, 7, 0].map(|shift| ((size >> shift) & 0x7F) as u8)
+}
+
+fn id3_text_frame(frames: &mut Vec<u8>, id: [u8; 4], text: &str) {
+    // The encoding byte counts towards the frame size
+    let text = truncate(text, ID3_MAX - 1);
+    frames.extend_from_slice(&id);
+    frames.extend_from_slice(&syncsafe(1 + text.len()));
+    frames.extend_from_slice(&[0, 0]);
+    frames.push(ID3_UTF8);
+    frames.extend_from_slice(text.as_bytes());
+}
+
+/// A property name: a string without its type marker
+fn amf_key(data: &mut Vec<u8>, key: &str) {
+    let key = truncate(key, usize::from(u16::MAX));
+    data.extend_from_slice(&u16::try_from(key.len()).unwrap_or(0).to_be_bytes());
+    data.extend_from_slice(key.as_bytes());
+}
+
+fn amf_string(data: &mut Vec<u8>, text: &str) {
+    let text = truncate(text, u32::MAX as usize);
+    if let Ok(len) = u16::try_from(text.len()) {
+        data.push(AMF_STRING);
+        data.extend_from_slice(&len.to_be_bytes());
+    } else {
+        data.push(AMF_LONG_STRING);
+        data.extend_from_slice(&u32::try_from(text.len()).unwrap_or(0).to_be_bytes());
+    }
+    data.extend_from_slice(text.as_bytes());
+}
@@ -3,8 +3,78 @@
 //! This module defines strongly typed enums for all supported
 //! input sources and output targets.
 
+pub mod aiff;
+pub mod bwf;
+pub mod cache;
+pub mod caf;
+pub mod cue;
+#[cfg(feature = "symphonia")]
+pub mod decode;
+#[cfg(any(
+    feature = "mp3",
+    feature = "opus",
+    feature = "srt",
+    feature = "whip",
+    feature = "rtmp"
+))]
+mod encoder;
+pub mod generator;
+#[cfg(feature = "symphonia")]
+pub mod hls;
+#[cfg(any(feature = "mp3", feature = "opus"))]
+pub mod icecast;
 pub mod input;
+#[cfg(unix)]
+pub mod ipc;
+pub mod jitter;
+pub mod metadata;
+#[cfg(feature = "mp3")]
+pub mod mp3;
+#[cfg(feature = "opus")]
+pub mod opus;
 pub mod output;
+pub mod pcm;
+pub mod playlist;
+pub mod preview;
+pub mod probe;
+#[cfg(feature = "rtmp")]
+pub mod rtmp;
+pub mod rtp;
+pub mod sampler;
+pub mod srt;
+pub mod streamer;
+pub mod wav;
+pub mod whip;
 
-pub use input::{FileInput, InputSource, NetworkInput};
-pub use output::{FileOutput, NetworkOutput, OutputTarget};
+pub use bwf::BroadcastInfo;
+pub use cache::{BlockSource, CacheSettings, CacheStats, FileCache, PrefetchHint};
+pub use cue::{CueMarker, MarkerCursor, MarkerList};
+pub use generator::SignalSource;
+#[cfg(feature = "symphonia")]
+pub use hls::{HlsStats, HlsStream};
+#[cfg(any(feature = "mp3", feature = "opus"))]
+pub use icecast::{ConnectionEvent, IcecastMetadata, IcecastSource};
+pub use input::{FileInput, InputSource, NetworkInput, SignalGenerator, SweepCurve};
+pub use jitter::{JitterBuffer, JitterInput, JitterSettings, JitterStats};
+pub use metadata::{MetadataFormat, MetadataSink, NowPlaying};
+pub use output::{
+    FileOutput, NetworkOutput, OutputTarget, ReconnectPolicy, RotationPolicy, StreamCredentials,
+    StreamInfo,
+};
+pub use pcm::{ByteOrder, PcmReader, PcmWriter};
+pub use playlist::{Playlist, PlaylistEvent, PlaylistPlayer, PlaylistSettings};
+pub use preview::{Preview, PreviewSettings};
+pub use probe::{FileInfo, probe_file};
+#[cfg(feature = "rtmp")]
+pub use rtmp::{RtmpEvent, RtmpPublisher};
+pub use rtp::{RtpReceiver, RtpSender, RtpSettings};
+pub use sampler::{
+    SampleId, Sampler, SamplerPlayer, SamplerSettings, Trigger, VoiceId, VoiceStealing,
+};
+#[cfg(feature = "srt")]
+pub use srt::SrtSender;
+pub use srt::{SrtMode, SrtSettings};
+pub use streamer::{FileStreamer, StreamSource, StreamerHealth, StreamerSettings};
+#[cfg(feature = "whip")]
+pub use whip::WhipSender;
+pub use whip::WhipSettings;
@@ -0,0 +1,261 @@
+//! MP3 encoding
+//!
+//! An [`Mp3Encoder`] turns interleaved samples into an MP3 file with the
+//! LAME encoder, run as a `lame` process that is fed 16 bit PCM on its
+//! stdin. Encoding happens off the audio thread: [`write`](Mp3Encoder::write)
+//! only pushes into a ring buffer, which a worker thread drains into the
+//! encoder, so it is safe to call from the device callback. Samples that
+//! don't fit because the encoder fell behind are dropped and counted. For a
+//! [`NetworkOutput`] the encoded frames come back on a pipe to send on.
+//!
+//! [`Mp3Settings`] map onto the encoder's options: the bitrate is encoded
+//! at a constant rate and has to be one MP3 allows, and the quality is
+//! LAME's algorithm quality, 0 being the best and slowest.
+//!
+//! Needs the `mp3` feature, and `lame` on the `PATH` at run time.
+
+use std::fmt;
+use std::path::Path;
+use std::process::ChildStdout;
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::encoder::{EncoderOutput, EncoderProcess};
+use crate::io::output::{Mp3Settings, NetworkOutput, StreamCodec};
+use crate::types::{AudioFormat, ChannelCount, Sample, SampleRate, StreamBitrate};
+
+/// Program the encoder runs
+const ENCODER_PROGRAM: &str = "lame";
+
+/// Constant bitrates MP3 allows at 32, 44.1 and 48 kHz, in kbps
+const BITRATES_KBPS: [u32; 14] = [
+    32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+
+/// Highest sample rate MP3 encodes; faster input is resampled to it
+const MAX_RATE_HZ: u32 = 48_000;
+
+/// Encoder options for `settings` on input in `format`.
+///
+/// # Errors
+/// Returns an error if the bitrate isn't one MP3 allows, the quality is
+/// out of range, or there are more than two channels.
+pub fn encoder_args(settings: &Mp3Settings, format: AudioFormat) -> Result<Vec<String>> {
+    let kbps = settings.bitrate.as_kbps();
+    if StreamBitrate::from_kbps(kbps) != settings.bitrate || !BITRATES_KBPS.contains(&kbps) {
+        return Err(AudioEngineError::configuration(format!(
+            "MP3 can't be encoded at {}",
+            settings.bitrate
+        )));
+    }
+    if settings.quality > 9 {
+        return Err(AudioEngineError::configuration(format!(
+            "MP3 quality goes from 0 to 9, not {}",
+            settings.quality
+        )));
+    }
+    let mode = match format.channels {
+        ChannelCount::Mono => "m",
+        ChannelCount::Stereo => "j",
+        channels => {
+            return Err(AudioEngineError::configuration(format!(
+                "MP3 holds at most two channels, not {channels:?}"
+            )));
+        }
+    };
+    let rate = match format.sample_rate {
+        SampleRate::Hz44100 => "44.1".to_string(),
+        rate => (rate.as_hz() / 1000).to_string(),
+    };
+    let mut args = vec![
+        "--quiet".to_string(),
+        "-r".to_string(),
+        "--signed".to_string(),
+        "--little-endian".to_string(),
+        "--bitwidth".to_string(),
+        "16".to_string(),
+        "-s".to_string(),
+        rate,
+        "-m".to_string(),
+        mode.to_string(),
+        "--cbr".to_string(),
+        "-b".to_string(),
+        kbps.to_string(),
+        "-q".to_string(),
+        settings.quality.to_string(),
+    ];
+    if format.sample_rate.as_hz() > MAX_RATE_HZ {
+        args.push("--resample".to_string());
+        args.push((MAX_RATE_HZ / 1000).to_string());
+    }
+    Ok(args)
+}
+
+/// Encodes interleaved samples to MP3 on a worker thread.
+pub struct Mp3Encoder {
+    /// The file or stream written to
+    target: String,
+    format: AudioFormat,
+    settings: Mp3Settings,
+    process: EncoderProcess,
+}
+
+impl Mp3Encoder {
+    /// Starts encoding samples in `format` to the file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the settings don't suit MP3 or `format` (see
+    /// [`encoder_args`]), `lame` can't be started, or the worker thread
+    /// can't be started.
+    pub fn create(
+        path: impl AsRef<Path>,
+        format: AudioFormat,
+        settings: &Mp3Settings,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let mut args = encoder_args(settings, format)?;
+        args.push("-".to_string());
+        args.push(path.display().to_string());
+        let (process, _) =
+            EncoderProcess::spawn(ENCODER_PROGRAM, &args, format, &EncoderOutput::File)?;
+        Ok(Self {
+            target: path.display().to_string(),
+            format,
+            settings: settings.clone(),
+            process,
+        })
+    }
+
+    /// Starts encoding samples in `format` for `output` at its bitrate,
+    /// returning the encoder and the MP3 frames it produces, ready to send
+    /// to the stream's url.
+    ///
+    /// # Errors
+    /// Returns an error if `output` isn't encoded with MP3, its bitrate
+    /// doesn't suit MP3 (see [`encoder_args`]), or `lame` or the worker
+    /// thread can't be started.
+    pub fn for_stream(output: &NetworkOutput, format: AudioFormat) -> Result<(Self, ChildStdout)> {
+        if output.codec != StreamCodec::Mp3 {
+            return Err(AudioEngineError::configuration(format!(
+                "{} is encoded with {}, not MP3",
+                output.url, output.codec
+            )));
+        }
+        let settings = Mp3Settings {
+            bitrate: output.audio_bitrate,
+            ..Mp3Settings::default()
+        };
+        let mut args = encoder_args(&settings, format)?;
+        // Flush every frame rather than filling LAME's output buffer first
+        args.push("--flush".to_string());
+        args.push("-".to_string());
+        args.push("-".to_string());
+        let (process, frames) =
+            EncoderProcess::spawn(ENCODER_PROGRAM, &args, format, &EncoderOutput::Pipe)?;
+        let Some(frames) = frames else {
+            return Err(AudioEngineError::configuration("encoder has no stdout"));
+        };
+        let encoder = Self {
+            target: output.url.to_string(),
+            format,
+            settings,
+            process,
+        };
+        Ok((encoder, frames))
+    }
+
+    /// The file or stream url written to
+    #[must_use]
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &Mp3Settings {
+        &self.settings
+    }
+
+    /// Samples dropped so far because the encoder fell behind
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.process.dropped()
+    }
+
+    /// Share of the encoder's ring waiting to be encoded, from 0 to 1;
+    /// samples are dropped once it reaches 1
+    #[must_use]
+    pub fn backlog(&self) -> f32 {
+        self.process.backlog()
+    }
+
+    /// Whether the encoder stopped taking samples, e.g. because it exited
+    #[must_use]
+    pub fn has_failed(&self) -> bool {
+        self.process.has_failed()
+    }
+
+    /// Queues interleaved samples for encoding without blocking, returning
+    /// how many were queued. The rest are dropped.
+    pub fn write(&mut self, samples: &[Sample]) -> usize {
+        self.process.write(samples)
+    }
+
+    /// Encodes what is still queued and waits for the encoder to finish
+    /// the file.
+    ///
+    /// # Errors
+    /// Returns an error if the encoder failed or exited unsuccessfully.
+    pub fn finish(mut self) -> Result<()> {
+        self.process.close()
+    }
+}
+
+impl Drop for Mp3Encoder {
+    fn drop(&mut self) {
+        if let Err(e) = self.process.close() {
+            log::error!("MP3 encoding to {} failed: {e}", self.target);
+        }
+    }
+}
+
+impl fmt::Debug for Mp3Encoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mp3Encoder")
+            .field("target", &self.target)
+            .field("format", &self.format)
+            .field("settings", &self.settings)
+            .field("dropped", &self.dropped())
+            .field("failed", &self.has_failed())
+            .finish_non_exhaustive()
+    }
+}
+
+impl crate::engine::RenderSink for Mp3Encoder {
+    fn start(&mut self, format: AudioFormat) -> Result<()> {
+        if format == self.format {
+            Ok(())
+        } else {
+            Err(AudioEngineError::FormatMismatch {
+                expected: format!("{:?} {:?}", self.format.sample_rate, self.format.channels),
+                actual: format!("{:?} {:?}", format.sample_rate, format.channels),
+            })
+        }
+    }
+
+    /// Waits for room rather than dropping, as an offline render can
+    fn write(&mut self, samples: &[Sample]) -> Result<()> {
+        self.process.write_all(samples)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.process.close()
+    }
+
+    fn describe(&self) -> String {
+        self.target.clone()
+    }
+}
@@ -0,0 +1,490 @@
+//! Opus encoding and decoding
+//!
+//! An [`OpusEncoder`] turns interleaved samples into Ogg Opus with the
+//! `opusenc` encoder, fed 16 bit PCM on its stdin the same way the MP3
+//! encoder feeds LAME: [`write`](OpusEncoder::write) only pushes into a
+//! ring buffer drained by a worker thread, so it is safe to call from the
+//! device callback. The result goes to a file, or for a
+//! [`NetworkOutput`] encoded with [`StreamCodec::Opus`] to a pipe of Ogg
+//! pages to send on, with the container delay kept to one frame for low
+//! latency.
+//!
+//! [`OpusSettings`] map onto the encoder's options: the bitrate, the
+//! complexity from 0 (fastest) to 10 (best), and the frame size.
+//!
+//! An [`OpusFileDecoder`] plays `.opus` files back through `opusdec`,
+//! which always decodes at 48 kHz. It reads as a
+//! [`StreamSource`](crate::io::StreamSource), so a file streamer or
+//! [`play_file`](crate::engine::play_file) can use it.
+//!
+//! Needs the `opus` feature, and `opusenc` and `opusdec` on the `PATH` at
+//! run time.
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::encoder::{EncoderOutput, EncoderProcess};
+use crate::io::output::{NetworkOutput, OpusSettings, StreamCodec};
+use crate::types::{AudioFormat, ChannelCount, Sample};
+
+/// Program the encoder runs
+const ENCODER_PROGRAM: &str = "opusenc";
+
+/// Program the decoder runs
+const DECODER_PROGRAM: &str = "opusdec";
+
+/// Rate Opus decodes at, in Hz
+pub const DECODE_RATE_HZ: u32 = 48_000;
+
+/// Bitrates Opus encodes at per channel, in kbps
+const KBPS_PER_CHANNEL: std::ops::RangeInclusive<u32> = 6..=256;
+
+/// Highest complexity the encoder takes
+const MAX_COMPLEXITY: u8 = 10;
+
+/// Bytes read from the start of a file looking for the Opus header
+const HEADER_SEARCH_BYTES: u64 = 4096;
+
+/// Bytes read from the end of a file looking for the last Ogg page
+const TAIL_SEARCH_BYTES: u64 = 65_536;
+
+/// Encoder options for `settings` on input in `format`.
+///
+/// # Errors
+/// Returns an error if the bitrate is out of Opus' range for the channel
+/// count or the complexity is out of range.
+pub fn encoder_args(settings: &OpusSettings, format: AudioFormat) -> Result<Vec<String>> {
+    let kbps = settings.bitrate.as_kbps();
+    let channels = format.channels.count();
+    let range = *KBPS_PER_CHANNEL.start()..=KBPS_PER_CHANNEL.end() * channels;
+    if !range.contains(&kbps) {
+        return Err(AudioEngineError::configuration(format!(
+            "Opus can't encode {channels} channels at {}",
+            settings.bitrate
+        )));
+    }
+    if settings.complexity > MAX_COMPLEXITY {
+        return Err(AudioEngineError::configuration(format!(
+            "Opus complexity goes from 0 to {MAX_COMPLEXITY}, not {}",
+            settings.complexity
+        )));
+    }
+    Ok(vec![
+        "--quiet".to_string(),
+        "--raw".to_string(),
+        "--raw-bits".to_string(),
+        "16".to_string(),
+        "--raw-rate".to_string(),
+        format.sample_rate.as_hz().to_string(),
+        "--raw-chan".to_string(),
+        channels.to_string(),
+        "--raw-endianness".to_string(),
+        "0".to_string(),
+        "--bitrate".to_string(),
+        kbps.to_string(),
+        "--comp".to_string(),
+        settings.complexity.to_string(),
+        "--framesize".to_string(),
+        settings.frame_size.as_ms().to_string(),
+    ])
+}
+
+/// Encodes interleaved samples to Ogg Opus on a worker thread.
+pub struct OpusEncoder {
+    /// The file or stream written to
+    target: String,
+    format: AudioFormat,
+    settings: OpusSettings,
+    process: EncoderProcess,
+}
+
+impl OpusEncoder {
+    /// Starts encoding samples in `format` to the file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the settings are out of range (see
+    /// [`encoder_args`]), or `opusenc` or the worker thread can't be
+    /// started.
+    pub fn create(
+        path: impl AsRef<Path>,
+        format: AudioFormat,
+        settings: &OpusSettings,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let mut args = encoder_args(settings, format)?;
+        args.push("-".to_string());
+        args.push(path.display().to_string());
+        let (process, _) =
+            EncoderProcess::spawn(ENCODER_PROGRAM, &args, format, &EncoderOutput::File)?;
+        Ok(Self {
+            target: path.display().to_string(),
+            format,
+            settings: *settings,
+            process,
+        })
+    }
+
+    /// Starts encoding samples in `format` for `output`, returning the
+    /// encoder and the Ogg pages it produces, ready to send to the
+    /// stream's url. Pages are flushed every frame rather than held back
+    /// to fill them, which keeps the latency down to about a frame.
+    ///
+    /// # Errors
+    /// Returns an error if `output` isn't encoded with Opus, its settings
+    /// are out of range (see [`encoder_args`]), or `opusenc` or the worker
+    /// thread can't be started.
+    pub fn for_stream(output: &NetworkOutput, format: AudioFormat) -> Result<(Self, ChildStdout)> {
+        let StreamCodec::Opus(settings) = output.codec else {
+            return Err(AudioEngineError::configuration(format!(
+                "{} is encoded with {}, not Opus",
+                output.url, output.codec
+            )));
+        };
+        let settings = settings.with_bitrate(output.audio_bitrate);
+        let mut args = encoder_args(&settings, format)?;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let max_delay = settings.frame_size.as_ms().ceil() as u32;
+        args.push("--max-delay".to_string());
+        args.push(max_delay.to_string());
+        args.push("-".to_string());
+        args.push("-".to_string());
+        let (process, pages) =
+            EncoderProcess::spawn(ENCODER_PROGRAM, &args, format, &EncoderOutput::Pipe)?;
+        let Some(pages) = pages else {
+            return Err(AudioEngineError::configuration("encoder has no stdout"));
+        };
+        let encoder = Self {
+            target: output.url.to_string(),
+            format,
+            settings,
+            process,
+        };
+        Ok((encoder, pages))
+    }
+
+    /// The file or stream url written to
+    #[must_use]
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &OpusSettings {
+        &self.settings
+    }
+
+    /// Samples dropped so far because the encoder fell behind
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.process.dropped()
+    }
+
+    /// Share of the encoder's ring waiting to be encoded, from 0 to 1;
+    /// samples are dropped once it reaches 1
+    #[must_use]
+    pub fn backlog(&self) -> f32 {
+        self.process.backlog()
+    }
+
+    /// Whether the encoder stopped taking samples, e.g. because it exited
+    #[must_use]
+    pub fn has_failed(&self) -> bool {
+        self.process.has_failed()
+    }
+
+    /// Queues interleaved samples for encoding without blocking, returning
+    /// how many were queued. The rest are dropped.
+    pub fn write(&mut self, samples: &[Sample]) -> usize {
+        self.process.write(samples)
+    }
+
+    /// Encodes what is still queued and waits for the encoder to finish.
+    ///
+    /// # Errors
+    /// Returns an error if the encoder failed or exited unsuccessfully.
+    pub fn finish(mut self) -> Result<()> {
+        self.process.close()
+    }
+}
+
+impl Drop for OpusEncoder {
+    fn drop(&mut self) {
+        if let Err(e) = self.process.close() {
+            log::error!("Opus encoding to {} failed: {e}", self.target);
+        }
+    }
+}
+
+impl fmt::Debug for OpusEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpusEncoder")
+            .field("target", &self.target)
+            .field("format", &self.format)
+            .field("settings", &self.settings)
+            .field("dropped", &self.dropped())
+            .field("failed", &self.has_failed())
+            .finish_non_exhaustive()
+    }
+}
+
+impl crate::engine::RenderSink for OpusEncoder {
+    fn start(&mut self, format: AudioFormat) -> Result<()> {
+        if format == self.format {
+            Ok(())
+        } else {
+            Err(AudioEngineError::FormatMismatch {
+                expected: format!("{:?} {:?}", self.format.sample_rate, self.format.channels),
+                actual: format!("{:?} {:?}", format.sample_rate, format.channels),
+            })
+        }
+    }
+
+    /// Waits for room rather than dropping, as an offline render can
+    fn write(&mut self, samples: &[Sample]) -> Result<()> {
+        self.process.write_all(samples)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.process.close()
+    }
+
+    fn describe(&self) -> String {
+        self.target.clone()
+    }
+}
+
+/// Decodes an Ogg Opus file into interleaved samples at 48 kHz.
+pub struct OpusFileDecoder {
+    path: PathBuf,
+    channels: ChannelCount,
+    /// Length in frames, from the last page of the file
+    frames: Option<u64>,
+    child: Child,
+    output: BufReader<ChildStdout>,
+    /// Raw 16 bit samples read from the decoder
+    bytes: Vec<u8>,
+    /// Frame the next sample read belongs to
+    position: u64,
+}
+
+impl OpusFileDecoder {
+    /// Opens the file at `path` and starts decoding from its start.
+    ///
+    /// # Errors
+    /// Returns an error if the file doesn't exist, isn't Ogg Opus, has
+    /// more channels than a [`ChannelCount`] allows, or `opusdec` can't be
+    /// started.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (channels, frames) = read_header(&path)?;
+        let (child, output) = spawn_decoder(&path)?;
+        Ok(Self {
+            path,
+            channels,
+            frames,
+            child,
+            output,
+            bytes: Vec::new(),
+            position: 0,
+        })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[must_use]
+    pub const fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    /// The rate the samples are read at, in Hz, always 48 kHz
+    #[must_use]
+    pub const fn sample_rate_hz(&self) -> u32 {
+        DECODE_RATE_HZ
+    }
+
+    /// Length in frames, if the file says
+    #[must_use]
+    pub const fn frames(&self) -> Option<u64> {
+        self.frames
+    }
+
+    /// Frame the next sample read belongs to
+    #[must_use]
+    pub const fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Reads the next interleaved samples into `out`, whole frames only,
+    /// returning how many were read; zero means the end of the file.
+    ///
+    /// # Errors
+    /// Returns an error if the decoder can't be read or exited
+    /// unsuccessfully.
+    pub fn read_samples(&mut self, out: &mut [Sample]) -> Result<usize> {
+        let channels = self.channels.count_usize();
+        let frame_bytes = channels * 2;
+        let wanted = out.len() / channels * frame_bytes;
+        if wanted == 0 {
+            return Ok(0);
+        }
+        self.bytes.resize(wanted, 0);
+        let mut filled = 0;
+        while filled < wanted {
+            let read = self.output.read(&mut self.bytes[filled..wanted])?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+            if filled.is_multiple_of(frame_bytes) {
+                break;
+            }
+        }
+        // A partial frame can only be left at the end of the stream
+        let filled = filled - filled % frame_bytes;
+        if filled == 0 {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(AudioEngineError::configuration(format!(
+                    "{DECODER_PROGRAM} exited with {status} decoding {}",
+                    self.path.display()
+                )));
+            }
+            return Ok(0);
+        }
+        for (sample, bytes) in out.iter_mut().zip(self.bytes[..filled].chunks_exact(2)) {
+            *sample = Sample::new(f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32768.0);
+        }
+        self.position += (filled / frame_bytes) as u64;
+        Ok(filled / 2)
+    }
+
+    /// Moves to a frame of the file. The decoder can't seek, so it starts
+    /// over and decodes up to `frame`.
+    ///
+    /// # Errors
+    /// Returns an error if the decoder can't be restarted or read.
+    pub fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        self.stop();
+        let (child, mut output) = spawn_decoder(&self.path)?;
+        self.child = child;
+        let skip = frame * self.channels.count_usize() as u64 * 2;
+        let skipped = io::copy(&mut (&mut output).take(skip), &mut io::sink())?;
+        self.output = output;
+        self.position = skipped / (self.channels.count_usize() as u64 * 2);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        // The decoder may have exited already
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for OpusFileDecoder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl fmt::Debug for OpusFileDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpusFileDecoder")
+            .field("path", &self.path)
+            .field("channels", &self.channels)
+            .field("frames", &self.frames)
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Starts `opusdec` on the file at `path`, writing raw 16 bit PCM to its
+/// stdout.
+fn spawn_decoder(path: &Path) -> Result<(Child, BufReader<ChildStdout>)> {
+    let mut child = start_decoder(path.as_os_str(), Stdio::null())?;
+    let Some(output) = child.stdout.take() else {
+        return Err(AudioEngineError::configuration("decoder has no stdout"));
+    };
+    Ok((child, BufReader::new(output)))
+}
+
+/// Starts `opusdec` on `input`, `-` for an Ogg stream written to its
+/// stdin, writing raw 16 bit PCM at [`DECODE_RATE_HZ`] to its stdout.
+pub(crate) fn start_decoder(input: &OsStr, stdin: Stdio) -> Result<Child> {
+    Command::new(DECODER_PROGRAM)
+        .args(["--quiet", "--rate", &DECODE_RATE_HZ.to_string()])
+        .arg(input)
+        .arg("-")
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => AudioEngineError::configuration(format!(
+                "Opus decoding needs `{DECODER_PROGRAM}` on the PATH"
+            )),
+            _ => e.into(),
+        })
+}
+
+/// Reads the channel count from the Opus header of the file at `path`,
+/// and the length in frames from the position of its last Ogg page.
+pub(crate) fn read_header(path: &Path) -> Result<(ChannelCount, Option<u64>)> {
+    let mut file = File::open(path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => AudioEngineError::FileNotFound {
+            path: path.to_path_buf(),
+        },
+        _ => e.into(),
+    })?;
+    let mut head = Vec::new();
+    (&mut file)
+        .take(HEADER_SEARCH_BYTES)
+        .read_to_end(&mut head)?;
+    // OpusHead: magic, version, channel count, pre-skip, ...
+    let header = find(&head, b"OpusHead")
+        .map(|at| &head[at..])
+        .filter(|header| header.len() >= 12)
+        .ok_or_else(|| AudioEngineError::UnsupportedFormat {
+            format: format!("{} isn't an Ogg Opus file", path.display()),
+        })?;
+    let channels = ChannelCount::try_from(u32::from(header[9]))?;
+    let pre_skip = u64::from(u16::from_le_bytes([header[10], header[11]]));
+
+    let length = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(length.saturating_sub(TAIL_SEARCH_BYTES)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    // The granule position of an Ogg page counts the frames decoded by its
+    // end, pre-skip included
+    let frames = rfind(&tail, b"OggS")
+        .and_then(|at| tail.get(at + 6..at + 14))
+        .and_then(|granule| granule.try_into().ok())
+        .map(i64::from_le_bytes)
+        .and_then(|granule| u64::try_from(granule).ok())
+        .map(|granule| granule.saturating_sub(pre_skip));
+    Ok((channels, frames))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
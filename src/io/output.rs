@@ -1,15 +1,27 @@
 //! Output target defintions
 
-use std::fmt;
-use std::path::PathBuf;
+use std::fmt::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
-use crate::types::{AudioFormat, DeviceId, StreamBitrate, StreamUrl};
+use crate::error::{AudioEngineError, Result};
+use crate::io::bwf::BroadcastInfo;
+use crate::io::cue::MarkerList;
+use crate::io::metadata::{MetadataFormat, NowPlaying};
+use crate::io::rtp::RtpSettings;
+use crate::io::srt::SrtSettings;
+use crate::io::whip::WhipSettings;
+use crate::scheduler::UtcDateTime;
+use crate::types::{AudioFormat, DeviceId, SampleRate, StreamBitrate, StreamUrl};
 
 /// Audio output targets.
 ///
 /// This enum will represent all supported output targets with thier configuration paramets.
 #[derive(Debug, Clone)]
 #[non_exhaustive]
+// Targets are configuration, built once per output, so the size of the
+// network settings isn't worth a box
+#[allow(clippy::large_enum_variant)]
 pub enum OutputTarget {
     /// Live audio playback to a device
     Device(DeviceOutputConfig),
@@ -114,6 +126,12 @@ pub struct FileOutput {
     pub format: OutputFileFormat,
     /// Audio format (sample rate, channels, etc)
     pub audio_format: Option<AudioFormat>,
+    /// Broadcast Wave metadata, for WAV output only
+    pub broadcast: Option<BroadcastInfo>,
+    /// Markers written as cue points, for WAV output only
+    pub markers: MarkerList,
+    /// Splits the output into several files
+    pub rotation: Option<RotationPolicy>,
 }
 
 impl FileOutput {
@@ -124,6 +142,9 @@ impl FileOutput {
             path: path.into(),
             format,
             audio_format: None,
+            broadcast: None,
+            markers: MarkerList::new(),
+            rotation: None,
         }
     }
 
@@ -134,6 +155,28 @@ impl FileOutput {
         self
     }
 
+    /// Writes the file as Broadcast Wave, with `bext` and `iXML` chunks.
+    #[must_use]
+    pub fn with_broadcast(mut self, info: BroadcastInfo) -> Self {
+        self.broadcast = Some(info);
+        self
+    }
+
+    /// Writes `markers` into the file as cue points.
+    #[must_use]
+    pub fn with_markers(mut self, markers: MarkerList) -> Self {
+        self.markers = markers;
+        self
+    }
+
+    /// Splits the output into files named by `rotation`, next to
+    /// [`path`](Self::path).
+    #[must_use]
+    pub fn with_rotation(mut self, rotation: RotationPolicy) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
     /// Creates a wave file output
     #[must_use]
     pub fn wav(path: impl Into<PathBuf>) -> Self {
@@ -144,6 +187,31 @@ impl FileOutput {
     pub fn mp3(path: impl Into<PathBuf>) -> Self {
         Self::new(path, OutputFileFormat::Mp3(Mp3Settings::default()))
     }
+
+    /// Creates an Opus file output.
+    #[must_use]
+    pub fn opus(path: impl Into<PathBuf>) -> Self {
+        Self::new(path, OutputFileFormat::Opus(OpusSettings::default()))
+    }
+
+    /// Creates an AIFF file output.
+    #[must_use]
+    pub fn aiff(path: impl Into<PathBuf>) -> Self {
+        Self::new(path, OutputFileFormat::Aiff)
+    }
+
+    /// Creates a CAF file output.
+    #[must_use]
+    pub fn caf(path: impl Into<PathBuf>) -> Self {
+        Self::new(path, OutputFileFormat::Caf)
+    }
+
+    /// Creates a headerless raw PCM file output of little-endian samples in
+    /// `audio_format`, which whatever reads the file has to be told.
+    #[must_use]
+    pub fn raw(path: impl Into<PathBuf>, audio_format: AudioFormat) -> Self {
+        Self::new(path, OutputFileFormat::Raw).with_audio_format(audio_format)
+    }
 }
 
 /// Supported output file formats.
@@ -153,6 +221,15 @@ pub enum OutputFileFormat {
     Wav,
     /// MPEG Audio Layer 3
     Mp3(Mp3Settings),
+    /// Opus in an Ogg container
+    Opus(OpusSettings),
+    /// Audio interchange file format; AIFF-C for float samples
+    Aiff,
+    /// Core audio format
+    Caf,
+    /// Headerless little-endian PCM. The [`FileOutput`] has to give its
+    /// audio format, as nothing in the file records it.
+    Raw,
 }
 
 impl OutputFileFormat {
@@ -162,15 +239,190 @@ impl OutputFileFormat {
         match self {
             Self::Wav => "wav",
             Self::Mp3(_) => "mp3",
+            Self::Opus(_) => "opus",
+            Self::Aiff => "aiff",
+            Self::Caf => "caf",
+            Self::Raw => "raw",
+        }
+    }
+
+    /// Bitrate of an encoded format
+    #[must_use]
+    pub const fn bitrate(&self) -> Option<StreamBitrate> {
+        match self {
+            Self::Mp3(settings) => Some(settings.bitrate),
+            Self::Opus(settings) => Some(settings.bitrate),
+            Self::Wav | Self::Aiff | Self::Caf | Self::Raw => None,
+        }
+    }
+}
+
+/// When a [`FileOutput`] moves on to a new file, and what the files are
+/// called.
+///
+/// The name template is a file name, placed in the directory of the
+/// output's path, in which these are replaced:
+///
+/// | Placeholder | Replaced by |
+/// |-------------|-------------|
+/// | `{stem}` | the output path's file name without its extension |
+/// | `{ext}` | the output path's extension, or the format's |
+/// | `{index}` | the file's number, from 001 |
+/// | `{date}` | the UTC date the file starts at, as `2024-05-01` |
+/// | `{time}` | the UTC time of day the file starts at, as `13-45-00` |
+///
+/// A file starts at the time the output was opened plus the audio in the
+/// files before it. Files with `{time}` but no `{index}` must be at least a
+/// second long to get distinct names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// Longest a file runs
+    pub max_duration: Option<Duration>,
+    /// Most audio data bytes in a file, headers not counted; encoded
+    /// formats are sized by their bitrate
+    pub max_size_bytes: Option<u64>,
+    pub template: String,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            max_duration: None,
+            max_size_bytes: None,
+            template: "{stem}-{index}.{ext}".to_string(),
         }
     }
 }
 
+impl RotationPolicy {
+    /// Starts a new file every `duration`.
+    #[must_use]
+    pub fn every(duration: Duration) -> Self {
+        Self::default().with_max_duration(duration)
+    }
+
+    #[must_use]
+    pub const fn with_max_duration(mut self, duration: Duration) -> Self {
+        self.max_duration = Some(duration);
+        self
+    }
+
+    #[must_use]
+    pub const fn with_max_size_bytes(mut self, bytes: u64) -> Self {
+        self.max_size_bytes = Some(bytes);
+        self
+    }
+
+    #[must_use]
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    /// Checks the policy limits the files and names them apart.
+    ///
+    /// # Errors
+    /// Returns an error if it sets neither a duration nor a size, or its
+    /// template has neither `{index}` nor `{time}`.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_duration.is_none() && self.max_size_bytes.is_none() {
+            return Err(AudioEngineError::configuration(
+                "file rotation needs a maximum duration or size",
+            ));
+        }
+        if !self.template.contains("{index}") && !self.template.contains("{time}") {
+            return Err(AudioEngineError::configuration(format!(
+                "file rotation template {:?} names every file the same; add {{index}} or {{time}}",
+                self.template
+            )));
+        }
+        Ok(())
+    }
+
+    /// Frames in each file of `format`, written at `audio_format`; at least
+    /// one
+    #[must_use]
+    pub fn frames_per_file(&self, format: &OutputFileFormat, audio_format: AudioFormat) -> u64 {
+        let rate = u128::from(audio_format.sample_rate.as_hz());
+        let by_duration = self
+            .max_duration
+            .map(|duration| duration.as_nanos() * rate / 1_000_000_000);
+        let by_size = self.max_size_bytes.map(|bytes| {
+            let bytes = u128::from(bytes);
+            format.bitrate().map_or_else(
+                || bytes / u128::from(audio_format.frame_size().max(1)),
+                |bitrate| bytes * 8 * rate / u128::from(bitrate.as_bps().max(1)),
+            )
+        });
+        let frames = match (by_duration, by_size) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => a.or(b).unwrap_or(u128::MAX),
+        };
+        u64::try_from(frames).unwrap_or(u64::MAX).max(1)
+    }
+
+    /// Path of file number `index`, from 1, of an output at `path`,
+    /// starting at `time`
+    #[must_use]
+    pub fn file_path(
+        &self,
+        path: &Path,
+        format: &OutputFileFormat,
+        index: u32,
+        time: SystemTime,
+    ) -> PathBuf {
+        let stem = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let ext = path
+            .extension()
+            .map_or_else(|| format.extension().into(), |ext| ext.to_string_lossy());
+        let start = UtcDateTime::from_system_time(time);
+        let mut name = String::new();
+        let mut rest = self.template.as_str();
+        while let Some(open) = rest.find('{') {
+            name.push_str(&rest[..open]);
+            let Some(close) = rest[open..].find('}') else {
+                break;
+            };
+            let placeholder = &rest[open + 1..open + close];
+            let _ = match placeholder {
+                "stem" => write!(name, "{stem}"),
+                "ext" => write!(name, "{ext}"),
+                "index" => write!(name, "{index:03}"),
+                "date" => write!(
+                    name,
+                    "{:04}-{:02}-{:02}",
+                    start.year, start.month, start.day
+                ),
+                "time" => write!(
+                    name,
+                    "{:02}-{:02}-{:02}",
+                    start.hour, start.minute, start.second
+                ),
+                _ => write!(name, "{{{placeholder}}}"),
+            };
+            rest = &rest[open + close + 1..];
+        }
+        name.push_str(rest);
+        path.with_file_name(name)
+    }
+}
+
+/// Wall-clock length of `frames` at `sample_rate`
+pub(crate) fn frames_duration(frames: u64, sample_rate: SampleRate) -> Duration {
+    Duration::from_nanos(frames.saturating_mul(sample_rate.period_nanos()))
+}
+
 impl fmt::Display for OutputFileFormat {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Wav => write!(f, "WAV"),
             Self::Mp3(settings) => write!(f, "MP3 ({})", settings.bitrate),
+            Self::Opus(settings) => write!(f, "Opus ({})", settings.bitrate),
+            Self::Aiff => write!(f, "AIFF"),
+            Self::Caf => write!(f, "CAF"),
+            Self::Raw => write!(f, "raw PCM"),
         }
     }
 }
@@ -193,6 +445,127 @@ impl Default for Mp3Settings {
     }
 }
 
+/// Opus encoding settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpusSettings {
+    /// Bitrate
+    pub bitrate: StreamBitrate,
+    /// Complexity (0-10, higher => better quality, more CPU)
+    pub complexity: u8,
+    /// Duration of each encoded frame
+    pub frame_size: OpusFrameSize,
+}
+
+impl OpusSettings {
+    /// Sets the bitrate
+    #[must_use]
+    pub const fn with_bitrate(mut self, bitrate: StreamBitrate) -> Self {
+        self.bitrate = bitrate;
+        self
+    }
+
+    /// Sets the complexity
+    #[must_use]
+    pub const fn with_complexity(mut self, complexity: u8) -> Self {
+        self.complexity = complexity;
+        self
+    }
+
+    /// Sets the frame size
+    #[must_use]
+    pub const fn with_frame_size(mut self, frame_size: OpusFrameSize) -> Self {
+        self.frame_size = frame_size;
+        self
+    }
+}
+
+impl Default for OpusSettings {
+    fn default() -> Self {
+        Self {
+            bitrate: StreamBitrate::KBPS_128,
+            complexity: 10,
+            frame_size: OpusFrameSize::Ms20,
+        }
+    }
+}
+
+/// Opus frame durations. Shorter frames lower the latency at some cost in
+/// quality per bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpusFrameSize {
+    Ms2_5,
+    Ms5,
+    Ms10,
+    #[default]
+    Ms20,
+    Ms40,
+    Ms60,
+}
+
+impl OpusFrameSize {
+    /// Frame duration in milliseconds
+    #[must_use]
+    pub const fn as_ms(self) -> f32 {
+        match self {
+            Self::Ms2_5 => 2.5,
+            Self::Ms5 => 5.0,
+            Self::Ms10 => 10.0,
+            Self::Ms20 => 20.0,
+            Self::Ms40 => 40.0,
+            Self::Ms60 => 60.0,
+        }
+    }
+}
+
+impl fmt::Display for OpusFrameSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ms", self.as_ms())
+    }
+}
+
+/// Codecs a network stream can be encoded with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamCodec {
+    /// MPEG Audio Layer 3
+    #[default]
+    Mp3,
+    /// Opus, for low latency streams. The stream's own bitrate takes the
+    /// place of the one in these settings.
+    Opus(OpusSettings),
+    /// Uncompressed 16 bit PCM, for RTP
+    L16,
+    /// Uncompressed 24 bit PCM, for RTP
+    L24,
+    /// Advanced Audio Coding (AAC-LC), for RTMP
+    Aac,
+}
+
+impl StreamCodec {
+    /// MIME type of the stream as sent over HTTP
+    #[must_use]
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Mp3 => "audio/mpeg",
+            Self::Opus(_) => "audio/ogg",
+            Self::L16 => "audio/L16",
+            Self::L24 => "audio/L24",
+            Self::Aac => "audio/aac",
+        }
+    }
+}
+
+impl fmt::Display for StreamCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mp3 => write!(f, "MP3"),
+            Self::Opus(settings) => write!(f, "Opus ({})", settings.frame_size),
+            Self::L16 => write!(f, "L16"),
+            Self::L24 => write!(f, "L24"),
+            Self::Aac => write!(f, "AAC"),
+        }
+    }
+}
+
 /// Network Stream output configuration
 #[derive(Debug, Clone)]
 pub struct NetworkOutput {
@@ -200,8 +573,25 @@ pub struct NetworkOutput {
     pub url: StreamUrl,
     /// Audio Bitrate
     pub audio_bitrate: StreamBitrate,
-    /// Buffer size in milliseconds
+    /// Buffer size in milliseconds; also how long a send may stall before
+    /// the connection counts as lost
     pub buffer_ms: u32,
+    /// Codec the stream is encoded with
+    pub codec: StreamCodec,
+    /// Login for the server's source mount
+    pub credentials: Option<StreamCredentials>,
+    /// Station details announced to the server
+    pub info: StreamInfo,
+    /// Title the stream starts with
+    pub now_playing: Option<NowPlaying>,
+    /// How lost connections are retried
+    pub reconnect: ReconnectPolicy,
+    /// Packet and report settings for RTP urls
+    pub rtp: RtpSettings,
+    /// Connection, encryption and latency settings for SRT and RIST urls
+    pub srt: SrtSettings,
+    /// Authorization and connection settings for WHIP endpoints
+    pub whip: WhipSettings,
 }
 
 impl NetworkOutput {
@@ -212,6 +602,14 @@ impl NetworkOutput {
             url,
             audio_bitrate: StreamBitrate::KBPS_192,
             buffer_ms: 1000,
+            codec: StreamCodec::Mp3,
+            credentials: None,
+            info: StreamInfo::default(),
+            now_playing: None,
+            reconnect: ReconnectPolicy::default(),
+            rtp: RtpSettings::default(),
+            srt: SrtSettings::default(),
+            whip: WhipSettings::default(),
         }
     }
 
@@ -228,4 +626,198 @@ impl NetworkOutput {
         self.buffer_ms = ms;
         self
     }
+
+    /// Encodes the stream with Opus, taking the bitrate from `settings`.
+    #[must_use]
+    pub const fn with_opus(mut self, settings: OpusSettings) -> Self {
+        self.audio_bitrate = settings.bitrate;
+        self.codec = StreamCodec::Opus(settings);
+        self
+    }
+
+    /// Sets the codec
+    #[must_use]
+    pub const fn with_codec(mut self, codec: StreamCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Sets the RTP packet and report settings
+    #[must_use]
+    pub const fn with_rtp(mut self, rtp: RtpSettings) -> Self {
+        self.rtp = rtp;
+        self
+    }
+
+    /// Sets the SRT or RIST connection, encryption and latency settings
+    #[must_use]
+    pub fn with_srt(mut self, srt: SrtSettings) -> Self {
+        self.srt = srt;
+        self
+    }
+
+    /// Sets the WHIP authorization and connection settings
+    #[must_use]
+    pub fn with_whip(mut self, whip: WhipSettings) -> Self {
+        self.whip = whip;
+        self
+    }
+
+    /// Logs in to the source mount as `username`.
+    #[must_use]
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some(StreamCredentials {
+            username: username.into(),
+            password: password.into(),
+        });
+        self
+    }
+
+    /// Sets the station details
+    #[must_use]
+    pub fn with_info(mut self, info: StreamInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Sets the title the stream starts with; see [`metadata`](crate::io::metadata).
+    #[must_use]
+    pub fn with_now_playing(mut self, now_playing: NowPlaying) -> Self {
+        self.now_playing = Some(now_playing);
+        self
+    }
+
+    /// How the url's protocol carries now-playing metadata, if it does
+    #[must_use]
+    pub const fn metadata_format(&self) -> Option<MetadataFormat> {
+        MetadataFormat::for_protocol(self.url.protocol())
+    }
+
+    /// Sets how lost connections are retried
+    #[must_use]
+    pub const fn with_reconnect(mut self, reconnect: ReconnectPolicy) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Disables reconnecting after a lost connection
+    #[must_use]
+    pub const fn without_reconnect(mut self) -> Self {
+        self.reconnect.max_attempts = Some(0);
+        self
+    }
+}
+
+/// Login for a stream server. The password is kept out of `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct StreamCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for StreamCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamCredentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Station details a stream server shows its listeners and directories.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub genre: Option<String>,
+    /// Station website
+    pub url: Option<String>,
+    /// Whether the server may list the stream in public directories
+    pub public: bool,
+}
+
+impl StreamInfo {
+    #[must_use]
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_genre(mut self, genre: impl Into<String>) -> Self {
+        self.genre = Some(genre.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Lists the stream in public directories
+    #[must_use]
+    pub const fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+}
+
+/// How a lost stream connection is retried: after `initial_delay`, then
+/// twice as long after each failed attempt, up to `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Attempts before giving up, `None` to keep trying
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_mins(1),
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    #[must_use]
+    pub const fn with_delays(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_delay = initial;
+        self.max_delay = max;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    /// Wait before attempt `attempt`, counted from 1
+    #[must_use]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(31);
+        self.initial_delay
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay)
+    }
+
+    /// Whether attempt `attempt`, counted from 1, may be made
+    #[must_use]
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt <= max)
+    }
 }
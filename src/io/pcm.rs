@@ -0,0 +1,282 @@
+//! Raw PCM streams
+//!
+//! [`PcmWriter`] writes interleaved samples as headerless PCM, little-endian
+//! unless told otherwise, to any [`Write`]: a pipe into an external
+//! encoder, a socket, or a file. With no header there is nothing to patch
+//! afterwards, so unlike a [`WavWriter`](crate::io::wav::WavWriter) it
+//! never seeks, and the reader has to be told the format separately.
+//!
+//! [`PcmReader`] reads such samples back from a region of a file. Without
+//! a header it takes the format from the caller; the WAV, AIFF and CAF
+//! readers parse their headers and hand it the region of sample data.
+//! [`PcmReader::open_input`] opens any of them by the file's extension.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::input::{AudioFileFormat, FileInput};
+use crate::io::wav::{WavReader, decode_sample, write_sample};
+use crate::types::{AudioFormat, BitDepth, Sample};
+
+/// Order of the bytes within each sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ByteOrder {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Streams interleaved samples as raw little-endian PCM.
+#[derive(Debug)]
+pub struct PcmWriter<W: Write> {
+    writer: W,
+    bit_depth: BitDepth,
+    byte_order: ByteOrder,
+    samples_written: u64,
+}
+
+impl<W: Write> PcmWriter<W> {
+    #[must_use]
+    pub const fn new(writer: W, bit_depth: BitDepth) -> Self {
+        Self {
+            writer,
+            bit_depth,
+            byte_order: ByteOrder::Little,
+            samples_written: 0,
+        }
+    }
+
+    /// Sets the byte order samples are written in
+    #[must_use]
+    pub const fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    #[must_use]
+    pub const fn bit_depth(&self) -> BitDepth {
+        self.bit_depth
+    }
+
+    #[must_use]
+    pub const fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    /// Samples written so far, across all channels
+    #[must_use]
+    pub const fn samples_written(&self) -> u64 {
+        self.samples_written
+    }
+
+    /// Appends interleaved samples, converting to the stream's bit depth.
+    ///
+    /// # Errors
+    /// Returns an error on I/O failure, e.g. the reading end of a pipe
+    /// having closed.
+    pub fn write_samples(&mut self, samples: &[Sample]) -> Result<()> {
+        for &sample in samples {
+            write_ordered(&mut self.writer, sample, self.bit_depth, self.byte_order)?;
+        }
+        self.samples_written += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Flushes anything buffered on the way to the writer.
+    ///
+    /// # Errors
+    /// Returns an error on I/O failure.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Flushes and hands back the writer, e.g. to close a pipe and wait
+    /// for the encoder on the other end.
+    ///
+    /// # Errors
+    /// Returns an error if the final flush fails.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads interleaved samples from a region of headerless PCM.
+#[derive(Debug)]
+pub struct PcmReader<R: Read + Seek> {
+    reader: R,
+    format: AudioFormat,
+    byte_order: ByteOrder,
+    data_start: u64,
+    data_bytes: u64,
+    position: u64,
+    bytes: Vec<u8>,
+}
+
+impl PcmReader<BufReader<File>> {
+    /// Opens a headerless file of little-endian samples in `format`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read.
+    pub fn open(path: impl AsRef<Path>, format: AudioFormat) -> Result<Self> {
+        let file = File::open(path)?;
+        Self::new(BufReader::new(file), format)
+    }
+
+    /// Opens the file of `input` by its extension: WAV, AIFF, CAF, or raw
+    /// PCM in the input's [`raw_format`](FileInput::raw_format).
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read, isn't in one of those
+    /// formats, or is raw without a format set.
+    pub fn open_input(input: &FileInput) -> Result<Self> {
+        match input.format() {
+            Some(AudioFileFormat::Wav) => Ok(WavReader::open(&input.path)?.into_pcm()),
+            Some(AudioFileFormat::Aiff) => Self::open_aiff(&input.path),
+            Some(AudioFileFormat::Caf) => Self::open_caf(&input.path),
+            Some(AudioFileFormat::Raw) => {
+                let format = input.raw_format.ok_or_else(|| {
+                    AudioEngineError::configuration(format!(
+                        "{} is raw PCM, so its format has to be given",
+                        input.path.display()
+                    ))
+                })?;
+                Self::open(&input.path, format)
+            }
+            format => Err(AudioEngineError::UnsupportedFormat {
+                format: format.map_or_else(
+                    || input.extension().unwrap_or("no extension").to_string(),
+                    |format| format.to_string(),
+                ),
+            }),
+        }
+    }
+}
+
+impl<R: Read + Seek> PcmReader<R> {
+    /// Reads the whole of `reader` as little-endian samples in `format`.
+    ///
+    /// # Errors
+    /// Returns an error if the reader can't be seeked.
+    pub fn new(reader: R, format: AudioFormat) -> Result<Self> {
+        Self::region(reader, format, ByteOrder::Little, 0, u64::MAX)
+    }
+
+    /// Reads `data_bytes` of samples from `data_start` on, or up to the end
+    /// of the stream if it is shorter, e.g. because its header was never
+    /// patched. A partial frame at the end is left out.
+    pub(crate) fn region(
+        mut reader: R,
+        format: AudioFormat,
+        byte_order: ByteOrder,
+        data_start: u64,
+        data_bytes: u64,
+    ) -> Result<Self> {
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(data_start))?;
+        let data_bytes = data_bytes.min(end.saturating_sub(data_start));
+        Ok(Self {
+            reader,
+            format,
+            byte_order,
+            data_start,
+            data_bytes: data_bytes - data_bytes % u64::from(format.frame_size()),
+            position: 0,
+            bytes: Vec::new(),
+        })
+    }
+
+    /// Sets the byte order samples are read in
+    #[must_use]
+    pub const fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    #[must_use]
+    pub const fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    /// Total number of frames
+    #[must_use]
+    pub fn frames(&self) -> u64 {
+        self.data_bytes / u64::from(self.format.frame_size())
+    }
+
+    /// Current read position in frames
+    #[must_use]
+    pub fn position(&self) -> u64 {
+        self.position / u64::from(self.format.frame_size())
+    }
+
+    /// Moves the read position to `frame`, clamped to the end of the data.
+    ///
+    /// # Errors
+    /// Returns an error if the underlying seek fails.
+    pub fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        self.position = frame
+            .saturating_mul(u64::from(self.format.frame_size()))
+            .min(self.data_bytes);
+        self.reader
+            .seek(SeekFrom::Start(self.data_start + self.position))?;
+        Ok(())
+    }
+
+    /// Reads interleaved samples into `out`, returning how many were read.
+    ///
+    /// Only whole frames are read; zero means the end of the data.
+    ///
+    /// # Errors
+    /// Returns an error on I/O failure.
+    pub fn read_samples(&mut self, out: &mut [Sample]) -> Result<usize> {
+        let channels = self.format.channels.count_usize();
+        let bytes_per_sample = self.format.bit_depth.bytes_per_sample() as usize;
+        let remaining = usize::try_from(self.data_bytes - self.position).unwrap_or(usize::MAX);
+        let count = (out.len() - out.len() % channels).min(remaining / bytes_per_sample);
+        if count == 0 {
+            return Ok(0);
+        }
+
+        self.bytes.resize(count * bytes_per_sample, 0);
+        self.reader.read_exact(&mut self.bytes)?;
+        self.position += self.bytes.len() as u64;
+        for (sample, bytes) in out
+            .iter_mut()
+            .zip(self.bytes.chunks_exact_mut(bytes_per_sample))
+        {
+            if self.byte_order == ByteOrder::Big {
+                bytes.reverse();
+            }
+            *sample = Sample::new(decode_sample(self.format.bit_depth, bytes));
+        }
+        Ok(count)
+    }
+}
+
+/// Writes one sample as `bit_depth` in `byte_order`, clipped to full scale.
+pub(crate) fn write_ordered(
+    writer: &mut impl Write,
+    sample: Sample,
+    bit_depth: BitDepth,
+    byte_order: ByteOrder,
+) -> std::io::Result<()> {
+    match byte_order {
+        ByteOrder::Little => write_sample(writer, sample, bit_depth),
+        ByteOrder::Big => {
+            let mut bytes = [0u8; 8];
+            write_sample(&mut &mut bytes[..], sample, bit_depth)?;
+            let len = bit_depth.bytes_per_sample() as usize;
+            bytes[..len].reverse();
+            writer.write_all(&bytes[..len])
+        }
+    }
+}
@@ -0,0 +1,565 @@
+//! Gapless playlist playback
+//!
+//! A [`Playlist`] queues [`FileInput`]s and a [`PlaylistPlayer`] plays
+//! them one after another. The split follows the graph's: the playlist
+//! lives on the control thread, opens files and keeps a ring of decoded
+//! audio filled for the playing track and the one after it; the player is
+//! a graph [`Source`] on the audio thread that only reads from those rings.
+//!
+//! The next track's first blocks are buffered while the current one is
+//! still playing, and the player knows exactly how many frames each track
+//! has, so it switches on the sample the current track ends: no gap, no
+//! overlap. With a crossfade set, the end of one track instead blends into
+//! the start of the next, and skipping fades between the two as well.
+//!
+//! Track changes, the end of the playlist and underruns come back as
+//! [`PlaylistEvent`]s. Call [`Playlist::pump`] regularly, at least a few
+//! times per ring length, to keep the rings filled.
+
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::buffer::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::channel::{
+    ControlReceiver, ControlSender, RealtimeReceiver, RealtimeSender, control_channel,
+    feedback_channel,
+};
+use crate::dsp::crossfade::{Crossfade, CrossfadeCurve};
+use crate::error::{AudioEngineError, Result};
+use crate::graph::Source;
+use crate::io::input::FileInput;
+use crate::io::wav::WavReader;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+/// Messages that can be waiting for the player
+const MESSAGE_CAPACITY: usize = 8;
+/// Events that can be waiting for the control thread
+const EVENT_CAPACITY: usize = 32;
+
+/// Sizes a [`Playlist`]'s buffers and sets its transitions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaylistSettings {
+    /// Length of each track's ring of decoded audio
+    pub buffer_ms: u32,
+    /// Overlap between tracks and fade time when skipping; zero plays the
+    /// tracks back to back
+    pub crossfade_ms: u32,
+    pub curve: CrossfadeCurve,
+}
+
+impl Default for PlaylistSettings {
+    fn default() -> Self {
+        Self {
+            buffer_ms: 1000,
+            crossfade_ms: 0,
+            curve: CrossfadeCurve::EqualPower,
+        }
+    }
+}
+
+impl PlaylistSettings {
+    #[must_use]
+    pub const fn with_buffer_ms(mut self, millis: u32) -> Self {
+        self.buffer_ms = millis;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_crossfade(mut self, millis: u32, curve: CrossfadeCurve) -> Self {
+        self.crossfade_ms = millis;
+        self.curve = curve;
+        self
+    }
+}
+
+/// Sent from the player to the control thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistEvent {
+    /// The track at this position in the playlist started playing
+    TrackChanged { index: usize },
+    /// The last track ended
+    Finished,
+    /// The track's ring ran dry; [`Playlist::pump`] isn't called often
+    /// enough
+    Underrun { index: usize },
+}
+
+/// One track on its way through the player.
+#[derive(Debug)]
+struct Deck {
+    /// Tells the deck apart from other plays of the same track
+    serial: u64,
+    index: usize,
+    /// Frames the track delivers in all
+    frames: u64,
+    played: u64,
+    ring: RingBufferReader<Sample>,
+}
+
+impl Deck {
+    const fn remaining(&self) -> u64 {
+        self.frames - self.played
+    }
+
+    const fn is_done(&self) -> bool {
+        self.played >= self.frames
+    }
+
+    /// Adds the next frame, scaled by `gain`, to `frame`. Returns false if
+    /// the frame hasn't been decoded yet.
+    fn mix(&mut self, frame: &mut [Sample], gain: f32) -> bool {
+        if self.is_done() {
+            return true;
+        }
+        if self.ring.slots() < frame.len() {
+            return false;
+        }
+        for out in frame {
+            let value = self.ring.pop().map_or(0.0, Sample::value);
+            *out = Sample::new(value.mul_add(gain, out.value()));
+        }
+        self.played += 1;
+        true
+    }
+}
+
+#[derive(Debug)]
+enum PlaylistMessage {
+    /// Play this deck now, dropping whatever was playing
+    Cue(Box<Deck>),
+    /// Play this deck when the current one ends
+    Queue(Box<Deck>),
+    SetCrossfade(u32),
+    Stop,
+}
+
+/// A track being decoded into its deck's ring.
+struct Loader {
+    serial: u64,
+    index: usize,
+    file: WavReader<BufReader<File>>,
+    ring: RingBufferWriter<Sample>,
+}
+
+impl Loader {
+    /// Decodes as much as fits in the ring.
+    fn fill(&mut self, scratch: &mut [Sample], channels: usize) -> Result<()> {
+        loop {
+            let room = (self.ring.slots() / channels * channels).min(scratch.len());
+            if room == 0 {
+                return Ok(());
+            }
+            let read = self.file.read_samples(&mut scratch[..room])?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.ring.push_slice(&scratch[..read]);
+        }
+    }
+}
+
+/// Control side of a playlist: the queue, the open files and the
+/// transport.
+pub struct Playlist {
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    settings: PlaylistSettings,
+    tracks: Vec<FileInput>,
+    /// The playing track first, then the one queued after it
+    loaders: Vec<Loader>,
+    /// Next track to queue
+    upcoming: usize,
+    serial: u64,
+    playing: bool,
+    scratch: Vec<Sample>,
+    messages: ControlSender<PlaylistMessage>,
+    returned: ControlReceiver<Box<Deck>>,
+    events: ControlReceiver<PlaylistEvent>,
+}
+
+impl Playlist {
+    /// Creates an empty playlist and the player that plays it.
+    ///
+    /// Every file queued must have `sample_rate` and `channels`.
+    #[must_use]
+    pub fn new(
+        sample_rate: SampleRate,
+        channels: ChannelCount,
+        settings: PlaylistSettings,
+    ) -> (Self, PlaylistPlayer) {
+        let (messages, message_receiver) = control_channel(MESSAGE_CAPACITY);
+        // Each message carries at most one deck, and two are in play
+        let (give_back, returned) = feedback_channel(MESSAGE_CAPACITY + 2);
+        let (event_sender, events) = feedback_channel(EVENT_CAPACITY);
+        let ring_frames = sample_rate
+            .samples_for_milliseconds(settings.buffer_ms)
+            .max(1);
+        let playlist = Self {
+            sample_rate,
+            channels,
+            settings,
+            tracks: Vec::new(),
+            loaders: Vec::with_capacity(2),
+            upcoming: 0,
+            serial: 0,
+            playing: false,
+            scratch: vec![Sample::SILENCE; ring_frames as usize * channels.count_usize()],
+            messages,
+            returned,
+            events,
+        };
+        let player = PlaylistPlayer {
+            channels,
+            crossfade_frames: sample_rate.samples_for_milliseconds(settings.crossfade_ms),
+            curve: settings.curve,
+            current: None,
+            next: None,
+            outgoing: None,
+            skip_fade: Crossfade::new(settings.curve),
+            starved: false,
+            messages: message_receiver,
+            returned: give_back,
+            events: event_sender,
+        };
+        (playlist, player)
+    }
+
+    /// Adds a file to the end of the queue, returning its index.
+    ///
+    /// The file is opened to check it can be played, then closed again
+    /// until its turn comes.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read, its format doesn't
+    /// match the playlist's, or it loops or is time-stretched.
+    pub fn push(&mut self, file: FileInput) -> Result<usize> {
+        if file.looping {
+            return Err(AudioEngineError::configuration(
+                "a looping file never ends, so nothing can follow it",
+            ));
+        }
+        if (file.speed - 1.0).abs() > f32::EPSILON {
+            return Err(AudioEngineError::configuration(
+                "playlists play files at their own speed",
+            ));
+        }
+        self.check_format(&WavReader::open(&file.path)?)?;
+        self.tracks.push(file);
+        Ok(self.tracks.len() - 1)
+    }
+
+    #[must_use]
+    pub fn tracks(&self) -> &[FileInput] {
+        &self.tracks
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> PlaylistSettings {
+        self.settings
+    }
+
+    /// Index of the track playing, as far as the control thread knows
+    #[must_use]
+    pub fn current(&self) -> Option<usize> {
+        self.loaders.first().map(|loader| loader.index)
+    }
+
+    #[must_use]
+    pub const fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Track changes, the end of the playlist and underruns
+    #[must_use]
+    pub const fn events(&self) -> &ControlReceiver<PlaylistEvent> {
+        &self.events
+    }
+
+    /// Starts playing from the track at `index`, buffering it and the
+    /// track after it first.
+    ///
+    /// # Errors
+    /// Returns an error if there is no such track, it can't be read, or
+    /// the message queue is full.
+    pub fn play(&mut self, index: usize) -> Result<()> {
+        if index >= self.tracks.len() {
+            return Err(AudioEngineError::configuration(format!(
+                "no track {index} in a playlist of {}",
+                self.tracks.len()
+            )));
+        }
+        let (loader, deck) = self.open(index)?;
+        self.messages.try_send(PlaylistMessage::Cue(deck))?;
+        self.loaders.clear();
+        self.loaders.push(loader);
+        self.upcoming = index + 1;
+        self.playing = true;
+        self.pump()
+    }
+
+    /// Moves on to the next track. At the last track this stops.
+    ///
+    /// # Errors
+    /// See [`play`](Self::play).
+    pub fn skip(&mut self) -> Result<()> {
+        match self.current() {
+            Some(index) if index + 1 < self.tracks.len() => self.play(index + 1),
+            Some(_) => self.stop(),
+            None => Ok(()),
+        }
+    }
+
+    /// Goes back to the previous track, or restarts the first one.
+    ///
+    /// # Errors
+    /// See [`play`](Self::play).
+    pub fn previous(&mut self) -> Result<()> {
+        self.current()
+            .map_or(Ok(()), |index| self.play(index.saturating_sub(1)))
+    }
+
+    /// Stops playback and closes the files.
+    ///
+    /// # Errors
+    /// Returns an error if the message queue is full.
+    pub fn stop(&mut self) -> Result<()> {
+        self.messages.try_send(PlaylistMessage::Stop)?;
+        self.loaders.clear();
+        self.playing = false;
+        Ok(())
+    }
+
+    /// Changes the crossfade length for the transitions to come.
+    ///
+    /// # Errors
+    /// Returns an error if the message queue is full.
+    pub fn set_crossfade_ms(&mut self, millis: u32) -> Result<()> {
+        let frames = self.sample_rate.samples_for_milliseconds(millis);
+        self.messages
+            .try_send(PlaylistMessage::SetCrossfade(frames))?;
+        self.settings.crossfade_ms = millis;
+        Ok(())
+    }
+
+    /// Frees the tracks the player has finished, queues the next one and
+    /// tops up the rings. Tracks that can't be opened are skipped.
+    ///
+    /// # Errors
+    /// Returns an error if reading a file fails.
+    pub fn pump(&mut self) -> Result<()> {
+        while let Some(deck) = self.returned.try_recv() {
+            self.loaders.retain(|loader| loader.serial != deck.serial);
+        }
+        if !self.playing {
+            return Ok(());
+        }
+        if self.loaders.is_empty() {
+            self.playing = false;
+            return Ok(());
+        }
+        while self.loaders.len() < 2 && self.upcoming < self.tracks.len() {
+            let index = self.upcoming;
+            self.upcoming += 1;
+            match self.open(index) {
+                Ok((loader, deck)) => {
+                    self.messages.try_send(PlaylistMessage::Queue(deck))?;
+                    self.loaders.push(loader);
+                }
+                Err(e) => log::warn!("Skipping playlist track {index}: {e}"),
+            }
+        }
+        let channels = self.channels.count_usize();
+        for loader in &mut self.loaders {
+            loader.fill(&mut self.scratch, channels)?;
+        }
+        Ok(())
+    }
+
+    /// Opens a track and buffers its first blocks.
+    fn open(&mut self, index: usize) -> Result<(Loader, Box<Deck>)> {
+        let track = &self.tracks[index];
+        let mut file = WavReader::open(&track.path)?;
+        self.check_format(&file)?;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let start = (track.start_position.max(0.0) * f64::from(self.sample_rate.as_hz())) as u64;
+        file.seek_frame(start.min(file.frames()))?;
+        let frames = file.frames() - file.position();
+        let (writer, reader) = RingBuffer::new(self.scratch.len());
+        self.serial += 1;
+        let mut loader = Loader {
+            serial: self.serial,
+            index,
+            file,
+            ring: writer,
+        };
+        loader.fill(&mut self.scratch, self.channels.count_usize())?;
+        let deck = Box::new(Deck {
+            serial: self.serial,
+            index,
+            frames,
+            played: 0,
+            ring: reader,
+        });
+        Ok((loader, deck))
+    }
+
+    fn check_format(&self, file: &WavReader<BufReader<File>>) -> Result<()> {
+        let format = file.format();
+        if format.sample_rate != self.sample_rate || format.channels != self.channels {
+            return Err(AudioEngineError::FormatMismatch {
+                expected: format!("{:?} {:?}", self.sample_rate, self.channels),
+                actual: format!("{:?} {:?}", format.sample_rate, format.channels),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Playlist {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Playlist")
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("settings", &self.settings)
+            .field("tracks", &self.tracks.len())
+            .field("current", &self.current())
+            .field("playing", &self.playing)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Audio thread side of a [`Playlist`].
+#[derive(Debug)]
+pub struct PlaylistPlayer {
+    channels: ChannelCount,
+    crossfade_frames: u32,
+    curve: CrossfadeCurve,
+    current: Option<Box<Deck>>,
+    next: Option<Box<Deck>>,
+    /// Track fading out after a skip
+    outgoing: Option<Box<Deck>>,
+    skip_fade: Crossfade,
+    /// Whether an underrun has been reported and not recovered from
+    starved: bool,
+    messages: RealtimeReceiver<PlaylistMessage>,
+    returned: RealtimeSender<Box<Deck>>,
+    events: RealtimeSender<PlaylistEvent>,
+}
+
+impl PlaylistPlayer {
+    fn receive(&mut self) {
+        while let Some(message) = self.messages.try_recv() {
+            match message {
+                PlaylistMessage::Cue(deck) => {
+                    let index = deck.index;
+                    let next = self.next.take();
+                    self.give_back(next);
+                    let outgoing = self.outgoing.take();
+                    self.give_back(outgoing);
+                    let previous = self.current.replace(deck);
+                    if self.crossfade_frames > 0 && previous.is_some() {
+                        self.outgoing = previous;
+                        self.skip_fade.start(self.crossfade_frames);
+                    } else {
+                        self.give_back(previous);
+                    }
+                    let _ = self.events.try_send(PlaylistEvent::TrackChanged { index });
+                }
+                PlaylistMessage::Queue(deck) => {
+                    if self.current.is_none() {
+                        let index = deck.index;
+                        self.current = Some(deck);
+                        let _ = self.events.try_send(PlaylistEvent::TrackChanged { index });
+                    } else {
+                        let previous = self.next.replace(deck);
+                        self.give_back(previous);
+                    }
+                }
+                PlaylistMessage::SetCrossfade(frames) => self.crossfade_frames = frames,
+                PlaylistMessage::Stop => {
+                    let decks = [self.current.take(), self.next.take(), self.outgoing.take()];
+                    for deck in decks {
+                        self.give_back(deck);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hands a deck back to be freed on the control thread.
+    fn give_back(&self, deck: Option<Box<Deck>>) {
+        if let Some(deck) = deck {
+            let _ = self.returned.try_send(deck);
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn render_frame(&mut self, frame: &mut [Sample]) {
+        let mut gain = 1.0;
+        if let Some(outgoing) = &mut self.outgoing {
+            let (out_gain, in_gain) = self.skip_fade.next_gains();
+            outgoing.mix(frame, out_gain);
+            gain = in_gain;
+            if !self.skip_fade.is_active() {
+                let outgoing = self.outgoing.take();
+                self.give_back(outgoing);
+            }
+        }
+        let Some(current) = &mut self.current else {
+            return;
+        };
+        let remaining = current.remaining();
+        if let Some(next) = &mut self.next
+            && self.crossfade_frames > 0
+            && remaining <= u64::from(self.crossfade_frames)
+        {
+            let t = 1.0 - remaining as f32 / self.crossfade_frames as f32;
+            let (out_gain, in_gain) = self.curve.gains(t);
+            next.mix(frame, in_gain * gain);
+            gain *= out_gain;
+        }
+        if current.mix(frame, gain) {
+            self.starved = false;
+        } else if !self.starved {
+            self.starved = true;
+            let index = current.index;
+            let _ = self.events.try_send(PlaylistEvent::Underrun { index });
+        }
+        if current.is_done() {
+            let finished = self.current.take();
+            self.give_back(finished);
+            self.current = self.next.take();
+            let event = self
+                .current
+                .as_ref()
+                .map_or(PlaylistEvent::Finished, |deck| {
+                    PlaylistEvent::TrackChanged { index: deck.index }
+                });
+            let _ = self.events.try_send(event);
+        }
+    }
+}
+
+impl Source for PlaylistPlayer {
+    /// Plays silence if `channels` isn't the playlist's layout.
+    fn render(&mut self, output: &mut [Sample], channels: ChannelCount) {
+        self.receive();
+        output.fill(Sample::SILENCE);
+        if channels != self.channels {
+            return;
+        }
+        for frame in output.chunks_exact_mut(channels.count_usize()) {
+            self.render_frame(frame);
+        }
+    }
+}
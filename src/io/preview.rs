@@ -0,0 +1,373 @@
+//! Short loudness-normalised previews for asset browsers
+//!
+//! [`Preview::render`] takes the first seconds of an input source, measures
+//! their integrated loudness, applies the gain that brings them to a common
+//! target (held below a true peak ceiling) and fades both ends, so clicking
+//! through a library doesn't jump in level or start and stop with a click.
+
+use std::path::Path;
+
+use crate::analysis::loudness::LoudnessMeter;
+use crate::dsp::crossfade::CrossfadeCurve;
+use crate::error::{AudioEngineError, Result};
+use crate::graph::Source;
+use crate::io::generator::SignalSource;
+use crate::io::input::{InputSource, SignalGenerator};
+use crate::io::wav::{WavReader, WavWriter};
+use crate::types::{AudioFormat, BitDepth, ChannelCount, Sample, SampleRate};
+
+/// Format generated signals are previewed in
+const SIGNAL_FORMAT: AudioFormat =
+    AudioFormat::new(SampleRate::Hz48000, ChannelCount::Stereo, BitDepth::F32);
+
+/// Longest generated signal previewed, ten minutes at 48 kHz; a signal
+/// with no end would otherwise fill memory
+const MAX_SIGNAL_FRAMES: u64 = 48_000 * 600;
+
+/// Frames read or generated at a time
+const BLOCK_FRAMES: usize = 4096;
+
+/// How a preview is cut and levelled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreviewSettings {
+    /// Longest preview, in seconds
+    pub duration_seconds: f64,
+    /// Integrated loudness the preview is normalised to, in LUFS
+    pub target_lufs: f64,
+    /// Highest true peak allowed after normalisation, in dBTP
+    pub peak_ceiling_db: f64,
+    /// Fade at the start, in milliseconds
+    pub fade_in_ms: f32,
+    /// Fade at the end, in milliseconds
+    pub fade_out_ms: f32,
+    /// Shape of both fades
+    pub fade_curve: CrossfadeCurve,
+}
+
+impl Default for PreviewSettings {
+    fn default() -> Self {
+        Self {
+            duration_seconds: 30.0,
+            target_lufs: -18.0,
+            peak_ceiling_db: -1.0,
+            fade_in_ms: 10.0,
+            fade_out_ms: 1000.0,
+            fade_curve: CrossfadeCurve::SCurve,
+        }
+    }
+}
+
+impl PreviewSettings {
+    /// Sets the longest preview length
+    #[must_use]
+    pub const fn with_duration(mut self, seconds: f64) -> Self {
+        self.duration_seconds = seconds;
+        self
+    }
+
+    /// Sets the loudness target
+    #[must_use]
+    pub const fn with_target_lufs(mut self, lufs: f64) -> Self {
+        self.target_lufs = lufs;
+        self
+    }
+
+    /// Sets the true peak ceiling
+    #[must_use]
+    pub const fn with_peak_ceiling(mut self, db: f64) -> Self {
+        self.peak_ceiling_db = db;
+        self
+    }
+
+    /// Sets the fade lengths
+    #[must_use]
+    pub const fn with_fades(mut self, fade_in_ms: f32, fade_out_ms: f32) -> Self {
+        self.fade_in_ms = fade_in_ms;
+        self.fade_out_ms = fade_out_ms;
+        self
+    }
+
+    /// Sets the fade shape
+    #[must_use]
+    pub const fn with_fade_curve(mut self, curve: CrossfadeCurve) -> Self {
+        self.fade_curve = curve;
+        self
+    }
+}
+
+/// A rendered preview held in memory.
+#[derive(Debug, Clone)]
+pub struct Preview {
+    format: AudioFormat,
+    samples: Vec<Sample>,
+    source_lufs: f64,
+    gain_db: f64,
+}
+
+impl Preview {
+    /// Renders a preview of `source`.
+    ///
+    /// Files start at their configured start position and play at normal
+    /// speed; only WAV files can be read. Generated signals are rendered as
+    /// 48 kHz stereo, cut short where sweeps end and at ten minutes. Device
+    /// and network inputs are live and have no beginning to preview, so
+    /// they are rejected.
+    ///
+    /// # Errors
+    /// Returns an error if the source can't be previewed or read.
+    pub fn render(source: &InputSource, settings: &PreviewSettings) -> Result<Self> {
+        let (format, samples) = match source {
+            InputSource::File(file) => {
+                let mut reader = WavReader::open(&file.path)?;
+                let format = reader.format();
+                let start = seconds_to_frames(file.start_position, format.sample_rate);
+                reader.seek_frame(start.min(reader.frames()))?;
+                let frames = seconds_to_frames(settings.duration_seconds, format.sample_rate);
+                (format, read_frames(&mut reader, frames)?)
+            }
+            InputSource::Signal(signal) => {
+                let frames = signal_frames(*signal, settings.duration_seconds);
+                (SIGNAL_FORMAT, generate(*signal, frames))
+            }
+            other => {
+                return Err(AudioEngineError::configuration(format!(
+                    "can't render a preview of live input {other}"
+                )));
+            }
+        };
+        Ok(Self::from_samples(format, samples, settings))
+    }
+
+    /// Normalises and fades already decoded audio.
+    ///
+    /// `samples` is interleaved and is truncated to the preview length.
+    #[must_use]
+    pub fn from_samples(
+        format: AudioFormat,
+        mut samples: Vec<Sample>,
+        settings: &PreviewSettings,
+    ) -> Self {
+        let channels = format.channels.count_usize();
+        let frames = seconds_to_frames(settings.duration_seconds, format.sample_rate);
+        samples.truncate(
+            usize::try_from(frames)
+                .unwrap_or(usize::MAX)
+                .saturating_mul(channels),
+        );
+        samples.truncate(samples.len() - samples.len() % channels);
+
+        let mut meter = LoudnessMeter::new(format);
+        meter.measure(&samples);
+        let source_lufs = meter.integrated();
+        let peak_db = meter.max_true_peak();
+        let gain_db = if source_lufs.is_finite() {
+            let headroom = if peak_db.is_finite() {
+                settings.peak_ceiling_db - peak_db
+            } else {
+                f64::INFINITY
+            };
+            (settings.target_lufs - source_lufs).min(headroom)
+        } else {
+            0.0
+        };
+
+        #[allow(clippy::cast_possible_truncation)]
+        let gain = 10.0f64.powf(gain_db / 20.0) as f32;
+        let fade_in = fade_frames(settings.fade_in_ms, format.sample_rate);
+        let fade_out = fade_frames(settings.fade_out_ms, format.sample_rate);
+        let total = samples.len() / channels;
+        for (index, frame) in samples.chunks_exact_mut(channels).enumerate() {
+            let mut factor = gain;
+            if index < fade_in {
+                factor *= fade_gain(settings.fade_curve, index, fade_in);
+            }
+            let remaining = total - index - 1;
+            if remaining < fade_out {
+                factor *= fade_gain(settings.fade_curve, remaining, fade_out);
+            }
+            for sample in frame {
+                *sample = Sample::new(sample.value() * factor);
+            }
+        }
+
+        Self {
+            format,
+            samples,
+            source_lufs,
+            gain_db,
+        }
+    }
+
+    /// Format of the preview; the bit depth is the one it's written with
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Interleaved preview audio
+    #[must_use]
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    /// Takes the preview audio
+    #[must_use]
+    pub fn into_samples(self) -> Vec<Sample> {
+        self.samples
+    }
+
+    #[must_use]
+    pub const fn frames(&self) -> usize {
+        self.samples.len() / self.format.channels.count_usize()
+    }
+
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn duration_seconds(&self) -> f64 {
+        self.frames() as f64 / f64::from(self.format.sample_rate.as_hz())
+    }
+
+    /// Integrated loudness of the excerpt before normalisation, in LUFS
+    #[must_use]
+    pub const fn source_lufs(&self) -> f64 {
+        self.source_lufs
+    }
+
+    /// Gain applied to reach the target, in dB
+    #[must_use]
+    pub const fn gain_db(&self) -> f64 {
+        self.gain_db
+    }
+
+    /// Writes the preview as a WAV file.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be written.
+    pub fn write_wav(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut writer = WavWriter::create(path, self.format)?;
+        writer.write_samples(&self.samples)?;
+        writer.finalize()
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn seconds_to_frames(seconds: f64, sample_rate: SampleRate) -> u64 {
+    (seconds.max(0.0) * f64::from(sample_rate.as_hz())).round() as u64
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn fade_frames(ms: f32, sample_rate: SampleRate) -> usize {
+    (ms.max(0.0) * sample_rate.as_f32() / 1000.0).round() as usize
+}
+
+/// Gain `position` frames into a fade of `length` frames from silence.
+#[allow(clippy::cast_precision_loss)]
+fn fade_gain(curve: CrossfadeCurve, position: usize, length: usize) -> f32 {
+    curve.gains(position as f32 / length as f32).1
+}
+
+fn read_frames(
+    reader: &mut WavReader<impl std::io::Read + std::io::Seek>,
+    frames: u64,
+) -> Result<Vec<Sample>> {
+    let channels = reader.format().channels.count_usize();
+    // Never more than the file holds, however long the preview asked for
+    let frames = frames.min(reader.frames().saturating_sub(reader.position()));
+    let wanted = usize::try_from(frames)
+        .unwrap_or(usize::MAX)
+        .saturating_mul(channels);
+    let mut samples = Vec::new();
+    let mut block = vec![Sample::SILENCE; BLOCK_FRAMES * channels];
+    while samples.len() < wanted {
+        let take = (wanted - samples.len()).min(block.len());
+        let count = reader.read_samples(&mut block[..take])?;
+        if count == 0 {
+            break;
+        }
+        samples.extend_from_slice(&block[..count]);
+    }
+    Ok(samples)
+}
+
+/// Frames of `signal` to preview, cut short where it ends
+fn signal_frames(signal: SignalGenerator, seconds: f64) -> u64 {
+    let rate = SIGNAL_FORMAT.sample_rate;
+    let end = signal.frames(rate).unwrap_or(u64::MAX);
+    seconds_to_frames(seconds, rate)
+        .min(end)
+        .min(MAX_SIGNAL_FRAMES)
+}
+
+fn generate(signal: SignalGenerator, frames: u64) -> Vec<Sample> {
+    let channels = SIGNAL_FORMAT.channels;
+    let wanted = usize::try_from(frames)
+        .unwrap_or(usize::MAX)
+        .saturating_mul(channels.count_usize());
+    let mut source = SignalSource::new(signal, SIGNAL_FORMAT.sample_rate);
+    let mut samples = Vec::new();
+    let mut block = vec![Sample::SILENCE; BLOCK_FRAMES * channels.count_usize()];
+    while samples.len() < wanted {
+        let take = (wanted - samples.len()).min(block.len());
+        source.render(&mut block[..take], channels);
+        samples.extend_from_slice(&block[..take]);
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const SINE: SignalGenerator = SignalGenerator::Sine {
+        frequency_hz: 440.0,
+    };
+
+    fn wav(frames: usize) -> WavReader<Cursor<Vec<u8>>> {
+        let format = AudioFormat::new(SampleRate::Hz48000, ChannelCount::Stereo, BitDepth::F32);
+        let mut bytes = Vec::new();
+        let mut writer = WavWriter::new(Cursor::new(&mut bytes), format).expect("header");
+        writer
+            .write_samples(&generate(SINE, frames as u64))
+            .expect("samples");
+        writer.finalize().expect("finalize");
+        drop(writer);
+        WavReader::new(Cursor::new(bytes)).expect("reads back")
+    }
+
+    #[test]
+    fn reads_no_further_than_the_file_holds() {
+        let frames = BLOCK_FRAMES * 2 + 100;
+        let mut reader = wav(frames);
+        let samples = read_frames(&mut reader, u64::MAX).expect("reads");
+        assert_eq!(samples.len(), frames * 2);
+        assert_eq!(samples, generate(SINE, frames as u64));
+
+        let mut reader = wav(frames);
+        reader.seek_frame(frames as u64 - 10).expect("seeks");
+        assert_eq!(read_frames(&mut reader, u64::MAX).expect("reads").len(), 20);
+        assert!(read_frames(&mut reader, 5).expect("reads").is_empty());
+    }
+
+    #[test]
+    fn generates_across_blocks_without_a_seam() {
+        let frames = BLOCK_FRAMES * 3 + 7;
+        let mut whole = vec![Sample::SILENCE; frames * 2];
+        SignalSource::new(SINE, SIGNAL_FORMAT.sample_rate)
+            .render(&mut whole, SIGNAL_FORMAT.channels);
+        assert_eq!(generate(SINE, frames as u64), whole);
+    }
+
+    #[test]
+    fn signals_without_an_end_are_capped() {
+        assert_eq!(signal_frames(SINE, f64::INFINITY), MAX_SIGNAL_FRAMES);
+        assert_eq!(signal_frames(SINE, 1e12), MAX_SIGNAL_FRAMES);
+        assert_eq!(signal_frames(SINE, f64::NAN), 0);
+        assert_eq!(signal_frames(SINE, 0.5), 24_000);
+
+        let settings = PreviewSettings::default().with_duration(0.5);
+        let preview = Preview::render(&InputSource::Signal(SINE), &settings).expect("renders");
+        assert_eq!(preview.frames(), 24_000);
+    }
+}
@@ -0,0 +1,345 @@
+//! Audio file probing
+//!
+//! [`probe_file`] reads what an application needs to list a file, its
+//! format, length, bitrate and title and artist tags, from the headers
+//! alone, without decoding any audio. It is cheap enough to run over a
+//! whole library when building a playlist.
+//!
+//! WAV, AIFF and CAF files are read natively, tags included (`LIST/INFO`,
+//! `NAME`/`AUTH` and `info` chunks). Opus files are read natively with the
+//! `opus` feature, and everything else needs the `symphonia` feature.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::input::{AudioFileFormat, FileInput};
+use crate::io::pcm::PcmReader;
+use crate::types::{AudioFormat, FrameCount, StreamBitrate};
+
+/// Largest metadata chunk read for tags
+const MAX_TAG_CHUNK: u64 = 64 * 1024;
+
+/// What [`probe_file`] found out about a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub path: PathBuf,
+    pub file_format: AudioFileFormat,
+    /// Format the file decodes to; lossy formats decode to
+    /// [`BitDepth::F32`](crate::types::BitDepth::F32)
+    pub format: AudioFormat,
+    /// Length in frames, zero if the file doesn't record it
+    pub duration: FrameCount,
+    /// Average bitrate, from the file size and duration
+    pub bitrate: Option<StreamBitrate>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+impl FileInfo {
+    /// Length in seconds, zero if the file doesn't record it
+    #[must_use]
+    pub fn duration_seconds(&self) -> f64 {
+        self.duration.clone().duration_seconds(self.format.sample_rate)
+    }
+}
+
+/// Reads the format, length and tags of the audio file at `path` without
+/// decoding it.
+///
+/// # Errors
+/// Returns an error if the file doesn't exist or can't be read, its format
+/// isn't one that can be probed with the enabled features, or its sample
+/// rate or channel count isn't supported by the engine. Raw PCM can't be
+/// probed, having no header.
+pub fn probe_file(path: impl AsRef<Path>) -> Result<FileInfo> {
+    let path = path.as_ref();
+    let size = std::fs::metadata(path)
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => AudioEngineError::FileNotFound {
+                path: path.to_path_buf(),
+            },
+            _ => e.into(),
+        })?
+        .len();
+    let input = FileInput::new(path);
+    let file_format = input
+        .format()
+        .ok_or_else(|| AudioEngineError::UnsupportedFormat {
+            format: input.extension().unwrap_or("no extension").to_string(),
+        })?;
+
+    let (format, frames, tags) = match file_format {
+        AudioFileFormat::Wav | AudioFileFormat::Aiff | AudioFileFormat::Caf => {
+            let reader = PcmReader::open_input(&input)?;
+            let tags = read_chunk_tags(path, file_format)?;
+            (reader.format(), Some(reader.frames()), tags)
+        }
+        #[cfg(feature = "opus")]
+        AudioFileFormat::Opus => probe_opus(path)?,
+        AudioFileFormat::Raw => {
+            return Err(AudioEngineError::UnsupportedFormat {
+                format: format!("{} is raw PCM, which has no header", path.display()),
+            });
+        }
+        #[cfg(feature = "symphonia")]
+        _ => probe_symphonia(path, file_format)?,
+        #[cfg(not(feature = "symphonia"))]
+        #[allow(unreachable_patterns)]
+        _ => {
+            return Err(AudioEngineError::UnsupportedFormat {
+                format: format!("probing {file_format} files needs the symphonia feature"),
+            });
+        }
+    };
+
+    let duration = FrameCount::new(frames.unwrap_or(0));
+    let seconds = duration.clone().duration_seconds(format.sample_rate);
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    let bitrate = (seconds > 0.0)
+        .then(|| StreamBitrate::from_bps((size as f64 * 8.0 / seconds).round() as u32));
+    Ok(FileInfo {
+        path: path.to_path_buf(),
+        file_format,
+        format,
+        duration,
+        bitrate,
+        title: tags.title,
+        artist: tags.artist,
+    })
+}
+
+/// Tags found in a file
+#[derive(Debug, Default)]
+struct Tags {
+    title: Option<String>,
+    artist: Option<String>,
+}
+
+impl Tags {
+    /// Sets the tag `key` names, if it is one of those kept and not set yet.
+    fn set(&mut self, key: &str, value: &[u8]) {
+        let slot = match key.to_ascii_lowercase().as_str() {
+            "title" | "inam" | "name" => &mut self.title,
+            "artist" | "iart" | "auth" => &mut self.artist,
+            _ => return,
+        };
+        let value = String::from_utf8_lossy(value);
+        let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        if slot.is_none() && !value.is_empty() {
+            *slot = Some(value.to_string());
+        }
+    }
+}
+
+/// Reads the tags of a WAV, AIFF or CAF file from its metadata chunks.
+fn read_chunk_tags(path: &Path, file_format: AudioFileFormat) -> Result<Tags> {
+    let mut file = File::open(path)?;
+    let end = file.seek(SeekFrom::End(0))?;
+    let mut tags = Tags::default();
+    // Chunks start after the file header; CAF chunk sizes are 64 bits
+    let (mut offset, header_len) = match file_format {
+        AudioFileFormat::Caf => (8, 12),
+        _ => (12, 8),
+    };
+    while offset + header_len <= end {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 12];
+        file.read_exact(&mut header[..usize::try_from(header_len).unwrap_or(8)])?;
+        let mut id = [0u8; 4];
+        id.copy_from_slice(&header[..4]);
+        let size = match file_format {
+            AudioFileFormat::Wav => u64::from(u32::from_le_bytes([
+                header[4], header[5], header[6], header[7],
+            ])),
+            AudioFileFormat::Aiff => u64::from(u32::from_be_bytes([
+                header[4], header[5], header[6], header[7],
+            ])),
+            _ => {
+                let mut size = [0u8; 8];
+                size.copy_from_slice(&header[4..]);
+                // A size of -1 runs to the end of the file
+                u64::try_from(i64::from_be_bytes(size)).unwrap_or(end - offset - header_len)
+            }
+        };
+        if matches!(&id, b"LIST" | b"NAME" | b"AUTH" | b"info") && size <= MAX_TAG_CHUNK {
+            let mut payload = vec![0u8; usize::try_from(size).unwrap_or(0)];
+            file.read_exact(&mut payload)?;
+            match &id {
+                b"LIST" if payload.starts_with(b"INFO") => {
+                    parse_info_list(&payload[4..], &mut tags);
+                }
+                b"NAME" | b"AUTH" => tags.set(&String::from_utf8_lossy(&id), &payload),
+                b"info" => parse_caf_info(&payload, &mut tags),
+                _ => {}
+            }
+        }
+        // RIFF and AIFF chunks are word aligned, CAF chunks aren't
+        let padding = if file_format == AudioFileFormat::Caf {
+            0
+        } else {
+            size % 2
+        };
+        offset += header_len + size + padding;
+    }
+    Ok(tags)
+}
+
+/// Reads the tags of a RIFF `LIST/INFO` chunk, past its list type.
+fn parse_info_list(mut list: &[u8], tags: &mut Tags) {
+    while list.len() >= 8 {
+        let id = String::from_utf8_lossy(&list[..4]).into_owned();
+        let size = u32::from_le_bytes([list[4], list[5], list[6], list[7]]) as usize;
+        let Some(value) = list.get(8..8 + size) else {
+            return;
+        };
+        tags.set(&id, value);
+        list = list.get(8 + size + size % 2..).unwrap_or_default();
+    }
+}
+
+/// Reads the tags of a CAF `info` chunk: an entry count, then that many
+/// NUL-terminated keys and values.
+fn parse_caf_info(info: &[u8], tags: &mut Tags) {
+    let mut strings = info.get(4..).unwrap_or_default().split(|&b| b == 0);
+    while let (Some(key), Some(value)) = (strings.next(), strings.next()) {
+        tags.set(&String::from_utf8_lossy(key), value);
+    }
+}
+
+/// Reads the channel count and length from the Opus header and the tags
+/// from the Vorbis comments that follow it.
+#[cfg(feature = "opus")]
+fn probe_opus(path: &Path) -> Result<(AudioFormat, Option<u64>, Tags)> {
+    use crate::io::opus::{DECODE_RATE_HZ, read_header};
+    use crate::types::{BitDepth, SampleRate};
+
+    let (channels, frames) = read_header(path)?;
+    let mut head = Vec::new();
+    File::open(path)?
+        .take(MAX_TAG_CHUNK)
+        .read_to_end(&mut head)?;
+    let mut tags = Tags::default();
+    if let Some(at) = head.windows(8).position(|window| window == b"OpusTags") {
+        parse_vorbis_comments(&head[at + 8..], &mut tags);
+    }
+    let format = AudioFormat::new(
+        SampleRate::try_from(DECODE_RATE_HZ)?,
+        channels,
+        BitDepth::F32,
+    );
+    Ok((format, frames, tags))
+}
+
+/// Reads `KEY=value` Vorbis comments, after the vendor string.
+#[cfg(feature = "opus")]
+fn parse_vorbis_comments(comments: &[u8], tags: &mut Tags) {
+    let read_u32 = |at: usize| {
+        comments
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+    let Some(vendor) = read_u32(0) else {
+        return;
+    };
+    let Some(count) = read_u32(4 + vendor) else {
+        return;
+    };
+    let mut at = 8 + vendor;
+    for _ in 0..count {
+        let Some(len) = read_u32(at) else {
+            return;
+        };
+        let Some(comment) = comments.get(at + 4..at + 4 + len) else {
+            return;
+        };
+        if let Some(split) = comment.iter().position(|&b| b == b'=') {
+            tags.set(
+                &String::from_utf8_lossy(&comment[..split]),
+                &comment[split + 1..],
+            );
+        }
+        at += 4 + len;
+    }
+}
+
+/// Reads the format, length and tags with symphonia's demuxers.
+#[cfg(feature = "symphonia")]
+fn probe_symphonia(
+    path: &Path,
+    file_format: AudioFileFormat,
+) -> Result<(AudioFormat, Option<u64>, Tags)> {
+    use symphonia::core::audio::Channels;
+    use symphonia::core::codecs::CODEC_TYPE_NULL;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+    use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+    use symphonia::core::probe::Hint;
+    use symphonia::core::sample::SampleFormat;
+
+    use crate::io::decode::decode_error;
+    use crate::types::{BitDepth, ChannelCount, SampleRate};
+
+    fn read_revision(revision: Option<&MetadataRevision>, tags: &mut Tags) {
+        for tag in revision.map(MetadataRevision::tags).unwrap_or_default() {
+            let key = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => "title",
+                Some(StandardTagKey::Artist) => "artist",
+                _ => continue,
+            };
+            tags.set(key, tag.value.to_string().as_bytes());
+        }
+    }
+
+    let file = File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), MediaSourceStreamOptions::default());
+    let mut hint = Hint::new();
+    hint.with_extension(file_format.extension());
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| decode_error(path, e))?;
+
+    let mut tags = Tags::default();
+    // Tags in the container win over ones found in front of it, e.g. ID3
+    read_revision(probed.format.metadata().skip_to_latest(), &mut tags);
+    if let Some(mut metadata) = probed.metadata.get() {
+        read_revision(metadata.skip_to_latest(), &mut tags);
+    }
+
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| AudioEngineError::UnsupportedFormat {
+            format: format!("{} has no audio track", path.display()),
+        })?;
+    let params = &track.codec_params;
+    let sample_rate = params
+        .sample_rate
+        .ok_or_else(|| AudioEngineError::UnsupportedFormat {
+            format: format!("{} has no sample rate", path.display()),
+        })?;
+    let channel_count = params.channels.map_or(0, Channels::count);
+    let channels = ChannelCount::try_from(u32::try_from(channel_count).unwrap_or(u32::MAX))?;
+    let bit_depth = match (params.bits_per_sample, params.sample_format) {
+        (_, Some(SampleFormat::F64)) => BitDepth::F64,
+        (_, Some(SampleFormat::F32)) => BitDepth::F32,
+        (Some(16), _) => BitDepth::I16,
+        (Some(24), _) => BitDepth::I24,
+        (Some(32), _) => BitDepth::I32,
+        _ => BitDepth::F32,
+    };
+    let format = AudioFormat::new(SampleRate::try_from(sample_rate)?, channels, bit_depth);
+    Ok((format, params.n_frames, tags))
+}
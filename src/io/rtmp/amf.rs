@@ -0,0 +1,258 @@
+//! AMF0, the encoding of RTMP commands and data messages
+
+use std::io::{self, ErrorKind};
+
+/// Type markers
+const NUMBER: u8 = 0x00;
+const BOOLEAN: u8 = 0x01;
+const STRING: u8 = 0x02;
+const OBJECT: u8 = 0x03;
+const NULL: u8 = 0x05;
+const UNDEFINED: u8 = 0x06;
+const ECMA_ARRAY: u8 = 0x08;
+const OBJECT_END: u8 = 0x09;
+const STRICT_ARRAY: u8 = 0x0A;
+const DATE: u8 = 0x0B;
+const LONG_STRING: u8 = 0x0C;
+
+/// Deepest nesting of objects and arrays decoded, so a hostile server
+/// can't run the stack out
+const MAX_DEPTH: usize = 32;
+
+/// An AMF0 value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, Self)>),
+    Null,
+    Undefined,
+    EcmaArray(Vec<(String, Self)>),
+    StrictArray(Vec<Self>),
+    /// Milliseconds since the Unix epoch
+    Date(f64),
+}
+
+impl Value {
+    pub fn string(text: impl Into<String>) -> Self {
+        Self::String(text.into())
+    }
+
+    /// An object of `properties`, in order
+    pub fn object<'a>(properties: impl IntoIterator<Item = (&'a str, Self)>) -> Self {
+        Self::Object(
+            properties
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// Property `key` of an object or ECMA array
+    pub fn get(&self, key: &str) -> Option<&Self> {
+        match self {
+            Self::Object(properties) | Self::EcmaArray(properties) => properties
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub const fn as_number(&self) -> Option<f64> {
+        match self {
+            Self::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    fn encode(&self, data: &mut Vec<u8>) {
+        match self {
+            Self::Number(number) => {
+                data.push(NUMBER);
+                data.extend_from_slice(&number.to_be_bytes());
+            }
+            Self::Boolean(flag) => data.extend_from_slice(&[BOOLEAN, u8::from(*flag)]),
+            Self::String(text) => {
+                if let Ok(len) = u16::try_from(text.len()) {
+                    data.push(STRING);
+                    data.extend_from_slice(&len.to_be_bytes());
+                } else {
+                    data.push(LONG_STRING);
+                    data.extend_from_slice(&u32::try_from(text.len()).unwrap_or(0).to_be_bytes());
+                }
+                data.extend_from_slice(text.as_bytes());
+            }
+            Self::Object(properties) => {
+                data.push(OBJECT);
+                encode_properties(data, properties);
+            }
+            Self::Null => data.push(NULL),
+            Self::Undefined => data.push(UNDEFINED),
+            Self::EcmaArray(properties) => {
+                data.push(ECMA_ARRAY);
+                data.extend_from_slice(&u32::try_from(properties.len()).unwrap_or(0).to_be_bytes());
+                encode_properties(data, properties);
+            }
+            Self::StrictArray(values) => {
+                data.push(STRICT_ARRAY);
+                data.extend_from_slice(&u32::try_from(values.len()).unwrap_or(0).to_be_bytes());
+                for value in values {
+                    value.encode(data);
+                }
+            }
+            Self::Date(millis) => {
+                data.push(DATE);
+                data.extend_from_slice(&millis.to_be_bytes());
+                // Time zone, which AMF0 says to leave at zero
+                data.extend_from_slice(&[0, 0]);
+            }
+        }
+    }
+}
+
+/// `values` one after another, as a command or data message carries them
+pub fn encode(values: &[Value]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for value in values {
+        value.encode(&mut data);
+    }
+    data
+}
+
+/// The values of a command or data message.
+///
+/// # Errors
+/// Returns an `InvalidData` error if `data` isn't well-formed AMF0 or uses
+/// a type RTMP servers don't send.
+pub fn decode(data: &[u8]) -> io::Result<Vec<Value>> {
+    let mut decoder = Decoder { data, depth: 0 };
+    let mut values = Vec::new();
+    while !decoder.data.is_empty() {
+        values.push(decoder.value()?);
+    }
+    Ok(values)
+}
+
+/// Property names and values, ended by an empty name and the end marker
+fn encode_properties(data: &mut Vec<u8>, properties: &[(String, Value)]) {
+    for (key, value) in properties {
+        let key = &key[..key.len().min(usize::from(u16::MAX))];
+        data.extend_from_slice(&u16::try_from(key.len()).unwrap_or(0).to_be_bytes());
+        data.extend_from_slice(key.as_bytes());
+        value.encode(data);
+    }
+    data.extend_from_slice(&[0, 0, OBJECT_END]);
+}
+
+/// Reads values off the front of a message
+struct Decoder<'a> {
+    data: &'a [u8],
+    depth: usize,
+}
+
+impl Decoder<'_> {
+    fn value(&mut self) -> io::Result<Value> {
+        let marker = self.take(1)?[0];
+        Ok(match marker {
+            NUMBER => Value::Number(self.number()?),
+            BOOLEAN => Value::Boolean(self.take(1)?[0] != 0),
+            STRING => {
+                let len = self.u16()?;
+                Value::String(self.text(usize::from(len))?)
+            }
+            LONG_STRING => {
+                let len = self.u32()?;
+                Value::String(self.text(len as usize)?)
+            }
+            OBJECT => Value::Object(self.nested(Self::properties)?),
+            NULL => Value::Null,
+            UNDEFINED => Value::Undefined,
+            ECMA_ARRAY => {
+                // The count is only a hint; the end marker ends the array
+                self.u32()?;
+                Value::EcmaArray(self.nested(Self::properties)?)
+            }
+            STRICT_ARRAY => {
+                let count = self.u32()?;
+                Value::StrictArray(
+                    self.nested(|decoder| (0..count).map(|_| decoder.value()).collect())?,
+                )
+            }
+            DATE => {
+                let millis = self.number()?;
+                self.take(2)?;
+                Value::Date(millis)
+            }
+            marker => {
+                return Err(invalid(&format!("unsupported AMF0 type {marker:#04x}")));
+            }
+        })
+    }
+
+    fn nested<T>(&mut self, read: impl FnOnce(&mut Self) -> io::Result<T>) -> io::Result<T> {
+        if self.depth == MAX_DEPTH {
+            return Err(invalid("AMF0 values nested too deep"));
+        }
+        self.depth += 1;
+        let value = read(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn properties(&mut self) -> io::Result<Vec<(String, Value)>> {
+        let mut properties = Vec::new();
+        loop {
+            let len = self.u16()?;
+            let key = self.text(usize::from(len))?;
+            if key.is_empty() && self.data.first() == Some(&OBJECT_END) {
+                self.take(1)?;
+                return Ok(properties);
+            }
+            properties.push((key, self.value()?));
+        }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&[u8]> {
+        if self.data.len() < len {
+            return Err(invalid("AMF0 value cut short"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn number(&mut self) -> io::Result<f64> {
+        let bytes = self.take(8)?;
+        let mut number = [0; 8];
+        number.copy_from_slice(bytes);
+        Ok(f64::from_be_bytes(number))
+    }
+
+    fn text(&mut self, len: usize) -> io::Result<String> {
+        let bytes = self.take(len)?;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
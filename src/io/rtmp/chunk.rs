@@ -0,0 +1,430 @@
+//! RTMP chunk streams
+//!
+//! Messages go over the connection split into chunks of at most the chunk
+//! size, each behind a header naming its chunk stream. A header can leave
+//! out what is the same as in the last one on its chunk stream, so the
+//! reader keeps what it last saw on each.
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Chunk size both sides start with
+pub const DEFAULT_CHUNK_SIZE: usize = 128;
+
+/// Message type ids
+pub const SET_CHUNK_SIZE: u8 = 1;
+pub const ABORT: u8 = 2;
+pub const ACKNOWLEDGEMENT: u8 = 3;
+pub const USER_CONTROL: u8 = 4;
+pub const WINDOW_ACK_SIZE: u8 = 5;
+pub const AUDIO: u8 = 8;
+pub const DATA: u8 = 18;
+pub const COMMAND: u8 = 20;
+
+/// Largest chunk size honoured. The spec allows 31 bits, but no chunk is
+/// longer than its message, and message lengths are 24 bits.
+const MAX_CHUNK_SIZE: usize = 0xFF_FFFF;
+
+/// A 24 bit timestamp field of all ones is followed by the full 32 bits
+const EXTENDED_TIMESTAMP: u32 = 0xFF_FFFF;
+
+/// What a message is and where it goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Message type id
+    pub kind: u8,
+    /// Message stream id; zero for the connection itself
+    pub stream: u32,
+    /// Milliseconds on the stream's clock
+    pub timestamp: u32,
+}
+
+/// A message read off the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub header: Header,
+    pub payload: Vec<u8>,
+}
+
+/// Splits messages into chunks.
+#[derive(Debug)]
+pub struct ChunkWriter {
+    chunk_size: usize,
+    /// Chunks of the message being written, sent in one go
+    buffer: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub const fn new() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Uses chunks of `size` bytes from now on. The peer has to be told
+    /// first with a Set Chunk Size message.
+    pub const fn set_chunk_size(&mut self, size: usize) {
+        self.chunk_size = size;
+    }
+
+    /// Writes `payload` to `out` as one message on chunk stream `csid`,
+    /// which is between 2 and 63.
+    ///
+    /// Every message gets a full header, so chunk streams can be shared by
+    /// messages of any type and stream without tracking what was sent.
+    pub fn write(
+        &mut self,
+        out: &mut impl Write,
+        csid: u8,
+        header: Header,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let length = u32::try_from(payload.len())
+            .ok()
+            .filter(|&length| length <= 0xFF_FFFF)
+            .ok_or_else(|| invalid("RTMP message too long"))?;
+        let extended = header.timestamp >= EXTENDED_TIMESTAMP;
+        self.buffer.clear();
+        self.buffer.push(csid & 0x3F);
+        self.buffer
+            .extend_from_slice(&u24(header.timestamp.min(EXTENDED_TIMESTAMP)));
+        self.buffer.extend_from_slice(&u24(length));
+        self.buffer.push(header.kind);
+        // The one little-endian field in RTMP
+        self.buffer.extend_from_slice(&header.stream.to_le_bytes());
+        for (index, chunk) in payload.chunks(self.chunk_size).enumerate() {
+            if index > 0 {
+                self.buffer.push(0xC0 | (csid & 0x3F));
+            }
+            if extended {
+                self.buffer
+                    .extend_from_slice(&header.timestamp.to_be_bytes());
+            }
+            self.buffer.extend_from_slice(chunk);
+        }
+        if payload.is_empty() && extended {
+            self.buffer
+                .extend_from_slice(&header.timestamp.to_be_bytes());
+        }
+        out.write_all(&self.buffer)
+    }
+}
+
+/// What the reader last saw on a chunk stream
+#[derive(Debug, Default)]
+struct ChunkStream {
+    kind: u8,
+    stream: u32,
+    timestamp: u32,
+    /// What the timestamp last moved by, applied again by headers that
+    /// leave it out
+    delta: u32,
+    length: usize,
+    extended: bool,
+    /// The message read so far
+    payload: Vec<u8>,
+}
+
+/// Puts chunks back together into messages.
+#[derive(Debug)]
+pub struct ChunkReader<R> {
+    inner: R,
+    chunk_size: usize,
+    streams: HashMap<u32, ChunkStream>,
+    received: u64,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            streams: HashMap::new(),
+            received: 0,
+        }
+    }
+
+    /// Bytes read so far, for acknowledgements
+    pub const fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Reads chunks until a message is whole and returns it. Set Chunk
+    /// Size and Abort messages are applied here, and returned too.
+    ///
+    /// # Errors
+    /// Returns an error if reading fails or the chunks are malformed.
+    pub fn read_message(&mut self) -> io::Result<Message> {
+        loop {
+            if let Some(message) = self.read_chunk()? {
+                match message.header.kind {
+                    SET_CHUNK_SIZE => {
+                        let size = be_u32(&message.payload)? & 0x7FFF_FFFF;
+                        if size == 0 {
+                            return Err(invalid("RTMP chunk size of zero"));
+                        }
+                        self.chunk_size = (size as usize).min(MAX_CHUNK_SIZE);
+                    }
+                    ABORT => {
+                        let csid = be_u32(&message.payload)?;
+                        if let Some(stream) = self.streams.get_mut(&csid) {
+                            stream.payload.clear();
+                        }
+                    }
+                    _ => {}
+                }
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Reads one chunk, returning the message it completes, if any.
+    fn read_chunk(&mut self) -> io::Result<Option<Message>> {
+        let [first] = self.read_array::<1>()?;
+        let format = first >> 6;
+        let csid = match first & 0x3F {
+            0 => 64 + u32::from(self.read_array::<1>()?[0]),
+            1 => {
+                let [low, high] = self.read_array::<2>()?;
+                64 + u32::from(low) + 256 * u32::from(high)
+            }
+            csid => u32::from(csid),
+        };
+        let header = match format {
+            0 => self.read_array::<11>()?.to_vec(),
+            1 => self.read_array::<7>()?.to_vec(),
+            2 => self.read_array::<3>()?.to_vec(),
+            _ => Vec::new(),
+        };
+        let known = self.streams.contains_key(&csid);
+        if format != 0 && !known {
+            return Err(invalid("RTMP chunk continues a stream that never started"));
+        }
+        let mut stream = self.streams.remove(&csid).unwrap_or_default();
+        let starting = stream.payload.is_empty();
+
+        let mut field = None;
+        if format <= 2 {
+            field = Some(from_u24(&header[0..3]));
+            stream.extended = field == Some(EXTENDED_TIMESTAMP);
+        }
+        if format <= 1 {
+            if !starting {
+                return Err(invalid("RTMP message header arrived mid-message"));
+            }
+            stream.length = from_u24(&header[3..6]) as usize;
+            stream.kind = header[6];
+        }
+        if format == 0 {
+            stream.stream = u32::from_le_bytes([header[7], header[8], header[9], header[10]]);
+        }
+        // Continuation chunks repeat the extended timestamp
+        let extended = if stream.extended {
+            Some(u32::from_be_bytes(self.read_array::<4>()?))
+        } else {
+            None
+        };
+        let value = extended.or(field);
+        match (format, value) {
+            (0, Some(timestamp)) => {
+                stream.timestamp = timestamp;
+                stream.delta = 0;
+            }
+            (1 | 2, Some(delta)) => {
+                stream.delta = delta;
+                stream.timestamp = stream.timestamp.wrapping_add(delta);
+            }
+            _ if starting => stream.timestamp = stream.timestamp.wrapping_add(stream.delta),
+            _ => {}
+        }
+
+        let remaining = stream
+            .length
+            .checked_sub(stream.payload.len())
+            .ok_or_else(|| invalid("RTMP chunk runs past its message length"))?;
+        let take = remaining.min(self.chunk_size);
+        let start = stream.payload.len();
+        stream.payload.resize(start + take, 0);
+        self.read_exact(&mut stream.payload[start..])?;
+
+        let message = (stream.payload.len() == stream.length).then(|| Message {
+            header: Header {
+                kind: stream.kind,
+                stream: stream.stream,
+                timestamp: stream.timestamp,
+            },
+            payload: std::mem::take(&mut stream.payload),
+        });
+        self.streams.insert(csid, stream);
+        Ok(message)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buffer)?;
+        self.received += buffer.len() as u64;
+        Ok(())
+    }
+}
+
+/// The low 24 bits of `value`, big-endian
+const fn u24(value: u32) -> [u8; 3] {
+    let [_, high, mid, low] = value.to_be_bytes();
+    [high, mid, low]
+}
+
+fn from_u24(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]])
+}
+
+/// The big-endian number a protocol control message carries
+pub fn be_u32(payload: &[u8]) -> io::Result<u32> {
+    payload
+        .get(..4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| invalid("RTMP control message cut short"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: Header = Header {
+        kind: AUDIO,
+        stream: 1,
+        timestamp: 1_000,
+    };
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|index| index as u8).collect()
+    }
+
+    fn write(writer: &mut ChunkWriter, header: Header, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        writer
+            .write(&mut bytes, 4, header, payload)
+            .expect("writes");
+        bytes
+    }
+
+    #[test]
+    fn messages_round_trip_across_chunks() {
+        let mut writer = ChunkWriter::new();
+        let long = payload(DEFAULT_CHUNK_SIZE * 2 + 5);
+        let extended = Header {
+            timestamp: 0x0100_0000,
+            ..HEADER
+        };
+        let mut bytes = write(&mut writer, HEADER, &long);
+        bytes.extend(write(&mut writer, extended, &long));
+        bytes.extend(write(&mut writer, extended, &[]));
+
+        let mut reader = ChunkReader::new(bytes.as_slice());
+        for header in [HEADER, extended] {
+            let message = reader.read_message().expect("reads");
+            assert_eq!(message.header, header);
+            assert_eq!(message.payload, long);
+        }
+        assert_eq!(reader.read_message().expect("reads").payload, []);
+        assert_eq!(reader.received(), bytes.len() as u64);
+    }
+
+    #[test]
+    fn set_chunk_size_applies_to_later_chunks() {
+        let mut writer = ChunkWriter::new();
+        let control = Header {
+            kind: SET_CHUNK_SIZE,
+            stream: 0,
+            timestamp: 0,
+        };
+        let mut bytes = write(&mut writer, control, &4096u32.to_be_bytes());
+        writer.set_chunk_size(4096);
+        let long = payload(3000);
+        bytes.extend(write(&mut writer, HEADER, &long));
+
+        let mut reader = ChunkReader::new(bytes.as_slice());
+        assert_eq!(reader.read_message().expect("reads").header, control);
+        assert_eq!(reader.read_message().expect("reads").payload, long);
+
+        let zero = write(&mut ChunkWriter::new(), control, &[0; 4]);
+        assert!(ChunkReader::new(zero.as_slice()).read_message().is_err());
+        let short = write(&mut ChunkWriter::new(), control, &[0; 2]);
+        assert!(ChunkReader::new(short.as_slice()).read_message().is_err());
+    }
+
+    #[test]
+    fn compressed_headers_reuse_the_last_on_their_stream() {
+        let mut bytes = write(&mut ChunkWriter::new(), HEADER, &[1]);
+        // Type 1: a timestamp delta of 20, a new length and type
+        bytes.extend_from_slice(&[0x40 | 4, 0, 0, 20, 0, 0, 2, DATA, 2, 3]);
+        // Type 3: everything as before, the delta applied again
+        bytes.extend_from_slice(&[0xC0 | 4, 4, 5]);
+
+        let mut reader = ChunkReader::new(bytes.as_slice());
+        assert_eq!(reader.read_message().expect("reads").header, HEADER);
+        let delta = Header {
+            kind: DATA,
+            timestamp: 1_020,
+            ..HEADER
+        };
+        for (timestamp, payload) in [(1_020, [2, 3]), (1_040, [4, 5])] {
+            let message = reader.read_message().expect("reads");
+            assert_eq!(message.header, Header { timestamp, ..delta });
+            assert_eq!(message.payload, payload);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_chunk_streams() {
+        // A continuation on a chunk stream that never started
+        let orphan = [0xC0 | 5, 0];
+        assert!(ChunkReader::new(&orphan[..]).read_message().is_err());
+
+        // A new message header before the last message is whole
+        let long = payload(DEFAULT_CHUNK_SIZE + 1);
+        let bytes = write(&mut ChunkWriter::new(), HEADER, &long);
+        let mut interrupted = bytes[..12 + DEFAULT_CHUNK_SIZE].to_vec();
+        interrupted.extend_from_slice(&bytes[..12]);
+        let mut reader = ChunkReader::new(interrupted.as_slice());
+        let error = reader.read_message().expect_err("mid-message header");
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        // A stream cut off part way through a chunk
+        let mut reader = ChunkReader::new(&bytes[..bytes.len() - 1]);
+        let error = reader.read_message().expect_err("truncated");
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn abort_drops_the_partial_message() {
+        let long = payload(DEFAULT_CHUNK_SIZE + 1);
+        let bytes = write(&mut ChunkWriter::new(), HEADER, &long);
+        let control = Header {
+            kind: ABORT,
+            stream: 0,
+            timestamp: 0,
+        };
+        let mut stream = bytes[..12 + DEFAULT_CHUNK_SIZE].to_vec();
+        let mut abort = Vec::new();
+        ChunkWriter::new()
+            .write(&mut abort, 2, control, &4u32.to_be_bytes())
+            .expect("writes");
+        stream.extend(abort);
+        stream.extend(write(&mut ChunkWriter::new(), HEADER, &[9]));
+
+        let mut reader = ChunkReader::new(stream.as_slice());
+        assert_eq!(reader.read_message().expect("reads").header, control);
+        assert_eq!(reader.read_message().expect("reads").payload, [9]);
+    }
+}
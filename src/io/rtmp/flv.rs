@@ -0,0 +1,257 @@
+//! Encoded audio frames and the FLV audio tags RTMP carries them in
+//!
+//! The encoder writes AAC as ADTS frames, or plain MP3 frames, each behind
+//! a header with its length, rate and channels. [`FrameReader`] reads them
+//! off the pipe one at a time and wraps each in the body of an FLV audio
+//! tag, the payload of an RTMP audio message: a byte with the codec, rate
+//! and channels, and for AAC a packet type ahead of the raw frame without
+//! its ADTS header. AAC streams start with a sequence header carrying the
+//! `AudioSpecificConfig` that the ADTS headers held.
+
+use std::io::{self, BufReader, ErrorKind, Read};
+
+use crate::io::output::StreamCodec;
+
+/// FLV sound format ids
+const FLV_MP3: u8 = 2;
+const FLV_AAC: u8 = 10;
+
+/// AAC packet types
+const AAC_SEQUENCE_HEADER: u8 = 0;
+const AAC_RAW: u8 = 1;
+
+/// Length of an ADTS header without and with its CRC
+const ADTS_HEADER: usize = 7;
+const ADTS_HEADER_CRC: usize = 9;
+
+/// Samples per channel in an AAC frame
+const AAC_FRAME_SAMPLES: u32 = 1024;
+
+/// Sample rates by ADTS sampling frequency index
+const ADTS_RATES: [u32; 13] = [
+    96_000, 88_200, 64_000, 48_000, 44_100, 32_000, 24_000, 22_050, 16_000, 12_000, 11_025, 8_000,
+    7_350,
+];
+
+/// Layer III bitrates by index in kbps, for MPEG-1 and for MPEG-2 and 2.5
+const MP3_BITRATES: [[u32; 15]; 2] = [
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// MPEG-1 sample rates by index; MPEG-2 halves them and MPEG-2.5
+/// quarters them
+const MP3_RATES: [u32; 3] = [44_100, 48_000, 32_000];
+
+/// Sync words in a row that turn out not to start a frame before the
+/// stream counts as broken
+const MAX_FALSE_SYNCS: usize = 1024;
+
+/// The FLV audio codec of a stream codec, if FLV carries it
+pub const fn flv_codec(codec: StreamCodec) -> Option<u8> {
+    match codec {
+        StreamCodec::Aac => Some(FLV_AAC),
+        StreamCodec::Mp3 => Some(FLV_MP3),
+        _ => None,
+    }
+}
+
+/// An encoded frame, ready to send.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioFrame {
+    /// Body of the FLV audio tag
+    pub tag: Vec<u8>,
+    /// Samples per channel the frame holds
+    pub samples: u32,
+    pub sample_rate: u32,
+    pub stereo: bool,
+    /// The AAC sequence header, for AAC frames
+    pub sequence_header: Option<Vec<u8>>,
+}
+
+/// Reads encoded frames of one codec.
+pub struct FrameReader<R> {
+    inner: BufReader<R>,
+    aac: bool,
+    /// A frame header, then the frame
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Reads AAC in ADTS frames if `aac`, MP3 frames otherwise.
+    pub fn new(inner: R, aac: bool) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+            aac,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// The next frame, or `None` once the encoder has finished.
+    ///
+    /// # Errors
+    /// Returns an error if reading fails or no frame turns up for too long.
+    pub fn next_frame(&mut self) -> io::Result<Option<AudioFrame>> {
+        let mut false_syncs = 0;
+        loop {
+            if !self.sync()? {
+                return Ok(None);
+            }
+            let frame = if self.aac {
+                self.adts_frame()?
+            } else {
+                self.mp3_frame()?
+            };
+            if let Some(frame) = frame {
+                return Ok(Some(frame));
+            }
+            // Frame data that looked like a header; look for the next one
+            false_syncs += 1;
+            if false_syncs > MAX_FALSE_SYNCS {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "encoder output has no audio frames",
+                ));
+            }
+        }
+    }
+
+    /// Reads up to the next sync word, leaving its first two bytes in the
+    /// buffer. Returns false at the end of the stream.
+    fn sync(&mut self) -> io::Result<bool> {
+        let mut previous = None;
+        loop {
+            let mut byte = [0];
+            match self.inner.read(&mut byte) {
+                Ok(0) => return Ok(false),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            let byte = byte[0];
+            // ADTS syncs on twelve set bits and layer zero, MP3 on eleven
+            let found = if self.aac {
+                byte & 0xF6 == 0xF0
+            } else {
+                byte & 0xE0 == 0xE0
+            };
+            if previous == Some(0xFF) && found {
+                self.buffer.clear();
+                self.buffer.extend_from_slice(&[0xFF, byte]);
+                return Ok(true);
+            }
+            previous = Some(byte);
+        }
+    }
+
+    /// Reads the rest of `len` bytes of frame into the buffer
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        let start = self.buffer.len();
+        if len > start {
+            self.buffer.resize(len, 0);
+            self.inner.read_exact(&mut self.buffer[start..])?;
+        }
+        Ok(())
+    }
+
+    fn adts_frame(&mut self) -> io::Result<Option<AudioFrame>> {
+        self.fill(ADTS_HEADER)?;
+        let header = &self.buffer;
+        let profile = header[2] >> 6;
+        let rate_index = (header[2] >> 2) & 0x0F;
+        let channels = ((header[2] & 0x01) << 2) | (header[3] >> 6);
+        let length = (usize::from(header[3] & 0x03) << 11)
+            | (usize::from(header[4]) << 3)
+            | usize::from(header[5] >> 5);
+        let blocks = u32::from(header[6] & 0x03) + 1;
+        let header_len = if header[1] & 0x01 == 0 {
+            ADTS_HEADER_CRC
+        } else {
+            ADTS_HEADER
+        };
+        let Some(&sample_rate) = ADTS_RATES.get(usize::from(rate_index)) else {
+            return Ok(None);
+        };
+        if length <= header_len || channels == 0 {
+            return Ok(None);
+        }
+        self.fill(length)?;
+
+        // `AudioSpecificConfig`: object type, rate index and channels, with
+        // the object type one above the ADTS profile
+        let object_type = profile + 1;
+        let config = [
+            (object_type << 3) | (rate_index >> 1),
+            ((rate_index & 0x01) << 7) | (channels << 3),
+        ];
+        let stereo = channels > 1;
+        let flags = audio_flags(FLV_AAC, sample_rate, stereo);
+        let mut tag = Vec::with_capacity(2 + length - header_len);
+        tag.extend_from_slice(&[flags, AAC_RAW]);
+        tag.extend_from_slice(&self.buffer[header_len..length]);
+        Ok(Some(AudioFrame {
+            tag,
+            samples: AAC_FRAME_SAMPLES * blocks,
+            sample_rate,
+            stereo,
+            sequence_header: Some(vec![flags, AAC_SEQUENCE_HEADER, config[0], config[1]]),
+        }))
+    }
+
+    fn mp3_frame(&mut self) -> io::Result<Option<AudioFrame>> {
+        self.fill(4)?;
+        let header = &self.buffer;
+        let version = (header[1] >> 3) & 0x03;
+        let layer = (header[1] >> 1) & 0x03;
+        let bitrate_index = usize::from(header[2] >> 4);
+        let rate_index = usize::from((header[2] >> 2) & 0x03);
+        let padding = u32::from((header[2] >> 1) & 0x01);
+        let stereo = header[3] >> 6 != 3;
+        // Only layer III, with a real bitrate and rate, and version 1
+        // reserved
+        if layer != 1 || version == 1 || bitrate_index == 0 || bitrate_index == 15 {
+            return Ok(None);
+        }
+        let Some(&base_rate) = MP3_RATES.get(rate_index) else {
+            return Ok(None);
+        };
+        let mpeg1 = version == 3;
+        let sample_rate = match version {
+            3 => base_rate,
+            2 => base_rate / 2,
+            _ => base_rate / 4,
+        };
+        let bitrate = MP3_BITRATES[usize::from(!mpeg1)][bitrate_index] * 1000;
+        let (samples, factor) = if mpeg1 { (1152, 144) } else { (576, 72) };
+        let length = (factor * bitrate / sample_rate + padding) as usize;
+        self.fill(length)?;
+
+        let mut tag = Vec::with_capacity(1 + length);
+        tag.push(audio_flags(FLV_MP3, sample_rate, stereo));
+        tag.extend_from_slice(&self.buffer[..length]);
+        Ok(Some(AudioFrame {
+            tag,
+            samples,
+            sample_rate,
+            stereo,
+            sequence_header: None,
+        }))
+    }
+}
+
+/// The first byte of an FLV audio tag: codec, rate, 16 bit samples and
+/// channels. FLV only names four rates, so others take the nearest below;
+/// players go by the stream itself.
+fn audio_flags(codec: u8, sample_rate: u32, stereo: bool) -> u8 {
+    // AAC tags always claim 44.1 kHz stereo, as the FLV spec asks
+    let (rate, stereo) = match sample_rate {
+        _ if codec == FLV_AAC => (3, true),
+        44_100.. => (3, stereo),
+        22_050.. => (2, stereo),
+        11_025.. => (1, stereo),
+        _ => (0, stereo),
+    };
+    (codec << 4) | (rate << 2) | (1 << 1) | u8::from(stereo)
+}
@@ -0,0 +1,99 @@
+//! RTMP publishing
+//!
+//! An [`RtmpPublisher`] sends a live stream to an RTMP ingest, the way
+//! most video platforms and media servers take one in. The url names the
+//! server, the application and the stream key:
+//! `rtmp://live.example.com/app/key` publishes `key` to the application
+//! `app`, with the port defaulting to 1935. Servers that authenticate
+//! publishers do it through the key or a query after it.
+//!
+//! The audio is encoded by `ffmpeg` as AAC or MP3 at the output's
+//! [`codec`](crate::io::NetworkOutput::codec) and bitrate, and each frame
+//! goes to the server as an FLV audio tag. The publisher runs the RTMP
+//! side itself: the handshake, the `connect`, `createStream` and `publish`
+//! commands, an `onMetaData` data message describing the stream, the AAC
+//! sequence header, and pings and acknowledgements while it streams.
+//!
+//! The first connection is made by [`connect`](RtmpPublisher::connect),
+//! which fails if the server refuses to publish the stream. A connection
+//! lost after that is retried as the output's
+//! [`ReconnectPolicy`](crate::io::ReconnectPolicy) says, starting the
+//! stream again from the handshake; the audio encoded in the meantime is
+//! dropped. The server's status reports, losses and reconnect attempts are
+//! all [`RtmpEvent`]s, picked up with
+//! [`try_event`](RtmpPublisher::try_event) or handed to the callback given
+//! to [`connect_with`](RtmpPublisher::connect_with).
+//!
+//! [`set_now_playing`](RtmpPublisher::set_now_playing) sends the title and
+//! artist in a fresh `onMetaData`, as servers pass on to players; the
+//! output's [`now_playing`](crate::io::NetworkOutput::now_playing) goes in
+//! the first one.
+//!
+//! Needs the `rtmp` feature, and at run time `ffmpeg` on the `PATH`.
+//! `rtmps://` urls are not supported, since that needs TLS.
+
+mod amf;
+mod chunk;
+mod flv;
+mod publisher;
+
+pub use publisher::{RtmpMetadata, RtmpPublisher};
+
+use std::fmt;
+
+/// Something that happened to an [`RtmpPublisher`]'s stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RtmpEvent {
+    /// The server reported on the stream, e.g. `NetStream.Publish.Start`
+    /// once it takes the audio
+    Status {
+        /// `status`, `warning` or `error`
+        level: String,
+        code: String,
+        description: Option<String>,
+    },
+    /// The connection was lost
+    Lost {
+        error: String,
+    },
+    /// Reconnect attempt `attempt`, counted from 1, got the stream going
+    /// again
+    Reconnected {
+        attempt: u32,
+    },
+    ReconnectFailed {
+        attempt: u32,
+        error: String,
+    },
+    /// The reconnect policy ran out after this many attempts, so the
+    /// stream stays down
+    GaveUp {
+        attempts: u32,
+    },
+}
+
+impl fmt::Display for RtmpEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Status {
+                level,
+                code,
+                description,
+            } => {
+                write!(f, "{level}: {code}")?;
+                if let Some(description) = description {
+                    write!(f, " ({description})")?;
+                }
+                Ok(())
+            }
+            Self::Lost { error } => write!(f, "connection lost: {error}"),
+            Self::Reconnected { attempt } => write!(f, "reconnected on attempt {attempt}"),
+            Self::ReconnectFailed { attempt, error } => {
+                write!(f, "reconnect attempt {attempt} failed: {error}")
+            }
+            Self::GaveUp { attempts } => {
+                write!(f, "gave up reconnecting after {attempts} attempts")
+            }
+        }
+    }
+}
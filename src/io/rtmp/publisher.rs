@@ -0,0 +1,978 @@
+//! Publishes a stream to an RTMP server
+
+use std::fmt;
+use std::hash::BuildHasher;
+use std::hash::RandomState;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::process::ChildStdout;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use flume::{Receiver, Sender, TryRecvError};
+
+use super::RtmpEvent;
+use super::amf::{self, Value};
+use super::chunk::{
+    self, ACKNOWLEDGEMENT, AUDIO, COMMAND, ChunkReader, ChunkWriter, DATA, Header, Message,
+    SET_CHUNK_SIZE, USER_CONTROL, WINDOW_ACK_SIZE,
+};
+use super::flv::{self, AudioFrame, FrameReader};
+use crate::engine::RenderSink;
+use crate::error::{AudioEngineError, Result};
+use crate::io::encoder::{EncoderOutput, EncoderProcess, ffmpeg_input_args};
+use crate::io::metadata::{MetadataSink, NowPlaying};
+use crate::io::output::{NetworkOutput, StreamCodec};
+use crate::types::{AudioFormat, BitDepth, NetworkProtocol, Sample};
+
+/// Program that encodes the audio
+const ENCODER_PROGRAM: &str = "ffmpeg";
+
+/// Longest the connection, or an answer to a command, may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Events that can wait to be picked up
+const EVENT_CAPACITY: usize = 64;
+
+/// Highest sample rate MP3 encodes; faster input is resampled to it
+const MP3_MAX_RATE_HZ: u32 = 48_000;
+
+/// RTMP version the handshake offers
+const RTMP_VERSION: u8 = 3;
+
+/// Length of the handshake's C1, S1, C2 and S2 packets
+const HANDSHAKE_LEN: usize = 1536;
+
+/// Chunk size the publisher sends with, which keeps a frame to one chunk
+const CHUNK_SIZE: u32 = 4096;
+
+/// Chunk stream ids
+const CONTROL_CHUNKS: u8 = 2;
+const COMMAND_CHUNKS: u8 = 3;
+const AUDIO_CHUNKS: u8 = 4;
+const DATA_CHUNKS: u8 = 5;
+
+/// User control events
+const PING_REQUEST: u16 = 6;
+const PING_RESPONSE: u16 = 7;
+
+/// Transaction ids of the commands answered with `_result`
+const CONNECT_TRANSACTION: f64 = 1.0;
+const CREATE_STREAM_TRANSACTION: f64 = 4.0;
+
+/// Status code of a stream the server takes
+const PUBLISH_START: &str = "NetStream.Publish.Start";
+
+/// Sent to servers as the client's name, in the form encoders use
+const FLASH_VERSION: &str = concat!(
+    "FMLE/3.0 (compatible; audio_engine/",
+    env!("CARGO_PKG_VERSION"),
+    ")"
+);
+
+/// Named as the encoder in the stream's metadata
+const ENCODER_NAME: &str = concat!("audio_engine/", env!("CARGO_PKG_VERSION"));
+
+/// Called with each event, on the thread that sends the stream
+type EventCallback = Box<dyn FnMut(&RtmpEvent) + Send>;
+
+/// State the sender thread shares with the publisher
+#[derive(Default)]
+struct Shared {
+    connected: AtomicBool,
+    bytes_sent: AtomicU64,
+    reconnects: AtomicU64,
+}
+
+/// Publishes a live stream to an RTMP server.
+///
+/// ```no_run
+/// use audio_engine::io::NetworkOutput;
+/// use audio_engine::io::metadata::NowPlaying;
+/// use audio_engine::io::output::StreamCodec;
+/// use audio_engine::io::rtmp::RtmpPublisher;
+/// use audio_engine::types::{AudioFormat, Sample, StreamBitrate, StreamUrl};
+///
+/// let output = NetworkOutput::new(StreamUrl::parse("rtmp://live.example.com/app/s3cret-key")?)
+///     .with_codec(StreamCodec::Aac)
+///     .with_audio_bitrate(StreamBitrate::KBPS_128);
+/// let mut publisher = RtmpPublisher::connect_with(&output, AudioFormat::default(), |event| {
+///     println!("{event}");
+/// })?;
+/// publisher.set_now_playing(&NowPlaying::new("Blue in Green").with_artist("Miles Davis"))?;
+/// let block = vec![Sample::SILENCE; 960];
+/// loop {
+///     publisher.write(&block);
+///     std::thread::sleep(std::time::Duration::from_millis(10));
+/// }
+/// # Ok::<(), audio_engine::error::AudioEngineError>(())
+/// ```
+pub struct RtmpPublisher {
+    output: NetworkOutput,
+    format: AudioFormat,
+    process: EncoderProcess,
+    shared: Arc<Shared>,
+    events: Receiver<RtmpEvent>,
+    /// Hands the sender now-playing updates
+    updates: Sender<NowPlaying>,
+    sender: Option<JoinHandle<()>>,
+}
+
+impl RtmpPublisher {
+    /// Starts the encoder for `output`'s codec on samples in `format` and
+    /// starts publishing to the server.
+    ///
+    /// # Errors
+    /// Returns an error if the url isn't an `rtmp://` one with a stream
+    /// key, the codec isn't AAC or MP3 or there are more than two
+    /// channels, `ffmpeg` can't be started, or the server can't be reached
+    /// or refuses the stream.
+    pub fn connect(output: &NetworkOutput, format: AudioFormat) -> Result<Self> {
+        Self::connect_with(output, format, |_| {})
+    }
+
+    /// Like [`connect`](Self::connect), also handing every event to
+    /// `on_event` as it happens. It runs on the thread sending the stream,
+    /// so it shouldn't block.
+    ///
+    /// # Errors
+    /// As for [`connect`](Self::connect).
+    pub fn connect_with(
+        output: &NetworkOutput,
+        format: AudioFormat,
+        on_event: impl FnMut(&RtmpEvent) + Send + 'static,
+    ) -> Result<Self> {
+        validate(output, format)?;
+        let (process, stdout) = EncoderProcess::spawn(
+            ENCODER_PROGRAM,
+            &encoder_args(output, format),
+            format,
+            &EncoderOutput::Pipe,
+        )?;
+        let Some(stdout) = stdout else {
+            return Err(AudioEngineError::configuration("encoder has no stdout"));
+        };
+        let (session, status) = Session::open(output)?;
+        log::info!("Publishing {} over RTMP to {}", output.codec, output.url);
+
+        let shared = Arc::new(Shared {
+            connected: AtomicBool::new(true),
+            ..Shared::default()
+        });
+        let (events_sender, events) = flume::bounded(EVENT_CAPACITY);
+        let (updates, pending) = flume::unbounded();
+        let mut worker = SenderThread {
+            output: output.clone(),
+            frames: FrameReader::new(stdout, output.codec == StreamCodec::Aac),
+            pending,
+            now_playing: output.now_playing.clone(),
+            session: Some(session),
+            shared: Arc::clone(&shared),
+            events: events_sender,
+            on_event: Box::new(on_event),
+            attempt: 0,
+            retry_at: None,
+            gave_up: false,
+        };
+        worker.event(status);
+        let sender = thread::Builder::new()
+            .name("rtmp-publisher".to_string())
+            .spawn(move || worker.run())?;
+        Ok(Self {
+            output: output.clone(),
+            format,
+            process,
+            shared,
+            events,
+            updates,
+            sender: Some(sender),
+        })
+    }
+
+    #[must_use]
+    pub const fn output(&self) -> &NetworkOutput {
+        &self.output
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Queues interleaved samples for encoding without blocking, returning
+    /// how many were queued. The rest are dropped.
+    pub fn write(&mut self, samples: &[Sample]) -> usize {
+        self.process.write(samples)
+    }
+
+    /// Whether the stream is being published
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        self.shared.connected.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of audio tags sent so far
+    #[must_use]
+    pub fn bytes_sent(&self) -> u64 {
+        self.shared.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Successful reconnects so far
+    #[must_use]
+    pub fn reconnects(&self) -> u64 {
+        self.shared.reconnects.load(Ordering::Relaxed)
+    }
+
+    /// Samples dropped so far because the encoder fell behind
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.process.dropped()
+    }
+
+    /// Share of the encoder's ring waiting to be encoded, from 0 to 1. It
+    /// fills up when the connection can't keep up with the bitrate.
+    #[must_use]
+    pub fn backlog(&self) -> f32 {
+        self.process.backlog()
+    }
+
+    /// Whether the encoder stopped taking samples, e.g. because it exited
+    #[must_use]
+    pub fn has_failed(&self) -> bool {
+        self.process.has_failed()
+    }
+
+    /// The next event, if any
+    #[must_use]
+    pub fn try_event(&self) -> Option<RtmpEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Sends `now` in the stream's metadata, now if the stream is being
+    /// published, and again after every reconnect.
+    ///
+    /// # Errors
+    /// Returns an error if the publisher has stopped.
+    pub fn set_now_playing(&self, now: &NowPlaying) -> Result<()> {
+        self.metadata().set_now_playing(now)
+    }
+
+    /// A handle that sets the now-playing metadata while the stream runs,
+    /// e.g. as an engine's [`MetadataSink`].
+    #[must_use]
+    pub fn metadata(&self) -> RtmpMetadata {
+        RtmpMetadata {
+            url: self.output.url.to_string(),
+            updates: self.updates.clone(),
+        }
+    }
+
+    /// Encodes and sends what is still queued, then ends the stream.
+    ///
+    /// # Errors
+    /// Returns an error if the encoder failed or exited unsuccessfully.
+    pub fn finish(mut self) -> Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let encoded = self.process.close();
+        // The encoder closing its output ends the sender
+        if let Some(sender) = self.sender.take() {
+            sender
+                .join()
+                .map_err(|_| AudioEngineError::configuration("rtmp sender thread panicked"))?;
+        }
+        encoded
+    }
+}
+
+impl Drop for RtmpPublisher {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::error!("Publishing to {} failed: {e}", self.output.url);
+        }
+    }
+}
+
+impl fmt::Debug for RtmpPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RtmpPublisher")
+            .field("url", &self.output.url)
+            .field("codec", &self.output.codec)
+            .field("format", &self.format)
+            .field("connected", &self.is_connected())
+            .field("bytes_sent", &self.bytes_sent())
+            .field("dropped", &self.dropped())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RenderSink for RtmpPublisher {
+    fn start(&mut self, format: AudioFormat) -> Result<()> {
+        if format == self.format {
+            Ok(())
+        } else {
+            Err(AudioEngineError::FormatMismatch {
+                expected: format!("{:?} {:?}", self.format.sample_rate, self.format.channels),
+                actual: format!("{:?} {:?}", format.sample_rate, format.channels),
+            })
+        }
+    }
+
+    /// Waits for room rather than dropping; render with
+    /// [`RenderPacing::RealTime`](crate::engine::RenderPacing::RealTime) so
+    /// the stream goes out as fast as it plays
+    fn write(&mut self, samples: &[Sample]) -> Result<()> {
+        self.process.write_all(samples)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.close()
+    }
+
+    fn describe(&self) -> String {
+        self.output.url.to_string()
+    }
+}
+
+/// Sets the now-playing metadata of an [`RtmpPublisher`]; see
+/// [`RtmpPublisher::metadata`].
+#[derive(Clone)]
+pub struct RtmpMetadata {
+    url: String,
+    updates: Sender<NowPlaying>,
+}
+
+impl MetadataSink for RtmpMetadata {
+    /// Sends `now` as the `title` and `artist` of a new `onMetaData`.
+    fn set_now_playing(&mut self, now: &NowPlaying) -> Result<()> {
+        self.updates.send(now.clone()).map_err(|_| {
+            AudioEngineError::configuration(format!("the publisher for {} has stopped", self.url))
+        })
+    }
+}
+
+impl fmt::Debug for RtmpMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RtmpMetadata")
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Checks `output` can be published with samples in `format`.
+fn validate(output: &NetworkOutput, format: AudioFormat) -> Result<()> {
+    let url = &output.url;
+    if url.protocol() != NetworkProtocol::RTMP {
+        return Err(AudioEngineError::configuration(format!(
+            "{url} isn't an RTMP url"
+        )));
+    }
+    if url.as_str().starts_with("rtmps://") {
+        return Err(AudioEngineError::configuration(format!(
+            "{url} needs TLS, which isn't supported"
+        )));
+    }
+    if url.stream_key().is_none_or(str::is_empty) {
+        return Err(AudioEngineError::configuration(format!(
+            "{url} has no stream key"
+        )));
+    }
+    if flv::flv_codec(output.codec).is_none() {
+        return Err(AudioEngineError::configuration(format!(
+            "RTMP carries AAC or MP3, not {}",
+            output.codec
+        )));
+    }
+    if format.channels.count() > 2 {
+        return Err(AudioEngineError::configuration(format!(
+            "FLV carries one or two channels, not {}",
+            format.channels.count()
+        )));
+    }
+    Ok(())
+}
+
+/// ffmpeg options encoding `output`'s codec from input in `format` to
+/// stdout, frame by frame.
+fn encoder_args(output: &NetworkOutput, format: AudioFormat) -> Vec<String> {
+    let bitrate = format!("{}k", output.audio_bitrate.as_kbps());
+    let mut args = ffmpeg_input_args(format, BitDepth::I16);
+    if output.codec == StreamCodec::Aac {
+        args.extend(["-c:a", "aac", "-b:a", bitrate.as_str(), "-f", "adts"].map(str::to_string));
+    } else {
+        args.extend(["-c:a", "libmp3lame", "-b:a", bitrate.as_str()].map(str::to_string));
+        if format.sample_rate.as_hz() > MP3_MAX_RATE_HZ {
+            args.extend(["-ar".to_string(), MP3_MAX_RATE_HZ.to_string()]);
+        }
+        // Bare frames, without the tag and Xing header of an MP3 file
+        args.extend(["-f", "mp3", "-id3v2_version", "0", "-write_xing", "0"].map(str::to_string));
+    }
+    // Every frame as soon as it is encoded, not a pipe buffer at a time
+    args.extend(["-flush_packets", "1", "-"].map(str::to_string));
+    args
+}
+
+/// A connection publishing the stream.
+struct Session {
+    link: TcpStream,
+    writer: ChunkWriter,
+    /// Messages the reader thread took off the connection, with the bytes
+    /// received by then
+    incoming: Receiver<(Message, u64)>,
+    /// Message stream the server created for the audio
+    stream: u32,
+    /// Bytes the server may send before it wants an acknowledgement, if
+    /// it said
+    window: Option<u32>,
+    acknowledged: u64,
+    /// Samples per channel sent, which time the audio
+    samples: u64,
+    /// Whether the metadata and AAC sequence header have been sent
+    started: bool,
+}
+
+impl Session {
+    /// Connects, shakes hands and starts publishing `output`'s stream key.
+    /// Returns the session and the server's status for the stream.
+    fn open(output: &NetworkOutput) -> Result<(Self, RtmpEvent)> {
+        let url = &output.url;
+        let link = TcpStream::connect_timeout(&url.to_socket_addr()?, CONNECT_TIMEOUT)?;
+        link.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        link.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+        link.set_nodelay(true)?;
+        handshake(&link)?;
+
+        let mut reader = ChunkReader::new(link.try_clone()?);
+        let (forward, incoming) = flume::unbounded();
+        let mut session = Self {
+            link,
+            writer: ChunkWriter::new(),
+            incoming,
+            stream: 0,
+            window: None,
+            acknowledged: 0,
+            samples: 0,
+            started: false,
+        };
+        session.send(
+            CONTROL_CHUNKS,
+            control(SET_CHUNK_SIZE),
+            &CHUNK_SIZE.to_be_bytes(),
+        )?;
+        session.writer.set_chunk_size(CHUNK_SIZE as usize);
+
+        let key = url.stream_key().unwrap_or_default();
+        let connect = Value::object([
+            ("app", Value::string(url.path())),
+            ("type", Value::string("nonprivate")),
+            ("flashVer", Value::string(FLASH_VERSION)),
+            (
+                "tcUrl",
+                Value::String(format!(
+                    "rtmp://{}:{}/{}",
+                    url.host(),
+                    url.port(),
+                    url.path()
+                )),
+            ),
+        ]);
+        session.command(0, "connect", CONNECT_TRANSACTION, connect, &[])?;
+        session.answer(&mut reader, output, CONNECT_TRANSACTION, "connect")?;
+        session.command(0, "releaseStream", 2.0, Value::Null, &[Value::string(key)])?;
+        session.command(0, "FCPublish", 3.0, Value::Null, &[Value::string(key)])?;
+        session.command(
+            0,
+            "createStream",
+            CREATE_STREAM_TRANSACTION,
+            Value::Null,
+            &[],
+        )?;
+        let created = session.answer(
+            &mut reader,
+            output,
+            CREATE_STREAM_TRANSACTION,
+            "createStream",
+        )?;
+        let stream = created
+            .get(3)
+            .and_then(Value::as_number)
+            .ok_or_else(|| refused(output, "createStream", "no stream id in the answer"))?;
+        // Stream ids are small whole numbers
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let stream = stream as u32;
+        session.stream = stream;
+        session.command(
+            stream,
+            "publish",
+            0.0,
+            Value::Null,
+            &[Value::string(key), Value::string("live")],
+        )?;
+        let status = session.publish_status(&mut reader, output)?;
+
+        // The reader blocks from now on, until the link is shut down
+        session.link.set_read_timeout(None)?;
+        session
+            .link
+            .set_write_timeout(Some(Duration::from_millis(u64::from(
+                output.buffer_ms.max(100),
+            ))))?;
+        thread::Builder::new()
+            .name("rtmp-reader".to_string())
+            .spawn(move || {
+                while let Ok(message) = reader.read_message() {
+                    if forward.send((message, reader.received())).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok((session, status))
+    }
+
+    /// Waits for the `_result` of command `transaction`, returning its
+    /// values.
+    fn answer(
+        &mut self,
+        reader: &mut ChunkReader<TcpStream>,
+        output: &NetworkOutput,
+        transaction: f64,
+        command: &str,
+    ) -> Result<Vec<Value>> {
+        loop {
+            let values = self.next_command(reader, output, command)?;
+            #[allow(clippy::float_cmp)]
+            let ours = values.get(1).and_then(Value::as_number) == Some(transaction);
+            match values.first().and_then(Value::as_str) {
+                Some("_result") if ours => return Ok(values),
+                Some("_error") if ours => {
+                    return Err(refused(output, command, &describe(values.get(3))));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Waits for the server's `onStatus` for the stream being published.
+    fn publish_status(
+        &mut self,
+        reader: &mut ChunkReader<TcpStream>,
+        output: &NetworkOutput,
+    ) -> Result<RtmpEvent> {
+        loop {
+            let values = self.next_command(reader, output, "publish")?;
+            let Some(event) = status(&values) else {
+                continue;
+            };
+            if let RtmpEvent::Status { level, code, .. } = &event {
+                if level == "error" {
+                    return Err(refused(output, "publish", code));
+                }
+                if code == PUBLISH_START {
+                    return Ok(event);
+                }
+            }
+        }
+    }
+
+    /// Reads messages until a command comes, handling the ones in between.
+    fn next_command(
+        &mut self,
+        reader: &mut ChunkReader<TcpStream>,
+        output: &NetworkOutput,
+        command: &str,
+    ) -> Result<Vec<Value>> {
+        loop {
+            let message = reader
+                .read_message()
+                .map_err(|e| refused(output, command, &format!("no answer ({e})")))?;
+            self.control(&message, reader.received())?;
+            if message.header.kind == COMMAND {
+                return Ok(amf::decode(&message.payload)?);
+            }
+        }
+    }
+
+    /// Takes what the server sent while the stream ran, returning its
+    /// status reports.
+    fn poll(&mut self) -> Result<Vec<RtmpEvent>> {
+        let mut statuses = Vec::new();
+        loop {
+            let (message, received) = match self.incoming.try_recv() {
+                Ok(incoming) => incoming,
+                Err(TryRecvError::Empty) => return Ok(statuses),
+                Err(TryRecvError::Disconnected) => {
+                    return Err(AudioEngineError::NetworkConnection {
+                        message: "the server closed the connection".to_string(),
+                    });
+                }
+            };
+            self.control(&message, received)?;
+            if message.header.kind == COMMAND
+                && let Some(event) = status(&amf::decode(&message.payload)?)
+            {
+                statuses.push(event);
+            }
+        }
+    }
+
+    /// Answers pings, notes the acknowledgement window, and acknowledges
+    /// what has been received once a window's worth has come in.
+    fn control(&mut self, message: &Message, received: u64) -> io::Result<()> {
+        let payload = &message.payload;
+        match message.header.kind {
+            WINDOW_ACK_SIZE => self.window = Some(chunk::be_u32(payload)?),
+            USER_CONTROL
+                if payload.len() >= 6
+                    && u16::from_be_bytes([payload[0], payload[1]]) == PING_REQUEST =>
+            {
+                let mut pong = PING_RESPONSE.to_be_bytes().to_vec();
+                pong.extend_from_slice(&payload[2..6]);
+                self.send(CONTROL_CHUNKS, control(USER_CONTROL), &pong)?;
+            }
+            _ => {}
+        }
+        if let Some(window) = self.window
+            && window > 0
+            && received - self.acknowledged >= u64::from(window)
+        {
+            self.acknowledged = received;
+            // The count wraps at 32 bits
+            #[allow(clippy::cast_possible_truncation)]
+            let count = received as u32;
+            self.send(
+                CONTROL_CHUNKS,
+                control(ACKNOWLEDGEMENT),
+                &count.to_be_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sends `frame`, after the AAC sequence header if it is the first.
+    fn send_frame(&mut self, frame: &AudioFrame) -> io::Result<()> {
+        if !self.started {
+            if let Some(sequence_header) = &frame.sequence_header {
+                self.send(AUDIO_CHUNKS, self.header(AUDIO, 0), sequence_header)?;
+            }
+            self.started = true;
+        }
+        let header = self.header(AUDIO, self.timestamp(frame.sample_rate));
+        self.send(AUDIO_CHUNKS, header, &frame.tag)?;
+        self.samples += u64::from(frame.samples);
+        Ok(())
+    }
+
+    /// Sends metadata at the current point in the stream.
+    fn send_metadata(&mut self, metadata: &[u8], sample_rate: u32) -> io::Result<()> {
+        let header = self.header(DATA, self.timestamp(sample_rate));
+        self.send(DATA_CHUNKS, header, metadata)
+    }
+
+    /// Ends the stream politely before the connection closes.
+    fn unpublish(&mut self, key: &str) -> io::Result<()> {
+        self.command(0, "FCUnpublish", 0.0, Value::Null, &[Value::string(key)])?;
+        let stream = Value::Number(f64::from(self.stream));
+        self.command(0, "deleteStream", 0.0, Value::Null, &[stream])
+    }
+
+    /// Milliseconds of audio sent, which wrap at 32 bits
+    #[allow(clippy::cast_possible_truncation)]
+    fn timestamp(&self, sample_rate: u32) -> u32 {
+        (self.samples * 1000 / u64::from(sample_rate.max(1))) as u32
+    }
+
+    const fn header(&self, kind: u8, timestamp: u32) -> Header {
+        Header {
+            kind,
+            stream: self.stream,
+            timestamp,
+        }
+    }
+
+    fn command(
+        &mut self,
+        stream: u32,
+        name: &str,
+        transaction: f64,
+        object: Value,
+        arguments: &[Value],
+    ) -> io::Result<()> {
+        let mut values = vec![Value::string(name), Value::Number(transaction), object];
+        values.extend_from_slice(arguments);
+        let header = Header {
+            kind: COMMAND,
+            stream,
+            timestamp: 0,
+        };
+        self.send(COMMAND_CHUNKS, header, &amf::encode(&values))
+    }
+
+    fn send(&mut self, csid: u8, header: Header, payload: &[u8]) -> io::Result<()> {
+        self.writer.write(&mut self.link, csid, header, payload)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Ends the reader thread too
+        let _ = self.link.shutdown(Shutdown::Both);
+    }
+}
+
+/// The header of a protocol control message
+const fn control(kind: u8) -> Header {
+    Header {
+        kind,
+        stream: 0,
+        timestamp: 0,
+    }
+}
+
+/// The simple handshake: C0 and C1, S0 and S1 back, C2 echoing S1, then
+/// S2.
+fn handshake(mut link: &TcpStream) -> Result<()> {
+    let mut hello = vec![0; 1 + HANDSHAKE_LEN];
+    hello[0] = RTMP_VERSION;
+    // A zero time and zero field, then random bytes for the server to echo
+    let random = RandomState::new();
+    let now = Instant::now();
+    for (index, bytes) in hello[9..].chunks_mut(8).enumerate() {
+        let value = random.hash_one((now, index)).to_be_bytes();
+        bytes.copy_from_slice(&value[..bytes.len()]);
+    }
+    link.write_all(&hello)?;
+
+    let mut reply = vec![0; 1 + HANDSHAKE_LEN];
+    link.read_exact(&mut reply)?;
+    if reply[0] != RTMP_VERSION {
+        return Err(AudioEngineError::NetworkConnection {
+            message: format!("the server speaks RTMP version {}, not 3", reply[0]),
+        });
+    }
+    link.write_all(&reply[1..])?;
+    link.read_exact(&mut reply[1..])?;
+    Ok(())
+}
+
+/// The status report in an `onStatus` command's values, if it is one
+fn status(values: &[Value]) -> Option<RtmpEvent> {
+    if values.first().and_then(Value::as_str) != Some("onStatus") {
+        return None;
+    }
+    let info = values.get(3)?;
+    let text = |key| info.get(key).and_then(Value::as_str).map(str::to_string);
+    Some(RtmpEvent::Status {
+        level: text("level").unwrap_or_default(),
+        code: text("code").unwrap_or_default(),
+        description: text("description"),
+    })
+}
+
+/// What an `_error` answer's information object says went wrong
+fn describe(info: Option<&Value>) -> String {
+    info.and_then(|info| info.get("description").or_else(|| info.get("code")))
+        .and_then(Value::as_str)
+        .unwrap_or("no reason given")
+        .to_string()
+}
+
+fn refused(output: &NetworkOutput, command: &str, reason: &str) -> AudioEngineError {
+    AudioEngineError::NetworkConnection {
+        message: format!("{} refused {command}: {reason}", output.url),
+    }
+}
+
+/// The `@setDataFrame` message describing the stream, with what is
+/// playing
+fn metadata(
+    output: &NetworkOutput,
+    now: Option<&NowPlaying>,
+    sample_rate: u32,
+    stereo: bool,
+) -> Vec<u8> {
+    let codec = flv::flv_codec(output.codec).unwrap_or_default();
+    let mut properties = vec![
+        ("duration".to_string(), Value::Number(0.0)),
+        ("audiocodecid".to_string(), Value::Number(f64::from(codec))),
+        (
+            "audiodatarate".to_string(),
+            Value::Number(f64::from(output.audio_bitrate.as_kbps())),
+        ),
+        (
+            "audiosamplerate".to_string(),
+            Value::Number(f64::from(sample_rate)),
+        ),
+        ("audiosamplesize".to_string(), Value::Number(16.0)),
+        ("stereo".to_string(), Value::Boolean(stereo)),
+        ("encoder".to_string(), Value::string(ENCODER_NAME)),
+    ];
+    if let Some(now) = now {
+        properties.push(("title".to_string(), Value::string(&now.title)));
+        if let Some(artist) = &now.artist {
+            properties.push(("artist".to_string(), Value::string(artist)));
+        }
+    }
+    amf::encode(&[
+        Value::string("@setDataFrame"),
+        Value::string("onMetaData"),
+        Value::EcmaArray(properties),
+    ])
+}
+
+/// Takes the encoder's frames and publishes them, reconnecting as needed.
+struct SenderThread {
+    output: NetworkOutput,
+    frames: FrameReader<ChildStdout>,
+    /// Now-playing updates not sent yet
+    pending: Receiver<NowPlaying>,
+    now_playing: Option<NowPlaying>,
+    session: Option<Session>,
+    shared: Arc<Shared>,
+    events: Sender<RtmpEvent>,
+    on_event: EventCallback,
+    /// Reconnect attempts since the connection was lost
+    attempt: u32,
+    retry_at: Option<Instant>,
+    gave_up: bool,
+}
+
+impl SenderThread {
+    fn run(mut self) {
+        loop {
+            let frame = match self.frames.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!(
+                        "Reading the encoded stream for {} failed: {e}",
+                        self.output.url
+                    );
+                    break;
+                }
+            };
+            if self.session.is_none() {
+                self.reconnect();
+            }
+            if let Err(e) = self.send(&frame) {
+                self.lost(&e.to_string());
+            }
+        }
+        if let Some(mut session) = self.session.take() {
+            let key = self.output.url.stream_key().unwrap_or_default();
+            if let Err(e) = session.unpublish(key) {
+                log::debug!("Unpublishing from {} failed: {e}", self.output.url);
+            }
+        }
+        self.shared.connected.store(false, Ordering::Relaxed);
+    }
+
+    /// Handles what the server sent, then sends `frame` if connected: after
+    /// the metadata at the start of a connection or when it changed.
+    fn send(&mut self, frame: &AudioFrame) -> Result<()> {
+        let updated = match self.pending.try_iter().last() {
+            Some(now) => {
+                self.now_playing = Some(now);
+                true
+            }
+            None => false,
+        };
+        let Some(session) = &mut self.session else {
+            return Ok(());
+        };
+        let statuses = session.poll()?;
+        if updated || !session.started {
+            let metadata = metadata(
+                &self.output,
+                self.now_playing.as_ref(),
+                frame.sample_rate,
+                frame.stereo,
+            );
+            session.send_metadata(&metadata, frame.sample_rate)?;
+        }
+        session.send_frame(frame)?;
+        self.shared
+            .bytes_sent
+            .fetch_add(frame.tag.len() as u64, Ordering::Relaxed);
+        for status in statuses {
+            let failed = matches!(&status, RtmpEvent::Status { level, .. } if level == "error");
+            let reason = status.to_string();
+            self.event(status);
+            if failed {
+                return Err(AudioEngineError::NetworkConnection { message: reason });
+            }
+        }
+        Ok(())
+    }
+
+    fn lost(&mut self, error: &str) {
+        log::warn!("Lost the connection to {}: {error}", self.output.url);
+        self.session = None;
+        self.shared.connected.store(false, Ordering::Relaxed);
+        self.attempt = 0;
+        self.retry_at = Some(Instant::now() + self.output.reconnect.delay(1));
+        self.event(RtmpEvent::Lost {
+            error: error.to_string(),
+        });
+    }
+
+    /// Makes a reconnect attempt if one is due.
+    fn reconnect(&mut self) {
+        let now = Instant::now();
+        if self.gave_up || self.retry_at.is_some_and(|at| now < at) {
+            return;
+        }
+        self.attempt += 1;
+        let policy = self.output.reconnect;
+        if !policy.allows(self.attempt) {
+            log::error!(
+                "Giving up on {} after {} reconnect attempts",
+                self.output.url,
+                self.attempt - 1
+            );
+            self.gave_up = true;
+            self.event(RtmpEvent::GaveUp {
+                attempts: self.attempt - 1,
+            });
+            return;
+        }
+        match Session::open(&self.output) {
+            Ok((session, status)) => {
+                log::info!(
+                    "Reconnected to {} on attempt {}",
+                    self.output.url,
+                    self.attempt
+                );
+                self.session = Some(session);
+                self.shared.connected.store(true, Ordering::Relaxed);
+                self.shared.reconnects.fetch_add(1, Ordering::Relaxed);
+                self.event(RtmpEvent::Reconnected {
+                    attempt: self.attempt,
+                });
+                self.event(status);
+                self.attempt = 0;
+                self.retry_at = None;
+            }
+            Err(e) => {
+                log::warn!(
+                    "Reconnect attempt {} to {} failed: {e}",
+                    self.attempt,
+                    self.output.url
+                );
+                self.retry_at = Some(now + policy.delay(self.attempt + 1));
+                self.event(RtmpEvent::ReconnectFailed {
+                    attempt: self.attempt,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    fn event(&mut self, event: RtmpEvent) {
+        (self.on_event)(&event);
+        let _ = self.events.try_send(event);
+    }
+}
@@ -0,0 +1,269 @@
+//! RTP network audio
+//!
+//! An [`RtpSender`] sends audio to an `rtp://host:port` url of a
+//! [`NetworkOutput`] as RTP packets over UDP, and an [`RtpReceiver`] bound
+//! to the url of a [`NetworkInput`] plays them back. The codec decides the
+//! payload:
+//!
+//! - [`StreamCodec::L16`] and [`StreamCodec::L24`]: uncompressed big-endian
+//!   PCM at the stream's sample rate, [`packet_time`](RtpSettings::packet_time)
+//!   of audio per packet. The lowest latency, for a LAN.
+//! - [`StreamCodec::Opus`]: one Opus frame per packet, always on a 48 kHz
+//!   clock (RFC 7587). Needs the `opus` feature and opus-tools, like the
+//!   other Opus streams.
+//!
+//! Both sides send RTCP on the port above: the sender a sender report
+//! every [`rtcp_interval`](RtpSettings::rtcp_interval), which the receiver
+//! answers with a receiver report on loss and jitter. Nothing negotiates
+//! the format, so the receiver must be set up like the sender; [`sdp`]
+//! describes a sender for players such as ffmpeg or VLC.
+//!
+//! The receiver keeps [`target_latency`](RtpSettings::target_latency) of
+//! audio buffered. Packets that arrive out of order are put back in order
+//! while they are within half of it; a packet that doesn't turn up by then
+//! counts as lost and, for PCM, is replaced by silence. When the buffer
+//! runs dry it plays silence until it has filled up again, and when it
+//! grows well past the target, e.g. because the sender's clock is a little
+//! faster, it skips ahead.
+//!
+//! ```no_run
+//! use audio_engine::io::output::StreamCodec;
+//! use audio_engine::io::rtp::{RtpReceiver, RtpSender};
+//! use audio_engine::io::{NetworkInput, NetworkOutput};
+//! use audio_engine::types::{AudioFormat, Sample, StreamUrl};
+//!
+//! let format = AudioFormat::default();
+//! let output = NetworkOutput::new(StreamUrl::parse("rtp://192.168.1.20:5004")?)
+//!     .with_codec(StreamCodec::L24);
+//! let mut sender = RtpSender::connect(&output, format)?;
+//!
+//! // On the other machine
+//! let input = NetworkInput::new(StreamUrl::parse("rtp://0.0.0.0:5004")?)
+//!     .with_codec(StreamCodec::L24);
+//! let mut receiver = RtpReceiver::bind(&input, format)?;
+//!
+//! let mut block = vec![Sample::SILENCE; 512];
+//! sender.write(&block);
+//! receiver.read(&mut block);
+//! println!("{}", receiver.stats());
+//! # Ok::<(), audio_engine::error::AudioEngineError>(())
+//! ```
+//!
+//! [`NetworkOutput`]: crate::io::NetworkOutput
+//! [`NetworkInput`]: crate::io::NetworkInput
+
+pub mod packet;
+mod receiver;
+mod sender;
+
+#[cfg(feature = "opus")]
+mod ogg;
+
+use std::fmt::Write as _;
+use std::hash::{BuildHasher, RandomState};
+use std::time::{Duration, Instant};
+
+pub use receiver::{RtpReceiver, RtpStats};
+pub use sender::RtpSender;
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::output::StreamCodec;
+use crate::types::{AudioFormat, BitDepth, StreamUrl};
+
+/// Largest payload sent in one packet, leaving room in a 1500 byte
+/// Ethernet frame for the IP, UDP and RTP headers
+pub const MAX_PAYLOAD: usize = 1400;
+
+/// Rate of the RTP clock for Opus, whatever the audio's rate
+pub const OPUS_CLOCK_RATE: u32 = 48_000;
+
+/// Canonical name both sides give themselves in RTCP
+const CNAME: &str = concat!("audio_engine-", env!("CARGO_PKG_VERSION"));
+
+/// Packet, buffering and report settings of an RTP stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpSettings {
+    /// Payload type number; 96-127 are the dynamic types streams agree on
+    /// out of band
+    pub payload_type: u8,
+    /// Audio per packet for L16 and L24, 1 ms by default as in AES67;
+    /// Opus sends one frame per packet
+    pub packet_time: Duration,
+    /// Audio the receiver keeps buffered to ride out jitter
+    pub target_latency: Duration,
+    pub rtcp_interval: Duration,
+    /// Synchronization source of the sender; random if `None`
+    pub ssrc: Option<u32>,
+}
+
+impl Default for RtpSettings {
+    fn default() -> Self {
+        Self {
+            payload_type: 96,
+            packet_time: Duration::from_millis(1),
+            target_latency: Duration::from_millis(20),
+            rtcp_interval: Duration::from_secs(5),
+            ssrc: None,
+        }
+    }
+}
+
+impl RtpSettings {
+    #[must_use]
+    pub const fn with_payload_type(mut self, payload_type: u8) -> Self {
+        self.payload_type = payload_type;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_packet_time(mut self, packet_time: Duration) -> Self {
+        self.packet_time = packet_time;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_target_latency(mut self, latency: Duration) -> Self {
+        self.target_latency = latency;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_rtcp_interval(mut self, interval: Duration) -> Self {
+        self.rtcp_interval = interval;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_ssrc(mut self, ssrc: u32) -> Self {
+        self.ssrc = Some(ssrc);
+        self
+    }
+
+    /// Frames per packet of `codec` in `format`
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn packet_frames(&self, codec: StreamCodec, format: AudioFormat) -> usize {
+        match codec {
+            StreamCodec::Opus(settings) => {
+                (settings.frame_size.as_ms() * OPUS_CLOCK_RATE as f32 / 1000.0) as usize
+            }
+            _ => ((self.packet_time.as_secs_f64() * f64::from(format.sample_rate.as_hz())).round()
+                as usize)
+                .max(1),
+        }
+    }
+
+    /// Checks the settings can carry `codec` in `format`.
+    ///
+    /// # Errors
+    /// Returns an error if the codec can't be sent over RTP, the payload
+    /// type is out of range, a PCM packet wouldn't fit in [`MAX_PAYLOAD`],
+    /// or Opus is asked for at a rate other than 48 kHz or with more than
+    /// two channels.
+    pub fn validate(&self, codec: StreamCodec, format: AudioFormat) -> Result<()> {
+        if self.payload_type > 127 {
+            return Err(AudioEngineError::configuration(format!(
+                "RTP payload type {} is out of range",
+                self.payload_type
+            )));
+        }
+        match codec {
+            StreamCodec::Mp3 | StreamCodec::Aac => Err(AudioEngineError::configuration(format!(
+                "RTP streams carry L16, L24 or Opus, not {codec}"
+            ))),
+            StreamCodec::Opus(_) if format.sample_rate.as_hz() != OPUS_CLOCK_RATE => {
+                Err(AudioEngineError::configuration(format!(
+                    "RTP Opus streams run at 48 kHz, not {}",
+                    format.sample_rate
+                )))
+            }
+            StreamCodec::Opus(_) if format.channels.count() > 2 => {
+                Err(AudioEngineError::configuration(format!(
+                    "RTP Opus streams carry one or two channels, not {}",
+                    format.channels.count()
+                )))
+            }
+            StreamCodec::Opus(_) => Ok(()),
+            StreamCodec::L16 | StreamCodec::L24 => {
+                let bytes = self.packet_frames(codec, format)
+                    * format.channels.count_usize()
+                    * bit_depth(codec).bytes_per_sample() as usize;
+                if bytes > MAX_PAYLOAD {
+                    return Err(AudioEngineError::configuration(format!(
+                        "{} ms packets of {codec} are {bytes} bytes, more than the {MAX_PAYLOAD} that fit",
+                        self.packet_time.as_millis()
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Bit depth of the PCM samples of `codec`
+const fn bit_depth(codec: StreamCodec) -> BitDepth {
+    match codec {
+        StreamCodec::L24 => BitDepth::I24,
+        _ => BitDepth::I16,
+    }
+}
+
+/// A random number for sequence numbers, timestamps and SSRCs, which
+/// RFC 3550 wants unpredictable
+#[allow(clippy::cast_possible_truncation)]
+fn random() -> u32 {
+    RandomState::new().hash_one(Instant::now()) as u32
+}
+
+/// Rate of the RTP clock of `codec` in `format`
+#[must_use]
+pub const fn clock_rate(codec: StreamCodec, format: AudioFormat) -> u32 {
+    match codec {
+        StreamCodec::Opus(_) => OPUS_CLOCK_RATE,
+        _ => format.sample_rate.as_hz(),
+    }
+}
+
+/// A session description (RFC 4566) of a stream sent to `url` with
+/// `codec` in `format`, which players can open to receive it.
+#[must_use]
+pub fn sdp(
+    url: &StreamUrl,
+    codec: StreamCodec,
+    format: AudioFormat,
+    settings: &RtpSettings,
+) -> String {
+    let payload_type = settings.payload_type;
+    let channels = format.channels.count();
+    let mut sdp = format!(
+        "v=0\r\no=- {} 0 IN IP4 {host}\r\ns={CNAME}\r\nc=IN IP4 {host}\r\nt=0 0\r\n\
+         m=audio {} RTP/AVP {payload_type}\r\n",
+        settings.ssrc.unwrap_or_default(),
+        url.port(),
+        host = url.host(),
+    );
+    match codec {
+        StreamCodec::Opus(opus) => {
+            let _ = write!(
+                sdp,
+                "a=rtpmap:{payload_type} opus/{OPUS_CLOCK_RATE}/2\r\n\
+                 a=fmtp:{payload_type} stereo={}\r\na=ptime:{}\r\n",
+                u8::from(channels > 1),
+                opus.frame_size.as_ms()
+            );
+        }
+        _ => {
+            let _ = write!(
+                sdp,
+                "a=rtpmap:{payload_type} {codec}/{}/{channels}\r\na=ptime:{}\r\n",
+                format.sample_rate.as_hz(),
+                settings.packet_time.as_secs_f64() * 1000.0
+            );
+        }
+    }
+    sdp
+}
@@ -0,0 +1,186 @@
+//! Ogg framing of Opus packets
+//!
+//! `opusenc` writes Ogg pages and `opusdec` reads them, while RTP carries
+//! the bare Opus packets. [`OggPacketReader`] takes the packets out of the
+//! encoder's pages and [`OggPacketWriter`] puts received ones back into
+//! pages for the decoder.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Length of an Ogg page header before its segment table
+const PAGE_HEADER_LEN: usize = 27;
+
+/// Page header flag of the first page of a stream
+const BEGINNING_OF_STREAM: u8 = 0x02;
+
+/// Serial number of the stream written to the decoder
+const SERIAL: u32 = 0x5254_5031;
+
+/// Lookup table of the Ogg CRC (polynomial 0x04c11db7, not reflected)
+const CRC_TABLE: [u32; 256] = crc_table();
+
+#[allow(clippy::cast_possible_truncation)]
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = (index as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x04c1_1db7
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+fn crc(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (crc << 8) ^ CRC_TABLE[usize::from((crc >> 24) as u8 ^ byte)]
+    })
+}
+
+/// Reads the packets out of an Ogg stream.
+pub struct OggPacketReader<R> {
+    reader: R,
+    /// Lacing values of the current page not yet read
+    lacing: Vec<u8>,
+    next_lace: usize,
+    page: Vec<u8>,
+    /// Start of the next packet in `page`
+    position: usize,
+}
+
+impl<R: Read> OggPacketReader<R> {
+    pub const fn new(reader: R) -> Self {
+        Self {
+            reader,
+            lacing: Vec::new(),
+            next_lace: 0,
+            page: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// The next packet, joined across pages if need be, or `None` at the end
+    /// of the stream.
+    pub fn next_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut packet = Vec::new();
+        loop {
+            if self.next_lace == self.lacing.len() && !self.read_page()? {
+                return Ok(None);
+            }
+            let lace = usize::from(self.lacing[self.next_lace]);
+            self.next_lace += 1;
+            packet.extend_from_slice(&self.page[self.position..self.position + lace]);
+            self.position += lace;
+            // A lacing value under 255 ends the packet
+            if lace < 255 {
+                return Ok(Some(packet));
+            }
+        }
+    }
+
+    fn read_page(&mut self) -> io::Result<bool> {
+        let mut header = [0; PAGE_HEADER_LEN];
+        match self.reader.read_exact(&mut header) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            read => read?,
+        }
+        if &header[..4] != b"OggS" {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "encoder output lost Ogg page sync",
+            ));
+        }
+        self.lacing.resize(usize::from(header[26]), 0);
+        self.reader.read_exact(&mut self.lacing)?;
+        let body = self.lacing.iter().map(|&lace| usize::from(lace)).sum();
+        self.page.resize(body, 0);
+        self.reader.read_exact(&mut self.page)?;
+        self.next_lace = 0;
+        self.position = 0;
+        Ok(!self.lacing.is_empty() || self.read_page()?)
+    }
+}
+
+/// Writes Opus packets as an Ogg Opus stream, one packet per page.
+pub struct OggPacketWriter<W> {
+    writer: W,
+    sequence: u32,
+    /// Samples at 48 kHz written so far
+    granule: u64,
+    page: Vec<u8>,
+}
+
+impl<W: Write> OggPacketWriter<W> {
+    /// Starts the stream with the Opus identification and comment
+    /// headers for `channels` (1 or 2) channels.
+    pub fn new(writer: W, channels: u8) -> io::Result<Self> {
+        let mut ogg = Self {
+            writer,
+            sequence: 0,
+            granule: 0,
+            page: Vec::new(),
+        };
+        let mut head = b"OpusHead".to_vec();
+        head.extend_from_slice(&[1, channels]);
+        head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+        head.extend_from_slice(&48_000u32.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // mono or stereo mapping
+        ogg.write_page(&head, BEGINNING_OF_STREAM)?;
+
+        let vendor = super::CNAME.as_bytes();
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(
+            &u32::try_from(vendor.len())
+                .unwrap_or_default()
+                .to_le_bytes(),
+        );
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        ogg.write_page(&tags, 0)?;
+        Ok(ogg)
+    }
+
+    /// Writes a packet holding `samples` samples per channel at 48 kHz.
+    pub fn write_packet(&mut self, packet: &[u8], samples: u64) -> io::Result<()> {
+        self.granule += samples;
+        self.write_page(packet, 0)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn write_page(&mut self, packet: &[u8], flags: u8) -> io::Result<()> {
+        let laces = packet.len() / 255 + 1;
+        if laces > 255 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Opus packet too long",
+            ));
+        }
+        self.page.clear();
+        self.page.extend_from_slice(b"OggS");
+        self.page.push(0);
+        self.page.push(flags);
+        self.page.extend_from_slice(&self.granule.to_le_bytes());
+        self.page.extend_from_slice(&SERIAL.to_le_bytes());
+        self.page.extend_from_slice(&self.sequence.to_le_bytes());
+        self.page.extend_from_slice(&[0; 4]);
+        self.page.push(laces as u8);
+        self.page.extend(std::iter::repeat_n(255, laces - 1));
+        self.page.push((packet.len() % 255) as u8);
+        self.page.extend_from_slice(packet);
+        let checksum = crc(&self.page);
+        self.page[22..26].copy_from_slice(&checksum.to_le_bytes());
+        self.sequence += 1;
+        self.writer.write_all(&self.page)?;
+        self.writer.flush()
+    }
+}
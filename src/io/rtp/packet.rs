@@ -0,0 +1,416 @@
+//! RTP and RTCP wire formats (RFC 3550)
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bytes of an RTP header without CSRCs or extensions
+pub const HEADER_LEN: usize = 12;
+
+const VERSION: u8 = 2;
+
+/// RTCP packet types
+const SENDER_REPORT: u8 = 200;
+const RECEIVER_REPORT: u8 = 201;
+const SOURCE_DESCRIPTION: u8 = 202;
+
+/// SDES item type of a canonical name
+const CNAME: u8 = 1;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// The fixed part of an RTP packet header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtpHeader {
+    pub payload_type: u8,
+    /// Set on the first packet after a gap in sending
+    pub marker: bool,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+}
+
+impl RtpHeader {
+    /// Appends the header to `out`.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.push(VERSION << 6);
+        out.push((u8::from(self.marker) << 7) | (self.payload_type & 0x7f));
+        out.extend_from_slice(&self.sequence.to_be_bytes());
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+    }
+
+    /// Splits a packet into its header and payload, skipping CSRCs, a
+    /// header extension and padding. `None` if it isn't an RTP packet.
+    #[must_use]
+    pub fn parse(packet: &[u8]) -> Option<(Self, &[u8])> {
+        if packet.len() < HEADER_LEN || packet[0] >> 6 != VERSION {
+            return None;
+        }
+        let header = Self {
+            payload_type: packet[1] & 0x7f,
+            marker: packet[1] & 0x80 != 0,
+            sequence: u16::from_be_bytes([packet[2], packet[3]]),
+            timestamp: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            ssrc: u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+        };
+        let mut start = HEADER_LEN + usize::from(packet[0] & 0x0f) * 4;
+        if packet[0] & 0x10 != 0 {
+            let words = packet.get(start + 2..start + 4)?;
+            start += 4 + usize::from(u16::from_be_bytes([words[0], words[1]])) * 4;
+        }
+        let mut end = packet.len();
+        if packet[0] & 0x20 != 0 {
+            end = end.checked_sub(usize::from(*packet.last()?))?;
+        }
+        Some((header, packet.get(start..end)?))
+    }
+}
+
+/// What an RTCP sender report says about the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenderReport {
+    pub ssrc: u32,
+    /// Wall clock time of the report, as a 64 bit NTP timestamp
+    pub ntp_timestamp: u64,
+    /// The same instant on the stream's RTP clock
+    pub rtp_timestamp: u32,
+    pub packets: u32,
+    /// Payload bytes sent
+    pub octets: u32,
+}
+
+impl SenderReport {
+    /// Appends the report to `out`, with no reception blocks.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn write(&self, out: &mut Vec<u8>) {
+        write_rtcp_header(out, 0, SENDER_REPORT, 6);
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        out.extend_from_slice(&self.ntp_timestamp.to_be_bytes());
+        out.extend_from_slice(&self.rtp_timestamp.to_be_bytes());
+        out.extend_from_slice(&self.packets.to_be_bytes());
+        out.extend_from_slice(&self.octets.to_be_bytes());
+    }
+}
+
+/// How a receiver is getting on with one source, from an RTCP receiver
+/// report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReceptionReport {
+    /// Source the report is about
+    pub ssrc: u32,
+    /// Share of packets lost since the last report, in 256ths
+    pub fraction_lost: u8,
+    /// Packets lost in all, up to 2^24 - 1
+    pub cumulative_lost: u32,
+    /// Highest sequence number received, with the wrap count in the top
+    /// 16 bits
+    pub highest_sequence: u32,
+    /// Interarrival jitter, in RTP timestamp units
+    pub jitter: u32,
+    /// Middle 32 bits of the last sender report's NTP timestamp
+    pub last_sender_report: u32,
+    /// Time since that report, in 65536ths of a second
+    pub delay_since_last: u32,
+}
+
+impl ReceptionReport {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        out.push(self.fraction_lost);
+        out.extend_from_slice(&self.cumulative_lost.min(0x00ff_ffff).to_be_bytes()[1..]);
+        out.extend_from_slice(&self.highest_sequence.to_be_bytes());
+        out.extend_from_slice(&self.jitter.to_be_bytes());
+        out.extend_from_slice(&self.last_sender_report.to_be_bytes());
+        out.extend_from_slice(&self.delay_since_last.to_be_bytes());
+    }
+
+    fn parse(block: &[u8]) -> Self {
+        let word = |at: usize| {
+            u32::from_be_bytes([block[at], block[at + 1], block[at + 2], block[at + 3]])
+        };
+        Self {
+            ssrc: word(0),
+            fraction_lost: block[4],
+            cumulative_lost: word(4) & 0x00ff_ffff,
+            highest_sequence: word(8),
+            jitter: word(12),
+            last_sender_report: word(16),
+            delay_since_last: word(20),
+        }
+    }
+}
+
+/// Appends a receiver report from `ssrc`, with one reception block if
+/// there is a source to report on.
+pub fn write_receiver_report(ssrc: u32, report: Option<&ReceptionReport>, out: &mut Vec<u8>) {
+    let blocks = u8::from(report.is_some());
+    write_rtcp_header(out, blocks, RECEIVER_REPORT, 1 + 6 * u16::from(blocks));
+    out.extend_from_slice(&ssrc.to_be_bytes());
+    if let Some(report) = report {
+        report.write(out);
+    }
+}
+
+/// Appends a source description giving `ssrc` the canonical name `cname`,
+/// which every compound RTCP packet carries.
+#[allow(clippy::cast_possible_truncation)]
+pub fn write_source_description(ssrc: u32, cname: &str, out: &mut Vec<u8>) {
+    let name = &cname.as_bytes()[..cname.len().min(255)];
+    // SSRC, item type and length, the name, and at least one zero byte
+    // to end the list, padded to a whole word
+    let words = (4 + 2 + name.len() + 1).div_ceil(4);
+    write_rtcp_header(out, 1, SOURCE_DESCRIPTION, words as u16);
+    let start = out.len();
+    out.extend_from_slice(&ssrc.to_be_bytes());
+    out.push(CNAME);
+    out.push(name.len() as u8);
+    out.extend_from_slice(name);
+    out.resize(start + words * 4, 0);
+}
+
+fn write_rtcp_header(out: &mut Vec<u8>, count: u8, packet_type: u8, words: u16) {
+    out.push((VERSION << 6) | (count & 0x1f));
+    out.push(packet_type);
+    out.extend_from_slice(&words.to_be_bytes());
+}
+
+/// The reports in a compound RTCP packet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RtcpReports {
+    pub sender: Option<SenderReport>,
+    pub reception: Vec<ReceptionReport>,
+}
+
+impl RtcpReports {
+    /// Reads the sender and reception reports out of a compound packet,
+    /// skipping other packet types. `None` if it isn't an RTCP packet.
+    #[must_use]
+    pub fn parse(mut compound: &[u8]) -> Option<Self> {
+        let mut reports = Self::default();
+        while compound.len() >= 4 {
+            if compound[0] >> 6 != VERSION {
+                return None;
+            }
+            let count = usize::from(compound[0] & 0x1f);
+            let len = (usize::from(u16::from_be_bytes([compound[2], compound[3]])) + 1) * 4;
+            let packet = compound.get(..len)?;
+            let blocks = match packet[1] {
+                SENDER_REPORT if len >= 28 => {
+                    let word = |at: usize| {
+                        u32::from_be_bytes([
+                            packet[at],
+                            packet[at + 1],
+                            packet[at + 2],
+                            packet[at + 3],
+                        ])
+                    };
+                    reports.sender = Some(SenderReport {
+                        ssrc: word(4),
+                        ntp_timestamp: (u64::from(word(8)) << 32) | u64::from(word(12)),
+                        rtp_timestamp: word(16),
+                        packets: word(20),
+                        octets: word(24),
+                    });
+                    &packet[28..]
+                }
+                RECEIVER_REPORT if len >= 8 => &packet[8..],
+                _ => &[][..],
+            };
+            reports.reception.extend(
+                blocks
+                    .chunks_exact(24)
+                    .take(count)
+                    .map(ReceptionReport::parse),
+            );
+            compound = &compound[len..];
+        }
+        Some(reports)
+    }
+}
+
+/// The wall clock now, as a 64 bit NTP timestamp
+#[must_use]
+pub fn ntp_now() -> u64 {
+    let since_unix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = since_unix.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = (u64::from(since_unix.subsec_nanos()) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+/// The middle 32 bits of an NTP timestamp, as reception reports quote it
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub const fn ntp_middle(ntp: u64) -> u32 {
+    (ntp >> 16) as u32
+}
+
+/// Extends 16 bit sequence numbers into a count that doesn't wrap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SequenceCounter {
+    highest: Option<u64>,
+}
+
+impl SequenceCounter {
+    /// The extended number of `sequence`, taken to be the one nearest the
+    /// highest seen so far.
+    pub fn extend(&mut self, sequence: u16) -> u64 {
+        // Start a cycle in, so numbers just before the first don't go negative
+        let Some(highest) = self.highest else {
+            let extended = (1 << 16) | u64::from(sequence);
+            self.highest = Some(extended);
+            return extended;
+        };
+        let candidate = (highest & !0xffff) | u64::from(sequence);
+        let extended = [
+            candidate.saturating_sub(1 << 16),
+            candidate,
+            candidate + (1 << 16),
+        ]
+        .into_iter()
+        .min_by_key(|&c| c.abs_diff(highest))
+        .unwrap_or(candidate);
+        self.highest = Some(highest.max(extended));
+        extended
+    }
+
+    /// The highest extended number seen
+    #[must_use]
+    pub const fn highest(&self) -> Option<u64> {
+        self.highest
+    }
+
+    pub const fn reset(&mut self) {
+        self.highest = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: RtpHeader = RtpHeader {
+        payload_type: 96,
+        marker: true,
+        sequence: 0xfffe,
+        timestamp: 0x1234_5678,
+        ssrc: 0xdead_beef,
+    };
+
+    #[test]
+    fn rtp_header_round_trips() {
+        let mut packet = Vec::new();
+        HEADER.write(&mut packet);
+        assert_eq!(packet.len(), HEADER_LEN);
+        packet.extend_from_slice(&[1, 2, 3]);
+        let (header, payload) = RtpHeader::parse(&packet).expect("parses");
+        assert_eq!(header, HEADER);
+        assert_eq!(payload, [1, 2, 3]);
+    }
+
+    #[test]
+    fn rtp_parse_skips_csrcs_extension_and_padding() {
+        let mut packet = Vec::new();
+        HEADER.write(&mut packet);
+        // Two CSRCs, an extension of one word and two bytes of padding
+        packet[0] |= 0x10 | 0x20 | 2;
+        packet.extend_from_slice(&[0; 8]);
+        packet.extend_from_slice(&[0xbe, 0xde, 0, 1, 9, 9, 9, 9]);
+        packet.extend_from_slice(&[7, 8]);
+        packet.extend_from_slice(&[0, 2]);
+        let (_, payload) = RtpHeader::parse(&packet).expect("parses");
+        assert_eq!(payload, [7, 8]);
+    }
+
+    #[test]
+    fn rtp_parse_rejects_malformed_packets() {
+        let mut packet = Vec::new();
+        HEADER.write(&mut packet);
+        assert!(RtpHeader::parse(&packet[..HEADER_LEN - 1]).is_none());
+
+        let mut version = packet.clone();
+        version[0] = 1 << 6;
+        assert!(RtpHeader::parse(&version).is_none());
+
+        // CSRCs or an extension running past the end
+        let mut csrcs = packet.clone();
+        csrcs[0] |= 15;
+        assert!(RtpHeader::parse(&csrcs).is_none());
+        let mut extension = packet.clone();
+        extension[0] |= 0x10;
+        extension.extend_from_slice(&[0, 0, 0xff, 0xff]);
+        assert!(RtpHeader::parse(&extension).is_none());
+
+        // Padding longer than the packet, or eating into the header
+        let mut padding = packet;
+        padding[0] |= 0x20;
+        padding.push(200);
+        assert!(RtpHeader::parse(&padding).is_none());
+        *padding.last_mut().expect("padding byte") = 2;
+        assert!(RtpHeader::parse(&padding).is_none());
+    }
+
+    #[test]
+    fn rtcp_reports_round_trip_through_a_compound_packet() {
+        let sender = SenderReport {
+            ssrc: 1,
+            ntp_timestamp: 0x0102_0304_0506_0708,
+            rtp_timestamp: 48_000,
+            packets: 10,
+            octets: 1_920,
+        };
+        let reception = ReceptionReport {
+            ssrc: 1,
+            fraction_lost: 64,
+            cumulative_lost: u32::MAX,
+            highest_sequence: 0x0001_0002,
+            jitter: 12,
+            last_sender_report: ntp_middle(sender.ntp_timestamp),
+            delay_since_last: 65_536,
+        };
+        let mut compound = Vec::new();
+        sender.write(&mut compound);
+        write_receiver_report(2, Some(&reception), &mut compound);
+        write_source_description(2, "receiver@example", &mut compound);
+        assert_eq!(compound.len() % 4, 0);
+
+        let reports = RtcpReports::parse(&compound).expect("parses");
+        assert_eq!(reports.sender, Some(sender));
+        // The loss count is clamped to the 24 bits it's sent in
+        assert_eq!(
+            reports.reception,
+            [ReceptionReport {
+                cumulative_lost: 0x00ff_ffff,
+                ..reception
+            }]
+        );
+        assert_eq!(reports.reception[0].last_sender_report, 0x0304_0506);
+    }
+
+    #[test]
+    fn rtcp_parse_rejects_truncated_and_foreign_packets() {
+        let mut compound = Vec::new();
+        write_receiver_report(2, Some(&ReceptionReport::default()), &mut compound);
+        assert!(RtcpReports::parse(&compound[..compound.len() - 4]).is_none());
+        compound[0] &= 0x3f;
+        assert!(RtcpReports::parse(&compound).is_none());
+        assert_eq!(RtcpReports::parse(&[]), Some(RtcpReports::default()));
+    }
+
+    #[test]
+    fn sequence_numbers_extend_across_wraps() {
+        let mut counter = SequenceCounter::default();
+        assert_eq!(counter.extend(0xfffe), 0x1_fffe);
+        assert_eq!(counter.extend(0xffff), 0x1_ffff);
+        assert_eq!(counter.extend(1), 0x2_0001);
+        // A late packet from before the wrap keeps its cycle
+        assert_eq!(counter.extend(0xfffd), 0x1_fffd);
+        assert_eq!(counter.highest(), Some(0x2_0001));
+        counter.reset();
+        assert_eq!(counter.highest(), None);
+        // Numbers just before the first one don't go below zero
+        assert_eq!(counter.extend(0), 0x1_0000);
+        assert_eq!(counter.extend(0xffff), 0xffff);
+    }
+}
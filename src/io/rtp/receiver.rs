@@ -0,0 +1,651 @@
+//! RTP receiver
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "opus")]
+use std::ffi::OsStr;
+#[cfg(feature = "opus")]
+use std::io::Read;
+#[cfg(feature = "opus")]
+use std::process::{Child, ChildStdin, ChildStdout, Stdio};
+
+use parking_lot::Mutex;
+
+use super::packet::{
+    ReceptionReport, RtcpReports, RtpHeader, SenderReport, SequenceCounter, ntp_middle,
+    write_receiver_report, write_source_description,
+};
+use super::{CNAME, RtpSettings, bit_depth, clock_rate, random};
+use crate::buffer::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::error::{AudioEngineError, Result};
+use crate::io::input::NetworkInput;
+#[cfg(feature = "opus")]
+use crate::io::opus::start_decoder;
+use crate::io::output::StreamCodec;
+#[cfg(feature = "opus")]
+use crate::io::rtp::ogg::OggPacketWriter;
+use crate::io::wav::decode_sample;
+use crate::types::{AudioFormat, BitDepth, NetworkProtocol, Sample};
+
+/// How long the network thread waits for a packet before checking
+/// whether held packets are due
+const RECV_TIMEOUT: Duration = Duration::from_millis(2);
+
+/// Packets held for reordering before the oldest is given up on
+const MAX_HELD: usize = 32;
+
+/// Buffered audio, in target latencies, past which the reader skips
+/// back down to the target
+const SKIP_FACTOR: usize = 3;
+
+/// Statistics of an RTP stream as received.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RtpStats {
+    pub packets: u64,
+    /// Packets that never arrived, or arrived too late to reorder
+    pub lost: u64,
+    /// Packets that arrived after their turn, or twice
+    pub late: u64,
+    /// Interarrival jitter (RFC 3550)
+    pub jitter: Duration,
+    /// Times the buffer ran dry and playing paused to fill it again
+    pub underruns: u64,
+    /// Samples skipped to bring the latency back down
+    pub skipped: u64,
+    /// Audio buffered now
+    pub buffered: Duration,
+}
+
+impl fmt::Display for RtpStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} packets, {} lost, {} late, {:.1} ms jitter, {} underruns, {:.1} ms buffered",
+            self.packets,
+            self.lost,
+            self.late,
+            self.jitter.as_secs_f64() * 1000.0,
+            self.underruns,
+            self.buffered.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// State the network thread shares with the reading side
+#[derive(Default)]
+struct Shared {
+    stop: AtomicBool,
+    packets: AtomicU64,
+    lost: AtomicU64,
+    late: AtomicU64,
+    /// Interarrival jitter in RTP clock units
+    jitter: AtomicU32,
+    sender_report: Mutex<Option<SenderReport>>,
+}
+
+/// Receives RTP audio sent to the url of a [`NetworkInput`] and plays it
+/// back through a jitter buffer.
+pub struct RtpReceiver {
+    input: NetworkInput,
+    format: AudioFormat,
+    local_addr: SocketAddr,
+    buffer: RingBufferReader<Sample>,
+    shared: Arc<Shared>,
+    /// Samples kept buffered: the target latency
+    target: usize,
+    /// Whether the buffer is filling up to the target before playing
+    priming: bool,
+    underruns: u64,
+    skipped: u64,
+    network: Option<JoinHandle<()>>,
+    #[cfg(feature = "opus")]
+    decoder: Option<(Child, JoinHandle<()>)>,
+}
+
+impl RtpReceiver {
+    /// Starts receiving on `input`'s url: its address and port if local,
+    /// or its port on every interface, joined to the group, if multicast.
+    /// The stream must be sent with `input`'s codec, in `format`.
+    ///
+    /// # Errors
+    /// Returns an error if the url isn't an RTP one or can't be resolved,
+    /// the codec or settings can't be received (see
+    /// [`RtpSettings::validate`]), the sockets can't be bound, or the Opus
+    /// decoder can't be started.
+    pub fn bind(input: &NetworkInput, format: AudioFormat) -> Result<Self> {
+        if input.url.protocol() != NetworkProtocol::RTP {
+            return Err(AudioEngineError::configuration(format!(
+                "{} isn't an RTP url",
+                input.url
+            )));
+        }
+        let settings = input.rtp;
+        settings.validate(input.codec, format)?;
+
+        let address = input.url.to_socket_addr()?;
+        let socket = bind(address)?;
+        let rtcp = bind(SocketAddr::new(
+            address.ip(),
+            address.port().wrapping_add(1),
+        ))?;
+        let local_addr = socket.local_addr()?;
+        socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+        rtcp.set_nonblocking(true)?;
+
+        let channels = format.channels.count_usize();
+        let rate = format.sample_rate.as_hz() as usize;
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_precision_loss,
+            clippy::cast_sign_loss
+        )]
+        let target_frames = (settings.target_latency.as_secs_f64() * rate as f64).round() as usize;
+        let target = target_frames.max(1) * channels;
+        // Room to skip from, and for a burst of packets on top
+        let (writer, reader) = RingBuffer::new(target * (SKIP_FACTOR + 1) + rate / 10 * channels);
+
+        let shared = Arc::new(Shared::default());
+        let packet_frames = settings.packet_frames(input.codec, format);
+        #[cfg(feature = "opus")]
+        let mut decoder = None;
+        let sink = match input.codec {
+            #[cfg(feature = "opus")]
+            StreamCodec::Opus(_) => {
+                let (child, stdin, thread) = spawn_opus_decoder(writer, channels)?;
+                decoder = Some((child, thread));
+                #[allow(clippy::cast_possible_truncation)]
+                PayloadSink::Opus(OggPacketWriter::new(stdin, channels as u8)?)
+            }
+            #[cfg(not(feature = "opus"))]
+            StreamCodec::Opus(_) => {
+                return Err(AudioEngineError::configuration(
+                    "can't receive Opus without the opus feature",
+                ));
+            }
+            codec => PayloadSink::Pcm {
+                buffer: writer,
+                depth: bit_depth(codec),
+                channels,
+                samples: Vec::with_capacity(packet_frames * channels),
+            },
+        };
+
+        let worker = Worker {
+            socket,
+            rtcp,
+            settings,
+            clock_rate: clock_rate(input.codec, format),
+            #[cfg(feature = "opus")]
+            packet_frames: u32::try_from(packet_frames).unwrap_or(u32::MAX),
+            max_gap: u32::try_from(target_frames).unwrap_or(u32::MAX),
+            sink,
+            shared: Arc::clone(&shared),
+            ssrc: random(),
+            started: Instant::now(),
+            source: SourceState::default(),
+            last_sender_report: None,
+            next_report: Instant::now() + settings.rtcp_interval,
+        };
+        let network = thread::Builder::new()
+            .name("rtp-receiver".to_string())
+            .spawn(move || worker.run())?;
+        log::info!("Receiving {} over RTP on {local_addr}", input.codec);
+        Ok(Self {
+            input: input.clone(),
+            format,
+            local_addr,
+            buffer: reader,
+            shared,
+            target,
+            priming: true,
+            underruns: 0,
+            skipped: 0,
+            network: Some(network),
+            #[cfg(feature = "opus")]
+            decoder,
+        })
+    }
+
+    #[must_use]
+    pub const fn input(&self) -> &NetworkInput {
+        &self.input
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Address the RTP socket is bound to
+    #[must_use]
+    pub const fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Fills `out` with the next interleaved samples, returning how many
+    /// came from the stream. The rest are silence: while the buffer fills
+    /// up to the target latency at the start and after an underrun.
+    pub fn read(&mut self, out: &mut [Sample]) -> usize {
+        let available = self.buffer.slots();
+        if self.priming {
+            if available < self.target {
+                out.fill(Sample::SILENCE);
+                return 0;
+            }
+            self.priming = false;
+        } else if available > self.target * SKIP_FACTOR {
+            let channels = self.format.channels.count_usize();
+            let excess = available - self.target;
+            self.skipped += self.buffer.discard(excess - excess % channels) as u64;
+        }
+        let read = self.buffer.pop_slice(out);
+        if read < out.len() {
+            out[read..].fill(Sample::SILENCE);
+            self.underruns += 1;
+            self.priming = true;
+        }
+        read
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> RtpStats {
+        let rate = f64::from(self.format.sample_rate.as_hz());
+        let channels = self.format.channels.count_usize();
+        #[allow(clippy::cast_precision_loss)]
+        let buffered = (self.buffer.slots() / channels) as f64 / rate;
+        let clock = f64::from(clock_rate(self.input.codec, self.format));
+        RtpStats {
+            packets: self.shared.packets.load(Ordering::Relaxed),
+            lost: self.shared.lost.load(Ordering::Relaxed),
+            late: self.shared.late.load(Ordering::Relaxed),
+            jitter: Duration::from_secs_f64(
+                f64::from(self.shared.jitter.load(Ordering::Relaxed)) / clock,
+            ),
+            underruns: self.underruns,
+            skipped: self.skipped,
+            buffered: Duration::from_secs_f64(buffered),
+        }
+    }
+
+    /// The last sender report of the stream's source, if one came
+    #[must_use]
+    pub fn sender_report(&self) -> Option<SenderReport> {
+        *self.shared.sender_report.lock()
+    }
+}
+
+impl Drop for RtpReceiver {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(network) = self.network.take() {
+            let _ = network.join();
+        }
+        #[cfg(feature = "opus")]
+        if let Some((mut child, thread)) = self.decoder.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for RtpReceiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RtpReceiver")
+            .field("url", &self.input.url)
+            .field("codec", &self.input.codec)
+            .field("format", &self.format)
+            .field("local_addr", &self.local_addr)
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where received payloads are decoded to
+enum PayloadSink {
+    Pcm {
+        buffer: RingBufferWriter<Sample>,
+        depth: BitDepth,
+        channels: usize,
+        samples: Vec<Sample>,
+    },
+    #[cfg(feature = "opus")]
+    Opus(OggPacketWriter<ChildStdin>),
+}
+
+/// A packet waiting for its turn
+struct Held {
+    arrived: Instant,
+    timestamp: u32,
+    payload: Vec<u8>,
+}
+
+/// What the network thread knows about the current source
+#[derive(Default)]
+struct SourceState {
+    ssrc: Option<u32>,
+    sequence: SequenceCounter,
+    held: BTreeMap<u64, Held>,
+    /// Extended sequence number of the next packet to play
+    next: Option<u64>,
+    /// RTP timestamp the next packet should carry
+    next_timestamp: Option<u32>,
+    /// Extended sequence number of the first packet
+    base: Option<u64>,
+    received: u64,
+    /// Packets expected and received at the last receiver report
+    expected_prior: u64,
+    received_prior: u64,
+    /// Relative transit time of the last packet, in RTP clock units
+    transit: Option<u32>,
+    jitter: f64,
+}
+
+/// Receives packets, puts them in order and decodes them, and answers
+/// sender reports.
+struct Worker {
+    socket: UdpSocket,
+    rtcp: UdpSocket,
+    settings: RtpSettings,
+    clock_rate: u32,
+    /// Frames per Opus packet, which the Ogg granule positions count
+    #[cfg(feature = "opus")]
+    packet_frames: u32,
+    /// Longest run of lost audio filled with silence, in frames
+    max_gap: u32,
+    sink: PayloadSink,
+    shared: Arc<Shared>,
+    /// Our own synchronization source, for the receiver reports
+    ssrc: u32,
+    started: Instant,
+    source: SourceState,
+    /// The source's last sender report, when it came and where from
+    last_sender_report: Option<(SenderReport, Instant, SocketAddr)>,
+    next_report: Instant,
+}
+
+impl Worker {
+    fn run(mut self) {
+        let mut datagram = vec![0; 65_536];
+        while !self.shared.stop.load(Ordering::Relaxed) {
+            match self.socket.recv(&mut datagram) {
+                Ok(len) => self.receive(&datagram[..len]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => {
+                    log::error!("Receiving RTP failed: {e}");
+                    return;
+                }
+            }
+            if let Err(e) = self.release(Instant::now()) {
+                log::error!("Decoding RTP failed: {e}");
+                return;
+            }
+            self.exchange_reports();
+        }
+    }
+
+    fn receive(&mut self, packet: &[u8]) {
+        let Some((header, payload)) = RtpHeader::parse(packet) else {
+            return;
+        };
+        if header.payload_type != self.settings.payload_type {
+            return;
+        }
+        if self.source.ssrc != Some(header.ssrc) {
+            if self.source.ssrc.is_some() {
+                log::info!("RTP source changed to {:08x}", header.ssrc);
+            }
+            self.source = SourceState {
+                ssrc: Some(header.ssrc),
+                ..SourceState::default()
+            };
+            self.last_sender_report = None;
+        }
+        let arrived = Instant::now();
+        self.update_jitter(header.timestamp, arrived);
+        self.shared.packets.fetch_add(1, Ordering::Relaxed);
+
+        let source = &mut self.source;
+        let sequence = source.sequence.extend(header.sequence);
+        source.received += 1;
+        source.base.get_or_insert(sequence);
+        let next = *source.next.get_or_insert(sequence);
+        if sequence < next || source.held.contains_key(&sequence) {
+            self.shared.late.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        source.held.insert(
+            sequence,
+            Held {
+                arrived,
+                timestamp: header.timestamp,
+                payload: payload.to_vec(),
+            },
+        );
+    }
+
+    /// Updates the interarrival jitter estimate (RFC 3550 A.8).
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn update_jitter(&mut self, timestamp: u32, arrived: Instant) {
+        let now = (arrived.duration_since(self.started).as_secs_f64() * f64::from(self.clock_rate))
+            as u64 as u32;
+        let transit = now.wrapping_sub(timestamp);
+        if let Some(previous) = self.source.transit {
+            #[allow(clippy::cast_possible_wrap)]
+            let change = transit.wrapping_sub(previous) as i32;
+            let jitter = &mut self.source.jitter;
+            *jitter += (f64::from(change.unsigned_abs()) - *jitter) / 16.0;
+            self.shared.jitter.store(*jitter as u32, Ordering::Relaxed);
+        }
+        self.source.transit = Some(transit);
+    }
+
+    /// Plays the held packets that are next in line, or that waited long
+    /// enough for the ones before them.
+    fn release(&mut self, now: Instant) -> std::io::Result<()> {
+        let wait = self.settings.target_latency / 2;
+        while let Some((&sequence, oldest)) = self.source.held.first_key_value() {
+            let Some(next) = self.source.next else {
+                break;
+            };
+            let overdue = now.duration_since(oldest.arrived) >= wait;
+            if sequence != next && !overdue && self.source.held.len() <= MAX_HELD {
+                break;
+            }
+            let Some((_, held)) = self.source.held.pop_first() else {
+                break;
+            };
+            self.shared
+                .lost
+                .fetch_add(sequence - next, Ordering::Relaxed);
+            self.source.next = Some(sequence + 1);
+            self.deliver(&held)?;
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(not(feature = "opus"), allow(clippy::unnecessary_wraps))]
+    fn deliver(&mut self, held: &Held) -> std::io::Result<()> {
+        // Frames missing before this packet, going by the timestamps
+        #[allow(clippy::cast_possible_wrap)]
+        let gap = self.source.next_timestamp.map_or(0, |expected| {
+            u32::try_from(held.timestamp.wrapping_sub(expected) as i32).unwrap_or(0)
+        });
+        let frames = match &mut self.sink {
+            PayloadSink::Pcm {
+                buffer,
+                depth,
+                channels,
+                samples,
+            } => {
+                let channels = *channels;
+                samples.clear();
+                samples.resize(gap.min(self.max_gap) as usize * channels, Sample::SILENCE);
+                let sample_bytes = depth.bytes_per_sample() as usize;
+                let frame_bytes = sample_bytes * channels;
+                let whole = held.payload.len() - held.payload.len() % frame_bytes;
+                samples.extend(
+                    held.payload[..whole]
+                        .chunks_exact(sample_bytes)
+                        .map(|bytes| {
+                            // Network byte order is big-endian
+                            let mut le = [0; 3];
+                            le[..sample_bytes].copy_from_slice(bytes);
+                            le[..sample_bytes].reverse();
+                            Sample::new(decode_sample(*depth, &le))
+                        }),
+                );
+                push_frames(buffer, samples);
+                u32::try_from(whole / frame_bytes).unwrap_or(u32::MAX)
+            }
+            #[cfg(feature = "opus")]
+            PayloadSink::Opus(ogg) => {
+                ogg.write_packet(&held.payload, u64::from(self.packet_frames))?;
+                self.packet_frames
+            }
+        };
+        self.source.next_timestamp = Some(held.timestamp.wrapping_add(frames));
+        Ok(())
+    }
+
+    /// Takes in sender reports and sends a receiver report when one is
+    /// due.
+    fn exchange_reports(&mut self) {
+        let mut datagram = [0; 1500];
+        while let Ok((len, from)) = self.rtcp.recv_from(&mut datagram) {
+            if let Some(report) = RtcpReports::parse(&datagram[..len]).and_then(|r| r.sender)
+                && Some(report.ssrc) == self.source.ssrc
+            {
+                *self.shared.sender_report.lock() = Some(report);
+                self.last_sender_report = Some((report, Instant::now(), from));
+            }
+        }
+
+        let now = Instant::now();
+        if now < self.next_report {
+            return;
+        }
+        // Reports go back to where the sender reports come from
+        let Some((sender_report, arrived, from)) = self.last_sender_report else {
+            return;
+        };
+        self.next_report = now + self.settings.rtcp_interval;
+        let report = self.reception_report(&sender_report, now.duration_since(arrived));
+        let mut compound = Vec::with_capacity(64);
+        write_receiver_report(self.ssrc, Some(&report), &mut compound);
+        write_source_description(self.ssrc, CNAME, &mut compound);
+        let _ = self.rtcp.send_to(&compound, from);
+    }
+
+    /// Loss and jitter since the last report (RFC 3550 A.3).
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn reception_report(
+        &mut self,
+        sender_report: &SenderReport,
+        delay: Duration,
+    ) -> ReceptionReport {
+        let source = &mut self.source;
+        let highest = source.sequence.highest().unwrap_or_default();
+        let expected = (highest + 1).saturating_sub(source.base.unwrap_or(highest));
+        let expected_interval = expected - source.expected_prior;
+        let received_interval = source.received - source.received_prior;
+        source.expected_prior = expected;
+        source.received_prior = source.received;
+        let lost_interval = expected_interval.saturating_sub(received_interval);
+        let fraction_lost = (lost_interval << 8)
+            .checked_div(expected_interval)
+            .map_or(0, |fraction| fraction.min(255) as u8);
+        ReceptionReport {
+            ssrc: source.ssrc.unwrap_or_default(),
+            fraction_lost,
+            cumulative_lost: expected.saturating_sub(source.received) as u32,
+            // The counter starts a cycle in
+            highest_sequence: highest.saturating_sub(1 << 16) as u32,
+            jitter: source.jitter as u32,
+            last_sender_report: ntp_middle(sender_report.ntp_timestamp),
+            delay_since_last: (delay.as_secs_f64() * 65_536.0) as u32,
+        }
+    }
+}
+
+/// Pushes `samples` if they all fit, so the buffer only ever holds whole
+/// frames. The reader skips ahead long before it fills up.
+fn push_frames(buffer: &mut RingBufferWriter<Sample>, samples: &[Sample]) {
+    if buffer.slots() >= samples.len() {
+        buffer.push_slice(samples);
+    }
+}
+
+/// Binds a socket on `address`, or on its port on every interface and
+/// joined to the group if it is a multicast address.
+fn bind(address: SocketAddr) -> Result<UdpSocket> {
+    let socket = match address.ip() {
+        IpAddr::V4(group) if group.is_multicast() => {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, address.port()))?;
+            socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+            socket
+        }
+        IpAddr::V6(group) if group.is_multicast() => {
+            let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, address.port()))?;
+            socket.join_multicast_v6(&group, 0)?;
+            socket
+        }
+        _ => UdpSocket::bind(address)?,
+    };
+    Ok(socket)
+}
+
+/// Starts `opusdec` on an Ogg stream written to its stdin, and a thread
+/// that pushes what it decodes into `buffer`.
+#[cfg(feature = "opus")]
+fn spawn_opus_decoder(
+    buffer: RingBufferWriter<Sample>,
+    channels: usize,
+) -> Result<(Child, ChildStdin, JoinHandle<()>)> {
+    let mut child = start_decoder(OsStr::new("-"), Stdio::piped())?;
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(AudioEngineError::configuration(
+            "decoder has no stdin or stdout",
+        ));
+    };
+    let thread = thread::Builder::new()
+        .name("rtp-opus-decoder".to_string())
+        .spawn(move || read_decoded(stdout, buffer, channels))?;
+    Ok((child, stdin, thread))
+}
+
+/// Pushes the decoder's 16 bit PCM into `buffer` until it ends.
+#[cfg(feature = "opus")]
+fn read_decoded(mut pcm: ChildStdout, mut buffer: RingBufferWriter<Sample>, channels: usize) {
+    let frame_bytes = 2 * channels;
+    let mut bytes = vec![0; 960 * frame_bytes];
+    let mut samples = Vec::with_capacity(960 * channels);
+    let mut pending = 0;
+    loop {
+        let read = match pcm.read(&mut bytes[pending..]) {
+            Ok(0) => return,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                log::error!("Reading decoded RTP Opus failed: {e}");
+                return;
+            }
+        };
+        let filled = pending + read;
+        let whole = filled - filled % frame_bytes;
+        samples.clear();
+        samples.extend(bytes[..whole].chunks_exact(2).map(|bytes| {
+            Sample::new(f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32768.0)
+        }));
+        push_frames(&mut buffer, &samples);
+        bytes.copy_within(whole..filled, 0);
+        pending = filled - whole;
+    }
+}
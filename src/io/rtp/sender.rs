@@ -0,0 +1,503 @@
+//! RTP sender
+
+use std::fmt;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "opus")]
+use std::process::ChildStdout;
+
+use parking_lot::Mutex;
+
+use super::packet::{
+    ReceptionReport, RtcpReports, RtpHeader, SenderReport, ntp_now, write_source_description,
+};
+use super::{CNAME, RtpSettings, bit_depth, clock_rate, random};
+use crate::buffer::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::engine::RenderSink;
+use crate::error::{AudioEngineError, Result};
+#[cfg(feature = "opus")]
+use crate::io::opus::OpusEncoder;
+use crate::io::output::{NetworkOutput, StreamCodec};
+use crate::io::pcm::{ByteOrder, write_ordered};
+#[cfg(feature = "opus")]
+use crate::io::rtp::ogg::OggPacketReader;
+use crate::types::{AudioFormat, NetworkProtocol, Sample};
+
+/// Seconds of audio the ring buffer holds for the sender
+const BUFFER_SECONDS: usize = 1;
+
+/// How long the worker waits when there is nothing to send
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+/// Packets before the first audio packet in an Ogg Opus stream: the
+/// identification and comment headers
+#[cfg(feature = "opus")]
+const OPUS_HEADER_PACKETS: usize = 2;
+
+/// Where the samples go on their way to the worker
+enum SenderInput {
+    Pcm(RingBufferWriter<Sample>),
+    #[cfg(feature = "opus")]
+    Opus(OpusEncoder),
+}
+
+/// Where the worker takes its payloads from
+enum PacketSource {
+    Pcm {
+        buffer: RingBufferReader<Sample>,
+        block: Vec<Sample>,
+        channels: usize,
+        codec: StreamCodec,
+    },
+    #[cfg(feature = "opus")]
+    Opus {
+        packets: OggPacketReader<ChildStdout>,
+        headers_left: usize,
+        frames: u32,
+    },
+}
+
+/// State the worker shares with the writing side
+#[derive(Default)]
+struct Shared {
+    /// Set once no more samples are coming
+    done: AtomicBool,
+    dropped: AtomicU64,
+    packets: AtomicU64,
+    octets: AtomicU64,
+    /// Latest receiver report about this stream
+    report: Mutex<Option<ReceptionReport>>,
+}
+
+/// Sends audio as RTP packets to an `rtp://` [`NetworkOutput`].
+pub struct RtpSender {
+    output: NetworkOutput,
+    format: AudioFormat,
+    ssrc: u32,
+    input: SenderInput,
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<Result<()>>>,
+}
+
+impl RtpSender {
+    /// Starts sending to `output`'s url, with samples in `format`.
+    ///
+    /// # Errors
+    /// Returns an error if the url isn't an RTP one or can't be resolved,
+    /// the codec or settings can't be sent (see
+    /// [`RtpSettings::validate`]), the sockets can't be opened, or the Opus
+    /// encoder can't be started.
+    pub fn connect(output: &NetworkOutput, format: AudioFormat) -> Result<Self> {
+        if output.url.protocol() != NetworkProtocol::RTP {
+            return Err(AudioEngineError::configuration(format!(
+                "{} isn't an RTP url",
+                output.url
+            )));
+        }
+        let settings = output.rtp;
+        settings.validate(output.codec, format)?;
+
+        let destination = output.url.to_socket_addr()?;
+        let socket = bind_any(destination)?;
+        socket.connect(destination)?;
+        let rtcp = bind_any(destination)?;
+        rtcp.connect(SocketAddr::new(
+            destination.ip(),
+            destination.port().wrapping_add(1),
+        ))?;
+        rtcp.set_nonblocking(true)?;
+
+        let ssrc = settings.ssrc.unwrap_or_else(random);
+        let frames = settings.packet_frames(output.codec, format);
+        let (input, source) = match output.codec {
+            #[cfg(feature = "opus")]
+            StreamCodec::Opus(_) => {
+                let (encoder, pages) = OpusEncoder::for_stream(output, format)?;
+                let source = PacketSource::Opus {
+                    packets: OggPacketReader::new(pages),
+                    headers_left: OPUS_HEADER_PACKETS,
+                    frames: u32::try_from(frames).unwrap_or(u32::MAX),
+                };
+                (SenderInput::Opus(encoder), source)
+            }
+            #[cfg(not(feature = "opus"))]
+            StreamCodec::Opus(_) => {
+                return Err(AudioEngineError::configuration(
+                    "can't stream Opus without the opus feature",
+                ));
+            }
+            codec => {
+                let channels = format.channels.count_usize();
+                let (writer, reader) = RingBuffer::new(
+                    format.sample_rate.as_hz() as usize * BUFFER_SECONDS * channels,
+                );
+                let source = PacketSource::Pcm {
+                    buffer: reader,
+                    block: vec![Sample::SILENCE; frames * channels],
+                    channels,
+                    codec,
+                };
+                (SenderInput::Pcm(writer), source)
+            }
+        };
+
+        let shared = Arc::new(Shared::default());
+        let worker = Worker {
+            socket,
+            rtcp,
+            header: RtpHeader {
+                payload_type: settings.payload_type,
+                marker: true,
+                #[allow(clippy::cast_possible_truncation)]
+                sequence: random() as u16,
+                timestamp: random(),
+                ssrc,
+            },
+            source,
+            shared: Arc::clone(&shared),
+            settings,
+            clock_rate: clock_rate(output.codec, format),
+            started: Instant::now(),
+            first_timestamp: 0,
+            next_report: Instant::now(),
+            packet: Vec::with_capacity(super::MAX_PAYLOAD + super::packet::HEADER_LEN),
+            payload: Vec::with_capacity(super::MAX_PAYLOAD),
+        };
+        let worker = thread::Builder::new()
+            .name("rtp-sender".to_string())
+            .spawn(move || worker.run())?;
+        log::info!("Sending {} over RTP to {}", output.codec, output.url);
+        Ok(Self {
+            output: output.clone(),
+            format,
+            ssrc,
+            input,
+            shared,
+            worker: Some(worker),
+        })
+    }
+
+    #[must_use]
+    pub const fn output(&self) -> &NetworkOutput {
+        &self.output
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Synchronization source the packets carry
+    #[must_use]
+    pub const fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    /// A session description of the stream for players; see
+    /// [`sdp`](super::sdp).
+    #[must_use]
+    pub fn sdp(&self) -> String {
+        super::sdp(
+            &self.output.url,
+            self.output.codec,
+            self.format,
+            &self.output.rtp.with_ssrc(self.ssrc),
+        )
+    }
+
+    /// Queues interleaved samples to send without blocking, returning how
+    /// many were queued. The rest are dropped.
+    pub fn write(&mut self, samples: &[Sample]) -> usize {
+        match &mut self.input {
+            SenderInput::Pcm(buffer) => {
+                let pushed = buffer.push_slice(samples);
+                if pushed < samples.len() {
+                    self.shared
+                        .dropped
+                        .fetch_add((samples.len() - pushed) as u64, Ordering::Relaxed);
+                }
+                pushed
+            }
+            #[cfg(feature = "opus")]
+            SenderInput::Opus(encoder) => encoder.write(samples),
+        }
+    }
+
+    /// Samples dropped so far because sending fell behind
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        match &self.input {
+            SenderInput::Pcm(_) => self.shared.dropped.load(Ordering::Relaxed),
+            #[cfg(feature = "opus")]
+            SenderInput::Opus(encoder) => encoder.dropped(),
+        }
+    }
+
+    #[must_use]
+    pub fn packets_sent(&self) -> u64 {
+        self.shared.packets.load(Ordering::Relaxed)
+    }
+
+    /// Payload bytes sent so far
+    #[must_use]
+    pub fn octets_sent(&self) -> u64 {
+        self.shared.octets.load(Ordering::Relaxed)
+    }
+
+    /// What the receiver last reported about the stream, if it has
+    #[must_use]
+    pub fn last_report(&self) -> Option<ReceptionReport> {
+        *self.shared.report.lock()
+    }
+
+    /// Whether sending stopped, e.g. because the socket failed
+    #[must_use]
+    pub fn has_failed(&self) -> bool {
+        self.worker.as_ref().is_some_and(JoinHandle::is_finished)
+            && !self.shared.done.load(Ordering::Relaxed)
+    }
+
+    /// Sends what is still queued and stops.
+    ///
+    /// # Errors
+    /// Returns an error if sending or the Opus encoder failed.
+    pub fn finish(mut self) -> Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let encoded = match &mut self.input {
+            SenderInput::Pcm(_) => Ok(()),
+            // Closing the encoder ends its output, which ends the worker
+            #[cfg(feature = "opus")]
+            SenderInput::Opus(encoder) => RenderSink::finish(encoder),
+        };
+        self.shared.done.store(true, Ordering::Release);
+        if let Some(worker) = self.worker.take() {
+            worker
+                .join()
+                .map_err(|_| AudioEngineError::configuration("RTP sender thread panicked"))??;
+        }
+        encoded
+    }
+}
+
+impl Drop for RtpSender {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::error!("Sending RTP to {} failed: {e}", self.output.url);
+        }
+    }
+}
+
+impl fmt::Debug for RtpSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RtpSender")
+            .field("url", &self.output.url)
+            .field("codec", &self.output.codec)
+            .field("format", &self.format)
+            .field("ssrc", &self.ssrc)
+            .field("packets_sent", &self.packets_sent())
+            .field("dropped", &self.dropped())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RenderSink for RtpSender {
+    fn start(&mut self, format: AudioFormat) -> Result<()> {
+        if format == self.format {
+            Ok(())
+        } else {
+            Err(AudioEngineError::configuration(format!(
+                "RTP sender expects {:?}, got {format:?}",
+                self.format
+            )))
+        }
+    }
+
+    /// Waits for room rather than dropping; render with
+    /// [`RenderPacing::RealTime`](crate::engine::RenderPacing::RealTime) so
+    /// the packets go out as fast as they play
+    fn write(&mut self, samples: &[Sample]) -> Result<()> {
+        let mut remaining = samples;
+        while !remaining.is_empty() {
+            if self.has_failed() {
+                return Err(AudioEngineError::NetworkConnection {
+                    message: format!("sending RTP to {} stopped", self.output.url),
+                });
+            }
+            let pushed = match &mut self.input {
+                SenderInput::Pcm(buffer) => buffer.push_slice(remaining),
+                #[cfg(feature = "opus")]
+                SenderInput::Opus(encoder) => {
+                    return RenderSink::write(encoder, remaining);
+                }
+            };
+            if pushed == 0 {
+                thread::sleep(IDLE_WAIT);
+            }
+            remaining = &remaining[pushed..];
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.close()
+    }
+
+    fn describe(&self) -> String {
+        self.output.url.to_string()
+    }
+}
+
+/// Sends packets and reports.
+struct Worker {
+    socket: UdpSocket,
+    rtcp: UdpSocket,
+    header: RtpHeader,
+    source: PacketSource,
+    shared: Arc<Shared>,
+    settings: RtpSettings,
+    clock_rate: u32,
+    started: Instant,
+    /// RTP timestamp of the first packet
+    first_timestamp: u32,
+    next_report: Instant,
+    packet: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl Worker {
+    fn run(mut self) -> Result<()> {
+        self.first_timestamp = self.header.timestamp;
+        loop {
+            self.exchange_reports();
+            let Some(frames) = next_payload(&mut self.source, &self.shared, &mut self.payload)?
+            else {
+                return Ok(());
+            };
+            if frames == 0 {
+                thread::sleep(IDLE_WAIT);
+                continue;
+            }
+            self.packet.clear();
+            self.header.write(&mut self.packet);
+            self.packet.extend_from_slice(&self.payload);
+            if let Err(e) = self.socket.send(&self.packet) {
+                // The receiver not listening yet isn't fatal
+                if e.kind() != std::io::ErrorKind::ConnectionRefused {
+                    return Err(e.into());
+                }
+            }
+            self.shared.packets.fetch_add(1, Ordering::Relaxed);
+            self.shared
+                .octets
+                .fetch_add(self.payload.len() as u64, Ordering::Relaxed);
+            self.header.marker = false;
+            self.header.sequence = self.header.sequence.wrapping_add(1);
+            self.header.timestamp = self.header.timestamp.wrapping_add(frames);
+        }
+    }
+
+    /// Sends a sender report when one is due and takes in receiver
+    /// reports.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn exchange_reports(&mut self) {
+        let mut datagram = [0; 1500];
+        while let Ok(len) = self.rtcp.recv(&mut datagram) {
+            let report = RtcpReports::parse(&datagram[..len]).and_then(|reports| {
+                reports
+                    .reception
+                    .into_iter()
+                    .find(|report| report.ssrc == self.header.ssrc)
+            });
+            if let Some(report) = report {
+                *self.shared.report.lock() = Some(report);
+            }
+        }
+
+        let now = Instant::now();
+        if now < self.next_report {
+            return;
+        }
+        self.next_report = now + self.settings.rtcp_interval;
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        let report = SenderReport {
+            ssrc: self.header.ssrc,
+            ntp_timestamp: ntp_now(),
+            // Where the stream's clock is now, taking it to run in real time
+            rtp_timestamp: self
+                .first_timestamp
+                .wrapping_add((elapsed * f64::from(self.clock_rate)) as u64 as u32),
+            packets: self.shared.packets.load(Ordering::Relaxed) as u32,
+            octets: self.shared.octets.load(Ordering::Relaxed) as u32,
+        };
+        let mut compound = Vec::with_capacity(64);
+        report.write(&mut compound);
+        write_source_description(self.header.ssrc, CNAME, &mut compound);
+        let _ = self.rtcp.send(&compound);
+    }
+}
+
+/// Fills `payload` with the next packet's payload, returning its length
+/// in frames: zero if there is nothing to send yet, `None` at the end.
+fn next_payload(
+    source: &mut PacketSource,
+    shared: &Shared,
+    payload: &mut Vec<u8>,
+) -> Result<Option<u32>> {
+    payload.clear();
+    match source {
+        PacketSource::Pcm {
+            buffer,
+            block,
+            channels,
+            codec,
+        } => {
+            // Read before the buffer so nothing pushed before `done` is missed
+            let done = shared.done.load(Ordering::Acquire);
+            if buffer.slots() < block.len() && !done {
+                return Ok(Some(0));
+            }
+            let count = buffer.pop_slice(block);
+            if count == 0 {
+                return Ok(None);
+            }
+            let depth = bit_depth(*codec);
+            for &sample in &block[..count] {
+                write_ordered(payload, sample, depth, ByteOrder::Big)?;
+            }
+            Ok(Some(u32::try_from(count / *channels).unwrap_or(u32::MAX)))
+        }
+        #[cfg(feature = "opus")]
+        PacketSource::Opus {
+            packets,
+            headers_left,
+            frames,
+        } => loop {
+            let Some(packet) = packets.next_packet()? else {
+                return Ok(None);
+            };
+            if *headers_left > 0 {
+                *headers_left -= 1;
+                continue;
+            }
+            payload.extend_from_slice(&packet);
+            return Ok(Some(*frames));
+        },
+    }
+}
+
+/// A socket on any local address of `destination`'s family
+fn bind_any(destination: SocketAddr) -> Result<UdpSocket> {
+    let local: SocketAddr = if destination.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    Ok(UdpSocket::bind(local)?)
+}
@@ -0,0 +1,544 @@
+//! Polyphonic playback of triggered samples
+//!
+//! A [`Sampler`] keeps short samples decoded in memory and a
+//! [`SamplerPlayer`] plays them on trigger, several at once. The split
+//! follows the playlist's: the sampler lives on the control thread, loads
+//! samples and sends triggers; the player is a graph [`Source`] on the
+//! audio thread.
+//!
+//! The player has a fixed pool of voices, allocated up front, so a
+//! trigger never allocates. Each voice plays one sample with its own gain,
+//! pan and pitch. When every voice is busy, the [`VoiceStealing`] policy
+//! decides which one makes way; the stolen voice fades out quickly rather
+//! than cutting off.
+//!
+//! Samples are shared with the player, never copied, and only ever freed
+//! on the control thread: an unloaded sample is handed back and dropped
+//! the next time the sampler is used.
+
+use std::path::Path;
+
+use crate::arrangement::ClipSource;
+use crate::channel::{
+    ControlReceiver, ControlSender, RealtimeReceiver, RealtimeSender, control_channel,
+    feedback_channel,
+};
+use crate::error::{AudioEngineError, Result};
+use crate::graph::Source;
+use crate::types::{ChannelCount, Gain, Pan, Sample, SampleRate};
+
+/// Messages that can be waiting for the player
+const MESSAGE_CAPACITY: usize = 256;
+
+/// A sample slot in a [`Sampler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SampleId(usize);
+
+impl SampleId {
+    #[must_use]
+    pub const fn value(self) -> usize {
+        self.0
+    }
+}
+
+/// One triggered playback of a sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoiceId(u64);
+
+impl VoiceId {
+    #[must_use]
+    pub const fn value(self) -> u64 {
+        self.0
+    }
+}
+
+/// Which voice makes way for a trigger when all of them are busy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoiceStealing {
+    /// The voice that started first
+    #[default]
+    Oldest,
+    /// The voice with the lowest gain
+    Quietest,
+    /// None: the trigger is dropped
+    Ignore,
+}
+
+/// Sizes a [`Sampler`]'s pools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplerSettings {
+    /// Voices that can play at once
+    pub voices: usize,
+    /// Samples that can be loaded at once
+    pub slots: usize,
+    pub stealing: VoiceStealing,
+    /// Fade out time of stolen and stopped voices
+    pub release_ms: u32,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            voices: 16,
+            slots: 64,
+            stealing: VoiceStealing::Oldest,
+            release_ms: 5,
+        }
+    }
+}
+
+impl SamplerSettings {
+    #[must_use]
+    pub const fn with_voices(mut self, voices: usize) -> Self {
+        self.voices = voices;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_slots(mut self, slots: usize) -> Self {
+        self.slots = slots;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_stealing(mut self, stealing: VoiceStealing) -> Self {
+        self.stealing = stealing;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_release_ms(mut self, millis: u32) -> Self {
+        self.release_ms = millis;
+        self
+    }
+}
+
+/// How a triggered voice plays its sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trigger {
+    pub gain: Gain,
+    pub pan: Pan,
+    /// Transposition in semitones; the sample plays faster or slower
+    pub pitch: f32,
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Self {
+            gain: Gain::UNITY,
+            pan: Pan::CENTER,
+            pitch: 0.0,
+        }
+    }
+}
+
+impl Trigger {
+    #[must_use]
+    pub const fn with_gain(mut self, gain: Gain) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_pan(mut self, pan: Pan) -> Self {
+        self.pan = pan;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_pitch(mut self, semitones: f32) -> Self {
+        self.pitch = semitones;
+        self
+    }
+}
+
+#[derive(Debug)]
+enum SamplerMessage {
+    Load {
+        slot: usize,
+        source: ClipSource,
+    },
+    Unload(usize),
+    Trigger {
+        voice: VoiceId,
+        slot: usize,
+        trigger: Trigger,
+    },
+    Stop(VoiceId),
+    StopAll,
+}
+
+/// Control side of a sampler: the loaded samples and the triggers.
+#[derive(Debug)]
+pub struct Sampler {
+    settings: SamplerSettings,
+    slots: Vec<Option<ClipSource>>,
+    next_voice: u64,
+    messages: ControlSender<SamplerMessage>,
+    returned: ControlReceiver<ClipSource>,
+}
+
+impl Sampler {
+    /// Creates an empty sampler and the player that plays it at
+    /// `sample_rate` into `channels`. Samples at other rates are resampled
+    /// as they play; mono samples play on every channel.
+    #[must_use]
+    pub fn new(
+        sample_rate: SampleRate,
+        channels: ChannelCount,
+        settings: SamplerSettings,
+    ) -> (Self, SamplerPlayer) {
+        let (messages, message_receiver) = control_channel(MESSAGE_CAPACITY);
+        // Each message gives back at most one sample
+        let (give_back, returned) = feedback_channel(MESSAGE_CAPACITY + settings.slots);
+        let voices = settings.voices.max(1);
+        let sampler = Self {
+            settings,
+            slots: vec![None; settings.slots],
+            next_voice: 0,
+            messages,
+            returned,
+        };
+        let player = SamplerPlayer {
+            sample_rate,
+            stealing: settings.stealing,
+            release_ms: settings.release_ms,
+            release_frames: sample_rate.samples_for_milliseconds(settings.release_ms),
+            slots: vec![None; settings.slots],
+            voices: Vec::with_capacity(voices),
+            releasing: Vec::with_capacity(voices),
+            frame: vec![0.0; channels.count_usize()],
+            clock: 0,
+            messages: message_receiver,
+            returned: give_back,
+        };
+        (sampler, player)
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> SamplerSettings {
+        self.settings
+    }
+
+    /// Loads a sample into the first free slot.
+    ///
+    /// # Errors
+    /// Returns an error if every slot is taken, the sample is empty, or
+    /// the message queue is full.
+    pub fn load(&mut self, source: ClipSource) -> Result<SampleId> {
+        self.collect();
+        if source.frames() == 0 {
+            return Err(AudioEngineError::configuration(format!(
+                "sample {} has no audio",
+                source.name()
+            )));
+        }
+        let slot = self.slots.iter().position(Option::is_none).ok_or_else(|| {
+            AudioEngineError::configuration(format!(
+                "all {} sampler slots are taken",
+                self.slots.len()
+            ))
+        })?;
+        self.messages.try_send(SamplerMessage::Load {
+            slot,
+            source: source.clone(),
+        })?;
+        self.slots[slot] = Some(source);
+        Ok(SampleId(slot))
+    }
+
+    /// Decodes a whole WAV file and loads it.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read, or see
+    /// [`load`](Self::load).
+    pub fn load_wav(&mut self, path: impl AsRef<Path>) -> Result<SampleId> {
+        self.load(ClipSource::load_wav(path)?)
+    }
+
+    /// Unloads a sample. Voices playing it stop at once.
+    ///
+    /// # Errors
+    /// Returns an error if the message queue is full.
+    pub fn unload(&mut self, id: SampleId) -> Result<()> {
+        self.collect();
+        if self.sample(id).is_some() {
+            self.messages.try_send(SamplerMessage::Unload(id.0))?;
+            self.slots[id.0] = None;
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn sample(&self, id: SampleId) -> Option<&ClipSource> {
+        self.slots.get(id.0).and_then(Option::as_ref)
+    }
+
+    /// Plays a loaded sample on a new voice.
+    ///
+    /// # Errors
+    /// Returns an error if no sample is loaded in the slot or the message
+    /// queue is full.
+    pub fn trigger(&mut self, id: SampleId, trigger: Trigger) -> Result<VoiceId> {
+        if self.sample(id).is_none() {
+            return Err(AudioEngineError::configuration(format!(
+                "no sample loaded in slot {}",
+                id.0
+            )));
+        }
+        let voice = VoiceId(self.next_voice);
+        self.messages.try_send(SamplerMessage::Trigger {
+            voice,
+            slot: id.0,
+            trigger,
+        })?;
+        self.next_voice += 1;
+        Ok(voice)
+    }
+
+    /// Fades out a voice, if it is still playing.
+    ///
+    /// # Errors
+    /// Returns an error if the message queue is full.
+    pub fn stop(&self, voice: VoiceId) -> Result<()> {
+        self.messages.try_send(SamplerMessage::Stop(voice))
+    }
+
+    /// Fades out every voice.
+    ///
+    /// # Errors
+    /// Returns an error if the message queue is full.
+    pub fn stop_all(&self) -> Result<()> {
+        self.messages.try_send(SamplerMessage::StopAll)
+    }
+
+    /// Frees the samples the player has handed back.
+    pub fn collect(&mut self) {
+        while self.returned.try_recv().is_some() {}
+    }
+}
+
+/// A sample playing.
+#[derive(Debug, Clone, Copy)]
+struct Voice {
+    id: VoiceId,
+    slot: usize,
+    /// Read position in source frames
+    position: f64,
+    /// Source frames per output frame
+    step: f64,
+    gain: f32,
+    left: f32,
+    right: f32,
+    /// When the voice started, for stealing the oldest
+    started: u64,
+    /// Frames left of the fade out, once released
+    release: u32,
+}
+
+impl Voice {
+    const fn loudness(&self) -> f32 {
+        self.gain * self.left.max(self.right)
+    }
+
+    /// Adds the voice to `output`. Returns false once it has finished.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn render(
+        &mut self,
+        source: &ClipSource,
+        output: &mut [Sample],
+        channels: usize,
+        release_frames: u32,
+        frame: &mut [f32],
+    ) -> bool {
+        let source_channels = source.format().channels.count_usize();
+        let samples = source.samples();
+        let frames = samples.len() / source_channels;
+        for out in output.chunks_exact_mut(channels) {
+            let index = self.position as usize;
+            if index >= frames || self.release == 0 {
+                return false;
+            }
+            let fraction = (self.position - index as f64) as f32;
+            for (channel, value) in frame.iter_mut().enumerate() {
+                let channel = channel.min(source_channels - 1);
+                let current = samples[index * source_channels + channel].value();
+                let next = samples
+                    .get((index + 1) * source_channels + channel)
+                    .map_or(0.0, |sample| sample.value());
+                *value = (next - current).mul_add(fraction, current);
+            }
+            let mut gain = self.gain;
+            if self.release < u32::MAX {
+                gain *= self.release as f32 / release_frames.max(1) as f32;
+                self.release = self.release.saturating_sub(1);
+            }
+            if channels == 2 {
+                out[0] = Sample::new((frame[0] * self.left).mul_add(gain, out[0].value()));
+                out[1] = Sample::new((frame[1] * self.right).mul_add(gain, out[1].value()));
+            } else {
+                for (channel, out) in out.iter_mut().enumerate() {
+                    let value = frame[channel.min(frame.len() - 1)];
+                    *out = Sample::new(value.mul_add(gain, out.value()));
+                }
+            }
+            self.position += self.step;
+        }
+        true
+    }
+}
+
+/// Audio thread side of a [`Sampler`].
+#[derive(Debug)]
+pub struct SamplerPlayer {
+    sample_rate: SampleRate,
+    stealing: VoiceStealing,
+    release_ms: u32,
+    release_frames: u32,
+    slots: Vec<Option<ClipSource>>,
+    voices: Vec<Voice>,
+    /// Stolen and stopped voices fading out
+    releasing: Vec<Voice>,
+    /// One frame read from a source, per output channel
+    frame: Vec<f32>,
+    /// Voices started so far
+    clock: u64,
+    messages: RealtimeReceiver<SamplerMessage>,
+    returned: RealtimeSender<ClipSource>,
+}
+
+impl SamplerPlayer {
+    fn receive(&mut self) {
+        while let Some(message) = self.messages.try_recv() {
+            match message {
+                SamplerMessage::Load { slot, source } => {
+                    let previous = self.slots[slot].replace(source);
+                    self.unload(slot, previous);
+                }
+                SamplerMessage::Unload(slot) => {
+                    let previous = self.slots[slot].take();
+                    self.unload(slot, previous);
+                }
+                SamplerMessage::Trigger {
+                    voice,
+                    slot,
+                    trigger,
+                } => self.start(voice, slot, trigger),
+                SamplerMessage::Stop(id) => {
+                    if let Some(index) = self.voices.iter().position(|voice| voice.id == id) {
+                        let voice = self.voices.swap_remove(index);
+                        self.release(voice);
+                    }
+                }
+                SamplerMessage::StopAll => {
+                    while let Some(voice) = self.voices.pop() {
+                        self.release(voice);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Silences the voices of a slot and hands its old sample back.
+    fn unload(&mut self, slot: usize, previous: Option<ClipSource>) {
+        self.voices.retain(|voice| voice.slot != slot);
+        self.releasing.retain(|voice| voice.slot != slot);
+        if let Some(previous) = previous {
+            let _ = self.returned.try_send(previous);
+        }
+    }
+
+    fn start(&mut self, id: VoiceId, slot: usize, trigger: Trigger) {
+        let Some(source) = &self.slots[slot] else {
+            return;
+        };
+        let rate =
+            f64::from(source.format().sample_rate.as_hz()) / f64::from(self.sample_rate.as_hz());
+        let (left, right) = trigger.pan.gains();
+        let voice = Voice {
+            id,
+            slot,
+            position: 0.0,
+            step: f64::from(trigger.pitch / 12.0).exp2() * rate,
+            gain: trigger.gain.as_linear(),
+            left: left.as_linear(),
+            right: right.as_linear(),
+            started: self.clock,
+            release: u32::MAX,
+        };
+        self.clock += 1;
+        if self.voices.len() == self.voices.capacity() {
+            let victim = match self.stealing {
+                VoiceStealing::Oldest => self
+                    .voices
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, voice)| voice.started),
+                VoiceStealing::Quietest => self
+                    .voices
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| a.loudness().total_cmp(&b.loudness())),
+                VoiceStealing::Ignore => None,
+            };
+            let Some((index, _)) = victim else {
+                return;
+            };
+            let stolen = self.voices.swap_remove(index);
+            self.release(stolen);
+        }
+        self.voices.push(voice);
+    }
+
+    /// Moves a voice over to fade out, if there is room; otherwise it
+    /// stops at once.
+    fn release(&mut self, mut voice: Voice) {
+        if self.release_frames > 0 && self.releasing.len() < self.releasing.capacity() {
+            voice.release = voice.release.min(self.release_frames);
+            self.releasing.push(voice);
+        }
+    }
+}
+
+impl Source for SamplerPlayer {
+    fn initialize(&mut self, sample_rate: SampleRate, channels: ChannelCount) {
+        self.sample_rate = sample_rate;
+        self.release_frames = sample_rate.samples_for_milliseconds(self.release_ms);
+        self.frame = vec![0.0; channels.count_usize()];
+    }
+
+    fn reset(&mut self) {
+        self.voices.clear();
+        self.releasing.clear();
+    }
+
+    fn render(&mut self, output: &mut [Sample], channels: ChannelCount) {
+        self.receive();
+        output.fill(Sample::SILENCE);
+        let channel_count = channels.count_usize();
+        if self.frame.len() != channel_count {
+            // Not initialized for this layout; play nothing rather than
+            // allocate here
+            return;
+        }
+        let (slots, frame, release_frames) = (&self.slots, &mut self.frame, self.release_frames);
+        let mut play = |voice: &mut Voice| {
+            slots[voice.slot].as_ref().is_some_and(|source| {
+                voice.render(source, output, channel_count, release_frames, frame)
+            })
+        };
+        self.voices.retain_mut(&mut play);
+        self.releasing.retain_mut(&mut play);
+    }
+
+    fn memory_bytes(&self) -> usize {
+        size_of_val(self)
+            + self.slots.capacity() * size_of::<Option<ClipSource>>()
+            + (self.voices.capacity() + self.releasing.capacity()) * size_of::<Voice>()
+            + self.frame.capacity() * size_of::<f32>()
+    }
+}
@@ -0,0 +1,242 @@
+//! SRT and RIST contribution streams
+//!
+//! An [`SrtSender`] sends a [`NetworkOutput`] with an `srt://` or
+//! `rist://` url to a broadcast contribution link: a studio, a playout
+//! centre or a cloud gateway. Both protocols carry an MPEG transport stream
+//! over UDP and retransmit lost packets within a latency budget, so they
+//! hold up over the public internet where RTP wouldn't. The sender hands
+//! the audio to `ffmpeg`, built with libsrt or librist, which encodes and
+//! muxes it and runs the connection.
+//!
+//! The output's codec picks what is carried: MP3, AAC or Opus at the
+//! output's bitrate, or for [`StreamCodec::L16`] and [`StreamCodec::L24`]
+//! uncompressed PCM as SMPTE 302M, the usual for contribution, which needs
+//! 48 kHz and an even number of channels.
+//!
+//! [`SrtSettings`] on the output set up the link:
+//!
+//! - [`mode`](SrtSettings::mode): [`SrtMode::Caller`] connects to a
+//!   listening receiver, [`SrtMode::Listener`] waits on the url's address
+//!   for the receiver to connect, and [`SrtMode::Rendezvous`] (SRT only)
+//!   has both sides connect at once through firewalls.
+//! - [`passphrase`](SrtSettings::passphrase): encrypts the stream with AES
+//!   at the [`key_length`](SrtSettings::key_length); RIST calls it the
+//!   secret.
+//! - [`latency`](SrtSettings::latency): how long the receiver holds packets
+//!   for retransmission, usually 3 to 4 times the round trip; RIST calls it
+//!   the buffer size.
+//!
+//! Further options in the url's query, such as `streamid` or `pkt_size`,
+//! are left for ffmpeg, and win over the settings.
+//!
+//! Sending needs the `srt` feature, and at run time `ffmpeg` on the `PATH`
+//! with the protocol enabled.
+//!
+//! [`NetworkOutput`]: crate::io::NetworkOutput
+//! [`StreamCodec::L16`]: crate::io::output::StreamCodec::L16
+//! [`StreamCodec::L24`]: crate::io::output::StreamCodec::L24
+
+#[cfg(feature = "srt")]
+mod sender;
+
+#[cfg(feature = "srt")]
+pub use sender::SrtSender;
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::error::{AudioEngineError, Result};
+use crate::types::{NetworkProtocol, StreamUrl};
+
+/// Shortest and longest passphrase SRT accepts
+const PASSPHRASE_CHARS: std::ops::RangeInclusive<usize> = 10..=79;
+
+/// Who opens the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SrtMode {
+    /// Connects to a receiver listening at the url
+    #[default]
+    Caller,
+    /// Listens at the url for the receiver to connect
+    Listener,
+    /// Both sides connect to each other at once, which gets through
+    /// firewalls on both ends; SRT only
+    Rendezvous,
+}
+
+impl SrtMode {
+    /// Name of the mode in SRT urls
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Caller => "caller",
+            Self::Listener => "listener",
+            Self::Rendezvous => "rendezvous",
+        }
+    }
+}
+
+impl fmt::Display for SrtMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// AES key length of an encrypted stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SrtKeyLength {
+    #[default]
+    Aes128,
+    /// SRT only
+    Aes192,
+    Aes256,
+}
+
+impl SrtKeyLength {
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        match self {
+            Self::Aes128 => 128,
+            Self::Aes192 => 192,
+            Self::Aes256 => 256,
+        }
+    }
+}
+
+/// Connection, encryption and latency settings of an SRT or RIST stream.
+/// The passphrase is kept out of `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SrtSettings {
+    pub mode: SrtMode,
+    /// Encrypts the stream when set; SRT wants 10 to 79 characters
+    pub passphrase: Option<String>,
+    pub key_length: SrtKeyLength,
+    /// How long the receiver holds packets to retransmit lost ones,
+    /// 120 ms by default as in SRT
+    pub latency: Duration,
+}
+
+impl Default for SrtSettings {
+    fn default() -> Self {
+        Self {
+            mode: SrtMode::Caller,
+            passphrase: None,
+            key_length: SrtKeyLength::Aes128,
+            latency: Duration::from_millis(120),
+        }
+    }
+}
+
+impl fmt::Debug for SrtSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SrtSettings")
+            .field("mode", &self.mode)
+            .field("encrypted", &self.passphrase.is_some())
+            .field("key_length", &self.key_length)
+            .field("latency", &self.latency)
+            .finish()
+    }
+}
+
+impl SrtSettings {
+    #[must_use]
+    pub const fn with_mode(mut self, mode: SrtMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Encrypts the stream with `passphrase`.
+    #[must_use]
+    pub fn with_passphrase(mut self, passphrase: impl Into<String>) -> Self {
+        self.passphrase = Some(passphrase.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn with_key_length(mut self, key_length: SrtKeyLength) -> Self {
+        self.key_length = key_length;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Checks the settings suit `protocol`.
+    ///
+    /// # Errors
+    /// Returns an error if `protocol` is neither SRT nor RIST, an SRT
+    /// passphrase is too short or long, or RIST is asked for rendezvous
+    /// mode or 192 bit keys.
+    pub fn validate(&self, protocol: NetworkProtocol) -> Result<()> {
+        match protocol {
+            NetworkProtocol::SRT => {
+                if let Some(passphrase) = &self.passphrase
+                    && !PASSPHRASE_CHARS.contains(&passphrase.chars().count())
+                {
+                    return Err(AudioEngineError::configuration(format!(
+                        "SRT passphrases are {} to {} characters long",
+                        PASSPHRASE_CHARS.start(),
+                        PASSPHRASE_CHARS.end()
+                    )));
+                }
+                Ok(())
+            }
+            NetworkProtocol::RIST => {
+                if self.mode == SrtMode::Rendezvous {
+                    return Err(AudioEngineError::configuration(
+                        "RIST has no rendezvous mode; use caller or listener",
+                    ));
+                }
+                if self.passphrase.is_some() && self.key_length == SrtKeyLength::Aes192 {
+                    return Err(AudioEngineError::configuration(
+                        "RIST encrypts with 128 or 256 bit keys, not 192",
+                    ));
+                }
+                Ok(())
+            }
+            other => Err(AudioEngineError::configuration(format!(
+                "{other} isn't SRT or RIST"
+            ))),
+        }
+    }
+
+    /// The ffmpeg options that set up the link, followed by the url to
+    /// send to.
+    ///
+    /// # Errors
+    /// Returns an error if the settings don't suit the url's protocol; see
+    /// [`validate`](Self::validate).
+    pub fn output_args(&self, url: &StreamUrl) -> Result<Vec<String>> {
+        let protocol = url.protocol();
+        self.validate(protocol)?;
+        let mut args = Vec::new();
+        let mut target = url.as_str().to_string();
+        if protocol == NetworkProtocol::SRT {
+            args.extend(["-mode".to_string(), self.mode.to_string()]);
+            // ffmpeg takes the latency in microseconds
+            args.extend(["-latency".to_string(), self.latency.as_micros().to_string()]);
+            if let Some(passphrase) = &self.passphrase {
+                args.extend(["-passphrase".to_string(), passphrase.clone()]);
+                let bytes = self.key_length.bits() / 8;
+                args.extend(["-pbkeylen".to_string(), bytes.to_string()]);
+            }
+        } else {
+            let millis = self.latency.as_millis().to_string();
+            args.extend(["-buffer_size".to_string(), millis]);
+            if let Some(passphrase) = &self.passphrase {
+                args.extend(["-secret".to_string(), passphrase.clone()]);
+                let bits = self.key_length.bits().to_string();
+                args.extend(["-encryption".to_string(), bits]);
+            }
+            // librist listens on addresses marked with an @
+            if self.mode == SrtMode::Listener && !target.starts_with("rist://@") {
+                target.insert(target.find("://").unwrap_or(0) + 3, '@');
+            }
+        }
+        args.push(target);
+        Ok(args)
+    }
+}
@@ -0,0 +1,222 @@
+//! Sends a stream through ffmpeg's SRT and RIST protocols
+
+use std::fmt;
+
+use crate::engine::RenderSink;
+use crate::error::{AudioEngineError, Result};
+use crate::io::encoder::{EncoderOutput, EncoderProcess, ffmpeg_input_args, ffmpeg_opus_args};
+use crate::io::output::{NetworkOutput, StreamCodec};
+use crate::types::{AudioFormat, BitDepth, NetworkProtocol, Sample};
+
+/// Program that encodes, muxes and sends
+const SENDER_PROGRAM: &str = "ffmpeg";
+
+/// Sample rate SMPTE 302M carries
+const PCM_RATE_HZ: u32 = 48_000;
+
+/// Sends audio to an `srt://` or `rist://` [`NetworkOutput`].
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use audio_engine::io::NetworkOutput;
+/// use audio_engine::io::output::StreamCodec;
+/// use audio_engine::io::srt::{SrtMode, SrtSender, SrtSettings};
+/// use audio_engine::types::{AudioFormat, Sample, StreamUrl};
+///
+/// let output = NetworkOutput::new(StreamUrl::parse("srt://studio.example.com:9000")?)
+///     .with_codec(StreamCodec::L24)
+///     .with_srt(
+///         SrtSettings::default()
+///             .with_mode(SrtMode::Caller)
+///             .with_passphrase("correct horse battery")
+///             .with_latency(Duration::from_millis(250)),
+///     );
+/// let mut sender = SrtSender::connect(&output, AudioFormat::default())?;
+/// sender.write(&[Sample::SILENCE; 960]);
+/// # Ok::<(), audio_engine::error::AudioEngineError>(())
+/// ```
+pub struct SrtSender {
+    output: NetworkOutput,
+    format: AudioFormat,
+    process: EncoderProcess,
+}
+
+impl SrtSender {
+    /// Starts sending to `output`'s url, with samples in `format`.
+    ///
+    /// In caller mode a receiver that isn't there shows up soon after as
+    /// [`has_failed`](Self::has_failed), once ffmpeg gives up connecting.
+    ///
+    /// # Errors
+    /// Returns an error if the url isn't an SRT or RIST one, the settings
+    /// don't suit it (see [`SrtSettings::validate`]), the codec can't carry
+    /// `format`, or `ffmpeg` can't be started.
+    ///
+    /// [`SrtSettings::validate`]: super::SrtSettings::validate
+    pub fn connect(output: &NetworkOutput, format: AudioFormat) -> Result<Self> {
+        let protocol = output.url.protocol();
+        if !matches!(protocol, NetworkProtocol::SRT | NetworkProtocol::RIST) {
+            return Err(AudioEngineError::configuration(format!(
+                "{} isn't an SRT or RIST url",
+                output.url
+            )));
+        }
+        let bit_depth = match output.codec {
+            StreamCodec::L24 => BitDepth::I24,
+            _ => BitDepth::I16,
+        };
+        let mut args = ffmpeg_input_args(format, bit_depth);
+        args.extend(codec_args(output, format)?);
+        args.extend(["-f", "mpegts"].map(str::to_string));
+        args.extend(output.srt.output_args(&output.url)?);
+
+        let (process, _) = EncoderProcess::spawn_with_depth(
+            SENDER_PROGRAM,
+            &args,
+            format,
+            &EncoderOutput::File,
+            bit_depth,
+        )?;
+        log::info!(
+            "Sending {} over {protocol} to {} as {}",
+            output.codec,
+            output.url,
+            output.srt.mode
+        );
+        Ok(Self {
+            output: output.clone(),
+            format,
+            process,
+        })
+    }
+
+    #[must_use]
+    pub const fn output(&self) -> &NetworkOutput {
+        &self.output
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Queues interleaved samples to send without blocking, returning how
+    /// many were queued. The rest are dropped.
+    pub fn write(&mut self, samples: &[Sample]) -> usize {
+        self.process.write(samples)
+    }
+
+    /// Samples dropped so far because sending fell behind
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.process.dropped()
+    }
+
+    /// Whether sending stopped, e.g. because the connection couldn't be
+    /// made or was lost for longer than the protocol rides out
+    #[must_use]
+    pub fn has_failed(&self) -> bool {
+        self.process.has_failed()
+    }
+
+    /// Sends what is still queued and closes the connection.
+    ///
+    /// # Errors
+    /// Returns an error if sending failed or ffmpeg exited unsuccessfully.
+    pub fn finish(mut self) -> Result<()> {
+        self.process.close()
+    }
+}
+
+/// Encoder options for `output`'s codec on input in `format`.
+fn codec_args(output: &NetworkOutput, format: AudioFormat) -> Result<Vec<String>> {
+    let bitrate = format!("{}k", output.audio_bitrate.as_kbps());
+    let args = match output.codec {
+        StreamCodec::Mp3 if format.channels.count() <= 2 => {
+            ["-c:a", "libmp3lame", "-b:a", bitrate.as_str()]
+                .map(str::to_string)
+                .into()
+        }
+        StreamCodec::Mp3 => {
+            return Err(AudioEngineError::configuration(format!(
+                "MP3 holds at most two channels, not {}",
+                format.channels.count()
+            )));
+        }
+        StreamCodec::Aac => ["-c:a", "aac", "-b:a", bitrate.as_str()]
+            .map(str::to_string)
+            .into(),
+        StreamCodec::Opus(settings) => {
+            ffmpeg_opus_args(settings.with_bitrate(output.audio_bitrate))
+        }
+        StreamCodec::L16 | StreamCodec::L24 => {
+            if format.sample_rate.as_hz() != PCM_RATE_HZ {
+                return Err(AudioEngineError::configuration(format!(
+                    "SMPTE 302M carries 48 kHz PCM, not {}",
+                    format.sample_rate
+                )));
+            }
+            if !format.channels.count().is_multiple_of(2) {
+                return Err(AudioEngineError::configuration(format!(
+                    "SMPTE 302M carries pairs of channels, not {}",
+                    format.channels.count()
+                )));
+            }
+            // ffmpeg counts its 302M encoder as experimental
+            ["-c:a", "s302m", "-strict", "-2"]
+                .map(str::to_string)
+                .into()
+        }
+    };
+    Ok(args)
+}
+
+impl Drop for SrtSender {
+    fn drop(&mut self) {
+        if let Err(e) = self.process.close() {
+            log::error!("Sending to {} failed: {e}", self.output.url);
+        }
+    }
+}
+
+impl fmt::Debug for SrtSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SrtSender")
+            .field("url", &self.output.url)
+            .field("codec", &self.output.codec)
+            .field("settings", &self.output.srt)
+            .field("format", &self.format)
+            .field("dropped", &self.dropped())
+            .field("failed", &self.has_failed())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RenderSink for SrtSender {
+    fn start(&mut self, format: AudioFormat) -> Result<()> {
+        if format == self.format {
+            Ok(())
+        } else {
+            Err(AudioEngineError::FormatMismatch {
+                expected: format!("{:?} {:?}", self.format.sample_rate, self.format.channels),
+                actual: format!("{:?} {:?}", format.sample_rate, format.channels),
+            })
+        }
+    }
+
+    /// Waits for room rather than dropping; render with
+    /// [`RenderPacing::RealTime`](crate::engine::RenderPacing::RealTime) so
+    /// the stream goes out as fast as it plays
+    fn write(&mut self, samples: &[Sample]) -> Result<()> {
+        self.process.write_all(samples)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.process.close()
+    }
+
+    fn describe(&self) -> String {
+        self.output.url.to_string()
+    }
+}
@@ -0,0 +1,510 @@
+//! Streaming file reader
+//!
+//! A [`FileStreamer`] plays a file too large to decode in the audio
+//! callback. Its worker thread reads the file ahead of playback into a
+//! [`RingBuffer`], and the audio thread only ever pops from that buffer, so
+//! [`read`](FileStreamer::read) never touches the disk or a decoder. When
+//! the worker falls behind, a read comes back short instead of blocking
+//! and is counted as an underrun.
+//!
+//! Seeks go to the worker over a control channel. Until it has moved, reads
+//! return nothing rather than audio from the old position; everything it
+//! buffered before the seek is dropped once it has.
+//!
+//! [`health`](FileStreamer::health) reports how much is buffered, so the
+//! engine can see an underrun coming while the buffer is still draining.
+
+use std::fmt;
+use std::io::{Read, Seek};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use flume::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+
+use crate::buffer::ring::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::error::{AudioEngineError, Result};
+use crate::io::pcm::PcmReader;
+use crate::io::wav::WavReader;
+use crate::types::{ChannelCount, Sample};
+
+/// Seek requests that can be queued for the worker
+const CONTROL_CAPACITY: usize = 16;
+
+/// How long the worker waits for a seek when there is nothing to read
+const IDLE_WAIT: Duration = Duration::from_millis(5);
+
+/// Where audio for a [`FileStreamer`] comes from, read front to back.
+pub trait StreamSource: Send + 'static {
+    fn channels(&self) -> ChannelCount;
+
+    /// Sample rate the samples are read at, in Hz
+    fn sample_rate_hz(&self) -> u32;
+
+    /// Reads the next interleaved samples into `out`, whole frames only,
+    /// returning how many were read; zero means the end of the source.
+    ///
+    /// # Errors
+    /// Returns an error if the source can't be read.
+    fn read_samples(&mut self, out: &mut [Sample]) -> Result<usize>;
+
+    /// Moves to a frame of the source.
+    ///
+    /// # Errors
+    /// Returns an error if the source can't be seeked.
+    fn seek_frame(&mut self, frame: u64) -> Result<()>;
+}
+
+impl<R: Read + Seek + Send + 'static> StreamSource for WavReader<R> {
+    fn channels(&self) -> ChannelCount {
+        self.format().channels
+    }
+
+    fn sample_rate_hz(&self) -> u32 {
+        self.format().sample_rate.as_hz()
+    }
+
+    fn read_samples(&mut self, out: &mut [Sample]) -> Result<usize> {
+        Self::read_samples(self, out)
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        Self::seek_frame(self, frame)
+    }
+}
+
+impl<R: Read + Seek + Send + 'static> StreamSource for PcmReader<R> {
+    fn channels(&self) -> ChannelCount {
+        self.format().channels
+    }
+
+    fn sample_rate_hz(&self) -> u32 {
+        self.format().sample_rate.as_hz()
+    }
+
+    fn read_samples(&mut self, out: &mut [Sample]) -> Result<usize> {
+        Self::read_samples(self, out)
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        Self::seek_frame(self, frame)
+    }
+}
+
+#[cfg(feature = "symphonia")]
+impl StreamSource for crate::io::decode::AudioFileDecoder {
+    fn channels(&self) -> ChannelCount {
+        Self::channels(self)
+    }
+
+    fn sample_rate_hz(&self) -> u32 {
+        Self::sample_rate_hz(self)
+    }
+
+    fn read_samples(&mut self, out: &mut [Sample]) -> Result<usize> {
+        Self::read_samples(self, out)
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        Self::seek_frame(self, frame)
+    }
+}
+
+#[cfg(feature = "opus")]
+impl StreamSource for crate::io::opus::OpusFileDecoder {
+    fn channels(&self) -> ChannelCount {
+        Self::channels(self)
+    }
+
+    fn sample_rate_hz(&self) -> u32 {
+        Self::sample_rate_hz(self)
+    }
+
+    fn read_samples(&mut self, out: &mut [Sample]) -> Result<usize> {
+        Self::read_samples(self, out)
+    }
+
+    fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        Self::seek_frame(self, frame)
+    }
+}
+
+/// Sizes a [`FileStreamer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamerSettings {
+    /// Frames buffered ahead of playback
+    pub buffer_frames: usize,
+    /// Frames the worker reads from the source at a time
+    pub chunk_frames: usize,
+    /// Buffered frames below which an underrun is considered close
+    pub low_water_frames: usize,
+    /// Go back to the start of the source at its end. Sources that loop by
+    /// themselves, like a decoder opened on a looping input, don't need it.
+    pub looping: bool,
+}
+
+impl Default for StreamerSettings {
+    fn default() -> Self {
+        Self {
+            buffer_frames: 65_536,
+            chunk_frames: 4096,
+            low_water_frames: 16_384,
+            looping: false,
+        }
+    }
+}
+
+impl StreamerSettings {
+    #[must_use]
+    pub const fn with_buffer_frames(mut self, frames: usize) -> Self {
+        self.buffer_frames = frames;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_chunk_frames(mut self, frames: usize) -> Self {
+        self.chunk_frames = frames;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_low_water(mut self, frames: usize) -> Self {
+        self.low_water_frames = frames;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_loop(mut self) -> Self {
+        self.looping = true;
+        self
+    }
+}
+
+/// Buffer state of a [`FileStreamer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StreamerHealth {
+    /// Frames ready to be read
+    pub buffered_frames: usize,
+    /// Frames the buffer holds when full
+    pub capacity_frames: usize,
+    /// Reads that came back short while the source still had audio
+    pub underruns: u64,
+    /// Chunks or seeks the source failed on
+    pub errors: u64,
+    /// A seek hasn't reached the worker yet
+    pub seeking: bool,
+    /// The source has ended and nothing more will be buffered
+    pub finished: bool,
+}
+
+impl StreamerHealth {
+    /// How full the buffer is, from 0 to 1
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn fill(&self) -> f32 {
+        if self.capacity_frames == 0 {
+            0.0
+        } else {
+            self.buffered_frames as f32 / self.capacity_frames as f32
+        }
+    }
+}
+
+enum Control {
+    Seek { frame: u64, epoch: u64 },
+}
+
+/// State the worker publishes to the reading side
+#[derive(Default)]
+struct Shared {
+    /// Samples pushed into the buffer since the start
+    pushed: AtomicU64,
+    /// Last seek the worker has carried out
+    applied_epoch: AtomicU64,
+    /// Value of `pushed` when that seek was carried out; everything before
+    /// it belongs to the old position
+    seek_mark: AtomicU64,
+    finished: AtomicBool,
+    errors: AtomicU64,
+}
+
+/// Plays a file through a prefetch buffer filled on a worker thread.
+pub struct FileStreamer {
+    channels: ChannelCount,
+    sample_rate: u32,
+    settings: StreamerSettings,
+    buffer: RingBufferReader<Sample>,
+    shared: Arc<Shared>,
+    control: Sender<Control>,
+    worker: Option<JoinHandle<()>>,
+    /// Samples popped or dropped since the start
+    popped: u64,
+    /// Last seek asked for
+    epoch: u64,
+    underruns: u64,
+}
+
+impl FileStreamer {
+    /// Streams a WAV file.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be opened or the worker thread
+    /// can't be started.
+    pub fn open_wav(path: impl AsRef<std::path::Path>, settings: StreamerSettings) -> Result<Self> {
+        Self::new(WavReader::open(path)?, settings)
+    }
+
+    /// Starts a worker thread buffering `source`.
+    ///
+    /// # Errors
+    /// Returns an error if the buffer or chunk size is zero, or the worker
+    /// thread can't be started.
+    pub fn new(source: impl StreamSource, settings: StreamerSettings) -> Result<Self> {
+        if settings.buffer_frames == 0 || settings.chunk_frames == 0 {
+            return Err(AudioEngineError::configuration(
+                "file streamer needs a non-zero buffer size and chunk size",
+            ));
+        }
+        let channels = source.channels();
+        let sample_rate = source.sample_rate_hz();
+        let (writer, buffer) = RingBuffer::new(settings.buffer_frames * channels.count_usize());
+        let shared = Arc::new(Shared::default());
+        let (control, receiver) = flume::bounded(CONTROL_CAPACITY);
+        let worker = Worker {
+            source,
+            settings,
+            buffer: writer,
+            shared: Arc::clone(&shared),
+            chunk: vec![Sample::SILENCE; settings.chunk_frames * channels.count_usize()],
+            read_since_start: false,
+        };
+        let worker = thread::Builder::new()
+            .name("file-streamer".to_string())
+            .spawn(move || worker.run(&receiver))?;
+        Ok(Self {
+            channels,
+            sample_rate,
+            settings,
+            buffer,
+            shared,
+            control,
+            worker: Some(worker),
+            popped: 0,
+            epoch: 0,
+            underruns: 0,
+        })
+    }
+
+    #[must_use]
+    pub const fn channels(&self) -> ChannelCount {
+        self.channels
+    }
+
+    /// Sample rate of the source in Hz
+    #[must_use]
+    pub const fn sample_rate_hz(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> StreamerSettings {
+        self.settings
+    }
+
+    /// Pops buffered samples into `out`, whole frames only, returning the
+    /// number of frames read. Never blocks: a short read while the source
+    /// still has audio counts as an underrun.
+    pub fn read(&mut self, out: &mut [Sample]) -> usize {
+        if !self.seek_done() {
+            return 0;
+        }
+        let channel_count = self.channels.count_usize();
+        let wanted = out.len() / channel_count * channel_count;
+        let available = self.buffer.slots() / channel_count * channel_count;
+        let count = self.buffer.pop_slice(&mut out[..wanted.min(available)]);
+        self.popped += count as u64;
+        if count < wanted && !self.shared.finished.load(Ordering::Acquire) {
+            self.underruns += 1;
+        }
+        count / channel_count
+    }
+
+    /// Asks the worker to move to a frame of the source. Reads return
+    /// nothing until it has.
+    pub fn seek(&mut self, frame: u64) {
+        self.epoch += 1;
+        let epoch = self.epoch;
+        if self
+            .control
+            .try_send(Control::Seek { frame, epoch })
+            .is_err()
+        {
+            log::warn!("File streamer control queue full, dropping seek to frame {frame}");
+            self.epoch -= 1;
+        }
+    }
+
+    /// Whether the source has ended and everything buffered has been read
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        let health = self.health();
+        health.finished && !health.seeking && self.buffer.is_empty()
+    }
+
+    /// Whether so little is buffered that an underrun is close
+    #[must_use]
+    pub fn is_low(&self) -> bool {
+        let health = self.health();
+        !health.finished && health.buffered_frames < self.settings.low_water_frames
+    }
+
+    #[must_use]
+    pub fn health(&self) -> StreamerHealth {
+        let channel_count = self.channels.count_usize();
+        let seeking = self.shared.applied_epoch.load(Ordering::Acquire) != self.epoch;
+        StreamerHealth {
+            buffered_frames: if seeking {
+                0
+            } else {
+                self.buffer.slots() / channel_count
+            },
+            capacity_frames: self.buffer.capacity() / channel_count,
+            underruns: self.underruns,
+            errors: self.shared.errors.load(Ordering::Relaxed),
+            seeking,
+            finished: self.shared.finished.load(Ordering::Acquire),
+        }
+    }
+
+    /// Drops what was buffered before the latest seek once the worker has
+    /// carried it out. Returns false while it hasn't.
+    fn seek_done(&mut self) -> bool {
+        if self.shared.applied_epoch.load(Ordering::Acquire) != self.epoch {
+            return false;
+        }
+        let mark = self.shared.seek_mark.load(Ordering::Acquire);
+        if self.popped < mark {
+            let stale = usize::try_from(mark - self.popped).unwrap_or(usize::MAX);
+            self.popped += self.buffer.discard(stale) as u64;
+        }
+        true
+    }
+}
+
+impl Drop for FileStreamer {
+    fn drop(&mut self) {
+        // Disconnect the worker so it finishes, then wait for it
+        let (disconnected, _) = flume::bounded(0);
+        self.control = disconnected;
+        if let Some(worker) = self.worker.take()
+            && worker.join().is_err()
+        {
+            log::error!("File streamer worker panicked");
+        }
+    }
+}
+
+impl fmt::Debug for FileStreamer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileStreamer")
+            .field("channels", &self.channels)
+            .field("sample_rate", &self.sample_rate)
+            .field("settings", &self.settings)
+            .field("health", &self.health())
+            .field("worker", &self.worker.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Worker thread state
+struct Worker<S> {
+    source: S,
+    settings: StreamerSettings,
+    buffer: RingBufferWriter<Sample>,
+    shared: Arc<Shared>,
+    chunk: Vec<Sample>,
+    /// Whether anything was read since the start of the source, so that
+    /// looping an empty source doesn't spin
+    read_since_start: bool,
+}
+
+impl<S: StreamSource> Worker<S> {
+    fn run(mut self, control: &Receiver<Control>) {
+        loop {
+            match control.try_recv() {
+                Ok(request) => {
+                    self.apply(&request);
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => return,
+                Err(TryRecvError::Empty) => {}
+            }
+            if self.fill() {
+                continue;
+            }
+            // Full or finished: wait for a seek or for room to free up
+            match control.recv_timeout(IDLE_WAIT) {
+                Ok(request) => self.apply(&request),
+                Err(RecvTimeoutError::Disconnected) => return,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+    }
+
+    fn apply(&mut self, request: &Control) {
+        match request {
+            &Control::Seek { frame, epoch } => {
+                if let Err(e) = self.source.seek_frame(frame) {
+                    self.shared.errors.fetch_add(1, Ordering::Relaxed);
+                    log::error!("File streamer failed to seek to frame {frame}: {e}");
+                }
+                self.read_since_start = false;
+                self.shared.finished.store(false, Ordering::Release);
+                let pushed = self.shared.pushed.load(Ordering::Relaxed);
+                self.shared.seek_mark.store(pushed, Ordering::Release);
+                self.shared.applied_epoch.store(epoch, Ordering::Release);
+            }
+        }
+    }
+
+    /// Reads a chunk into the buffer if there is room for one. Returns
+    /// false if there was nothing to do.
+    fn fill(&mut self) -> bool {
+        if self.shared.finished.load(Ordering::Acquire) {
+            return false;
+        }
+        let channel_count = self.source.channels().count_usize();
+        let room = self.buffer.slots() / channel_count * channel_count;
+        let count = room.min(self.chunk.len());
+        // Wait for a whole chunk's worth unless the buffer is smaller
+        if count < self.chunk.len().min(self.buffer.capacity()) {
+            return false;
+        }
+        match self.source.read_samples(&mut self.chunk[..count]) {
+            Ok(0) if self.settings.looping && self.read_since_start => {
+                self.read_since_start = false;
+                if let Err(e) = self.source.seek_frame(0) {
+                    self.shared.errors.fetch_add(1, Ordering::Relaxed);
+                    self.shared.finished.store(true, Ordering::Release);
+                    log::error!("File streamer failed to loop: {e}");
+                }
+            }
+            Ok(0) => self.shared.finished.store(true, Ordering::Release),
+            Ok(read) => {
+                self.read_since_start = true;
+                let pushed = self.buffer.push_slice(&self.chunk[..read]);
+                self.shared
+                    .pushed
+                    .fetch_add(pushed as u64, Ordering::Release);
+            }
+            Err(e) => {
+                self.shared.errors.fetch_add(1, Ordering::Relaxed);
+                self.shared.finished.store(true, Ordering::Release);
+                log::error!("File streamer failed to read: {e}");
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,613 @@
+//! WAV file writing
+//!
+//! [`WavWriter`] streams interleaved samples to a RIFF/WAVE file. The header
+//! is written up front with placeholder sizes and patched when the writer is
+//! finalized (or dropped). Broadcast Wave metadata ([`BroadcastInfo`]) goes
+//! between the `fmt ` and `data` chunks. Cue points are kept in memory and written as
+//! `cue ` and `LIST/adtl` label chunks after the sample data on finalize.
+//!
+//! [`WavReader`] reads PCM and float files (including
+//! `WAVE_FORMAT_EXTENSIBLE`) back as [`Sample`]s, and [`write_chunk`] adds or
+//! replaces a metadata chunk in an existing file without rewriting the audio.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::error::{AudioEngineError, Result};
+use crate::io::bwf::BroadcastInfo;
+use crate::io::pcm::{ByteOrder, PcmReader};
+use crate::types::{AudioFormat, BitDepth, ChannelCount, Sample, SampleRate};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Size of the RIFF header up to the start of the sample data, without
+/// metadata chunks
+const HEADER_LEN: u32 = 44;
+
+/// A labelled position in a WAV file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CuePoint {
+    /// Position in frames from the start of the data
+    pub position: u32,
+    pub label: String,
+}
+
+/// Streams interleaved samples into a WAV file.
+#[derive(Debug)]
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    format: AudioFormat,
+    /// Metadata chunks written between `fmt ` and `data`
+    metadata: Vec<u8>,
+    header_len: u32,
+    data_bytes: u32,
+    cues: Vec<CuePoint>,
+    finalized: bool,
+}
+
+impl WavWriter<BufWriter<File>> {
+    /// Creates (or truncates) a WAV file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be created or the header can't be written.
+    pub fn create(path: impl AsRef<Path>, format: AudioFormat) -> Result<Self> {
+        let file = File::create(path)?;
+        Self::new(BufWriter::new(file), format)
+    }
+
+    /// Creates (or truncates) a Broadcast Wave file at `path`, with the
+    /// `bext` and `iXML` chunks described by `info`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be created or the header can't be written.
+    pub fn create_broadcast(
+        path: impl AsRef<Path>,
+        format: AudioFormat,
+        info: &BroadcastInfo,
+    ) -> Result<Self> {
+        let file = File::create(path)?;
+        Self::new_broadcast(BufWriter::new(file), format, info)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Wraps a writer and writes the WAV header.
+    ///
+    /// # Errors
+    /// Returns an error if the header can't be written.
+    pub fn new(writer: W, format: AudioFormat) -> Result<Self> {
+        Self::with_metadata(writer, format, Vec::new())
+    }
+
+    /// Wraps a writer and writes a Broadcast Wave header with the `bext`
+    /// and `iXML` chunks described by `info`.
+    ///
+    /// # Errors
+    /// Returns an error if the header can't be written.
+    pub fn new_broadcast(writer: W, format: AudioFormat, info: &BroadcastInfo) -> Result<Self> {
+        Self::with_metadata(writer, format, info.chunks(format))
+    }
+
+    fn with_metadata(writer: W, format: AudioFormat, metadata: Vec<u8>) -> Result<Self> {
+        let header_len = u32::try_from(metadata.len())
+            .ok()
+            .and_then(|len| len.checked_add(HEADER_LEN))
+            .ok_or_else(|| AudioEngineError::configuration("WAV metadata too large"))?;
+        let mut wav = Self {
+            writer,
+            format,
+            metadata,
+            header_len,
+            data_bytes: 0,
+            cues: Vec::new(),
+            finalized: false,
+        };
+        wav.write_header(header_len - 8)?;
+        Ok(wav)
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Number of complete frames written so far
+    #[must_use]
+    pub fn frames_written(&self) -> u64 {
+        u64::from(self.data_bytes / self.format.frame_size())
+    }
+
+    /// Adds a cue point, written out on the next [`finalize`](Self::finalize).
+    pub fn add_cue(&mut self, position: u32, label: impl Into<String>) {
+        self.cues.push(CuePoint {
+            position,
+            label: label.into(),
+        });
+        self.finalized = false;
+    }
+
+    #[must_use]
+    pub fn cues(&self) -> &[CuePoint] {
+        &self.cues
+    }
+
+    fn write_header(&mut self, riff_size: u32) -> Result<()> {
+        let format = self.format;
+        let channels = u16::try_from(format.channels.count())
+            .map_err(|_| AudioEngineError::numeric_conversion("too many channels for WAV"))?;
+        let bits = u16::try_from(format.bit_depth.bits())
+            .map_err(|_| AudioEngineError::numeric_conversion("bit depth out of range"))?;
+        let block_align = u16::try_from(format.frame_size())
+            .map_err(|_| AudioEngineError::numeric_conversion("frame size out of range"))?;
+        let format_tag = if format.bit_depth.is_float() {
+            FORMAT_IEEE_FLOAT
+        } else {
+            FORMAT_PCM
+        };
+
+        let w = &mut self.writer;
+        w.write_all(b"RIFF")?;
+        w.write_all(&riff_size.to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+        w.write_all(b"fmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&format_tag.to_le_bytes())?;
+        w.write_all(&channels.to_le_bytes())?;
+        w.write_all(&format.sample_rate.as_hz().to_le_bytes())?;
+        w.write_all(&format.byte_rate().to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&bits.to_le_bytes())?;
+        w.write_all(&self.metadata)?;
+        w.write_all(b"data")?;
+        w.write_all(&self.data_bytes.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Appends interleaved samples, converting to the file's bit depth.
+    ///
+    /// # Errors
+    /// Returns an error on I/O failure or if the file would exceed the 4 GiB
+    /// RIFF limit.
+    pub fn write_samples(&mut self, samples: &[Sample]) -> Result<()> {
+        let bytes_per_sample = self.format.bit_depth.bytes_per_sample();
+        let added = u32::try_from(samples.len())
+            .ok()
+            .and_then(|len| len.checked_mul(bytes_per_sample))
+            .and_then(|bytes| bytes.checked_add(self.data_bytes))
+            .filter(|&total| total <= u32::MAX - self.header_len)
+            .ok_or_else(|| AudioEngineError::configuration("WAV file would exceed 4 GiB"))?;
+
+        for &sample in samples {
+            write_sample(&mut self.writer, sample, self.format.bit_depth)?;
+        }
+        self.data_bytes = added;
+        self.finalized = false;
+        Ok(())
+    }
+
+    /// Writes the cue chunks, patches the header sizes and flushes.
+    ///
+    /// The writer is left positioned at the end of the sample data, so
+    /// further writes are still allowed and replace the trailing chunks until
+    /// the next call.
+    ///
+    /// # Errors
+    /// Returns an error on I/O failure.
+    pub fn finalize(&mut self) -> Result<()> {
+        self.seek_to_data_end()?;
+        let mut trailer = Vec::new();
+        if self.data_bytes % 2 == 1 {
+            // RIFF chunks are word aligned
+            trailer.push(0);
+        }
+        self.append_cue_chunks(&mut trailer)?;
+        self.writer.write_all(&trailer)?;
+
+        let trailer_len = u32::try_from(trailer.len())
+            .map_err(|_| AudioEngineError::numeric_conversion("cue chunks too large"))?;
+        let riff_size = (self.header_len - 8 + self.data_bytes)
+            .checked_add(trailer_len)
+            .ok_or_else(|| AudioEngineError::configuration("WAV file would exceed 4 GiB"))?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header(riff_size)?;
+        self.seek_to_data_end()?;
+        self.writer.flush()?;
+        self.finalized = true;
+        Ok(())
+    }
+
+    fn seek_to_data_end(&mut self) -> Result<()> {
+        self.writer.seek(SeekFrom::Start(u64::from(
+            self.header_len + self.data_bytes,
+        )))?;
+        Ok(())
+    }
+
+    fn append_cue_chunks(&self, out: &mut Vec<u8>) -> Result<()> {
+        if self.cues.is_empty() {
+            return Ok(());
+        }
+        let count = u32::try_from(self.cues.len())
+            .map_err(|_| AudioEngineError::numeric_conversion("too many cue points"))?;
+
+        out.extend_from_slice(b"cue ");
+        out.extend_from_slice(&(4 + 24 * count).to_le_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+        for (id, cue) in (1u32..).zip(&self.cues) {
+            out.extend_from_slice(&id.to_le_bytes());
+            out.extend_from_slice(&cue.position.to_le_bytes());
+            out.extend_from_slice(b"data");
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&cue.position.to_le_bytes());
+        }
+
+        let mut labels = Vec::new();
+        labels.extend_from_slice(b"adtl");
+        for (id, cue) in (1u32..).zip(&self.cues) {
+            let text_len = u32::try_from(cue.label.len() + 1)
+                .map_err(|_| AudioEngineError::numeric_conversion("cue label too long"))?;
+            labels.extend_from_slice(b"labl");
+            labels.extend_from_slice(&(4 + text_len).to_le_bytes());
+            labels.extend_from_slice(&id.to_le_bytes());
+            labels.extend_from_slice(cue.label.as_bytes());
+            labels.push(0);
+            if text_len % 2 == 1 {
+                labels.push(0);
+            }
+        }
+        let labels_len = u32::try_from(labels.len())
+            .map_err(|_| AudioEngineError::numeric_conversion("cue labels too large"))?;
+        out.extend_from_slice(b"LIST");
+        out.extend_from_slice(&labels_len.to_le_bytes());
+        out.extend_from_slice(&labels);
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if !self.finalized
+            && let Err(e) = self.finalize()
+        {
+            log::error!("Failed to finalize WAV file: {e}");
+        }
+    }
+}
+
+/// Reads interleaved samples from a WAV file.
+#[derive(Debug)]
+pub struct WavReader<R: Read + Seek> {
+    pcm: PcmReader<R>,
+}
+
+impl WavReader<BufReader<File>> {
+    /// Opens the WAV file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read or isn't a supported WAV file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> WavReader<R> {
+    /// Parses the header and positions the reader at the first sample.
+    ///
+    /// # Errors
+    /// Returns an error if the stream isn't a WAV file, or its sample rate,
+    /// channel count or encoding isn't supported by the engine.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+            return Err(AudioEngineError::UnsupportedFormat {
+                format: "not a RIFF/WAVE file".to_string(),
+            });
+        }
+
+        let mut format = None;
+        loop {
+            let (id, size) = read_chunk_header(&mut reader)?;
+            match &id {
+                b"fmt " => {
+                    let mut fmt = vec![0u8; usize::try_from(size).unwrap_or(0)];
+                    reader.read_exact(&mut fmt)?;
+                    format = Some(parse_fmt(&fmt)?);
+                    if size % 2 == 1 {
+                        reader.seek(SeekFrom::Current(1))?;
+                    }
+                }
+                b"data" => {
+                    let format = format.ok_or_else(|| AudioEngineError::UnsupportedFormat {
+                        format: "WAV data chunk before fmt chunk".to_string(),
+                    })?;
+                    let data_start = reader.stream_position()?;
+                    // Streams that were never finalized carry a placeholder size
+                    let pcm = PcmReader::region(
+                        reader,
+                        format,
+                        ByteOrder::Little,
+                        data_start,
+                        u64::from(size),
+                    )?;
+                    return Ok(Self { pcm });
+                }
+                _ => {
+                    // Widened first: a size of u32::MAX plus its pad byte
+                    // overflows
+                    reader.seek(SeekFrom::Current(i64::from(size) + i64::from(size % 2)))?;
+                }
+            }
+        }
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.pcm.format()
+    }
+
+    /// Total number of frames in the file
+    #[must_use]
+    pub fn frames(&self) -> u64 {
+        self.pcm.frames()
+    }
+
+    /// Current read position in frames
+    #[must_use]
+    pub fn position(&self) -> u64 {
+        self.pcm.position()
+    }
+
+    /// Moves the read position to `frame`, clamped to the end of the data.
+    ///
+    /// # Errors
+    /// Returns an error if the underlying seek fails.
+    pub fn seek_frame(&mut self, frame: u64) -> Result<()> {
+        self.pcm.seek_frame(frame)
+    }
+
+    /// Reads interleaved samples into `out`, returning how many were read.
+    ///
+    /// Only whole frames are read; zero means the end of the data.
+    ///
+    /// # Errors
+    /// Returns an error on I/O failure.
+    pub fn read_samples(&mut self, out: &mut [Sample]) -> Result<usize> {
+        self.pcm.read_samples(out)
+    }
+
+    /// Hands over the reader of the sample data, positioned where this one
+    /// was.
+    #[must_use]
+    pub fn into_pcm(self) -> PcmReader<R> {
+        self.pcm
+    }
+}
+
+fn read_chunk_header(reader: &mut impl Read) -> Result<([u8; 4], u32)> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    let id = [header[0], header[1], header[2], header[3]];
+    let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    Ok((id, size))
+}
+
+fn parse_fmt(fmt: &[u8]) -> Result<AudioFormat> {
+    let unsupported = |what: String| AudioEngineError::UnsupportedFormat { format: what };
+    if fmt.len() < 16 {
+        return Err(unsupported("truncated WAV fmt chunk".to_string()));
+    }
+    let read_u16 = |at: usize| u16::from_le_bytes([fmt[at], fmt[at + 1]]);
+    let mut format_tag = read_u16(0);
+    if format_tag == FORMAT_EXTENSIBLE && fmt.len() >= 26 {
+        // The sub-format GUID starts with the plain format tag
+        format_tag = read_u16(24);
+    }
+    let channels = ChannelCount::try_from(u32::from(read_u16(2)))?;
+    let sample_rate = SampleRate::try_from(u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]))?;
+    let bits = read_u16(14);
+    let bit_depth = match (format_tag, bits) {
+        (FORMAT_PCM, 16) => BitDepth::I16,
+        (FORMAT_PCM, 24) => BitDepth::I24,
+        (FORMAT_PCM, 32) => BitDepth::I32,
+        (FORMAT_IEEE_FLOAT, 32) => BitDepth::F32,
+        (FORMAT_IEEE_FLOAT, 64) => BitDepth::F64,
+        _ => {
+            return Err(unsupported(format!(
+                "WAV format tag {format_tag} with {bits} bits"
+            )));
+        }
+    };
+    Ok(AudioFormat::new(sample_rate, channels, bit_depth))
+}
+
+/// Decodes one little-endian sample of `bit_depth`.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn decode_sample(bit_depth: BitDepth, bytes: &[u8]) -> f32 {
+    match bit_depth {
+        BitDepth::I16 => f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32768.0,
+        BitDepth::I24 => {
+            let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+            (f64::from(value) / 8_388_608.0) as f32
+        }
+        BitDepth::I32 => {
+            let value = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            (f64::from(value) / 2_147_483_648.0) as f32
+        }
+        BitDepth::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        BitDepth::F64 => f64::from_le_bytes([
+            bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+        ]) as f32,
+    }
+}
+
+/// Adds a chunk to the end of an existing WAV file, replacing any chunk with
+/// the same id.
+///
+/// A replaced chunk at the end of the file is cut off; one elsewhere is
+/// renamed to `JUNK`, which readers skip, so the audio is never rewritten.
+///
+/// # Errors
+/// Returns an error if the file isn't a RIFF/WAVE file, can't be modified,
+/// or would exceed the 4 GiB RIFF limit.
+pub fn write_chunk(path: impl AsRef<Path>, id: [u8; 4], payload: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+        return Err(AudioEngineError::UnsupportedFormat {
+            format: "not a RIFF/WAVE file".to_string(),
+        });
+    }
+
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let mut end = 12;
+    let mut offset = 12;
+    while offset + 8 <= file_len {
+        file.seek(SeekFrom::Start(offset))?;
+        let (chunk_id, size) = read_chunk_header(&mut file)?;
+        let next = (offset + 8 + u64::from(size) + u64::from(size % 2)).min(file_len);
+        if chunk_id == id {
+            if next >= file_len {
+                break;
+            }
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(b"JUNK")?;
+        }
+        end = next;
+        offset = next;
+    }
+
+    let payload_len = u32::try_from(payload.len())
+        .map_err(|_| AudioEngineError::configuration("WAV chunk too large"))?;
+    let new_end = end + 8 + u64::from(payload_len) + u64::from(payload_len % 2);
+    let riff_size = u32::try_from(new_end - 8)
+        .map_err(|_| AudioEngineError::configuration("WAV file would exceed 4 GiB"))?;
+
+    file.set_len(end)?;
+    file.seek(SeekFrom::Start(end))?;
+    let mut chunk = Vec::with_capacity(payload.len() + 9);
+    chunk.extend_from_slice(&id);
+    chunk.extend_from_slice(&payload_len.to_le_bytes());
+    chunk.extend_from_slice(payload);
+    if payload_len % 2 == 1 {
+        chunk.push(0);
+    }
+    file.write_all(&chunk)?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&riff_size.to_le_bytes())?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Writes one sample as little-endian `bit_depth`, clipped to full scale.
+pub(crate) fn write_sample(
+    writer: &mut impl Write,
+    sample: Sample,
+    bit_depth: BitDepth,
+) -> std::io::Result<()> {
+    let value = sample.value().clamp(-1.0, 1.0);
+    match bit_depth {
+        BitDepth::I16 => writer.write_all(&quantize_i16(value).to_le_bytes()),
+        BitDepth::I24 => writer.write_all(&quantize_i32(value, 8_388_607.0).to_le_bytes()[..3]),
+        BitDepth::I32 => writer.write_all(&quantize_i32(value, 2_147_483_647.0).to_le_bytes()),
+        BitDepth::F32 => writer.write_all(&value.to_le_bytes()),
+        BitDepth::F64 => writer.write_all(&f64::from(value).to_le_bytes()),
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn quantize_i16(value: f32) -> i16 {
+    (value * 32767.0).round() as i16
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn quantize_i32(value: f32, scale: f64) -> i32 {
+    (f64::from(value) * scale).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::types::{BitDepth, ChannelCount, SampleRate};
+
+    #[allow(clippy::cast_precision_loss)]
+    fn ramp(len: usize) -> Vec<Sample> {
+        (0..len)
+            .map(|index| Sample::new((index as f32 * 0.1).sin() * 0.8))
+            .collect()
+    }
+
+    fn encode(format: AudioFormat, samples: &[Sample]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut writer = WavWriter::new(Cursor::new(&mut bytes), format).expect("header");
+        writer.write_samples(samples).expect("samples");
+        writer.finalize().expect("finalize");
+        drop(writer);
+        bytes
+    }
+
+    #[test]
+    fn round_trips_every_bit_depth() {
+        // Within two steps of the integer formats
+        for (bit_depth, tolerance) in [
+            (BitDepth::I16, 2.0 / 32_768.0),
+            (BitDepth::I24, 2.0 / 8_388_608.0),
+            (BitDepth::I32, 1e-7),
+            (BitDepth::F32, 0.0),
+        ] {
+            let format = AudioFormat::new(SampleRate::Hz48000, ChannelCount::Stereo, bit_depth);
+            let samples = ramp(200);
+            let bytes = encode(format, &samples);
+            let mut reader = WavReader::new(Cursor::new(bytes)).expect("reads back");
+            assert_eq!(reader.format(), format);
+            assert_eq!(reader.frames(), 100);
+            let mut read = vec![Sample::SILENCE; 200];
+            assert_eq!(reader.read_samples(&mut read).expect("samples"), 200);
+            for (written, read) in samples.iter().zip(&read) {
+                assert!(
+                    (written.value() - read.value()).abs() <= tolerance,
+                    "{bit_depth:?}: {} read as {}",
+                    written.value(),
+                    read.value()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_wav() {
+        let mut bytes = b"RIFF\0\0\0\0AVI ".to_vec();
+        bytes.extend_from_slice(&[0; 32]);
+        assert!(WavReader::new(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn skips_unknown_chunks_and_survives_huge_sizes() {
+        let format = AudioFormat::new(SampleRate::Hz44100, ChannelCount::Mono, BitDepth::I16);
+        let wav = encode(format, &ramp(10));
+        // An unknown chunk between the RIFF header and fmt
+        let mut bytes = wav[..12].to_vec();
+        bytes.extend_from_slice(b"junk");
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3, 0]);
+        bytes.extend_from_slice(&wav[12..]);
+        let reader = WavReader::new(Cursor::new(bytes)).expect("skips the chunk");
+        assert_eq!(reader.frames(), 10);
+
+        // One that claims the largest size runs off the end instead of
+        // overflowing
+        let mut bytes = wav[..12].to_vec();
+        bytes.extend_from_slice(b"junk");
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&wav[12..]);
+        assert!(WavReader::new(Cursor::new(bytes)).is_err());
+    }
+}
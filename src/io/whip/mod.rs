@@ -0,0 +1,128 @@
+//! WebRTC output through WHIP
+//!
+//! A [`WhipSender`] publishes the engine's output as a WebRTC stream to a
+//! WHIP (WebRTC-HTTP ingestion protocol) endpoint, the `http://` or
+//! `https://` url of a [`NetworkOutput`]. The media server behind the
+//! endpoint hands the stream on to browsers, which play it with well under
+//! a second of delay and no plugin: handy for monitoring a show from
+//! anywhere without running an RTMP server and an HLS packager.
+//!
+//! WebRTC carries Opus at 48 kHz, so the output's codec has to be
+//! [`StreamCodec::Opus`]; other sample rates are resampled and other
+//! layouts mixed to stereo. The sender hands the audio to `ffmpeg` 8 or
+//! later, whose WHIP muxer does the offer and answer exchange, ICE, DTLS
+//! and SRTP.
+//!
+//! [`WhipSettings`] on the output hold what the endpoint asks for: most
+//! want a bearer token, and slow links may need a longer handshake
+//! timeout or smaller packets.
+//!
+//! Sending needs the `whip` feature, and at run time `ffmpeg` on the
+//! `PATH`.
+//!
+//! [`NetworkOutput`]: crate::io::NetworkOutput
+//! [`StreamCodec::Opus`]: crate::io::output::StreamCodec::Opus
+
+#[cfg(feature = "whip")]
+mod sender;
+
+#[cfg(feature = "whip")]
+pub use sender::WhipSender;
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::error::{AudioEngineError, Result};
+
+/// Smallest packet a WebRTC path is expected to carry
+const MIN_PACKET_BYTES: u16 = 576;
+
+/// What a WHIP endpoint asks of the sender.
+/// The bearer token is kept out of `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct WhipSettings {
+    /// Sent as `Authorization: Bearer <token>` with the offer
+    pub bearer_token: Option<String>,
+    /// How long connecting, from the offer to the end of the DTLS
+    /// handshake, may take; 5 s by default
+    pub handshake_timeout: Duration,
+    /// Largest RTP packet in bytes, 1200 by default to stay under the path
+    /// MTU with room for tunnels
+    pub packet_size: u16,
+}
+
+impl Default for WhipSettings {
+    fn default() -> Self {
+        Self {
+            bearer_token: None,
+            handshake_timeout: Duration::from_secs(5),
+            packet_size: 1200,
+        }
+    }
+}
+
+impl fmt::Debug for WhipSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WhipSettings")
+            .field("authorized", &self.bearer_token.is_some())
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("packet_size", &self.packet_size)
+            .finish()
+    }
+}
+
+impl WhipSettings {
+    /// Authorizes the offer with `token`.
+    #[must_use]
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_packet_size(mut self, bytes: u16) -> Self {
+        self.packet_size = bytes;
+        self
+    }
+
+    /// The ffmpeg options for the WHIP muxer, followed by the endpoint.
+    ///
+    /// # Errors
+    /// Returns an error if `endpoint` isn't an `http://` or `https://`
+    /// url, the packet size is under 576 bytes, or the handshake timeout
+    /// is zero.
+    pub fn output_args(&self, endpoint: &str) -> Result<Vec<String>> {
+        if !(endpoint.starts_with("http://") || endpoint.starts_with("https://")) {
+            return Err(AudioEngineError::configuration(format!(
+                "WHIP endpoints are http or https urls, not {endpoint}"
+            )));
+        }
+        if self.packet_size < MIN_PACKET_BYTES {
+            return Err(AudioEngineError::configuration(format!(
+                "WHIP packets need at least {MIN_PACKET_BYTES} bytes, not {}",
+                self.packet_size
+            )));
+        }
+        if self.handshake_timeout.is_zero() {
+            return Err(AudioEngineError::configuration(
+                "WHIP handshake timeout must be above zero",
+            ));
+        }
+        let mut args: Vec<String> = ["-f", "whip"].map(str::to_string).into();
+        // ffmpeg takes the timeout in milliseconds
+        let timeout = self.handshake_timeout.as_millis().to_string();
+        args.extend(["-handshake_timeout".to_string(), timeout]);
+        args.extend(["-pkt_size".to_string(), self.packet_size.to_string()]);
+        if let Some(token) = &self.bearer_token {
+            args.extend(["-authorization".to_string(), token.clone()]);
+        }
+        args.push(endpoint.to_string());
+        Ok(args)
+    }
+}
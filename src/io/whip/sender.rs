@@ -0,0 +1,158 @@
+//! Publishes a stream through ffmpeg's WHIP muxer
+
+use std::fmt;
+
+use crate::engine::RenderSink;
+use crate::error::{AudioEngineError, Result};
+use crate::io::encoder::{EncoderOutput, EncoderProcess, ffmpeg_input_args, ffmpeg_opus_args};
+use crate::io::output::{NetworkOutput, StreamCodec};
+use crate::types::{AudioFormat, BitDepth, Sample};
+
+/// Program that encodes and publishes
+const SENDER_PROGRAM: &str = "ffmpeg";
+
+/// Publishes audio to the WHIP endpoint of a [`NetworkOutput`].
+///
+/// ```no_run
+/// use audio_engine::io::NetworkOutput;
+/// use audio_engine::io::output::{OpusFrameSize, OpusSettings};
+/// use audio_engine::io::whip::{WhipSender, WhipSettings};
+/// use audio_engine::types::{AudioFormat, Sample, StreamUrl};
+///
+/// let output = NetworkOutput::new(StreamUrl::parse("https://live.example.com/whip/studio")?)
+///     .with_opus(OpusSettings::default().with_frame_size(OpusFrameSize::Ms10))
+///     .with_whip(WhipSettings::default().with_bearer_token("s3cret"));
+/// let mut sender = WhipSender::connect(&output, AudioFormat::default())?;
+/// sender.write(&[Sample::SILENCE; 960]);
+/// # Ok::<(), audio_engine::error::AudioEngineError>(())
+/// ```
+pub struct WhipSender {
+    output: NetworkOutput,
+    format: AudioFormat,
+    process: EncoderProcess,
+}
+
+impl WhipSender {
+    /// Starts publishing to `output`'s url, with samples in `format`.
+    ///
+    /// An endpoint that can't be reached or turns the offer down shows up
+    /// soon after as [`has_failed`](Self::has_failed).
+    ///
+    /// # Errors
+    /// Returns an error if the codec isn't Opus, the url or settings don't
+    /// suit WHIP (see [`WhipSettings::output_args`]), or `ffmpeg` can't be
+    /// started.
+    ///
+    /// [`WhipSettings::output_args`]: super::WhipSettings::output_args
+    pub fn connect(output: &NetworkOutput, format: AudioFormat) -> Result<Self> {
+        let StreamCodec::Opus(settings) = output.codec else {
+            return Err(AudioEngineError::configuration(format!(
+                "WebRTC carries Opus, not {}",
+                output.codec
+            )));
+        };
+        let mut args = ffmpeg_input_args(format, BitDepth::I16);
+        args.extend(ffmpeg_opus_args(
+            settings.with_bitrate(output.audio_bitrate),
+        ));
+        // Browsers expect stereo Opus
+        args.extend(["-ac", "2"].map(str::to_string));
+        args.extend(output.whip.output_args(output.url.as_str())?);
+
+        let (process, _) =
+            EncoderProcess::spawn(SENDER_PROGRAM, &args, format, &EncoderOutput::File)?;
+        log::info!("Publishing Opus over WHIP to {}", output.url);
+        Ok(Self {
+            output: output.clone(),
+            format,
+            process,
+        })
+    }
+
+    #[must_use]
+    pub const fn output(&self) -> &NetworkOutput {
+        &self.output
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Queues interleaved samples to send without blocking, returning how
+    /// many were queued. The rest are dropped.
+    pub fn write(&mut self, samples: &[Sample]) -> usize {
+        self.process.write(samples)
+    }
+
+    /// Samples dropped so far because publishing fell behind
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.process.dropped()
+    }
+
+    /// Whether publishing stopped, e.g. because the endpoint refused the
+    /// offer or the peer connection was lost
+    #[must_use]
+    pub fn has_failed(&self) -> bool {
+        self.process.has_failed()
+    }
+
+    /// Sends what is still queued and ends the session.
+    ///
+    /// # Errors
+    /// Returns an error if publishing failed or ffmpeg exited
+    /// unsuccessfully.
+    pub fn finish(mut self) -> Result<()> {
+        self.process.close()
+    }
+}
+
+impl Drop for WhipSender {
+    fn drop(&mut self) {
+        if let Err(e) = self.process.close() {
+            log::error!("Publishing to {} failed: {e}", self.output.url);
+        }
+    }
+}
+
+impl fmt::Debug for WhipSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WhipSender")
+            .field("url", &self.output.url)
+            .field("codec", &self.output.codec)
+            .field("settings", &self.output.whip)
+            .field("format", &self.format)
+            .field("dropped", &self.dropped())
+            .field("failed", &self.has_failed())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RenderSink for WhipSender {
+    fn start(&mut self, format: AudioFormat) -> Result<()> {
+        if format == self.format {
+            Ok(())
+        } else {
+            Err(AudioEngineError::FormatMismatch {
+                expected: format!("{:?} {:?}", self.format.sample_rate, self.format.channels),
+                actual: format!("{:?} {:?}", format.sample_rate, format.channels),
+            })
+        }
+    }
+
+    /// Waits for room rather than dropping; render with
+    /// [`RenderPacing::RealTime`](crate::engine::RenderPacing::RealTime) so
+    /// the stream goes out as fast as it plays
+    fn write(&mut self, samples: &[Sample]) -> Result<()> {
+        self.process.write_all(samples)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.process.close()
+    }
+
+    fn describe(&self) -> String {
+        self.output.url.to_string()
+    }
+}
@@ -18,6 +18,20 @@ pub mod io;
 pub mod markers;
 pub mod types;
 pub mod dsp;
+pub mod mixer;
+pub mod recorder;
+pub mod scheduler;
+pub mod analysis;
+pub mod measurement;
+pub mod engine;
+pub mod graph;
+pub mod arrangement;
+#[cfg(feature = "midi")]
+pub mod midi;
+#[cfg(feature = "osc")]
+pub mod osc;
+#[cfg(feature = "rpc")]
+pub mod rpc;
 
 /// Prelude module for convenient imports
 pub mod prelude {
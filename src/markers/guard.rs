@@ -0,0 +1,86 @@
+//! Runtime guard against allocating on the audio thread
+//!
+//! The marker traits catch the wrong types at compile time; this guard
+//! catches what slips through at run time. Code on the audio thread runs
+//! inside a [`RealtimeScope`], and every allocation reported while a scope
+//! is open counts as a violation.
+//!
+//! The crate forbids unsafe code, so it can't install a global allocator
+//! itself. An application that wants the guard wraps its allocator and
+//! reports each allocation with [`record_allocation`]:
+//!
+//! ```ignore
+//! struct Guarded;
+//!
+//! unsafe impl GlobalAlloc for Guarded {
+//!     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+//!         audio_engine::markers::guard::record_allocation();
+//!         unsafe { System.alloc(layout) }
+//!     }
+//!     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+//!         unsafe { System.dealloc(ptr, layout) }
+//!     }
+//! }
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: Guarded = Guarded;
+//! ```
+//!
+//! Recording doesn't allocate, lock or log, so it is safe to call from
+//! inside an allocator.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+thread_local! {
+    static REALTIME: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Allocations recorded inside a realtime scope, on any thread
+static VIOLATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Marks the current thread as realtime until dropped.
+///
+/// Scopes nest; dropping one restores whatever was in effect before it.
+#[derive(Debug)]
+pub struct RealtimeScope {
+    previous: bool,
+    /// A scope belongs to the thread that opened it
+    _thread: PhantomData<*const ()>,
+}
+
+impl RealtimeScope {
+    #[must_use]
+    pub fn enter() -> Self {
+        Self {
+            previous: REALTIME.replace(true),
+            _thread: PhantomData,
+        }
+    }
+}
+
+impl Drop for RealtimeScope {
+    fn drop(&mut self) {
+        REALTIME.set(self.previous);
+    }
+}
+
+/// Whether the current thread is inside a [`RealtimeScope`]
+#[must_use]
+pub fn is_realtime() -> bool {
+    REALTIME.try_with(Cell::get).unwrap_or(false)
+}
+
+/// Reports an allocation; counted as a violation inside a realtime scope.
+pub fn record_allocation() {
+    if is_realtime() {
+        VIOLATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Violations recorded so far
+#[must_use]
+pub fn violations() -> u64 {
+    VIOLATIONS.load(Ordering::Relaxed)
+}
@@ -3,6 +3,10 @@
 //! Using traits here just as lablels , not for behavior.
 //! The goal is to let the compiler from using the wrong types in real-time audio code.
 //!
+//! [`guard`] checks at run time what the traits can't: that nothing
+//! allocates on the audio thread.
+
+pub mod guard;
 
 ///This trait marks types that are safe to use on a real time audio thread.
 ///
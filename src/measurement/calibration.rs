@@ -0,0 +1,323 @@
+//! Speaker calibration and channel identification
+//!
+//! [`CalibrationGenerator`] walks through the output channels one at a
+//! time. Each channel first beeps its position in the layout (one beep for
+//! the first channel, two for the second, ...) and then plays pink noise or
+//! a tone, so an installer can check that every speaker is wired to the
+//! right output and set its level. All other channels are silent.
+
+use crate::dsp::random::{PinkNoise, Rng};
+use crate::measurement::signals::amplitude;
+use crate::types::{ChannelCount, ChannelLayout, Sample, SampleRate};
+
+/// Length of an identification beep and of the pause after it, in seconds
+const BEEP_SECONDS: f32 = 0.15;
+/// Pause between the identification beeps and the test signal, in seconds
+const IDENTIFY_GAP_SECONDS: f32 = 0.5;
+/// Silence after the test signal before the next channel, in seconds
+const CHANNEL_GAP_SECONDS: f32 = 0.5;
+/// Raised-cosine ramp at either end of every burst, in seconds
+const RAMP_SECONDS: f32 = 0.005;
+/// Frequency of identification beeps
+const BEEP_HZ: f32 = 1000.0;
+/// Tones on the LFE channel are moved here so a subwoofer reproduces them
+const LFE_HZ: f32 = 50.0;
+/// Fixed so every run plays the same noise
+const CALIBRATION_SEED: u32 = 0x9E37_79B9;
+
+/// What each channel plays once it has been identified.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationSignal {
+    /// Full band pink noise
+    PinkNoise,
+    /// A steady sine
+    Tone {
+        /// Frequency in Hz
+        frequency_hz: f32,
+    },
+}
+
+/// Settings for a calibration sequence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationSettings {
+    pub signal: CalibrationSignal,
+    /// RMS level of the test signal in dBFS
+    pub level_db: f32,
+    /// How long each channel plays the test signal, in seconds
+    pub signal_seconds: f32,
+    /// Whether each channel beeps its number before the test signal
+    pub identify: bool,
+    /// Whether the sequence starts over after the last channel
+    pub repeat: bool,
+}
+
+impl Default for CalibrationSettings {
+    fn default() -> Self {
+        Self {
+            signal: CalibrationSignal::PinkNoise,
+            level_db: -20.0,
+            signal_seconds: 3.0,
+            identify: true,
+            repeat: false,
+        }
+    }
+}
+
+impl CalibrationSettings {
+    #[must_use]
+    pub const fn with_signal(mut self, signal: CalibrationSignal) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_level(mut self, level_db: f32) -> Self {
+        self.level_db = level_db;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_signal_duration(mut self, seconds: f32) -> Self {
+        self.signal_seconds = seconds;
+        self
+    }
+
+    /// Skips the identification beeps
+    #[must_use]
+    pub const fn without_identification(mut self) -> Self {
+        self.identify = false;
+        self
+    }
+
+    #[must_use]
+    pub const fn repeating(mut self) -> Self {
+        self.repeat = true;
+        self
+    }
+}
+
+/// Part of the sequence a channel is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationStage {
+    /// Beeping the channel number
+    Identify,
+    /// Playing the test signal
+    Signal,
+    /// Silent before the next channel
+    Gap,
+    /// Every channel has been played
+    Finished,
+}
+
+/// Lengths of the stages of one channel, in frames
+#[derive(Debug, Clone, Copy)]
+struct Timing {
+    beep: usize,
+    identify_gap: usize,
+    signal: usize,
+    channel_gap: usize,
+    ramp: usize,
+}
+
+impl Timing {
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn new(sample_rate: SampleRate, settings: &CalibrationSettings) -> Self {
+        let frames = |seconds: f32| (seconds.max(0.0) * sample_rate.as_f32()) as usize;
+        Self {
+            beep: frames(BEEP_SECONDS).max(1),
+            identify_gap: frames(IDENTIFY_GAP_SECONDS),
+            signal: frames(settings.signal_seconds).max(1),
+            channel_gap: frames(CHANNEL_GAP_SECONDS),
+            ramp: frames(RAMP_SECONDS).max(1),
+        }
+    }
+
+    const fn identify(&self, beeps: usize) -> usize {
+        beeps * 2 * self.beep + self.identify_gap
+    }
+}
+
+/// Generates a channel-by-channel calibration sequence.
+///
+/// Call [`fill`](Self::fill) from the output callback; it doesn't allocate.
+#[derive(Debug, Clone)]
+pub struct CalibrationGenerator {
+    settings: CalibrationSettings,
+    layout: ChannelLayout,
+    sample_rate: SampleRate,
+    timing: Timing,
+    channel: usize,
+    position: usize,
+    phase: f32,
+    pink: PinkNoise,
+}
+
+impl CalibrationGenerator {
+    #[must_use]
+    pub fn new(
+        settings: CalibrationSettings,
+        sample_rate: SampleRate,
+        layout: ChannelLayout,
+    ) -> Self {
+        Self {
+            settings,
+            layout,
+            sample_rate,
+            timing: Timing::new(sample_rate, &settings),
+            channel: 0,
+            position: 0,
+            phase: 0.0,
+            pink: PinkNoise::new(Rng::new(CALIBRATION_SEED)),
+        }
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &CalibrationSettings {
+        &self.settings
+    }
+
+    #[must_use]
+    pub const fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    #[must_use]
+    pub const fn channels(&self) -> ChannelCount {
+        self.layout.channel_count()
+    }
+
+    /// Channel currently being played
+    #[must_use]
+    pub const fn current_channel(&self) -> usize {
+        self.channel
+    }
+
+    /// Layout label of the current channel, e.g. "FL" or "LFE"
+    #[must_use]
+    pub fn current_label(&self) -> Option<&'static str> {
+        self.layout.channel_labels().get(self.channel).copied()
+    }
+
+    #[must_use]
+    pub const fn stage(&self) -> CalibrationStage {
+        if self.channel >= self.layout.channel_count().count_usize() {
+            return CalibrationStage::Finished;
+        }
+        let identify = self.identify_frames();
+        if self.position < identify {
+            CalibrationStage::Identify
+        } else if self.position < identify + self.timing.signal {
+            CalibrationStage::Signal
+        } else {
+            CalibrationStage::Gap
+        }
+    }
+
+    #[must_use]
+    pub const fn is_finished(&self) -> bool {
+        matches!(self.stage(), CalibrationStage::Finished)
+    }
+
+    /// Restarts the sequence at `channel`, e.g. to check one speaker again.
+    pub fn jump_to(&mut self, channel: usize) {
+        self.channel = channel.min(self.channels().count_usize());
+        self.position = 0;
+        self.phase = 0.0;
+    }
+
+    /// Writes the next `samples.len()` interleaved samples.
+    ///
+    /// The buffer must hold whole frames of the layout's channel count.
+    /// Once the sequence is finished, silence is written.
+    pub fn fill(&mut self, samples: &mut [Sample]) {
+        let channel_count = self.channels().count_usize();
+        for frame in samples.chunks_exact_mut(channel_count) {
+            frame.fill(Sample::SILENCE);
+            if self.is_finished() {
+                continue;
+            }
+            let value = self.next_value();
+            frame[self.channel] = Sample::new(value);
+            self.advance();
+        }
+    }
+
+    const fn identify_frames(&self) -> usize {
+        if self.settings.identify {
+            self.timing.identify(self.channel + 1)
+        } else {
+            0
+        }
+    }
+
+    const fn advance(&mut self) {
+        self.position += 1;
+        let total = self.identify_frames() + self.timing.signal + self.timing.channel_gap;
+        if self.position >= total {
+            self.position = 0;
+            self.phase = 0.0;
+            self.channel += 1;
+            if self.settings.repeat && self.channel >= self.channels().count_usize() {
+                self.channel = 0;
+            }
+        }
+    }
+
+    fn is_lfe(&self) -> bool {
+        self.current_label() == Some("LFE")
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn next_value(&mut self) -> f32 {
+        let identify = self.identify_frames();
+        let timing = self.timing;
+        if self.position < identify {
+            let beep = self.position / timing.beep;
+            let offset = self.position % timing.beep;
+            // Odd slots are the pauses between beeps, the tail is the gap
+            if beep % 2 == 1 || self.position >= identify - timing.identify_gap {
+                return 0.0;
+            }
+            let hz = if self.is_lfe() { LFE_HZ } else { BEEP_HZ };
+            let envelope = ramp(offset, timing.beep, timing.ramp);
+            return envelope * amplitude(self.settings.level_db) * self.sine(hz);
+        }
+
+        let offset = self.position - identify;
+        if offset >= timing.signal {
+            return 0.0;
+        }
+        let envelope = ramp(offset, timing.signal, timing.ramp);
+        let rms = amplitude(self.settings.level_db);
+        let value = match self.settings.signal {
+            CalibrationSignal::PinkNoise => rms * self.pink.next_sample(),
+            CalibrationSignal::Tone { frequency_hz } => {
+                let hz = if self.is_lfe() { LFE_HZ } else { frequency_hz };
+                rms * std::f32::consts::SQRT_2 * self.sine(hz)
+            }
+        };
+        envelope * value
+    }
+
+    fn sine(&mut self, frequency_hz: f32) -> f32 {
+        let value = (std::f32::consts::TAU * self.phase).sin();
+        self.phase = (self.phase + frequency_hz / self.sample_rate.as_f32()).fract();
+        value
+    }
+}
+
+/// Raised-cosine fade in and out over `ramp` frames of a `length` frame
+/// burst
+#[allow(clippy::cast_precision_loss)]
+fn ramp(offset: usize, length: usize, ramp: usize) -> f32 {
+    let edge = offset.min(length - 1 - offset);
+    if edge >= ramp {
+        return 1.0;
+    }
+    let t = edge as f32 / ramp as f32;
+    0.5f32.mul_add(-(std::f32::consts::PI * t).cos(), 0.5)
+}
@@ -0,0 +1,357 @@
+//! Distortion and noise measurement
+
+use crate::error::Result;
+use crate::measurement::signals::sine;
+use crate::measurement::{DeviceUnderTest, analysis_frames, power_spectrum};
+use crate::types::SampleRate;
+
+/// Bins either side of a tone counted as part of it; the Blackman-Harris
+/// main lobe is four bins wide on each side
+const TONE_HALF_WIDTH: usize = 6;
+/// Highest harmonic counted in THD
+const MAX_HARMONIC: usize = 10;
+
+/// Test tone for distortion and noise measurements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DistortionSettings {
+    /// Tone frequency; it is moved to the nearest analysis bin
+    pub frequency_hz: f32,
+    /// Peak level of the tone in dBFS
+    pub level_db: f32,
+    /// How long the tone (and, for noise, the silence) plays, in seconds
+    pub duration_seconds: f32,
+    /// Measurement bandwidth
+    pub low_hz: f32,
+    pub high_hz: f32,
+}
+
+impl Default for DistortionSettings {
+    fn default() -> Self {
+        Self {
+            frequency_hz: 1000.0,
+            level_db: -1.0,
+            duration_seconds: 2.0,
+            low_hz: 20.0,
+            high_hz: 20_000.0,
+        }
+    }
+}
+
+impl DistortionSettings {
+    #[must_use]
+    pub const fn with_frequency(mut self, frequency_hz: f32) -> Self {
+        self.frequency_hz = frequency_hz;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_level(mut self, level_db: f32) -> Self {
+        self.level_db = level_db;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_bandwidth(mut self, low_hz: f32, high_hz: f32) -> Self {
+        self.low_hz = low_hz;
+        self.high_hz = high_hz;
+        self
+    }
+
+    /// Tone and analysis lengths in frames; the tone always covers at least
+    /// one and a half analysis windows.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn frames(&self, sample_rate: SampleRate) -> (usize, usize) {
+        let analysis = analysis_frames(sample_rate);
+        let tone = ((self.duration_seconds * sample_rate.as_f32()) as usize).max(analysis * 3 / 2);
+        (tone, analysis)
+    }
+
+    /// The tone's bin in an `analysis`-point FFT
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn bin(&self, sample_rate: SampleRate, analysis: usize) -> usize {
+        let bin_hz = sample_rate.as_f32() / analysis as f32;
+        ((self.frequency_hz / bin_hz).round() as usize).clamp(TONE_HALF_WIDTH + 1, analysis / 2)
+    }
+
+    /// Bins within the measurement bandwidth
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn band(&self, sample_rate: SampleRate, analysis: usize) -> (usize, usize) {
+        let bin_hz = sample_rate.as_f32() / analysis as f32;
+        let low = ((self.low_hz / bin_hz).floor() as usize).max(1);
+        let high = ((self.high_hz / bin_hz).ceil() as usize).min(analysis / 2);
+        (low, high.max(low + 1))
+    }
+}
+
+/// Harmonic distortion of a sine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DistortionReport {
+    /// Frequency actually played
+    pub frequency_hz: f32,
+    /// Level of the fundamental at the output, in dBFS
+    pub fundamental_db: f32,
+    /// Harmonics 2 to 10 relative to the fundamental, in percent
+    pub thd_percent: f32,
+    /// Everything but the fundamental within the bandwidth, relative to the
+    /// fundamental, in percent
+    pub thd_n_percent: f32,
+}
+
+impl DistortionReport {
+    /// Plays a sine and measures what comes back.
+    ///
+    /// # Errors
+    /// Returns an error if the device fails.
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    pub fn measure(
+        device: &mut dyn DeviceUnderTest,
+        settings: &DistortionSettings,
+    ) -> Result<Self> {
+        let sample_rate = device.sample_rate();
+        let (tone_frames, analysis) = settings.frames(sample_rate);
+        let bin = settings.bin(sample_rate, analysis);
+        let frequency_hz = bin as f32 * sample_rate.as_f32() / analysis as f32;
+
+        let stimulus = sine(frequency_hz, settings.level_db, tone_frames, sample_rate);
+        let response = device.run(&stimulus, 0)?;
+        let start = (tone_frames - analysis) / 2;
+        let power = power_spectrum(&response[start..start + analysis])?;
+
+        let (low, high) = settings.band(sample_rate, analysis);
+        let fundamental = tone_power(&power, bin);
+        let harmonics: f64 = (2..=MAX_HARMONIC)
+            .map(|h| h * bin)
+            .take_while(|&harmonic| harmonic + TONE_HALF_WIDTH < high)
+            .map(|harmonic| tone_power(&power, harmonic))
+            .sum();
+        let total: f64 = power[low..high].iter().sum();
+        let residual = (total - fundamental).max(0.0);
+
+        let ratio = |part: f64| {
+            if fundamental > 0.0 {
+                (100.0 * (part / fundamental).sqrt()) as f32
+            } else {
+                0.0
+            }
+        };
+        Ok(Self {
+            frequency_hz,
+            fundamental_db: sine_level_db(fundamental, analysis),
+            thd_percent: ratio(harmonics),
+            thd_n_percent: ratio(residual),
+        })
+    }
+
+    #[must_use]
+    pub fn thd_db(&self) -> f32 {
+        percent_to_db(self.thd_percent)
+    }
+
+    #[must_use]
+    pub fn thd_n_db(&self) -> f32 {
+        percent_to_db(self.thd_n_percent)
+    }
+}
+
+/// Signal to noise ratio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseReport {
+    /// In-band level with the tone playing, in dBFS
+    pub signal_db: f32,
+    /// In-band level with silence playing, in dBFS
+    pub noise_db: f32,
+}
+
+impl NoiseReport {
+    /// Plays silence and then a sine, and compares their in-band levels.
+    ///
+    /// The silence comes first so its analysis window can't catch the tone
+    /// arriving late.
+    ///
+    /// # Errors
+    /// Returns an error if the device fails.
+    pub fn measure(
+        device: &mut dyn DeviceUnderTest,
+        settings: &DistortionSettings,
+    ) -> Result<Self> {
+        let sample_rate = device.sample_rate();
+        let (tone_frames, analysis) = settings.frames(sample_rate);
+        let bin = settings.bin(sample_rate, analysis);
+        #[allow(clippy::cast_precision_loss)]
+        let frequency_hz = bin as f32 * sample_rate.as_f32() / analysis as f32;
+
+        let mut stimulus = vec![0.0; tone_frames];
+        stimulus.extend(sine(
+            frequency_hz,
+            settings.level_db,
+            tone_frames,
+            sample_rate,
+        ));
+        let response = device.run(&stimulus, 0)?;
+
+        let (low, high) = settings.band(sample_rate, analysis);
+        let start = (tone_frames - analysis) / 2;
+        let in_band = |offset: usize| -> Result<f64> {
+            let power = power_spectrum(&response[offset + start..offset + start + analysis])?;
+            Ok(power[low..high].iter().sum())
+        };
+        let noise = in_band(0)?;
+        let signal = in_band(tone_frames)?;
+        Ok(Self {
+            signal_db: sine_level_db(signal, analysis),
+            noise_db: sine_level_db(noise, analysis),
+        })
+    }
+
+    /// Signal to noise ratio in dB, infinite if there is no noise at all
+    #[must_use]
+    pub fn snr_db(&self) -> f32 {
+        self.signal_db - self.noise_db
+    }
+}
+
+fn tone_power(power: &[f64], bin: usize) -> f64 {
+    let low = bin.saturating_sub(TONE_HALF_WIDTH);
+    let high = (bin + TONE_HALF_WIDTH + 1).min(power.len());
+    power[low.min(high)..high].iter().sum()
+}
+
+/// Converts windowed FFT power to the level of a sine with that power, in
+/// dBFS, so a full scale sine reads 0 dB.
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+fn sine_level_db(power: f64, analysis: usize) -> f32 {
+    // A sine of amplitude A windowed by w puts A^2 N sum(w^2) / 4 in the
+    // positive half of the spectrum; sum(w^2) / N is 0.257964 for
+    // Blackman-Harris
+    let n = analysis as f64;
+    let full_scale = n * n * 0.257_964 / 4.0;
+    if power > 0.0 {
+        (10.0 * (power / full_scale).log10()) as f32
+    } else {
+        f32::NEG_INFINITY
+    }
+}
+
+fn percent_to_db(percent: f32) -> f32 {
+    if percent > 0.0 {
+        20.0 * (percent / 100.0).log10()
+    } else {
+        f32::NEG_INFINITY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::signals::amplitude;
+
+    /// Adds a third harmonic at `third` of the tone's amplitude and white
+    /// noise with a peak of `noise`
+    struct Shaper {
+        peak: f32,
+        third: f32,
+        noise: f32,
+    }
+
+    impl DeviceUnderTest for Shaper {
+        fn sample_rate(&self) -> SampleRate {
+            SampleRate::Hz48000
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        fn run(&mut self, stimulus: &[f32], tail: usize) -> Result<Vec<f32>> {
+            let mut seed = 0x2545_f491_u32;
+            let mut output: Vec<f32> = stimulus
+                .iter()
+                .map(|&x| {
+                    // sin 3t = 3 sin t - 4 sin^3 t
+                    let u = x / self.peak;
+                    let third = self.third * self.peak * 4.0f32.mul_add(-u * u * u, 3.0 * u);
+                    seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    let noise = ((seed >> 8) as f32 / (1 << 24) as f32).mul_add(2.0, -1.0);
+                    self.noise.mul_add(noise, x + third)
+                })
+                .collect();
+            output.resize(stimulus.len() + tail, 0.0);
+            Ok(output)
+        }
+    }
+
+    fn shaper(third: f32, noise: f32) -> Shaper {
+        Shaper {
+            peak: amplitude(DistortionSettings::default().level_db),
+            third,
+            noise,
+        }
+    }
+
+    #[test]
+    fn a_clean_sine_has_no_distortion() {
+        let report =
+            DistortionReport::measure(&mut shaper(0.0, 0.0), &DistortionSettings::default())
+                .expect("measures");
+        assert!((report.frequency_hz - 1000.0).abs() < 1.0);
+        assert!((report.fundamental_db + 1.0).abs() < 0.05);
+        assert!(report.thd_db() < -90.0, "{}", report.thd_db());
+        assert!(report.thd_n_db() < -90.0, "{}", report.thd_n_db());
+    }
+
+    #[test]
+    fn reads_a_known_third_harmonic() {
+        let report =
+            DistortionReport::measure(&mut shaper(0.01, 0.0), &DistortionSettings::default())
+                .expect("measures");
+        assert!(
+            (report.thd_percent - 1.0).abs() < 0.01,
+            "{}",
+            report.thd_percent
+        );
+        assert!((report.thd_db() + 40.0).abs() < 0.1);
+        assert!((report.thd_n_percent - report.thd_percent).abs() < 0.01);
+
+        // Out of band, the harmonic isn't counted
+        let narrow = DistortionSettings::default().with_bandwidth(20.0, 2000.0);
+        let report = DistortionReport::measure(&mut shaper(0.01, 0.0), &narrow).expect("measures");
+        assert!(report.thd_percent < 0.01, "{}", report.thd_percent);
+    }
+
+    #[test]
+    fn noise_counts_in_thd_n_but_not_thd() {
+        let report =
+            DistortionReport::measure(&mut shaper(0.0, 0.01), &DistortionSettings::default())
+                .expect("measures");
+        assert!(report.thd_percent < report.thd_n_percent / 5.0);
+        assert!(report.thd_n_percent > 0.5);
+    }
+
+    #[test]
+    fn snr_compares_the_tone_with_the_noise_floor() {
+        // Uniform noise with a peak of 0.01 has an RMS of 0.01 / sqrt(3),
+        // of which 20 Hz to 20 kHz is 19980 / 24000; read as the peak of a
+        // sine, that is sqrt(2) times the RMS
+        let expected_noise_db = 20.0 * (0.01 / 3.0f32.sqrt() * 2.0f32.sqrt()).log10()
+            + 10.0 * (19_980.0f32 / 24_000.0).log10();
+        let report = NoiseReport::measure(&mut shaper(0.0, 0.01), &DistortionSettings::default())
+            .expect("measures");
+        assert!((report.signal_db + 1.0).abs() < 0.1, "{}", report.signal_db);
+        assert!(
+            (report.noise_db - expected_noise_db).abs() < 1.0,
+            "{} against {expected_noise_db}",
+            report.noise_db
+        );
+        assert!((report.snr_db() - (report.signal_db - expected_noise_db)).abs() < 1.0);
+
+        let silent = NoiseReport::measure(&mut shaper(0.0, 0.0), &DistortionSettings::default())
+            .expect("measures");
+        assert_eq!(silent.noise_db, f32::NEG_INFINITY);
+        assert_eq!(silent.snr_db(), f32::INFINITY);
+    }
+}
@@ -0,0 +1,178 @@
+//! Impulse response capture
+//!
+//! [`ImpulseCapture`] plays an exponential sweep through a device under
+//! test, deconvolves the recording into an impulse response, trims the
+//! loop latency off the front, fades and normalises it, and can write it
+//! as a mono 32-bit float WAV file for use as a convolution kernel.
+
+use std::path::Path;
+
+use crate::error::Result;
+use crate::io::wav::WavWriter;
+use crate::measurement::DeviceUnderTest;
+use crate::measurement::response::{deconvolve, sweep_tail_frames};
+use crate::measurement::signals::{ExponentialSweep, amplitude};
+use crate::types::{AudioFormat, BitDepth, ChannelCount, Sample, SampleRate};
+
+/// Level relative to the peak that marks the arrival of the direct sound
+const ONSET_THRESHOLD: f32 = 0.1;
+/// Kept before the detected onset so the leading edge isn't cut, in seconds
+const PRE_ONSET_SECONDS: f32 = 0.001;
+/// Share of the response faded out at its end
+const FADE_OUT_FRACTION: usize = 20;
+
+/// Settings for an impulse response capture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImpulseCapture {
+    pub sweep: ExponentialSweep,
+    /// Length of the captured response, in seconds
+    pub length_seconds: f32,
+    /// Whether the silence before the direct sound is trimmed
+    pub remove_latency: bool,
+    /// Peak level the response is normalised to in dBFS, or `None` to keep
+    /// the measured gain
+    pub normalize_db: Option<f32>,
+}
+
+impl Default for ImpulseCapture {
+    fn default() -> Self {
+        Self {
+            sweep: ExponentialSweep::new(20.0, 20_000.0, 10.0),
+            length_seconds: 3.0,
+            remove_latency: true,
+            normalize_db: Some(-1.0),
+        }
+    }
+}
+
+impl ImpulseCapture {
+    #[must_use]
+    pub const fn with_sweep(mut self, sweep: ExponentialSweep) -> Self {
+        self.sweep = sweep;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_length(mut self, seconds: f32) -> Self {
+        self.length_seconds = seconds;
+        self
+    }
+
+    /// Keeps the loop latency at the start of the response
+    #[must_use]
+    pub const fn keep_latency(mut self) -> Self {
+        self.remove_latency = false;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_normalization(mut self, peak_db: Option<f32>) -> Self {
+        self.normalize_db = peak_db;
+        self
+    }
+
+    /// Runs the sweep through `device` and recovers its impulse response.
+    ///
+    /// # Errors
+    /// Returns an error if the device fails.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn capture(&self, device: &mut dyn DeviceUnderTest) -> Result<ImpulseResponse> {
+        let sample_rate = device.sample_rate();
+        let length = ((self.length_seconds.max(0.0) * sample_rate.as_f32()) as usize).max(1);
+        let stimulus = self.sweep.generate(sample_rate);
+        let tail = length + sweep_tail_frames(sample_rate);
+        let response = device.run(&stimulus, tail)?;
+        let impulse = deconvolve(&stimulus, &response)?;
+
+        // Harmonic distortion wraps around to the end of the deconvolved
+        // signal, so only the first `tail` frames hold the linear response
+        let causal = &impulse[..tail.min(impulse.len())];
+        let start = if self.remove_latency {
+            let peak = causal.iter().fold(0.0f32, |peak, v| peak.max(v.abs()));
+            let onset = causal
+                .iter()
+                .position(|v| v.abs() >= peak * ONSET_THRESHOLD)
+                .unwrap_or(0);
+            onset.saturating_sub((PRE_ONSET_SECONDS * sample_rate.as_f32()) as usize)
+        } else {
+            0
+        };
+
+        let mut samples: Vec<f32> = causal[start..].iter().copied().take(length).collect();
+        samples.resize(length, 0.0);
+        let fade = (length / FADE_OUT_FRACTION).max(1);
+        for (i, value) in samples[length - fade..].iter_mut().enumerate() {
+            let t = i as f32 / fade as f32;
+            *value *= 0.5f32.mul_add((std::f32::consts::PI * t).cos(), 0.5);
+        }
+
+        if let Some(peak_db) = self.normalize_db {
+            let peak = samples.iter().fold(0.0f32, |peak, v| peak.max(v.abs()));
+            if peak > 0.0 {
+                let gain = amplitude(peak_db) / peak;
+                for value in &mut samples {
+                    *value *= gain;
+                }
+            }
+        }
+
+        Ok(ImpulseResponse {
+            sample_rate,
+            samples,
+            latency_frames: start,
+        })
+    }
+}
+
+/// A captured mono impulse response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImpulseResponse {
+    sample_rate: SampleRate,
+    samples: Vec<f32>,
+    latency_frames: usize,
+}
+
+impl ImpulseResponse {
+    #[must_use]
+    pub const fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    #[must_use]
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    #[must_use]
+    pub fn into_samples(self) -> Vec<f32> {
+        self.samples
+    }
+
+    /// Frames trimmed off the front, i.e. the measured loop latency
+    #[must_use]
+    pub const fn latency_frames(&self) -> usize {
+        self.latency_frames
+    }
+
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn duration_seconds(&self) -> f64 {
+        self.samples.len() as f64 / f64::from(self.sample_rate.as_hz())
+    }
+
+    /// Writes the response as a mono 32-bit float WAV file.
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be written.
+    pub fn write_wav(&self, path: impl AsRef<Path>) -> Result<()> {
+        let format = AudioFormat::new(self.sample_rate, ChannelCount::Mono, BitDepth::F32);
+        let mut writer = WavWriter::create(path, format)?;
+        let samples: Vec<Sample> = self.samples.iter().map(|&v| Sample::new(v)).collect();
+        writer.write_samples(&samples)?;
+        writer.finalize()
+    }
+}
@@ -0,0 +1,268 @@
+//! Frequency response measurement
+
+use crate::dsp::fft::Fft;
+use crate::error::{AudioEngineError, Result};
+use crate::measurement::DeviceUnderTest;
+use crate::measurement::signals::{ExponentialSweep, Multitone};
+use crate::types::SampleRate;
+
+/// Silence recorded after a sweep to catch latency and decay, in seconds
+const SWEEP_TAIL_SECONDS: f32 = 0.5;
+/// Longest part of the impulse response used for the frequency response
+const RESPONSE_WINDOW_SECONDS: f32 = 0.25;
+/// Bins below this fraction of the stimulus' peak power are not deconvolved
+const REGULARISATION: f32 = 1e-6;
+/// Periods of multitone played; the third is analysed
+const MULTITONE_PERIODS: usize = 4;
+
+/// Gain at one frequency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResponsePoint {
+    pub frequency_hz: f32,
+    /// Gain relative to the stimulus, 0 dB is unity
+    pub magnitude_db: f32,
+}
+
+/// Magnitude response, sorted by frequency.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FrequencyResponse {
+    points: Vec<ResponsePoint>,
+}
+
+impl FrequencyResponse {
+    /// Measures with an exponential sweep.
+    ///
+    /// The impulse response is recovered by deconvolution and windowed to
+    /// 250 ms around its start, which drops the harmonic distortion the sweep
+    /// produces (it lands before time zero) and late reflections. The
+    /// result is smoothed to `points_per_octave` points per octave across
+    /// the sweep's range.
+    ///
+    /// # Errors
+    /// Returns an error if the device fails.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn measure_sweep(
+        device: &mut dyn DeviceUnderTest,
+        sweep: &ExponentialSweep,
+        points_per_octave: u32,
+    ) -> Result<Self> {
+        let sample_rate = device.sample_rate();
+        let stimulus = sweep.generate(sample_rate);
+        let response = device.run(&stimulus, sweep_tail_frames(sample_rate))?;
+        let impulse = deconvolve(&stimulus, &response)?;
+
+        let window = ((RESPONSE_WINDOW_SECONDS * sample_rate.as_f32()) as usize)
+            .next_power_of_two()
+            .min(impulse.len());
+        // Keep a little before time zero: the band limited impulse rings on
+        // both sides of its peak
+        let pre = window / 16;
+        let mut re: Vec<f32> = impulse[impulse.len() - pre..]
+            .iter()
+            .chain(&impulse[..window - pre])
+            .copied()
+            .collect();
+        let fade = window / 8;
+        for (i, value) in re[..pre].iter_mut().enumerate() {
+            let t = i as f32 / pre as f32;
+            *value *= 0.5f32.mul_add(-(std::f32::consts::PI * t).cos(), 0.5);
+        }
+        for (i, value) in re[window - fade..].iter_mut().enumerate() {
+            let t = i as f32 / fade as f32;
+            *value *= 0.5f32.mul_add((std::f32::consts::PI * t).cos(), 0.5);
+        }
+        let mut im = vec![0.0; window];
+        Fft::new(window)?.forward(&mut re, &mut im);
+        let power: Vec<f64> = re[..=window / 2]
+            .iter()
+            .zip(&im)
+            .map(|(&r, &i)| f64::from(r).mul_add(f64::from(r), f64::from(i) * f64::from(i)))
+            .collect();
+
+        let bin_hz = sample_rate.as_f32() / window as f32;
+        let points = octave_points(
+            sweep.start_hz,
+            sweep.end_hz_for(sample_rate),
+            points_per_octave,
+        )
+        .into_iter()
+        .map(|frequency_hz| {
+            let half_band = (0.5 / points_per_octave.max(1) as f32).exp2();
+            let low = ((frequency_hz / half_band / bin_hz).floor() as usize).max(1);
+            let high =
+                ((frequency_hz * half_band / bin_hz).ceil() as usize).clamp(low + 1, power.len());
+            let mean = power[low..high].iter().sum::<f64>() / (high - low) as f64;
+            ResponsePoint {
+                frequency_hz,
+                magnitude_db: power_db(mean),
+            }
+        })
+        .collect();
+        Ok(Self { points })
+    }
+
+    /// Measures with a multitone.
+    ///
+    /// Each tone sits exactly on an FFT bin and the signal is periodic, so a
+    /// single steady-state period gives the gain at every tone at once
+    /// without windowing.
+    ///
+    /// # Errors
+    /// Returns an error if the device fails.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn measure_multitone(
+        device: &mut dyn DeviceUnderTest,
+        multitone: &Multitone,
+    ) -> Result<Self> {
+        let sample_rate = device.sample_rate();
+        let period = multitone.period();
+        let stimulus = multitone.generate(MULTITONE_PERIODS);
+        let response = device.run(&stimulus, period)?;
+
+        let fft = Fft::new(period)?;
+        let spectrum = |segment: &[f32]| {
+            let mut re = segment.to_vec();
+            let mut im = vec![0.0; period];
+            fft.forward(&mut re, &mut im);
+            (re, im)
+        };
+        let (stim_re, stim_im) = spectrum(&stimulus[..period]);
+        let (resp_re, resp_im) = spectrum(&response[2 * period..3 * period]);
+
+        let points = multitone
+            .bins()
+            .iter()
+            .zip(multitone.frequencies(sample_rate))
+            .map(|(&bin, frequency_hz)| {
+                let input = f64::from(stim_re[bin].hypot(stim_im[bin]));
+                let output = f64::from(resp_re[bin].hypot(resp_im[bin]));
+                ResponsePoint {
+                    frequency_hz,
+                    magnitude_db: power_db((output / input).powi(2)),
+                }
+            })
+            .collect();
+        Ok(Self { points })
+    }
+
+    #[must_use]
+    pub fn points(&self) -> &[ResponsePoint] {
+        &self.points
+    }
+
+    /// Gain at `frequency_hz`, interpolated on a log frequency axis, or
+    /// `None` outside the measured range.
+    #[must_use]
+    pub fn magnitude_at(&self, frequency_hz: f32) -> Option<f32> {
+        let upper = self
+            .points
+            .iter()
+            .position(|p| p.frequency_hz >= frequency_hz)?;
+        let high = self.points[upper];
+        if upper == 0 {
+            return (frequency_hz >= high.frequency_hz).then_some(high.magnitude_db);
+        }
+        let low = self.points[upper - 1];
+        let t = (frequency_hz / low.frequency_hz).log(high.frequency_hz / low.frequency_hz);
+        Some((high.magnitude_db - low.magnitude_db).mul_add(t, low.magnitude_db))
+    }
+
+    /// Largest deviation from the mean gain between `low_hz` and `high_hz`,
+    /// in dB; 0 for a perfectly flat response.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn flatness(&self, low_hz: f32, high_hz: f32) -> f32 {
+        let band: Vec<f32> = self
+            .points
+            .iter()
+            .filter(|p| (low_hz..=high_hz).contains(&p.frequency_hz))
+            .map(|p| p.magnitude_db)
+            .collect();
+        if band.is_empty() {
+            return 0.0;
+        }
+        let mean = band.iter().sum::<f32>() / band.len() as f32;
+        band.iter().fold(0.0, |max, db| max.max((db - mean).abs()))
+    }
+}
+
+/// Recovers the impulse response of the system that turned `stimulus` into
+/// `response`, by regularised spectral division.
+///
+/// The result is circular: its length is the FFT size, and anything that
+/// arrives before time zero — such as the harmonic distortion of an
+/// exponential sweep — wraps around to the end.
+///
+/// # Errors
+/// Returns an error if either signal is empty.
+#[allow(clippy::cast_precision_loss)]
+pub fn deconvolve(stimulus: &[f32], response: &[f32]) -> Result<Vec<f32>> {
+    if stimulus.is_empty() || response.is_empty() {
+        return Err(AudioEngineError::configuration(
+            "deconvolution needs a stimulus and a response",
+        ));
+    }
+    let n = (stimulus.len() + response.len()).next_power_of_two();
+    let fft = Fft::new(n)?;
+
+    let mut s_re = stimulus.to_vec();
+    s_re.resize(n, 0.0);
+    let mut s_im = vec![0.0; n];
+    fft.forward(&mut s_re, &mut s_im);
+
+    let mut r_re = response.to_vec();
+    r_re.resize(n, 0.0);
+    let mut r_im = vec![0.0; n];
+    fft.forward(&mut r_re, &mut r_im);
+
+    let peak = s_re
+        .iter()
+        .zip(&s_im)
+        .map(|(&r, &i)| r.mul_add(r, i * i))
+        .fold(0.0f32, f32::max);
+    let floor = peak * REGULARISATION;
+    for k in 0..n {
+        // R * conj(S) / (|S|^2 + floor)
+        let power = s_re[k].mul_add(s_re[k], s_im[k] * s_im[k]) + floor;
+        let re = r_re[k].mul_add(s_re[k], r_im[k] * s_im[k]) / power;
+        let im = r_im[k].mul_add(s_re[k], -(r_re[k] * s_im[k])) / power;
+        r_re[k] = re;
+        r_im[k] = im;
+    }
+    fft.inverse(&mut r_re, &mut r_im);
+    Ok(r_re)
+}
+
+/// Log-spaced frequencies from `start_hz` to `end_hz`.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn octave_points(start_hz: f32, end_hz: f32, points_per_octave: u32) -> Vec<f32> {
+    let per_octave = points_per_octave.max(1) as f32;
+    let count = ((end_hz / start_hz).log2() * per_octave).floor().max(0.0) as usize + 1;
+    (0..count)
+        .map(|k| start_hz * (k as f32 / per_octave).exp2())
+        .collect()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn power_db(power: f64) -> f32 {
+    if power > 0.0 {
+        (10.0 * power.log10()) as f32
+    } else {
+        f32::NEG_INFINITY
+    }
+}
+
+/// Frames of silence recorded after a sweep at `sample_rate`
+#[must_use]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn sweep_tail_frames(sample_rate: SampleRate) -> usize {
+    (SWEEP_TAIL_SECONDS * sample_rate.as_f32()) as usize
+}
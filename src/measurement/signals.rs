@@ -0,0 +1,206 @@
+//! Test signals
+//!
+//! Stimuli are mono `f32` at full scale 1.0; the device under test is fed
+//! the same signal on every channel.
+
+use std::f64::consts::{PI, TAU};
+
+use crate::types::SampleRate;
+
+/// Length of the raised-cosine ramps at either end of a sweep, in seconds
+const SWEEP_RAMP_SECONDS: f64 = 0.01;
+
+/// Converts a level in dBFS to a peak amplitude.
+#[must_use]
+pub fn amplitude(level_db: f32) -> f32 {
+    10.0f32.powf(level_db / 20.0)
+}
+
+/// Exponential (logarithmic) sine sweep.
+///
+/// Every octave takes the same time, so the sweep carries equal energy per
+/// octave and its harmonic distortion separates cleanly from the linear
+/// response on deconvolution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExponentialSweep {
+    pub start_hz: f32,
+    pub end_hz: f32,
+    pub duration_seconds: f32,
+    /// Peak level in dBFS
+    pub level_db: f32,
+}
+
+impl Default for ExponentialSweep {
+    fn default() -> Self {
+        Self {
+            start_hz: 20.0,
+            end_hz: 20_000.0,
+            duration_seconds: 5.0,
+            level_db: -6.0,
+        }
+    }
+}
+
+impl ExponentialSweep {
+    #[must_use]
+    pub const fn new(start_hz: f32, end_hz: f32, duration_seconds: f32) -> Self {
+        Self {
+            start_hz,
+            end_hz,
+            duration_seconds,
+            level_db: -6.0,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_level(mut self, level_db: f32) -> Self {
+        self.level_db = level_db;
+        self
+    }
+
+    /// The end frequency, kept below Nyquist for `sample_rate`
+    #[must_use]
+    pub fn end_hz_for(&self, sample_rate: SampleRate) -> f32 {
+        self.end_hz.min(sample_rate.as_f32() * 0.45)
+    }
+
+    /// Renders the sweep, with short ramps at either end so it starts and
+    /// stops without a click.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn generate(&self, sample_rate: SampleRate) -> Vec<f32> {
+        let rate = f64::from(sample_rate.as_hz());
+        let frames = (f64::from(self.duration_seconds.max(0.0)) * rate) as usize;
+        let start = f64::from(self.start_hz.max(1.0));
+        let end = f64::from(self.end_hz_for(sample_rate)).max(start * 1.01);
+        let duration = frames as f64 / rate;
+        let rate_constant = duration / (end / start).ln();
+        let ramp = ((SWEEP_RAMP_SECONDS * rate) as usize)
+            .min(frames / 2)
+            .max(1);
+        let gain = f64::from(amplitude(self.level_db));
+
+        (0..frames)
+            .map(|i| {
+                let t = i as f64 / rate;
+                let phase = TAU * start * rate_constant * (t / rate_constant).exp_m1();
+                let edge = i.min(frames - 1 - i);
+                let envelope = if edge < ramp {
+                    0.5f64.mul_add(-(PI * edge as f64 / ramp as f64).cos(), 0.5)
+                } else {
+                    1.0
+                };
+                (gain * envelope * phase.sin()) as f32
+            })
+            .collect()
+    }
+}
+
+/// Renders `frames` of a sine at `frequency_hz`.
+#[must_use]
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+pub fn sine(frequency_hz: f32, level_db: f32, frames: usize, sample_rate: SampleRate) -> Vec<f32> {
+    let step = TAU * f64::from(frequency_hz) / f64::from(sample_rate.as_hz());
+    let gain = amplitude(level_db);
+    (0..frames)
+        .map(|i| gain * (step * i as f64).sin() as f32)
+        .collect()
+}
+
+/// A periodic sum of sines, each on an exact bin of a `period`-point FFT.
+///
+/// Phases follow Schroeder's formula to keep the crest factor low, so the
+/// tones can be played loud without clipping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Multitone {
+    period: usize,
+    bins: Vec<usize>,
+    level_db: f32,
+}
+
+impl Multitone {
+    /// Log-spaced tones between `start_hz` and `end_hz`, at most
+    /// `tones_per_octave` per octave, peaking at `level_db`.
+    ///
+    /// Tones that would fall on the same bin are merged, so low octaves may
+    /// get fewer.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn new(
+        sample_rate: SampleRate,
+        start_hz: f32,
+        end_hz: f32,
+        tones_per_octave: u32,
+        level_db: f32,
+    ) -> Self {
+        let period = ((sample_rate.as_hz() / 4) as usize).next_power_of_two();
+        let bin_hz = sample_rate.as_f32() / period as f32;
+        let end_hz = end_hz.min(sample_rate.as_f32() * 0.45);
+        let octaves = (end_hz / start_hz.max(bin_hz)).log2().max(0.0);
+        let count = (octaves * tones_per_octave.max(1) as f32).ceil() as usize + 1;
+        let mut bins: Vec<usize> = (0..count)
+            .map(|k| {
+                let hz = start_hz.max(bin_hz) * (k as f32 / tones_per_octave.max(1) as f32).exp2();
+                ((hz / bin_hz).round() as usize).max(1)
+            })
+            .filter(|&bin| bin < period / 2)
+            .collect();
+        bins.dedup();
+        Self {
+            period,
+            bins,
+            level_db,
+        }
+    }
+
+    /// Frames in one period of the signal
+    #[must_use]
+    pub const fn period(&self) -> usize {
+        self.period
+    }
+
+    /// FFT bins of a `period`-point transform the tones sit on
+    #[must_use]
+    pub fn bins(&self) -> &[usize] {
+        &self.bins
+    }
+
+    /// Frequencies of the tones
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn frequencies(&self, sample_rate: SampleRate) -> Vec<f32> {
+        let bin_hz = sample_rate.as_f32() / self.period as f32;
+        self.bins.iter().map(|&bin| bin as f32 * bin_hz).collect()
+    }
+
+    /// Renders `periods` whole periods.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    pub fn generate(&self, periods: usize) -> Vec<f32> {
+        let tones = self.bins.len().max(1) as f64;
+        let mut one: Vec<f64> = vec![0.0; self.period];
+        for (k, &bin) in self.bins.iter().enumerate() {
+            let phase = -PI * (k * k) as f64 / tones;
+            let step = TAU * bin as f64 / self.period as f64;
+            for (i, value) in one.iter_mut().enumerate() {
+                *value += step.mul_add(i as f64, phase).cos();
+            }
+        }
+        let peak = one.iter().fold(0.0f64, |peak, v| peak.max(v.abs()));
+        let scale = if peak > 0.0 {
+            f64::from(amplitude(self.level_db)) / peak
+        } else {
+            0.0
+        };
+        let one: Vec<f32> = one.iter().map(|v| (v * scale) as f32).collect();
+        one.repeat(periods)
+    }
+}
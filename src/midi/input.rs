@@ -0,0 +1,278 @@
+//! Connection to a MIDI input port
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use flume::{Receiver, Sender};
+use parking_lot::{Mutex, MutexGuard};
+
+use crate::channel::EngineCommand;
+use crate::dsp::automation::{ParamEvent, ParamEventList};
+use crate::dsp::params::{ParamId, ParamValue};
+use crate::dsp::traits::EffectId;
+use crate::engine::Engine;
+use crate::error::{AudioEngineError, Result};
+use crate::midi::{MidiMap, MidiMessage, SyncFollower};
+use crate::types::SampleRate;
+
+/// Commands that can be waiting for the control thread
+const COMMAND_CAPACITY: usize = 1024;
+/// Client name shown to the MIDI system
+const CLIENT_NAME: &str = "audio_engine";
+
+/// A command mapped from a MIDI message, and when the message arrived.
+#[derive(Debug, Clone)]
+pub struct TimedCommand {
+    pub time: Instant,
+    pub command: EngineCommand,
+}
+
+impl TimedCommand {
+    /// Frames from `block_start` to the message's arrival; 0 if it arrived
+    /// earlier.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn frame_offset(&self, block_start: Instant, sample_rate: SampleRate) -> u64 {
+        let elapsed = self.time.saturating_duration_since(block_start);
+        (elapsed.as_secs_f64() * f64::from(sample_rate.as_hz())) as u64
+    }
+}
+
+/// An open MIDI input port.
+///
+/// The port stays connected until this is dropped.
+pub struct MidiInput {
+    port: String,
+    map: Arc<Mutex<MidiMap>>,
+    sync: Arc<Mutex<Option<SyncFollower>>>,
+    commands: Receiver<TimedCommand>,
+    /// Queues the drop-out commands [`poll_sync`](Self::poll_sync) finds
+    sender: Sender<TimedCommand>,
+    /// A command [`schedule`](Self::schedule) held back for a later block
+    pending: Option<TimedCommand>,
+    _connection: midir::MidiInputConnection<()>,
+}
+
+impl MidiInput {
+    /// Names of the MIDI input ports
+    ///
+    /// # Errors
+    /// Returns an error if the MIDI system can't be reached.
+    pub fn ports() -> Result<Vec<String>> {
+        let input = Self::client()?;
+        Ok(input
+            .ports()
+            .iter()
+            .filter_map(|port| input.port_name(port).ok())
+            .collect())
+    }
+
+    /// Connects to the first port whose name contains `name`, translating
+    /// its messages with `map`.
+    ///
+    /// # Errors
+    /// Returns an error if the MIDI system can't be reached, there is no
+    /// such port, or connecting to it fails.
+    pub fn open(name: &str, map: MidiMap) -> Result<Self> {
+        let input = Self::client()?;
+        let (port, port_name) = input
+            .ports()
+            .into_iter()
+            .find_map(|port| {
+                let port_name = input.port_name(&port).ok()?;
+                port_name.contains(name).then_some((port, port_name))
+            })
+            .ok_or_else(|| AudioEngineError::DeviceNotFound {
+                device_name: name.to_string(),
+            })?;
+        let map = Arc::new(Mutex::new(map));
+        let sync = Arc::new(Mutex::new(None));
+        let (sender, commands) = flume::bounded(COMMAND_CAPACITY);
+        let (callback_map, callback_sync, callback_sender) =
+            (Arc::clone(&map), Arc::clone(&sync), sender.clone());
+        let connection = input
+            .connect(
+                &port,
+                CLIENT_NAME,
+                move |_, bytes, ()| receive(bytes, &callback_map, &callback_sync, &callback_sender),
+                (),
+            )
+            .map_err(|e| AudioEngineError::DeviceAccess {
+                message: format!("Failed to connect to MIDI port {port_name}: {e}"),
+            })?;
+        log::info!("Listening to MIDI port {port_name}");
+        Ok(Self {
+            port: port_name,
+            map,
+            sync,
+            commands,
+            sender,
+            pending: None,
+            _connection: connection,
+        })
+    }
+
+    fn client() -> Result<midir::MidiInput> {
+        midir::MidiInput::new(CLIENT_NAME).map_err(|e| AudioEngineError::DeviceAccess {
+            message: format!("Failed to open MIDI input: {e}"),
+        })
+    }
+
+    /// Name of the connected port
+    #[must_use]
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    /// The mapping table, locked for editing or MIDI learn. Messages wait
+    /// while it is held.
+    pub fn map(&self) -> MutexGuard<'_, MidiMap> {
+        self.map.lock()
+    }
+
+    /// Follows MIDI clock or timecode from the port with `follower`, or
+    /// stops following with `None`. The commands it sends queue up with
+    /// the mapped ones.
+    pub fn set_sync(&self, follower: Option<SyncFollower>) {
+        *self.sync.lock() = follower;
+    }
+
+    /// The sync follower, locked. Messages wait while it is held.
+    pub fn sync(&self) -> MutexGuard<'_, Option<SyncFollower>> {
+        self.sync.lock()
+    }
+
+    /// Checks the sync follower for a drop-out, queueing the commands it
+    /// leads to. Collecting commands does this first.
+    pub fn poll_sync(&self) {
+        let time = Instant::now();
+        if let Some(follower) = self.sync.lock().as_mut() {
+            follower.poll(time, |command| queue(&self.sender, time, command));
+        }
+    }
+
+    /// The next command, oldest first
+    pub fn try_recv(&mut self) -> Option<TimedCommand> {
+        if self.pending.is_none() {
+            self.poll_sync();
+        }
+        self.pending
+            .take()
+            .or_else(|| self.commands.try_recv().ok())
+    }
+
+    /// Sends every waiting command to `engine`, returning how many were
+    /// sent. They take effect at the start of the engine's next block.
+    ///
+    /// # Errors
+    /// Returns an error if the engine's command queue is full; the
+    /// remaining commands stay queued.
+    pub fn dispatch(&mut self, engine: &mut Engine) -> Result<usize> {
+        let mut sent = 0;
+        while let Some(timed) = self.try_recv() {
+            if let Err(e) = engine.send(timed.command.clone()) {
+                self.pending = Some(timed);
+                return Err(e);
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Places the commands that arrived before the end of a block of
+    /// `frames` starting at `block_start` for sample-accurate processing.
+    ///
+    /// Effect parameter changes and switches go into `events` at the frame
+    /// matching their arrival, for [`EffectChain::process_block`]; other
+    /// commands, and changes that don't fit in `events`, are passed to
+    /// `apply` for the start of the block. Commands for later blocks stay
+    /// queued.
+    ///
+    /// Messages arrive while the block before is playing, so hosts
+    /// usually pass the time that block started: every change then lands
+    /// one block late, always by the same amount, instead of jittering.
+    /// Returns how many commands were placed.
+    ///
+    /// [`EffectChain::process_block`]: crate::dsp::chain::EffectChain::process_block
+    pub fn schedule(
+        &mut self,
+        block_start: Instant,
+        sample_rate: SampleRate,
+        frames: usize,
+        events: &mut ParamEventList,
+        mut apply: impl FnMut(EngineCommand),
+    ) -> usize {
+        let mut placed = 0;
+        while let Some(timed) = self.try_recv() {
+            let offset = timed.frame_offset(block_start, sample_rate);
+            let (Ok(offset), true) = (u32::try_from(offset), offset < frames as u64) else {
+                self.pending = Some(timed);
+                break;
+            };
+            let scheduled = match timed.command {
+                EngineCommand::SetEffectParam {
+                    effect_id,
+                    param_id,
+                    value,
+                } => events.push(ParamEvent::new(
+                    offset,
+                    EffectId::new(effect_id),
+                    ParamId::new(param_id),
+                    ParamValue::Float(value),
+                )),
+                EngineCommand::SetEffectEnabled { effect_id, enabled } => events.push(
+                    ParamEvent::enabled(offset, EffectId::new(effect_id), enabled),
+                ),
+                _ => false,
+            };
+            if !scheduled {
+                apply(timed.command);
+            }
+            placed += 1;
+        }
+        placed
+    }
+}
+
+impl std::fmt::Debug for MidiInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MidiInput")
+            .field("port", &self.port)
+            .field("map", &self.map)
+            .field("sync", &self.sync)
+            .field("commands", &self.commands.len())
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Runs on midir's thread for every incoming message.
+fn receive(
+    bytes: &[u8],
+    map: &Mutex<MidiMap>,
+    sync: &Mutex<Option<SyncFollower>>,
+    sender: &Sender<TimedCommand>,
+) {
+    let time = Instant::now();
+    if let Some(follower) = sync.lock().as_mut()
+        && follower.receive(bytes, time, |command| queue(sender, time, command))
+    {
+        return;
+    }
+    let Some(message) = MidiMessage::parse(bytes) else {
+        return;
+    };
+    let mut map = map.lock();
+    for command in map.translate(message) {
+        queue(sender, time, command);
+    }
+}
+
+fn queue(sender: &Sender<TimedCommand>, time: Instant, command: EngineCommand) {
+    if let Err(e) = sender.try_send(TimedCommand { time, command }) {
+        log::warn!(
+            "MIDI command queue full, dropping {:?}",
+            e.into_inner().command
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Mapping from MIDI messages to engine commands
+//!
+//! A [`MidiMapping`] ties a [`MidiSource`], such as a controller on a
+//! channel, to a [`MidiTarget`], such as an effect parameter. Incoming
+//! values are scaled to 0.0 - 1.0 first: velocity and controller values
+//! over 127, pitch bend over its full range. Targets then map that onto
+//! their own range.
+
+use crate::channel::EngineCommand;
+use crate::dsp::params::ParamId;
+use crate::dsp::traits::EffectId;
+use crate::midi::MidiMessage;
+use crate::types::{Gain, Pan};
+
+/// Which messages a mapping listens to. A `None` channel listens on all
+/// of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MidiSource {
+    /// Velocity of note ons; note offs read as 0.0
+    Note {
+        channel: Option<u8>,
+        note: u8,
+    },
+    Control {
+        channel: Option<u8>,
+        controller: u8,
+    },
+    /// A change to this program reads as 1.0
+    Program {
+        channel: Option<u8>,
+        program: u8,
+    },
+    PitchBend {
+        channel: Option<u8>,
+    },
+}
+
+impl MidiSource {
+    /// The source a message comes from, on its own channel, and its value.
+    #[must_use]
+    pub fn of(message: MidiMessage) -> (Self, f32) {
+        let channel = Some(message.channel());
+        match message {
+            MidiMessage::NoteOn { note, velocity, .. } => {
+                (Self::Note { channel, note }, f32::from(velocity) / 127.0)
+            }
+            MidiMessage::NoteOff { note, .. } => (Self::Note { channel, note }, 0.0),
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } => (
+                Self::Control {
+                    channel,
+                    controller,
+                },
+                f32::from(value) / 127.0,
+            ),
+            MidiMessage::ProgramChange { program, .. } => (Self::Program { channel, program }, 1.0),
+            MidiMessage::PitchBend { value, .. } => {
+                (Self::PitchBend { channel }, f32::from(value) / 16383.0)
+            }
+        }
+    }
+
+    /// Whether this source listens to `other`, which names one channel
+    #[must_use]
+    pub fn matches(self, other: Self) -> bool {
+        let on = |channel: Option<u8>, other: Option<u8>| channel.is_none() || channel == other;
+        match (self, other) {
+            (
+                Self::Note { channel, note },
+                Self::Note {
+                    channel: other_channel,
+                    note: other_note,
+                },
+            ) => note == other_note && on(channel, other_channel),
+            (
+                Self::Control {
+                    channel,
+                    controller,
+                },
+                Self::Control {
+                    channel: other_channel,
+                    controller: other_controller,
+                },
+            ) => controller == other_controller && on(channel, other_channel),
+            (
+                Self::Program { channel, program },
+                Self::Program {
+                    channel: other_channel,
+                    program: other_program,
+                },
+            ) => program == other_program && on(channel, other_channel),
+            (Self::PitchBend { channel }, Self::PitchBend { channel: other }) => on(channel, other),
+            _ => false,
+        }
+    }
+}
+
+/// What a mapping controls.
+#[derive(Debug, Clone)]
+pub enum MidiTarget {
+    /// Master gain, from `min_db` at 0.0 to `max_db` at 1.0
+    Gain { min_db: f32, max_db: f32 },
+    /// Master pan, hard left at 0.0 to hard right at 1.0
+    Pan,
+    /// An effect parameter, from `min` at 0.0 to `max` at 1.0
+    EffectParam {
+        effect_id: EffectId,
+        param_id: ParamId,
+        min: f32,
+        max: f32,
+    },
+    /// Turns an effect on at 0.5 and above, off below
+    EffectEnabled { effect_id: EffectId },
+    /// The crossfader position
+    Crossfade,
+    /// Sends a fixed command at 0.5 and above, e.g. start on a pad hit
+    Command(EngineCommand),
+}
+
+impl MidiTarget {
+    /// The command for a value from 0.0 to 1.0, if the target sends one
+    #[must_use]
+    pub fn command(&self, value: f32) -> Option<EngineCommand> {
+        let value = value.clamp(0.0, 1.0);
+        match self {
+            Self::Gain { min_db, max_db } => Some(EngineCommand::SetGain(Gain::from_db(
+                (max_db - min_db).mul_add(value, *min_db),
+            ))),
+            Self::Pan => Some(EngineCommand::SetPan(Pan::new(value.mul_add(2.0, -1.0)))),
+            Self::EffectParam {
+                effect_id,
+                param_id,
+                min,
+                max,
+            } => Some(EngineCommand::SetEffectParam {
+                effect_id: effect_id.value(),
+                param_id: param_id.value(),
+                value: (max - min).mul_add(value, *min),
+            }),
+            Self::EffectEnabled { effect_id } => Some(EngineCommand::SetEffectEnabled {
+                effect_id: effect_id.value(),
+                enabled: value >= 0.5,
+            }),
+            Self::Crossfade => Some(EngineCommand::SetCrossfade(value)),
+            Self::Command(command) => (value >= 0.5).then(|| command.clone()),
+        }
+    }
+}
+
+/// One entry in a [`MidiMap`].
+#[derive(Debug, Clone)]
+pub struct MidiMapping {
+    pub source: MidiSource,
+    pub target: MidiTarget,
+}
+
+/// The mapping table, with MIDI learn.
+#[derive(Debug, Clone, Default)]
+pub struct MidiMap {
+    mappings: Vec<MidiMapping>,
+    /// Target waiting for the next message
+    learning: Option<MidiTarget>,
+}
+
+impl MidiMap {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            mappings: Vec::new(),
+            learning: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_mapping(mut self, source: MidiSource, target: MidiTarget) -> Self {
+        self.add(source, target);
+        self
+    }
+
+    /// Adds a mapping. A source can drive several targets.
+    pub fn add(&mut self, source: MidiSource, target: MidiTarget) {
+        self.mappings.push(MidiMapping { source, target });
+    }
+
+    /// Removes every mapping from `source`.
+    pub fn remove(&mut self, source: MidiSource) {
+        self.mappings.retain(|mapping| mapping.source != source);
+    }
+
+    pub fn clear(&mut self) {
+        self.mappings.clear();
+    }
+
+    #[must_use]
+    pub fn mappings(&self) -> &[MidiMapping] {
+        &self.mappings
+    }
+
+    /// Binds `target` to the next note on, controller, program change or
+    /// pitch bend that arrives, on its channel.
+    pub const fn learn(&mut self, target: MidiTarget) {
+        self.learning = Some(target);
+    }
+
+    #[must_use]
+    pub const fn is_learning(&self) -> bool {
+        self.learning.is_some()
+    }
+
+    pub const fn cancel_learn(&mut self) {
+        self.learning = None;
+    }
+
+    /// The commands a message maps to. A message that completes MIDI
+    /// learn is consumed by it and maps to nothing.
+    pub fn translate(&mut self, message: MidiMessage) -> impl Iterator<Item = EngineCommand> + '_ {
+        let (source, value) = MidiSource::of(message);
+        let learned = !matches!(message, MidiMessage::NoteOff { .. })
+            && self.learning.take().is_some_and(|target| {
+                self.mappings.push(MidiMapping { source, target });
+                true
+            });
+        self.mappings
+            .iter()
+            .filter(move |mapping| !learned && mapping.source.matches(source))
+            .filter_map(move |mapping| mapping.target.command(value))
+    }
+}
@@ -0,0 +1,127 @@
+//! MIDI input
+//!
+//! [`MidiInput`] listens to a MIDI port through midir. Its callback runs on
+//! midir's thread, never the audio thread: each message is parsed into a
+//! [`MidiMessage`], stamped with the time it arrived and run through a
+//! [`MidiMap`], which turns notes, controllers and program changes into
+//! [`EngineCommand`]s. The commands queue up until the control thread
+//! collects them.
+//!
+//! The map is filled by hand or by MIDI learn: arm a target with
+//! [`MidiMap::learn`] and the next note, controller or program change to
+//! arrive is bound to it.
+//!
+//! Commands can be sent to an [`Engine`] as they are, landing at the start
+//! of the next block, or scheduled into a [`ParamEventList`] with
+//! [`MidiInput::schedule`] for hosts that run an [`EffectChain`] with
+//! [`EffectChain::process_block`]; parameter changes then land on the
+//! frame matching their arrival time.
+//!
+//! The [`sync`] module slaves the transport to MIDI clock or MIDI Time Code
+//! arriving on the same port, and sends MIDI clock when the engine is the
+//! master.
+//!
+//! [`EngineCommand`]: crate::channel::EngineCommand
+//! [`Engine`]: crate::engine::Engine
+//! [`ParamEventList`]: crate::dsp::automation::ParamEventList
+//! [`EffectChain`]: crate::dsp::chain::EffectChain
+//! [`EffectChain::process_block`]: crate::dsp::chain::EffectChain::process_block
+
+pub mod input;
+pub mod map;
+pub mod sync;
+
+pub use input::{MidiInput, TimedCommand};
+pub use map::{MidiMap, MidiMapping, MidiSource, MidiTarget};
+pub use sync::{ClockFollower, ClockOutput, SyncFollower, SyncSettings, TimecodeFollower};
+
+pub use crate::types::{FrameRate, Timecode};
+
+/// A channel message. Channels count from zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    /// Bend from 0 to 16383, centred on 8192
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+}
+
+impl MidiMessage {
+    /// Parses one complete message. System messages, and anything
+    /// malformed, give `None`. A note on with zero velocity is a note off.
+    #[must_use]
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (&status, data) = bytes.split_first()?;
+        let channel = status & 0x0F;
+        let data1 = data.first().copied().filter(|byte| *byte < 0x80);
+        let data2 = data.get(1).copied().filter(|byte| *byte < 0x80);
+        match status & 0xF0 {
+            0x80 => Some(Self::NoteOff {
+                channel,
+                note: data1?,
+                velocity: data2?,
+            }),
+            0x90 => {
+                let (note, velocity) = (data1?, data2?);
+                Some(if velocity == 0 {
+                    Self::NoteOff {
+                        channel,
+                        note,
+                        velocity,
+                    }
+                } else {
+                    Self::NoteOn {
+                        channel,
+                        note,
+                        velocity,
+                    }
+                })
+            }
+            0xB0 => Some(Self::ControlChange {
+                channel,
+                controller: data1?,
+                value: data2?,
+            }),
+            0xC0 => Some(Self::ProgramChange {
+                channel,
+                program: data1?,
+            }),
+            0xE0 => Some(Self::PitchBend {
+                channel,
+                value: u16::from(data2?) << 7 | u16::from(data1?),
+            }),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn channel(self) -> u8 {
+        match self {
+            Self::NoteOn { channel, .. }
+            | Self::NoteOff { channel, .. }
+            | Self::ControlChange { channel, .. }
+            | Self::ProgramChange { channel, .. }
+            | Self::PitchBend { channel, .. } => channel,
+        }
+    }
+}
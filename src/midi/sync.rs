@@ -0,0 +1,717 @@
+//! MIDI clock and MIDI Time Code sync
+//!
+//! A follower slaves the engine's transport to another device. It reads
+//! the system messages of a port and turns them into [`EngineCommand`]s:
+//! [`ClockFollower`] follows MIDI clock, deriving the tempo from the 24
+//! ticks per quarter note and the position from song position pointers;
+//! [`TimecodeFollower`] follows MIDI Time Code, deriving the position from
+//! quarter frames and full-frame messages. [`MidiInput::set_sync`] runs one
+//! on an open port.
+//!
+//! Tick and quarter frame arrival times jitter with the MIDI driver, so
+//! neither follower passes them on as they are. The clock follower
+//! measures the tick interval across the last [`SyncSettings::smoothing`]
+//! ticks, so a late tick shifts the tempo by its lateness divided by the
+//! whole window, evens the result out over a beat, and only sends tempo
+//! changes bigger than [`SyncSettings::tempo_tolerance`]. The timecode
+//! follower lets the engine roll on its own clock and only moves the
+//! playhead when the timecode drifts further than
+//! [`SyncSettings::chase_threshold`] from it.
+//!
+//! When ticks or quarter frames stop arriving for
+//! [`SyncSettings::dropout`], the source has dropped out. The transport
+//! freewheels at the last tempo for [`SyncSettings::freewheel`], then
+//! pauses; if the source comes back in time, nothing is lost. Drop-outs
+//! are noticed in [`poll`](SyncFollower::poll), which the input calls
+//! whenever its commands are collected.
+//!
+//! When the engine is the master instead, [`ClockOutput`] sends MIDI clock
+//! at its tempo.
+//!
+//! [`EngineCommand`]: crate::channel::EngineCommand
+//! [`MidiInput::set_sync`]: crate::midi::MidiInput::set_sync
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::channel::EngineCommand;
+use crate::error::{AudioEngineError, Result};
+use crate::types::{FrameRate, SampleRate, Tempo, Timecode, Timestamp};
+
+/// MIDI clock ticks per quarter note
+pub const TICKS_PER_BEAT: u32 = 24;
+/// Client name shown to the MIDI system
+const CLIENT_NAME: &str = "audio_engine";
+
+const CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const CONTINUE: u8 = 0xFB;
+const STOP: u8 = 0xFC;
+const SONG_POSITION: u8 = 0xF2;
+const QUARTER_FRAME: u8 = 0xF1;
+const SYSEX: u8 = 0xF0;
+
+/// How a follower smooths its source and copes with it dropping out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncSettings {
+    /// Clock ticks the tick interval is measured across. Longer windows
+    /// steady the tempo but follow the master's tempo changes later.
+    pub smoothing: u32,
+    /// Smallest tempo change, in BPM, passed on to the engine
+    pub tempo_tolerance: f64,
+    /// Timecode drift from the engine's own clock tolerated before the
+    /// playhead is moved to catch up
+    pub chase_threshold: Duration,
+    /// Silence after which the source counts as dropped out
+    pub dropout: Duration,
+    /// How long the transport keeps rolling after a drop-out before it
+    /// pauses
+    pub freewheel: Duration,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            smoothing: 4 * TICKS_PER_BEAT,
+            tempo_tolerance: 0.1,
+            chase_threshold: Duration::from_millis(40),
+            dropout: Duration::from_millis(250),
+            freewheel: Duration::from_secs(2),
+        }
+    }
+}
+
+impl SyncSettings {
+    #[must_use]
+    pub const fn with_smoothing(mut self, ticks: u32) -> Self {
+        self.smoothing = ticks;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_tempo_tolerance(mut self, bpm: f64) -> Self {
+        self.tempo_tolerance = bpm;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_chase_threshold(mut self, threshold: Duration) -> Self {
+        self.chase_threshold = threshold;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_dropout(mut self, dropout: Duration) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_freewheel(mut self, freewheel: Duration) -> Self {
+        self.freewheel = freewheel;
+        self
+    }
+}
+
+/// Where a source stands after its last message arrived at `last`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    Present,
+    /// Dropped out; the transport is freewheeling
+    Freewheeling,
+    /// Gone for longer than the freewheel time
+    Lost,
+}
+
+impl Signal {
+    fn at(settings: &SyncSettings, last: Instant, now: Instant) -> Self {
+        let silence = now.saturating_duration_since(last);
+        if silence < settings.dropout {
+            Self::Present
+        } else if silence < settings.dropout + settings.freewheel {
+            Self::Freewheeling
+        } else {
+            Self::Lost
+        }
+    }
+}
+
+/// Follows MIDI clock: tempo from ticks, position from song position
+/// pointers, and start, stop and continue.
+#[derive(Debug, Clone)]
+pub struct ClockFollower {
+    settings: SyncSettings,
+    /// Arrival of the last clock message
+    last_tick: Option<Instant>,
+    /// Arrival of the ticks in the smoothing window, oldest first
+    window: VecDeque<Instant>,
+    /// Smoothed seconds between ticks
+    interval: Option<f64>,
+    /// Tempo last sent to the engine
+    sent: Option<Tempo>,
+    /// Whether the master's transport is running
+    running: bool,
+    signal: Signal,
+    /// Ticks since the start of the song
+    ticks: u64,
+}
+
+impl ClockFollower {
+    #[must_use]
+    pub const fn new(settings: SyncSettings) -> Self {
+        Self {
+            settings,
+            last_tick: None,
+            window: VecDeque::new(),
+            interval: None,
+            sent: None,
+            running: false,
+            signal: Signal::Present,
+            ticks: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &SyncSettings {
+        &self.settings
+    }
+
+    /// The master's tempo, once two ticks have arrived
+    #[must_use]
+    pub fn tempo(&self) -> Option<Tempo> {
+        self.interval
+            .map(|interval| Tempo::new(60.0 / (interval * f64::from(TICKS_PER_BEAT))))
+    }
+
+    /// The master's position in quarter notes, counted in ticks
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn beats(&self) -> f64 {
+        self.ticks as f64 / f64::from(TICKS_PER_BEAT)
+    }
+
+    /// Whether the master's transport is running
+    #[must_use]
+    pub const fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Handles a message that arrived at `time`, passing the commands it
+    /// leads to to `emit`. Returns whether it was a clock message.
+    pub fn receive(
+        &mut self,
+        bytes: &[u8],
+        time: Instant,
+        mut emit: impl FnMut(EngineCommand),
+    ) -> bool {
+        match *bytes {
+            [CLOCK, ..] => self.tick(time, &mut emit),
+            [START, ..] => {
+                // Strictly the song starts on the next tick; the difference
+                // is one tick
+                self.ticks = 0;
+                self.running = true;
+                self.signal = Signal::Present;
+                self.last_tick = Some(time);
+                emit(EngineCommand::SeekBeats(0.0));
+                emit(EngineCommand::Start);
+            }
+            [CONTINUE, ..] => {
+                self.running = true;
+                self.signal = Signal::Present;
+                self.last_tick = Some(time);
+                emit(EngineCommand::Resume);
+            }
+            [STOP, ..] => {
+                self.running = false;
+                emit(EngineCommand::Pause);
+            }
+            [SONG_POSITION, lsb, msb, ..] if lsb < 0x80 && msb < 0x80 => {
+                // Counted in sixteenth notes, six ticks each
+                let sixteenths = u16::from(msb) << 7 | u16::from(lsb);
+                self.ticks = u64::from(sixteenths) * 6;
+                emit(EngineCommand::SeekBeats(f64::from(sixteenths) / 4.0));
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn tick(&mut self, time: Instant, emit: &mut impl FnMut(EngineCommand)) {
+        // A gap as long as a drop-out says nothing about the tempo
+        if self
+            .window
+            .back()
+            .is_some_and(|last| time.saturating_duration_since(*last) >= self.settings.dropout)
+        {
+            self.window.clear();
+        }
+        let size = self.settings.smoothing.max(1) as usize + 1;
+        while self.window.len() >= size {
+            self.window.pop_front();
+        }
+        self.window.push_back(time);
+        if let (Some(first), Some(last)) = (self.window.front(), self.window.back())
+            // Wait for a beat's worth before trusting the interval
+            && self.window.len() > size.min(TICKS_PER_BEAT as usize + 1) - 1
+            && last > first
+        {
+            #[allow(clippy::cast_precision_loss)]
+            let ticks = (self.window.len() - 1) as f64;
+            let measured = last.duration_since(*first).as_secs_f64() / ticks;
+            // The window still moves a little with the ticks entering and
+            // leaving it; a one-beat average on top evens that out
+            self.interval = Some(self.interval.map_or(measured, |interval| {
+                interval + (measured - interval) / f64::from(TICKS_PER_BEAT)
+            }));
+            self.send_tempo(emit);
+        }
+        self.last_tick = Some(time);
+        if self.running {
+            self.ticks += 1;
+            if self.signal == Signal::Lost {
+                log::info!("MIDI clock is back, resuming");
+                emit(EngineCommand::Resume);
+            }
+        }
+        self.signal = Signal::Present;
+    }
+
+    fn send_tempo(&mut self, emit: &mut impl FnMut(EngineCommand)) {
+        let Some(tempo) = self.tempo() else {
+            return;
+        };
+        let changed = self
+            .sent
+            .is_none_or(|sent| (tempo.bpm() - sent.bpm()).abs() >= self.settings.tempo_tolerance);
+        if changed {
+            self.sent = Some(tempo);
+            emit(EngineCommand::SetTempo(tempo));
+        }
+    }
+
+    /// Checks for a drop-out at `now`, pausing the transport once the
+    /// clock has been gone for the freewheel time.
+    pub fn poll(&mut self, now: Instant, mut emit: impl FnMut(EngineCommand)) {
+        let Some(last) = self.last_tick.filter(|_| self.running) else {
+            return;
+        };
+        let signal = Signal::at(&self.settings, last, now);
+        if signal == self.signal || signal == Signal::Present {
+            return;
+        }
+        if signal == Signal::Freewheeling {
+            log::warn!("MIDI clock dropped out, freewheeling");
+        } else {
+            log::warn!("MIDI clock lost, pausing");
+            emit(EngineCommand::Pause);
+        }
+        self.signal = signal;
+    }
+}
+
+/// The frame rate in the rate bits of an MTC hours byte
+const fn mtc_frame_rate(bits: u8) -> FrameRate {
+    match bits & 0x03 {
+        0 => FrameRate::Fps24,
+        1 => FrameRate::Fps25,
+        2 => FrameRate::Fps2997Drop,
+        _ => FrameRate::Fps30,
+    }
+}
+
+/// The timecode in the hours (with the rate bits), minutes, seconds and
+/// frames bytes of a full-frame message or eight quarter frames
+const fn mtc_timecode(hours: u8, minutes: u8, seconds: u8, frames: u8) -> Timecode {
+    Timecode {
+        hours: hours & 0x1F,
+        minutes: minutes & 0x3F,
+        seconds: seconds & 0x3F,
+        frames: frames & 0x1F,
+        rate: mtc_frame_rate(hours >> 5),
+    }
+}
+
+/// Follows MIDI Time Code: quarter frames while the master rolls, and
+/// full-frame messages when it locates.
+#[derive(Debug, Clone)]
+pub struct TimecodeFollower {
+    sample_rate: SampleRate,
+    settings: SyncSettings,
+    /// Nibbles of the timecode being received
+    pieces: [u8; 8],
+    /// Bit per piece received since piece 0
+    received: u8,
+    /// Arrival of the last quarter frame
+    last_frame: Option<Instant>,
+    /// Timecode seconds at an arrival time the engine was placed at,
+    /// predicting where it is since
+    anchor: Option<(Instant, f64)>,
+    timecode: Option<Timecode>,
+    running: bool,
+    signal: Signal,
+}
+
+impl TimecodeFollower {
+    #[must_use]
+    pub const fn new(sample_rate: SampleRate, settings: SyncSettings) -> Self {
+        Self {
+            sample_rate,
+            settings,
+            pieces: [0; 8],
+            received: 0,
+            last_frame: None,
+            anchor: None,
+            timecode: None,
+            running: false,
+            signal: Signal::Present,
+        }
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> &SyncSettings {
+        &self.settings
+    }
+
+    /// The last complete timecode received
+    #[must_use]
+    pub const fn timecode(&self) -> Option<Timecode> {
+        self.timecode
+    }
+
+    /// Whether quarter frames are arriving
+    #[must_use]
+    pub const fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Handles a message that arrived at `time`, passing the commands it
+    /// leads to to `emit`. Returns whether it was a timecode message.
+    pub fn receive(
+        &mut self,
+        bytes: &[u8],
+        time: Instant,
+        mut emit: impl FnMut(EngineCommand),
+    ) -> bool {
+        match *bytes {
+            [QUARTER_FRAME, data, ..] if data < 0x80 => {
+                self.quarter_frame(data, time, &mut emit);
+            }
+            // Full frame: F0 7F <device> 01 01 hh mm ss ff F7
+            [
+                SYSEX,
+                0x7F,
+                _,
+                0x01,
+                0x01,
+                hours,
+                minutes,
+                seconds,
+                frames,
+                ..,
+            ] => {
+                let timecode = mtc_timecode(hours, minutes, seconds, frames);
+                self.timecode = Some(timecode);
+                self.received = 0;
+                if self.running {
+                    self.anchor = Some((time, timecode.as_seconds()));
+                }
+                emit(EngineCommand::Seek(timecode.to_timestamp(self.sample_rate)));
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn quarter_frame(&mut self, data: u8, time: Instant, emit: &mut impl FnMut(EngineCommand)) {
+        self.last_frame = Some(time);
+        let (piece, value) = (usize::from(data >> 4), data & 0x0F);
+        if piece == 0 {
+            self.received = 0;
+        }
+        self.pieces[piece] = value;
+        self.received |= 1 << piece;
+        if piece != 7 || self.received != 0xFF {
+            return;
+        }
+        self.received = 0;
+        let byte = |low: usize| self.pieces[low] | self.pieces[low + 1] << 4;
+        let timecode = mtc_timecode(byte(6), byte(4), byte(2), byte(0));
+        self.timecode = Some(timecode);
+        // The eight pieces take two frames to send, so the timecode they
+        // carry is two frames old when the last arrives
+        let seconds = timecode.as_seconds() + 2.0 / timecode.rate.fps();
+        let position = Timestamp::from_duration(Duration::from_secs_f64(seconds), self.sample_rate);
+        if !self.running {
+            self.running = true;
+            self.signal = Signal::Present;
+            self.anchor = Some((time, seconds));
+            emit(EngineCommand::Seek(position));
+            emit(EngineCommand::Resume);
+            return;
+        }
+        self.signal = Signal::Present;
+        let drift = self.anchor.map_or(f64::INFINITY, |(at, anchor)| {
+            (anchor + time.saturating_duration_since(at).as_secs_f64() - seconds).abs()
+        });
+        if drift > self.settings.chase_threshold.as_secs_f64() {
+            log::debug!("Timecode drifted {drift:.3}s, chasing");
+            self.anchor = Some((time, seconds));
+            emit(EngineCommand::Seek(position));
+        }
+    }
+
+    /// Checks for a drop-out at `now`, pausing the transport once the
+    /// timecode has been gone for the freewheel time. Timecode has no stop
+    /// message, so this is also how a stopped master stops the engine.
+    pub fn poll(&mut self, now: Instant, mut emit: impl FnMut(EngineCommand)) {
+        let Some(last) = self.last_frame.filter(|_| self.running) else {
+            return;
+        };
+        let signal = Signal::at(&self.settings, last, now);
+        if signal == self.signal || signal == Signal::Present {
+            return;
+        }
+        if signal == Signal::Freewheeling {
+            log::warn!("MIDI timecode dropped out, freewheeling");
+        } else {
+            log::info!("MIDI timecode stopped, pausing");
+            self.running = false;
+            emit(EngineCommand::Pause);
+        }
+        self.signal = signal;
+    }
+}
+
+/// The follower a [`MidiInput`](crate::midi::MidiInput) runs.
+#[derive(Debug, Clone)]
+pub enum SyncFollower {
+    Clock(ClockFollower),
+    Timecode(TimecodeFollower),
+}
+
+impl SyncFollower {
+    /// Handles a message that arrived at `time`. Returns whether it was
+    /// one the follower reads.
+    pub fn receive(
+        &mut self,
+        bytes: &[u8],
+        time: Instant,
+        emit: impl FnMut(EngineCommand),
+    ) -> bool {
+        match self {
+            Self::Clock(follower) => follower.receive(bytes, time, emit),
+            Self::Timecode(follower) => follower.receive(bytes, time, emit),
+        }
+    }
+
+    /// Checks for a drop-out at `now`.
+    pub fn poll(&mut self, now: Instant, emit: impl FnMut(EngineCommand)) {
+        match self {
+            Self::Clock(follower) => follower.poll(now, emit),
+            Self::Timecode(follower) => follower.poll(now, emit),
+        }
+    }
+}
+
+/// Sends MIDI clock to an output port, for when the engine is the
+/// master.
+///
+/// Ticks are sent from a thread of their own, continuously so followers
+/// can lock to the tempo before the song starts. Start, stop and
+/// song position are sent when asked; hosts send them alongside the
+/// matching engine commands. The thread stops when this is dropped.
+pub struct ClockOutput {
+    port: String,
+    connection: Arc<Mutex<midir::MidiOutputConnection>>,
+    tempo: Arc<Mutex<Tempo>>,
+    quit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ClockOutput {
+    /// Names of the MIDI output ports
+    ///
+    /// # Errors
+    /// Returns an error if the MIDI system can't be reached.
+    pub fn ports() -> Result<Vec<String>> {
+        let output = Self::client()?;
+        Ok(output
+            .ports()
+            .iter()
+            .filter_map(|port| output.port_name(port).ok())
+            .collect())
+    }
+
+    /// Connects to the first port whose name contains `name` and starts
+    /// sending ticks at `tempo`.
+    ///
+    /// # Errors
+    /// Returns an error if the MIDI system can't be reached, there is no
+    /// such port, or connecting to it or starting the thread fails.
+    pub fn open(name: &str, tempo: Tempo) -> Result<Self> {
+        let output = Self::client()?;
+        let (port, port_name) = output
+            .ports()
+            .into_iter()
+            .find_map(|port| {
+                let port_name = output.port_name(&port).ok()?;
+                port_name.contains(name).then_some((port, port_name))
+            })
+            .ok_or_else(|| AudioEngineError::DeviceNotFound {
+                device_name: name.to_string(),
+            })?;
+        let connection =
+            output
+                .connect(&port, CLIENT_NAME)
+                .map_err(|e| AudioEngineError::DeviceAccess {
+                    message: format!("Failed to connect to MIDI port {port_name}: {e}"),
+                })?;
+        let connection = Arc::new(Mutex::new(connection));
+        let tempo = Arc::new(Mutex::new(tempo));
+        let quit = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name("midi-clock".to_string())
+            .spawn({
+                let (connection, tempo, quit) = (
+                    Arc::clone(&connection),
+                    Arc::clone(&tempo),
+                    Arc::clone(&quit),
+                );
+                move || send_ticks(&connection, &tempo, &quit)
+            })
+            .map_err(|e| AudioEngineError::DeviceAccess {
+                message: format!("Failed to start MIDI clock thread: {e}"),
+            })?;
+        log::info!("Sending MIDI clock to {port_name}");
+        Ok(Self {
+            port: port_name,
+            connection,
+            tempo,
+            quit,
+            thread: Some(thread),
+        })
+    }
+
+    fn client() -> Result<midir::MidiOutput> {
+        midir::MidiOutput::new(CLIENT_NAME).map_err(|e| AudioEngineError::DeviceAccess {
+            message: format!("Failed to open MIDI output: {e}"),
+        })
+    }
+
+    /// Name of the connected port
+    #[must_use]
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    #[must_use]
+    pub fn tempo(&self) -> Tempo {
+        *self.tempo.lock()
+    }
+
+    /// Changes the tempo from the next tick on.
+    pub fn set_tempo(&self, tempo: Tempo) {
+        *self.tempo.lock() = tempo;
+    }
+
+    /// Sends start, which followers take as playing from the top.
+    ///
+    /// # Errors
+    /// Returns an error if the message can't be sent.
+    pub fn start(&self) -> Result<()> {
+        self.send(&[START])
+    }
+
+    /// # Errors
+    /// Returns an error if the message can't be sent.
+    pub fn stop(&self) -> Result<()> {
+        self.send(&[STOP])
+    }
+
+    /// Sends continue, which followers take as playing from where they
+    /// are.
+    ///
+    /// # Errors
+    /// Returns an error if the message can't be sent.
+    pub fn resume(&self) -> Result<()> {
+        self.send(&[CONTINUE])
+    }
+
+    /// Sends a song position `beats` quarter notes from the top, rounded
+    /// to the sixteenth note the message counts in.
+    ///
+    /// # Errors
+    /// Returns an error if the message can't be sent.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn song_position(&self, beats: f64) -> Result<()> {
+        let sixteenths = (beats * 4.0).round().clamp(0.0, 16383.0) as u16;
+        self.send(&[
+            SONG_POSITION,
+            (sixteenths & 0x7F) as u8,
+            (sixteenths >> 7) as u8,
+        ])
+    }
+
+    fn send(&self, message: &[u8]) -> Result<()> {
+        self.connection
+            .lock()
+            .send(message)
+            .map_err(|e| AudioEngineError::DeviceAccess {
+                message: format!("Failed to send to MIDI port {}: {e}", self.port),
+            })
+    }
+}
+
+impl Drop for ClockOutput {
+    fn drop(&mut self) {
+        self.quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl std::fmt::Debug for ClockOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClockOutput")
+            .field("port", &self.port)
+            .field("tempo", &self.tempo())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Runs on the clock thread, sending a tick every 24th of a beat. Ticks
+/// are due at fixed times rather than a sleep after each, so late wake-ups
+/// don't add up.
+fn send_ticks(
+    connection: &Mutex<midir::MidiOutputConnection>,
+    tempo: &Mutex<Tempo>,
+    quit: &AtomicBool,
+) {
+    let mut due = Instant::now();
+    let mut failed = false;
+    while !quit.load(Ordering::Relaxed) {
+        let sent = connection.lock().send(&[CLOCK]);
+        if let Err(e) = sent {
+            if !failed {
+                log::warn!("Failed to send MIDI clock: {e}");
+            }
+            failed = true;
+        }
+        let seconds = tempo.lock().beat_seconds() / f64::from(TICKS_PER_BEAT);
+        due += Duration::from_secs_f64(seconds);
+        let now = Instant::now();
+        if due > now {
+            std::thread::sleep(due - now);
+        } else if now - due > Duration::from_secs_f64(seconds) {
+            // Too far behind to catch up without a burst of ticks
+            due = now;
+        }
+    }
+}
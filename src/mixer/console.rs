@@ -0,0 +1,1007 @@
+//! Mixing console with aux sends and return buses
+//!
+//! A [`Mixer`] sums any number of input channels into a master bus. Each
+//! channel has an insert chain, gain, pan, mute and solo, plus a send level
+//! to every return bus. Return buses carry the shared effects (a reverb, a
+//! delay) fed by those sends, and are summed into the master bus alongside
+//! the channels. The master bus has its own chain.
+//!
+//! ```text
+//! input ─ M/S ─ inserts ─ gain/pan ─┬──────────────────┬─ master chain ─ out
+//!                                   └─ send ─ return ──┘
+//! ```
+//!
+//! Sends are taken after the fader, so muting or pulling down a channel
+//! also pulls down its effects. All buffers are allocated when the mixer is
+//! created; processing doesn't allocate.
+//!
+//! A stereo mixer's channel fed by a mid-side microphone pair, mid on the
+//! left input and side on the right, can decode it to left and right with
+//! [`Mixer::set_input_mode`] before anything else sees it. The side gain
+//! sets the width: unity is the pair as recorded, less narrows the image
+//! towards the mid mic alone and more widens it.
+//!
+//! A channel can be ducked by another, its key, with
+//! [`Mixer::set_ducking`]: music is turned down while the voice channel
+//! keying it is above a threshold. The key is taken after its fader and
+//! the ducking applies before the sends.
+//!
+//! Channels can also be listened to before or after their fader with
+//! [`Mixer::set_listen`], on a cue bus that feeds the monitor output
+//! rather than the master; see [`monitor`](crate::mixer::monitor). Listen
+//! and monitor settings belong to the operator rather than the show, so
+//! scenes leave them alone.
+
+use crate::buffer::memory::heap_bytes;
+use crate::buffer::realtime::AudioBuffer;
+use crate::buffer::{MemoryKind, MemoryReport, RingBufferWriter};
+use crate::dsp::chain::EffectChain;
+use crate::dsp::params::SmoothParam;
+use crate::dsp::preset::ChainPreset;
+use crate::dsp::quality::EffectQuality;
+use crate::dsp::random::derive_seed;
+use crate::engine::SafetySettings;
+use crate::error::{AudioEngineError, Result};
+use crate::mixer::ducker::{Ducker, DuckerSettings};
+use crate::mixer::monitor::{Listen, Monitor, MonitorSettings};
+use crate::types::{ChannelCount, Gain, Pan, Sample, SampleRate};
+
+/// Ramp length for gain, pan and send changes, in milliseconds
+const SMOOTHING_MS: u32 = 10;
+
+/// Commands for a [`Mixer`], typically sent from the control thread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MixerCommand {
+    SetGain {
+        channel: usize,
+        gain: Gain,
+    },
+    SetPan {
+        channel: usize,
+        pan: Pan,
+    },
+    SetMute {
+        channel: usize,
+        muted: bool,
+    },
+    SetSolo {
+        channel: usize,
+        soloed: bool,
+    },
+    /// Level a channel sends to a return bus
+    SetSend {
+        channel: usize,
+        bus: usize,
+        level: Gain,
+    },
+    SetReturnGain {
+        bus: usize,
+        gain: Gain,
+    },
+    SetReturnMute {
+        bus: usize,
+        muted: bool,
+    },
+    SetMasterGain(Gain),
+    /// Ducks `channel` while `key` is loud
+    SetDucking {
+        channel: usize,
+        key: usize,
+        settings: DuckerSettings,
+    },
+    ClearDucking {
+        channel: usize,
+    },
+    /// Puts a channel on the cue bus, or takes it off
+    SetListen {
+        channel: usize,
+        listen: Listen,
+    },
+    SetMonitor(MonitorSettings),
+    SetInputMode {
+        channel: usize,
+        mode: InputMode,
+    },
+}
+
+/// How a channel reads its input.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InputMode {
+    /// The input as it comes
+    #[default]
+    Normal,
+    /// Mid on the first input channel and side on the second, decoded to
+    /// left `mid + side` and right `mid - side` with the side scaled by
+    /// `side_gain`
+    MidSide { side_gain: Gain },
+}
+
+/// A channel's ducker and the channel keying it.
+#[derive(Debug)]
+struct Ducking {
+    key: usize,
+    ducker: Ducker,
+}
+
+/// One input channel of a [`Mixer`].
+#[derive(Debug)]
+pub struct MixerChannel {
+    inserts: EffectChain,
+    gain: Gain,
+    pan: Pan,
+    muted: bool,
+    soloed: bool,
+    sends: Vec<Gain>,
+    /// Gain after mute and solo
+    level: SmoothParam,
+    left: SmoothParam,
+    right: SmoothParam,
+    send_levels: Vec<SmoothParam>,
+    ducking: Option<Ducking>,
+    listen: Listen,
+    input_mode: InputMode,
+    /// Side gain while decoding mid-side
+    side: SmoothParam,
+    scratch: AudioBuffer,
+}
+
+impl MixerChannel {
+    /// Effects run on the channel before the fader
+    #[must_use]
+    pub const fn inserts(&self) -> &EffectChain {
+        &self.inserts
+    }
+
+    pub const fn inserts_mut(&mut self) -> &mut EffectChain {
+        &mut self.inserts
+    }
+
+    #[must_use]
+    pub const fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    #[must_use]
+    pub const fn pan(&self) -> Pan {
+        self.pan
+    }
+
+    #[must_use]
+    pub const fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    #[must_use]
+    pub const fn is_soloed(&self) -> bool {
+        self.soloed
+    }
+
+    /// Send level to a return bus, or `None` if there is no such bus
+    #[must_use]
+    pub fn send(&self, bus: usize) -> Option<Gain> {
+        self.sends.get(bus).copied()
+    }
+
+    /// The channel keying this one's ducker, and the ducker
+    #[must_use]
+    pub fn ducking(&self) -> Option<(usize, &Ducker)> {
+        self.ducking
+            .as_ref()
+            .map(|ducking| (ducking.key, &ducking.ducker))
+    }
+
+    /// Where the channel is taken for the cue bus, if it is
+    #[must_use]
+    pub const fn listen(&self) -> Listen {
+        self.listen
+    }
+
+    #[must_use]
+    pub const fn input_mode(&self) -> InputMode {
+        self.input_mode
+    }
+}
+
+/// A return bus: the sum of the sends to it, run through its chain.
+#[derive(Debug)]
+pub struct ReturnBus {
+    chain: EffectChain,
+    gain: Gain,
+    muted: bool,
+    level: SmoothParam,
+    buffer: AudioBuffer,
+}
+
+impl ReturnBus {
+    #[must_use]
+    pub const fn chain(&self) -> &EffectChain {
+        &self.chain
+    }
+
+    pub const fn chain_mut(&mut self) -> &mut EffectChain {
+        &mut self.chain
+    }
+
+    #[must_use]
+    pub const fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    #[must_use]
+    pub const fn is_muted(&self) -> bool {
+        self.muted
+    }
+}
+
+/// Settings of one mixer channel.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelScene {
+    pub gain: Gain,
+    pub pan: Pan,
+    pub muted: bool,
+    pub soloed: bool,
+    /// Send level to each return bus
+    pub sends: Vec<Gain>,
+    pub inserts: ChainPreset,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ducking: Option<DuckingScene>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub input_mode: InputMode,
+}
+
+/// Ducking of one mixer channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DuckingScene {
+    /// Channel whose level ducks this one
+    pub key: usize,
+    pub settings: DuckerSettings,
+}
+
+/// Settings of one return bus.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReturnScene {
+    pub gain: Gain,
+    pub muted: bool,
+    pub chain: ChainPreset,
+}
+
+/// Settings of a whole [`Mixer`], captured with [`Mixer::scene`].
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MixerScene {
+    pub channels: Vec<ChannelScene>,
+    pub returns: Vec<ReturnScene>,
+    pub master_gain: Gain,
+    pub master: ChainPreset,
+}
+
+/// Channels, return buses and a master bus.
+#[derive(Debug)]
+pub struct Mixer {
+    channels: Vec<MixerChannel>,
+    returns: Vec<ReturnBus>,
+    master: EffectChain,
+    master_gain: Gain,
+    master_level: SmoothParam,
+    master_buffer: AudioBuffer,
+    monitor: Monitor,
+    sample_rate: SampleRate,
+    channel_count: ChannelCount,
+    block_frames: usize,
+    /// Ramp for level changes; stretched while a scene is recalled
+    ramp_frames: u32,
+}
+
+impl Mixer {
+    /// Creates a mixer with `inputs` channels and `returns` return buses.
+    ///
+    /// Chains are initialized with `sample_rate` and `channels`. Blocks
+    /// longer than `max_block_frames` are processed in pieces.
+    ///
+    /// # Errors
+    /// Returns an error if `inputs` is zero.
+    pub fn new(
+        inputs: usize,
+        returns: usize,
+        channels: ChannelCount,
+        sample_rate: SampleRate,
+        max_block_frames: usize,
+    ) -> Result<Self> {
+        if inputs == 0 {
+            return Err(AudioEngineError::configuration(
+                "mixer needs at least one input channel",
+            ));
+        }
+        let block_frames = max_block_frames.max(1);
+        let chain = || {
+            let mut chain = EffectChain::new();
+            chain.initialize(sample_rate, channels);
+            chain
+        };
+        Ok(Self {
+            channels: (0..inputs)
+                .map(|_| MixerChannel {
+                    inserts: chain(),
+                    gain: Gain::UNITY,
+                    pan: Pan::CENTER,
+                    muted: false,
+                    soloed: false,
+                    sends: vec![Gain::SILENCE; returns],
+                    level: SmoothParam::new(1.0),
+                    left: SmoothParam::new(1.0),
+                    right: SmoothParam::new(1.0),
+                    send_levels: (0..returns).map(|_| SmoothParam::new(0.0)).collect(),
+                    ducking: None,
+                    listen: Listen::Off,
+                    input_mode: InputMode::Normal,
+                    side: SmoothParam::new(1.0),
+                    scratch: AudioBuffer::new(block_frames, channels),
+                })
+                .collect(),
+            returns: (0..returns)
+                .map(|_| ReturnBus {
+                    chain: chain(),
+                    gain: Gain::UNITY,
+                    muted: false,
+                    level: SmoothParam::new(1.0),
+                    buffer: AudioBuffer::new(block_frames, channels),
+                })
+                .collect(),
+            master: chain(),
+            master_gain: Gain::UNITY,
+            master_level: SmoothParam::new(1.0),
+            master_buffer: AudioBuffer::new(block_frames, channels),
+            monitor: Monitor::new(channels, sample_rate, block_frames),
+            sample_rate,
+            channel_count: channels,
+            block_frames,
+            ramp_frames: sample_rate.samples_for_milliseconds(SMOOTHING_MS),
+        })
+    }
+
+    #[must_use]
+    pub const fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    #[must_use]
+    pub const fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    #[must_use]
+    pub const fn return_count(&self) -> usize {
+        self.returns.len()
+    }
+
+    #[must_use]
+    pub fn channel(&self, channel: usize) -> Option<&MixerChannel> {
+        self.channels.get(channel)
+    }
+
+    pub fn channel_mut(&mut self, channel: usize) -> Option<&mut MixerChannel> {
+        self.channels.get_mut(channel)
+    }
+
+    #[must_use]
+    pub fn return_bus(&self, bus: usize) -> Option<&ReturnBus> {
+        self.returns.get(bus)
+    }
+
+    pub fn return_bus_mut(&mut self, bus: usize) -> Option<&mut ReturnBus> {
+        self.returns.get_mut(bus)
+    }
+
+    #[must_use]
+    pub const fn master_chain(&self) -> &EffectChain {
+        &self.master
+    }
+
+    pub const fn master_chain_mut(&mut self) -> &mut EffectChain {
+        &mut self.master
+    }
+
+    #[must_use]
+    pub const fn master_gain(&self) -> Gain {
+        self.master_gain
+    }
+
+    /// Applies a command. Returns false if it referenced an unknown channel
+    /// or bus.
+    pub fn apply(&mut self, command: MixerCommand) -> bool {
+        match command {
+            MixerCommand::SetGain { channel, gain } => self.set_gain(channel, gain),
+            MixerCommand::SetPan { channel, pan } => self.set_pan(channel, pan),
+            MixerCommand::SetMute { channel, muted } => self.set_mute(channel, muted),
+            MixerCommand::SetSolo { channel, soloed } => self.set_solo(channel, soloed),
+            MixerCommand::SetSend {
+                channel,
+                bus,
+                level,
+            } => self.set_send(channel, bus, level),
+            MixerCommand::SetReturnGain { bus, gain } => self.set_return_gain(bus, gain),
+            MixerCommand::SetReturnMute { bus, muted } => self.set_return_mute(bus, muted),
+            MixerCommand::SetMasterGain(gain) => {
+                self.set_master_gain(gain);
+                true
+            }
+            MixerCommand::SetDucking {
+                channel,
+                key,
+                settings,
+            } => self.set_ducking(channel, key, settings),
+            MixerCommand::ClearDucking { channel } => self.clear_ducking(channel),
+            MixerCommand::SetListen { channel, listen } => self.set_listen(channel, listen),
+            MixerCommand::SetMonitor(settings) => {
+                self.set_monitor(settings);
+                true
+            }
+            MixerCommand::SetInputMode { channel, mode } => self.set_input_mode(channel, mode),
+        }
+    }
+
+    pub fn set_gain(&mut self, channel: usize, gain: Gain) -> bool {
+        let Some(strip) = self.channels.get_mut(channel) else {
+            return false;
+        };
+        strip.gain = gain;
+        self.update_levels();
+        true
+    }
+
+    /// Pans a stereo mixer's channel; other layouts ignore pan.
+    pub fn set_pan(&mut self, channel: usize, pan: Pan) -> bool {
+        let ramp = self.ramp();
+        let Some(strip) = self.channels.get_mut(channel) else {
+            return false;
+        };
+        strip.pan = pan;
+        let (left, right) = pan_gains(pan);
+        strip.left.set_target(left, ramp);
+        strip.right.set_target(right, ramp);
+        true
+    }
+
+    pub fn set_mute(&mut self, channel: usize, muted: bool) -> bool {
+        let Some(strip) = self.channels.get_mut(channel) else {
+            return false;
+        };
+        strip.muted = muted;
+        self.update_levels();
+        true
+    }
+
+    /// While any channel is soloed, only soloed channels are heard. Return
+    /// buses stay up, so soloed channels keep their effects.
+    pub fn set_solo(&mut self, channel: usize, soloed: bool) -> bool {
+        let Some(strip) = self.channels.get_mut(channel) else {
+            return false;
+        };
+        strip.soloed = soloed;
+        self.update_levels();
+        true
+    }
+
+    pub fn set_send(&mut self, channel: usize, bus: usize, level: Gain) -> bool {
+        let ramp = self.ramp();
+        let Some(strip) = self.channels.get_mut(channel) else {
+            return false;
+        };
+        let (Some(send), Some(smoothed)) =
+            (strip.sends.get_mut(bus), strip.send_levels.get_mut(bus))
+        else {
+            return false;
+        };
+        *send = level;
+        smoothed.set_target(level.as_linear(), ramp);
+        true
+    }
+
+    pub fn set_return_gain(&mut self, bus: usize, gain: Gain) -> bool {
+        let ramp = self.ramp();
+        let Some(bus) = self.returns.get_mut(bus) else {
+            return false;
+        };
+        bus.gain = gain;
+        bus.level.set_target(return_level(bus), ramp);
+        true
+    }
+
+    pub fn set_return_mute(&mut self, bus: usize, muted: bool) -> bool {
+        let ramp = self.ramp();
+        let Some(bus) = self.returns.get_mut(bus) else {
+            return false;
+        };
+        bus.muted = muted;
+        bus.level.set_target(return_level(bus), ramp);
+        true
+    }
+
+    /// Ducks `channel` while `key` is above the threshold in `settings`.
+    /// Changing the settings of an existing ducker carries on from its
+    /// current gain.
+    ///
+    /// Returns false if either channel doesn't exist or they are the same.
+    pub fn set_ducking(&mut self, channel: usize, key: usize, settings: DuckerSettings) -> bool {
+        if key == channel || key >= self.channels.len() {
+            return false;
+        }
+        let sample_rate = self.sample_rate;
+        let Some(strip) = self.channels.get_mut(channel) else {
+            return false;
+        };
+        match &mut strip.ducking {
+            Some(ducking) if ducking.key == key => ducking.ducker.set_settings(settings),
+            ducking => {
+                *ducking = Some(Ducking {
+                    key,
+                    ducker: Ducker::new(settings, sample_rate),
+                });
+            }
+        }
+        true
+    }
+
+    /// Stops ducking `channel`. Returns false if there is no such channel.
+    pub fn clear_ducking(&mut self, channel: usize) -> bool {
+        let Some(strip) = self.channels.get_mut(channel) else {
+            return false;
+        };
+        strip.ducking = None;
+        true
+    }
+
+    /// Puts `channel` on the cue bus before or after its fader, or takes
+    /// it off. Returns false if there is no such channel.
+    pub fn set_listen(&mut self, channel: usize, listen: Listen) -> bool {
+        let Some(strip) = self.channels.get_mut(channel) else {
+            return false;
+        };
+        strip.listen = listen;
+        true
+    }
+
+    /// Decodes `channel`'s input from mid-side, or stops decoding it, in a
+    /// stereo mixer; other layouts ignore the mode. A new side gain is
+    /// ramped to. Returns false if there is no such channel.
+    pub fn set_input_mode(&mut self, channel: usize, mode: InputMode) -> bool {
+        let ramp = self.ramp();
+        let Some(strip) = self.channels.get_mut(channel) else {
+            return false;
+        };
+        if let InputMode::MidSide { side_gain } = mode {
+            if matches!(strip.input_mode, InputMode::MidSide { .. }) {
+                strip.side.set_target(side_gain.as_linear(), ramp);
+            } else {
+                strip.side.set_immediate(side_gain.as_linear());
+            }
+        }
+        strip.input_mode = mode;
+        true
+    }
+
+    #[must_use]
+    pub const fn monitor(&self) -> &Monitor {
+        &self.monitor
+    }
+
+    /// Changes the monitor level, dim and mono, ramping to them.
+    pub fn set_monitor(&mut self, settings: MonitorSettings) {
+        self.monitor.set_settings(settings);
+    }
+
+    /// Sends the monitor output to `output`, usually the writer of a
+    /// second device's [`AudioOutputStream`], or stops sending it. Returns
+    /// the writer it was sent to before.
+    ///
+    /// Frames the writer has no room for are dropped and counted in
+    /// [`Monitor::dropped`]. The monitor output passes a safety limiter and
+    /// DC blocker first, with default [`SafetySettings`] unless changed
+    /// with [`set_cue_safety`](Self::set_cue_safety).
+    ///
+    /// [`AudioOutputStream`]: crate::audio::stream::AudioOutputStream
+    pub const fn set_cue_output(
+        &mut self,
+        output: Option<RingBufferWriter<Sample>>,
+    ) -> Option<RingBufferWriter<Sample>> {
+        self.monitor.set_output(output)
+    }
+
+    /// Sets the safety stage the monitor output passes last, as
+    /// [`EngineBuilder::with_safety`] does for the engine's output.
+    ///
+    /// [`EngineBuilder::with_safety`]: crate::engine::EngineBuilder::with_safety
+    pub fn set_cue_safety(&mut self, settings: SafetySettings) {
+        self.monitor.set_safety(settings, self.sample_rate);
+    }
+
+    pub fn set_master_gain(&mut self, gain: Gain) {
+        self.master_gain = gain;
+        self.master_level.set_target(gain.as_linear(), self.ramp());
+    }
+
+    /// Captures every level, switch and chain setting.
+    #[must_use]
+    pub fn scene(&self) -> MixerScene {
+        MixerScene {
+            channels: self
+                .channels
+                .iter()
+                .map(|strip| ChannelScene {
+                    gain: strip.gain,
+                    pan: strip.pan,
+                    muted: strip.muted,
+                    soloed: strip.soloed,
+                    sends: strip.sends.clone(),
+                    inserts: strip.inserts.preset(),
+                    ducking: strip.ducking.as_ref().map(|ducking| DuckingScene {
+                        key: ducking.key,
+                        settings: ducking.ducker.settings(),
+                    }),
+                    input_mode: strip.input_mode,
+                })
+                .collect(),
+            returns: self
+                .returns
+                .iter()
+                .map(|bus| ReturnScene {
+                    gain: bus.gain,
+                    muted: bus.muted,
+                    chain: bus.chain.preset(),
+                })
+                .collect(),
+            master_gain: self.master_gain,
+            master: self.master.preset(),
+        }
+    }
+
+    /// Recalls a scene, ramping every level to it over `transition_frames`.
+    /// Chain settings are applied as presets.
+    ///
+    /// Returns false if the scene has more channels or buses than the mixer,
+    /// or a chain rejected its preset; everything else is still applied.
+    pub fn recall(&mut self, scene: &MixerScene, transition_frames: u32) -> bool {
+        let ramp = self.ramp_frames;
+        self.ramp_frames = transition_frames;
+        let mut ok = scene.channels.len() <= self.channels.len()
+            && scene.returns.len() <= self.returns.len();
+        for (index, channel) in scene.channels.iter().enumerate() {
+            let Some(strip) = self.channels.get_mut(index) else {
+                break;
+            };
+            strip.gain = channel.gain;
+            strip.muted = channel.muted;
+            strip.soloed = channel.soloed;
+            ok &= strip.inserts.load_preset(&channel.inserts);
+            self.set_pan(index, channel.pan);
+            for (bus, &level) in channel.sends.iter().enumerate() {
+                ok &= self.set_send(index, bus, level);
+            }
+            ok &= match channel.ducking {
+                Some(ducking) => self.set_ducking(index, ducking.key, ducking.settings),
+                None => self.clear_ducking(index),
+            };
+            self.set_input_mode(index, channel.input_mode);
+        }
+        self.update_levels();
+        for (index, bus) in scene.returns.iter().enumerate() {
+            if let Some(target) = self.returns.get_mut(index) {
+                target.muted = bus.muted;
+                ok &= target.chain.load_preset(&bus.chain);
+                self.set_return_gain(index, bus.gain);
+            }
+        }
+        ok &= self.master.load_preset(&scene.master);
+        self.set_master_gain(scene.master_gain);
+        self.ramp_frames = ramp;
+        ok
+    }
+
+    /// Resets every chain and jumps all levels to their targets.
+    pub fn reset(&mut self) {
+        for strip in &mut self.channels {
+            strip.inserts.reset();
+            for param in [
+                &mut strip.level,
+                &mut strip.left,
+                &mut strip.right,
+                &mut strip.side,
+            ]
+            .into_iter()
+            .chain(&mut strip.send_levels)
+            {
+                param.set_immediate(param.target());
+            }
+            if let Some(ducking) = &mut strip.ducking {
+                ducking.ducker.reset();
+            }
+        }
+        for bus in &mut self.returns {
+            bus.chain.reset();
+            bus.level.set_immediate(bus.level.target());
+        }
+        self.master.reset();
+        self.master_level.set_immediate(self.master_level.target());
+        self.monitor.reset();
+    }
+
+    /// Reseeds every chain, each from a seed derived from `seed` and its
+    /// position: channels first, then return buses, then the master bus.
+    pub fn reseed(&mut self, seed: u64) {
+        let chains = self
+            .channels
+            .iter_mut()
+            .map(|strip| &mut strip.inserts)
+            .chain(self.returns.iter_mut().map(|bus| &mut bus.chain))
+            .chain(std::iter::once(&mut self.master));
+        for (index, chain) in (0u64..).zip(chains) {
+            chain.reseed(derive_seed(seed, index));
+        }
+    }
+
+    /// Moves the effects of every chain to a quality tier.
+    pub fn set_quality(&mut self, quality: EffectQuality) {
+        for strip in &mut self.channels {
+            strip.inserts.set_quality(quality);
+        }
+        for bus in &mut self.returns {
+            bus.chain.set_quality(quality);
+        }
+        self.master.set_quality(quality);
+    }
+
+    /// Memory held by every chain and bus buffer, channels numbered from 1.
+    #[must_use]
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport::new();
+        for (index, strip) in self.channels.iter().enumerate() {
+            let name = format!("channel {}", index + 1);
+            let bytes = size_of_val(strip.scratch.samples())
+                + heap_bytes(&strip.sends)
+                + heap_bytes(&strip.send_levels);
+            report.add(format!("{name}/buffer"), MemoryKind::Buffer, bytes);
+            report.append(&name, strip.inserts.memory_report());
+        }
+        for (index, bus) in self.returns.iter().enumerate() {
+            let name = format!("return {}", index + 1);
+            report.add(
+                format!("{name}/buffer"),
+                MemoryKind::Buffer,
+                size_of_val(bus.buffer.samples()),
+            );
+            report.append(&name, bus.chain.memory_report());
+        }
+        report.add(
+            "master/buffer",
+            MemoryKind::Buffer,
+            size_of_val(self.master_buffer.samples()),
+        );
+        report.append("master", self.master.memory_report());
+        report.add(
+            "monitor/buffer",
+            MemoryKind::Buffer,
+            self.monitor.buffer_bytes(),
+        );
+        report
+    }
+
+    /// Mixes one block of every input into `output`.
+    ///
+    /// Inputs are matched to channels by index and must have the mixer's
+    /// channel count; missing inputs and frames past the end of a shorter
+    /// input are silence.
+    pub fn process(&mut self, inputs: &[AudioBuffer], output: &mut AudioBuffer) {
+        self.process_into(inputs, output, None);
+    }
+
+    /// Like [`process`](Self::process), also writing the monitor output
+    /// into `monitor`, which should be as long as `output`.
+    pub fn process_with_monitor(
+        &mut self,
+        inputs: &[AudioBuffer],
+        output: &mut AudioBuffer,
+        monitor: &mut AudioBuffer,
+    ) {
+        self.process_into(inputs, output, Some(monitor));
+    }
+
+    fn process_into(
+        &mut self,
+        inputs: &[AudioBuffer],
+        output: &mut AudioBuffer,
+        mut monitor: Option<&mut AudioBuffer>,
+    ) {
+        let channel_count = self.channel_count.count_usize();
+        let frames = output.frames();
+        let mut start = 0;
+        while start < frames {
+            let len = self.block_frames.min(frames - start);
+            let range = start * channel_count..(start + len) * channel_count;
+            self.run(inputs, range.clone());
+            output.samples_mut()[range.clone()]
+                .copy_from_slice(&self.master_buffer.samples()[..len * channel_count]);
+            if let Some(out) = monitor
+                .as_deref_mut()
+                .and_then(|monitor| monitor.samples_mut().get_mut(range))
+            {
+                out.copy_from_slice(self.monitor.output(len * channel_count));
+            }
+            start += len;
+        }
+    }
+
+    /// Mixes the samples in `range` of every input into the master buffer.
+    fn run(&mut self, inputs: &[AudioBuffer], range: std::ops::Range<usize>) {
+        let channels = self.channel_count;
+        let channel_count = channels.count_usize();
+        let len = range.len();
+        let stereo = channels == ChannelCount::Stereo;
+
+        let master = &mut self.master_buffer.samples_mut()[..len];
+        master.fill(Sample::SILENCE);
+        for bus in &mut self.returns {
+            bus.buffer.samples_mut()[..len].fill(Sample::SILENCE);
+        }
+        let cue = self.monitor.cue_mut(len);
+        cue.fill(Sample::SILENCE);
+        let mut listening = false;
+
+        for (index, strip) in self.channels.iter_mut().enumerate() {
+            let scratch = &mut strip.scratch.samples_mut()[..len];
+            let input = inputs
+                .get(index)
+                .and_then(|input| input.samples().get(range.start..))
+                .unwrap_or(&[]);
+            let available = input.len().min(len);
+            scratch[..available].copy_from_slice(&input[..available]);
+            scratch[available..].fill(Sample::SILENCE);
+            if stereo && matches!(strip.input_mode, InputMode::MidSide { .. }) {
+                decode_mid_side(scratch, &mut strip.side);
+            }
+            strip.inserts.process(scratch, channels);
+            if strip.listen == Listen::PreFader {
+                mix(cue, scratch, 1.0);
+                listening = true;
+            }
+
+            for frame in scratch.chunks_exact_mut(channel_count) {
+                let level = strip.level.next();
+                let (left, right) = (strip.left.next(), strip.right.next());
+                for (i, sample) in frame.iter_mut().enumerate() {
+                    let pan = match (stereo, i) {
+                        (true, 0) => left,
+                        (true, _) => right,
+                        (false, _) => 1.0,
+                    };
+                    *sample = Sample::new(sample.value() * level * pan);
+                }
+            }
+        }
+
+        // Every key has passed its fader before anything is ducked
+        for index in 0..self.channels.len() {
+            let Some(key) = self.channels[index]
+                .ducking
+                .as_ref()
+                .map(|ducking| ducking.key)
+            else {
+                continue;
+            };
+            let Some((strip, key)) = pair_mut(&mut self.channels, index, key) else {
+                continue;
+            };
+            if let Some(ducking) = &mut strip.ducking {
+                ducking.ducker.process(
+                    &key.scratch.samples()[..len],
+                    &mut strip.scratch.samples_mut()[..len],
+                    channels,
+                );
+            }
+        }
+
+        for strip in &mut self.channels {
+            let scratch = &strip.scratch.samples()[..len];
+            if strip.listen == Listen::AfterFader {
+                mix(cue, scratch, 1.0);
+                listening = true;
+            }
+            for (frame_index, frame) in scratch.chunks_exact(channel_count).enumerate() {
+                let at = frame_index * channel_count;
+                mix(&mut master[at..at + channel_count], frame, 1.0);
+                for (bus, send) in self.returns.iter_mut().zip(&mut strip.send_levels) {
+                    let send = send.next();
+                    let bus_frame = &mut bus.buffer.samples_mut()[at..at + channel_count];
+                    mix(bus_frame, frame, send);
+                }
+            }
+        }
+
+        for bus in &mut self.returns {
+            let buffer = &mut bus.buffer.samples_mut()[..len];
+            bus.chain.process(buffer, channels);
+            for (out, frame) in master
+                .chunks_exact_mut(channel_count)
+                .zip(buffer.chunks_exact(channel_count))
+            {
+                mix(out, frame, bus.level.next());
+            }
+        }
+
+        self.master.process(master, channels);
+        for frame in master.chunks_exact_mut(channel_count) {
+            let level = self.master_level.next();
+            for sample in frame {
+                *sample = Sample::new(sample.value() * level);
+            }
+        }
+        self.monitor.finish(len, master, listening);
+    }
+
+    /// Recomputes every channel's level after a gain, mute or solo change.
+    fn update_levels(&mut self) {
+        let ramp = self.ramp();
+        let any_solo = self.channels.iter().any(|strip| strip.soloed);
+        for strip in &mut self.channels {
+            let audible = !strip.muted && (!any_solo || strip.soloed);
+            let level = if audible { strip.gain.as_linear() } else { 0.0 };
+            strip.level.set_target(level, ramp);
+        }
+    }
+
+    const fn ramp(&self) -> u32 {
+        self.ramp_frames
+    }
+}
+
+/// Mutable `channels[index]` alongside `channels[other]`, if both exist
+/// and differ
+fn pair_mut(
+    channels: &mut [MixerChannel],
+    index: usize,
+    other: usize,
+) -> Option<(&mut MixerChannel, &MixerChannel)> {
+    if index == other || index.max(other) >= channels.len() {
+        return None;
+    }
+    if index < other {
+        let (low, high) = channels.split_at_mut(other);
+        Some((&mut low[index], &high[0]))
+    } else {
+        let (low, high) = channels.split_at_mut(index);
+        Some((&mut high[0], &low[other]))
+    }
+}
+
+const fn return_level(bus: &ReturnBus) -> f32 {
+    if bus.muted { 0.0 } else { bus.gain.as_linear() }
+}
+
+/// Constant power pan gains, scaled so the centre position is unity
+fn pan_gains(pan: Pan) -> (f32, f32) {
+    let (left, right) = pan.gains();
+    (
+        left.as_linear() * std::f32::consts::SQRT_2,
+        right.as_linear() * std::f32::consts::SQRT_2,
+    )
+}
+
+/// Decodes stereo mid-side frames to left and right in place
+fn decode_mid_side(samples: &mut [Sample], side_gain: &mut SmoothParam) {
+    let (frames, _) = samples.as_chunks_mut::<2>();
+    for [left, right] in frames {
+        let mid = left.value();
+        let side = right.value() * side_gain.next();
+        *left = Sample::new(mid + side);
+        *right = Sample::new(mid - side);
+    }
+}
+
+fn mix(output: &mut [Sample], input: &[Sample], gain: f32) {
+    for (out, sample) in output.iter_mut().zip(input) {
+        *out = Sample::new(sample.value().mul_add(gain, out.value()));
+    }
+}
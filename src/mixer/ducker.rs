@@ -0,0 +1,219 @@
+//! Ducking for voice over music
+//!
+//! A [`Ducker`] turns one signal down while another, the key, is loud: the
+//! music under a presenter, say. When the key rises above the threshold
+//! the music falls by the depth over the attack time; once the key has
+//! stayed below it for the hold time, the music comes back over the
+//! release time.
+//!
+//! In a [`Mixer`](crate::mixer::Mixer), set a channel to be ducked by
+//! another with [`Mixer::set_ducking`](crate::mixer::Mixer::set_ducking).
+
+use crate::types::{ChannelCount, Gain, Sample, SampleRate};
+
+/// Release of the key's level detector, which bridges the gaps between
+/// waveform peaks
+const DETECTOR_RELEASE_MS: f32 = 10.0;
+
+/// How a [`Ducker`] responds to its key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DuckerSettings {
+    /// Key level in dBFS above which the signal is ducked
+    pub threshold_db: f32,
+    /// How far the signal is turned down, in dB (as a positive number)
+    pub depth_db: f32,
+    /// Time to fall to full depth, in milliseconds
+    pub attack_ms: f32,
+    /// Time the key must stay below the threshold before the signal comes
+    /// back, in milliseconds
+    pub hold_ms: f32,
+    /// Time to come back from full depth, in milliseconds
+    pub release_ms: f32,
+}
+
+impl Default for DuckerSettings {
+    fn default() -> Self {
+        Self {
+            threshold_db: -30.0,
+            depth_db: 12.0,
+            attack_ms: 20.0,
+            hold_ms: 300.0,
+            release_ms: 500.0,
+        }
+    }
+}
+
+impl DuckerSettings {
+    #[must_use]
+    pub const fn with_threshold_db(mut self, db: f32) -> Self {
+        self.threshold_db = db;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_depth_db(mut self, db: f32) -> Self {
+        self.depth_db = db;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_attack_ms(mut self, ms: f32) -> Self {
+        self.attack_ms = ms;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_hold_ms(mut self, ms: f32) -> Self {
+        self.hold_ms = ms;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_release_ms(mut self, ms: f32) -> Self {
+        self.release_ms = ms;
+        self
+    }
+}
+
+/// Turns a signal down while a key signal is above a threshold.
+///
+/// Doesn't allocate, so it can be created and reconfigured on the audio
+/// thread.
+#[derive(Debug, Clone)]
+pub struct Ducker {
+    settings: DuckerSettings,
+    sample_rate: SampleRate,
+    threshold: f32,
+    /// Gain at full depth
+    floor: f32,
+    attack: f32,
+    release: f32,
+    detector_release: f32,
+    hold_frames: u32,
+    /// Level of the key
+    envelope: f32,
+    /// Frames left before the release starts
+    hold: u32,
+    gain: f32,
+}
+
+impl Ducker {
+    #[must_use]
+    pub fn new(settings: DuckerSettings, sample_rate: SampleRate) -> Self {
+        let mut ducker = Self {
+            settings,
+            sample_rate,
+            threshold: 0.0,
+            floor: 1.0,
+            attack: 0.0,
+            release: 0.0,
+            detector_release: 0.0,
+            hold_frames: 0,
+            envelope: 0.0,
+            hold: 0,
+            gain: 1.0,
+        };
+        ducker.set_settings(settings);
+        ducker
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> DuckerSettings {
+        self.settings
+    }
+
+    /// Changes the settings, carrying on from the current gain.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn set_settings(&mut self, settings: DuckerSettings) {
+        let rate = self.sample_rate.as_f32();
+        self.settings = settings;
+        self.threshold = Gain::from_db(settings.threshold_db).as_linear();
+        self.floor = Gain::from_db(-settings.depth_db.abs()).as_linear();
+        self.attack = coefficient(settings.attack_ms, rate);
+        self.release = coefficient(settings.release_ms, rate);
+        self.detector_release = coefficient(DETECTOR_RELEASE_MS, rate);
+        self.hold_frames = (settings.hold_ms.max(0.0) * rate / 1000.0) as u32;
+    }
+
+    /// Changes the sample rate the times are counted in.
+    pub fn set_sample_rate(&mut self, sample_rate: SampleRate) {
+        self.sample_rate = sample_rate;
+        self.set_settings(self.settings);
+    }
+
+    /// Current gain applied to the signal
+    #[must_use]
+    pub fn gain(&self) -> Gain {
+        Gain::from_linear_clamped(self.gain)
+    }
+
+    /// How far the signal is currently turned down, in dB (as a positive
+    /// number)
+    #[must_use]
+    pub fn reduction_db(&self) -> f32 {
+        (-self.gain().as_db()).max(0.0)
+    }
+
+    /// Whether the key is above the threshold, or was within the hold time
+    #[must_use]
+    pub const fn is_ducking(&self) -> bool {
+        self.hold > 0
+    }
+
+    /// Returns to unity gain and forgets the key's level.
+    pub const fn reset(&mut self) {
+        self.envelope = 0.0;
+        self.hold = 0;
+        self.gain = 1.0;
+    }
+
+    /// Ducks `signal` by `key`, both interleaved with `channels`. Frames
+    /// past the end of a shorter key count as silence.
+    pub fn process(&mut self, key: &[Sample], signal: &mut [Sample], channels: ChannelCount) {
+        let channel_count = channels.count_usize();
+        let mut key_frames = key.chunks_exact(channel_count);
+        for frame in signal.chunks_exact_mut(channel_count) {
+            let peak = key_frames.next().map_or(0.0, |frame| {
+                frame
+                    .iter()
+                    .fold(0.0_f32, |peak, sample| peak.max(sample.value().abs()))
+            });
+            let gain = self.next(peak);
+            for sample in frame {
+                *sample = Sample::new(sample.value() * gain);
+            }
+        }
+    }
+
+    /// The gain for one frame whose key peaks at `peak`
+    fn next(&mut self, peak: f32) -> f32 {
+        self.envelope = if peak > self.envelope {
+            peak
+        } else {
+            (self.envelope - peak).mul_add(self.detector_release, peak)
+        };
+        if self.envelope > self.threshold {
+            self.hold = self.hold_frames.max(1);
+        } else {
+            self.hold = self.hold.saturating_sub(1);
+        }
+        let (target, coefficient) = if self.hold > 0 {
+            (self.floor, self.attack)
+        } else {
+            (1.0, self.release)
+        };
+        self.gain = (self.gain - target).mul_add(coefficient, target);
+        self.gain
+    }
+}
+
+/// One-pole coefficient with a time constant of `ms`
+fn coefficient(ms: f32, sample_rate: f32) -> f32 {
+    let frames = ms.max(0.0) * sample_rate / 1000.0;
+    if frames < 1.0 {
+        0.0
+    } else {
+        (-1.0 / frames).exp()
+    }
+}
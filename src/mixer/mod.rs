@@ -0,0 +1,23 @@
+//! Mixing and bus routing
+
+pub mod console;
+pub mod crossfader;
+pub mod ducker;
+pub mod monitor;
+pub mod program;
+pub mod routing;
+pub mod switcher;
+
+pub use console::{
+    ChannelScene, DuckingScene, InputMode, Mixer, MixerChannel, MixerCommand, MixerScene,
+    ReturnBus, ReturnScene,
+};
+pub use crossfader::Crossfader;
+pub use ducker::{Ducker, DuckerSettings};
+pub use monitor::{Listen, Monitor, MonitorSettings};
+pub use program::{BusCommand, ProgramPreviewBus};
+pub use routing::{
+    ChannelRef, InputPatch, OutputPatch, PortKind, RoutePort, RoutingCommand, RoutingMatrix,
+    RoutingScene,
+};
+pub use switcher::{SourceSwitcher, SwitchCommand};
@@ -0,0 +1,234 @@
+//! Monitoring and cue
+//!
+//! The monitor output is what the operator hears, separate from the
+//! program a [`Mixer`](crate::mixer::Mixer) sends on. Channels put in
+//! [`Listen::PreFader`] (PFL) or [`Listen::AfterFader`] (AFL) are summed
+//! onto the cue bus; while none are, the monitor follows the master bus.
+//! Dim and mono act on the monitor output only, so neither listening nor
+//! dimming touches the main mix.
+//!
+//! The monitor output can be routed to a second device by handing the
+//! writer of its [`AudioOutputStream`] to
+//! [`Mixer::set_cue_output`](crate::mixer::Mixer::set_cue_output). Like
+//! the engine's main output it passes a [safety stage](crate::engine::safety)
+//! last, so a hot cue can't blast the operator's headphones; it is set
+//! with [`Mixer::set_cue_safety`](crate::mixer::Mixer::set_cue_safety).
+//!
+//! [`AudioOutputStream`]: crate::audio::stream::AudioOutputStream
+
+use crate::buffer::RingBufferWriter;
+use crate::buffer::realtime::AudioBuffer;
+use crate::dsp::params::SmoothParam;
+use crate::engine::safety::{SafetyLimiter, SafetySettings};
+use crate::types::{ChannelCount, Gain, Sample, SampleRate};
+
+/// Ramp length for monitor level, dim and mono changes, in milliseconds
+const SMOOTHING_MS: u32 = 10;
+
+/// Channels in the widest layout, 7.1
+const MAX_CHANNELS: usize = 8;
+
+/// Where a channel is taken for the cue bus, if it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Listen {
+    #[default]
+    Off,
+    /// After the inserts, before the fader and pan (PFL)
+    PreFader,
+    /// After the fader, pan and ducking (AFL)
+    AfterFader,
+}
+
+/// Level, dim and mono on the monitor output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MonitorSettings {
+    pub level: Gain,
+    /// How far dimming turns the monitor down, in dB (as a positive
+    /// number)
+    pub dim_db: f32,
+    pub dimmed: bool,
+    /// Sum the channels, to check the mix folds down
+    pub mono: bool,
+}
+
+impl Default for MonitorSettings {
+    fn default() -> Self {
+        Self {
+            level: Gain::UNITY,
+            dim_db: 20.0,
+            dimmed: false,
+            mono: false,
+        }
+    }
+}
+
+impl MonitorSettings {
+    #[must_use]
+    pub const fn with_level(mut self, level: Gain) -> Self {
+        self.level = level;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_dim_db(mut self, db: f32) -> Self {
+        self.dim_db = db;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_dimmed(mut self, dimmed: bool) -> Self {
+        self.dimmed = dimmed;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_mono(mut self, mono: bool) -> Self {
+        self.mono = mono;
+        self
+    }
+
+    /// Gain of the monitor output, with any dim
+    fn gain(&self) -> f32 {
+        let dim = if self.dimmed {
+            Gain::from_db(-self.dim_db.abs()).as_linear()
+        } else {
+            1.0
+        };
+        self.level.as_linear() * dim
+    }
+}
+
+/// The cue bus and the monitor output made from it.
+#[derive(Debug)]
+pub struct Monitor {
+    settings: MonitorSettings,
+    channels: ChannelCount,
+    ramp_frames: u32,
+    level: SmoothParam,
+    /// Amount of the mono sum in the output, ramped so switching doesn't
+    /// click
+    mono: SmoothParam,
+    buffer: AudioBuffer,
+    /// Safety stage on the monitor output, `None` if turned off
+    safety: Option<SafetyLimiter>,
+    output: Option<RingBufferWriter<Sample>>,
+    /// Samples the output had no room for
+    dropped: u64,
+}
+
+impl Monitor {
+    pub(crate) fn new(
+        channels: ChannelCount,
+        sample_rate: SampleRate,
+        block_frames: usize,
+    ) -> Self {
+        let settings = MonitorSettings::default();
+        Self {
+            settings,
+            channels,
+            ramp_frames: sample_rate.samples_for_milliseconds(SMOOTHING_MS),
+            level: SmoothParam::new(settings.gain()),
+            mono: SmoothParam::new(0.0),
+            buffer: AudioBuffer::new(block_frames, channels),
+            safety: SafetyLimiter::new(SafetySettings::default(), sample_rate, channels),
+            output: None,
+            dropped: 0,
+        }
+    }
+
+    #[must_use]
+    pub const fn settings(&self) -> MonitorSettings {
+        self.settings
+    }
+
+    pub(crate) fn set_settings(&mut self, settings: MonitorSettings) {
+        self.settings = settings;
+        self.level.set_target(settings.gain(), self.ramp_frames);
+        self.mono
+            .set_target(f32::from(u8::from(settings.mono)), self.ramp_frames);
+    }
+
+    pub(crate) fn set_safety(&mut self, settings: SafetySettings, sample_rate: SampleRate) {
+        self.safety = SafetyLimiter::new(settings, sample_rate, self.channels);
+    }
+
+    /// Whether the monitor output goes to a second device
+    #[must_use]
+    pub const fn is_routed(&self) -> bool {
+        self.output.is_some()
+    }
+
+    /// Samples of monitor output dropped because the second device's
+    /// buffer was full
+    #[must_use]
+    pub const fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub(crate) const fn set_output(
+        &mut self,
+        output: Option<RingBufferWriter<Sample>>,
+    ) -> Option<RingBufferWriter<Sample>> {
+        std::mem::replace(&mut self.output, output)
+    }
+
+    pub(crate) fn buffer_bytes(&self) -> usize {
+        size_of_val(self.buffer.samples())
+    }
+
+    /// The cue bus, to be cleared and summed into for a block
+    pub(crate) fn cue_mut(&mut self, len: usize) -> &mut [Sample] {
+        &mut self.buffer.samples_mut()[..len]
+    }
+
+    /// The monitor output of the last block of `len` samples
+    pub(crate) fn output(&self, len: usize) -> &[Sample] {
+        &self.buffer.samples()[..len]
+    }
+
+    /// Turns the first `len` samples of the cue bus into the monitor
+    /// output, taking `master` instead if nothing was listened to, runs
+    /// the safety stage over it and sends it to the second device if
+    /// routed.
+    pub(crate) fn finish(&mut self, len: usize, master: &[Sample], listening: bool) {
+        let channel_count = self.channels.count_usize();
+        let buffer = &mut self.buffer.samples_mut()[..len];
+        if !listening {
+            buffer.copy_from_slice(&master[..len]);
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let scale = 1.0 / channel_count as f32;
+        let mut values = [0.0; MAX_CHANNELS];
+        let values = &mut values[..channel_count];
+        for frame in buffer.chunks_exact_mut(channel_count) {
+            let level = self.level.next();
+            let mono = self.mono.next();
+            let sum = frame.iter().map(|sample| sample.value()).sum::<f32>() * scale;
+            for (value, sample) in values.iter_mut().zip(frame.iter()) {
+                *value = (sum - sample.value()).mul_add(mono, sample.value()) * level;
+            }
+            if let Some(safety) = &mut self.safety {
+                safety.process_frame(values);
+            }
+            for (sample, &value) in frame.iter_mut().zip(values.iter()) {
+                *sample = Sample::new(value);
+            }
+        }
+        if let Some(output) = &mut self.output {
+            // Only whole frames, so the device's channels stay aligned
+            let room = output.slots() / channel_count * channel_count;
+            let written = output.push_slice(&buffer[..room.min(len)]);
+            self.dropped += (len - written) as u64;
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.level.set_immediate(self.level.target());
+        self.mono.set_immediate(self.mono.target());
+        if let Some(safety) = &mut self.safety {
+            safety.reset();
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Program / preview dual bus
+//!
+//! The program bus is what goes to air. The preview bus is what the operator
+//! hears on headphones: either the source lined up to go next, or any sources
+//! put in cue (PFL). A take crossfades program over to the preview source and
+//! swaps the two, so the previous program source is lined up for the next take.
+
+use crate::dsp::crossfade::{Crossfade, CrossfadeCurve};
+use crate::error::{AudioEngineError, Result};
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+/// Commands for a [`ProgramPreviewBus`], typically sent from the control thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusCommand {
+    /// Cut the program bus to a source immediately
+    SetProgram(usize),
+    /// Line up a source on the preview bus
+    SetPreview(usize),
+    /// Put a source in or out of cue
+    SetCue {
+        /// Source index
+        source: usize,
+        /// Whether the source is cued
+        cued: bool,
+    },
+    /// Crossfade preview to program over the given time
+    Take {
+        /// Crossfade length in milliseconds
+        crossfade_ms: u32,
+    },
+}
+
+#[derive(Debug)]
+pub struct ProgramPreviewBus {
+    sources: usize,
+    channels: ChannelCount,
+    sample_rate: SampleRate,
+    program: usize,
+    preview: usize,
+    cued: Vec<bool>,
+    fade: Crossfade,
+}
+
+impl ProgramPreviewBus {
+    /// Creates a bus pair switching between `sources` inputs.
+    ///
+    /// # Errors
+    /// Returns an error if `sources` is zero.
+    pub fn new(sources: usize, channels: ChannelCount, sample_rate: SampleRate) -> Result<Self> {
+        if sources == 0 {
+            return Err(AudioEngineError::configuration(
+                "program/preview bus needs at least one source",
+            ));
+        }
+        Ok(Self {
+            sources,
+            channels,
+            sample_rate,
+            program: 0,
+            preview: usize::from(sources > 1),
+            cued: vec![false; sources],
+            fade: Crossfade::new(CrossfadeCurve::EqualPower),
+        })
+    }
+
+    #[must_use]
+    pub const fn with_curve(mut self, curve: CrossfadeCurve) -> Self {
+        self.fade.set_curve(curve);
+        self
+    }
+
+    #[must_use]
+    pub const fn source_count(&self) -> usize {
+        self.sources
+    }
+
+    #[must_use]
+    pub const fn program(&self) -> usize {
+        self.program
+    }
+
+    #[must_use]
+    pub const fn preview(&self) -> usize {
+        self.preview
+    }
+
+    #[must_use]
+    pub fn is_cued(&self, source: usize) -> bool {
+        self.cued.get(source).copied().unwrap_or(false)
+    }
+
+    /// Returns true while a take is crossfading.
+    #[must_use]
+    pub fn is_taking(&self) -> bool {
+        self.fade.is_active()
+    }
+
+    pub const fn set_sample_rate(&mut self, sample_rate: SampleRate) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Applies a command. Returns false if it referenced an unknown source
+    /// or a take is already in progress.
+    pub fn apply(&mut self, command: BusCommand) -> bool {
+        match command {
+            BusCommand::SetProgram(source) if source < self.sources => {
+                self.program = source;
+                self.fade.start(0);
+                true
+            }
+            BusCommand::SetPreview(source) if source < self.sources && !self.is_taking() => {
+                self.preview = source;
+                true
+            }
+            BusCommand::SetCue { source, cued } if source < self.sources => {
+                self.cued[source] = cued;
+                true
+            }
+            BusCommand::Take { crossfade_ms } => self.take(crossfade_ms),
+            _ => false,
+        }
+    }
+
+    /// Starts crossfading the preview source onto the program bus.
+    ///
+    /// Returns false if a take is already running.
+    pub fn take(&mut self, crossfade_ms: u32) -> bool {
+        if self.is_taking() {
+            return false;
+        }
+        let samples = self.sample_rate.samples_for_milliseconds(crossfade_ms);
+        self.fade.start(samples);
+        if !self.fade.is_active() {
+            self.swap();
+        }
+        true
+    }
+
+    const fn swap(&mut self) {
+        std::mem::swap(&mut self.program, &mut self.preview);
+    }
+
+    /// Renders one block of both buses.
+    ///
+    /// `inputs` holds one interleaved block per source, all the same length as
+    /// the outputs. Missing inputs are treated as silence.
+    pub fn process(
+        &mut self,
+        inputs: &[&[Sample]],
+        program_out: &mut [Sample],
+        preview_out: &mut [Sample],
+    ) {
+        let channels = self.channels.count_usize();
+        let silence: &[Sample] = &[];
+        let sample_at = |buf: &[Sample], i: usize| buf.get(i).map_or(0.0, |s| s.value());
+
+        for (frame_index, frame) in program_out.chunks_exact_mut(channels).enumerate() {
+            let program_in = inputs.get(self.program).copied().unwrap_or(silence);
+            let preview_in = inputs.get(self.preview).copied().unwrap_or(silence);
+            let (out_gain, in_gain) = if self.fade.is_active() {
+                self.fade.next_gains()
+            } else {
+                (1.0, 0.0)
+            };
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let i = frame_index * channels + ch;
+                let mixed =
+                    sample_at(program_in, i).mul_add(out_gain, sample_at(preview_in, i) * in_gain);
+                *sample = Sample::new(mixed);
+            }
+            if !self.fade.is_active() && in_gain > 0.0 {
+                // The take finished on this frame
+                self.swap();
+            }
+        }
+
+        let preview_in = inputs.get(self.preview).copied().unwrap_or(silence);
+        let any_cued = self.cued.iter().any(|&c| c);
+        for (i, sample) in preview_out.iter_mut().enumerate() {
+            let value = if any_cued {
+                inputs
+                    .iter()
+                    .zip(&self.cued)
+                    .filter(|&(_, &cued)| cued)
+                    .map(|(input, _)| sample_at(input, i))
+                    .sum()
+            } else {
+                sample_at(preview_in, i)
+            };
+            *sample = Sample::new(value);
+        }
+    }
+}
@@ -0,0 +1,568 @@
+//! Input and output routing
+//!
+//! A [`RoutingMatrix`] has two patch grids. The input grid connects any
+//! channel of a source (a device input, a file player or a network stream)
+//! to any channel of a track input. The output grid connects any channel of
+//! a track or bus to any output channel. Every crosspoint has its own gain,
+//! ramped whenever it changes, so patches can be made and broken while audio
+//! is running without clicks.
+
+use std::fmt;
+
+use crate::buffer::memory::heap_bytes;
+use crate::channel::{ControlSender, RealtimeReceiver, control_channel};
+use crate::dsp::params::SmoothParam;
+use crate::error::{AudioEngineError, Result};
+use crate::markers::RealtimeSafe;
+use crate::types::{ChannelCount, Gain, Sample, SampleRate};
+
+/// Ramp time for crosspoint gain changes
+const RAMP_MS: u32 = 10;
+
+/// Where the audio on a port comes from or goes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PortKind {
+    /// Physical channels of an audio device
+    Device,
+    /// A file player
+    File,
+    /// A network stream
+    Network,
+    Track,
+    Bus,
+}
+
+impl fmt::Display for PortKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Device => write!(f, "device"),
+            Self::File => write!(f, "file"),
+            Self::Network => write!(f, "network"),
+            Self::Track => write!(f, "track"),
+            Self::Bus => write!(f, "bus"),
+        }
+    }
+}
+
+/// A named group of channels on one side of a patch grid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutePort {
+    pub name: String,
+    pub kind: PortKind,
+    pub channels: ChannelCount,
+}
+
+impl RoutePort {
+    #[must_use]
+    pub fn new(name: impl Into<String>, kind: PortKind, channels: ChannelCount) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            channels,
+        }
+    }
+}
+
+/// One channel of a port, by index into the matrix's sources or tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelRef {
+    pub port: usize,
+    pub channel: usize,
+}
+
+impl ChannelRef {
+    #[must_use]
+    pub const fn new(port: usize, channel: usize) -> Self {
+        Self { port, channel }
+    }
+}
+
+/// Patch change for a [`RoutingMatrix`], picked up at the start of a block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoutingCommand {
+    /// Feeds a source channel into a track channel
+    ConnectInput {
+        from: ChannelRef,
+        to: ChannelRef,
+        gain: Gain,
+    },
+    DisconnectInput {
+        from: ChannelRef,
+        to: ChannelRef,
+    },
+    /// Feeds a track channel to an output channel
+    ConnectOutput {
+        from: ChannelRef,
+        to: usize,
+        gain: Gain,
+    },
+    DisconnectOutput {
+        from: ChannelRef,
+        to: usize,
+    },
+    /// Breaks every patch in both grids
+    Clear,
+}
+
+impl RealtimeSafe for RoutingCommand {}
+
+/// A dense grid of crosspoints between flattened input and output channels.
+#[derive(Debug)]
+struct PatchGrid {
+    /// First flat channel of each input port
+    input_offsets: Vec<usize>,
+    /// First flat channel of each output port
+    output_offsets: Vec<usize>,
+    inputs: usize,
+    outputs: usize,
+    /// Crosspoint levels, row-major by input channel
+    levels: Vec<SmoothParam>,
+    /// Scratch for one frame of input and output
+    frame_in: Vec<f32>,
+    frame_out: Vec<f32>,
+}
+
+impl PatchGrid {
+    fn new(input_ports: &[usize], output_ports: &[usize]) -> Self {
+        let (input_offsets, inputs) = offsets(input_ports);
+        let (output_offsets, outputs) = offsets(output_ports);
+        Self {
+            input_offsets,
+            output_offsets,
+            inputs,
+            outputs,
+            levels: vec![SmoothParam::new(0.0); inputs * outputs],
+            frame_in: vec![0.0; inputs],
+            frame_out: vec![0.0; outputs],
+        }
+    }
+
+    const fn memory_bytes(&self) -> usize {
+        heap_bytes(&self.input_offsets)
+            + heap_bytes(&self.output_offsets)
+            + heap_bytes(&self.levels)
+            + heap_bytes(&self.frame_in)
+            + heap_bytes(&self.frame_out)
+    }
+
+    /// Index of the crosspoint, or None if either end doesn't exist
+    fn index(&self, from: ChannelRef, to: ChannelRef) -> Option<usize> {
+        let input = flat_channel(&self.input_offsets, self.inputs, from)?;
+        let output = flat_channel(&self.output_offsets, self.outputs, to)?;
+        Some(input * self.outputs + output)
+    }
+
+    fn set(&mut self, from: ChannelRef, to: ChannelRef, level: f32, ramp: u32) -> bool {
+        let Some(index) = self.index(from, to) else {
+            return false;
+        };
+        self.levels[index].set_target(level, ramp);
+        true
+    }
+
+    fn level(&self, from: ChannelRef, to: ChannelRef) -> Option<f32> {
+        self.index(from, to)
+            .map(|index| self.levels[index].target())
+            .filter(|&level| level != 0.0)
+    }
+
+    /// Every crosspoint with a level, as (from, to, level)
+    fn patches(&self) -> impl Iterator<Item = (ChannelRef, ChannelRef, f32)> + '_ {
+        self.levels
+            .iter()
+            .enumerate()
+            .filter(|(_, level)| level.target() != 0.0)
+            .map(|(index, level)| {
+                (
+                    port_channel(&self.input_offsets, index / self.outputs),
+                    port_channel(&self.output_offsets, index % self.outputs),
+                    level.target(),
+                )
+            })
+    }
+
+    fn clear(&mut self, ramp: u32) {
+        for level in &mut self.levels {
+            if level.target() != 0.0 {
+                level.set_target(0.0, ramp);
+            }
+        }
+    }
+
+    /// Mixes `inputs` into `outputs` through the grid. Ports are interleaved
+    /// blocks; short or missing inputs read as silence.
+    fn process(&mut self, inputs: &[&[Sample]], outputs: &mut [&mut [Sample]], frames: usize) {
+        let input_ports = self.input_offsets.len();
+        for frame in 0..frames {
+            for port in 0..input_ports {
+                let (start, end) = port_range(&self.input_offsets, self.inputs, port);
+                let width = end - start;
+                let samples = inputs
+                    .get(port)
+                    .and_then(|input| input.get(frame * width..(frame + 1) * width));
+                for (channel, value) in self.frame_in[start..end].iter_mut().enumerate() {
+                    *value = samples.map_or(0.0, |samples| samples[channel].value());
+                }
+            }
+
+            self.frame_out.fill(0.0);
+            for (row, &input) in self
+                .levels
+                .chunks_exact_mut(self.outputs)
+                .zip(&self.frame_in)
+            {
+                for (level, out) in row.iter_mut().zip(self.frame_out.iter_mut()) {
+                    if level.target() == 0.0 && !level.is_smoothing() {
+                        continue;
+                    }
+                    *out = input.mul_add(level.next(), *out);
+                }
+            }
+
+            for (port, output) in outputs.iter_mut().enumerate() {
+                let (start, end) = port_range(&self.output_offsets, self.outputs, port);
+                let width = end - start;
+                if let Some(samples) = output.get_mut(frame * width..(frame + 1) * width) {
+                    for (sample, &value) in samples.iter_mut().zip(&self.frame_out[start..end]) {
+                        *sample = Sample::new(sample.value() + value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Start of each port in the flattened channel list, and the total width
+fn offsets(widths: &[usize]) -> (Vec<usize>, usize) {
+    let mut total = 0;
+    let offsets = widths
+        .iter()
+        .map(|&width| {
+            let start = total;
+            total += width;
+            start
+        })
+        .collect();
+    (offsets, total)
+}
+
+fn port_range(offsets: &[usize], total: usize, port: usize) -> (usize, usize) {
+    let end = offsets.get(port + 1).copied().unwrap_or(total);
+    (offsets[port], end)
+}
+
+fn port_channel(offsets: &[usize], flat: usize) -> ChannelRef {
+    let port = offsets.partition_point(|&start| start <= flat) - 1;
+    ChannelRef::new(port, flat - offsets[port])
+}
+
+fn flat_channel(offsets: &[usize], total: usize, channel: ChannelRef) -> Option<usize> {
+    if channel.port >= offsets.len() {
+        return None;
+    }
+    let (start, end) = port_range(offsets, total, channel.port);
+    (channel.channel < end - start).then_some(start + channel.channel)
+}
+
+/// A source channel feeding a track channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InputPatch {
+    pub from: ChannelRef,
+    pub to: ChannelRef,
+    pub gain: Gain,
+}
+
+/// A track channel feeding an output channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutputPatch {
+    pub from: ChannelRef,
+    pub to: usize,
+    pub gain: Gain,
+}
+
+/// Every patch of a [`RoutingMatrix`], captured with
+/// [`RoutingMatrix::scene`].
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RoutingScene {
+    pub inputs: Vec<InputPatch>,
+    pub outputs: Vec<OutputPatch>,
+}
+
+/// Patches sources to track inputs and tracks to output channels.
+#[derive(Debug)]
+pub struct RoutingMatrix {
+    sources: Vec<RoutePort>,
+    tracks: Vec<RoutePort>,
+    output_channels: usize,
+    sample_rate: SampleRate,
+    ramp_frames: u32,
+    inputs: PatchGrid,
+    outputs: PatchGrid,
+    commands: Option<RealtimeReceiver<RoutingCommand>>,
+}
+
+impl RoutingMatrix {
+    /// Creates an empty matrix: nothing is patched.
+    ///
+    /// `tracks` are the ports on both grids: their inputs are fed from
+    /// `sources`, and their outputs feed `output_channels` output channels.
+    ///
+    /// # Errors
+    /// Returns an error if there are no tracks or no output channels.
+    pub fn new(
+        sources: Vec<RoutePort>,
+        tracks: Vec<RoutePort>,
+        output_channels: usize,
+        sample_rate: SampleRate,
+    ) -> Result<Self> {
+        if tracks.is_empty() {
+            return Err(AudioEngineError::configuration(
+                "routing matrix needs at least one track",
+            ));
+        }
+        if output_channels == 0 {
+            return Err(AudioEngineError::configuration(
+                "routing matrix needs at least one output channel",
+            ));
+        }
+        let source_widths: Vec<usize> = sources.iter().map(|p| p.channels.count_usize()).collect();
+        let track_widths: Vec<usize> = tracks.iter().map(|p| p.channels.count_usize()).collect();
+        Ok(Self {
+            inputs: PatchGrid::new(&source_widths, &track_widths),
+            outputs: PatchGrid::new(&track_widths, &[output_channels]),
+            sources,
+            tracks,
+            output_channels,
+            sample_rate,
+            ramp_frames: sample_rate.samples_for_milliseconds(RAMP_MS),
+            commands: None,
+        })
+    }
+
+    #[must_use]
+    pub fn with_ramp_ms(mut self, millis: u32) -> Self {
+        self.set_ramp_ms(millis);
+        self
+    }
+
+    pub fn set_ramp_ms(&mut self, millis: u32) {
+        self.ramp_frames = self.sample_rate.samples_for_milliseconds(millis);
+    }
+
+    #[must_use]
+    pub fn sources(&self) -> &[RoutePort] {
+        &self.sources
+    }
+
+    #[must_use]
+    pub fn tracks(&self) -> &[RoutePort] {
+        &self.tracks
+    }
+
+    #[must_use]
+    pub const fn output_channels(&self) -> usize {
+        self.output_channels
+    }
+
+    /// Creates the control side of the matrix's command channel.
+    ///
+    /// Commands sent through it are picked up at the start of each block.
+    pub fn command_sender(&mut self, capacity: usize) -> ControlSender<RoutingCommand> {
+        let (tx, rx) = control_channel(capacity);
+        self.commands = Some(rx);
+        tx
+    }
+
+    /// Feeds source channel `from` into track channel `to` at `gain`.
+    /// Reconnecting an existing patch changes its gain.
+    ///
+    /// Returns false if either channel doesn't exist.
+    pub fn connect_input(&mut self, from: ChannelRef, to: ChannelRef, gain: Gain) -> bool {
+        self.inputs
+            .set(from, to, gain.as_linear(), self.ramp_frames)
+    }
+
+    pub fn disconnect_input(&mut self, from: ChannelRef, to: ChannelRef) -> bool {
+        self.inputs.set(from, to, 0.0, self.ramp_frames)
+    }
+
+    /// Feeds track channel `from` to output channel `to` at `gain`.
+    ///
+    /// Returns false if either channel doesn't exist.
+    pub fn connect_output(&mut self, from: ChannelRef, to: usize, gain: Gain) -> bool {
+        self.outputs.set(
+            from,
+            ChannelRef::new(0, to),
+            gain.as_linear(),
+            self.ramp_frames,
+        )
+    }
+
+    pub fn disconnect_output(&mut self, from: ChannelRef, to: usize) -> bool {
+        self.outputs
+            .set(from, ChannelRef::new(0, to), 0.0, self.ramp_frames)
+    }
+
+    /// Patches a whole source into a track, channel for channel. A source
+    /// with fewer channels than the track repeats its last channel, so a
+    /// mono source feeds both sides of a stereo track.
+    ///
+    /// Returns false if either port doesn't exist.
+    pub fn connect_source(&mut self, source: usize, track: usize, gain: Gain) -> bool {
+        let (Some(from), Some(to)) = (self.sources.get(source), self.tracks.get(track)) else {
+            return false;
+        };
+        let (from_channels, to_channels) = (from.channels.count_usize(), to.channels.count_usize());
+        for channel in 0..to_channels {
+            self.connect_input(
+                ChannelRef::new(source, channel.min(from_channels - 1)),
+                ChannelRef::new(track, channel),
+                gain,
+            );
+        }
+        true
+    }
+
+    /// Sends a whole track to consecutive output channels from
+    /// `first_output`. Channels past the last output are left unpatched.
+    ///
+    /// Returns false if the track doesn't exist.
+    pub fn connect_track(&mut self, track: usize, first_output: usize, gain: Gain) -> bool {
+        let Some(port) = self.tracks.get(track) else {
+            return false;
+        };
+        for channel in 0..port.channels.count_usize() {
+            self.connect_output(
+                ChannelRef::new(track, channel),
+                first_output + channel,
+                gain,
+            );
+        }
+        true
+    }
+
+    /// Breaks every patch in both grids.
+    pub fn clear(&mut self) {
+        self.inputs.clear(self.ramp_frames);
+        self.outputs.clear(self.ramp_frames);
+    }
+
+    /// Captures every patch.
+    #[must_use]
+    pub fn scene(&self) -> RoutingScene {
+        RoutingScene {
+            inputs: self
+                .inputs
+                .patches()
+                .map(|(from, to, level)| InputPatch {
+                    from,
+                    to,
+                    gain: Gain::new(level),
+                })
+                .collect(),
+            outputs: self
+                .outputs
+                .patches()
+                .map(|(from, to, level)| OutputPatch {
+                    from,
+                    to: to.channel,
+                    gain: Gain::new(level),
+                })
+                .collect(),
+        }
+    }
+
+    /// Replaces every patch with those of `scene`, crossfading over
+    /// `transition_frames`: patches in both only change level.
+    ///
+    /// Returns false if the scene names a channel that doesn't exist; the
+    /// other patches are still made.
+    pub fn recall(&mut self, scene: &RoutingScene, transition_frames: u32) -> bool {
+        let ramp = self.ramp_frames;
+        self.ramp_frames = transition_frames;
+        self.clear();
+        let mut ok = true;
+        for patch in &scene.inputs {
+            ok &= self.connect_input(patch.from, patch.to, patch.gain);
+        }
+        for patch in &scene.outputs {
+            ok &= self.connect_output(patch.from, patch.to, patch.gain);
+        }
+        self.ramp_frames = ramp;
+        ok
+    }
+
+    /// Gain of a source-to-track patch, or None if it isn't patched
+    #[must_use]
+    pub fn input_gain(&self, from: ChannelRef, to: ChannelRef) -> Option<Gain> {
+        self.inputs.level(from, to).map(Gain::new)
+    }
+
+    /// Gain of a track-to-output patch, or None if it isn't patched
+    #[must_use]
+    pub fn output_gain(&self, from: ChannelRef, to: usize) -> Option<Gain> {
+        self.outputs
+            .level(from, ChannelRef::new(0, to))
+            .map(Gain::new)
+    }
+
+    /// Bytes held by the matrix, mostly one level per crosspoint of each
+    /// grid
+    #[must_use]
+    pub const fn memory_bytes(&self) -> usize {
+        size_of::<Self>() + self.inputs.memory_bytes() + self.outputs.memory_bytes()
+    }
+
+    /// Applies a command directly. Returns false if it refers to a channel
+    /// that doesn't exist.
+    pub fn apply(&mut self, command: RoutingCommand) -> bool {
+        match command {
+            RoutingCommand::ConnectInput { from, to, gain } => self.connect_input(from, to, gain),
+            RoutingCommand::DisconnectInput { from, to } => self.disconnect_input(from, to),
+            RoutingCommand::ConnectOutput { from, to, gain } => self.connect_output(from, to, gain),
+            RoutingCommand::DisconnectOutput { from, to } => self.disconnect_output(from, to),
+            RoutingCommand::Clear => {
+                self.clear();
+                true
+            }
+        }
+    }
+
+    fn drain_commands(&mut self) {
+        while let Some(command) = self.commands.as_ref().and_then(RealtimeReceiver::try_recv) {
+            self.apply(command);
+        }
+    }
+
+    /// Fills each track's input block from the sources.
+    ///
+    /// `sources` and `tracks` hold one interleaved block per port, in the
+    /// order the ports were given, all `frames` frames long. Track blocks
+    /// are overwritten; missing or short sources read as silence.
+    pub fn route_inputs(
+        &mut self,
+        sources: &[&[Sample]],
+        tracks: &mut [&mut [Sample]],
+        frames: usize,
+    ) {
+        self.drain_commands();
+        for track in tracks.iter_mut() {
+            track.fill(Sample::SILENCE);
+        }
+        self.inputs.process(sources, tracks, frames);
+    }
+
+    /// Mixes the tracks' output blocks into `output`, interleaved with
+    /// [`output_channels`](Self::output_channels) channels. `output` is
+    /// overwritten.
+    pub fn route_outputs(&mut self, tracks: &[&[Sample]], output: &mut [Sample]) {
+        self.drain_commands();
+        output.fill(Sample::SILENCE);
+        let frames = output.len() / self.output_channels;
+        self.outputs.process(tracks, &mut [output], frames);
+    }
+}
@@ -0,0 +1,255 @@
+//! Click-free source switching
+//!
+//! A [`SourceSwitcher`] holds N inputs and outputs one of them. Switching ramps
+//! every input's level towards its new target over the configured crossfade
+//! time, so a switch issued in the middle of another fade never jumps.
+//! Commands carry a frame offset into the next block, so switches land on
+//! exact samples.
+
+use crate::channel::{ControlSender, RealtimeReceiver, control_channel};
+use crate::dsp::crossfade::CrossfadeCurve;
+use crate::error::{AudioEngineError, Result};
+use crate::markers::RealtimeSafe;
+use crate::types::{ChannelCount, Sample, SampleRate};
+
+/// Maximum number of switches that can be queued for a single block
+const MAX_PENDING: usize = 16;
+
+/// Request to switch to an input at a frame offset within the next block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwitchCommand {
+    /// Input to switch to
+    pub input: usize,
+    /// Frame offset within the next processed block
+    pub offset_frames: u32,
+    /// Crossfade length override in frames
+    pub crossfade_frames: Option<u32>,
+}
+
+impl SwitchCommand {
+    /// Switch at the start of the next block using the configured crossfade.
+    #[must_use]
+    pub const fn now(input: usize) -> Self {
+        Self {
+            input,
+            offset_frames: 0,
+            crossfade_frames: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn at_offset(mut self, offset_frames: u32) -> Self {
+        self.offset_frames = offset_frames;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_crossfade_frames(mut self, frames: u32) -> Self {
+        self.crossfade_frames = Some(frames);
+        self
+    }
+}
+
+impl RealtimeSafe for SwitchCommand {}
+
+#[derive(Debug)]
+pub struct SourceSwitcher {
+    channels: ChannelCount,
+    sample_rate: SampleRate,
+    curve: CrossfadeCurve,
+    crossfade_frames: u32,
+    selected: usize,
+    levels: Vec<f32>,
+    steps: Vec<f32>,
+    remaining: Vec<u32>,
+    pending: Vec<SwitchCommand>,
+    commands: Option<RealtimeReceiver<SwitchCommand>>,
+}
+
+impl SourceSwitcher {
+    /// Creates a switcher over `inputs` sources with input 0 selected.
+    ///
+    /// # Errors
+    /// Returns an error if `inputs` is zero.
+    pub fn new(inputs: usize, channels: ChannelCount, sample_rate: SampleRate) -> Result<Self> {
+        if inputs == 0 {
+            return Err(AudioEngineError::configuration(
+                "source switcher needs at least one input",
+            ));
+        }
+        let mut levels = vec![0.0; inputs];
+        levels[0] = 1.0;
+        Ok(Self {
+            channels,
+            sample_rate,
+            curve: CrossfadeCurve::EqualPower,
+            crossfade_frames: sample_rate.samples_for_milliseconds(50),
+            selected: 0,
+            levels,
+            steps: vec![0.0; inputs],
+            remaining: vec![0; inputs],
+            pending: Vec::with_capacity(MAX_PENDING),
+            commands: None,
+        })
+    }
+
+    #[must_use]
+    pub const fn with_curve(mut self, curve: CrossfadeCurve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    #[must_use]
+    pub fn with_crossfade_ms(mut self, millis: u32) -> Self {
+        self.crossfade_frames = self.sample_rate.samples_for_milliseconds(millis);
+        self
+    }
+
+    pub const fn set_curve(&mut self, curve: CrossfadeCurve) {
+        self.curve = curve;
+    }
+
+    pub fn set_crossfade_ms(&mut self, millis: u32) {
+        self.crossfade_frames = self.sample_rate.samples_for_milliseconds(millis);
+    }
+
+    #[must_use]
+    pub const fn input_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    #[must_use]
+    pub const fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Returns true while any input is still ramping.
+    #[must_use]
+    pub fn is_switching(&self) -> bool {
+        self.remaining.iter().any(|&r| r > 0)
+    }
+
+    /// Creates the control side of the switcher's command channel.
+    ///
+    /// Commands sent through it are picked up at the start of each block.
+    pub fn command_sender(&mut self, capacity: usize) -> ControlSender<SwitchCommand> {
+        let (tx, rx) = control_channel(capacity);
+        self.commands = Some(rx);
+        tx
+    }
+
+    /// Queues a switch for the next block.
+    ///
+    /// Returns false if the command is invalid or the queue is full.
+    pub fn schedule(&mut self, command: SwitchCommand) -> bool {
+        if command.input >= self.levels.len() || self.pending.len() == MAX_PENDING {
+            return false;
+        }
+        self.pending.push(command);
+        true
+    }
+
+    /// Switches to `input` at the start of the next block.
+    pub fn select(&mut self, input: usize) -> bool {
+        self.schedule(SwitchCommand::now(input))
+    }
+
+    fn begin_switch(&mut self, command: SwitchCommand) {
+        let frames = command.crossfade_frames.unwrap_or(self.crossfade_frames);
+        self.selected = command.input;
+        for (index, ((level, step), remaining)) in self
+            .levels
+            .iter_mut()
+            .zip(self.steps.iter_mut())
+            .zip(self.remaining.iter_mut())
+            .enumerate()
+        {
+            let target = if index == command.input { 1.0 } else { 0.0 };
+            if frames == 0 {
+                *level = target;
+                *remaining = 0;
+            } else {
+                *step = (target - *level) / frames_as_f32(frames);
+                *remaining = frames;
+            }
+        }
+    }
+
+    /// Renders one block.
+    ///
+    /// `inputs` holds one interleaved block per input, the same length as
+    /// `output`. Missing inputs are treated as silence.
+    pub fn process(&mut self, inputs: &[&[Sample]], output: &mut [Sample]) {
+        if let Some(commands) = &self.commands {
+            while self.pending.len() < MAX_PENDING {
+                match commands.try_recv() {
+                    Some(command) if command.input < self.levels.len() => {
+                        self.pending.push(command);
+                    }
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+        }
+        // Stable so same-offset commands keep their order; never allocates at this length
+        self.pending.sort_by_key(|c| c.offset_frames);
+
+        let channels = self.channels.count_usize();
+        let mut next_pending = 0;
+        for (frame_index, frame) in output.chunks_exact_mut(channels).enumerate() {
+            while let Some(&command) = self.pending.get(next_pending) {
+                if command.offset_frames as usize > frame_index {
+                    break;
+                }
+                self.begin_switch(command);
+                next_pending += 1;
+            }
+
+            frame.fill(Sample::SILENCE);
+            for (input_index, &level) in self.levels.iter().enumerate() {
+                if level <= 0.0 {
+                    continue;
+                }
+                let gain = self.curve.gains(level).1;
+                let start = frame_index * channels;
+                let Some(input) = inputs
+                    .get(input_index)
+                    .and_then(|input| input.get(start..start + channels))
+                else {
+                    continue;
+                };
+                for (out, sample) in frame.iter_mut().zip(input) {
+                    *out = Sample::new(sample.value().mul_add(gain, out.value()));
+                }
+            }
+
+            for ((level, step), remaining) in self
+                .levels
+                .iter_mut()
+                .zip(&self.steps)
+                .zip(self.remaining.iter_mut())
+            {
+                if *remaining > 0 {
+                    *remaining -= 1;
+                    *level = if *remaining == 0 {
+                        level.round()
+                    } else {
+                        (*level + step).clamp(0.0, 1.0)
+                    };
+                }
+            }
+        }
+
+        // Offsets past the end of the block apply at the start of the next one
+        let applied = next_pending;
+        self.pending.drain(..applied);
+        for command in &mut self.pending {
+            command.offset_frames = 0;
+        }
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+const fn frames_as_f32(frames: u32) -> f32 {
+    frames as f32
+}
@@ -0,0 +1,166 @@
+//! The server's address space
+//!
+//! [`request`] reads an incoming message as a command or a subscription;
+//! [`feedback_message`] turns engine feedback into the message broadcast
+//! to subscribers. The addresses are listed in the [module
+//! docs](crate::osc).
+
+use std::time::Duration;
+
+use crate::channel::{EngineCommand, EngineFeedback};
+use crate::dsp::names::NameTable;
+use crate::engine::transport::CountIn;
+use crate::osc::{OscArg, OscMessage};
+use crate::types::{Gain, Pan, SampleRate, Tempo, Timestamp};
+
+/// What an incoming message asks for.
+#[derive(Debug, Clone)]
+pub enum OscRequest {
+    Command(EngineCommand),
+    /// Send feedback to the sender, at this port if given
+    Subscribe(Option<u16>),
+    /// Stop sending feedback to the sender, at this port if given
+    Unsubscribe(Option<u16>),
+}
+
+/// Reads `message`, or `None` for an unknown address or arguments that
+/// don't fit it. `sample_rate` places `/engine/seek`; addresses that
+/// aren't built in are looked up in `names`.
+#[must_use]
+pub fn request(
+    message: &OscMessage,
+    sample_rate: SampleRate,
+    names: &NameTable,
+) -> Option<OscRequest> {
+    let number = || message.args.first().and_then(OscArg::as_f32);
+    let port = || {
+        message
+            .args
+            .first()
+            .and_then(OscArg::as_i32)
+            .and_then(|port| u16::try_from(port).ok())
+    };
+    let mut parts = message.address.split('/').skip(1);
+    let command = match (parts.next()?, parts.next(), parts.next(), parts.next()) {
+        ("subscribe", None, ..) => return Some(OscRequest::Subscribe(port())),
+        ("unsubscribe", None, ..) => return Some(OscRequest::Unsubscribe(port())),
+        ("engine", Some(name), None, _) => match name {
+            "start" => EngineCommand::Start,
+            "stop" => EngineCommand::Stop,
+            "pause" => EngineCommand::Pause,
+            "resume" => EngineCommand::Resume,
+            "gain" => EngineCommand::SetGain(Gain::from_db(number()?)),
+            "pan" => EngineCommand::SetPan(Pan::new(number()?)),
+            "crossfade" => EngineCommand::SetCrossfade(number()?.clamp(0.0, 1.0)),
+            "tempo" => EngineCommand::SetTempo(Tempo::new(f64::from(number()?).max(1.0))),
+            "beat" => EngineCommand::SeekBeats(f64::from(number()?.max(0.0))),
+            "record" => {
+                let bars = |index: usize| {
+                    message
+                        .args
+                        .get(index)
+                        .and_then(OscArg::as_i32)
+                        .map_or(0, |bars| u32::try_from(bars).unwrap_or(0))
+                };
+                EngineCommand::Record(CountIn::new(bars(0), bars(1)))
+            }
+            "record_automation" => {
+                EngineCommand::RecordAutomation(message.args.first()?.as_bool()?)
+            }
+            "seek" => {
+                let seconds = Duration::try_from_secs_f32(number()?.max(0.0)).ok()?;
+                EngineCommand::Seek(Timestamp::from_duration(seconds, sample_rate))
+            }
+            _ => return None,
+        },
+        ("effect", Some(effect_id), Some("enabled"), None) => EngineCommand::SetEffectEnabled {
+            effect_id: effect_id.parse().ok()?,
+            enabled: message.args.first()?.as_bool()?,
+        },
+        ("effect", Some(effect_id), Some("param"), Some(param_id)) if parts.next().is_none() => {
+            EngineCommand::SetEffectParam {
+                effect_id: effect_id.parse().ok()?,
+                param_id: param_id.parse().ok()?,
+                value: number()?,
+            }
+        }
+        _ => named(message, names)?,
+    };
+    Some(OscRequest::Command(command))
+}
+
+/// The command for a path in `names`: a parameter, or an effect followed
+/// by `enabled`.
+fn named(message: &OscMessage, names: &NameTable) -> Option<EngineCommand> {
+    let arg = message.args.first()?;
+    if let Some(address) = names.param(&message.address) {
+        return Some(EngineCommand::SetEffectParam {
+            effect_id: address.effect_id.value(),
+            param_id: address.param_id.value(),
+            value: arg.as_f32()?,
+        });
+    }
+    let effect_id = names.effect(message.address.strip_suffix("/enabled")?)?;
+    Some(EngineCommand::SetEffectEnabled {
+        effect_id: effect_id.value(),
+        enabled: arg.as_bool()?,
+    })
+}
+
+/// The message broadcast for `feedback`, if it has one
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn feedback_message(feedback: &EngineFeedback) -> Option<OscMessage> {
+    Some(match feedback {
+        EngineFeedback::Levels {
+            input_db,
+            output_db,
+        } => OscMessage::new("/engine/levels")
+            .with_arg(OscArg::Float(input_db.value()))
+            .with_arg(OscArg::Float(output_db.value())),
+        EngineFeedback::Position(position) => {
+            let message = OscMessage::new("/engine/position")
+                .with_arg(OscArg::Float(position.total_seconds_f64() as f32));
+            match position.timecode() {
+                Some(timecode) => message.with_arg(OscArg::String(timecode.to_string())),
+                None => message,
+            }
+        }
+        EngineFeedback::StateChanged(state) => {
+            OscMessage::new("/engine/state").with_arg(OscArg::String(state.name().to_string()))
+        }
+        EngineFeedback::Underrun => OscMessage::new("/engine/underrun"),
+        EngineFeedback::Error(message) => {
+            OscMessage::new("/engine/error").with_arg(OscArg::String(message.clone()))
+        }
+        EngineFeedback::StreamRecovery {
+            attempt,
+            device,
+            recovered,
+            ..
+        } => OscMessage::new("/engine/recovery")
+            .with_arg(OscArg::Int(i32::try_from(*attempt).unwrap_or(i32::MAX)))
+            .with_arg(OscArg::String(device.clone()))
+            .with_arg(OscArg::Bool(*recovered)),
+        EngineFeedback::EffectFailed { effect_id } => OscMessage::new("/engine/effect_failed")
+            .with_arg(OscArg::Int(i32::try_from(*effect_id).unwrap_or(i32::MAX))),
+        EngineFeedback::NetworkLatency { latency, jitter } => {
+            OscMessage::new("/engine/network_latency")
+                .with_arg(OscArg::Float(latency.as_secs_f32() * 1000.0))
+                .with_arg(OscArg::Float(jitter.as_secs_f32() * 1000.0))
+        }
+        EngineFeedback::ExposureWarning(warning) => OscMessage::new("/engine/exposure")
+            .with_arg(OscArg::String(warning.limit.to_string()))
+            .with_arg(OscArg::Float(warning.dose as f32))
+            .with_arg(OscArg::Float(warning.level as f32)),
+        EngineFeedback::AllocationViolation { .. }
+        | EngineFeedback::RenderProgress { .. }
+        | EngineFeedback::ParamRecorded { .. }
+        | EngineFeedback::StreamClosed { .. }
+        | EngineFeedback::StreamDetached { .. }
+        | EngineFeedback::MarkerReached { .. }
+        | EngineFeedback::BufferSizeChanged { .. }
+        | EngineFeedback::QualityChanged { .. }
+        | EngineFeedback::BitrateChanged { .. } => return None,
+    })
+}
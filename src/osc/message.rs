@@ -0,0 +1,231 @@
+//! OSC 1.0 messages and their wire format
+//!
+//! Messages carry an address and typed arguments, each padded to four
+//! bytes and big endian. Bundles are unpacked into their messages; their
+//! time tags are ignored and everything in them applies on arrival.
+
+/// One argument of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Blob(Vec<u8>),
+    /// The `T` and `F` types, which carry no data
+    Bool(bool),
+}
+
+impl OscArg {
+    const fn tag(&self) -> u8 {
+        match self {
+            Self::Int(_) => b'i',
+            Self::Float(_) => b'f',
+            Self::String(_) => b's',
+            Self::Blob(_) => b'b',
+            Self::Bool(true) => b'T',
+            Self::Bool(false) => b'F',
+        }
+    }
+
+    /// The argument as a number, if it is one
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub const fn as_f32(&self) -> Option<f32> {
+        match *self {
+            Self::Int(value) => Some(value as f32),
+            Self::Float(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn as_i32(&self) -> Option<i32> {
+        match *self {
+            Self::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The argument as a switch: `T` and `F`, or a number, on from 0.5
+    #[must_use]
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Self::Bool(value) => Some(value),
+            _ => self.as_f32().map(|value| value >= 0.5),
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// An address and its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+/// Bundles nested deeper than this are dropped
+const MAX_BUNDLE_DEPTH: usize = 8;
+const BUNDLE_TAG: &[u8] = b"#bundle";
+
+impl OscMessage {
+    #[must_use]
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            args: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_arg(mut self, arg: OscArg) -> Self {
+        self.args.push(arg);
+        self
+    }
+
+    /// The message as sent on the wire
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_string(&mut bytes, self.address.as_bytes());
+        let mut tags = vec![b','];
+        tags.extend(self.args.iter().map(OscArg::tag));
+        write_string(&mut bytes, &tags);
+        for arg in &self.args {
+            match arg {
+                OscArg::Int(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+                OscArg::Float(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+                OscArg::String(value) => write_string(&mut bytes, value.as_bytes()),
+                OscArg::Blob(data) => {
+                    let size = i32::try_from(data.len()).unwrap_or(i32::MAX);
+                    bytes.extend_from_slice(&size.to_be_bytes());
+                    bytes.extend_from_slice(data);
+                    pad(&mut bytes);
+                }
+                OscArg::Bool(_) => {}
+            }
+        }
+        bytes
+    }
+
+    /// Parses one message. Anything malformed, or with argument types
+    /// other than `i`, `f`, `s`, `b`, `T` and `F`, gives `None`.
+    #[must_use]
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader { bytes, position: 0 };
+        let address = reader.string()?;
+        if !address.starts_with('/') {
+            return None;
+        }
+        // A message without type tags is allowed and has no arguments
+        let tags = if reader.is_empty() {
+            String::from(",")
+        } else {
+            reader.string()?
+        };
+        let tags = tags.strip_prefix(',')?;
+        let args = tags
+            .bytes()
+            .map(|tag| match tag {
+                b'i' => reader.i32().map(OscArg::Int),
+                b'f' => reader.f32().map(OscArg::Float),
+                b's' => reader.string().map(OscArg::String),
+                b'b' => reader.blob().map(OscArg::Blob),
+                b'T' => Some(OscArg::Bool(true)),
+                b'F' => Some(OscArg::Bool(false)),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { address, args })
+    }
+}
+
+/// The messages in a packet: the message itself, or every message in a
+/// bundle and the bundles nested in it. Malformed parts are skipped.
+#[must_use]
+pub fn decode_packet(bytes: &[u8]) -> Vec<OscMessage> {
+    let mut messages = Vec::new();
+    unpack(bytes, 0, &mut messages);
+    messages
+}
+
+fn unpack(bytes: &[u8], depth: usize, messages: &mut Vec<OscMessage>) {
+    if !bytes.starts_with(BUNDLE_TAG) {
+        messages.extend(OscMessage::decode(bytes));
+        return;
+    }
+    if depth >= MAX_BUNDLE_DEPTH {
+        return;
+    }
+    let mut reader = Reader { bytes, position: 0 };
+    // Skip the tag and the time tag
+    if reader.string().is_none() || reader.take(8).is_none() {
+        return;
+    }
+    while let Some(element) = reader.blob_slice() {
+        unpack(element, depth + 1, messages);
+    }
+}
+
+/// Writes `value` with its terminating zero, padded to four bytes.
+fn write_string(bytes: &mut Vec<u8>, value: &[u8]) {
+    bytes.extend_from_slice(value);
+    bytes.push(0);
+    pad(bytes);
+}
+
+fn pad(bytes: &mut Vec<u8>) {
+    bytes.resize(bytes.len().next_multiple_of(4), 0);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    const fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(count)?;
+        let slice = self.bytes.get(self.position..end)?;
+        self.position = end.next_multiple_of(4);
+        Some(slice)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let rest = self.bytes.get(self.position..)?;
+        let length = rest.iter().position(|byte| *byte == 0)?;
+        let value = std::str::from_utf8(&rest[..length]).ok()?.to_string();
+        self.take(length + 1)?;
+        Some(value)
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        let bytes = self.take(4)?;
+        Some(i32::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        let bytes = self.take(4)?;
+        Some(f32::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    fn blob_slice(&mut self) -> Option<&'a [u8]> {
+        let size = usize::try_from(self.i32()?).ok()?;
+        self.take(size)
+    }
+
+    fn blob(&mut self) -> Option<Vec<u8>> {
+        self.blob_slice().map(<[u8]>::to_vec)
+    }
+}
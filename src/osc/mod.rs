@@ -0,0 +1,59 @@
+//! OSC remote control
+//!
+//! [`OscServer`] listens for Open Sound Control messages on a UDP socket
+//! and turns them into [`EngineCommand`]s, queued for the control thread
+//! to send on like those from MIDI. Clients that subscribe get engine
+//! feedback, such as level meters, sent back to them.
+//!
+//! Addresses the server reads, with their arguments:
+//!
+//! | Address | Arguments | Command |
+//! |---------|-----------|---------|
+//! | `/engine/start`, `/engine/stop`, `/engine/pause`, `/engine/resume` | | the same |
+//! | `/engine/gain` | dB | [`EngineCommand::SetGain`] |
+//! | `/engine/pan` | -1.0 to 1.0 | [`EngineCommand::SetPan`] |
+//! | `/engine/crossfade` | 0.0 to 1.0 | [`EngineCommand::SetCrossfade`] |
+//! | `/engine/tempo` | BPM | [`EngineCommand::SetTempo`] |
+//! | `/engine/seek` | seconds | [`EngineCommand::Seek`] |
+//! | `/engine/beat` | quarter notes | [`EngineCommand::SeekBeats`] |
+//! | `/engine/record` | count-in and pre-roll bars, both optional | [`EngineCommand::Record`] |
+//! | `/engine/record_automation` | `T`/`F` or a number | [`EngineCommand::RecordAutomation`] |
+//! | `/effect/{id}/param/{id}` | value | [`EngineCommand::SetEffectParam`] |
+//! | `/effect/{id}/enabled` | `T`/`F` or a number | [`EngineCommand::SetEffectEnabled`] |
+//! | `/{path}` | value | [`EngineCommand::SetEffectParam`] |
+//! | `/{path}/enabled` | `T`/`F` or a number | [`EngineCommand::SetEffectEnabled`] |
+//! | `/subscribe`, `/unsubscribe` | optional reply port | |
+//!
+//! Paths are those of the [`NameTable`] given to
+//! [`OscServer::set_names`], so `/master/eq/frequency` works as well as
+//! `/effect/3/param/0`.
+//!
+//! Numbers may be sent as ints or floats. Subscribers get
+//! `/engine/levels` (input and output dB), `/engine/position` (seconds,
+//! then the SMPTE timecode if the transport has a frame rate),
+//! `/engine/state`, `/engine/underrun`, `/engine/error` and
+//! `/engine/recovery` (attempt, device and whether it worked),
+//! `/engine/effect_failed` (effect id) and `/engine/network_latency`
+//! (latency and jitter in ms), sent to the address they subscribed from or
+//! to the port they named.
+//!
+//! [`NameTable`]: crate::dsp::names::NameTable
+//! [`EngineCommand`]: crate::channel::EngineCommand
+//! [`EngineCommand::SetGain`]: crate::channel::EngineCommand::SetGain
+//! [`EngineCommand::SetPan`]: crate::channel::EngineCommand::SetPan
+//! [`EngineCommand::SetCrossfade`]: crate::channel::EngineCommand::SetCrossfade
+//! [`EngineCommand::SetTempo`]: crate::channel::EngineCommand::SetTempo
+//! [`EngineCommand::Seek`]: crate::channel::EngineCommand::Seek
+//! [`EngineCommand::SeekBeats`]: crate::channel::EngineCommand::SeekBeats
+//! [`EngineCommand::Record`]: crate::channel::EngineCommand::Record
+//! [`EngineCommand::RecordAutomation`]: crate::channel::EngineCommand::RecordAutomation
+//! [`EngineCommand::SetEffectParam`]: crate::channel::EngineCommand::SetEffectParam
+//! [`EngineCommand::SetEffectEnabled`]: crate::channel::EngineCommand::SetEffectEnabled
+
+pub mod address;
+pub mod message;
+pub mod server;
+
+pub use address::{OscRequest, feedback_message, request};
+pub use message::{OscArg, OscMessage, decode_packet};
+pub use server::OscServer;
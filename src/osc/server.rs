@@ -0,0 +1,248 @@
+//! UDP server for OSC remote control
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use flume::{Receiver, Sender};
+use parking_lot::Mutex;
+
+use crate::channel::{EngineCommand, EngineFeedback};
+use crate::dsp::names::NameTable;
+use crate::engine::Engine;
+use crate::error::{AudioEngineError, Result};
+use crate::osc::address::{OscRequest, feedback_message, request};
+use crate::osc::decode_packet;
+use crate::types::SampleRate;
+
+/// Commands that can be waiting for the control thread
+const COMMAND_CAPACITY: usize = 1024;
+/// Clients that can subscribe at once
+const MAX_CLIENTS: usize = 16;
+/// Largest UDP payload
+const MAX_PACKET: usize = 65_507;
+/// How often the listening thread checks whether to stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An OSC server listening on a UDP socket.
+///
+/// Messages are read on a thread of their own and queue up as commands
+/// until the control thread collects them. The thread stops when this is
+/// dropped.
+pub struct OscServer {
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+    clients: Arc<Mutex<Vec<SocketAddr>>>,
+    names: Arc<Mutex<NameTable>>,
+    commands: Receiver<EngineCommand>,
+    /// A command [`dispatch`](Self::dispatch) couldn't send yet
+    pending: Option<EngineCommand>,
+    quit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OscServer {
+    /// Listens on `address`, e.g. `"0.0.0.0:9000"`. `sample_rate` is the
+    /// engine's, for placing `/engine/seek`.
+    ///
+    /// # Errors
+    /// Returns an error if the socket can't be bound or the thread
+    /// started.
+    pub fn bind(address: impl ToSocketAddrs, sample_rate: SampleRate) -> Result<Self> {
+        let socket = UdpSocket::bind(address).map_err(|e| AudioEngineError::NetworkConnection {
+            message: format!("Failed to bind OSC socket: {e}"),
+        })?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let local_addr = socket.local_addr()?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let names = Arc::new(Mutex::new(NameTable::new()));
+        let quit = Arc::new(AtomicBool::new(false));
+        let (sender, commands) = flume::bounded(COMMAND_CAPACITY);
+        let thread = std::thread::Builder::new()
+            .name("osc-server".to_string())
+            .spawn({
+                let socket = socket.try_clone()?;
+                let (clients, names) = (Arc::clone(&clients), Arc::clone(&names));
+                let quit = Arc::clone(&quit);
+                move || listen(&socket, sample_rate, &clients, &names, &sender, &quit)
+            })
+            .map_err(|e| AudioEngineError::NetworkConnection {
+                message: format!("Failed to start OSC server thread: {e}"),
+            })?;
+        log::info!("Listening for OSC on {local_addr}");
+        Ok(Self {
+            socket,
+            local_addr,
+            clients,
+            names,
+            commands,
+            pending: None,
+            quit,
+            thread: Some(thread),
+        })
+    }
+
+    /// Binds like [`bind`](Self::bind), resolving `address` and binding on
+    /// another thread so an async caller isn't blocked.
+    ///
+    /// # Errors
+    /// Returns an error if the socket can't be bound or the thread
+    /// started.
+    #[cfg(feature = "async")]
+    pub async fn bind_async(
+        address: impl ToSocketAddrs + Send + 'static,
+        sample_rate: SampleRate,
+    ) -> Result<Self> {
+        crate::channel::unblock("osc-bind", move || Self::bind(address, sample_rate)).await
+    }
+
+    /// Address the server listens on
+    #[must_use]
+    pub const fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Clients receiving feedback
+    #[must_use]
+    pub fn clients(&self) -> Vec<SocketAddr> {
+        self.clients.lock().clone()
+    }
+
+    /// Makes the paths in `names` addresses, so `/master/eq/gain` sets
+    /// that parameter and `/master/eq/enabled` switches that effect.
+    /// Built-in addresses take precedence.
+    pub fn set_names(&self, names: NameTable) {
+        *self.names.lock() = names;
+    }
+
+    /// The next command, oldest first
+    pub fn try_recv(&mut self) -> Option<EngineCommand> {
+        self.pending
+            .take()
+            .or_else(|| self.commands.try_recv().ok())
+    }
+
+    /// Sends every waiting command to `engine`, returning how many were
+    /// sent. They take effect at the start of the engine's next block.
+    ///
+    /// # Errors
+    /// Returns an error if the engine's command queue is full; the
+    /// remaining commands stay queued.
+    pub fn dispatch(&mut self, engine: &mut Engine) -> Result<usize> {
+        let mut sent = 0;
+        while let Some(command) = self.try_recv() {
+            if let Err(e) = engine.send(command.clone()) {
+                self.pending = Some(command);
+                return Err(e);
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Sends `feedback` to every subscribed client, returning how many it
+    /// reached. Feedback without an address is skipped.
+    ///
+    /// Hosts pass on what they read from [`Engine::feedback`]; the
+    /// server doesn't read it itself, so the host still sees all of it.
+    #[must_use]
+    pub fn broadcast(&self, feedback: &EngineFeedback) -> usize {
+        let Some(message) = feedback_message(feedback) else {
+            return 0;
+        };
+        let bytes = message.encode();
+        let clients = self.clients.lock();
+        clients
+            .iter()
+            .filter(|client| match self.socket.send_to(&bytes, client) {
+                Ok(_) => true,
+                Err(e) => {
+                    log::debug!("Failed to send OSC feedback to {client}: {e}");
+                    false
+                }
+            })
+            .count()
+    }
+}
+
+impl Drop for OscServer {
+    fn drop(&mut self) {
+        self.quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl std::fmt::Debug for OscServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OscServer")
+            .field("local_addr", &self.local_addr)
+            .field("clients", &self.clients)
+            .field("commands", &self.commands.len())
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Runs on the server thread until `quit` is set.
+fn listen(
+    socket: &UdpSocket,
+    sample_rate: SampleRate,
+    clients: &Mutex<Vec<SocketAddr>>,
+    names: &Mutex<NameTable>,
+    sender: &Sender<EngineCommand>,
+    quit: &AtomicBool,
+) {
+    let mut packet = vec![0; MAX_PACKET];
+    while !quit.load(Ordering::Relaxed) {
+        let (length, from) = match socket.recv_from(&mut packet) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => {
+                log::warn!("OSC receive failed: {e}");
+                continue;
+            }
+        };
+        for message in decode_packet(&packet[..length]) {
+            let parsed = request(&message, sample_rate, &names.lock());
+            match parsed {
+                Some(OscRequest::Command(command)) => {
+                    if let Err(e) = sender.try_send(command) {
+                        log::warn!("OSC command queue full, dropping {:?}", e.into_inner());
+                    }
+                }
+                Some(OscRequest::Subscribe(port)) => subscribe(clients, from, port),
+                Some(OscRequest::Unsubscribe(port)) => {
+                    let client = with_port(from, port);
+                    clients.lock().retain(|subscribed| *subscribed != client);
+                }
+                None => log::debug!("Ignoring OSC message {} from {from}", message.address),
+            }
+        }
+    }
+}
+
+fn subscribe(clients: &Mutex<Vec<SocketAddr>>, from: SocketAddr, port: Option<u16>) {
+    let client = with_port(from, port);
+    let mut clients = clients.lock();
+    if clients.contains(&client) {
+        return;
+    }
+    if clients.len() >= MAX_CLIENTS {
+        log::warn!("Too many OSC clients, ignoring {client}");
+        return;
+    }
+    log::info!("OSC client {client} subscribed");
+    clients.push(client);
+}
+
+const fn with_port(mut address: SocketAddr, port: Option<u16>) -> SocketAddr {
+    if let Some(port) = port {
+        address.set_port(port);
+    }
+    address
+}
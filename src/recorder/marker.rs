@@ -0,0 +1,116 @@
+//! Named markers dropped while recording
+
+use std::fmt;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::markers::RealtimeSafe;
+use crate::types::SampleRate;
+
+/// Longest marker name in bytes; longer names are truncated
+pub const MAX_MARKER_NAME: usize = 64;
+
+/// A marker name stored inline so it can pass through the audio thread
+/// without allocating.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MarkerName {
+    bytes: [u8; MAX_MARKER_NAME],
+    len: u8,
+}
+
+impl MarkerName {
+    /// Creates a name, truncating at a character boundary if needed.
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        let mut end = name.len().min(MAX_MARKER_NAME);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut bytes = [0; MAX_MARKER_NAME];
+        bytes[..end].copy_from_slice(&name.as_bytes()[..end]);
+        Self {
+            bytes,
+            len: u8::try_from(end).unwrap_or(u8::MAX),
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..usize::from(self.len)]).unwrap_or_default()
+    }
+}
+
+impl From<&str> for MarkerName {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl fmt::Debug for MarkerName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for MarkerName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl RealtimeSafe for MarkerName {}
+
+/// A marker in a finished take.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    /// Position in frames from the start of the take
+    pub position: u64,
+    pub name: String,
+}
+
+impl Marker {
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn seconds(&self, sample_rate: SampleRate) -> f64 {
+        self.position as f64 / f64::from(sample_rate.as_hz())
+    }
+}
+
+/// Writes markers as a podcast chapters JSON file.
+///
+/// # Errors
+/// Returns an error if the file can't be written.
+pub fn write_chapters_json(path: &Path, markers: &[Marker], sample_rate: SampleRate) -> Result<()> {
+    let mut json = String::from("{\n  \"version\": \"1.2.0\",\n  \"chapters\": [");
+    for (index, marker) in markers.iter().enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        let _ = write!(
+            json,
+            "{separator}\n    {{ \"startTime\": {:.3}, \"title\": \"{}\" }}",
+            marker.seconds(sample_rate),
+            escape_json(&marker.name)
+        );
+    }
+    json.push_str("\n  ]\n}\n");
+    std::fs::write(path, json)?;
+    Ok(())
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", u32::from(c));
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
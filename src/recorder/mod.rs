@@ -0,0 +1,591 @@
+//! Recording to disk
+//!
+//! Recording is split across two threads. The [`Recorder`] runs on the audio
+//! thread: it decides when a take starts and stops and pushes samples into a
+//! lock-free ring. The [`RecordingWriter`] runs on a normal thread and drains
+//! that ring into one WAV file per take.
+//!
+//! In [`RecordMode::LevelTriggered`] the recorder starts a take when the
+//! input crosses a threshold, prepends the configured pre-roll, and ends the
+//! take once the input has stayed below the threshold for the hang time.
+//!
+//! Named markers can be dropped into the current take with
+//! [`RecorderCommand::Marker`]. They are stored as cue points in the WAV file
+//! and, optionally, in a podcast chapters JSON file next to it.
+//!
+//! With [`RecorderSettings::with_loudness_log`] the writer also meters each
+//! take and logs its loudness history next to the audio file, and with
+//! [`RecorderSettings::with_broadcast`] takes are written as Broadcast Wave
+//! files.
+//!
+//! [`MultitrackRecorder`] records several tracks at once, each from its own
+//! input, with takes following the transport's punch range.
+
+pub mod marker;
+pub mod multitrack;
+pub mod trigger;
+
+pub use marker::{Marker, MarkerName};
+pub use multitrack::{
+    MultitrackCommand, MultitrackRecorder, MultitrackSettings, MultitrackWriter, RecordedTake,
+    TakeLane, TrackInput, WriterThread,
+};
+pub use trigger::TriggerSettings;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::{LoudnessLogFormat, LoudnessLogger};
+use crate::buffer::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::channel::{
+    ControlReceiver, ControlSender, RealtimeReceiver, RealtimeSender, control_channel,
+    feedback_channel,
+};
+use crate::error::Result;
+use crate::io::bwf::BroadcastInfo;
+use crate::io::wav::WavWriter;
+use crate::markers::RealtimeSafe;
+use crate::types::{AudioFormat, Sample};
+
+use trigger::LevelTrigger;
+
+/// Number of take start/stop events that can be in flight
+const EVENT_CAPACITY: usize = 64;
+
+/// How the recorder decides when to capture.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RecordMode {
+    /// Record everything between `arm` and `disarm`
+    #[default]
+    Continuous,
+    /// Record only while the input is above a threshold
+    LevelTriggered(TriggerSettings),
+}
+
+/// Recorder configuration.
+#[derive(Debug, Clone)]
+pub struct RecorderSettings {
+    /// Directory takes are written to
+    pub directory: PathBuf,
+    /// File name prefix, followed by the take number
+    pub prefix: String,
+    /// Format of the incoming audio and the files written
+    pub format: AudioFormat,
+    pub mode: RecordMode,
+    /// Ring buffer length between the audio thread and the writer
+    pub buffer_ms: u32,
+    /// Also write markers to a `.chapters.json` file next to each take
+    pub chapters_sidecar: bool,
+    /// Also write a loudness log (`.loudness.csv` / `.loudness.json`) per take
+    pub loudness_log: Option<LoudnessLogFormat>,
+    /// Broadcast Wave metadata written into each take
+    pub broadcast: Option<BroadcastInfo>,
+}
+
+impl RecorderSettings {
+    #[must_use]
+    pub fn new(directory: impl Into<PathBuf>, format: AudioFormat) -> Self {
+        Self {
+            directory: directory.into(),
+            prefix: "take".to_string(),
+            format,
+            mode: RecordMode::Continuous,
+            buffer_ms: 2000,
+            chapters_sidecar: false,
+            loudness_log: None,
+            broadcast: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    #[must_use]
+    pub const fn with_mode(mut self, mode: RecordMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_buffer_ms(mut self, buffer_ms: u32) -> Self {
+        self.buffer_ms = buffer_ms;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_chapters_sidecar(mut self) -> Self {
+        self.chapters_sidecar = true;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_loudness_log(mut self, format: LoudnessLogFormat) -> Self {
+        self.loudness_log = Some(format);
+        self
+    }
+
+    /// Writes takes as Broadcast Wave files. The origination and time
+    /// reference, unless set, are taken from when each take starts.
+    #[must_use]
+    pub fn with_broadcast(mut self, info: BroadcastInfo) -> Self {
+        self.broadcast = Some(info);
+        self
+    }
+}
+
+/// Commands for a [`Recorder`], sent from the control thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecorderCommand {
+    Arm,
+    Disarm,
+    /// Drop a named marker at the current position of the take
+    Marker(MarkerName),
+}
+
+impl RealtimeSafe for RecorderCommand {}
+
+/// Take boundaries and markers sent from the audio thread to the writer.
+///
+/// Sample indices count every sample pushed into the ring since creation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecorderEvent {
+    TakeStarted {
+        take: u32,
+        sample_index: u64,
+    },
+    TakeStopped {
+        take: u32,
+        sample_index: u64,
+    },
+    Marker {
+        take: u32,
+        /// Frames from the start of the take
+        position: u64,
+        name: MarkerName,
+    },
+}
+
+impl RealtimeSafe for RecorderEvent {}
+
+/// Audio thread side of a recording.
+#[derive(Debug)]
+pub struct Recorder {
+    mode: RecordMode,
+    channels: usize,
+    samples: RingBufferWriter<Sample>,
+    events: RealtimeSender<RecorderEvent>,
+    commands: Option<RealtimeReceiver<RecorderCommand>>,
+    trigger: Option<LevelTrigger>,
+    armed: bool,
+    recording: bool,
+    take: u32,
+    take_start: u64,
+    pushed: u64,
+    dropped_frames: u64,
+}
+
+impl Recorder {
+    /// Creates the recorder and the writer that drains it.
+    #[must_use]
+    pub fn new(settings: RecorderSettings) -> (Self, RecordingWriter) {
+        let format = settings.format;
+        let channels = format.channels.count_usize();
+        let frames = format
+            .sample_rate
+            .samples_for_milliseconds(settings.buffer_ms) as usize;
+        let (samples_tx, samples_rx) = RingBuffer::new((frames * channels).max(channels));
+        let (events_tx, events_rx) = feedback_channel(EVENT_CAPACITY);
+
+        let trigger = match settings.mode {
+            RecordMode::Continuous => None,
+            RecordMode::LevelTriggered(trigger) => Some(LevelTrigger::new(trigger, format)),
+        };
+
+        let recorder = Self {
+            mode: settings.mode,
+            channels,
+            samples: samples_tx,
+            events: events_tx,
+            commands: None,
+            trigger,
+            armed: false,
+            recording: false,
+            take: 0,
+            take_start: 0,
+            pushed: 0,
+            dropped_frames: 0,
+        };
+        let writer = RecordingWriter {
+            directory: settings.directory,
+            prefix: settings.prefix,
+            format,
+            chapters_sidecar: settings.chapters_sidecar,
+            loudness_log: settings.loudness_log,
+            broadcast: settings.broadcast,
+            samples: samples_rx,
+            events: events_rx,
+            pending: VecDeque::with_capacity(EVENT_CAPACITY),
+            current: None,
+            popped: 0,
+            scratch: vec![Sample::SILENCE; 4096 * channels],
+        };
+        (recorder, writer)
+    }
+
+    #[must_use]
+    pub const fn mode(&self) -> RecordMode {
+        self.mode
+    }
+
+    #[must_use]
+    pub const fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    /// Returns true while a take is being captured.
+    #[must_use]
+    pub const fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Frames lost because the writer fell behind
+    #[must_use]
+    pub const fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    /// Creates the control side of the recorder's command channel.
+    ///
+    /// Commands sent through it are applied at the start of each block.
+    pub fn command_sender(&mut self, capacity: usize) -> ControlSender<RecorderCommand> {
+        let (tx, rx) = control_channel(capacity);
+        self.commands = Some(rx);
+        tx
+    }
+
+    /// Applies a command immediately.
+    pub fn apply(&mut self, command: RecorderCommand) {
+        match command {
+            RecorderCommand::Arm => self.arm(),
+            RecorderCommand::Disarm => self.disarm(),
+            RecorderCommand::Marker(name) => {
+                self.add_marker(name);
+            }
+        }
+    }
+
+    /// Drops a marker at the current position of the take.
+    ///
+    /// Returns false if no take is being recorded.
+    pub fn add_marker(&mut self, name: MarkerName) -> bool {
+        if !self.recording {
+            return false;
+        }
+        self.events.try_send(RecorderEvent::Marker {
+            take: self.take,
+            position: (self.pushed - self.take_start) / self.channels as u64,
+            name,
+        })
+    }
+
+    /// Arms the recorder.
+    ///
+    /// In continuous mode this starts a take immediately; in level-triggered
+    /// mode it starts listening for the threshold.
+    pub fn arm(&mut self) {
+        self.armed = true;
+        if let Some(trigger) = &mut self.trigger {
+            trigger.reset();
+        } else {
+            self.start_take();
+        }
+    }
+
+    /// Disarms the recorder, ending any take in progress.
+    pub fn disarm(&mut self) {
+        self.armed = false;
+        self.stop_take();
+    }
+
+    fn start_take(&mut self) {
+        if self.recording {
+            return;
+        }
+        self.recording = true;
+        self.take += 1;
+        self.take_start = self.pushed;
+        let _ = self.events.try_send(RecorderEvent::TakeStarted {
+            take: self.take,
+            sample_index: self.pushed,
+        });
+    }
+
+    fn stop_take(&mut self) {
+        if !self.recording {
+            return;
+        }
+        self.recording = false;
+        let _ = self.events.try_send(RecorderEvent::TakeStopped {
+            take: self.take,
+            sample_index: self.pushed,
+        });
+    }
+
+    fn push(&mut self, samples: &[Sample]) {
+        // Only whole blocks go in so the file never loses channel alignment
+        if self.samples.slots() < samples.len() {
+            self.dropped_frames += (samples.len() / self.channels) as u64;
+            return;
+        }
+        self.pushed += self.samples.push_slice(samples) as u64;
+    }
+
+    /// Feeds one block of interleaved input.
+    pub fn process(&mut self, input: &[Sample]) {
+        while let Some(command) = self.commands.as_ref().and_then(RealtimeReceiver::try_recv) {
+            self.apply(command);
+        }
+        if !self.armed {
+            return;
+        }
+        let Some(mut trigger) = self.trigger.take() else {
+            self.push(input);
+            return;
+        };
+
+        for frame in input.chunks_exact(self.channels) {
+            let over = trigger.is_over_threshold(frame);
+            if self.recording {
+                self.push(frame);
+                if trigger.hang_elapsed(over) {
+                    self.stop_take();
+                }
+            } else if over {
+                self.start_take();
+                let (older, newer) = trigger.pre_roll();
+                self.push(older);
+                self.push(newer);
+                trigger.clear_pre_roll();
+                self.push(frame);
+                trigger.hang_elapsed(over);
+            } else {
+                trigger.store_pre_roll(frame);
+            }
+        }
+        self.trigger = Some(trigger);
+    }
+}
+
+/// A finished take.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TakeInfo {
+    pub take: u32,
+    pub path: PathBuf,
+    pub frames: u64,
+    pub markers: Vec<Marker>,
+    /// Loudness log written alongside the take
+    pub loudness_log: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+struct OpenTake {
+    take: u32,
+    path: PathBuf,
+    wav: WavWriter<BufWriter<File>>,
+    markers: Vec<Marker>,
+    loudness: Option<(PathBuf, LoudnessLogger<BufWriter<File>>)>,
+}
+
+/// Writer thread side of a recording.
+#[derive(Debug)]
+pub struct RecordingWriter {
+    directory: PathBuf,
+    prefix: String,
+    format: AudioFormat,
+    chapters_sidecar: bool,
+    loudness_log: Option<LoudnessLogFormat>,
+    broadcast: Option<BroadcastInfo>,
+    samples: RingBufferReader<Sample>,
+    events: ControlReceiver<RecorderEvent>,
+    pending: VecDeque<RecorderEvent>,
+    current: Option<OpenTake>,
+    popped: u64,
+    scratch: Vec<Sample>,
+}
+
+impl RecordingWriter {
+    #[must_use]
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Path of the take currently being written
+    #[must_use]
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current.as_ref().map(|t| t.path.as_path())
+    }
+
+    /// Share of the ring waiting to be written, from 0 to 1; the recorder
+    /// drops frames once it reaches 1
+    #[must_use]
+    pub fn backlog(&self) -> f32 {
+        #[allow(clippy::cast_precision_loss)]
+        let backlog = self.samples.slots() as f32 / self.samples.capacity().max(1) as f32;
+        backlog
+    }
+
+    /// Moves everything the recorder produced so far to disk.
+    ///
+    /// Call this regularly from a non-realtime thread. Returns the takes that
+    /// were completed during this call.
+    ///
+    /// # Errors
+    /// Returns an error if a file can't be created or written.
+    pub fn service(&mut self) -> Result<Vec<TakeInfo>> {
+        while let Some(event) = self.events.try_recv() {
+            self.pending.push_back(event);
+        }
+
+        let mut finished = Vec::new();
+        while let Some(&event) = self.pending.front() {
+            match event {
+                RecorderEvent::TakeStarted { take, sample_index } => {
+                    // Anything before the start belongs to no take
+                    self.drain_until(Some(sample_index))?;
+                    if let Some(info) = self.close_take()? {
+                        finished.push(info);
+                    }
+                    self.open_take(take)?;
+                }
+                RecorderEvent::TakeStopped { sample_index, .. } => {
+                    self.drain_until(Some(sample_index))?;
+                    if self.popped < sample_index {
+                        // The recorder hasn't pushed everything yet
+                        break;
+                    }
+                    if let Some(info) = self.close_take()? {
+                        finished.push(info);
+                    }
+                }
+                RecorderEvent::Marker {
+                    take,
+                    position,
+                    name,
+                } => self.add_marker(take, position, name),
+            }
+            self.pending.pop_front();
+        }
+        if self.pending.is_empty() {
+            self.drain_until(None)?;
+        }
+        Ok(finished)
+    }
+
+    /// Writes out what is left and closes the current take.
+    ///
+    /// # Errors
+    /// Returns an error if the remaining audio can't be written.
+    pub fn finish(&mut self) -> Result<Vec<TakeInfo>> {
+        let mut finished = self.service()?;
+        if let Some(info) = self.close_take()? {
+            finished.push(info);
+        }
+        Ok(finished)
+    }
+
+    fn open_take(&mut self, take: u32) -> Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        let path = self
+            .directory
+            .join(format!("{}-{take:04}.wav", self.prefix));
+        let wav = match &self.broadcast {
+            Some(info) => WavWriter::create_broadcast(&path, self.format, info)?,
+            None => WavWriter::create(&path, self.format)?,
+        };
+        let loudness = match self.loudness_log {
+            Some(log_format) => {
+                let log_path = path.with_extension(format!("loudness.{}", log_format.extension()));
+                let logger = LoudnessLogger::create(&log_path, self.format, log_format)?;
+                Some((log_path, logger))
+            }
+            None => None,
+        };
+        self.current = Some(OpenTake {
+            take,
+            path,
+            wav,
+            markers: Vec::new(),
+            loudness,
+        });
+        Ok(())
+    }
+
+    fn add_marker(&mut self, take: u32, position: u64, name: MarkerName) {
+        let Some(open) = self.current.as_mut().filter(|open| open.take == take) else {
+            return;
+        };
+        match u32::try_from(position) {
+            Ok(cue_position) => open.wav.add_cue(cue_position, name.as_str()),
+            Err(_) => log::warn!("marker '{name}' is beyond the range of WAV cue points"),
+        }
+        open.markers.push(Marker {
+            position,
+            name: name.as_str().to_string(),
+        });
+    }
+
+    fn close_take(&mut self) -> Result<Option<TakeInfo>> {
+        let Some(mut open) = self.current.take() else {
+            return Ok(None);
+        };
+        open.wav.finalize()?;
+        let loudness_log = match open.loudness {
+            Some((log_path, mut logger)) => {
+                logger.finish()?;
+                Some(log_path)
+            }
+            None => None,
+        };
+        if self.chapters_sidecar && !open.markers.is_empty() {
+            let sidecar = open.path.with_extension("chapters.json");
+            marker::write_chapters_json(&sidecar, &open.markers, self.format.sample_rate)?;
+        }
+        Ok(Some(TakeInfo {
+            take: open.take,
+            frames: open.wav.frames_written(),
+            path: open.path,
+            markers: open.markers,
+            loudness_log,
+        }))
+    }
+
+    /// Pops samples up to `limit` (or everything available) into the open take.
+    fn drain_until(&mut self, limit: Option<u64>) -> Result<()> {
+        loop {
+            let wanted = limit.map_or(self.scratch.len(), |limit| {
+                usize::try_from(limit.saturating_sub(self.popped))
+                    .unwrap_or(usize::MAX)
+                    .min(self.scratch.len())
+            });
+            if wanted == 0 {
+                return Ok(());
+            }
+            let count = self.samples.pop_slice(&mut self.scratch[..wanted]);
+            if count == 0 {
+                return Ok(());
+            }
+            self.popped += count as u64;
+            if let Some(open) = &mut self.current {
+                open.wav.write_samples(&self.scratch[..count])?;
+                if let Some((_, logger)) = &mut open.loudness {
+                    logger.process(&self.scratch[..count])?;
+                }
+            }
+        }
+    }
+}
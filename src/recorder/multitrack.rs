@@ -0,0 +1,691 @@
+//! Multi-track recording with punch-in/out
+//!
+//! [`MultitrackRecorder`] runs on the audio thread next to the transport.
+//! Each track has its own input, arm state and ring buffer. Armed tracks
+//! capture while the transport rolls, or only inside the punch range when
+//! one is set, and every pass starts a new take: punching in again, or
+//! looping back to the start of the loop, leaves the earlier takes in
+//! place.
+//!
+//! [`MultitrackWriter`] drains the rings into one WAV file per track and
+//! take. Service it from a thread of your own, or hand it its own thread
+//! with [`MultitrackWriter::spawn`]. Finished takes are collected per track
+//! in [`TakeLane`]s, where the newest take covering a position is the one
+//! that plays.
+//!
+//! With [`MultitrackSettings::with_broadcast`] takes are written as
+//! Broadcast Wave files named after their track, with the take's position
+//! on the timeline as the time reference.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::buffer::{RingBuffer, RingBufferReader, RingBufferWriter};
+use crate::channel::{
+    ControlReceiver, ControlSender, RealtimeReceiver, RealtimeSender, control_channel,
+    feedback_channel,
+};
+use crate::engine::transport::{Transport, TransportSpan};
+use crate::error::{AudioEngineError, Result};
+use crate::io::bwf::BroadcastInfo;
+use crate::io::wav::WavWriter;
+use crate::markers::RealtimeSafe;
+use crate::types::{AudioFormat, BitDepth, ChannelCount, Sample, SampleRate, TimeRange, Timestamp};
+
+/// Number of take start/stop events that can be in flight
+const EVENT_CAPACITY: usize = 256;
+/// How long the writer thread waits for events between services
+const SERVICE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// One recordable track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackInput {
+    pub name: String,
+    pub channels: ChannelCount,
+}
+
+/// Multi-track recorder configuration.
+#[derive(Debug, Clone)]
+pub struct MultitrackSettings {
+    /// Directory takes are written to
+    pub directory: PathBuf,
+    /// File name prefix, followed by the track and take numbers
+    pub prefix: String,
+    pub sample_rate: SampleRate,
+    pub bit_depth: BitDepth,
+    pub tracks: Vec<TrackInput>,
+    /// Ring buffer length per track between the audio thread and the writer
+    pub buffer_ms: u32,
+    /// Broadcast Wave metadata written into each take
+    pub broadcast: Option<BroadcastInfo>,
+}
+
+impl MultitrackSettings {
+    #[must_use]
+    pub fn new(directory: impl Into<PathBuf>, sample_rate: SampleRate) -> Self {
+        Self {
+            directory: directory.into(),
+            prefix: "track".to_string(),
+            sample_rate,
+            bit_depth: BitDepth::default(),
+            tracks: Vec::new(),
+            buffer_ms: 2000,
+            broadcast: None,
+        }
+    }
+
+    /// Adds a track with its own input of `channels`
+    #[must_use]
+    pub fn with_track(mut self, name: impl Into<String>, channels: ChannelCount) -> Self {
+        self.tracks.push(TrackInput {
+            name: name.into(),
+            channels,
+        });
+        self
+    }
+
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    #[must_use]
+    pub const fn with_bit_depth(mut self, bit_depth: BitDepth) -> Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_buffer_ms(mut self, buffer_ms: u32) -> Self {
+        self.buffer_ms = buffer_ms;
+        self
+    }
+
+    /// Writes takes as Broadcast Wave files. Each take's `iXML` names its
+    /// channels after the track, and its time reference is the take's
+    /// position on the timeline, offset by `info`'s time reference if set.
+    #[must_use]
+    pub fn with_broadcast(mut self, info: BroadcastInfo) -> Self {
+        self.broadcast = Some(info);
+        self
+    }
+}
+
+/// Commands for a [`MultitrackRecorder`], sent from the control thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultitrackCommand {
+    Arm(usize),
+    /// Disarms a track, ending any take in progress on it
+    Disarm(usize),
+    DisarmAll,
+}
+
+impl RealtimeSafe for MultitrackCommand {}
+
+/// Take boundaries sent from the audio thread to the writer.
+///
+/// Sample indices count every sample pushed into the track's ring since
+/// creation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TakeEvent {
+    Started {
+        track: usize,
+        take: u32,
+        sample_index: u64,
+        position: Timestamp,
+    },
+    Stopped {
+        track: usize,
+        sample_index: u64,
+    },
+}
+
+impl RealtimeSafe for TakeEvent {}
+
+#[derive(Debug)]
+struct RecordTrack {
+    channels: usize,
+    samples: RingBufferWriter<Sample>,
+    armed: bool,
+    recording: bool,
+    pushed: u64,
+}
+
+/// Audio thread side of a multi-track recording.
+#[derive(Debug)]
+pub struct MultitrackRecorder {
+    tracks: Vec<RecordTrack>,
+    events: RealtimeSender<TakeEvent>,
+    commands: Option<RealtimeReceiver<MultitrackCommand>>,
+    take: u32,
+    dropped_frames: u64,
+}
+
+impl MultitrackRecorder {
+    /// Creates the recorder and the writer that drains it.
+    #[must_use]
+    pub fn new(settings: MultitrackSettings) -> (Self, MultitrackWriter) {
+        let (events_tx, events_rx) = feedback_channel(EVENT_CAPACITY);
+        let frames = settings
+            .sample_rate
+            .samples_for_milliseconds(settings.buffer_ms) as usize;
+        let mut tracks = Vec::with_capacity(settings.tracks.len());
+        let mut writers = Vec::with_capacity(settings.tracks.len());
+        for (index, input) in settings.tracks.into_iter().enumerate() {
+            let channels = input.channels.count_usize();
+            let broadcast = settings.broadcast.clone().map(|info| {
+                info.with_track_names(std::iter::repeat_n(input.name.clone(), channels))
+            });
+            let (samples_tx, samples_rx) = RingBuffer::new((frames * channels).max(channels));
+            tracks.push(RecordTrack {
+                channels,
+                samples: samples_tx,
+                armed: false,
+                recording: false,
+                pushed: 0,
+            });
+            writers.push(WriterTrack {
+                index,
+                name: input.name,
+                broadcast,
+                format: AudioFormat {
+                    sample_rate: settings.sample_rate,
+                    channels: input.channels,
+                    bit_depth: settings.bit_depth,
+                },
+                samples: samples_rx,
+                pending: VecDeque::with_capacity(EVENT_CAPACITY),
+                current: None,
+                popped: 0,
+                scratch: vec![Sample::SILENCE; 4096 * channels],
+            });
+        }
+
+        let recorder = Self {
+            tracks,
+            events: events_tx,
+            commands: None,
+            take: 0,
+            dropped_frames: 0,
+        };
+        let writer = MultitrackWriter {
+            directory: settings.directory,
+            prefix: settings.prefix,
+            events: events_rx,
+            tracks: writers,
+        };
+        (recorder, writer)
+    }
+
+    #[must_use]
+    pub const fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    #[must_use]
+    pub fn is_armed(&self, track: usize) -> bool {
+        self.tracks.get(track).is_some_and(|track| track.armed)
+    }
+
+    /// Returns true while a take is being captured on `track`.
+    #[must_use]
+    pub fn is_recording(&self, track: usize) -> bool {
+        self.tracks.get(track).is_some_and(|track| track.recording)
+    }
+
+    /// Frames lost because the writer fell behind, over all tracks
+    #[must_use]
+    pub const fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    /// Creates the control side of the recorder's command channel.
+    ///
+    /// Commands sent through it are applied at the start of each block.
+    pub fn command_sender(&mut self, capacity: usize) -> ControlSender<MultitrackCommand> {
+        let (tx, rx) = control_channel(capacity);
+        self.commands = Some(rx);
+        tx
+    }
+
+    /// Applies a command immediately.
+    pub fn apply(&mut self, command: MultitrackCommand) {
+        match command {
+            MultitrackCommand::Arm(track) => self.arm(track),
+            MultitrackCommand::Disarm(track) => self.disarm(track),
+            MultitrackCommand::DisarmAll => {
+                for track in 0..self.tracks.len() {
+                    self.disarm(track);
+                }
+            }
+        }
+    }
+
+    /// Arms a track. It starts capturing with the next rolling block
+    /// inside the punch range.
+    pub fn arm(&mut self, track: usize) {
+        if let Some(track) = self.tracks.get_mut(track) {
+            track.armed = true;
+        }
+    }
+
+    /// Disarms a track, ending any take in progress on it.
+    pub fn disarm(&mut self, track: usize) {
+        if track < self.tracks.len() {
+            self.tracks[track].armed = false;
+            self.stop_take(track);
+        }
+    }
+
+    /// Records one block while advancing `transport` over it.
+    ///
+    /// `inputs` holds one interleaved slice per track, in the track's own
+    /// channel count and all the same number of frames.
+    pub fn process(&mut self, transport: &mut Transport, inputs: &[&[Sample]]) {
+        let Some(frames) = self
+            .tracks
+            .iter()
+            .zip(inputs)
+            .map(|(track, input)| input.len() / track.channels)
+            .min()
+        else {
+            return;
+        };
+        let mut done = 0;
+        while done < frames {
+            let span = transport.advance(frames - done);
+            self.record_span(span, transport.punch_range(), inputs, done);
+            done += span.frames;
+        }
+    }
+
+    /// Records one span returned by [`Transport::advance`], for callers
+    /// that advance the transport themselves, e.g. to play an
+    /// arrangement in the same callback.
+    ///
+    /// The span's frames are read from each input starting at frame
+    /// `offset`. Without a punch range, armed tracks capture whenever the
+    /// transport rolls.
+    pub fn record_span(
+        &mut self,
+        span: TransportSpan,
+        punch: Option<TimeRange>,
+        inputs: &[&[Sample]],
+        offset: usize,
+    ) {
+        while let Some(command) = self.commands.as_ref().and_then(RealtimeReceiver::try_recv) {
+            self.apply(command);
+        }
+
+        // Spans never cross a punch edge, so the first frame decides
+        let capturing = span.rolling && punch.is_none_or(|range| range.contains(span.start));
+        for (index, input) in inputs.iter().enumerate().take(self.tracks.len()) {
+            let track = &self.tracks[index];
+            if !(capturing && track.armed) {
+                self.stop_take(index);
+                continue;
+            }
+            if !track.recording {
+                self.start_take(index, span.start);
+            }
+            let channels = self.tracks[index].channels;
+            let from = (offset * channels).min(input.len());
+            let to = ((offset + span.frames) * channels).min(input.len());
+            self.push(index, &input[from..to]);
+            if span.wrapped {
+                // Back to the loop start: the next pass is a new take
+                self.stop_take(index);
+            }
+        }
+    }
+
+    fn start_take(&mut self, index: usize, position: Timestamp) {
+        self.take += 1;
+        let track = &mut self.tracks[index];
+        track.recording = true;
+        let _ = self.events.try_send(TakeEvent::Started {
+            track: index,
+            take: self.take,
+            sample_index: track.pushed,
+            position,
+        });
+    }
+
+    fn stop_take(&mut self, index: usize) {
+        let track = &mut self.tracks[index];
+        if !track.recording {
+            return;
+        }
+        track.recording = false;
+        let _ = self.events.try_send(TakeEvent::Stopped {
+            track: index,
+            sample_index: track.pushed,
+        });
+    }
+
+    fn push(&mut self, index: usize, samples: &[Sample]) {
+        let track = &mut self.tracks[index];
+        // Only whole blocks go in so the file never loses channel alignment
+        if track.samples.slots() < samples.len() {
+            self.dropped_frames += (samples.len() / track.channels) as u64;
+            return;
+        }
+        track.pushed += track.samples.push_slice(samples) as u64;
+    }
+}
+
+/// A finished take on one track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedTake {
+    pub track: usize,
+    pub track_name: String,
+    /// Take number, counted across all tracks
+    pub take: u32,
+    pub path: PathBuf,
+    /// Timeline position of the first frame
+    pub position: Timestamp,
+    pub frames: u64,
+}
+
+impl RecordedTake {
+    /// The part of the timeline the take covers
+    #[must_use]
+    pub fn range(&self) -> TimeRange {
+        TimeRange::new(
+            self.position,
+            Timestamp::from_samples(self.position.as_samples() + self.frames),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct OpenTake {
+    take: u32,
+    path: PathBuf,
+    position: Timestamp,
+    wav: WavWriter<BufWriter<File>>,
+}
+
+#[derive(Debug)]
+struct WriterTrack {
+    index: usize,
+    name: String,
+    broadcast: Option<BroadcastInfo>,
+    format: AudioFormat,
+    samples: RingBufferReader<Sample>,
+    pending: VecDeque<TakeEvent>,
+    current: Option<OpenTake>,
+    popped: u64,
+    scratch: Vec<Sample>,
+}
+
+impl WriterTrack {
+    /// Handles the track's pending events and writes out what is ready.
+    fn service(&mut self, directory: &Path, prefix: &str) -> Result<Vec<RecordedTake>> {
+        let mut finished = Vec::new();
+        while let Some(&event) = self.pending.front() {
+            match event {
+                TakeEvent::Started {
+                    take,
+                    sample_index,
+                    position,
+                    ..
+                } => {
+                    // Anything before the start belongs to no take
+                    self.drain_until(Some(sample_index))?;
+                    finished.extend(self.close_take()?);
+                    std::fs::create_dir_all(directory)?;
+                    let path =
+                        directory.join(format!("{prefix}-{:02}-{take:04}.wav", self.index + 1));
+                    let wav = match &self.broadcast {
+                        Some(info) => {
+                            let offset = info.time_reference.unwrap_or(Timestamp::ZERO);
+                            let info = info.clone().with_time_reference(Timestamp::from_samples(
+                                offset.as_samples() + position.as_samples(),
+                            ));
+                            WavWriter::create_broadcast(&path, self.format, &info)?
+                        }
+                        None => WavWriter::create(&path, self.format)?,
+                    };
+                    self.current = Some(OpenTake {
+                        take,
+                        path,
+                        position,
+                        wav,
+                    });
+                }
+                TakeEvent::Stopped { sample_index, .. } => {
+                    self.drain_until(Some(sample_index))?;
+                    if self.popped < sample_index {
+                        // The recorder hasn't pushed everything yet
+                        break;
+                    }
+                    finished.extend(self.close_take()?);
+                }
+            }
+            self.pending.pop_front();
+        }
+        if self.pending.is_empty() {
+            self.drain_until(None)?;
+        }
+        Ok(finished)
+    }
+
+    fn close_take(&mut self) -> Result<Option<RecordedTake>> {
+        let Some(mut open) = self.current.take() else {
+            return Ok(None);
+        };
+        open.wav.finalize()?;
+        Ok(Some(RecordedTake {
+            track: self.index,
+            track_name: self.name.clone(),
+            take: open.take,
+            frames: open.wav.frames_written(),
+            path: open.path,
+            position: open.position,
+        }))
+    }
+
+    /// Pops samples up to `limit` (or everything available) into the open
+    /// take.
+    fn drain_until(&mut self, limit: Option<u64>) -> Result<()> {
+        loop {
+            let wanted = limit.map_or(self.scratch.len(), |limit| {
+                usize::try_from(limit.saturating_sub(self.popped))
+                    .unwrap_or(usize::MAX)
+                    .min(self.scratch.len())
+            });
+            if wanted == 0 {
+                return Ok(());
+            }
+            let count = self.samples.pop_slice(&mut self.scratch[..wanted]);
+            if count == 0 {
+                return Ok(());
+            }
+            self.popped += count as u64;
+            if let Some(open) = &mut self.current {
+                open.wav.write_samples(&self.scratch[..count])?;
+            }
+        }
+    }
+}
+
+/// Writer side of a multi-track recording.
+#[derive(Debug)]
+pub struct MultitrackWriter {
+    directory: PathBuf,
+    prefix: String,
+    events: ControlReceiver<TakeEvent>,
+    tracks: Vec<WriterTrack>,
+}
+
+impl MultitrackWriter {
+    /// Moves everything the recorder produced so far to disk.
+    ///
+    /// Call this regularly from a non-realtime thread. Returns the takes
+    /// that were completed during this call.
+    ///
+    /// # Errors
+    /// Returns an error if a file can't be created or written.
+    pub fn service(&mut self) -> Result<Vec<RecordedTake>> {
+        while let Some(event) = self.events.try_recv() {
+            let (TakeEvent::Started { track, .. } | TakeEvent::Stopped { track, .. }) = event;
+            if let Some(track) = self.tracks.get_mut(track) {
+                track.pending.push_back(event);
+            }
+        }
+        let mut finished = Vec::new();
+        for track in &mut self.tracks {
+            finished.extend(track.service(&self.directory, &self.prefix)?);
+        }
+        Ok(finished)
+    }
+
+    /// Writes out what is left and closes every take.
+    ///
+    /// # Errors
+    /// Returns an error if the remaining audio can't be written.
+    pub fn finish(&mut self) -> Result<Vec<RecordedTake>> {
+        let mut finished = self.service()?;
+        for track in &mut self.tracks {
+            finished.extend(track.close_take()?);
+        }
+        Ok(finished)
+    }
+
+    /// Services the writer on a thread of its own until the recorder is
+    /// dropped or [`WriterThread::stop`] is called.
+    ///
+    /// # Errors
+    /// Returns an error if the thread can't be started.
+    pub fn spawn(mut self) -> Result<WriterThread> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (finished_tx, finished_rx) = flume::unbounded();
+        let stopping = Arc::clone(&stop);
+        let thread = thread::Builder::new()
+            .name("multitrack-writer".to_string())
+            .spawn(move || {
+                while !stopping.load(Ordering::Acquire) && !self.events.is_disconnected() {
+                    thread::sleep(SERVICE_INTERVAL);
+                    for take in self.service()? {
+                        let _ = finished_tx.send(take);
+                    }
+                }
+                for take in self.finish()? {
+                    let _ = finished_tx.send(take);
+                }
+                Ok(())
+            })
+            .map_err(|e| AudioEngineError::configuration(format!("can't start writer: {e}")))?;
+        Ok(WriterThread {
+            stop,
+            finished: finished_rx,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A [`MultitrackWriter`] running on its own thread.
+#[derive(Debug)]
+pub struct WriterThread {
+    stop: Arc<AtomicBool>,
+    finished: flume::Receiver<RecordedTake>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl WriterThread {
+    /// Takes finished since the last call
+    #[must_use]
+    pub fn finished_takes(&self) -> Vec<RecordedTake> {
+        self.finished.try_iter().collect()
+    }
+
+    /// Writes out what is left, stops the thread and returns the takes
+    /// not yet collected.
+    ///
+    /// # Errors
+    /// Returns an error if writing failed at any point.
+    pub fn stop(mut self) -> Result<Vec<RecordedTake>> {
+        self.join()?;
+        Ok(self.finished_takes())
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Release);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(AudioEngineError::configuration("writer thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for WriterThread {
+    fn drop(&mut self) {
+        if let Err(e) = self.join() {
+            log::error!("Multitrack writer failed: {e}");
+        }
+    }
+}
+
+/// The takes recorded on one track, newest on top.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TakeLane {
+    /// Bottom to top
+    takes: Vec<RecordedTake>,
+}
+
+impl TakeLane {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { takes: Vec::new() }
+    }
+
+    /// Puts a take on top of the others.
+    pub fn add(&mut self, take: RecordedTake) {
+        self.takes.push(take);
+    }
+
+    /// Takes from bottom to top
+    #[must_use]
+    pub fn takes(&self) -> &[RecordedTake] {
+        &self.takes
+    }
+
+    /// Removes and returns the take numbered `take`
+    pub fn remove(&mut self, take: u32) -> Option<RecordedTake> {
+        let index = self.takes.iter().position(|t| t.take == take)?;
+        Some(self.takes.remove(index))
+    }
+
+    /// Moves a take to the top, so it plays over any it overlaps. Returns
+    /// false if there is no such take.
+    pub fn promote(&mut self, take: u32) -> bool {
+        self.remove(take)
+            .map(|take| self.takes.push(take))
+            .is_some()
+    }
+
+    /// The take that plays at `position`: the topmost one covering it
+    #[must_use]
+    pub fn active_at(&self, position: Timestamp) -> Option<&RecordedTake> {
+        self.takes
+            .iter()
+            .rev()
+            .find(|take| take.range().contains(position))
+    }
+
+    /// Takes that overlap `range`, bottom to top
+    pub fn overlapping(&self, range: TimeRange) -> impl Iterator<Item = &RecordedTake> {
+        self.takes.iter().filter(move |take| {
+            take.range().start() < range.end() && range.start() < take.range().end()
+        })
+    }
+}
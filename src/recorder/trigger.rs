@@ -0,0 +1,120 @@
+//! Level detection for sound-activated recording
+
+use crate::types::{AudioFormat, Decibels, Sample};
+
+/// Settings for [`RecordMode::LevelTriggered`](super::RecordMode::LevelTriggered).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriggerSettings {
+    /// Peak level that starts a take
+    pub threshold: Decibels,
+    /// How long the input must stay below the threshold before the take ends
+    pub hang_ms: u32,
+    /// Audio from before the trigger that is kept at the start of each take
+    pub pre_roll_ms: u32,
+}
+
+impl TriggerSettings {
+    #[must_use]
+    pub const fn new(threshold: Decibels) -> Self {
+        Self {
+            threshold,
+            hang_ms: 2000,
+            pre_roll_ms: 500,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_hang_ms(mut self, hang_ms: u32) -> Self {
+        self.hang_ms = hang_ms;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_pre_roll_ms(mut self, pre_roll_ms: u32) -> Self {
+        self.pre_roll_ms = pre_roll_ms;
+        self
+    }
+}
+
+impl Default for TriggerSettings {
+    fn default() -> Self {
+        Self::new(Decibels::new(-40.0))
+    }
+}
+
+/// Threshold detector with hang time and a circular pre-roll buffer.
+#[derive(Debug)]
+pub(crate) struct LevelTrigger {
+    threshold: f32,
+    hang_frames: u32,
+    frames_below: u32,
+    pre_roll: Vec<Sample>,
+    write_pos: usize,
+    filled: usize,
+}
+
+impl LevelTrigger {
+    pub(crate) fn new(settings: TriggerSettings, format: AudioFormat) -> Self {
+        let channels = format.channels.count_usize();
+        let pre_roll_frames = format
+            .sample_rate
+            .samples_for_milliseconds(settings.pre_roll_ms) as usize;
+        Self {
+            threshold: settings.threshold.to_linear(),
+            hang_frames: format
+                .sample_rate
+                .samples_for_milliseconds(settings.hang_ms),
+            frames_below: 0,
+            pre_roll: vec![Sample::SILENCE; pre_roll_frames * channels],
+            write_pos: 0,
+            filled: 0,
+        }
+    }
+
+    pub(crate) const fn reset(&mut self) {
+        self.frames_below = 0;
+        self.clear_pre_roll();
+    }
+
+    pub(crate) fn is_over_threshold(&self, frame: &[Sample]) -> bool {
+        frame.iter().any(|s| s.value().abs() >= self.threshold)
+    }
+
+    /// Tracks the hang counter; returns true once the input has been below
+    /// the threshold for the whole hang time.
+    pub(crate) const fn hang_elapsed(&mut self, over: bool) -> bool {
+        if over {
+            self.frames_below = 0;
+            false
+        } else {
+            self.frames_below = self.frames_below.saturating_add(1);
+            self.frames_below >= self.hang_frames
+        }
+    }
+
+    pub(crate) fn store_pre_roll(&mut self, frame: &[Sample]) {
+        if self.pre_roll.is_empty() {
+            return;
+        }
+        for &sample in frame {
+            self.pre_roll[self.write_pos] = sample;
+            self.write_pos = (self.write_pos + 1) % self.pre_roll.len();
+        }
+        self.filled = (self.filled + frame.len()).min(self.pre_roll.len());
+    }
+
+    /// Stored pre-roll in chronological order, as two slices.
+    pub(crate) fn pre_roll(&self) -> (&[Sample], &[Sample]) {
+        if self.filled < self.pre_roll.len() {
+            (&self.pre_roll[..self.filled], &[])
+        } else {
+            let (newer, older) = self.pre_roll.split_at(self.write_pos);
+            (older, newer)
+        }
+    }
+
+    pub(crate) const fn clear_pre_roll(&mut self) {
+        self.write_pos = 0;
+        self.filled = 0;
+    }
+}
@@ -0,0 +1,36 @@
+//! Line-based control server
+//!
+//! [`RpcServer`] lets other processes drive the engine over a local TCP
+//! or Unix socket without linking against it. Each line sent is a JSON
+//! request and gets one JSON response line back:
+//!
+//! ```text
+//! > {"id":1,"cmd":"set_gain","db":-6.0}
+//! < {"id":1,"ok":true}
+//! > {"id":2,"cmd":"status"}
+//! < {"id":2,"ok":true,"status":{"state":"running","input_db":-80.0,"output_db":-12.5,"position_seconds":3.2}}
+//! > {"cmd":"set_tempo","bpm":"fast"}
+//! < {"ok":false,"error":"Invalid request: ..."}
+//! ```
+//!
+//! Effects and parameters are given by id or by a path from the
+//! [`NameTable`] handed to [`RpcServer::set_names`], e.g.
+//! `{"cmd":"set_param","param":"master/eq/frequency","value":1200.0}`.
+//!
+//! The commands are listed in [`RpcCommand`]. Those that change the
+//! engine are queued as [`EngineCommand`]s for the control thread to send
+//! on, like those from MIDI and OSC; a response to them means the command
+//! was queued. After `subscribe`, a client also gets event lines such as
+//! `{"event":"levels","input_db":-80.0,"output_db":-12.5}`, from the
+//! feedback the host passes to [`RpcServer::broadcast`].
+//!
+//! [`EngineCommand`]: crate::channel::EngineCommand
+//! [`NameTable`]: crate::dsp::names::NameTable
+
+pub mod protocol;
+pub mod server;
+
+pub use protocol::{
+    DeviceEntry, DeviceList, EngineStatus, RpcCommand, RpcEvent, RpcRequest, RpcResponse, Target,
+};
+pub use server::{Endpoint, RpcServer};
@@ -0,0 +1,369 @@
+//! Messages of the control protocol
+//!
+//! Every message is one JSON object on one line. Requests name their
+//! command in `cmd`; responses carry `ok`, plus `error` or the data asked
+//! for; events pushed to subscribers name themselves in `event`.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::device::AudioDevice;
+use crate::channel::{EngineCommand, EngineFeedback, EngineState};
+use crate::dsp::names::NameTable;
+use crate::engine::transport::CountIn;
+use crate::types::{Decibels, Gain, Pan, SampleRate, Tempo, Timestamp};
+
+/// A request line. `id`, if given, is echoed in the response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub command: RpcCommand,
+}
+
+/// What a request asks for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum RpcCommand {
+    Start,
+    Stop,
+    Pause,
+    Resume,
+    /// Master gain in dB
+    SetGain {
+        db: f32,
+    },
+    /// Master pan from -1.0 to 1.0
+    SetPan {
+        pan: f32,
+    },
+    /// An effect parameter, by `effect` and `param` id or by a `param`
+    /// path alone
+    SetParam {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        effect: Option<u32>,
+        param: Target,
+        value: f32,
+    },
+    /// Switches an effect, by id or path
+    SetEnabled {
+        effect: Target,
+        enabled: bool,
+    },
+    /// Crossfader position from 0.0 to 1.0
+    SetCrossfade {
+        position: f32,
+    },
+    SetTempo {
+        bpm: f64,
+    },
+    /// Move the playhead to a time in seconds
+    Seek {
+        seconds: f64,
+    },
+    /// Move the playhead to a position in quarter notes
+    SeekBeats {
+        beats: f64,
+    },
+    /// Start a recording pass after a count-in, from a pre-roll before
+    /// the punch-in point
+    Record {
+        #[serde(default)]
+        count_in_bars: u32,
+        #[serde(default)]
+        pre_roll_bars: u32,
+    },
+    /// Start or stop recording automation
+    RecordAutomation {
+        enabled: bool,
+    },
+    /// List the audio devices
+    Devices,
+    /// Report the engine's last known state, levels and position
+    Status,
+    /// Receive events
+    Subscribe,
+    Unsubscribe,
+    Ping,
+}
+
+/// An effect or parameter, by numeric id or by a path in a
+/// [`NameTable`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Target {
+    Id(u32),
+    Path(String),
+}
+
+impl RpcCommand {
+    /// The engine command this sends, if it sends one. `sample_rate`
+    /// places [`Seek`](Self::Seek) and `names` resolves paths; `None` also
+    /// means an unknown path or a parameter id without an effect.
+    #[must_use]
+    pub fn engine_command(
+        &self,
+        sample_rate: SampleRate,
+        names: &NameTable,
+    ) -> Option<EngineCommand> {
+        Some(match *self {
+            Self::Start => EngineCommand::Start,
+            Self::Stop => EngineCommand::Stop,
+            Self::Pause => EngineCommand::Pause,
+            Self::Resume => EngineCommand::Resume,
+            Self::SetGain { db } => EngineCommand::SetGain(Gain::from_db(db)),
+            Self::SetPan { pan } => EngineCommand::SetPan(Pan::new(pan)),
+            Self::SetParam {
+                effect,
+                ref param,
+                value,
+            } => {
+                let (effect_id, param_id) = match param {
+                    Target::Id(param) => (effect?, *param),
+                    Target::Path(path) => {
+                        let address = names.param(path)?;
+                        (address.effect_id.value(), address.param_id.value())
+                    }
+                };
+                EngineCommand::SetEffectParam {
+                    effect_id,
+                    param_id,
+                    value,
+                }
+            }
+            Self::SetEnabled {
+                ref effect,
+                enabled,
+            } => EngineCommand::SetEffectEnabled {
+                effect_id: match effect {
+                    Target::Id(effect) => *effect,
+                    Target::Path(path) => names.effect(path)?.value(),
+                },
+                enabled,
+            },
+            Self::SetCrossfade { position } => {
+                EngineCommand::SetCrossfade(position.clamp(0.0, 1.0))
+            }
+            Self::SetTempo { bpm } => EngineCommand::SetTempo(Tempo::new(bpm.max(1.0))),
+            Self::Seek { seconds } => EngineCommand::Seek(Timestamp::from_duration(
+                Duration::try_from_secs_f64(seconds.max(0.0)).ok()?,
+                sample_rate,
+            )),
+            Self::SeekBeats { beats } => EngineCommand::SeekBeats(beats.max(0.0)),
+            Self::Record {
+                count_in_bars,
+                pre_roll_bars,
+            } => EngineCommand::Record(CountIn::new(count_in_bars, pre_roll_bars)),
+            Self::RecordAutomation { enabled } => EngineCommand::RecordAutomation(enabled),
+            Self::Devices | Self::Status | Self::Subscribe | Self::Unsubscribe | Self::Ping => {
+                return None;
+            }
+        })
+    }
+}
+
+/// The reply to a request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub devices: Option<DeviceList>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<EngineStatus>,
+}
+
+impl RpcResponse {
+    #[must_use]
+    pub const fn ok(id: Option<u64>) -> Self {
+        Self {
+            id,
+            ok: true,
+            error: None,
+            devices: None,
+            status: None,
+        }
+    }
+
+    #[must_use]
+    pub fn error(id: Option<u64>, message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error: Some(message.into()),
+            ..Self::ok(id)
+        }
+    }
+}
+
+/// One audio device in a [`DeviceList`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceEntry {
+    pub name: String,
+    pub max_channels: u32,
+    pub sample_rates: Vec<u32>,
+    pub default: bool,
+}
+
+impl From<&AudioDevice> for DeviceEntry {
+    fn from(device: &AudioDevice) -> Self {
+        let info = device.info();
+        Self {
+            name: info.name.clone(),
+            max_channels: info.max_channels,
+            sample_rates: info
+                .supported_sample_rates
+                .iter()
+                .map(|rate| rate.as_hz())
+                .collect(),
+            default: info.is_default,
+        }
+    }
+}
+
+/// Reply to [`RpcCommand::Devices`].
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DeviceList {
+    pub inputs: Vec<DeviceEntry>,
+    pub outputs: Vec<DeviceEntry>,
+}
+
+/// Reply to [`RpcCommand::Status`], gathered from the feedback the host
+/// passes to the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EngineStatus {
+    pub state: String,
+    pub input_db: f32,
+    pub output_db: f32,
+    pub position_seconds: f64,
+    /// SMPTE timecode of the position, when the transport has a frame rate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timecode: Option<String>,
+}
+
+impl Default for EngineStatus {
+    fn default() -> Self {
+        Self {
+            state: EngineState::Stopped.name().to_string(),
+            input_db: Decibels::SILENCE.value(),
+            output_db: Decibels::SILENCE.value(),
+            position_seconds: 0.0,
+            timecode: None,
+        }
+    }
+}
+
+impl EngineStatus {
+    /// Updates the status with `feedback`.
+    pub fn track(&mut self, feedback: &EngineFeedback) {
+        match feedback {
+            EngineFeedback::Levels {
+                input_db,
+                output_db,
+            } => {
+                self.input_db = input_db.value();
+                self.output_db = output_db.value();
+            }
+            EngineFeedback::Position(position) => {
+                self.position_seconds = position.total_seconds_f64();
+                self.timecode = position.timecode().map(|timecode| timecode.to_string());
+            }
+            EngineFeedback::StateChanged(state) => self.state = state.name().to_string(),
+            _ => {}
+        }
+    }
+}
+
+/// A line pushed to subscribers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RpcEvent {
+    Levels {
+        input_db: f32,
+        output_db: f32,
+    },
+    Position {
+        seconds: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timecode: Option<String>,
+    },
+    State {
+        state: String,
+    },
+    Underrun,
+    Error {
+        message: String,
+    },
+    Recovery {
+        attempt: u32,
+        device: String,
+        reason: String,
+        recovered: bool,
+    },
+    EffectFailed {
+        effect_id: u32,
+    },
+    NetworkLatency {
+        latency_ms: f64,
+        jitter_ms: f64,
+    },
+}
+
+impl RpcEvent {
+    /// The event for `feedback`, if it has one
+    #[must_use]
+    pub fn from_feedback(feedback: &EngineFeedback) -> Option<Self> {
+        Some(match feedback {
+            EngineFeedback::Levels {
+                input_db,
+                output_db,
+            } => Self::Levels {
+                input_db: input_db.value(),
+                output_db: output_db.value(),
+            },
+            EngineFeedback::Position(position) => Self::Position {
+                seconds: position.total_seconds_f64(),
+                timecode: position.timecode().map(|timecode| timecode.to_string()),
+            },
+            EngineFeedback::StateChanged(state) => Self::State {
+                state: state.name().to_string(),
+            },
+            EngineFeedback::Underrun => Self::Underrun,
+            EngineFeedback::Error(message) => Self::Error {
+                message: message.clone(),
+            },
+            EngineFeedback::StreamRecovery {
+                attempt,
+                device,
+                reason,
+                recovered,
+            } => Self::Recovery {
+                attempt: *attempt,
+                device: device.clone(),
+                reason: reason.clone(),
+                recovered: *recovered,
+            },
+            EngineFeedback::EffectFailed { effect_id } => Self::EffectFailed {
+                effect_id: *effect_id,
+            },
+            EngineFeedback::NetworkLatency { latency, jitter } => Self::NetworkLatency {
+                latency_ms: latency.as_secs_f64() * 1000.0,
+                jitter_ms: jitter.as_secs_f64() * 1000.0,
+            },
+            EngineFeedback::AllocationViolation { .. }
+            | EngineFeedback::RenderProgress { .. }
+            | EngineFeedback::ParamRecorded { .. }
+            | EngineFeedback::StreamClosed { .. }
+            | EngineFeedback::StreamDetached { .. }
+            | EngineFeedback::MarkerReached { .. }
+            | EngineFeedback::BufferSizeChanged { .. }
+            | EngineFeedback::QualityChanged { .. }
+            | EngineFeedback::BitrateChanged { .. }
+            | EngineFeedback::ExposureWarning(_) => return None,
+        })
+    }
+}
@@ -0,0 +1,474 @@
+//! Socket server for the control protocol
+
+use std::fmt;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use flume::{Receiver, Sender};
+use parking_lot::Mutex;
+
+use crate::audio::device::AudioDeviceManager;
+use crate::channel::{EngineCommand, EngineFeedback};
+use crate::dsp::names::NameTable;
+use crate::engine::Engine;
+use crate::error::{AudioEngineError, Result};
+use crate::rpc::protocol::{
+    DeviceEntry, DeviceList, EngineStatus, RpcCommand, RpcEvent, RpcRequest, RpcResponse,
+};
+use crate::types::SampleRate;
+
+/// Commands that can be waiting for the control thread
+const COMMAND_CAPACITY: usize = 1024;
+/// Clients that can be connected at once
+const MAX_CLIENTS: usize = 16;
+/// Longest request line; clients sending longer ones are disconnected
+const MAX_LINE: usize = 64 * 1024;
+/// How often the server threads check whether to stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a write to a client may block before the client is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+type Reader = Box<dyn Read + Send>;
+type Writer = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Where an [`RpcServer`] listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "tcp://{address}"),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// The next connection, split into its reading and writing halves
+    fn accept(&self) -> std::io::Result<(Reader, Writer)> {
+        let (reader, writer): (Reader, Box<dyn Write + Send>) = match self {
+            Self::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                stream.set_nodelay(true)?;
+                stream.set_read_timeout(Some(POLL_INTERVAL))?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                (Box::new(stream.try_clone()?), Box::new(stream))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(POLL_INTERVAL))?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                (Box::new(stream.try_clone()?), Box::new(stream))
+            }
+        };
+        Ok((reader, Arc::new(Mutex::new(writer))))
+    }
+}
+
+struct Client {
+    id: u64,
+    writer: Writer,
+    subscribed: bool,
+}
+
+/// State shared by the server and its threads
+struct Shared {
+    sample_rate: SampleRate,
+    sender: Sender<EngineCommand>,
+    clients: Mutex<Vec<Client>>,
+    status: Mutex<EngineStatus>,
+    names: Mutex<NameTable>,
+    next_client: AtomicU64,
+    quit: AtomicBool,
+}
+
+/// A control server listening on a TCP or Unix socket.
+///
+/// Each client is read on a thread of its own; engine commands queue up
+/// until the control thread collects them, everything else is answered
+/// right away. The threads stop when this is dropped.
+pub struct RpcServer {
+    endpoint: Endpoint,
+    shared: Arc<Shared>,
+    commands: Receiver<EngineCommand>,
+    /// A command [`dispatch`](Self::dispatch) couldn't send yet
+    pending: Option<EngineCommand>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RpcServer {
+    /// Listens on TCP at `address`, e.g. `"127.0.0.1:7400"`.
+    /// `sample_rate` is the engine's, for placing `seek`.
+    ///
+    /// The protocol has no authentication; bind to a loopback address
+    /// unless the network is trusted.
+    ///
+    /// # Errors
+    /// Returns an error if the socket can't be bound or the thread
+    /// started.
+    pub fn bind_tcp(address: impl ToSocketAddrs, sample_rate: SampleRate) -> Result<Self> {
+        let listener =
+            TcpListener::bind(address).map_err(|e| AudioEngineError::NetworkConnection {
+                message: format!("Failed to bind control socket: {e}"),
+            })?;
+        let endpoint = Endpoint::Tcp(listener.local_addr()?);
+        listener.set_nonblocking(true)?;
+        Self::start(Listener::Tcp(listener), endpoint, sample_rate)
+    }
+
+    /// Listens like [`bind_tcp`](Self::bind_tcp), resolving `address` and
+    /// binding on another thread so an async caller isn't blocked.
+    ///
+    /// # Errors
+    /// Returns an error if the socket can't be bound or the thread
+    /// started.
+    #[cfg(feature = "async")]
+    pub async fn bind_tcp_async(
+        address: impl ToSocketAddrs + Send + 'static,
+        sample_rate: SampleRate,
+    ) -> Result<Self> {
+        crate::channel::unblock("rpc-bind", move || Self::bind_tcp(address, sample_rate)).await
+    }
+
+    /// Listens on a Unix socket at `path`, which is removed again when
+    /// the server is dropped. `sample_rate` is the engine's, for placing
+    /// `seek`.
+    ///
+    /// # Errors
+    /// Returns an error if the socket can't be bound, for instance
+    /// because `path` already exists, or the thread started.
+    #[cfg(unix)]
+    pub fn bind_unix(path: impl AsRef<Path>, sample_rate: SampleRate) -> Result<Self> {
+        let path = path.as_ref();
+        let listener =
+            UnixListener::bind(path).map_err(|e| AudioEngineError::NetworkConnection {
+                message: format!("Failed to bind control socket {}: {e}", path.display()),
+            })?;
+        listener.set_nonblocking(true)?;
+        Self::start(
+            Listener::Unix(listener),
+            Endpoint::Unix(path.to_path_buf()),
+            sample_rate,
+        )
+    }
+
+    fn start(listener: Listener, endpoint: Endpoint, sample_rate: SampleRate) -> Result<Self> {
+        let (sender, commands) = flume::bounded(COMMAND_CAPACITY);
+        let shared = Arc::new(Shared {
+            sample_rate,
+            sender,
+            clients: Mutex::new(Vec::new()),
+            status: Mutex::new(EngineStatus::default()),
+            names: Mutex::new(NameTable::new()),
+            next_client: AtomicU64::new(0),
+            quit: AtomicBool::new(false),
+        });
+        let thread = std::thread::Builder::new()
+            .name("rpc-server".to_string())
+            .spawn({
+                let shared = Arc::clone(&shared);
+                move || accept(&listener, &shared)
+            })
+            .map_err(|e| AudioEngineError::NetworkConnection {
+                message: format!("Failed to start control server thread: {e}"),
+            })?;
+        log::info!("Listening for control connections on {endpoint}");
+        Ok(Self {
+            endpoint,
+            shared,
+            commands,
+            pending: None,
+            thread: Some(thread),
+        })
+    }
+
+    /// Where the server listens
+    #[must_use]
+    pub const fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Clients connected
+    #[must_use]
+    pub fn clients(&self) -> usize {
+        self.shared.clients.lock().len()
+    }
+
+    /// Lets requests name effects and parameters by the paths in
+    /// `names`.
+    pub fn set_names(&self, names: NameTable) {
+        *self.shared.names.lock() = names;
+    }
+
+    /// The next command, oldest first
+    pub fn try_recv(&mut self) -> Option<EngineCommand> {
+        self.pending
+            .take()
+            .or_else(|| self.commands.try_recv().ok())
+    }
+
+    /// Sends every waiting command to `engine`, returning how many were
+    /// sent. They take effect at the start of the engine's next block.
+    ///
+    /// # Errors
+    /// Returns an error if the engine's command queue is full; the
+    /// remaining commands stay queued.
+    pub fn dispatch(&mut self, engine: &mut Engine) -> Result<usize> {
+        let mut sent = 0;
+        while let Some(command) = self.try_recv() {
+            if let Err(e) = engine.send(command.clone()) {
+                self.pending = Some(command);
+                return Err(e);
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Records `feedback` for `status` requests and sends it to every
+    /// subscribed client, returning how many it reached. Clients that
+    /// can't keep up are disconnected.
+    ///
+    /// Hosts pass on what they read from [`Engine::feedback`]; the
+    /// server doesn't read it itself, so the host still sees all of it.
+    #[must_use]
+    pub fn broadcast(&self, feedback: &EngineFeedback) -> usize {
+        self.shared.status.lock().track(feedback);
+        let Some(line) = RpcEvent::from_feedback(feedback).and_then(|event| encode(&event)) else {
+            return 0;
+        };
+        let mut reached = 0;
+        self.shared.clients.lock().retain(|client| {
+            if !client.subscribed {
+                return true;
+            }
+            let written = client.writer.lock().write_all(line.as_bytes());
+            match written {
+                Ok(()) => {
+                    reached += 1;
+                    true
+                }
+                Err(e) => {
+                    log::debug!("Dropping control client {}: {e}", client.id);
+                    false
+                }
+            }
+        });
+        reached
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.shared.quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        #[cfg(unix)]
+        if let Endpoint::Unix(path) = &self.endpoint {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl fmt::Debug for RpcServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcServer")
+            .field("endpoint", &self.endpoint)
+            .field("clients", &self.clients())
+            .field("commands", &self.commands.len())
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Runs on the server thread until `quit` is set, starting a thread for
+/// each client.
+fn accept(listener: &Listener, shared: &Arc<Shared>) {
+    let mut threads: Vec<JoinHandle<()>> = Vec::new();
+    while !shared.quit.load(Ordering::Relaxed) {
+        let (reader, writer) = match listener.accept() {
+            Ok(connection) => connection,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("Control connection failed: {e}");
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+        threads.retain(|thread| !thread.is_finished());
+        let id = shared.next_client.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut clients = shared.clients.lock();
+            if clients.len() >= MAX_CLIENTS {
+                log::warn!("Too many control clients, refusing client {id}");
+                reply(&writer, &RpcResponse::error(None, "Too many clients"));
+                continue;
+            }
+            clients.push(Client {
+                id,
+                writer: Arc::clone(&writer),
+                subscribed: false,
+            });
+        }
+        log::info!("Control client {id} connected");
+        let spawned = std::thread::Builder::new()
+            .name(format!("rpc-client-{id}"))
+            .spawn({
+                let shared = Arc::clone(shared);
+                move || serve(id, reader, &writer, &shared)
+            });
+        match spawned {
+            Ok(thread) => threads.push(thread),
+            Err(e) => {
+                log::warn!("Failed to start thread for control client {id}: {e}");
+                shared.clients.lock().retain(|client| client.id != id);
+            }
+        }
+    }
+    for thread in threads {
+        let _ = thread.join();
+    }
+}
+
+/// Answers one client's requests until it disconnects or `quit` is set.
+fn serve(id: u64, reader: Reader, writer: &Writer, shared: &Shared) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    while !shared.quit.load(Ordering::Relaxed) {
+        let limit = (MAX_LINE + 1 - line.len()) as u64;
+        match reader.by_ref().take(limit).read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) if line.last() == Some(&b'\n') => {
+                if let Some(response) = handle(id, &line, shared) {
+                    reply(writer, &response);
+                }
+                line.clear();
+                continue;
+            }
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => {
+                log::debug!("Control client {id} read failed: {e}");
+                break;
+            }
+        }
+        if line.len() > MAX_LINE {
+            reply(writer, &RpcResponse::error(None, "Request too long"));
+            break;
+        }
+    }
+    shared.clients.lock().retain(|client| client.id != id);
+    log::info!("Control client {id} disconnected");
+}
+
+/// The response to a request line, or `None` for a blank line.
+fn handle(id: u64, line: &[u8], shared: &Shared) -> Option<RpcResponse> {
+    let line = line.trim_ascii();
+    if line.is_empty() {
+        return None;
+    }
+    let request: RpcRequest = match serde_json::from_slice(line) {
+        Ok(request) => request,
+        Err(e) => return Some(RpcResponse::error(None, format!("Invalid request: {e}"))),
+    };
+    let request_id = request.id;
+    Some(match request.command {
+        RpcCommand::Devices => match devices() {
+            Ok(devices) => RpcResponse {
+                devices: Some(devices),
+                ..RpcResponse::ok(request_id)
+            },
+            Err(e) => RpcResponse::error(request_id, e.to_string()),
+        },
+        RpcCommand::Status => RpcResponse {
+            status: Some(shared.status.lock().clone()),
+            ..RpcResponse::ok(request_id)
+        },
+        RpcCommand::Subscribe | RpcCommand::Unsubscribe => {
+            let subscribed = request.command == RpcCommand::Subscribe;
+            for client in shared.clients.lock().iter_mut() {
+                if client.id == id {
+                    client.subscribed = subscribed;
+                }
+            }
+            RpcResponse::ok(request_id)
+        }
+        RpcCommand::Ping => RpcResponse::ok(request_id),
+        ref command => {
+            let command = command.engine_command(shared.sample_rate, &shared.names.lock());
+            command.map_or_else(
+                || RpcResponse::error(request_id, "Invalid argument or unknown name"),
+                |command| match shared.sender.try_send(command) {
+                    Ok(()) => RpcResponse::ok(request_id),
+                    Err(_) => RpcResponse::error(request_id, "Command queue full"),
+                },
+            )
+        }
+    })
+}
+
+fn devices() -> Result<DeviceList> {
+    let manager = AudioDeviceManager::new();
+    Ok(DeviceList {
+        inputs: manager
+            .input_devices()?
+            .iter()
+            .map(DeviceEntry::from)
+            .collect(),
+        outputs: manager
+            .output_devices()?
+            .iter()
+            .map(DeviceEntry::from)
+            .collect(),
+    })
+}
+
+/// `value` as a line of JSON
+fn encode(value: &impl serde::Serialize) -> Option<String> {
+    match serde_json::to_string(value) {
+        Ok(mut line) => {
+            line.push('\n');
+            Some(line)
+        }
+        Err(e) => {
+            log::warn!("Failed to encode control message: {e}");
+            None
+        }
+    }
+}
+
+fn reply(writer: &Writer, response: &RpcResponse) {
+    if let Some(line) = encode(response) {
+        let written = writer.lock().write_all(line.as_bytes());
+        if let Err(e) = written {
+            log::debug!("Failed to send control response: {e}");
+        }
+    }
+}
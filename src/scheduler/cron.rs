@@ -0,0 +1,328 @@
+//! Cron-style recurrence rules and UTC calendar conversion
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{AudioEngineError, Result};
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+const SECONDS_PER_DAY: u64 = MINUTES_PER_DAY * 60;
+
+/// Days searched before giving up on a rule (covers the 28 year weekday cycle)
+const SEARCH_DAYS: u64 = 28 * 366;
+
+/// A calendar date and time in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UtcDateTime {
+    pub year: u32,
+    /// 1-12
+    pub month: u32,
+    /// 1-31
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl UtcDateTime {
+    /// Creates a date-time, validating every field.
+    ///
+    /// # Errors
+    /// Returns an error if the date is before 1970 or any field is out of range.
+    pub fn new(
+        year: u32,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: u32,
+    ) -> Result<Self> {
+        let valid = year >= 1970
+            && (1..=12).contains(&month)
+            && day >= 1
+            && day <= days_in_month(year, month)
+            && hour < 24
+            && minute < 60
+            && second < 60;
+        if !valid {
+            return Err(AudioEngineError::configuration(format!(
+                "invalid UTC date-time {year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02}"
+            )));
+        }
+        Ok(Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+
+    /// Converts a system time to UTC. Times before the epoch clamp to it.
+    #[must_use]
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let (year, month, day) = civil_from_days(secs / SECONDS_PER_DAY);
+        let second_of_day = secs % SECONDS_PER_DAY;
+        Self {
+            year,
+            month,
+            day,
+            hour: narrow(second_of_day / 3600),
+            minute: narrow(second_of_day / 60 % 60),
+            second: narrow(second_of_day % 60),
+        }
+    }
+
+    #[must_use]
+    pub fn to_system_time(self) -> SystemTime {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let secs = days * SECONDS_PER_DAY
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second);
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    /// Day of the week, 0 = Sunday
+    #[must_use]
+    pub fn weekday(&self) -> u32 {
+        weekday(days_from_civil(self.year, self.month, self.day))
+    }
+}
+
+impl fmt::Display for UtcDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// A recurring schedule in the usual five-field cron syntax, evaluated in UTC.
+///
+/// Fields are `minute hour day-of-month month day-of-week`. Each accepts `*`,
+/// single values, ranges (`8-18`), steps (`*/15`, `0-30/10`) and comma lists.
+/// Day-of-week runs 0-7 with both 0 and 7 meaning Sunday. As in classic cron,
+/// when both day fields are restricted a day matching either one fires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronRule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+impl CronRule {
+    /// Parses a five-field cron expression.
+    ///
+    /// # Errors
+    /// Returns an error if the expression doesn't have five valid fields.
+    pub fn parse(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, dom, month, dow] = fields[..] else {
+            return Err(AudioEngineError::configuration(format!(
+                "cron expression '{expression}' must have 5 fields"
+            )));
+        };
+        let field = |text: &str, min: u32, max: u32| {
+            parse_field(text, min, max).ok_or_else(|| {
+                AudioEngineError::configuration(format!(
+                    "invalid cron field '{text}' in '{expression}'"
+                ))
+            })
+        };
+
+        let mut days_of_week = field(dow, 0, 7)?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            expression: expression.trim().to_string(),
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days_of_month: field(dom, 1, 31)?,
+            months: field(month, 1, 12)?,
+            days_of_week,
+            day_of_month_restricted: !dom.starts_with('*'),
+            day_of_week_restricted: !dow.starts_with('*'),
+        })
+    }
+
+    /// Every day at the given UTC time
+    #[must_use]
+    pub fn daily(hour: u32, minute: u32) -> Self {
+        Self {
+            expression: format!("{} {} * * *", minute % 60, hour % 24),
+            minutes: 1 << (minute % 60),
+            hours: 1 << (hour % 24),
+            days_of_month: field_mask(1, 31),
+            months: field_mask(1, 12),
+            days_of_week: field_mask(0, 6),
+            day_of_month_restricted: false,
+            day_of_week_restricted: false,
+        }
+    }
+
+    #[must_use]
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// Returns true if the rule fires during the given minute.
+    #[must_use]
+    pub fn matches(&self, time: &UtcDateTime) -> bool {
+        self.matches_day(time.month, time.day, time.weekday())
+            && bit(self.hours, time.hour)
+            && bit(self.minutes, time.minute)
+    }
+
+    const fn matches_day(&self, month: u32, day: u32, weekday: u32) -> bool {
+        if !bit(self.months, month) {
+            return false;
+        }
+        let dom = bit(self.days_of_month, day);
+        let dow = bit(self.days_of_week, weekday);
+        if self.day_of_month_restricted && self.day_of_week_restricted {
+            dom || dow
+        } else {
+            dom && dow
+        }
+    }
+
+    /// Returns the first minute boundary strictly after `time` that matches.
+    ///
+    /// Returns `None` if the rule can never fire (e.g. February 30th).
+    #[must_use]
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let start_minute = secs / 60 + 1;
+        let start_day = start_minute / MINUTES_PER_DAY;
+
+        for day in start_day..start_day + SEARCH_DAYS {
+            let (_, month, day_of_month) = civil_from_days(day);
+            if !self.matches_day(month, day_of_month, weekday(day)) {
+                continue;
+            }
+            let first = if day == start_day {
+                start_minute % MINUTES_PER_DAY
+            } else {
+                0
+            };
+            for minute_of_day in first..MINUTES_PER_DAY {
+                if bit(self.hours, narrow(minute_of_day / 60))
+                    && bit(self.minutes, narrow(minute_of_day % 60))
+                {
+                    let minute = day * MINUTES_PER_DAY + minute_of_day;
+                    return Some(UNIX_EPOCH + Duration::from_secs(minute * 60));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl FromStr for CronRule {
+    type Err = AudioEngineError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for CronRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+fn parse_field(text: &str, min: u32, max: u32) -> Option<u64> {
+    let mut mask = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&s| s > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse().ok()?, end.parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            // "5/10" means every 10 starting at 5
+            (value, if step > 1 { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Some(mask)
+}
+
+const fn field_mask(min: u32, max: u32) -> u64 {
+    (u64::MAX >> (63 - max)) & !((1 << min) - 1)
+}
+
+const fn bit(mask: u64, value: u32) -> bool {
+    value < 64 && mask & (1 << value) != 0
+}
+
+fn narrow(value: u64) -> u32 {
+    u32::try_from(value).unwrap_or(u32::MAX)
+}
+
+const fn is_leap_year(year: u32) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+const fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Day of the week for days since the epoch, 0 = Sunday
+fn weekday(days: u64) -> u32 {
+    // 1970-01-01 was a Thursday
+    narrow((days + 4) % 7)
+}
+
+/// Converts days since the epoch to `(year, month, day)`.
+fn civil_from_days(days: u64) -> (u32, u32, u32) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (narrow(year), narrow(month), narrow(day))
+}
+
+/// Converts a date to days since the epoch. Dates must not precede 1970.
+fn days_from_civil(year: u32, month: u32, day: u32) -> u64 {
+    let year = u64::from(year) - u64::from(month <= 2);
+    let month = u64::from(month);
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * shifted_month + 2) / 5 + u64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
@@ -0,0 +1,266 @@
+//! Time-of-day scheduling for playout automation
+//!
+//! A [`Scheduler`] holds actions that fire at a fixed wall-clock time or on a
+//! recurring [`CronRule`]. Firing an action sends its command through a
+//! [`ControlSender`], so any command type the engine already accepts
+//! ([`EngineCommand`](crate::channel::EngineCommand), bus or switcher
+//! commands, ...) can be scheduled. Every firing produces a
+//! [`ScheduleReport`] describing what happened.
+//!
+//! The scheduler does not own a thread; call [`Scheduler::poll`] regularly
+//! from the control thread.
+
+pub mod cron;
+
+pub use cron::{CronRule, UtcDateTime};
+
+use std::time::{Duration, SystemTime};
+
+use crate::channel::ControlSender;
+use crate::error::{AudioEngineError, Result};
+
+/// Identifier of a scheduled action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScheduleId(u32);
+
+impl ScheduleId {
+    #[must_use]
+    pub const fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+/// When a scheduled action fires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// Once, at the given time
+    At(SystemTime),
+    /// Every time the rule matches
+    Recurring(CronRule),
+}
+
+impl Trigger {
+    fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        match self {
+            Self::At(at) => (*at > time).then_some(*at),
+            Self::Recurring(rule) => rule.next_after(time),
+        }
+    }
+}
+
+/// What happened when an action came due.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleOutcome {
+    /// The command was handed to the engine
+    Sent,
+    /// The action was due longer ago than the allowed lateness and was skipped
+    Missed,
+    /// The command could not be sent
+    Failed(String),
+}
+
+/// Result of one firing of a scheduled action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleReport {
+    pub id: ScheduleId,
+    pub label: String,
+    /// Time the action was due
+    pub scheduled_for: SystemTime,
+    /// Time the scheduler handled it
+    pub handled_at: SystemTime,
+    pub outcome: ScheduleOutcome,
+}
+
+impl ScheduleReport {
+    /// How late the action was handled
+    #[must_use]
+    pub fn lateness(&self) -> Duration {
+        self.handled_at
+            .duration_since(self.scheduled_for)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+struct ScheduledAction<T> {
+    id: ScheduleId,
+    label: String,
+    trigger: Trigger,
+    command: T,
+    next_fire: Option<SystemTime>,
+    enabled: bool,
+}
+
+/// Fires commands at wall-clock times.
+#[derive(Debug)]
+pub struct Scheduler<T> {
+    sender: ControlSender<T>,
+    actions: Vec<ScheduledAction<T>>,
+    next_id: u32,
+    max_lateness: Duration,
+}
+
+impl<T: Clone> Scheduler<T> {
+    /// Creates a scheduler that sends commands through `sender`.
+    #[must_use]
+    pub const fn new(sender: ControlSender<T>) -> Self {
+        Self {
+            sender,
+            actions: Vec::new(),
+            next_id: 0,
+            max_lateness: Duration::from_mins(1),
+        }
+    }
+
+    /// Sets how late an action may still fire, e.g. after the host was
+    /// suspended. Later actions are reported as missed.
+    #[must_use]
+    pub const fn with_max_lateness(mut self, max_lateness: Duration) -> Self {
+        self.max_lateness = max_lateness;
+        self
+    }
+
+    /// Adds an action, returning its id.
+    ///
+    /// # Errors
+    /// Returns an error if the trigger will never fire.
+    pub fn add(
+        &mut self,
+        label: impl Into<String>,
+        trigger: Trigger,
+        command: T,
+    ) -> Result<ScheduleId> {
+        self.add_from(label, trigger, command, SystemTime::now())
+    }
+
+    /// Adds an action whose first firing is computed relative to `now`.
+    ///
+    /// # Errors
+    /// Returns an error if the trigger will never fire after `now`.
+    pub fn add_from(
+        &mut self,
+        label: impl Into<String>,
+        trigger: Trigger,
+        command: T,
+        now: SystemTime,
+    ) -> Result<ScheduleId> {
+        let label = label.into();
+        let Some(next_fire) = trigger.next_after(now) else {
+            return Err(AudioEngineError::configuration(format!(
+                "scheduled action '{label}' would never fire"
+            )));
+        };
+        let id = ScheduleId(self.next_id);
+        self.next_id += 1;
+        self.actions.push(ScheduledAction {
+            id,
+            label,
+            trigger,
+            command,
+            next_fire: Some(next_fire),
+            enabled: true,
+        });
+        Ok(id)
+    }
+
+    /// Removes an action. Returns false if it doesn't exist.
+    pub fn remove(&mut self, id: ScheduleId) -> bool {
+        let before = self.actions.len();
+        self.actions.retain(|a| a.id != id);
+        self.actions.len() != before
+    }
+
+    /// Enables or disables an action without removing it.
+    ///
+    /// Disabled recurring actions skip their firings rather than catching up.
+    pub fn set_enabled(&mut self, id: ScheduleId, enabled: bool) -> bool {
+        self.actions
+            .iter_mut()
+            .find(|a| a.id == id)
+            .is_some_and(|action| {
+                action.enabled = enabled;
+                true
+            })
+    }
+
+    /// Next time the action fires, if it is still pending.
+    #[must_use]
+    pub fn next_fire(&self, id: ScheduleId) -> Option<SystemTime> {
+        self.actions.iter().find(|a| a.id == id)?.next_fire
+    }
+
+    /// The earliest pending enabled action
+    #[must_use]
+    pub fn upcoming(&self) -> Option<(ScheduleId, SystemTime)> {
+        self.actions
+            .iter()
+            .filter(|a| a.enabled)
+            .filter_map(|a| Some((a.id, a.next_fire?)))
+            .min_by_key(|&(_, time)| time)
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Fires every action due at or before `now`.
+    ///
+    /// Each due action fires at most once per call; a recurring action that
+    /// was due several times since the last poll fires once and moves on to
+    /// its next time after `now`. Finished one-shot actions are removed.
+    pub fn poll(&mut self, now: SystemTime) -> Vec<ScheduleReport> {
+        let mut due: Vec<(usize, SystemTime)> = self
+            .actions
+            .iter()
+            .enumerate()
+            .filter_map(|(index, a)| a.next_fire.filter(|&t| t <= now).map(|t| (index, t)))
+            .collect();
+        due.sort_by_key(|&(index, time)| (time, index));
+
+        let mut reports = Vec::with_capacity(due.len());
+        for (index, scheduled_for) in due {
+            let action = &mut self.actions[index];
+            action.next_fire = action.trigger.next_after(now);
+            if !action.enabled {
+                continue;
+            }
+            let late = now.duration_since(scheduled_for).unwrap_or_default() > self.max_lateness;
+            let outcome = if late {
+                ScheduleOutcome::Missed
+            } else {
+                match self.sender.try_send(action.command.clone()) {
+                    Ok(()) => ScheduleOutcome::Sent,
+                    Err(e) => ScheduleOutcome::Failed(e.to_string()),
+                }
+            };
+            if outcome != ScheduleOutcome::Sent {
+                log::warn!("scheduled action '{}' {outcome:?}", action.label);
+            }
+            reports.push(ScheduleReport {
+                id: action.id,
+                label: action.label.clone(),
+                scheduled_for,
+                handled_at: now,
+                outcome,
+            });
+        }
+
+        self.actions.retain(|a| a.next_fire.is_some());
+        reports
+    }
+
+    /// Time until the next enabled action is due, measured from `now`.
+    ///
+    /// Useful as a sleep or receive timeout for the thread calling [`poll`](Self::poll).
+    #[must_use]
+    pub fn time_until_next(&self, now: SystemTime) -> Option<Duration> {
+        let (_, next) = self.upcoming()?;
+        Some(next.duration_since(now).unwrap_or_default())
+    }
+}
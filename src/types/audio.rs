@@ -180,7 +180,7 @@ impl From<ChannelCount> for ChannelLayout {
 /// Audio buffer size in sample per channel.
 ///
 /// Must be a power of 2 in the range of 62-8192
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct BufferSize(NonZeroU32);
 impl BufferSize {
     /// Minimum allowed buffer size
@@ -1,11 +1,15 @@
 pub mod audio;
 pub mod device;
+pub mod music;
 pub mod network;
 pub mod sample;
 pub mod time;
+pub mod timecode;
 
 pub use audio::{AudioFormat, BitDepth, BufferSize, ChannelCount, ChannelLayout, FrameCount};
 pub use device::{DeviceId, DeviceInfo, DeviceType};
+pub use music::{MusicalTime, Tempo, TempoMap, TimeSignature};
 pub use network::{NetworkProtocol, StreamBitrate, StreamUrl};
 pub use sample::{Decibels, Gain, Pan, Sample, SampleRate};
-pub use time::{Timestamp, TransportPosition};
+pub use time::{TimeRange, Timestamp, TransportPosition};
+pub use timecode::{FrameRate, Timecode};
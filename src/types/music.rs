@@ -0,0 +1,362 @@
+//! Musical time: tempo, meter and bar/beat positions
+//!
+//! Beats are counted in quarter notes throughout, whatever the meter: a
+//! tempo of 120 is 120 quarter notes a minute, and a bar of 6/8 is three
+//! quarter notes long. [`TempoMap`] maps between the sample timeline and
+//! musical time across tempo and meter changes.
+
+use std::fmt;
+
+use crate::error::{AudioEngineError, Result};
+use crate::types::{SampleRate, Timestamp};
+
+/// Resolution of [`MusicalTime::tick`]
+pub const TICKS_PER_BEAT: u32 = 960;
+
+/// Rounding error tolerated when deciding which bar a position falls in
+const BAR_EPSILON: f64 = 1e-9;
+
+/// Tempo in quarter notes per minute.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Tempo(f64);
+
+impl Tempo {
+    pub const MIN_BPM: f64 = 1.0;
+    pub const MAX_BPM: f64 = 999.0;
+
+    /// Creates a tempo, clamped to [`MIN_BPM`](Self::MIN_BPM) and
+    /// [`MAX_BPM`](Self::MAX_BPM)
+    #[must_use]
+    pub const fn new(bpm: f64) -> Self {
+        Self(bpm.clamp(Self::MIN_BPM, Self::MAX_BPM))
+    }
+
+    #[must_use]
+    pub const fn bpm(self) -> f64 {
+        self.0
+    }
+
+    /// Length of one quarter note in seconds
+    #[must_use]
+    pub fn beat_seconds(self) -> f64 {
+        60.0 / self.0
+    }
+
+    /// Length of `beats` quarter notes in samples
+    #[must_use]
+    pub fn beats_to_samples(self, beats: f64, sample_rate: SampleRate) -> f64 {
+        beats * self.beat_seconds() * f64::from(sample_rate.as_hz())
+    }
+
+    /// Rate of something that cycles once every `beats` quarter notes
+    #[must_use]
+    pub fn cycle_hz(self, beats: f64) -> f64 {
+        self.0 / 60.0 / beats
+    }
+}
+
+impl Default for Tempo {
+    fn default() -> Self {
+        Self(120.0)
+    }
+}
+
+impl fmt::Display for Tempo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2} BPM", self.0)
+    }
+}
+
+/// Meter, e.g. 4/4 or 6/8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimeSignature {
+    numerator: u8,
+    denominator: u8,
+}
+
+impl TimeSignature {
+    pub const COMMON: Self = Self {
+        numerator: 4,
+        denominator: 4,
+    };
+
+    /// Creates a meter of `numerator` notes of value `denominator` a bar.
+    ///
+    /// # Errors
+    /// Returns an error if the numerator is zero or the denominator isn't a
+    /// power of two up to 64.
+    pub fn new(numerator: u8, denominator: u8) -> Result<Self> {
+        if numerator == 0 || !denominator.is_power_of_two() || denominator > 64 {
+            return Err(AudioEngineError::configuration(format!(
+                "invalid time signature {numerator}/{denominator}"
+            )));
+        }
+        Ok(Self {
+            numerator,
+            denominator,
+        })
+    }
+
+    #[must_use]
+    pub const fn numerator(self) -> u8 {
+        self.numerator
+    }
+
+    #[must_use]
+    pub const fn denominator(self) -> u8 {
+        self.denominator
+    }
+
+    /// Length of a bar in quarter notes
+    #[must_use]
+    pub fn bar_beats(self) -> f64 {
+        f64::from(self.numerator) * 4.0 / f64::from(self.denominator)
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self::COMMON
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+/// A bar/beat/tick position. Bars and beats count from zero; [`Display`]
+/// shows them from one, as editors do.
+///
+/// [`Display`]: fmt::Display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct MusicalTime {
+    pub bar: u32,
+    /// Quarter note within the bar
+    pub beat: u32,
+    /// Position within the beat, out of [`TICKS_PER_BEAT`]
+    pub tick: u32,
+}
+
+impl MusicalTime {
+    /// The position `beats` quarter notes later in a meter of `bar_beats`
+    /// quarter notes per bar
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn advanced(self, beats: f64, bar_beats: f64) -> Self {
+        let in_bar = f64::from(self.beat)
+            + f64::from(self.tick) / f64::from(TICKS_PER_BEAT)
+            + beats.max(0.0);
+        let bars = (in_bar / bar_beats + BAR_EPSILON).floor().max(0.0);
+        let in_bar = (in_bar - bars * bar_beats).max(0.0);
+        let beat = in_bar.floor();
+        Self {
+            bar: self.bar.saturating_add(bars as u32),
+            beat: beat as u32,
+            tick: (((in_bar - beat) * f64::from(TICKS_PER_BEAT)) as u32).min(TICKS_PER_BEAT - 1),
+        }
+    }
+}
+
+impl fmt::Display for MusicalTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{:03}", self.bar + 1, self.beat + 1, self.tick)
+    }
+}
+
+/// A run of the timeline with one tempo and meter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Segment {
+    start: Timestamp,
+    tempo: Tempo,
+    signature: TimeSignature,
+    /// Quarter notes from the start of the timeline to `start`
+    start_beats: f64,
+    /// Bars from the start of the timeline to `start`
+    start_bars: f64,
+}
+
+/// Tempo and meter changes along the timeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    sample_rate: SampleRate,
+    /// Ordered by start; the first starts at zero
+    segments: Vec<Segment>,
+}
+
+impl TempoMap {
+    /// A map with one tempo and meter from the start.
+    #[must_use]
+    pub fn new(sample_rate: SampleRate, tempo: Tempo, signature: TimeSignature) -> Self {
+        Self {
+            sample_rate,
+            segments: vec![Segment {
+                start: Timestamp::ZERO,
+                tempo,
+                signature,
+                start_beats: 0.0,
+                start_bars: 0.0,
+            }],
+        }
+    }
+
+    #[must_use]
+    pub const fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    /// Changes the tempo from `at` onwards, up to the next tempo change.
+    pub fn set_tempo(&mut self, at: Timestamp, tempo: Tempo) {
+        let index = self.split(at);
+        let old = self.segments[index].tempo;
+        for segment in &mut self.segments[index..] {
+            if segment.tempo != old {
+                break;
+            }
+            segment.tempo = tempo;
+        }
+        self.update();
+    }
+
+    /// Changes the meter from `at` onwards, up to the next meter change.
+    /// Bars are counted afresh from `at`, so a change mid-bar cuts that
+    /// bar short.
+    pub fn set_signature(&mut self, at: Timestamp, signature: TimeSignature) {
+        let index = self.split(at);
+        let old = self.segments[index].signature;
+        for segment in &mut self.segments[index..] {
+            if segment.signature != old {
+                break;
+            }
+            segment.signature = signature;
+        }
+        self.update();
+    }
+
+    /// Removes every change, keeping the tempo and meter at the start.
+    pub fn clear_changes(&mut self) {
+        self.segments.truncate(1);
+    }
+
+    #[must_use]
+    pub fn tempo_at(&self, position: Timestamp) -> Tempo {
+        self.segment_at(position).tempo
+    }
+
+    #[must_use]
+    pub fn signature_at(&self, position: Timestamp) -> TimeSignature {
+        self.segment_at(position).signature
+    }
+
+    /// Quarter notes from the start of the timeline to `position`
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn beats_at(&self, position: Timestamp) -> f64 {
+        let segment = self.segment_at(position);
+        let samples = (position.as_samples() - segment.start.as_samples()) as f64;
+        segment.start_beats + samples / segment.tempo.beats_to_samples(1.0, self.sample_rate)
+    }
+
+    /// Bar, beat and tick at `position`
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn musical_time_at(&self, position: Timestamp) -> MusicalTime {
+        let segment = self.segment_at(position);
+        let beats = self.beats_at(position) - segment.start_beats;
+        let bar_beats = segment.signature.bar_beats();
+        let bars = segment.start_bars + beats / bar_beats;
+        let bar = (bars + BAR_EPSILON).floor();
+        let in_bar = ((bars - bar) * bar_beats).max(0.0);
+        let beat = in_bar.floor();
+        MusicalTime {
+            bar: bar as u32,
+            beat: beat as u32,
+            tick: (((in_bar - beat) * f64::from(TICKS_PER_BEAT)) as u32).min(TICKS_PER_BEAT - 1),
+        }
+    }
+
+    /// Timeline position `beats` quarter notes from the start
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn position_of_beats(&self, beats: f64) -> Timestamp {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start_beats <= beats)
+            .saturating_sub(1);
+        let segment = &self.segments[index];
+        let samples = (beats.max(0.0) - segment.start_beats)
+            * segment.tempo.beats_to_samples(1.0, self.sample_rate);
+        Timestamp::from_samples(segment.start.as_samples() + samples.round() as u64)
+    }
+
+    /// Timeline position of a bar/beat/tick
+    #[must_use]
+    pub fn position_of(&self, time: MusicalTime) -> Timestamp {
+        // Find the meter in force at the bar, then count from its start
+        let bar = f64::from(time.bar);
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start_bars <= bar + BAR_EPSILON)
+            .saturating_sub(1);
+        let segment = &self.segments[index];
+        let beats = (bar - segment.start_bars)
+            .mul_add(segment.signature.bar_beats(), segment.start_beats)
+            + f64::from(time.beat)
+            + f64::from(time.tick) / f64::from(TICKS_PER_BEAT);
+        self.position_of_beats(beats)
+    }
+
+    fn segment_at(&self, position: Timestamp) -> &Segment {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start <= position)
+            .saturating_sub(1);
+        &self.segments[index]
+    }
+
+    /// Makes sure a segment starts at `at` and returns its index.
+    fn split(&mut self, at: Timestamp) -> usize {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start <= at)
+            .saturating_sub(1);
+        if self.segments[index].start == at {
+            return index;
+        }
+        let segment = Segment {
+            start: at,
+            ..self.segments[index]
+        };
+        self.segments.insert(index + 1, segment);
+        index + 1
+    }
+
+    /// Recomputes where each segment starts in beats and bars, and merges
+    /// segments that no longer change anything.
+    #[allow(clippy::cast_precision_loss)]
+    fn update(&mut self) {
+        self.segments.dedup_by(|later, earlier| {
+            later.tempo == earlier.tempo && later.signature == earlier.signature
+        });
+        for index in 1..self.segments.len() {
+            let previous = self.segments[index - 1];
+            let samples =
+                (self.segments[index].start.as_samples() - previous.start.as_samples()) as f64;
+            let beats = samples / previous.tempo.beats_to_samples(1.0, self.sample_rate);
+            let segment = &mut self.segments[index];
+            segment.start_beats = previous.start_beats + beats;
+            let bars = previous.start_bars + beats / previous.signature.bar_beats();
+            segment.start_bars = if segment.signature == previous.signature {
+                bars
+            } else {
+                // A new meter starts a new bar
+                (bars - BAR_EPSILON).ceil()
+            };
+        }
+    }
+}
@@ -16,6 +16,14 @@ pub enum NetworkProtocol {
     HLS,
     /// Real time transport protocol
     RTP,
+    /// Icecast source mount (output only)
+    Icecast,
+    /// Shoutcast source (output only)
+    Shoutcast,
+    /// Secure Reliable Transport (output only)
+    SRT,
+    /// Reliable Internet Stream Transport (output only)
+    RIST,
 }
 
 impl NetworkProtocol {
@@ -26,6 +34,9 @@ impl NetworkProtocol {
             Self::RTMP => 1935,
             Self::HLS => 80,
             Self::RTP => 5004,
+            Self::Icecast | Self::Shoutcast => 8000,
+            Self::SRT => 9000,
+            Self::RIST => 1968,
         }
     }
 
@@ -36,6 +47,10 @@ impl NetworkProtocol {
             Self::RTMP => "rtmp",
             Self::HLS => "https",
             Self::RTP => "rtp",
+            Self::Icecast => "icecast",
+            Self::Shoutcast => "shoutcast",
+            Self::SRT => "srt",
+            Self::RIST => "rist",
         }
     }
 }
@@ -46,6 +61,10 @@ impl fmt::Display for NetworkProtocol {
             Self::RTMP => write!(f, "RTMP"),
             Self::HLS => write!(f, "HLS"),
             Self::RTP => write!(f, "RTP"),
+            Self::Icecast => write!(f, "Icecast"),
+            Self::Shoutcast => write!(f, "SHOUTcast"),
+            Self::SRT => write!(f, "SRT"),
+            Self::RIST => write!(f, "RIST"),
         }
     }
 }
@@ -57,6 +76,10 @@ impl FromStr for NetworkProtocol {
             "rtmp" => Ok(Self::RTMP),
             "hls" => Ok(Self::HLS),
             "rtp" => Ok(Self::RTP),
+            "icecast" => Ok(Self::Icecast),
+            "shoutcast" => Ok(Self::Shoutcast),
+            "srt" => Ok(Self::SRT),
+            "rist" => Ok(Self::RIST),
             _ => Err(AudioEngineError::InvalidStreamUrl {
                 url: s.to_string(),
                 reason: "Unknown protocol".to_string(),
@@ -105,6 +128,14 @@ impl StreamUrl {
             (NetworkProtocol::HLS, rest)
         } else if let Some(rest) = url.strip_prefix("rtp://") {
             (NetworkProtocol::RTP, rest)
+        } else if let Some(rest) = url.strip_prefix("icecast://") {
+            (NetworkProtocol::Icecast, rest)
+        } else if let Some(rest) = url.strip_prefix("shoutcast://") {
+            (NetworkProtocol::Shoutcast, rest)
+        } else if let Some(rest) = url.strip_prefix("srt://") {
+            (NetworkProtocol::SRT, rest)
+        } else if let Some(rest) = url.strip_prefix("rist://") {
+            (NetworkProtocol::RIST, rest)
         } else {
             return Err(AudioEngineError::InvalidStreamUrl {
                 url: url.to_string(),
@@ -113,6 +144,8 @@ impl StreamUrl {
         };
 
         let (host_port, path) = rest.split_once('/').unwrap_or((rest, ""));
+        // Options may follow the port directly, as in `srt://host:9000?mode=listener`
+        let host_port = host_port.split('?').next().unwrap_or(host_port);
 
         let (host, port) = if let Some((h, p)) = host_port.split_once(':') {
             let port = p.parse().map_err(|_| AudioEngineError::InvalidStreamUrl {
@@ -56,6 +56,15 @@ impl SampleRate {
         }
     }
 
+    /// Returns the sample rate as an `f32`
+    ///
+    /// Every supported rate is exactly representable, so this is lossless.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub const fn as_f32(self) -> f32 {
+        self.as_hz() as f32
+    }
+
     /// Returns the sample period in seconds
     #[must_use]
     pub fn period_seconds(self) -> f64 {
@@ -85,7 +94,7 @@ impl TryFrom<u32> for SampleRate {
             44100 => Ok(Self::Hz44100),
             48000 => Ok(Self::Hz48000),
             96000 => Ok(Self::Hz96000),
-            19200 => Ok(Self::Hz192000),
+            192_000 => Ok(Self::Hz192000),
             _ => Err(AudioEngineError::InvalidSampleRate { value }),
         }
     }
@@ -222,6 +231,7 @@ impl fmt::Display for Sample {
 /// Stored as a linear value (not decibels). A value of 1.0 means unit gain.l
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Gain(f32);
 
 impl Gain {
@@ -331,6 +341,7 @@ impl fmt::Display for Gain {
 /// Used for level metering, gain display, and other UI facing values
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Decibels(f32);
 impl Decibels {
     /// Silence threshold
@@ -413,6 +424,7 @@ impl fmt::Display for Decibels {
 /// 0.0 is center
 /// and 1.0 is full right
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pan(f32);
 impl Pan {
     /// Center Position
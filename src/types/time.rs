@@ -1,12 +1,13 @@
 //! Time related types for audio processing
 //!
 
-use crate::types::SampleRate;
+use crate::types::{FrameRate, SampleRate, Timecode};
 use std::fmt;
 use std::time::Duration;
 
 /// A Timestamp in the audio timeline, measured in samples.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Timestamp(u64);
 
 impl Timestamp {
@@ -67,13 +68,68 @@ impl fmt::Display for Timestamp {
     }
 }
 
+/// A span of the timeline, from `start` up to but not including `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeRange {
+    start: Timestamp,
+    end: Timestamp,
+}
+
+impl TimeRange {
+    /// Creates a range between two timestamps, in either order
+    #[must_use]
+    pub fn new(a: Timestamp, b: Timestamp) -> Self {
+        Self {
+            start: a.min(b),
+            end: a.max(b),
+        }
+    }
+
+    #[must_use]
+    pub const fn start(self) -> Timestamp {
+        self.start
+    }
+
+    #[must_use]
+    pub const fn end(self) -> Timestamp {
+        self.end
+    }
+
+    /// Length in samples
+    #[must_use]
+    pub const fn len(self) -> u64 {
+        self.end.0 - self.start.0
+    }
+
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.end.0 == self.start.0
+    }
+
+    #[must_use]
+    pub const fn contains(self, timestamp: Timestamp) -> bool {
+        timestamp.0 >= self.start.0 && timestamp.0 < self.end.0
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
 /// Transport position with time code formatting
+///
+/// Shown as hours, minutes, seconds and milliseconds, or as SMPTE
+/// timecode once it has one at a video frame rate.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct TransportPosition {
     hours: u8,
     minutes: u8,
     seconds: u8,
     millis: u16,
+    timecode: Option<Timecode>,
 }
 
 impl TransportPosition {
@@ -83,6 +139,7 @@ impl TransportPosition {
         minutes: 0,
         seconds: 0,
         millis: 0,
+        timecode: None,
     };
 
     /// Creates a transport position from milliseconds
@@ -100,6 +157,7 @@ impl TransportPosition {
             minutes,
             seconds,
             millis,
+            timecode: None,
         }
     }
 
@@ -118,6 +176,28 @@ impl TransportPosition {
         Self::from_seconds_f64(total_seconds)
     }
 
+    /// The position with its timecode at `rate`, to the millisecond. Use
+    /// [`with_timecode`](Self::with_timecode) with
+    /// [`Timecode::from_timestamp`] for the exact frame.
+    #[must_use]
+    pub fn with_frame_rate(self, rate: FrameRate) -> Self {
+        let (frames, seconds) = rate.ratio();
+        let count = self.total_millis() * frames / (1000 * seconds);
+        self.with_timecode(Timecode::from_frame_count(count, rate))
+    }
+
+    #[must_use]
+    pub const fn with_timecode(mut self, timecode: Timecode) -> Self {
+        self.timecode = Some(timecode);
+        self
+    }
+
+    /// The SMPTE timecode, if the position has a frame rate
+    #[must_use]
+    pub const fn timecode(self) -> Option<Timecode> {
+        self.timecode
+    }
+
     #[must_use]
     pub fn total_millis(self) -> u64 {
         u64::from(self.hours) * 3_600_000
@@ -134,6 +214,9 @@ impl TransportPosition {
 
 impl fmt::Display for TransportPosition {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(timecode) = self.timecode {
+            return write!(f, "{timecode}");
+        }
         write! {
             f,
             "{:02}:{:02}:{:02}.{:03}",
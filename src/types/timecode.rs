@@ -0,0 +1,264 @@
+//! SMPTE timecode at video frame rates
+//!
+
+use crate::types::{SampleRate, Timestamp};
+use std::fmt;
+
+/// Frames in ten minutes of 29.97 drop frame timecode
+const DROP_FRAMES_PER_TEN_MINUTES: u64 = 17_982;
+/// Frames in each minute but the first of ten, with two numbers dropped
+const DROP_FRAMES_PER_MINUTE: u64 = 1_798;
+
+/// A video frame rate, as timecode counts it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FrameRate {
+    /// 23.976 frames per second, counted as 24 without dropping numbers
+    Fps23976,
+    Fps24,
+    Fps25,
+    /// 29.97 frames per second, drop frame
+    Fps2997Drop,
+    Fps30,
+}
+
+impl FrameRate {
+    /// Frames per second
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn fps(self) -> f64 {
+        let (frames, seconds) = self.ratio();
+        frames as f64 / seconds as f64
+    }
+
+    /// Frames per second as frames over seconds, exact for the NTSC rates
+    #[must_use]
+    pub const fn ratio(self) -> (u64, u64) {
+        match self {
+            Self::Fps23976 => (24_000, 1001),
+            Self::Fps24 => (24, 1),
+            Self::Fps25 => (25, 1),
+            Self::Fps2997Drop => (30_000, 1001),
+            Self::Fps30 => (30, 1),
+        }
+    }
+
+    /// Frames counted per second of timecode
+    #[must_use]
+    pub const fn nominal(self) -> u32 {
+        match self {
+            Self::Fps23976 | Self::Fps24 => 24,
+            Self::Fps25 => 25,
+            Self::Fps2997Drop | Self::Fps30 => 30,
+        }
+    }
+
+    /// Whether frame numbers are skipped to keep timecode on the clock
+    #[must_use]
+    pub const fn is_drop_frame(self) -> bool {
+        matches!(self, Self::Fps2997Drop)
+    }
+}
+
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Fps23976 => "23.976",
+            Self::Fps24 => "24",
+            Self::Fps25 => "25",
+            Self::Fps2997Drop => "29.97 DF",
+            Self::Fps30 => "30",
+        })
+    }
+}
+
+/// An hours:minutes:seconds:frames position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub rate: FrameRate,
+}
+
+impl Timecode {
+    /// The timecode of frame `count` from zero, skipping the frame numbers
+    /// drop frame leaves out. Hours wrap at 24, as they do on a deck.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)] // each field is reduced below its limit
+    pub const fn from_frame_count(count: u64, rate: FrameRate) -> Self {
+        let number = if rate.is_drop_frame() {
+            // Two numbers are dropped at the start of every minute but
+            // each tenth
+            let tens = count / DROP_FRAMES_PER_TEN_MINUTES;
+            let rest = count % DROP_FRAMES_PER_TEN_MINUTES;
+            let minutes = if rest < 2 {
+                0
+            } else {
+                (rest - 2) / DROP_FRAMES_PER_MINUTE
+            };
+            count + 18 * tens + 2 * minutes
+        } else {
+            count
+        };
+        let nominal = rate.nominal() as u64;
+        let seconds = number / nominal;
+        Self {
+            hours: (seconds / 3600 % 24) as u8,
+            minutes: (seconds / 60 % 60) as u8,
+            seconds: (seconds % 60) as u8,
+            frames: (number % nominal) as u8,
+            rate,
+        }
+    }
+
+    /// The timecode of the frame playing at `timestamp`, counted exactly
+    /// so it stays in step with an editor's over long programmes.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)] // past u64 frames is past any timeline
+    pub const fn from_timestamp(
+        timestamp: Timestamp,
+        sample_rate: SampleRate,
+        rate: FrameRate,
+    ) -> Self {
+        let (frames, seconds) = rate.ratio();
+        let count = timestamp.as_samples() as u128 * frames as u128
+            / (sample_rate.as_hz() as u128 * seconds as u128);
+        Self::from_frame_count(count as u64, rate)
+    }
+
+    /// Frames from zero, leaving out the frame numbers drop frame skips
+    #[must_use]
+    pub fn frame_count(self) -> u64 {
+        let minutes = u64::from(self.hours) * 60 + u64::from(self.minutes);
+        let seconds = minutes * 60 + u64::from(self.seconds);
+        let frames = seconds * u64::from(self.rate.nominal()) + u64::from(self.frames);
+        if self.rate.is_drop_frame() {
+            frames - 2 * (minutes - minutes / 10)
+        } else {
+            frames
+        }
+    }
+
+    /// Seconds from zero
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn as_seconds(self) -> f64 {
+        self.frame_count() as f64 / self.rate.fps()
+    }
+
+    /// The first sample of the frame, so that `from_timestamp` gives this
+    /// timecode back
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)] // past u64 samples is past any timeline
+    pub fn to_timestamp(self, sample_rate: SampleRate) -> Timestamp {
+        let (frames, seconds) = self.rate.ratio();
+        let samples = (u128::from(self.frame_count())
+            * u128::from(sample_rate.as_hz())
+            * u128::from(seconds))
+        .div_ceil(u128::from(frames));
+        Timestamp::from_samples(samples as u64)
+    }
+}
+
+impl fmt::Display for Timecode {
+    /// `HH:MM:SS:FF`, with a semicolon before the frames for drop frame
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.rate.is_drop_frame() { ';' } else { ':' };
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{separator}{:02}",
+            self.hours, self.minutes, self.seconds, self.frames
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drop_frame(count: u64) -> String {
+        Timecode::from_frame_count(count, FrameRate::Fps2997Drop).to_string()
+    }
+
+    #[test]
+    fn drop_frame_skips_two_numbers_a_minute_but_every_tenth() {
+        assert_eq!(drop_frame(0), "00:00:00;00");
+        assert_eq!(drop_frame(1799), "00:00:59;29");
+        assert_eq!(drop_frame(1800), "00:01:00;02");
+        assert_eq!(drop_frame(3597), "00:01:59;29");
+        assert_eq!(drop_frame(3598), "00:02:00;02");
+        assert_eq!(drop_frame(17_981), "00:09:59;29");
+        assert_eq!(drop_frame(17_982), "00:10:00;00");
+        assert_eq!(drop_frame(17_982 + 1800), "00:11:00;02");
+    }
+
+    #[test]
+    fn frame_count_inverts_from_frame_count() {
+        for rate in [
+            FrameRate::Fps23976,
+            FrameRate::Fps24,
+            FrameRate::Fps25,
+            FrameRate::Fps2997Drop,
+            FrameRate::Fps30,
+        ] {
+            // Every frame either side of the first hour's minute marks
+            for count in (0..120_000).step_by(7).chain(17_970..18_000) {
+                let timecode = Timecode::from_frame_count(count, rate);
+                assert_eq!(timecode.frame_count(), count, "{rate} frame {count}");
+            }
+        }
+    }
+
+    #[test]
+    fn drop_frame_keeps_to_the_clock() {
+        // An hour of 29.97 frames is 01:00:00;00 exactly
+        let hour = Timestamp::from_samples(48_000 * 3600);
+        let timecode = Timecode::from_timestamp(hour, SampleRate::Hz48000, FrameRate::Fps2997Drop);
+        assert_eq!(timecode.to_string(), "01:00:00;00");
+        assert_eq!(timecode.frame_count(), 107_892);
+
+        // Counted without drops, the same hour falls 3.6 seconds short
+        let timecode = Timecode::from_timestamp(hour, SampleRate::Hz48000, FrameRate::Fps23976);
+        assert_eq!(timecode.to_string(), "00:59:56:09");
+    }
+
+    #[test]
+    fn hours_wrap_at_a_day() {
+        let day = 24 * 3600 * 25;
+        let timecode = Timecode::from_frame_count(day + 26, FrameRate::Fps25);
+        assert_eq!(timecode.to_string(), "00:00:01:01");
+    }
+
+    #[test]
+    fn timestamps_round_trip_to_the_frame() {
+        // Floating point seconds used to land a sample short of the frame
+        for rate in [
+            FrameRate::Fps23976,
+            FrameRate::Fps25,
+            FrameRate::Fps2997Drop,
+        ] {
+            for sample_rate in SampleRate::ALL {
+                for count in [0, 1, 1799, 1800, 54_321, 2_000_000] {
+                    let timecode = Timecode::from_frame_count(count, rate);
+                    let timestamp = timecode.to_timestamp(sample_rate);
+                    assert_eq!(
+                        Timecode::from_timestamp(timestamp, sample_rate, rate),
+                        timecode,
+                        "{rate} frame {count} at {}",
+                        sample_rate.as_hz()
+                    );
+                    let before = Timestamp::from_samples(timestamp.as_samples().saturating_sub(1));
+                    if count > 0 {
+                        assert_ne!(
+                            Timecode::from_timestamp(before, sample_rate, rate),
+                            timecode
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
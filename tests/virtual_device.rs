@@ -0,0 +1,50 @@
+//! The engine's full stream path, run on a virtual device
+
+use audio_engine::audio::backend::VirtualDevice;
+use audio_engine::dsp::chain::EffectChain;
+use audio_engine::dsp::gain::GainEffect;
+use audio_engine::dsp::traits::EffectId;
+use audio_engine::engine::{Engine, SafetySettings};
+use audio_engine::types::{AudioFormat, Gain, Sample};
+
+const BUFFER_FRAMES: usize = 256;
+
+#[test]
+fn engine_plays_input_through_its_chain() {
+    let device = VirtualDevice::new(AudioFormat::PROFESSIONAL, BUFFER_FRAMES);
+    let gain = Gain::from_db(-6.0);
+    let mut chain = EffectChain::new();
+    chain.push(Box::new(GainEffect::with_gain(EffectId::new(1), gain)));
+    let mut engine = Engine::builder()
+        .with_virtual_device(device.clone())
+        .with_chain(chain)
+        .with_safety(SafetySettings::disabled())
+        .build()
+        .expect("the engine builds on a virtual device");
+    engine.start().expect("the virtual streams start");
+
+    #[allow(clippy::cast_precision_loss)]
+    let input: Vec<Sample> = (0..BUFFER_FRAMES * 8 * 2)
+        .map(|index| Sample::new((index as f32 * 0.01).sin() * 0.5))
+        .collect();
+    device.push_input(&input);
+    device.advance(BUFFER_FRAMES * 8);
+    let mut output = vec![Sample::SILENCE; input.len()];
+    assert_eq!(device.pull_output(&mut output), input.len());
+    assert_eq!(device.input_shortfall().as_u64(), 0);
+    // Captured and played in the same buffer, through the chain's gain
+    for (index, (input, output)) in input.iter().zip(&output).enumerate() {
+        let expected = input.value() * gain.as_linear();
+        assert!(
+            (output.value() - expected).abs() < 1e-6,
+            "sample {index}: {} instead of {expected}",
+            output.value()
+        );
+    }
+
+    // A stopped engine's device plays silence
+    engine.stop().expect("the virtual streams stop");
+    device.push_input(&input);
+    device.advance(BUFFER_FRAMES);
+    assert!(device.take_output().iter().all(|s| s.value() == 0.0));
+}